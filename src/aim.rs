@@ -0,0 +1,149 @@
+//! AIM (Aggregated Internet Measurement) quality scores
+//!
+//! speed.cloudflare.com summarizes a run as how well the connection suits streaming,
+//! gaming and real-time communication. Each experience rates the metrics it depends
+//! on from Bad to Great against fixed thresholds; the experience gets the rating of
+//! its weakest metric, since a single bottleneck (e.g. loss in a video call) is what
+//! users notice. Metrics missing from a run are left out.
+
+use crate::model::{AimRating, AimScores, RunResult};
+
+/// Minimum value for Poor, Average, Good and Great (higher is better)
+type AtLeast = [f64; 4];
+/// Maximum value for Poor, Average, Good and Great (lower is better)
+type AtMost = [f64; 4];
+
+const STREAMING_DOWNLOAD_MBPS: AtLeast = [1.0, 5.0, 10.0, 25.0];
+const STREAMING_LOADED_LATENCY_MS: AtMost = [500.0, 200.0, 100.0, 50.0];
+const STREAMING_LOSS_PCT: AtMost = [5.0, 2.0, 1.0, 0.5];
+
+const GAMING_DOWNLOAD_MBPS: AtLeast = [1.0, 3.0, 5.0, 10.0];
+const GAMING_LOADED_LATENCY_MS: AtMost = [200.0, 100.0, 50.0, 20.0];
+const GAMING_JITTER_MS: AtMost = [50.0, 30.0, 15.0, 5.0];
+const GAMING_LOSS_PCT: AtMost = [5.0, 2.0, 1.0, 0.1];
+
+const RTC_DOWNLOAD_MBPS: AtLeast = [1.0, 2.0, 5.0, 10.0];
+const RTC_UPLOAD_MBPS: AtLeast = [0.5, 1.0, 2.0, 5.0];
+const RTC_LOADED_LATENCY_MS: AtMost = [300.0, 150.0, 80.0, 40.0];
+const RTC_JITTER_MS: AtMost = [60.0, 30.0, 20.0, 10.0];
+const RTC_LOSS_PCT: AtMost = [5.0, 3.0, 1.0, 0.5];
+
+const RATINGS: [AimRating; 4] = [
+    AimRating::Poor,
+    AimRating::Average,
+    AimRating::Good,
+    AimRating::Great,
+];
+
+fn at_least(value: Option<f64>, t: AtLeast) -> Option<AimRating> {
+    let v = value?;
+    Some(
+        t.iter()
+            .zip(RATINGS)
+            .rev()
+            .find(|(min, _)| v >= **min)
+            .map_or(AimRating::Bad, |(_, r)| r),
+    )
+}
+
+fn at_most(value: Option<f64>, t: AtMost) -> Option<AimRating> {
+    let v = value?;
+    Some(
+        t.iter()
+            .zip(RATINGS)
+            .rev()
+            .find(|(max, _)| v <= **max)
+            .map_or(AimRating::Bad, |(_, r)| r),
+    )
+}
+
+/// Weakest of the available ratings
+fn weakest(ratings: &[Option<AimRating>]) -> Option<AimRating> {
+    ratings.iter().flatten().min().copied()
+}
+
+/// Score a run; None when it has no throughput or latency to judge.
+pub fn scores(r: &RunResult) -> Option<AimScores> {
+    let download = (r.download.mbps > 0.0).then_some(r.download.mbps);
+    let upload = (r.upload.mbps > 0.0).then_some(r.upload.mbps);
+    // The worse direction is what an interactive application experiences
+    let loaded_latency = [
+        r.loaded_latency_download.median_ms,
+        r.loaded_latency_upload.median_ms,
+    ]
+    .into_iter()
+    .flatten()
+    .reduce(f64::max);
+    let jitter = [
+        r.idle_latency.jitter_ms,
+        r.loaded_latency_download.jitter_ms,
+        r.loaded_latency_upload.jitter_ms,
+    ]
+    .into_iter()
+    .flatten()
+    .reduce(f64::max);
+    // UDP loss when measured, otherwise loss of the idle HTTP probes
+    let loss_pct = match &r.experimental_udp {
+        Some(udp) if udp.latency.sent > 0 => Some(udp.latency.loss * 100.0),
+        _ => (r.idle_latency.sent > 0).then_some(r.idle_latency.loss * 100.0),
+    };
+
+    if download.is_none() && upload.is_none() && loaded_latency.is_none() {
+        return None;
+    }
+
+    Some(AimScores {
+        streaming: weakest(&[
+            at_least(download, STREAMING_DOWNLOAD_MBPS),
+            at_most(loaded_latency, STREAMING_LOADED_LATENCY_MS),
+            at_most(loss_pct, STREAMING_LOSS_PCT),
+        ])?,
+        gaming: weakest(&[
+            at_least(download, GAMING_DOWNLOAD_MBPS),
+            at_most(loaded_latency, GAMING_LOADED_LATENCY_MS),
+            at_most(jitter, GAMING_JITTER_MS),
+            at_most(loss_pct, GAMING_LOSS_PCT),
+        ])?,
+        rtc: weakest(&[
+            at_least(download, RTC_DOWNLOAD_MBPS),
+            at_least(upload, RTC_UPLOAD_MBPS),
+            at_most(loaded_latency, RTC_LOADED_LATENCY_MS),
+            at_most(jitter, RTC_JITTER_MS),
+            at_most(loss_pct, RTC_LOSS_PCT),
+        ])?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ratings() {
+        assert_eq!(
+            at_least(Some(30.0), STREAMING_DOWNLOAD_MBPS),
+            Some(AimRating::Great)
+        );
+        assert_eq!(
+            at_least(Some(7.0), STREAMING_DOWNLOAD_MBPS),
+            Some(AimRating::Average)
+        );
+        assert_eq!(
+            at_least(Some(0.5), STREAMING_DOWNLOAD_MBPS),
+            Some(AimRating::Bad)
+        );
+        assert_eq!(
+            at_most(Some(20.0), GAMING_LOADED_LATENCY_MS),
+            Some(AimRating::Great)
+        );
+        assert_eq!(
+            at_most(Some(120.0), GAMING_LOADED_LATENCY_MS),
+            Some(AimRating::Poor)
+        );
+        assert_eq!(at_most(None, GAMING_LOADED_LATENCY_MS), None);
+        assert_eq!(
+            weakest(&[Some(AimRating::Great), None, Some(AimRating::Average)]),
+            Some(AimRating::Average)
+        );
+    }
+}
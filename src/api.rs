@@ -0,0 +1,506 @@
+//! Local REST API (`serve --api`).
+//!
+//! Lets dashboards and home-automation systems run tests and read results over HTTP
+//! instead of parsing the command line's output:
+//!
+//! - `POST /api/run`: start a test with the options `serve` was given; 202 with its
+//!   `meas_id`, 409 while another test is running
+//! - `GET /api/status`: whether a test is running, and its `meas_id`
+//! - `GET /api/events`: server-sent events. Each `TestEvent` of a running test is sent
+//!   as a message with the JSON of `--events-ndjson`; a test begins with a `started`
+//!   event and ends with a `result` event carrying the `RunResult`, or an `error` event
+//! - `GET /api/results/latest`: the newest result, 404 when there is none
+//! - `GET /api/results`: saved results, newest first, filtered like `history query` by
+//!   the `since`, `before`, `interface`, `colo`, `vpn`, `offset` and `limit` parameters
+//!
+//! Results are published to the configured sinks (the history by default), as in monitor
+//! mode. There is no authentication, so the API listens on localhost unless told
+//! otherwise. Against web pages the user happens to visit, requests must name the server
+//! by address or as `localhost` in their Host header (a DNS rebinding page cannot), a
+//! request from another `Origin` is refused unless that origin was allowed with
+//! `--api-allow-origin` (and only those get CORS headers), and `POST /api/run` needs a
+//! JSON content type, which a cross-site form cannot send.
+
+use crate::cli::{build_config, Cli};
+use crate::engine::TestEngine;
+use crate::model::{RunConfig, RunResult, TestEvent};
+use crate::sinks::Sinks;
+use crate::storage::RunQuery;
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::StreamExt;
+use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::header::{self, HeaderMap};
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc};
+
+/// Address `serve --api` listens on when none is given
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+
+/// Time a client gets to send its request head
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval of the comments keeping idle event streams open
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// Results `/api/results` returns without a `limit`, as `history query`
+const DEFAULT_LIMIT: usize = 50;
+
+const ENDPOINTS: [&str; 5] = [
+    "/api/events",
+    "/api/run",
+    "/api/status",
+    "/api/results/latest",
+    "/api/results",
+];
+
+type Body = BoxBody<Bytes, Infallible>;
+
+/// Serve the API on `listen` until interrupted, running tests configured by `args`.
+/// Browser pages from `allowed_origins` may use it besides same-origin ones.
+pub async fn serve(listen: &str, allowed_origins: Vec<String>, args: Cli) -> Result<()> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("failed to listen on {}", listen))?;
+    let server = Arc::new(Server {
+        sinks: crate::sinks::configured(&args, false, args.auto_save),
+        args,
+        allowed_origins,
+        running: Mutex::new(None),
+        latest: Mutex::new(None),
+        events: broadcast::channel(1024).0,
+    });
+
+    println!("Serving the API on http://{}/api", listener.local_addr()?);
+    crate::systemd::ready();
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        let server = server.clone();
+        tokio::spawn(async move {
+            let service = hyper::service::service_fn(move |req| {
+                let server = server.clone();
+                async move { Ok::<_, Infallible>(server.handle(req)) }
+            });
+            if let Err(e) = hyper::server::conn::http1::Builder::new()
+                .timer(TokioTimer::new())
+                .header_read_timeout(REQUEST_TIMEOUT)
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("API client {}: {:#}", peer, e);
+            }
+        });
+    }
+}
+
+struct Server {
+    args: Cli,
+    sinks: Sinks,
+    /// `--api-allow-origin`: origins of browser dashboards that may read and run tests
+    allowed_origins: Vec<String>,
+    /// Measurement ID of the running test
+    running: Mutex<Option<String>>,
+    /// Result of the last test run here, which the history may not have
+    latest: Mutex<Option<RunResult>>,
+    /// Server-sent event frames, for every `/api/events` client
+    events: broadcast::Sender<String>,
+}
+
+impl Server {
+    fn handle(self: Arc<Self>, request: Request<Incoming>) -> Response<Body> {
+        if !is_direct_host(request.headers()) {
+            return error_response(StatusCode::FORBIDDEN, "unexpected Host header");
+        }
+        let cors = match allowed_origin(request.headers(), &self.allowed_origins) {
+            Ok(cors) => cors,
+            Err(message) => return error_response(StatusCode::FORBIDDEN, message),
+        };
+        let path = request.uri().path().trim_end_matches('/');
+        let mut response = match (request.method(), path) {
+            (&Method::GET, "/api/events") => self.stream_events(),
+            (&Method::POST, "/api/run") => match check_run_request(request.headers()) {
+                Ok(()) => self.start_run(),
+                Err((status, message)) => error_response(status, message),
+            },
+            (&Method::GET, "/api/status") => {
+                let running = self.running.lock().unwrap().clone();
+                json_response(
+                    StatusCode::OK,
+                    &serde_json::json!({ "running": running.is_some(), "meas_id": running }),
+                )
+            }
+            (&Method::GET, "/api/results/latest") => match self.latest() {
+                Ok(Some(result)) => json_response(StatusCode::OK, &result),
+                Ok(None) => error_response(StatusCode::NOT_FOUND, "no results yet"),
+                Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &format!("{:#}", e)),
+            },
+            (&Method::GET, "/api/results") => match run_query(&query_params(&request)) {
+                Ok(query) => match crate::storage::query_runs(&query) {
+                    Ok(runs) => json_response(StatusCode::OK, &runs),
+                    Err(e) => {
+                        error_response(StatusCode::INTERNAL_SERVER_ERROR, &format!("{:#}", e))
+                    }
+                },
+                Err(e) => error_response(StatusCode::BAD_REQUEST, &e),
+            },
+            // CORS preflight of an allowed dashboard's JSON POST
+            (&Method::OPTIONS, _) if cors.is_some() && ENDPOINTS.contains(&path) => {
+                Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .header(header::ACCESS_CONTROL_ALLOW_METHODS, "GET, POST")
+                    .header(header::ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type")
+                    .body(Full::default().boxed())
+                    .expect("valid response")
+            }
+            _ if ENDPOINTS.contains(&path) => {
+                error_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+            }
+            _ => error_response(StatusCode::NOT_FOUND, "not found"),
+        };
+        if let Some(origin) = cors {
+            let headers = response.headers_mut();
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            headers.insert(header::VARY, header::HeaderValue::from_static("Origin"));
+        }
+        response
+    }
+
+    /// Start a test in the background unless one is running.
+    fn start_run(self: &Arc<Self>) -> Response<Body> {
+        let cfg = build_config(&self.args);
+        let meas_id = cfg.meas_id.clone();
+        {
+            let mut running = self.running.lock().unwrap();
+            if let Some(other) = running.as_ref() {
+                return json_response(
+                    StatusCode::CONFLICT,
+                    &serde_json::json!({ "error": "a test is already running", "meas_id": other }),
+                );
+            }
+            *running = Some(meas_id.clone());
+        }
+        self.emit(Some("started"), &serde_json::json!({ "meas_id": meas_id }));
+        tokio::spawn(self.clone().run(cfg));
+        json_response(
+            StatusCode::ACCEPTED,
+            &serde_json::json!({ "meas_id": meas_id }),
+        )
+    }
+    /// Run a test, publish its result and tell the event streams how it ended.
+    async fn run(self: Arc<Self>, cfg: RunConfig) {
+        let meas_id = cfg.meas_id.clone();
+        match self.run_test(cfg).await {
+            Ok(result) => {
+                if let Err(e) = crate::cli::publish(&self.sinks, &result, true).await {
+                    tracing::error!("{:#}", e);
+                }
+                *self.latest.lock().unwrap() = Some(result.clone());
+                // Clients may start the next test as soon as they see the result
+                *self.running.lock().unwrap() = None;
+                self.emit(Some("result"), &result);
+            }
+            Err(e) => {
+                tracing::error!("API run {} failed: {:#}", meas_id, e);
+                *self.running.lock().unwrap() = None;
+                self.emit(
+                    Some("error"),
+                    &serde_json::json!({ "meas_id": meas_id, "error": format!("{:#}", e) }),
+                );
+            }
+        }
+    }
+
+    /// Run the engine, forwarding its events to the event streams; returns the enriched
+    /// result.
+    async fn run_test(&self, cfg: RunConfig) -> Result<RunResult> {
+        let network_info = crate::network::gather_network_info(self.args.interface.as_deref());
+        let (evt_tx, mut evt_rx) = mpsc::channel::<TestEvent>(2048);
+        // Nothing pauses or cancels a test started over the API
+        let (_ctrl_tx, ctrl_rx) = mpsc::channel(1);
+        let handle = tokio::spawn(TestEngine::new(cfg).run(evt_tx, ctrl_rx));
+        while let Some(ev) = evt_rx.recv().await {
+            self.emit(None, &ev);
+        }
+        let result = handle
+            .await
+            .context("test engine task failed")?
+            .context("speed test failed")?;
+        Ok(crate::network::enrich_result(&result, &network_info))
+    }
+
+    /// Send `data` to every event stream, as an `event` or as a plain message.
+    fn emit(&self, event: Option<&str>, data: &impl Serialize) {
+        let Ok(data) = serde_json::to_string(data) else {
+            return;
+        };
+        let frame = match event {
+            Some(event) => format!("event: {}\ndata: {}\n\n", event, data),
+            None => format!("data: {}\n\n", data),
+        };
+        // Fails only when nobody is listening
+        self.events.send(frame).ok();
+    }
+
+    /// Newest of the last result run here and the newest saved one, which may come from a
+    /// run outside the server.
+    fn latest(&self) -> Result<Option<RunResult>> {
+        let saved = crate::storage::load_recent(1)?.into_iter().next();
+        let here = self.latest.lock().unwrap().clone();
+        Ok([saved, here]
+            .into_iter()
+            .flatten()
+            .max_by(|a, b| a.timestamp_utc.cmp(&b.timestamp_utc)))
+    }
+
+    /// Server-sent events from now on, opened with a comment and kept alive with more.
+    fn stream_events(&self) -> Response<Body> {
+        let frames = self.events.subscribe();
+        let keepalive =
+            tokio::time::interval_at(tokio::time::Instant::now() + KEEPALIVE, KEEPALIVE);
+        let events = futures::stream::unfold(
+            (frames, keepalive),
+            |(mut frames, mut keepalive)| async move {
+                let frame = loop {
+                    tokio::select! {
+                        frame = frames.recv() => match frame {
+                            Ok(frame) => break frame,
+                            // A slow client misses events rather than holding up the test
+                            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(broadcast::error::RecvError::Closed) => return None,
+                        },
+                        // Also notices clients that went away between tests
+                        _ = keepalive.tick() => break ": keepalive\n\n".to_string(),
+                    }
+                };
+                Some((Ok(Frame::data(Bytes::from(frame))), (frames, keepalive)))
+            },
+        );
+        let connected = Frame::data(Bytes::from(": connected\n\n"));
+        let body = futures::stream::once(async { Ok(connected) }).chain(events);
+        Response::builder()
+            .header(header::CONTENT_TYPE, "text/event-stream")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(BodyExt::boxed(StreamBody::new(body)))
+            .expect("valid response")
+    }
+}
+
+/// Whether the Host header names the server by IP address or as `localhost`. A page
+/// that rebinds its own domain name to this address still sends that name.
+fn is_direct_host(headers: &HeaderMap) -> bool {
+    let Some(host) = headers.get(header::HOST).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let Ok(url) = reqwest::Url::parse(&format!("http://{}", host)) else {
+        return false;
+    };
+    url.host_str().is_some_and(|name| {
+        name.eq_ignore_ascii_case("localhost")
+            || name
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<std::net::IpAddr>()
+                .is_ok()
+    })
+}
+
+/// The `Origin` to answer with CORS headers: none for same-origin and non-browser
+/// requests, the origin itself when `--api-allow-origin` lists it. Any other origin is
+/// refused, so pages elsewhere can neither read results nor start tests.
+fn allowed_origin(
+    headers: &HeaderMap,
+    allowed: &[String],
+) -> Result<Option<header::HeaderValue>, &'static str> {
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return Ok(None);
+    };
+    let text = origin.to_str().unwrap_or_default();
+    let authority = |url: &str| {
+        reqwest::Url::parse(url)
+            .ok()
+            .map(|u| (u.host_str().map(str::to_owned), u.port_or_known_default()))
+    };
+    let host = headers
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| authority(&format!("http://{}", h)));
+    if host.is_some() && authority(text) == host {
+        return Ok(None);
+    }
+    if allowed
+        .iter()
+        .any(|a| a.trim_end_matches('/').eq_ignore_ascii_case(text))
+    {
+        return Ok(Some(origin.clone()));
+    }
+    Err("cross-origin requests are refused; allow the origin with --api-allow-origin")
+}
+
+/// Refuse a `POST /api/run` without a JSON content type, which a cross-site form could
+/// have sent.
+fn check_run_request(headers: &HeaderMap) -> Result<(), (StatusCode, &'static str)> {
+    let json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .eq_ignore_ascii_case("application/json")
+        });
+    if !json {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "send Content-Type: application/json",
+        ));
+    }
+    Ok(())
+}
+
+/// The query parameters of `request`
+fn query_params(request: &Request<Incoming>) -> HashMap<String, String> {
+    let target = request.uri().path_and_query().map_or("/", |p| p.as_str());
+    reqwest::Url::parse(&format!("http://localhost{}", target))
+        .map(|url| url.query_pairs().into_owned().collect())
+        .unwrap_or_default()
+}
+
+/// The history filters of `/api/results`
+fn run_query(params: &HashMap<String, String>) -> Result<RunQuery, String> {
+    fn parse<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+        value
+            .parse()
+            .map_err(|_| format!("invalid {} '{}'", name, value))
+    }
+    let mut query = RunQuery {
+        limit: DEFAULT_LIMIT,
+        ..Default::default()
+    };
+    for (name, value) in params {
+        match name.as_str() {
+            "since" => query.since = Some(value.clone()),
+            "before" => query.before = Some(value.clone()),
+            "interface" => query.interface = Some(value.clone()),
+            "colo" => query.colo = Some(value.clone()),
+            "vpn" => query.vpn = Some(parse(name, value)?),
+            "offset" => query.offset = parse(name, value)?,
+            "limit" => query.limit = parse(name, value)?,
+            _ => return Err(format!("unknown parameter '{}'", name)),
+        }
+    }
+    Ok(query)
+}
+
+fn json_response(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    let body = serde_json::to_string_pretty(body).unwrap_or_default();
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body)).boxed())
+        .expect("valid response")
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(status, &serde_json::json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_query_from_params() {
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let query = run_query(&params(&[
+            ("since", "2026-01-31"),
+            ("colo", "AMS"),
+            ("vpn", "false"),
+            ("limit", "5"),
+        ]))
+        .unwrap();
+        assert_eq!(query.since.as_deref(), Some("2026-01-31"));
+        assert_eq!(query.colo.as_deref(), Some("AMS"));
+        assert_eq!(query.vpn, Some(false));
+        assert_eq!((query.offset, query.limit), (0, 5));
+        assert_eq!(run_query(&params(&[])).unwrap().limit, DEFAULT_LIMIT);
+        assert!(run_query(&params(&[("vpn", "maybe")])).is_err());
+        assert!(run_query(&params(&[("color", "blue")])).is_err());
+    }
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_host_must_be_an_address_or_localhost() {
+        for host in [
+            "127.0.0.1:8080",
+            "[::1]:8080",
+            "localhost:8080",
+            "192.168.1.5",
+        ] {
+            assert!(is_direct_host(&headers(&[(header::HOST, host)])), "{host}");
+        }
+        assert!(!is_direct_host(&headers(&[(
+            header::HOST,
+            "rebind.example:8080"
+        )])));
+        assert!(!is_direct_host(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_run_request_needs_json() {
+        let json = (header::CONTENT_TYPE, "application/json; charset=utf-8");
+        assert!(check_run_request(&headers(&[json])).is_ok());
+        let form = (header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+        assert_eq!(
+            check_run_request(&headers(&[form])).unwrap_err().0,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert!(check_run_request(&HeaderMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_only_same_or_allowed_origins() {
+        let host = (header::HOST, "127.0.0.1:8080");
+        let allowed = ["https://dash.example/".to_string()];
+        let origin = |o: &'static str| headers(&[host.clone(), (header::ORIGIN, o)]);
+
+        assert_eq!(
+            allowed_origin(&headers(std::slice::from_ref(&host)), &allowed),
+            Ok(None)
+        );
+        assert_eq!(
+            allowed_origin(&origin("http://127.0.0.1:8080"), &allowed),
+            Ok(None)
+        );
+        assert_eq!(
+            allowed_origin(&origin("https://dash.example"), &allowed)
+                .unwrap()
+                .unwrap(),
+            "https://dash.example"
+        );
+        assert!(allowed_origin(&origin("https://evil.example"), &allowed).is_err());
+        assert!(allowed_origin(&origin("https://dash.example"), &[]).is_err());
+    }
+}
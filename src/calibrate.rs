@@ -0,0 +1,274 @@
+//! Validation against the speed.cloudflare.com browser test (`--calibrate`).
+//!
+//! Runs the regular text-mode test, then compares it with a browser result entered via
+//! flags or interactively. The browser reports a high percentile of individual
+//! transfers rather than a whole-phase average, so our p75 is used as the normalized
+//! figure when judging the deviation.
+
+use crate::cli::Cli;
+use crate::model::RunResult;
+use anyhow::{Context, Result};
+use std::io::{BufRead, IsTerminal, Write};
+
+/// Throughput deviations beyond this (in percent) trigger an adjustment suggestion
+const THROUGHPUT_TOLERANCE_PCT: f64 = 10.0;
+
+/// Latency deviations beyond this (in percent and in ms) trigger a suggestion
+const LATENCY_TOLERANCE_PCT: f64 = 20.0;
+const LATENCY_TOLERANCE_MS: f64 = 3.0;
+
+/// Upper bound for suggested --concurrency values
+const MAX_SUGGESTED_CONCURRENCY: usize = 16;
+
+/// Phase length below which a longer duration is suggested for slow results
+const MIN_SUGGESTED_DURATION_SECS: u64 = 15;
+
+/// Numbers read off the speed.cloudflare.com result page
+#[derive(Debug, Clone, Default)]
+pub struct BrowserResult {
+    pub download_mbps: Option<f64>,
+    pub upload_mbps: Option<f64>,
+    pub latency_ms: Option<f64>,
+}
+
+#[derive(Debug)]
+struct Row {
+    metric: &'static str,
+    unit: &'static str,
+    ours: Option<f64>,
+    normalized: Option<f64>,
+    browser: Option<f64>,
+}
+
+impl Row {
+    /// Deviation of our normalized value from the browser's, in percent
+    fn deviation_pct(&self) -> Option<f64> {
+        let (n, b) = (self.normalized?, self.browser?);
+        (b != 0.0).then(|| (n - b) / b * 100.0)
+    }
+}
+
+fn rows(result: &RunResult, browser: &BrowserResult) -> Vec<Row> {
+    vec![
+        Row {
+            metric: "Download",
+            unit: "Mbps",
+            ours: Some(result.download.mbps),
+            normalized: result.download.p75_mbps.or(Some(result.download.mbps)),
+            browser: browser.download_mbps,
+        },
+        Row {
+            metric: "Upload",
+            unit: "Mbps",
+            ours: Some(result.upload.mbps),
+            normalized: result.upload.p75_mbps.or(Some(result.upload.mbps)),
+            browser: browser.upload_mbps,
+        },
+        Row {
+            metric: "Idle latency",
+            unit: "ms",
+            ours: result.idle_latency.mean_ms,
+            normalized: result.idle_latency.median_ms,
+            browser: browser.latency_ms,
+        },
+    ]
+}
+
+fn throughput_suggestions(
+    out: &mut Vec<String>,
+    row: &Row,
+    concurrency: usize,
+    duration_flag: &str,
+    duration_secs: u64,
+) {
+    let Some(dev) = row.deviation_pct() else {
+        return;
+    };
+    if dev < -THROUGHPUT_TOLERANCE_PCT {
+        let more = (concurrency * 2).min(MAX_SUGGESTED_CONCURRENCY);
+        if more > concurrency {
+            out.push(format!(
+                "{} is {:.0}% below the browser: try --concurrency {} to fill the pipe like the browser's parallel transfers",
+                row.metric, -dev, more
+            ));
+        }
+        if duration_secs < MIN_SUGGESTED_DURATION_SECS {
+            out.push(format!(
+                "{} is {:.0}% below the browser: try {} {}s so TCP has time to ramp up",
+                row.metric, -dev, duration_flag, MIN_SUGGESTED_DURATION_SECS
+            ));
+        }
+    } else if dev > THROUGHPUT_TOLERANCE_PCT {
+        let fewer = (concurrency / 2).max(1);
+        if fewer < concurrency {
+            out.push(format!(
+                "{} is {:.0}% above the browser: the browser uses fewer simultaneous transfers; try --concurrency {}",
+                row.metric, dev, fewer
+            ));
+        }
+    }
+}
+
+/// Suggest flag changes that move this tool's methodology closer to the browser test.
+fn suggestions(rows: &[Row], args: &Cli) -> Vec<String> {
+    let mut out = Vec::new();
+    throughput_suggestions(
+        &mut out,
+        &rows[0],
+        args.concurrency,
+        "--download-duration",
+        std::time::Duration::from(args.download_duration).as_secs(),
+    );
+    throughput_suggestions(
+        &mut out,
+        &rows[1],
+        args.concurrency,
+        "--upload-duration",
+        std::time::Duration::from(args.upload_duration).as_secs(),
+    );
+
+    let latency = &rows[2];
+    if let (Some(dev), Some(n), Some(b)) =
+        (latency.deviation_pct(), latency.normalized, latency.browser)
+    {
+        if dev.abs() > LATENCY_TOLERANCE_PCT && (n - b).abs() > LATENCY_TOLERANCE_MS {
+            out.push(format!(
+                "Idle latency differs by {:+.1} ms: the browser measures over warm connections; a longer --idle-latency-duration smooths out spikes",
+                n - b
+            ));
+        }
+    }
+    out
+}
+
+fn fmt_cell(v: Option<f64>) -> String {
+    v.map(|v| format!("{:.1}", v))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn format_table(rows: &[Row]) -> String {
+    let mut out = format!(
+        "{:<20} {:>10} {:>11} {:>10} {:>10}\n",
+        "metric", "this tool", "normalized", "browser", "deviation"
+    );
+    for r in rows {
+        out.push_str(&format!(
+            "{:<20} {:>10} {:>11} {:>10} {:>10}\n",
+            format!("{} ({})", r.metric, r.unit),
+            fmt_cell(r.ours),
+            fmt_cell(r.normalized),
+            fmt_cell(r.browser),
+            r.deviation_pct()
+                .map(|d| format!("{:+.1}%", d))
+                .unwrap_or_else(|| "-".to_string())
+        ));
+    }
+    out
+}
+
+/// Ask for a value on stderr/stdin; an empty answer skips the metric.
+fn prompt(question: &str) -> Result<Option<f64>> {
+    let stdin = std::io::stdin();
+    loop {
+        eprint!("{}: ", question);
+        std::io::stderr().flush().ok();
+        let mut line = String::new();
+        stdin
+            .lock()
+            .read_line(&mut line)
+            .context("read browser result")?;
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+        match line.parse::<f64>() {
+            Ok(v) if v >= 0.0 => return Ok(Some(v)),
+            _ => eprintln!("Please enter a number (or leave empty to skip)"),
+        }
+    }
+}
+
+/// Fill in browser numbers not given as flags, asking interactively when possible.
+fn complete_browser_result(mut browser: BrowserResult) -> Result<BrowserResult> {
+    if browser.download_mbps.is_some() || browser.upload_mbps.is_some() {
+        return Ok(browser);
+    }
+    anyhow::ensure!(
+        std::io::stdin().is_terminal(),
+        "--calibrate needs --browser-download/--browser-upload when stdin is not a terminal"
+    );
+    eprintln!();
+    eprintln!("Enter the numbers shown by speed.cloudflare.com (leave empty to skip):");
+    browser.download_mbps = prompt("Browser download (Mbps)")?;
+    browser.upload_mbps = prompt("Browser upload (Mbps)")?;
+    browser.latency_ms = prompt("Browser latency (ms)")?;
+    Ok(browser)
+}
+
+/// Entry point for `--calibrate`.
+pub async fn run(args: Cli) -> Result<()> {
+    eprintln!("Calibration: run https://speed.cloudflare.com in a browser on this machine");
+    eprintln!("right before or after this test, with nothing else using the network.");
+    eprintln!();
+
+    let browser = BrowserResult {
+        download_mbps: args.browser_download,
+        upload_mbps: args.browser_upload,
+        latency_ms: args.browser_latency,
+    };
+    let result = crate::cli::run_text(args.clone()).await?;
+    let browser = complete_browser_result(browser)?;
+
+    let rows = rows(&result, &browser);
+    println!();
+    println!(
+        "Calibration against speed.cloudflare.com (normalized = p75 throughput, median latency):"
+    );
+    print!("{}", format_table(&rows));
+
+    let tips = suggestions(&rows, &args);
+    if tips.is_empty() {
+        println!(
+            "Within {:.0}% of the browser; no adjustment needed.",
+            THROUGHPUT_TOLERANCE_PCT
+        );
+    } else {
+        for tip in tips {
+            println!("- {}", tip);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn row(metric: &'static str, normalized: f64, browser: f64) -> Row {
+        Row {
+            metric,
+            unit: "Mbps",
+            ours: Some(normalized),
+            normalized: Some(normalized),
+            browser: Some(browser),
+        }
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let args = Cli::parse_from(["cloudflare-speed-cli", "--concurrency", "4"]);
+        let rows = [
+            row("Download", 50.0, 100.0),
+            row("Upload", 40.0, 20.0),
+            row("Idle latency", 10.0, 10.5),
+        ];
+        assert!((rows[0].deviation_pct().unwrap() + 50.0).abs() < 1e-9);
+
+        let tips = suggestions(&rows, &args);
+        assert_eq!(tips.len(), 3);
+        assert!(tips[0].contains("--concurrency 8"));
+        assert!(tips[1].contains("--download-duration 15s"));
+        assert!(tips[2].contains("--concurrency 2"));
+    }
+}
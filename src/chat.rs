@@ -0,0 +1,157 @@
+//! Chat notifications: Slack, Discord and Telegram (`--slack-webhook` and friends)
+//!
+//! Each result is posted as a short summary. In monitor mode, results that start or stop
+//! missing the `--alert-*` thresholds are posted as alerts too, and with
+//! `--chat-alerts-only` nothing else is. The webhook URLs and bot token are credentials,
+//! so they are best kept in the configuration file (`slack_webhook = "https://..."`).
+
+use crate::cli::{fmt_opt, Cli};
+use crate::model::RunResult;
+use crate::sinks::ChatTarget;
+use crate::thresholds::Transition;
+use anyhow::Result;
+use serde_json::json;
+
+/// Telegram Bot API endpoint
+const TELEGRAM_API: &str = "https://api.telegram.org";
+
+/// Discord rejects messages longer than this
+const DISCORD_MAX_CHARS: usize = 2000;
+
+/// The chat services configured on the command line or in the configuration file
+pub fn targets(args: &Cli) -> Vec<ChatTarget> {
+    let mut targets = Vec::new();
+    if let Some(url) = &args.slack_webhook {
+        targets.push(ChatTarget::Slack(url.clone()));
+    }
+    if let Some(url) = &args.discord_webhook {
+        targets.push(ChatTarget::Discord(url.clone()));
+    }
+    if let (Some(token), Some(chat_id)) = (&args.telegram_bot_token, &args.telegram_chat_id) {
+        targets.push(ChatTarget::Telegram {
+            token: token.clone(),
+            chat_id: chat_id.clone(),
+        });
+    }
+    targets
+}
+
+impl ChatTarget {
+    /// Name used in status lines and errors; never the URL, which is the secret
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChatTarget::Slack(_) => "Slack",
+            ChatTarget::Discord(_) => "Discord",
+            ChatTarget::Telegram { .. } => "Telegram",
+        }
+    }
+
+    /// Post a plain text message.
+    pub async fn post(&self, text: &str) -> Result<()> {
+        let (url, body) = match self {
+            ChatTarget::Slack(url) => (url.clone(), json!({ "text": text })),
+            ChatTarget::Discord(url) => (
+                url.clone(),
+                json!({ "content": text.chars().take(DISCORD_MAX_CHARS).collect::<String>() }),
+            ),
+            ChatTarget::Telegram { token, chat_id } => (
+                format!("{}/bot{}/sendMessage", TELEGRAM_API, token),
+                json!({ "chat_id": chat_id, "text": text, "disable_web_page_preview": true }),
+            ),
+        };
+        crate::sinks::post_json(&url, body.to_string(), self.name()).await
+    }
+}
+
+/// Post a monitor alert (or recovery) to every target, logging the ones that fail.
+pub async fn alert(targets: &[ChatTarget], transition: &Transition, run: &RunResult) {
+    let text = format!("{}\n{}", transition, summary(run));
+    for target in targets {
+        if let Err(e) = target.post(&text).await {
+            tracing::error!("{} alert failed: {:#}", target.name(), e);
+        }
+    }
+}
+
+/// A few lines describing a result: where it ran, throughput and latency, e.g.
+///
+/// ```text
+/// Speed test on home-wifi (wlan0) via AMS, AS13335
+/// Download 94.2 Mbit/s, Upload 19.8 Mbit/s
+/// Idle latency 12.1 ms, jitter 0.8 ms, loss 0.0%
+/// ```
+pub fn summary(r: &RunResult) -> String {
+    let mut heading = String::from("Speed test");
+    match (&r.network_name, &r.interface_name) {
+        (Some(network), Some(iface)) => heading.push_str(&format!(" on {} ({})", network, iface)),
+        (Some(name), None) | (None, Some(name)) => heading.push_str(&format!(" on {}", name)),
+        (None, None) => {}
+    }
+    if let Some(colo) = &r.colo {
+        heading.push_str(&format!(" via {}", colo));
+    }
+    if let Some(asn) = &r.asn {
+        heading.push_str(&format!(", AS{}", asn.trim_start_matches("AS")));
+    }
+    if r.partial {
+        heading.push_str(" (partial)");
+    }
+
+    let mut lines = vec![heading];
+    let mut throughput = Vec::new();
+    if r.download.bytes > 0 {
+        throughput.push(format!("Download {:.1} Mbit/s", r.download.mbps));
+    }
+    if r.upload.bytes > 0 {
+        throughput.push(format!("Upload {:.1} Mbit/s", r.upload.mbps));
+    }
+    if !throughput.is_empty() {
+        lines.push(throughput.join(", "));
+    }
+    let mut latency = format!(
+        "Idle latency {} ms, jitter {} ms",
+        fmt_opt(r.idle_latency.median_ms),
+        fmt_opt(r.idle_latency.jitter_ms)
+    );
+    if let Some(loss) = crate::thresholds::loss_pct(r) {
+        latency.push_str(&format!(", loss {:.1}%", loss));
+    }
+    lines.push(latency);
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{LatencySummary, ThroughputSummary};
+
+    #[test]
+    fn test_summary() {
+        let run: RunResult = serde_json::from_value(json!({
+            "timestamp_utc": "2026-01-01T00:00:00Z",
+            "base_url": "https://speed.cloudflare.com",
+            "meas_id": "1",
+            "idle_latency": LatencySummary {
+                sent: 20,
+                received: 20,
+                median_ms: Some(12.06),
+                jitter_ms: Some(0.8),
+                ..Default::default()
+            },
+            "download": ThroughputSummary::default(),
+            "upload": ThroughputSummary { bytes: 1, mbps: 19.84, ..Default::default() },
+            "loaded_latency_download": LatencySummary::default(),
+            "loaded_latency_upload": LatencySummary::default(),
+            "colo": "AMS",
+            "asn": "13335",
+            "interface_name": "wlan0",
+        }))
+        .unwrap();
+        assert_eq!(
+            summary(&run),
+            "Speed test on wlan0 via AMS, AS13335\n\
+             Upload 19.8 Mbit/s\n\
+             Idle latency 12.1 ms, jitter 0.8 ms, loss 0.0%"
+        );
+    }
+}
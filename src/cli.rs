@@ -1,8 +1,10 @@
 use crate::engine::{EngineControl, TestEngine};
-use crate::model::{RunConfig, TestEvent};
+use crate::model::{
+    Backend, HttpVersion, PhaseSelection, ResolveOverride, RunConfig, RunResult, SteadyWindow,
+    TestEvent, TracerouteProtocol,
+};
 use anyhow::{Context, Result};
-use clap::Parser;
-use rand::RngCore;
+use clap::{Parser, Subcommand};
 use std::time::Duration;
 use tokio::sync::mpsc;
 
@@ -13,9 +15,14 @@ use tokio::sync::mpsc;
     about = "Cloudflare-based speed test with optional TUI"
 )]
 pub struct Cli {
-    /// Base URL for the Cloudflare speed test service
-    #[arg(long, default_value = "https://speed.cloudflare.com")]
-    pub base_url: String,
+    /// Base URL for the Cloudflare speed test service; repeat it to list fallbacks, tried
+    /// in order when the ones before do not answer
+    #[arg(
+        long,
+        value_name = "URL",
+        default_value = "https://speed.cloudflare.com"
+    )]
+    pub base_url: Vec<String>,
 
     /// Print JSON result and exit (no TUI)
     #[arg(long)]
@@ -29,16 +36,29 @@ pub struct Cli {
     #[arg(long)]
     pub silent: bool,
 
+    /// Stream every engine event (phase starts, latency samples, throughput ticks) to stdout
+    /// as one JSON object per line, ending with the result (no TUI)
+    #[arg(long, conflicts_with_all = ["json", "text", "silent", "monitor", "count", "calibrate"])]
+    pub events_ndjson: bool,
+
+    /// Log more detail to stderr: -v for debug messages, -vv for trace
+    #[arg(short, long, action = clap::ArgAction::Count, global = true)]
+    pub verbose: u8,
+
+    /// Log only errors to stderr (no notices or warnings)
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
     /// Download phase duration
-    #[arg(long, default_value = "10s")]
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
     pub download_duration: humantime::Duration,
 
     /// Upload phase duration
-    #[arg(long, default_value = "10s")]
+    #[arg(long, default_value = "10s", value_parser = parse_duration)]
     pub upload_duration: humantime::Duration,
 
     /// Idle latency probe duration (pre-test)
-    #[arg(long, default_value = "2s")]
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
     pub idle_latency_duration: humantime::Duration,
 
     /// Concurrency for download/upload workers
@@ -61,6 +81,36 @@ pub struct Cli {
     #[arg(long, default_value_t = 800)]
     pub probe_timeout_ms: u64,
 
+    /// Hard limit for the whole run, a --sync-start wait included; the test is cancelled and partial results are kept (e.g. 2m)
+    #[arg(long, value_parser = parse_duration)]
+    pub max_run_time: Option<humantime::Duration>,
+
+    /// Stop the download and upload phases (and the ramped, sweep and protocol comparison
+    /// transfers) early once they have transferred this much data in total, for metered
+    /// connections (e.g. 200MB, 1.5GB)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_total_bytes: Option<u64>,
+
+    /// Leave the first part of download and upload out of their figures [default: 20% of
+    /// the phase, at least 1s]
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub warmup: Option<humantime::Duration>,
+
+    /// Part of download and upload their figures come from: everything after the warm-up
+    /// (trimmed), only the last stretch (e.g. last-5s) or the whole phase (full)
+    #[arg(long, value_name = "WINDOW", default_value = "trimmed")]
+    pub steady_window: SteadyWindow,
+
+    /// End the download and upload phases as soon as throughput is stable; their
+    /// durations become the maximum
+    #[arg(long)]
+    pub adaptive: bool,
+
+    /// Throughput counts as stable once the coefficient of variation of its one-second
+    /// rate over the last two seconds is below this percentage
+    #[arg(long, value_name = "PCT", default_value_t = 5.0, requires = "adaptive")]
+    pub adaptive_cv: f64,
+
     /// Reserved for future experimental features
     #[arg(long)]
     pub experimental: bool,
@@ -73,6 +123,83 @@ pub struct Cli {
     #[arg(long)]
     pub export_csv: Option<std::path::PathBuf>,
 
+    /// Record every measurement request (URL, timings, status, sizes) into a HAR file
+    #[arg(long, value_name = "PATH")]
+    pub export_har: Option<std::path::PathBuf>,
+
+    /// Export results in InfluxDB line protocol: appended to a file, or POSTed when given a
+    /// write URL (e.g. http://localhost:8086/api/v2/write?org=home&bucket=speedtest)
+    #[arg(long, value_name = "PATH|URL")]
+    pub export_influx: Option<String>,
+
+    /// API token for --export-influx URLs (default: $INFLUX_TOKEN)
+    #[arg(long, value_name = "TOKEN")]
+    pub influx_token: Option<String>,
+
+    /// Publish each result to an MQTT broker: mqtt://[user:pass@]host[:port] (mqtts:// for TLS)
+    #[arg(long, value_name = "URL")]
+    pub mqtt: Option<String>,
+
+    /// Base MQTT topic; results are published retained to TOPIC/state
+    #[arg(long, value_name = "TOPIC", default_value = crate::sinks::MQTT_DEFAULT_TOPIC)]
+    pub mqtt_topic: String,
+
+    /// Publish Home Assistant MQTT discovery configs so the results appear as sensors
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub mqtt_discovery: bool,
+
+    /// Compress run files saved to the history
+    #[arg(long, value_enum, default_value_t = crate::storage::Compression::None)]
+    pub compress: crate::storage::Compression,
+
+    /// Delete saved runs older than this many days whenever a run is saved
+    #[arg(long, value_name = "DAYS")]
+    pub keep_days: Option<u64>,
+
+    /// Keep at most this many saved runs, deleting the oldest whenever a run is saved
+    #[arg(long, value_name = "N")]
+    pub keep_runs: Option<usize>,
+
+    /// POST each completed result as JSON to this URL (retried on errors)
+    #[arg(long, value_name = "URL")]
+    pub webhook_url: Option<String>,
+
+    /// Webhook body instead of the result JSON; {{field.path}} is replaced with values from
+    /// the result and {{json}} with all of it. Use @FILE to read it from a file
+    #[arg(long, value_name = "TEMPLATE")]
+    pub webhook_template: Option<String>,
+
+    /// Content-Type of --webhook-template bodies (default: application/json, which also
+    /// JSON-escapes the strings filled in)
+    #[arg(long, value_name = "TYPE", requires = "webhook_template")]
+    pub webhook_content_type: Option<String>,
+
+    /// Also publish a snapshot after each phase to --mqtt (on TOPIC/phase) and
+    /// --webhook-url (with a "phase" field), e.g. the download while the upload runs
+    #[arg(long)]
+    pub publish_phases: bool,
+
+    /// Post a summary of each result to this Slack incoming webhook URL
+    #[arg(long, value_name = "URL")]
+    pub slack_webhook: Option<String>,
+
+    /// Post a summary of each result to this Discord channel webhook URL
+    #[arg(long, value_name = "URL")]
+    pub discord_webhook: Option<String>,
+
+    /// Post a summary of each result through this Telegram bot (needs --telegram-chat-id)
+    #[arg(long, value_name = "TOKEN", requires = "telegram_chat_id")]
+    pub telegram_bot_token: Option<String>,
+
+    /// Telegram chat (user, group or channel id) the bot posts to
+    #[arg(long, value_name = "ID", requires = "telegram_bot_token")]
+    pub telegram_chat_id: Option<String>,
+
+    /// In monitor mode, post to Slack, Discord and Telegram only when results start or
+    /// stop missing the --alert-* thresholds
+    #[arg(long, requires = "monitor")]
+    pub chat_alerts_only: bool,
+
     /// Use --auto-save true or --auto-save false to override
     #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
     pub auto_save: bool,
@@ -89,22 +216,121 @@ pub struct Cli {
     #[arg(long)]
     pub proxy: Option<String>,
 
+    /// Ignore proxies from HTTP_PROXY/HTTPS_PROXY/ALL_PROXY and OS settings
+    #[arg(long, conflicts_with = "proxy")]
+    pub no_proxy: bool,
+
     /// Path to a custom TLS certificate file (PEM or DER format)
     #[arg(long)]
     pub certificate: Option<std::path::PathBuf>,
 
+    /// Client certificate (PEM) for endpoints that require mutual TLS
+    #[arg(long, requires = "client_key")]
+    pub client_cert: Option<std::path::PathBuf>,
+
+    /// Private key (PEM) for --client-cert
+    #[arg(long, requires = "client_cert")]
+    pub client_key: Option<std::path::PathBuf>,
+
+    /// Resolve the test endpoint with this DNS server instead of the system resolver
+    #[arg(long, value_name = "IP[:PORT]", value_parser = crate::engine::resolver::parse_server)]
+    pub dns_server: Option<std::net::SocketAddr>,
+
+    /// Resolve the test endpoint with this DNS-over-HTTPS server, e.g.
+    /// https://cloudflare-dns.com/dns-query
+    #[arg(long = "doh", value_name = "URL", value_parser = parse_doh, conflicts_with = "dns_server")]
+    pub doh: Option<String>,
+
+    /// Resolve the test endpoint with this DNS-over-TLS server (port 853 by default)
+    #[arg(
+        long = "dot",
+        value_name = "HOST[:PORT]",
+        value_parser = parse_dot,
+        conflicts_with_all = ["dns_server", "doh"]
+    )]
+    pub dot: Option<String>,
+
+    /// Send an extra HTTP header with every test request, e.g. "X-Auth: token" (repeatable)
+    #[arg(long = "header", value_name = "NAME: VALUE", value_parser = parse_header)]
+    pub headers: Vec<(String, String)>,
+
+    /// Kind of server at --base-url: cloudflare (speed.cloudflare.com or a Worker serving
+    /// its endpoints) or librespeed (the directory holding a LibreSpeed server's garbage.php)
+    #[arg(long, value_parser = ["cloudflare", "librespeed"], default_value = "cloudflare")]
+    pub backend: String,
+
+    /// Token of a self-hosted endpoint, sent as "Authorization: Bearer TOKEN" with every
+    /// test request
+    #[arg(long, value_name = "TOKEN")]
+    pub endpoint_auth_token: Option<String>,
+
+    /// Path of the download endpoint; "{bytes}" in it is replaced by the size to
+    /// download, which is otherwise sent as the bytes query parameter
+    #[arg(long, value_name = "PATH", default_value = "/__down", value_parser = parse_endpoint_path)]
+    pub down_path: String,
+
+    /// Path of the upload endpoint
+    #[arg(long, value_name = "PATH", default_value = "/__up", value_parser = parse_endpoint_path)]
+    pub up_path: String,
+
+    /// Connect to ADDR for HOST:PORT instead of resolving it, like curl's --resolve
+    /// (repeatable; the entry for the base URL's host and port applies)
+    #[arg(long, value_name = "HOST:PORT:ADDR", value_parser = parse_resolve)]
+    pub resolve: Vec<ResolveOverride>,
+
+    /// Test against this Cloudflare data center (IATA code, e.g. SIN) by asking for it
+    /// with the cf.colo override; the run fails if another one answers
+    #[arg(long, value_name = "IATA", value_parser = parse_colo)]
+    pub colo: Option<String>,
+
+    /// Measure the LAN instead of the internet connection: test against another instance
+    /// running `serve --lan` at HOST[:PORT] (port 7879 by default) over plain TCP and UDP
+    #[arg(
+        long,
+        value_name = "HOST[:PORT]",
+        value_parser = parse_lan_peer,
+        conflicts_with_all = ["colo", "backend"]
+    )]
+    pub lan: Option<String>,
+
+    /// Mark the latency probes and the UDP probe with this DSCP code point (EF, AF41,
+    /// CS1, ... or 0-63) to check how QoS on the path treats them
+    #[arg(long, value_name = "CODE", value_parser = crate::engine::sockets::parse_dscp)]
+    pub dscp: Option<u8>,
+
+    /// TCP congestion control for the test connections, e.g. bbr or cubic, to compare
+    /// algorithms on your path (Linux and FreeBSD)
+    #[arg(long, value_name = "NAME", value_parser = crate::engine::sockets::parse_congestion)]
+    pub congestion: Option<String>,
+
+    /// Socket send buffer for the test connections, e.g. 8MiB, for high-BDP links such as
+    /// satellite or intercontinental paths; the kernel may cap it (see -v)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub send_buffer: Option<u64>,
+
+    /// Socket receive buffer for the test connections, e.g. 8MiB; the kernel may cap it
+    /// (see -v). Set once a connection is up, so the receive window cannot grow past the
+    /// window scale agreed in the handshake from the system's buffer settings
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub recv_buffer: Option<u64>,
+
     /// Automatically start a test when the app launches
     #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
     pub test_on_launch: bool,
 
-    /// Attach custom comments to this run
-    #[arg(long)]
+    /// Attach custom comments to this run (editable in the dashboard with 'c')
+    #[arg(long, visible_alias = "comment")]
     pub comments: Option<String>,
 
     /// Compare IPv4 vs IPv6 performance
     #[arg(long)]
     pub compare_ip_versions: bool,
 
+    /// After the main phases, run a short download over HTTP/1.1, HTTP/2 and (in builds
+    /// with the http3 feature) HTTP/3, recording throughput and time to first byte
+    #[arg(long)]
+    pub compare_protocols: bool,
+
     /// Run traceroute to Cloudflare edge
     #[arg(long)]
     pub traceroute: bool,
@@ -113,24 +339,578 @@ pub struct Cli {
     #[arg(long, default_value_t = 30)]
     pub traceroute_max_hops: u8,
 
-    /// Force IPv4 only (no IPv6)
+    /// Traceroute probes: auto, icmp (raw socket, needs root), udp (high ports) or tcp
+    /// (SYN to 443); udp and tcp work unprivileged on Linux
+    #[arg(
+        long,
+        value_parser = ["auto", "icmp", "udp", "tcp"],
+        default_value = "auto",
+        requires = "traceroute"
+    )]
+    pub traceroute_protocol: String,
+
+    /// Paris traceroute: keep the flow identifiers constant across probes, so ECMP load
+    /// balancing cannot mix hops of different paths into one trace (ICMP or UDP probes)
+    #[arg(long, requires = "traceroute")]
+    pub traceroute_paris: bool,
+
+    /// Trace N flows with different identifiers (implies --traceroute-paris) to find the
+    /// paths ECMP load balancing spreads traffic over
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u8).range(1..=16),
+        requires = "traceroute"
+    )]
+    pub traceroute_paths: u8,
+
+    /// Discover the path MTU toward the Cloudflare edge and check for MSS clamping
+    /// (Linux; ICMP, falling back to UDP)
     #[arg(long)]
+    pub mtu: bool,
+
+    /// Run the whole test over IPv4 only (HTTP, latency probes, UDP probe, traceroute)
+    #[arg(short = '4', long, conflicts_with_all = ["ipv6_only", "compare_ip_versions"])]
     pub ipv4_only: bool,
 
-    /// Force IPv6 only (no IPv4)
-    #[arg(long)]
+    /// Run the whole test over IPv6 only (HTTP, latency probes, UDP probe, traceroute)
+    #[arg(short = '6', long, conflicts_with = "compare_ip_versions")]
     pub ipv6_only: bool,
 
-    /// Skip default diagnostic measurements (DNS, TLS)
+    /// Skip default diagnostic measurements (DNS, TLS, request timing)
     #[arg(long)]
     pub skip_diagnostics: bool,
 
+    /// Check whether the DNS resolver validates DNSSEC, by looking up a signed domain and
+    /// one with deliberately broken signatures
+    #[arg(long, conflicts_with = "skip_diagnostics")]
+    pub dnssec: bool,
+
     /// Number of UDP packets to send for packet loss measurement
     #[arg(long, default_value_t = 50)]
     pub udp_packets: u64,
+
+    /// UDP payload size of each packet loss probe in bytes; 20 is a bare STUN request,
+    /// larger probes are padded (to a multiple of 4)
+    #[arg(long, value_name = "BYTES", default_value_t = 20, value_parser = clap::value_parser!(u16).range(20..=1472))]
+    pub udp_packet_size: u16,
+
+    /// Pause between packet loss probes in milliseconds
+    #[arg(long, default_value_t = 80)]
+    pub udp_interval_ms: u64,
+
+    /// How long each packet loss probe waits for its answer, in milliseconds
+    #[arg(long, default_value_t = 600, value_parser = clap::value_parser!(u64).range(1..))]
+    pub udp_timeout_ms: u64,
+
+    /// Reference anchor to probe during idle latency, as host[:port] or "gateway" (repeatable)
+    #[arg(long = "anchor", value_name = "HOST[:PORT]")]
+    pub anchors: Vec<String>,
+
+    /// Ping the Cloudflare edge over ICMP during idle latency, to compare the network
+    /// round trip with the HTTP probes (raw socket, or an unprivileged ping socket)
+    #[arg(long)]
+    pub icmp_ping: bool,
+
+    /// URL answering with an empty 204, fetched before testing to detect captive portals
+    /// and intercepting proxies
+    #[arg(
+        long,
+        value_name = "URL",
+        default_value = "http://cp.cloudflare.com/generate_204"
+    )]
+    pub captive_portal_url: String,
+
+    /// Skip the captive portal check
+    #[arg(long, conflicts_with = "allow_captive_portal")]
+    pub no_captive_portal_check: bool,
+
+    /// Run the test behind a detected captive portal anyway, flagging the result
+    #[arg(long)]
+    pub allow_captive_portal: bool,
+
+    /// Start at an agreed instant: an RFC 3339 time or the URL of a `coordinate` server
+    #[arg(long, value_name = "RFC3339|URL")]
+    pub sync_start: Option<String>,
+
+    /// Attach a KEY=VALUE pair to the result's context (repeatable)
+    #[arg(long = "context", value_name = "KEY=VALUE", value_parser = crate::context::parse_pair)]
+    pub context: Vec<(String, String)>,
+
+    /// Label the run with KEY=VALUE (e.g. location=office, vpn=on) to group it in history (repeatable)
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = crate::context::parse_pair)]
+    pub tags: Vec<(String, String)>,
+
+    /// Command run after each test whose output (JSON object or KEY=VALUE lines) is added to the result's context
+    #[arg(long, value_name = "COMMAND")]
+    pub context_hook: Option<String>,
+
+    /// Scrape modem line stats after each run: preset ("docsis", "dsl") or status page URL
+    #[arg(long, value_name = "PRESET|URL")]
+    pub modem: Option<String>,
+
+    /// Modem metric to extract, as NAME=regex:PATTERN or NAME=json:PATH (repeatable)
+    #[arg(long = "modem-metric", value_name = "NAME=SPEC", value_parser = crate::engine::modem::parse_metric, requires = "modem")]
+    pub modem_metrics: Vec<(String, String)>,
+
+    /// After the regular test, keep the link saturated for this long and report stability per minute (e.g. 1h)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub soak: Option<humantime::Duration>,
+
+    /// Direction to saturate during --soak
+    #[arg(long, value_parser = ["download", "upload"], default_value = "download", requires = "soak")]
+    pub soak_direction: String,
+
+    /// After the packet loss probe, push a paced UDP stream through the TURN relay for
+    /// this long and report the goodput and reordering (e.g. 10s)
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub udp_throughput: Option<humantime::Duration>,
+
+    /// Rate of the --udp-throughput stream in Mbit/s
+    #[arg(
+        long,
+        value_name = "MBPS",
+        default_value_t = 20.0,
+        requires = "udp_throughput"
+    )]
+    pub udp_throughput_rate: f64,
+
+    /// Skip the download phase (and its loaded latency)
+    #[arg(long, conflicts_with_all = ["latency_only", "packet_loss_only"])]
+    pub no_download: bool,
+
+    /// Skip the upload phase (and its loaded latency)
+    #[arg(long, conflicts_with_all = ["latency_only", "packet_loss_only"])]
+    pub no_upload: bool,
+
+    /// Measure idle latency only: no download, upload or packet loss phase
+    #[arg(long, conflicts_with = "packet_loss_only")]
+    pub latency_only: bool,
+
+    /// Measure UDP packet loss only
+    #[arg(long)]
+    pub packet_loss_only: bool,
+
+    /// After the upload phase, repeat the download at each of these connection counts
+    /// (e.g. 1,2,4,8,16) to tell a per-connection limit from a link limit
+    #[arg(long, value_name = "N,...", value_delimiter = ',', value_parser = clap::value_parser!(u16).range(1..))]
+    pub sweep_concurrency: Vec<u16>,
+
+    /// After the upload phase, run the website's staged 100 kB / 1 MB / 10 MB / 25 MB measurements
+    #[arg(long)]
+    pub ramped: bool,
+
+    /// Monitor mode: keep running, starting a test every DURATION (e.g. 15m) and saving each result
+    #[arg(long, value_name = "DURATION", group = "monitor", value_parser = parse_duration)]
+    pub interval: Option<humantime::Duration>,
+
+    /// Write a systemd service and timer that run a test with the other options given every
+    /// --interval (default 1h), then exit. Installed system-wide when run as root
+    #[arg(long, conflicts_with_all = ["schedule", "count", "calibrate", "events_ndjson"])]
+    pub install_systemd: bool,
+
+    /// Run tests at the times of a cron expression (e.g. "0 */2 * * *"), in local time.
+    /// With --text/--json this is monitor mode; otherwise the dashboard starts each run
+    #[arg(long, value_name = "CRON", value_parser = crate::schedule::parse, group = "monitor")]
+    pub schedule: Option<crate::schedule::Schedule>,
+
+    /// In monitor mode, make every Nth run a full test and the others latency-only
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "monitor")]
+    pub full_every: u32,
+
+    /// In monitor mode, alert when the download speed drops below MBPS
+    #[arg(long, value_name = "MBPS", requires = "monitor")]
+    pub alert_below_download: Option<f64>,
+
+    /// In monitor mode, alert when the upload speed drops below MBPS
+    #[arg(long, value_name = "MBPS", requires = "monitor")]
+    pub alert_below_upload: Option<f64>,
+
+    /// In monitor mode, alert when the idle latency median rises above MS
+    #[arg(long, value_name = "MS", requires = "monitor")]
+    pub alert_above_latency: Option<f64>,
+
+    /// In monitor mode, alert when packet loss rises above PERCENT
+    #[arg(long, value_name = "PERCENT", requires = "monitor")]
+    pub alert_above_loss: Option<f64>,
+
+    /// Show a desktop notification when a test finishes; in monitor mode, when results
+    /// start or stop missing the --alert-* thresholds instead
+    #[arg(long)]
+    pub notify: bool,
+
+    /// Run the full test N times back-to-back and print the mean, median, min and max of
+    /// download, upload and latency (with --text or --json); every run is still saved
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), conflicts_with_all = ["monitor", "calibrate"])]
+    pub count: u32,
+
+    /// Exit with code 3 if the download speed is below MBPS (with --text or --json)
+    #[arg(long, value_name = "MBPS", conflicts_with_all = ["monitor", "calibrate"])]
+    pub fail_below_download: Option<f64>,
+
+    /// Exit with code 3 if the upload speed is below MBPS
+    #[arg(long, value_name = "MBPS", conflicts_with_all = ["monitor", "calibrate"])]
+    pub fail_below_upload: Option<f64>,
+
+    /// Exit with code 3 if the idle latency median is above MS
+    #[arg(long, value_name = "MS", conflicts_with_all = ["monitor", "calibrate"])]
+    pub fail_above_latency: Option<f64>,
+
+    /// Exit with code 3 if packet loss is above PERCENT
+    #[arg(long, value_name = "PERCENT", conflicts_with_all = ["monitor", "calibrate"])]
+    pub fail_above_loss: Option<f64>,
+
+    /// HTTP version for the measurement requests: auto, h1, h2 or h3 (QUIC, needs the http3 build feature)
+    #[arg(long, value_parser = ["auto", "h1", "h2", "h3"], default_value = "auto")]
+    pub protocol: String,
+
+    /// Run the text-mode test, then compare it with a speed.cloudflare.com browser result
+    #[arg(long)]
+    pub calibrate: bool,
+
+    /// Download speed reported by the browser test, in Mbps (prompted for if omitted)
+    #[arg(long, value_name = "MBPS", requires = "calibrate")]
+    pub browser_download: Option<f64>,
+
+    /// Upload speed reported by the browser test, in Mbps
+    #[arg(long, value_name = "MBPS", requires = "calibrate")]
+    pub browser_upload: Option<f64>,
+
+    /// Latency reported by the browser test, in ms
+    #[arg(long, value_name = "MS", requires = "calibrate")]
+    pub browser_latency: Option<f64>,
+
+    /// Extra output for each result: stdout-json, history, file-json:PATH, file-csv:PATH, csv-append:PATH, influx:PATH|URL, mqtt:URL, webhook:URL (repeatable, or comma-separated)
+    #[arg(long = "sink", value_name = "SINK", value_delimiter = ',', value_parser = crate::sinks::parse_spec)]
+    pub sinks: Vec<crate::sinks::SinkSpec>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum Command {
+    /// Compare two result files field by field (b relative to a)
+    Diff {
+        /// Baseline result JSON
+        a: std::path::PathBuf,
+        /// Result JSON to compare against the baseline
+        b: std::path::PathBuf,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = crate::diff::DiffFormat::Table)]
+        format: crate::diff::DiffFormat,
+    },
+    /// Hand out a common start time to probes using --sync-start http://HOST:PORT
+    Coordinate {
+        /// Address to listen on
+        #[arg(long, default_value = "0.0.0.0:7878")]
+        listen: String,
+        /// How far in the future the synchronized start is
+        #[arg(long, default_value = "30s", value_parser = parse_duration)]
+        delay: humantime::Duration,
+    },
+    /// Query or maintain the saved run history
+    History {
+        #[command(subcommand)]
+        action: HistoryCommand,
+    },
+    /// Manage the configuration file holding default options
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Answer tests from other instances of this binary
+    Serve {
+        /// Answer LAN tests (`--lan HOST:PORT` on the other side) on this address, over
+        /// TCP and UDP
+        #[arg(
+            long,
+            value_name = "ADDR",
+            num_args = 0..=1,
+            default_missing_value = "0.0.0.0:7879"
+        )]
+        lan: Option<String>,
+        /// Serve the REST API on this address: start tests, follow them as server-sent
+        /// events, and read the latest result and the history
+        #[arg(
+            long,
+            value_name = "ADDR",
+            num_args = 0..=1,
+            default_missing_value = crate::api::DEFAULT_LISTEN
+        )]
+        api: Option<String>,
+        /// Let browser pages from this origin (e.g. https://dash.example) use the API;
+        /// repeatable. Requests from other origins are refused.
+        #[arg(long, value_name = "ORIGIN", requires = "api")]
+        api_allow_origin: Vec<String>,
+    },
+    /// List the Cloudflare data centers, nearest first, with the latency to the nearest
+    /// few (their codes are what --colo takes)
+    Locations {
+        /// Number of the nearest colos to probe for latency (0 to only list them)
+        #[arg(long, value_name = "N", default_value_t = 5)]
+        probe: usize,
+        /// Print the colos as a JSON array
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum ConfigCommand {
+    /// Write a config file listing every option at its default
+    Init {
+        /// Replace an existing config file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the effective options and where they were set
+    Show,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum HistoryCommand {
+    /// List saved runs, newest first
+    Query {
+        /// Only runs at or after this time (RFC 3339 or YYYY-MM-DD, UTC)
+        #[arg(long)]
+        since: Option<String>,
+        /// Only runs before this time (RFC 3339 or YYYY-MM-DD, UTC)
+        #[arg(long)]
+        before: Option<String>,
+        /// Only runs on this network interface
+        #[arg(long)]
+        interface: Option<String>,
+        /// Only runs served by this Cloudflare colo (e.g. AMS)
+        #[arg(long)]
+        colo: Option<String>,
+        /// Only runs over a detected VPN (true) or without one (false)
+        #[arg(long, value_name = "BOOL")]
+        vpn: Option<bool>,
+        /// Maximum number of runs
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        /// Print the matching results as a JSON array
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete saved runs beyond a retention limit
+    Prune {
+        /// Delete runs older than this many days
+        #[arg(long, value_name = "DAYS", required_unless_present = "keep_runs")]
+        keep_days: Option<u64>,
+        /// Keep only the newest N runs
+        #[arg(long, value_name = "N")]
+        keep_runs: Option<usize>,
+    },
+    /// Write saved runs to one CSV or NDJSON file, oldest first
+    Export {
+        /// Export every saved run instead of the newest --limit
+        #[arg(long)]
+        all: bool,
+        /// Number of most recent runs to export
+        #[arg(long, default_value_t = 50, conflicts_with = "all")]
+        limit: usize,
+        #[arg(long, value_enum, default_value_t = crate::storage::ExportFormat::Csv)]
+        format: crate::storage::ExportFormat,
+        /// Output file
+        #[arg(long, value_name = "PATH")]
+        out: std::path::PathBuf,
+    },
+    /// Add results from a file to the history: this tool's JSON/NDJSON output or
+    /// results exported from speed.cloudflare.com
+    Import { file: std::path::PathBuf },
+    /// Rewrite the saved run files with another compression
+    Compress {
+        #[arg(long, value_enum, default_value_t = crate::storage::Compression::Zstd)]
+        format: crate::storage::Compression,
+    },
+    /// Move the history into an indexed SQLite database; saved JSON runs are imported
+    /// and left in place, and from then on runs are stored in the database
+    #[cfg(feature = "sqlite")]
+    MigrateSqlite,
+}
+
+/// Parse a duration like "90s" or "15m"; a bare number is taken as seconds, which suits
+/// values coming from environment variables.
+pub fn parse_duration(s: &str) -> Result<humantime::Duration, String> {
+    if let Ok(secs) = s.trim().parse::<u64>() {
+        return Ok(Duration::from_secs(secs).into());
+    }
+    s.parse::<humantime::Duration>().map_err(|e| e.to_string())
+}
+
+/// Parse a `NAME: VALUE` HTTP header.
+pub fn parse_header(s: &str) -> Result<(String, String), String> {
+    let Some((name, value)) = s.split_once(':') else {
+        return Err(format!("expected NAME: VALUE, got '{}'", s));
+    };
+    let name = name.trim();
+    let value = value.trim();
+    reqwest::header::HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| format!("invalid header name '{}'", name))?;
+    reqwest::header::HeaderValue::from_str(value)
+        .map_err(|_| format!("invalid value for header '{}'", name))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Check a `--doh` URL, which has to be https.
+pub fn parse_doh(s: &str) -> Result<String, String> {
+    let url = reqwest::Url::parse(s.trim()).map_err(|e| format!("invalid URL '{}': {}", s, e))?;
+    if url.scheme() != "https" || url.host_str().is_none() {
+        return Err(format!("expected an https:// URL, got '{}'", s));
+    }
+    Ok(url.to_string())
+}
+
+/// Check a `--dot` server, keeping it as given.
+pub fn parse_dot(s: &str) -> Result<String, String> {
+    crate::engine::resolver::parse_dot_server(s).map(|_| s.trim().to_string())
+}
+
+/// Parse a `--resolve` entry: `HOST:PORT:ADDR`, with IPv6 addresses optionally in brackets.
+pub fn parse_resolve(s: &str) -> Result<ResolveOverride, String> {
+    let mut parts = s.trim().splitn(3, ':');
+    let (Some(host), Some(port), Some(ip)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(format!("expected HOST:PORT:ADDR, got '{}'", s));
+    };
+    if host.is_empty() {
+        return Err(format!("missing host in '{}'", s));
+    }
+    Ok(ResolveOverride {
+        host: host.to_ascii_lowercase(),
+        port: port
+            .parse()
+            .map_err(|_| format!("invalid port '{}' in '{}'", port, s))?,
+        ip: ip
+            .trim_matches(['[', ']'])
+            .parse()
+            .map_err(|_| format!("invalid address '{}' in '{}'", ip, s))?,
+    })
+}
+
+/// Parse a `--down-path`/`--up-path`: a path on the base URL's host.
+pub fn parse_endpoint_path(s: &str) -> Result<String, String> {
+    if s.starts_with('/') && !s.starts_with("//") {
+        Ok(s.to_string())
+    } else {
+        Err(format!("invalid path '{}': must start with a single /", s))
+    }
+}
+
+/// Parse a `--colo` code: three letters, as Cloudflare names its data centers by airport.
+pub fn parse_colo(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if s.len() == 3 && s.chars().all(|c| c.is_ascii_alphabetic()) {
+        Ok(s.to_ascii_uppercase())
+    } else {
+        Err(format!(
+            "invalid colo '{}': expected a three-letter IATA code like SIN",
+            s
+        ))
+    }
+}
+
+/// Parse a `--lan` peer, HOST or HOST:PORT (`[IPv6]:PORT` with a port), into HOST:PORT.
+pub fn parse_lan_peer(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if s.parse::<std::net::SocketAddr>().is_ok() {
+        return Ok(s.to_string());
+    }
+    if let Ok(ip) = s.parse::<std::net::IpAddr>() {
+        let addr = std::net::SocketAddr::new(ip, crate::engine::lan::DEFAULT_PORT);
+        return Ok(addr.to_string());
+    }
+    match s.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() && !host.contains(':') => port
+            .parse::<u16>()
+            .map(|port| format!("{}:{}", host, port))
+            .map_err(|_| format!("invalid port in '{}'", s)),
+        None if !s.is_empty() => Ok(format!("{}:{}", s, crate::engine::lan::DEFAULT_PORT)),
+        _ => Err(format!(
+            "invalid LAN peer '{}': expected HOST or HOST:PORT",
+            s
+        )),
+    }
+}
+
+/// Parse a byte count like "200MB", "1.5GB" or "512KiB"; a bare number is taken as bytes.
+/// kB/MB/GB are decimal, KiB/MiB/GiB binary.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        other => return Err(format!("unknown size unit '{}'", other)),
+    };
+    let bytes = (number * multiplier as f64).round();
+    if bytes < 1.0 {
+        return Err("size must be at least 1 byte".to_string());
+    }
+    Ok(bytes as u64)
+}
+
+impl Cli {
+    /// How runs are saved to the history, from `--compress`/`--keep-days`/`--keep-runs`
+    pub fn history_options(&self) -> crate::storage::HistoryOptions {
+        crate::storage::HistoryOptions {
+            compression: self.compress,
+            retention: crate::storage::Retention {
+                keep_days: self.keep_days,
+                keep_runs: self.keep_runs,
+            },
+        }
+    }
 }
 
-pub async fn run(args: Cli) -> Result<()> {
+pub async fn run(mut args: Cli) -> Result<()> {
+    match &args.command {
+        Some(Command::Diff { a, b, format }) => return crate::diff::run(a, b, *format),
+        Some(Command::Coordinate { listen, delay }) => {
+            return crate::sync::run_coordinator(listen, Duration::from(*delay)).await
+        }
+        Some(Command::History { action }) => return run_history(action, args.compress),
+        Some(Command::Config { action }) => {
+            return match action {
+                ConfigCommand::Init { force } => {
+                    let path = crate::config::init(*force)?;
+                    println!("Wrote {}", path.display());
+                    Ok(())
+                }
+                ConfigCommand::Show => crate::config::show(),
+            }
+        }
+        Some(Command::Serve { lan, api: None, .. }) => {
+            let listen = lan
+                .as_deref()
+                .context("nothing to serve; pass --lan or --api")?;
+            return crate::engine::lan::serve(listen).await;
+        }
+        Some(Command::Serve { .. } | Command::Locations { .. }) | None => {}
+    }
+    if args.install_systemd {
+        return crate::systemd::install(&args);
+    }
+
+    // Resolve the synchronized start once so every output mode uses the same instant
+    if let Some(spec) = args.sync_start.take() {
+        let start_at = crate::sync::resolve_start(&spec).await?;
+        args.sync_start = Some(humantime::format_rfc3339_millis(start_at).to_string());
+    }
+
     // Validate that --silent can only be used with --json
     if args.silent && !args.json {
         return Err(anyhow::anyhow!(
@@ -138,12 +918,68 @@ pub async fn run(args: Cli) -> Result<()> {
         ));
     }
 
+    // Honor system proxy settings unless --proxy or --no-proxy was given
+    if args.proxy.is_none() && !args.no_proxy {
+        if let Some(sys) = crate::network::detect_system_proxy(&args.base_url[0]) {
+            tracing::info!(
+                "Detected system proxy from {} (use --no-proxy to bypass it).",
+                sys.source
+            );
+            args.proxy = Some(sys.url);
+        }
+    }
+
     // Warn when using a proxy
     if let Some(ref proxy_url) = args.proxy {
-        eprintln!(
-            "Warning: using proxy {}. Speed results reflect performance through the proxy, not your direct connection.",
-            proxy_url
+        tracing::warn!(
+            "Using proxy {}. Speed results reflect performance through the proxy, not your direct connection.",
+            crate::network::redact_proxy_url(proxy_url)
+        );
+    }
+
+    // After the proxy settings, which the probes go through like a test
+    if let Some(Command::Locations { probe, json }) = &args.command {
+        return crate::locations::run(&build_config(&args), *probe, *json).await;
+    }
+    if let Some(Command::Serve {
+        lan,
+        api: Some(api),
+        api_allow_origin,
+    }) = args.command.clone()
+    {
+        return match lan {
+            Some(lan) => tokio::try_join!(
+                crate::engine::lan::serve(&lan),
+                crate::api::serve(&api, api_allow_origin, args)
+            )
+            .map(|_| ()),
+            None => crate::api::serve(&api, api_allow_origin, args).await,
+        };
+    }
+
+    // Without --text/--json a schedule keeps the dashboard open and runs tests in it
+    let headless_schedule = args.schedule.is_some()
+        && (args.silent || args.json || args.text || !cfg!(feature = "tui"));
+    if args.interval.is_some() || headless_schedule {
+        return crate::monitor::run(args).await;
+    }
+
+    let thresholds = crate::thresholds::Thresholds::from_args(&args);
+    anyhow::ensure!(
+        thresholds.is_empty() || args.json || args.text || args.events_ndjson,
+        "--fail-below-*/--fail-above-* need --text, --json or --events-ndjson"
+    );
+
+    if args.events_ndjson {
+        return run_events(args).await;
+    }
+
+    if args.count > 1 {
+        anyhow::ensure!(
+            args.json || args.text,
+            "--count needs --text or --json (the dashboard shows one run at a time)"
         );
+        return run_repeated(args).await;
     }
 
     // Silent mode takes precedence over other output modes
@@ -151,6 +987,10 @@ pub async fn run(args: Cli) -> Result<()> {
         return run_test_engine(args, true).await;
     }
 
+    if args.calibrate {
+        return crate::calibrate::run(args).await;
+    }
+
     if !args.json && !args.text {
         #[cfg(feature = "tui")]
         {
@@ -159,7 +999,11 @@ pub async fn run(args: Cli) -> Result<()> {
         #[cfg(not(feature = "tui"))]
         {
             // Fallback when built without TUI support.
-            return run_text(args).await;
+            let result = run_text(args.clone()).await?;
+            if args.notify {
+                crate::notify::finished(std::slice::from_ref(&result));
+            }
+            return Ok(());
         }
     }
 
@@ -167,23 +1011,28 @@ pub async fn run(args: Cli) -> Result<()> {
         return run_test_engine(args, false).await;
     }
 
-    run_text(args).await
-}
-
-/// Generate a random measurement ID for the speed test.
-fn gen_meas_id() -> String {
-    let mut b = [0u8; 8];
-    rand::thread_rng().fill_bytes(&mut b);
-    u64::from_le_bytes(b).to_string()
+    let notify = args.notify;
+    let result = run_text(args).await?;
+    if notify {
+        crate::notify::finished(std::slice::from_ref(&result));
+    }
+    thresholds.enforce(std::slice::from_ref(&result))
 }
 
 /// Build a `RunConfig` from CLI arguments.
 pub fn build_config(args: &Cli) -> RunConfig {
-    // DNS and TLS run by default unless --skip-diagnostics is set
+    // DNS, TLS and request timing run by default unless --skip-diagnostics is set
     let skip = args.skip_diagnostics;
     RunConfig {
-        base_url: args.base_url.clone(),
-        meas_id: gen_meas_id(),
+        base_url: match &args.lan {
+            Some(peer) => format!("lan://{}", peer),
+            None => args.base_url[0].clone(),
+        },
+        fallback_urls: match &args.lan {
+            Some(_) => Vec::new(),
+            None => args.base_url[1..].to_vec(),
+        },
+        meas_id: crate::model::gen_meas_id(),
         comments: args.comments.clone(),
         download_bytes_per_req: args.download_bytes_per_req,
         upload_bytes_per_req: args.upload_bytes_per_req,
@@ -193,21 +1042,103 @@ pub fn build_config(args: &Cli) -> RunConfig {
         upload_duration: Duration::from(args.upload_duration),
         probe_interval_ms: args.probe_interval_ms,
         probe_timeout_ms: args.probe_timeout_ms,
+        max_run_time: args.max_run_time.map(Duration::from),
+        max_total_bytes: args.max_total_bytes,
+        sweep_concurrency: args.sweep_concurrency.iter().map(|&n| n as usize).collect(),
+        warmup: args.warmup.map(Duration::from),
+        steady_window: args.steady_window,
+        adaptive_cv_pct: args.adaptive.then_some(args.adaptive_cv),
         user_agent: format!("cloudflare-speed-cli/{}", env!("CARGO_PKG_VERSION")),
         experimental: args.experimental,
         interface: args.interface.clone(),
         source_ip: args.source.clone(),
         proxy: args.proxy.clone(),
         certificate_path: args.certificate.clone(),
-        // Diagnostic options: DNS and TLS run by default unless --skip-diagnostics
+        client_cert_path: args.client_cert.clone(),
+        client_key_path: args.client_key.clone(),
+        dns_server: args.dns_server,
+        doh_url: args.doh.clone(),
+        dot_server: args.dot.clone(),
+        headers: args.headers.clone(),
+        backend: match (&args.lan, args.backend.as_str()) {
+            (Some(_), _) => Backend::Lan,
+            (None, "librespeed") => Backend::Librespeed,
+            (None, _) => Backend::Cloudflare,
+        },
+        endpoint_auth_token: args.endpoint_auth_token.clone(),
+        down_path: args.down_path.clone(),
+        up_path: args.up_path.clone(),
+        resolve: args.resolve.clone(),
+        colo: args.colo.clone(),
+        dscp: args.dscp,
+        congestion: args.congestion.clone(),
+        send_buffer: args.send_buffer,
+        recv_buffer: args.recv_buffer,
+        // Diagnostic options: DNS, TLS and request timing run by default unless --skip-diagnostics
         measure_dns: !skip,
+        check_dnssec: args.dnssec,
         measure_tls: !skip,
+        measure_timing: !skip,
         compare_ip_versions: args.compare_ip_versions,
+        compare_protocols: args.compare_protocols,
+        export_har: args.export_har.clone(),
         traceroute: args.traceroute,
         traceroute_max_hops: args.traceroute_max_hops,
+        traceroute_protocol: match args.traceroute_protocol.as_str() {
+            "icmp" => TracerouteProtocol::Icmp,
+            "udp" => TracerouteProtocol::Udp,
+            "tcp" => TracerouteProtocol::Tcp,
+            _ => TracerouteProtocol::Auto,
+        },
+        traceroute_paris: args.traceroute_paris,
+        traceroute_paths: args.traceroute_paths,
+        measure_mtu: args.mtu,
         ipv4_only: args.ipv4_only,
         ipv6_only: args.ipv6_only,
         udp_packets: args.udp_packets,
+        udp_packet_size: args.udp_packet_size,
+        udp_interval_ms: args.udp_interval_ms,
+        udp_timeout_ms: args.udp_timeout_ms,
+        udp_throughput: args.udp_throughput.map(Duration::from),
+        udp_throughput_mbps: args.udp_throughput_rate,
+        anchors: args.anchors.clone(),
+        icmp_ping: args.icmp_ping,
+        captive_portal_url: (!args.no_captive_portal_check)
+            .then(|| args.captive_portal_url.clone()),
+        allow_captive_portal: args.allow_captive_portal,
+        sync_start: args
+            .sync_start
+            .as_deref()
+            .and_then(|s| humantime::parse_rfc3339_weak(s).ok()),
+        context: args.context.iter().cloned().collect(),
+        tags: args.tags.iter().cloned().collect(),
+        context_hook: args.context_hook.clone(),
+        modem: args.modem.clone(),
+        modem_metrics: args.modem_metrics.clone(),
+        soak: args.soak.map(Duration::from),
+        soak_direction: if args.soak_direction == "upload" {
+            crate::model::Phase::Upload
+        } else {
+            crate::model::Phase::Download
+        },
+        ramped: args.ramped,
+        phases: if args.latency_only {
+            PhaseSelection::latency_only()
+        } else if args.packet_loss_only {
+            PhaseSelection::packet_loss_only()
+        } else {
+            PhaseSelection {
+                download: !args.no_download,
+                upload: !args.no_upload,
+                ..Default::default()
+            }
+        },
+        http_version: match args.protocol.as_str() {
+            "h1" => HttpVersion::Http1,
+            "h2" => HttpVersion::Http2,
+            "h3" => HttpVersion::Http3,
+            _ => HttpVersion::Auto,
+        },
     }
 }
 
@@ -215,66 +1146,288 @@ pub fn build_config(args: &Cli) -> RunConfig {
 /// `silent` controls whether to consume events and suppress output.
 async fn run_test_engine(args: Cli, silent: bool) -> Result<()> {
     let cfg = build_config(&args);
-    let network_info = crate::network::gather_network_info(&args);
     let enriched = if silent {
-        // In silent mode, spawn task and consume events
-        let (evt_tx, mut evt_rx) = mpsc::channel::<TestEvent>(2048);
-        let (_, ctrl_rx) = mpsc::channel::<EngineControl>(16);
+        run_quiet(&args, cfg).await?
+    } else {
+        let network_info = crate::network::gather_network_info(args.interface.as_deref());
+        // In JSON mode, directly await the engine (no need to consume events)
+        let (evt_tx, _) = mpsc::channel::<TestEvent>(1024);
+        let (ctrl_rx, ctrl_c) = cancel_on_ctrl_c();
 
         let engine = TestEngine::new(cfg);
-        let handle = tokio::spawn(async move { engine.run(evt_tx, ctrl_rx).await });
+        let result = engine.run(evt_tx, ctrl_rx).await;
+        ctrl_c.abort();
+        let result = result.context("speed test failed")?;
 
-        // Consume events silently (no output)
-        while let Some(_ev) = evt_rx.recv().await {
-            // All events are silently consumed - no output
-        }
+        crate::network::enrich_result(&result, &network_info)
+    };
 
-        let result = handle
-            .await
-            .context("test engine task failed")?
-            .context("speed test failed")?;
+    // Exports fail the run; history saves only fail it in silent mode
+    let sinks = crate::sinks::configured(&args, !silent, args.auto_save);
+    publish(&sinks, &enriched, silent).await?;
+    if args.notify {
+        crate::notify::finished(std::slice::from_ref(&enriched));
+    }
+    crate::thresholds::Thresholds::from_args(&args).enforce(std::slice::from_ref(&enriched))
+}
 
-        crate::network::enrich_result(&result, &network_info)
+/// `--count`: run the test back-to-back, publishing each result, then print the aggregate.
+async fn run_repeated(args: Cli) -> Result<()> {
+    let count = args.count as usize;
+    let mut runs = Vec::with_capacity(count);
+    // A failed run is reported and left out of the summary; the others still count
+    let mut failures = Vec::new();
+    for i in 1..=count {
+        let result = if args.text {
+            eprintln!("==== Run {}/{} ====", i, count);
+            run_text(args.clone()).await
+        } else {
+            match run_quiet(&args, build_config(&args)).await {
+                Ok(result) => {
+                    // The combined document goes to stdout at the end instead of one per run
+                    let sinks = crate::sinks::configured(&args, false, args.auto_save);
+                    publish(&sinks, &result, args.silent).await?;
+                    Ok(result)
+                }
+                Err(e) => Err(e),
+            }
+        };
+        match result {
+            Ok(result) => runs.push(result),
+            Err(e) => {
+                eprintln!("Run {}/{} failed: {:#}", i, count, e);
+                failures.push(serde_json::json!({ "run": i, "error": format!("{:#}", e) }));
+            }
+        }
+    }
+    anyhow::ensure!(!runs.is_empty(), "all {} runs failed", count);
+    if args.notify {
+        crate::notify::finished(&runs);
+    }
+
+    let summary = crate::stats::repeat_summary(&runs, failures.len());
+    let thresholds = crate::thresholds::Thresholds::from_args(&args);
+    let outcome = || {
+        thresholds.enforce(&runs)?;
+        anyhow::ensure!(
+            failures.is_empty(),
+            "{} of {} runs failed",
+            failures.len(),
+            count
+        );
+        Ok(())
+    };
+    if args.silent {
+        return outcome();
+    }
+    if args.json {
+        let doc = serde_json::json!({ "summary": summary, "runs": runs, "failures": failures });
+        println!("{}", serde_json::to_string_pretty(&doc)?);
+        return outcome();
+    }
+    println!();
+    if summary.failed > 0 {
+        println!(
+            "Summary of {} runs ({} failed, left out):",
+            summary.runs, summary.failed
+        );
     } else {
-        // In JSON mode, directly await the engine (no need to consume events)
-        let (evt_tx, _) = mpsc::channel::<TestEvent>(1024);
-        let (_, ctrl_rx) = mpsc::channel::<EngineControl>(16);
+        println!("Summary of {} runs:", summary.runs);
+    }
+    println!(
+        "{:<16} {:>10} {:>10} {:>10} {:>10}",
+        "", "mean", "median", "min", "max"
+    );
+    for (label, agg) in [
+        ("Download (Mbps)", summary.download_mbps),
+        ("Upload (Mbps)", summary.upload_mbps),
+        ("Latency (ms)", summary.idle_latency_ms),
+    ] {
+        match agg {
+            Some(a) => println!(
+                "{:<16} {:>10.2} {:>10.2} {:>10.2} {:>10.2}",
+                label, a.mean, a.median, a.min, a.max
+            ),
+            None => println!("{:<16} {:>10}", label, "-"),
+        }
+    }
+    outcome()
+}
 
-        let engine = TestEngine::new(cfg);
-        let result = engine
-            .run(evt_tx, ctrl_rx)
-            .await
-            .context("speed test failed")?;
+/// `--events-ndjson`: print each engine event as it happens, then the result, one JSON
+/// object per line. Every line carries `t_ms`, the time since the run started.
+async fn run_events(args: Cli) -> Result<()> {
+    let cfg = build_config(&args);
+    let network_info = crate::network::gather_network_info(args.interface.as_deref());
+    let (evt_tx, mut evt_rx) = mpsc::channel::<TestEvent>(2048);
+    let (ctrl_rx, ctrl_c) = cancel_on_ctrl_c();
 
-        crate::network::enrich_result(&result, &network_info)
+    let engine = TestEngine::new(cfg);
+    let start = std::time::Instant::now();
+    let handle = tokio::spawn(async move { engine.run(evt_tx, ctrl_rx).await });
+
+    let line = |mut value: serde_json::Value| -> Result<String> {
+        if let Some(obj) = value.as_object_mut() {
+            obj.insert("t_ms".into(), (start.elapsed().as_millis() as u64).into());
+        }
+        Ok(serde_json::to_string(&value)?)
     };
+    while let Some(ev) = evt_rx.recv().await {
+        println!("{}", line(serde_json::to_value(&ev)?)?);
+    }
 
-    // Handle exports (errors will propagate)
-    handle_exports(&args, &enriched)?;
+    ctrl_c.abort();
+    let result = handle
+        .await
+        .context("test engine task failed")?
+        .context("speed test failed")?;
+    let enriched = crate::network::enrich_result(&result, &network_info);
+    println!(
+        "{}",
+        line(serde_json::json!({ "type": "result", "result": enriched }))?
+    );
 
-    if !silent {
-        // Print JSON output in non-silent mode
-        println!("{}", serde_json::to_string_pretty(&enriched)?);
+    let sinks = crate::sinks::configured(&args, false, args.auto_save);
+    publish(&sinks, &enriched, false).await?;
+    if args.notify {
+        crate::notify::finished(std::slice::from_ref(&enriched));
     }
+    crate::thresholds::Thresholds::from_args(&args).enforce(std::slice::from_ref(&enriched))
+}
 
-    // Save results if auto_save is enabled
-    if args.auto_save {
-        if silent {
-            crate::storage::save_run(&enriched).context("failed to save run results")?;
-        } else {
-            if let Ok(p) = crate::storage::save_run(&enriched) {
-                eprintln!("Saved: {}", p.display());
+fn run_history(action: &HistoryCommand, compression: crate::storage::Compression) -> Result<()> {
+    match action {
+        HistoryCommand::Query {
+            since,
+            before,
+            interface,
+            colo,
+            vpn,
+            limit,
+            json,
+        } => {
+            let runs = crate::storage::query_runs(&crate::storage::RunQuery {
+                since: since.clone(),
+                before: before.clone(),
+                interface: interface.clone(),
+                colo: colo.clone(),
+                vpn: *vpn,
+                offset: 0,
+                limit: *limit,
+            })?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&runs)?);
+                return Ok(());
+            }
+            for r in &runs {
+                println!(
+                    "{}  {:<4} {:<10} DL {:>8.2} Mbps  UL {:>8.2} Mbps  idle {} ms{}{}",
+                    r.timestamp_utc,
+                    r.colo.as_deref().unwrap_or("-"),
+                    r.interface_name.as_deref().unwrap_or("-"),
+                    r.download.mbps,
+                    r.upload.mbps,
+                    fmt_opt(r.idle_latency.median_ms),
+                    if r.vpn_detected { "  via VPN" } else { "" },
+                    if r.tags.is_empty() {
+                        String::new()
+                    } else {
+                        format!("  [{}]", r.tags_text())
+                    }
+                );
             }
+            Ok(())
+        }
+        HistoryCommand::Prune {
+            keep_days,
+            keep_runs,
+        } => {
+            let removed = crate::storage::prune(&crate::storage::Retention {
+                keep_days: *keep_days,
+                keep_runs: *keep_runs,
+            })?;
+            println!("Deleted {} run(s)", removed);
+            Ok(())
+        }
+        HistoryCommand::Export {
+            all,
+            limit,
+            format,
+            out,
+        } => {
+            let written = crate::storage::export_history(out, *format, (!*all).then_some(*limit))?;
+            println!("Exported {} run(s) to {}", written, out.display());
+            Ok(())
+        }
+        HistoryCommand::Import { file } => {
+            let runs = crate::import::load(file)?;
+            for run in &runs {
+                crate::storage::save_run(run, compression)?;
+            }
+            println!("Imported {} run(s) from {}", runs.len(), file.display());
+            Ok(())
+        }
+        HistoryCommand::Compress { format } => {
+            let changed = crate::storage::recompress(*format)?;
+            println!("Rewrote {} run file(s)", changed);
+            Ok(())
+        }
+        #[cfg(feature = "sqlite")]
+        HistoryCommand::MigrateSqlite => {
+            let (path, imported) = crate::storage::migrate_from_json()?;
+            println!("Imported {} run(s) into {}", imported, path.display());
+            Ok(())
         }
     }
+}
 
-    Ok(())
+/// Engine control for the text and JSON modes: the first Ctrl-C cancels the run, which
+/// still returns (and saves) its partial result; a second one exits right away. Abort the
+/// returned task once the engine has finished.
+fn cancel_on_ctrl_c() -> (mpsc::Receiver<EngineControl>, tokio::task::JoinHandle<()>) {
+    let (ctrl_tx, ctrl_rx) = mpsc::channel::<EngineControl>(16);
+    let handle = tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        tracing::warn!("Interrupted; finishing with partial results (Ctrl-C again to exit)");
+        ctrl_tx.send(EngineControl::Cancel).await.ok();
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+    (ctrl_rx, handle)
+}
+
+/// Run the engine without any progress output; returns the enriched result.
+pub(crate) async fn run_quiet(args: &Cli, cfg: RunConfig) -> Result<RunResult> {
+    let network_info = crate::network::gather_network_info(args.interface.as_deref());
+    let (evt_tx, mut evt_rx) = mpsc::channel::<TestEvent>(2048);
+    let (ctrl_rx, ctrl_c) = cancel_on_ctrl_c();
+
+    let engine = TestEngine::new(cfg);
+    let handle = tokio::spawn(async move { engine.run(evt_tx, ctrl_rx).await });
+
+    // Consume events silently (no output)
+    while let Some(_ev) = evt_rx.recv().await {
+        // All events are silently consumed - no output
+    }
+    ctrl_c.abort();
+
+    let result = handle
+        .await
+        .context("test engine task failed")?
+        .context("speed test failed")?;
+
+    Ok(crate::network::enrich_result(&result, &network_info))
 }
 
-async fn run_text(args: Cli) -> Result<()> {
+/// Run the test with line-by-line progress and a text summary; returns the enriched result.
+pub(crate) async fn run_text(args: Cli) -> Result<RunResult> {
     let cfg = build_config(&args);
+    let phases = cfg.phases;
     let (evt_tx, mut evt_rx) = mpsc::channel::<TestEvent>(2048);
-    let (_, ctrl_rx) = mpsc::channel::<EngineControl>(16);
+    let (ctrl_rx, ctrl_c) = cancel_on_ctrl_c();
 
     let engine = TestEngine::new(cfg);
     let handle = tokio::spawn(async move { engine.run(evt_tx, ctrl_rx).await });
@@ -286,6 +1439,9 @@ async fn run_text(args: Cli) -> Result<()> {
     let mut loaded_ul_latency_samples: Vec<f64> = Vec::new();
     let mut dl_points: Vec<(f64, f64)> = Vec::new();
     let mut ul_points: Vec<(f64, f64)> = Vec::new();
+    // Collapse repeated identical info/error lines instead of flooding stderr
+    let info_lines = crate::report::ErrorAggregator::default();
+    let sinks = crate::sinks::configured(&args, false, args.auto_save);
 
     while let Some(ev) = evt_rx.recv().await {
         match ev {
@@ -344,7 +1500,12 @@ async fn run_text(args: Cli) -> Result<()> {
                     }
                 }
             }
-            TestEvent::Info { message } => eprintln!("{message}"),
+            TestEvent::Info { message } => {
+                info_lines.record(message);
+                for line in info_lines.drain(false) {
+                    eprintln!("{line}");
+                }
+            }
             TestEvent::UdpLossProgress {
                 sent,
                 received,
@@ -367,9 +1528,37 @@ async fn run_text(args: Cli) -> Result<()> {
             TestEvent::MetaInfo { .. } => {
                 // Meta info is handled in TUI, ignore in text mode
             }
+            TestEvent::StreamTick { .. } => {
+                // Only charted in the TUI; the per-stream totals are in the result
+            }
+            TestEvent::IpComparisonTick { .. } => {
+                // Only shown live in the TUI; each family's totals follow
+            }
+            TestEvent::TracerouteReply { .. } => {
+                // Printed per hop once the trace is done
+            }
             // Diagnostic events
             TestEvent::DiagnosticDns { summary } => {
-                eprintln!("DNS: {:.2}ms", summary.resolution_time_ms);
+                match summary.resolver.as_deref() {
+                    Some(resolver) => {
+                        eprintln!("DNS: {:.2}ms via {resolver}", summary.resolution_time_ms)
+                    }
+                    None => eprintln!("DNS: {:.2}ms", summary.resolution_time_ms),
+                }
+                if let Some(ref encrypted) = summary.encrypted {
+                    eprintln!(
+                        "{}: {:.2}ms via {}",
+                        encrypted.protocol, encrypted.resolution_time_ms, encrypted.resolver
+                    );
+                }
+                if let Some(ref detail) = summary.dnssec_detail {
+                    let status = match summary.dnssec_validating {
+                        Some(true) => "validated",
+                        Some(false) => "not validated",
+                        None => "unknown",
+                    };
+                    eprintln!("DNSSEC: {} ({})", status, detail);
+                }
             }
             TestEvent::DiagnosticTls { summary } => {
                 eprintln!(
@@ -379,30 +1568,19 @@ async fn run_text(args: Cli) -> Result<()> {
                     summary.cipher_suite.as_deref().unwrap_or("-")
                 );
             }
+            TestEvent::DiagnosticTiming { timing } => {
+                eprintln!("Timing ({:?}): {}", timing.phase, timing.breakdown());
+            }
             TestEvent::DiagnosticIpComparison { comparison } => {
                 if let Some(ref v4) = comparison.ipv4_result {
-                    if v4.available {
-                        eprintln!(
-                            "IPv4: {} - DL {:.2} Mbps, UL {:.2} Mbps, latency {:.1}ms",
-                            v4.ip_address, v4.download_mbps, v4.upload_mbps, v4.latency_ms
-                        );
-                    } else {
-                        eprintln!("IPv4: unavailable - {:?}", v4.error);
-                    }
+                    eprintln!("{}", format_ip_version("IPv4", v4));
                 }
                 if let Some(ref v6) = comparison.ipv6_result {
-                    if v6.available {
-                        eprintln!(
-                            "IPv6: {} - DL {:.2} Mbps, UL {:.2} Mbps, latency {:.1}ms",
-                            v6.ip_address, v6.download_mbps, v6.upload_mbps, v6.latency_ms
-                        );
-                    } else {
-                        eprintln!("IPv6: unavailable - {:?}", v6.error);
-                    }
+                    eprintln!("{}", format_ip_version("IPv6", v6));
                 }
             }
             TestEvent::TracerouteHop { hop_number, hop } => {
-                let addr = hop.ip_address.as_deref().unwrap_or("*");
+                let addr = hop.describe();
                 let rtts: Vec<String> = hop.rtt_ms.iter().map(|r| format!("{:.1}ms", r)).collect();
                 let rtt_str = if rtts.is_empty() {
                     "*".to_string()
@@ -413,31 +1591,105 @@ async fn run_text(args: Cli) -> Result<()> {
             }
             TestEvent::TracerouteComplete { summary } => {
                 eprintln!(
-                    "Traceroute to {} {} ({} hops)",
+                    "Traceroute to {} {} ({} hops{})",
                     summary.destination,
                     if summary.completed {
                         "completed"
                     } else {
                         "incomplete"
                     },
-                    summary.hops.len()
+                    summary.hops.len(),
+                    summary
+                        .method
+                        .as_deref()
+                        .map(|method| format!(", {method}"))
+                        .unwrap_or_default()
                 );
+                if let Some(path) = summary.as_path() {
+                    eprintln!("AS path: {}", path);
+                }
+                if !summary.paths.is_empty() {
+                    let split = summary.ecmp_hops();
+                    let flows = summary.paths.len() + 1;
+                    if split.is_empty() {
+                        eprintln!("ECMP: {} flows, all on one path", flows);
+                    } else {
+                        let hops: Vec<String> = split.iter().map(u8::to_string).collect();
+                        eprintln!(
+                            "ECMP: {} flows, paths differ at hops {}",
+                            flows,
+                            hops.join(", ")
+                        );
+                    }
+                    for path in &summary.paths {
+                        let route: Vec<String> = path
+                            .hops
+                            .iter()
+                            .filter(|hop| split.contains(&hop.hop_number))
+                            .map(|hop| {
+                                format!(
+                                    "{}:{}",
+                                    hop.hop_number,
+                                    hop.ip_address.as_deref().unwrap_or("*")
+                                )
+                            })
+                            .collect();
+                        if !route.is_empty() {
+                            eprintln!("  flow {}: {}", path.flow, route.join(" "));
+                        }
+                    }
+                }
+            }
+            TestEvent::DiagnosticMtu { summary } => {
+                eprintln!("Path MTU: {}", summary.describe());
             }
             TestEvent::ExternalIps { ipv4, ipv6 } => {
                 let v4 = ipv4.as_deref().unwrap_or("-");
                 let v6 = ipv6.as_deref().unwrap_or("-");
                 eprintln!("External IPs: v4={} v6={}", v4, v6);
             }
+            TestEvent::Wireless { event } => {
+                eprintln!("{}", event.describe());
+            }
+            TestEvent::Protocol { protocol } => {
+                eprintln!("{:?} protocol: {}", protocol.phase, protocol.describe());
+            }
+            TestEvent::PhaseResult { phase, snapshot } => {
+                for line in sinks.publish_phase(phase, &snapshot).await {
+                    eprintln!("{line}");
+                }
+            }
         }
     }
 
+    for line in info_lines.drain(true) {
+        eprintln!("{line}");
+    }
+
+    ctrl_c.abort();
     let result = handle.await??;
 
     // Gather network information and enrich result
-    let network_info = crate::network::gather_network_info(&args);
+    let network_info = crate::network::gather_network_info(args.interface.as_deref());
     let enriched = crate::network::enrich_result(&result, &network_info);
 
-    handle_exports(&args, &enriched)?;
+    if enriched.partial {
+        tracing::warn!("Run was cut short; results are partial");
+    }
+    if enriched.backend != Backend::Cloudflare {
+        println!(
+            "Server: {} at {}",
+            enriched.backend.label(),
+            enriched.base_url
+        );
+    }
+    if !enriched.unreachable_endpoints.is_empty() {
+        println!(
+            "Endpoint: {} (unreachable: {})",
+            enriched.base_url,
+            enriched.unreachable_endpoints.join(", ")
+        );
+    }
     if let Some(meta) = enriched.meta.as_ref() {
         let extracted = crate::network::extract_metadata(meta);
         let ip = extracted.ip.as_deref().unwrap_or("-");
@@ -446,74 +1698,300 @@ async fn run_text(args: Cli) -> Result<()> {
         let org = extracted.as_org.as_deref().unwrap_or("-");
         println!("IP/Colo/ASN: {ip} / {colo} / {asn} ({org})");
     }
+    if let Some(speed) = enriched.link_speed_mbps {
+        println!("Link speed: {speed} Mbit/s");
+    }
+    if let Some(link) = &enriched.wireless_link {
+        println!("Wi-Fi: {}", link.describe());
+    }
+    if let Some(nat) = enriched.nat_type {
+        println!("NAT: {}", nat.label());
+    }
     if let Some(server) = enriched.server.as_deref() {
         println!("Server: {server}");
     }
+    if let Some(proxy) = enriched.proxy.as_deref() {
+        println!("Proxy: {proxy}");
+    }
+    if let Some(ip) = enriched.pinned_ip.as_deref() {
+        println!("Pinned to: {ip}");
+    }
+    if let Some(dscp) = enriched.dscp.as_deref() {
+        println!("DSCP: {dscp}");
+    }
+    if enriched.captive_portal {
+        println!("Warning: tested behind a captive portal; the results are not meaningful");
+    }
+    if let Some(name) = enriched
+        .download
+        .congestion
+        .as_deref()
+        .or(enriched.upload.congestion.as_deref())
+    {
+        println!("Congestion control: {name}");
+    }
     if let Some(comments) = enriched.comments.as_deref() {
         if !comments.trim().is_empty() {
             println!("Comments: {}", comments);
         }
     }
+    for protocol in &enriched.protocols {
+        println!("Protocol {:?}: {}", protocol.phase, protocol.describe());
+    }
+    if enriched.protocols.iter().any(|p| p.is_http1()) {
+        println!("Note: some phases ran over HTTP/1.x; results are not comparable to HTTP/2 runs");
+    }
+    for energy in &enriched.energy {
+        println!(
+            "Energy {:?}: {:.1} J ({} J/GB)",
+            energy.phase,
+            energy.joules,
+            fmt_opt(energy.joules_per_gb)
+        );
+    }
+    for event in &enriched.wireless_events {
+        println!("{}", event.describe());
+    }
+    if let Some(soak) = &enriched.soak {
+        println!(
+            "Soak ({:?}, {:.0} min): avg {:.1} Mbps, minute range {}-{} Mbps, variation {}, stalls {} s, loaded latency med {} ms (worst minute {} ms), {} error(s)",
+            soak.direction,
+            soak.duration_ms as f64 / 60_000.0,
+            soak.mean_mbps,
+            fmt_opt(soak.min_minute_mbps),
+            fmt_opt(soak.max_minute_mbps),
+            soak.cv_pct
+                .map(|v| format!("{:.1}%", v))
+                .unwrap_or_else(|| "-".to_string()),
+            soak.stall_seconds,
+            fmt_opt(soak.latency_median_ms),
+            fmt_opt(soak.latency_worst_minute_ms),
+            soak.errors
+        );
+    }
+    if let Some(modem) = &enriched.modem {
+        for (name, value) in &modem.metrics {
+            println!("Modem {}: {}", name, value);
+        }
+    }
+    for (key, value) in &enriched.context {
+        println!("Context {}: {}", key, value);
+    }
+    if !enriched.tags.is_empty() {
+        println!("Tags: {}", enriched.tags_text());
+    }
 
     // Compute and display throughput metrics (mean, median, p25, p75)
-    let dl_values: Vec<f64> = dl_points.iter().map(|(_, y)| *y).collect();
-    let (dl_mean, dl_median, dl_p25, dl_p75) = crate::metrics::compute_metrics(&dl_values)
-        .context("insufficient download throughput data to compute metrics")?;
-    println!(
-        "Download: avg {:.2} med {:.2} p25 {:.2} p75 {:.2}",
-        dl_mean, dl_median, dl_p25, dl_p75
-    );
+    if phases.download {
+        let dl_values: Vec<f64> = dl_points.iter().map(|(_, y)| *y).collect();
+        let (dl_mean, dl_median, dl_p25, dl_p75) = crate::metrics::compute_metrics(&dl_values)
+            .context("insufficient download throughput data to compute metrics")?;
+        println!(
+            "Download: avg {:.2} med {:.2} p25 {:.2} p75 {:.2}",
+            dl_mean, dl_median, dl_p25, dl_p75
+        );
+        if let Some(tcp) = &enriched.download.tcp_info {
+            println!("Download TCP: {}", format_tcp_info(tcp));
+        }
+    } else {
+        println!("Download: skipped");
+    }
 
-    let ul_values: Vec<f64> = ul_points.iter().map(|(_, y)| *y).collect();
-    let (ul_mean, ul_median, ul_p25, ul_p75) = crate::metrics::compute_metrics(&ul_values)
-        .context("insufficient upload throughput data to compute metrics")?;
-    println!(
-        "Upload:   avg {:.2} med {:.2} p25 {:.2} p75 {:.2}",
-        ul_mean, ul_median, ul_p25, ul_p75
-    );
+    if phases.upload {
+        let ul_values: Vec<f64> = ul_points.iter().map(|(_, y)| *y).collect();
+        let (ul_mean, ul_median, ul_p25, ul_p75) = crate::metrics::compute_metrics(&ul_values)
+            .context("insufficient upload throughput data to compute metrics")?;
+        println!(
+            "Upload:   avg {:.2} med {:.2} p25 {:.2} p75 {:.2}",
+            ul_mean, ul_median, ul_p25, ul_p75
+        );
+        if let Some(pct) = enriched.upload.ack_discrepancy_pct {
+            println!("Upload server acknowledgment: {:.1}% unacknowledged", pct);
+        }
+        if let Some(tcp) = &enriched.upload.tcp_info {
+            println!("Upload TCP: {}", format_tcp_info(tcp));
+        }
+    } else {
+        println!("Upload:   skipped");
+    }
 
     // Compute and display latency metrics (mean, median, p25, p75)
-    let (idle_mean, idle_median, idle_p25, idle_p75) =
-        crate::metrics::compute_metrics(&idle_latency_samples)
-            .context("insufficient idle latency data to compute metrics")?;
-    println!(
-        "Idle latency: avg {:.1} med {:.1} p25 {:.1} p75 {:.1} ms (loss {:.1}%, jitter {:.1} ms)",
-        idle_mean,
-        idle_median,
-        idle_p25,
-        idle_p75,
-        enriched.idle_latency.loss * 100.0,
-        enriched.idle_latency.jitter_ms.unwrap_or(f64::NAN)
-    );
+    if phases.idle_latency {
+        let (idle_mean, idle_median, idle_p25, idle_p75) =
+            crate::metrics::compute_metrics(&idle_latency_samples)
+                .context("insufficient idle latency data to compute metrics")?;
+        println!(
+            "Idle latency: avg {:.1} med {:.1} p25 {:.1} p75 {:.1} ms (loss {:.1}%, jitter {:.1} ms)",
+            idle_mean,
+            idle_median,
+            idle_p25,
+            idle_p75,
+            enriched.idle_latency.loss * 100.0,
+            enriched.idle_latency.jitter_ms.unwrap_or(f64::NAN)
+        );
+        if let Some(ttfb) = &enriched.ttfb {
+            println!(
+                "TTFB: med {} min {} max {} ms ({}/{} requests)",
+                fmt_opt(ttfb.median_ms),
+                fmt_opt(ttfb.min_ms),
+                fmt_opt(ttfb.max_ms),
+                ttfb.received,
+                ttfb.sent
+            );
+        }
+    } else {
+        println!("Idle latency: skipped");
+    }
 
-    let (dl_lat_mean, dl_lat_median, dl_lat_p25, dl_lat_p75) =
-        crate::metrics::compute_metrics(&loaded_dl_latency_samples)
-            .context("insufficient loaded download latency data to compute metrics")?;
-    println!(
-        "Loaded latency (download): avg {:.1} med {:.1} p25 {:.1} p75 {:.1} ms (loss {:.1}%, jitter {:.1} ms)",
-        dl_lat_mean,
-        dl_lat_median,
-        dl_lat_p25,
-        dl_lat_p75,
-        enriched.loaded_latency_download.loss * 100.0,
-        enriched.loaded_latency_download.jitter_ms.unwrap_or(f64::NAN)
-    );
+    if phases.download {
+        let (dl_lat_mean, dl_lat_median, dl_lat_p25, dl_lat_p75) =
+            crate::metrics::compute_metrics(&loaded_dl_latency_samples)
+                .context("insufficient loaded download latency data to compute metrics")?;
+        println!(
+            "Loaded latency (download): avg {:.1} med {:.1} p25 {:.1} p75 {:.1} ms (loss {:.1}%, jitter {:.1} ms)",
+            dl_lat_mean,
+            dl_lat_median,
+            dl_lat_p25,
+            dl_lat_p75,
+            enriched.loaded_latency_download.loss * 100.0,
+            enriched.loaded_latency_download.jitter_ms.unwrap_or(f64::NAN)
+        );
+        if let Some(seg) = &enriched.loaded_latency_download_segments {
+            println!("  by third: {}", format_latency_segments(seg));
+        }
+    }
 
-    let (ul_lat_mean, ul_lat_median, ul_lat_p25, ul_lat_p75) =
-        crate::metrics::compute_metrics(&loaded_ul_latency_samples)
-            .context("insufficient loaded upload latency data to compute metrics")?;
-    println!(
-        "Loaded latency (upload): avg {:.1} med {:.1} p25 {:.1} p75 {:.1} ms (loss {:.1}%, jitter {:.1} ms)",
-        ul_lat_mean,
-        ul_lat_median,
-        ul_lat_p25,
-        ul_lat_p75,
-        enriched.loaded_latency_upload.loss * 100.0,
-        enriched.loaded_latency_upload.jitter_ms.unwrap_or(f64::NAN)
-    );
+    if phases.upload {
+        let (ul_lat_mean, ul_lat_median, ul_lat_p25, ul_lat_p75) =
+            crate::metrics::compute_metrics(&loaded_ul_latency_samples)
+                .context("insufficient loaded upload latency data to compute metrics")?;
+        println!(
+            "Loaded latency (upload): avg {:.1} med {:.1} p25 {:.1} p75 {:.1} ms (loss {:.1}%, jitter {:.1} ms)",
+            ul_lat_mean,
+            ul_lat_median,
+            ul_lat_p25,
+            ul_lat_p75,
+            enriched.loaded_latency_upload.loss * 100.0,
+            enriched.loaded_latency_upload.jitter_ms.unwrap_or(f64::NAN)
+        );
+        if let Some(seg) = &enriched.loaded_latency_upload_segments {
+            println!("  by third: {}", format_latency_segments(seg));
+        }
+    }
+    for m in &enriched.measurements {
+        println!(
+            "{:?} {:>6} kB x{}: median {} Mbps, p90 {} Mbps, latency {} ms{}",
+            m.direction,
+            m.bytes / 1000,
+            m.count,
+            fmt_opt(m.median_mbps),
+            fmt_opt(m.p90_mbps),
+            fmt_opt(m.median_latency_ms),
+            if m.errors > 0 {
+                format!(" ({} failed)", m.errors)
+            } else {
+                String::new()
+            }
+        );
+    }
+    for p in &enriched.protocol_comparison {
+        match (p.mbps, &p.error) {
+            (Some(mbps), _) => println!(
+                "Protocol {}: {:.2} Mbps, TTFB {} ms ({} requests{})",
+                p.protocol.label(),
+                mbps,
+                fmt_opt(p.ttfb_ms),
+                p.requests,
+                p.negotiated
+                    .as_deref()
+                    .map(|v| format!(", negotiated {}", v))
+                    .unwrap_or_default()
+            ),
+            (None, error) => println!(
+                "Protocol {}: unavailable{}",
+                p.protocol.label(),
+                error
+                    .as_deref()
+                    .map(|e| format!(" ({})", e))
+                    .unwrap_or_default()
+            ),
+        }
+    }
+    if let Some(sweep) = &enriched.concurrency_sweep {
+        for level in &sweep.levels {
+            println!(
+                "Sweep {:>3} connection(s): {:.2} Mbps ({:.2} per connection), loaded latency {} ms",
+                level.connections,
+                level.mbps,
+                level.mbps / level.connections as f64,
+                fmt_opt(level.loaded_latency_median_ms)
+            );
+        }
+        match sweep.bottleneck {
+            Some(crate::model::SweepBottleneck::PerFlow) => println!(
+                "Sweep: throughput grows with connections, so each connection is limited (window, per-flow shaping)"
+            ),
+            Some(crate::model::SweepBottleneck::Aggregate) => {
+                println!("Sweep: few connections already fill the link, so the link is the limit")
+            }
+            None => {}
+        }
+    }
+    if let Some(aim) = &enriched.aim {
+        println!(
+            "Good for: streaming {}, gaming {}, video calls {}",
+            aim.streaming.label(),
+            aim.gaming.label(),
+            aim.rtc.label()
+        );
+    }
+    if let Some(bloat) = &enriched.bufferbloat {
+        println!(
+            "Bufferbloat: grade {} (p75 increase: download +{} ms, upload +{} ms)",
+            bloat.grade.label(),
+            fmt_opt(bloat.download_increase_ms),
+            fmt_opt(bloat.upload_increase_ms)
+        );
+    }
+    for cmp in &enriched.comparisons {
+        println!("{}", crate::comparison::summarize(cmp));
+    }
+    if let Some(icmp) = &enriched.icmp_latency {
+        match (&icmp.error, icmp.latency.median_ms) {
+            (Some(e), _) => println!("ICMP ping: {}", e),
+            (None, Some(median)) => println!(
+                "ICMP ping {}: med {:.1} ms (loss {:.1}%, jitter {:.1} ms)",
+                icmp.target,
+                median,
+                icmp.latency.loss * 100.0,
+                icmp.latency.jitter_ms.unwrap_or(f64::NAN)
+            ),
+            (None, None) => println!("ICMP ping {}: no response", icmp.target),
+        }
+    }
+    for anchor in &enriched.anchors {
+        match (&anchor.error, anchor.latency.median_ms) {
+            (Some(e), _) => println!("Anchor {}: {}", anchor.target, e),
+            (None, Some(median)) => println!(
+                "Anchor {}: med {:.1} ms (loss {:.1}%, jitter {:.1} ms)",
+                anchor.target,
+                median,
+                anchor.latency.loss * 100.0,
+                anchor.latency.jitter_ms.unwrap_or(f64::NAN)
+            ),
+            (None, None) => println!("Anchor {}: no response", anchor.target),
+        }
+    }
     if let Some(ref exp) = enriched.experimental_udp {
-        let mos_str = exp.mos.map(|m| format!("MOS {:.1}", m)).unwrap_or_else(|| "N/A".to_string());
-        let jitter_str = exp.latency.jitter_ms.map(|j| format!("{:.1}ms", j)).unwrap_or_else(|| "-".to_string());
+        let mos_str = exp
+            .mos
+            .map(|m| format!("MOS {:.1}", m))
+            .unwrap_or_else(|| "N/A".to_string());
+        let jitter_str = exp
+            .latency
+            .jitter_ms
+            .map(|j| format!("{:.1}ms", j))
+            .unwrap_or_else(|| "-".to_string());
         println!(
             "UDP quality: {} ({}) | loss {:.1}% jitter {} reorder {:.1}% rtt {}ms",
             exp.quality_label,
@@ -523,22 +2001,223 @@ async fn run_text(args: Cli) -> Result<()> {
             exp.out_of_order_pct,
             exp.latency.median_ms.unwrap_or(f64::NAN)
         );
+        if let Some(relay) = &exp.relay {
+            println!(
+                "UDP via TURN relay: loss {:.1}% jitter {} rtt {}ms",
+                relay.latency.loss * 100.0,
+                relay
+                    .latency
+                    .jitter_ms
+                    .map(|j| format!("{:.1}ms", j))
+                    .unwrap_or_else(|| "-".to_string()),
+                relay.latency.median_ms.unwrap_or(f64::NAN)
+            );
+        }
+        if let Some(e) = &exp.relay_error {
+            println!("UDP via TURN relay: failed ({e})");
+        }
+    }
+    if let Some(reason) = enriched.udp_skipped.as_deref() {
+        println!("UDP quality: skipped ({reason})");
+    }
+    if let Some(udp) = &enriched.udp_throughput {
+        match &udp.error {
+            Some(e) => println!("UDP throughput: failed ({e})"),
+            None => println!(
+                "UDP throughput: {:.1} Mbps of {:.0} paced | loss {:.1}% reorder {} of {}",
+                udp.goodput_mbps,
+                udp.target_mbps,
+                udp.loss * 100.0,
+                udp.out_of_order,
+                udp.received
+            ),
+        }
     }
-    if args.auto_save {
-        if let Ok(p) = crate::storage::save_run(&enriched) {
-            eprintln!("Saved: {}", p.display());
+    publish(&sinks, &enriched, false).await?;
+    Ok(enriched)
+}
+
+/// Publish a result to each sink in order, reporting status lines and warnings on stderr.
+/// A failing history save is only reported unless `silent`; any other failure is an error.
+pub(crate) async fn publish(
+    sinks: &crate::sinks::Sinks,
+    result: &RunResult,
+    silent: bool,
+) -> Result<()> {
+    crate::systemd::log_result(result);
+    for (spec, sink) in sinks.iter() {
+        match sink.publish(result).await {
+            Ok(published) => {
+                if let Some(msg) = published.message.filter(|_| !silent) {
+                    eprintln!("{}", msg);
+                }
+                if let Some(w) = published.warning {
+                    tracing::warn!("{}", w);
+                }
+            }
+            Err(e) if matches!(spec, crate::sinks::SinkSpec::History(_)) && !silent => {
+                tracing::error!("Failed to save run: {e:#}");
+            }
+            Err(e) => return Err(e.context(format!("sink {} failed", sink.name()))),
         }
     }
     Ok(())
 }
 
-/// Handle export operations (JSON and CSV) for both text and JSON modes.
-fn handle_exports(args: &Cli, result: &crate::model::RunResult) -> Result<()> {
-    if let Some(p) = args.export_json.as_deref() {
-        crate::storage::export_json(p, result)?;
+/// Format an optional value with one decimal, or "-" when missing.
+pub(crate) fn fmt_opt(v: Option<f64>) -> String {
+    v.map(|v| format!("{:.1}", v))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Median latency per third of a phase, e.g. "early 12.0 / mid 30.1 / late 45.2 ms".
+fn format_latency_segments(seg: &crate::model::LatencySegments) -> String {
+    let med = |l: &crate::model::LatencySummary| fmt_opt(l.median_ms);
+    format!(
+        "early {} / mid {} / late {} ms",
+        med(&seg.early),
+        med(&seg.mid),
+        med(&seg.late)
+    )
+}
+
+/// One line of a phase's TCP statistics, e.g. "4 connection(s), RTT 21.0 ms (min 12.3),
+/// cwnd 64, retransmits 0.20%".
+fn format_tcp_info(tcp: &crate::model::TcpInfoSummary) -> String {
+    let cwnd: Vec<f64> = tcp.connections.iter().map(|c| c.cwnd as f64).collect();
+    let mut line = format!(
+        "{} connection(s), RTT {} ms (min {}), cwnd {}",
+        tcp.connections.len(),
+        fmt_opt(tcp.rtt_ms),
+        fmt_opt(tcp.min_rtt_ms),
+        crate::metrics::percentile(&cwnd, 0.5).unwrap_or(0.0)
+    );
+    if let Some(pct) = tcp.retransmit_pct {
+        line.push_str(&format!(", retransmits {:.2}%", pct));
     }
-    if let Some(p) = args.export_csv.as_deref() {
-        crate::storage::export_csv(p, result)?;
+    let pacing: u64 = tcp
+        .connections
+        .iter()
+        .filter_map(|c| c.pacing_rate_bps)
+        .sum();
+    if pacing > 0 {
+        line.push_str(&format!(", pacing {:.1} Mbps", pacing as f64 / 1_000_000.0));
+    }
+    line
+}
+
+/// One family of the IPv4 vs IPv6 comparison, e.g. "IPv4: 1.2.3.4 - DL 95.00 Mbps, UL 20.00
+/// Mbps, latency 12.0ms, loaded DL 30.1 / p75 41.0 ms, UL 55.2 / p75 70.3 ms, jitter 4.1ms,
+/// loss 0.0%".
+fn format_ip_version(label: &str, r: &crate::model::IpVersionResult) -> String {
+    if !r.available {
+        return format!("{}: unavailable - {:?}", label, r.error);
+    }
+    let mut line = format!(
+        "{}: {} - DL {:.2} Mbps, UL {:.2} Mbps, latency {:.1}ms",
+        label, r.ip_address, r.download_mbps, r.upload_mbps, r.latency_ms
+    );
+    let loaded: Vec<String> = [
+        ("DL", &r.loaded_latency_download),
+        ("UL", &r.loaded_latency_upload),
+    ]
+    .into_iter()
+    .filter_map(|(phase, l)| {
+        let l = l.as_ref()?;
+        Some(format!(
+            "{} {} / p75 {} ms",
+            phase,
+            fmt_opt(l.median_ms),
+            fmt_opt(l.p75_ms)
+        ))
+    })
+    .collect();
+    if !loaded.is_empty() {
+        line.push_str(&format!(", loaded {}", loaded.join(", ")));
+    }
+    if let Some(jitter) = r.loaded_jitter_ms() {
+        line.push_str(&format!(", jitter {:.1}ms", jitter));
+    }
+    if let Some(loss) = r.loss() {
+        line.push_str(&format!(", loss {:.1}%", loss * 100.0));
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_matches_no_options() {
+        let args = Cli::try_parse_from(["cloudflare-speed-cli"]).unwrap();
+        let mut built = serde_json::to_value(build_config(&args)).unwrap();
+        let mut default = serde_json::to_value(RunConfig::default()).unwrap();
+        built["meas_id"] = serde_json::Value::Null;
+        default["meas_id"] = serde_json::Value::Null;
+        assert_eq!(built, default);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("200MB"), Ok(200_000_000));
+        assert_eq!(parse_size("1.5 GB"), Ok(1_500_000_000));
+        assert_eq!(parse_size("512KiB"), Ok(512 * 1024));
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert!(parse_size("10 parsecs").is_err());
+        assert!(parse_size("0MB").is_err());
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            parse_header("X-Auth: token"),
+            Ok(("X-Auth".to_string(), "token".to_string()))
+        );
+        assert_eq!(
+            parse_header("cf-access-client-id:abc.access"),
+            Ok(("cf-access-client-id".to_string(), "abc.access".to_string()))
+        );
+        assert!(parse_header("X-Auth token").is_err());
+        assert!(parse_header("Bad Name: x").is_err());
+    }
+
+    #[test]
+    fn test_parse_colo() {
+        assert_eq!(parse_colo(" sin").unwrap(), "SIN");
+        assert!(parse_colo("SING").is_err());
+        assert!(parse_colo("S1N").is_err());
+    }
+
+    #[test]
+    fn test_parse_lan_peer() {
+        assert_eq!(parse_lan_peer("nas.local").unwrap(), "nas.local:7879");
+        assert_eq!(
+            parse_lan_peer("192.168.1.2:9000").unwrap(),
+            "192.168.1.2:9000"
+        );
+        assert_eq!(parse_lan_peer("fe80::1").unwrap(), "[fe80::1]:7879");
+        assert!(parse_lan_peer("nas.local:http").is_err());
+    }
+
+    #[test]
+    fn test_parse_resolve() {
+        let pin = parse_resolve("speed.cloudflare.com:443:203.0.113.7").unwrap();
+        assert_eq!(pin.host, "speed.cloudflare.com");
+        assert_eq!(pin.port, 443);
+        assert_eq!(pin.ip, "203.0.113.7".parse::<std::net::IpAddr>().unwrap());
+        assert_eq!(pin.to_string(), "speed.cloudflare.com:443:203.0.113.7");
+
+        let pin = parse_resolve("Speed.Cloudflare.com:443:[2001:db8::7]").unwrap();
+        assert_eq!(pin.host, "speed.cloudflare.com");
+        assert_eq!(pin.to_string(), "speed.cloudflare.com:443:[2001:db8::7]");
+        assert_eq!(
+            parse_resolve("speed.cloudflare.com:443:2001:db8::7").map(|p| p.ip),
+            Ok(pin.ip)
+        );
+
+        assert!(parse_resolve("speed.cloudflare.com:203.0.113.7").is_err());
+        assert!(parse_resolve("speed.cloudflare.com:https:203.0.113.7").is_err());
+        assert!(parse_resolve(":443:203.0.113.7").is_err());
     }
-    Ok(())
 }
@@ -0,0 +1,261 @@
+//! Building and rendering of comparison results.
+//!
+//! All comparison modes produce a `Comparison` so that JSON, CSV, text and TUI output
+//! agree on deltas and on which side won.
+
+use crate::model::{
+    Comparison, ComparisonDeltas, ComparisonKind, ComparisonSide, IpVersionComparison,
+    IpVersionResult, ProtocolResult,
+};
+
+/// Differences smaller than this (in percent) count as a tie for that metric
+const TIE_THRESHOLD_PCT: f64 = 5.0;
+
+/// Loss differences smaller than this (in percentage points) count as a tie
+const LOSS_TIE_THRESHOLD_PP: f64 = 1.0;
+
+/// Compare `candidate` against `baseline`, computing relative deltas and the winner.
+pub fn compare(
+    kind: ComparisonKind,
+    baseline: ComparisonSide,
+    candidate: ComparisonSide,
+) -> Comparison {
+    let both = baseline.available && candidate.available;
+    let deltas = if both {
+        ComparisonDeltas {
+            download_pct: relative_delta(baseline.download_mbps, candidate.download_mbps),
+            upload_pct: relative_delta(baseline.upload_mbps, candidate.upload_mbps),
+            latency_pct: relative_delta(baseline.latency_ms, candidate.latency_ms),
+            loaded_latency_pct: relative_delta(
+                baseline.loaded_latency_ms,
+                candidate.loaded_latency_ms,
+            ),
+            jitter_pct: relative_delta(baseline.jitter_ms, candidate.jitter_ms),
+            loss_pp: baseline
+                .loss
+                .zip(candidate.loss)
+                .map(|(base, value)| (value - base) * 100.0),
+        }
+    } else {
+        ComparisonDeltas::default()
+    };
+
+    let winner = match (baseline.available, candidate.available) {
+        (true, false) => Some(baseline.label.clone()),
+        (false, true) => Some(candidate.label.clone()),
+        (false, false) => None,
+        (true, true) => {
+            // Higher throughput is better, lower latency, jitter and loss are better
+            let score = [
+                (deltas.download_pct, TIE_THRESHOLD_PCT),
+                (deltas.upload_pct, TIE_THRESHOLD_PCT),
+                (deltas.latency_pct.map(|d| -d), TIE_THRESHOLD_PCT),
+                (deltas.loaded_latency_pct.map(|d| -d), TIE_THRESHOLD_PCT),
+                (deltas.jitter_pct.map(|d| -d), TIE_THRESHOLD_PCT),
+                (deltas.loss_pp.map(|d| -d), LOSS_TIE_THRESHOLD_PP),
+            ]
+            .iter()
+            .filter_map(|(d, threshold)| d.map(|d| (d, *threshold)))
+            .map(|(d, threshold)| {
+                if d > threshold {
+                    1
+                } else if d < -threshold {
+                    -1
+                } else {
+                    0
+                }
+            })
+            .sum::<i32>();
+            match score {
+                s if s > 0 => Some(candidate.label.clone()),
+                s if s < 0 => Some(baseline.label.clone()),
+                _ => None,
+            }
+        }
+    };
+
+    Comparison {
+        kind,
+        baseline,
+        candidate,
+        deltas,
+        winner,
+    }
+}
+
+/// Percent change from `base` to `value`; None when either is missing or base is zero.
+fn relative_delta(base: Option<f64>, value: Option<f64>) -> Option<f64> {
+    let (base, value) = (base?, value?);
+    if base == 0.0 {
+        return None;
+    }
+    Some((value - base) / base * 100.0)
+}
+
+fn ip_version_side(label: &str, r: Option<&IpVersionResult>) -> ComparisonSide {
+    match r {
+        Some(r) => ComparisonSide {
+            label: label.to_string(),
+            download_mbps: r.available.then_some(r.download_mbps),
+            upload_mbps: (r.available && r.error.is_none()).then_some(r.upload_mbps),
+            latency_ms: r.available.then_some(r.latency_ms),
+            loaded_latency_ms: r.loaded_latency_ms(),
+            jitter_ms: r.loaded_jitter_ms(),
+            loss: r.loss(),
+            available: r.available,
+            error: r.error.clone(),
+        },
+        None => ComparisonSide {
+            label: label.to_string(),
+            download_mbps: None,
+            upload_mbps: None,
+            latency_ms: None,
+            loaded_latency_ms: None,
+            jitter_ms: None,
+            loss: None,
+            available: false,
+            error: None,
+        },
+    }
+}
+
+/// Build the structured comparison for an IPv4 vs IPv6 run (IPv4 is the baseline).
+pub fn from_ip_versions(cmp: &IpVersionComparison) -> Comparison {
+    compare(
+        ComparisonKind::IpVersion,
+        ip_version_side("IPv4", cmp.ipv4_result.as_ref()),
+        ip_version_side("IPv6", cmp.ipv6_result.as_ref()),
+    )
+}
+
+/// Each HTTP version against HTTP/1.1 (`--compare-protocols`), with time to first byte as
+/// the latency.
+pub fn from_protocols(results: &[ProtocolResult]) -> Vec<Comparison> {
+    let side = |r: &ProtocolResult| ComparisonSide {
+        label: r.protocol.label().to_string(),
+        download_mbps: r.mbps,
+        upload_mbps: None,
+        latency_ms: r.ttfb_ms,
+        loaded_latency_ms: None,
+        jitter_ms: None,
+        loss: None,
+        available: r.available,
+        error: r.error.clone(),
+    };
+    let Some((baseline, rest)) = results.split_first() else {
+        return Vec::new();
+    };
+    rest.iter()
+        .map(|r| compare(ComparisonKind::Protocol, side(baseline), side(r)))
+        .collect()
+}
+
+fn format_delta(name: &str, d: Option<f64>) -> Option<String> {
+    d.map(|d| format!("{} {:+.1}%", name, d))
+}
+
+/// One-line summary, e.g. "IPv4 vs IPv6: IPv6 better (DL +12.3%, UL -1.0%, latency -8.2%,
+/// loaded -20.5%, jitter -3.0%, loss +0.0pp)".
+pub fn summarize(c: &Comparison) -> String {
+    let verdict = match &c.winner {
+        Some(w) => format!("{} better", w),
+        None if c.baseline.available || c.candidate.available => "tie".to_string(),
+        None => "unavailable".to_string(),
+    };
+    let deltas: Vec<String> = [
+        format_delta("DL", c.deltas.download_pct),
+        format_delta("UL", c.deltas.upload_pct),
+        format_delta("latency", c.deltas.latency_pct),
+        format_delta("loaded", c.deltas.loaded_latency_pct),
+        format_delta("jitter", c.deltas.jitter_pct),
+        c.deltas.loss_pp.map(|d| format!("loss {:+.1}pp", d)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    if deltas.is_empty() {
+        format!("{} vs {}: {}", c.baseline.label, c.candidate.label, verdict)
+    } else {
+        format!(
+            "{} vs {}: {} ({})",
+            c.baseline.label,
+            c.candidate.label,
+            verdict,
+            deltas.join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn side(label: &str, dl: f64, ul: f64, lat: f64) -> ComparisonSide {
+        ComparisonSide {
+            label: label.to_string(),
+            download_mbps: Some(dl),
+            upload_mbps: Some(ul),
+            latency_ms: Some(lat),
+            loaded_latency_ms: None,
+            jitter_ms: None,
+            loss: None,
+            available: true,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_compare_deltas_and_winner() {
+        let c = compare(
+            ComparisonKind::IpVersion,
+            side("IPv4", 100.0, 50.0, 20.0),
+            side("IPv6", 120.0, 50.0, 10.0),
+        );
+        assert!((c.deltas.download_pct.unwrap() - 20.0).abs() < 1e-9);
+        assert!((c.deltas.upload_pct.unwrap()).abs() < 1e-9);
+        assert!((c.deltas.latency_pct.unwrap() + 50.0).abs() < 1e-9);
+        assert_eq!(c.winner.as_deref(), Some("IPv6"));
+        assert_eq!(
+            summarize(&c),
+            "IPv4 vs IPv6: IPv6 better (DL +20.0%, UL +0.0%, latency -50.0%)"
+        );
+    }
+
+    #[test]
+    fn test_compare_tie_and_unavailable() {
+        let c = compare(
+            ComparisonKind::IpVersion,
+            side("IPv4", 100.0, 50.0, 20.0),
+            side("IPv6", 102.0, 49.0, 20.5),
+        );
+        assert_eq!(c.winner, None);
+
+        let mut down = side("IPv6", 0.0, 0.0, 0.0);
+        down.available = false;
+        let c = compare(ComparisonKind::IpVersion, side("IPv4", 1.0, 1.0, 1.0), down);
+        assert_eq!(c.winner.as_deref(), Some("IPv4"));
+        assert!(c.deltas.download_pct.is_none());
+    }
+
+    #[test]
+    fn test_compare_under_load() {
+        // Equal when idle, but IPv6 queues up and drops probes under load
+        let mut v4 = side("IPv4", 100.0, 50.0, 20.0);
+        v4.loaded_latency_ms = Some(40.0);
+        v4.jitter_ms = Some(4.0);
+        v4.loss = Some(0.0);
+        let mut v6 = side("IPv6", 100.0, 50.0, 20.0);
+        v6.loaded_latency_ms = Some(120.0);
+        v6.jitter_ms = Some(12.0);
+        v6.loss = Some(0.05);
+        let c = compare(ComparisonKind::IpVersion, v4, v6);
+        assert!((c.deltas.loss_pp.unwrap() - 5.0).abs() < 1e-9);
+        assert_eq!(c.winner.as_deref(), Some("IPv4"));
+        assert_eq!(
+            summarize(&c),
+            "IPv4 vs IPv6: IPv4 better (DL +0.0%, UL +0.0%, latency +0.0%, loaded +200.0%, \
+             jitter +200.0%, loss +5.0pp)"
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! Configuration file (`~/.config/cloudflare-speed-cli/config.toml`) and environment
+//!
+//! Every top-level option can be given a default in the file, keyed by its long name
+//! (`download_duration = "20s"`, `auto_save = false`, `anchors = ["gateway"]`,
+//! `verbose = 1`), or set
+//! through a `CFSPEED_<NAME>` environment variable (`CFSPEED_DOWNLOAD_DURATION=20s`).
+//! Settings are turned into arguments placed before the real command line, so they go
+//! through the same parsing and validation as flags. The command line wins over the
+//! environment, which wins over the file; a file setting is also ignored when an option
+//! it conflicts with was given.
+
+use crate::cli::Cli;
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, CommandFactory, FromArgMatches};
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+const CONFIG_FILE: &str = "config.toml";
+
+pub fn path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("cloudflare-speed-cli")
+        .join(CONFIG_FILE)
+}
+
+/// Command line merged with the configuration file
+struct Resolved {
+    cli: Cli,
+    matches: ArgMatches,
+    /// Ids of the options whose value came from the file
+    from_file: BTreeSet<String>,
+}
+
+/// Parse the process arguments with defaults from the configuration file. Exits with
+/// clap's usage message on invalid flags, like `Cli::parse`.
+pub fn parse_args() -> Result<Cli> {
+    Ok(resolve(std::env::args_os().collect())?.cli)
+}
+
+fn resolve(argv: Vec<OsString>) -> Result<Resolved> {
+    let cmd = command();
+    let given = cmd.clone().get_matches_from(&argv);
+    let path = path();
+    // `config init --force` has to work even when the existing file is broken
+    let replacing_file = given
+        .subcommand_matches("config")
+        .is_some_and(|m| m.subcommand_name() == Some("init"));
+    let table = match std::fs::read_to_string(&path) {
+        _ if replacing_file => toml::Table::new(),
+        Ok(text) => text
+            .parse::<toml::Table>()
+            .with_context(|| format!("parse {}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => toml::Table::new(),
+        Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+    };
+
+    let mut extra = Vec::new();
+    let mut from_file = BTreeSet::new();
+    for (key, value) in &table {
+        let arg = find_arg(&cmd, key)
+            .with_context(|| format!("{}: unknown setting '{}'", path.display(), key))?;
+        let id = arg.get_id().as_str();
+        if is_overridden(&cmd, arg, &given) {
+            continue;
+        }
+        let values = setting_args(arg, value)
+            .with_context(|| format!("{}: invalid value for '{}'", path.display(), key))?;
+        if !values.is_empty() {
+            from_file.insert(id.to_string());
+        }
+        extra.extend(values);
+    }
+    if extra.is_empty() {
+        return Ok(Resolved {
+            cli: Cli::from_arg_matches(&given)?,
+            matches: given,
+            from_file,
+        });
+    }
+
+    let mut merged = argv;
+    let rest = merged.split_off(merged.len().min(1));
+    merged.extend(extra);
+    merged.extend(rest);
+    let matches = cmd
+        .try_get_matches_from(merged)
+        .with_context(|| format!("invalid settings in {}", path.display()))?;
+    Ok(Resolved {
+        cli: Cli::from_arg_matches(&matches)?,
+        matches,
+        from_file,
+    })
+}
+
+/// The option a config key names: its id (`download_duration`) or long flag
+/// (`download-duration`)
+fn find_arg<'a>(cmd: &'a clap::Command, key: &str) -> Option<&'a Arg> {
+    let key = key.replace('-', "_");
+    settings(cmd).find(|a| {
+        a.get_id() == key.as_str() || a.get_long().map(|l| l.replace('-', "_")) == Some(key.clone())
+    })
+}
+
+/// Whether the command line already settles this option: given explicitly (or through the
+/// environment), or something it conflicts with was given.
+fn is_overridden(cmd: &clap::Command, arg: &Arg, given: &ArgMatches) -> bool {
+    let explicit = |id: &str| {
+        matches!(
+            given.value_source(id),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        )
+    };
+    if explicit(arg.get_id().as_str()) {
+        return true;
+    }
+    // Conflicts are declared on one side only (`quiet` conflicts with `verbose`)
+    let declared_against = cmd.get_arguments().filter(|other| {
+        cmd.get_arg_conflicts_with(other)
+            .iter()
+            .any(|a| a.get_id() == arg.get_id())
+    });
+    let conflicting = cmd
+        .get_arg_conflicts_with(arg)
+        .into_iter()
+        .chain(declared_against)
+        .map(|a| a.get_id().clone())
+        .chain(
+            cmd.get_groups()
+                .filter(|g| {
+                    !(*g).clone().is_multiple() && g.get_args().any(|id| id == arg.get_id())
+                })
+                .flat_map(|g| g.get_args().cloned().collect::<Vec<_>>()),
+        );
+    conflicting
+        .filter(|id| id != arg.get_id())
+        .any(|id| explicit(id.as_str()))
+}
+
+/// Arguments equivalent to `key = value`
+fn setting_args(arg: &Arg, value: &toml::Value) -> Result<Vec<OsString>> {
+    let flag = format!("--{}", arg.get_long().unwrap_or_default());
+    let scalar = |v: &toml::Value| -> Result<String> {
+        Ok(match v {
+            toml::Value::String(s) => s.clone(),
+            toml::Value::Integer(i) => i.to_string(),
+            toml::Value::Float(f) => f.to_string(),
+            toml::Value::Boolean(b) => b.to_string(),
+            toml::Value::Datetime(d) => d.to_string(),
+            _ => anyhow::bail!("expected a string, number or boolean"),
+        })
+    };
+    Ok(match (arg.get_action(), value) {
+        (ArgAction::SetTrue, toml::Value::Boolean(true)) => vec![flag.into()],
+        (ArgAction::SetTrue, toml::Value::Boolean(false)) => Vec::new(),
+        (ArgAction::SetTrue, _) => anyhow::bail!("expected true or false"),
+        // `verbose = 2` is `-vv`
+        (ArgAction::Count, toml::Value::Integer(n)) => {
+            let n = usize::try_from(*n).context("expected a count of 0 or more")?;
+            vec![flag.into(); n]
+        }
+        (ArgAction::Count, _) => anyhow::bail!("expected a count"),
+        (ArgAction::Append, toml::Value::Array(items)) => items
+            .iter()
+            .map(|v| Ok(format!("{}={}", flag, scalar(v)?).into()))
+            .collect::<Result<_>>()?,
+        _ => vec![format!("{}={}", flag, scalar(value)?).into()],
+    })
+}
+
+/// A raw option value as TOML: booleans and numbers unquoted, everything else a string.
+fn toml_value(raw: &str) -> toml::Value {
+    if let Ok(b) = raw.parse::<bool>() {
+        toml::Value::Boolean(b)
+    } else if let Ok(i) = raw.parse::<i64>() {
+        toml::Value::Integer(i)
+    } else if let Ok(f) = raw.parse::<f64>() {
+        toml::Value::Float(f)
+    } else {
+        toml::Value::String(raw.to_string())
+    }
+}
+
+/// Prefix of the environment variables that set options
+const ENV_PREFIX: &str = "CFSPEED_";
+
+/// The CLI definition with an environment variable for every option, built so implicit
+/// groups (`group = "monitor"`) and flag defaults are filled in
+fn command() -> clap::Command {
+    let mut cmd = Cli::command().mut_args(|arg| {
+        let name = format!("{}{}", ENV_PREFIX, arg.get_id().as_str().to_uppercase());
+        match arg.get_action() {
+            ArgAction::Help | ArgAction::Version => arg,
+            // Values can hold credentials (proxy, tokens); keep them out of --help
+            _ => arg.env(name).hide_env_values(true),
+        }
+    });
+    cmd.build();
+    cmd
+}
+
+/// Options that can be set in the file, in `--help` order
+fn settings(cmd: &clap::Command) -> impl Iterator<Item = &Arg> {
+    cmd.get_arguments().filter(|a| {
+        a.get_long().is_some() && !matches!(a.get_action(), ArgAction::Help | ArgAction::Version)
+    })
+}
+
+/// `config init`: write a file listing every option, commented out at its default.
+pub fn init(force: bool) -> Result<PathBuf> {
+    let path = path();
+    anyhow::ensure!(
+        force || !path.exists(),
+        "{} already exists (use --force to overwrite)",
+        path.display()
+    );
+    let cmd = command();
+    let mut out = String::from(
+        "# cloudflare-speed-cli defaults; command line flags take precedence.\n\
+         # Uncomment and edit the settings to change.\n",
+    );
+    for arg in settings(&cmd) {
+        out.push('\n');
+        if let Some(help) = arg.get_help() {
+            for line in help.to_string().lines() {
+                out.push_str(&format!("# {}\n", line));
+            }
+        }
+        let defaults: Vec<_> = arg
+            .get_default_values()
+            .iter()
+            .map(|v| toml_value(&v.to_string_lossy()))
+            .collect();
+        let example = match (defaults.as_slice(), arg.get_action()) {
+            ([value], _) => value.to_string(),
+            (_, ArgAction::Append) => "[]".to_string(),
+            _ => {
+                let name = arg
+                    .get_value_names()
+                    .and_then(|n| n.first())
+                    .map(|n| n.to_string())
+                    .unwrap_or_else(|| arg.get_id().as_str().to_uppercase());
+                toml::Value::String(name).to_string()
+            }
+        };
+        out.push_str(&format!("# {} = {}\n", arg.get_id(), example));
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context("create config directory")?;
+    }
+    std::fs::write(&path, out).with_context(|| format!("write {}", path.display()))?;
+    Ok(path)
+}
+
+/// `config show`: print the effective settings and where non-default values come from.
+pub fn show() -> Result<()> {
+    let resolved = resolve(std::env::args_os().collect())?;
+    let path = path();
+    println!(
+        "# {}{}",
+        path.display(),
+        if path.exists() { "" } else { " (not found)" }
+    );
+    let cmd = command();
+    for arg in settings(&cmd) {
+        let id = arg.get_id().as_str();
+        let raw: Vec<String> = resolved
+            .matches
+            .get_raw(id)
+            .into_iter()
+            .flatten()
+            .map(|v| v.to_string_lossy().into_owned())
+            .collect();
+        let value = match (raw.as_slice(), arg.get_action()) {
+            (_, ArgAction::Append) => {
+                toml::Value::Array(raw.iter().map(|v| toml_value(v)).collect()).to_string()
+            }
+            ([value], _) => toml_value(value).to_string(),
+            _ => {
+                println!("# {} is not set", id);
+                continue;
+            }
+        };
+        let source = if resolved.from_file.contains(id) {
+            "  # config file"
+        } else {
+            match resolved.matches.value_source(id) {
+                Some(ValueSource::CommandLine) => "  # command line",
+                Some(ValueSource::EnvVariable) => "  # environment",
+                _ => "",
+            }
+        };
+        println!("{} = {}{}", id, value, source);
+    }
+    Ok(())
+}
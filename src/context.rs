@@ -0,0 +1,103 @@
+//! External context attached to each run.
+//!
+//! Static `--context KEY=VALUE` pairs and the output of an optional `--context-hook`
+//! command (e.g. a script reading modem SNR or a local outage feed) are stored under
+//! `context` in the result, so domain-specific data rides along with the measurements.
+//! The hook prints either a flat JSON object or `KEY=VALUE` lines on stdout.
+
+use crate::model::RunResult;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Upper bound for a context hook to finish
+const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parse a `KEY=VALUE` argument.
+pub fn parse_pair(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((k, v)) if !k.trim().is_empty() => Ok((k.trim().to_string(), v.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", s)),
+    }
+}
+
+/// Run the context hook for `result`. The run's headline numbers are passed in
+/// `CFSPEED_*` environment variables so the hook can log or react to them.
+pub async fn run_hook(command: &str, result: &RunResult) -> Result<BTreeMap<String, String>> {
+    let mut cmd = if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(command);
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(command);
+        c
+    };
+    cmd.env("CFSPEED_MEAS_ID", &result.meas_id)
+        .env("CFSPEED_TIMESTAMP", &result.timestamp_utc)
+        .env(
+            "CFSPEED_DOWNLOAD_MBPS",
+            format!("{:.2}", result.download.mbps),
+        )
+        .env("CFSPEED_UPLOAD_MBPS", format!("{:.2}", result.upload.mbps))
+        .env(
+            "CFSPEED_IDLE_LATENCY_MS",
+            result
+                .idle_latency
+                .median_ms
+                .map(|v| format!("{:.2}", v))
+                .unwrap_or_default(),
+        )
+        .kill_on_drop(true);
+
+    let output = tokio::time::timeout(HOOK_TIMEOUT, cmd.output())
+        .await
+        .context("context hook timed out")?
+        .context("failed to run context hook")?;
+    anyhow::ensure!(
+        output.status.success(),
+        "context hook exited with {}",
+        output.status
+    );
+    parse_output(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse hook output: a flat JSON object, or `KEY=VALUE` lines (blank lines and
+/// `#` comments are ignored).
+fn parse_output(out: &str) -> Result<BTreeMap<String, String>> {
+    let out = out.trim();
+    if out.starts_with('{') {
+        let obj: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(out).context("context hook printed invalid JSON")?;
+        return Ok(obj
+            .into_iter()
+            .map(|(k, v)| match v {
+                serde_json::Value::String(s) => (k, s),
+                other => (k, other.to_string()),
+            })
+            .collect());
+    }
+    Ok(out
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| parse_pair(l).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        let kv = parse_output("# modem\nsnr_db=38.5\n\ndownstream_lock=yes\n").unwrap();
+        assert_eq!(kv.get("snr_db").map(String::as_str), Some("38.5"));
+        assert_eq!(kv.len(), 2);
+
+        let json = parse_output(r#"{"weather": "storm", "outage": false}"#).unwrap();
+        assert_eq!(json.get("weather").map(String::as_str), Some("storm"));
+        assert_eq!(json.get("outage").map(String::as_str), Some("false"));
+    }
+}
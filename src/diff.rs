@@ -0,0 +1,333 @@
+//! Field-by-field comparison of two run results (`diff a.json b.json`).
+//!
+//! The structured `ResultDiff` is what both the JSON output and the table renderer use,
+//! so scripts and humans see the same deltas and significance hints.
+
+use crate::model::{LatencySummary, RunResult, ThroughputSummary};
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::path::Path;
+
+/// Relative changes smaller than this (in percent) are reported as noise
+const NOISE_THRESHOLD_PCT: f64 = 5.0;
+
+/// Packet loss changes smaller than this (in percentage points) are reported as noise
+const LOSS_NOISE_THRESHOLD_PP: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiffFormat {
+    Json,
+    Table,
+}
+
+/// How much weight a delta deserves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Significance {
+    /// Within run-to-run noise
+    Noise,
+    /// Noticeable change, but the sample spreads overlap (or are unknown)
+    Possible,
+    /// The interquartile ranges of both runs do not overlap
+    Significant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldDiff {
+    pub field: String,
+    pub unit: &'static str,
+    pub a: Option<f64>,
+    pub b: Option<f64>,
+    pub delta: Option<f64>,
+    pub delta_pct: Option<f64>,
+    /// True when b is better than a, false when worse, None when equal or unknown
+    pub improved: Option<bool>,
+    pub significance: Option<Significance>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunRef {
+    pub timestamp_utc: String,
+    pub meas_id: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResultDiff {
+    pub a: RunRef,
+    pub b: RunRef,
+    pub fields: Vec<FieldDiff>,
+}
+
+/// One compared metric: its value plus the p25/p75 spread when known.
+struct Metric {
+    value: Option<f64>,
+    spread: Option<(f64, f64)>,
+}
+
+impl Metric {
+    fn plain(value: Option<f64>) -> Self {
+        Self {
+            value,
+            spread: None,
+        }
+    }
+
+    fn throughput(t: &ThroughputSummary) -> Self {
+        Self {
+            value: Some(t.mbps),
+            spread: t.p25_mbps.zip(t.p75_mbps),
+        }
+    }
+
+    fn latency(l: &LatencySummary) -> Self {
+        Self {
+            value: l.median_ms,
+            spread: l.p25_ms.zip(l.p75_ms),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Better {
+    Higher,
+    Lower,
+}
+
+fn field_diff(field: &str, unit: &'static str, better: Better, a: Metric, b: Metric) -> FieldDiff {
+    let delta = a.value.zip(b.value).map(|(a, b)| b - a);
+    let delta_pct = a
+        .value
+        .zip(b.value)
+        .filter(|(a, _)| *a != 0.0)
+        .map(|(a, b)| (b - a) / a * 100.0);
+    let improved = delta.filter(|d| *d != 0.0).map(|d| match better {
+        Better::Higher => d > 0.0,
+        Better::Lower => d < 0.0,
+    });
+
+    let is_loss = unit == "%";
+    let significance = delta.map(|d| {
+        let noise = if is_loss {
+            d.abs() < LOSS_NOISE_THRESHOLD_PP
+        } else {
+            delta_pct.is_none_or(|p| p.abs() < NOISE_THRESHOLD_PCT)
+        };
+        match (a.spread, b.spread) {
+            _ if noise => Significance::Noise,
+            (Some((a25, a75)), Some((b25, b75))) if b25 > a75 || b75 < a25 => {
+                Significance::Significant
+            }
+            _ => Significance::Possible,
+        }
+    });
+
+    FieldDiff {
+        field: field.to_string(),
+        unit,
+        a: a.value,
+        b: b.value,
+        delta,
+        delta_pct,
+        improved,
+        significance,
+    }
+}
+
+fn loss_pct(l: &LatencySummary) -> Metric {
+    Metric::plain((l.sent > 0).then_some(l.loss * 100.0))
+}
+
+/// Compare two results; `b` is measured against `a`.
+pub fn diff_results(a: &RunResult, b: &RunResult) -> ResultDiff {
+    use Better::{Higher, Lower};
+
+    let mut fields = vec![
+        field_diff(
+            "download",
+            "Mbps",
+            Higher,
+            Metric::throughput(&a.download),
+            Metric::throughput(&b.download),
+        ),
+        field_diff(
+            "upload",
+            "Mbps",
+            Higher,
+            Metric::throughput(&a.upload),
+            Metric::throughput(&b.upload),
+        ),
+    ];
+
+    let latencies: [(&str, &LatencySummary, &LatencySummary); 3] = [
+        ("idle_latency", &a.idle_latency, &b.idle_latency),
+        (
+            "loaded_latency_download",
+            &a.loaded_latency_download,
+            &b.loaded_latency_download,
+        ),
+        (
+            "loaded_latency_upload",
+            &a.loaded_latency_upload,
+            &b.loaded_latency_upload,
+        ),
+    ];
+    for (name, la, lb) in latencies {
+        fields.push(field_diff(
+            name,
+            "ms",
+            Lower,
+            Metric::latency(la),
+            Metric::latency(lb),
+        ));
+        fields.push(field_diff(
+            &format!("{name}_jitter"),
+            "ms",
+            Lower,
+            Metric::plain(la.jitter_ms),
+            Metric::plain(lb.jitter_ms),
+        ));
+        fields.push(field_diff(
+            &format!("{name}_loss"),
+            "%",
+            Lower,
+            loss_pct(la),
+            loss_pct(lb),
+        ));
+    }
+
+    fields.push(field_diff(
+        "udp_loss",
+        "%",
+        Lower,
+        Metric::plain(a.experimental_udp.as_ref().map(|u| u.latency.loss * 100.0)),
+        Metric::plain(b.experimental_udp.as_ref().map(|u| u.latency.loss * 100.0)),
+    ));
+    fields.push(field_diff(
+        "dns_resolution",
+        "ms",
+        Lower,
+        Metric::plain(a.dns.as_ref().map(|d| d.resolution_time_ms)),
+        Metric::plain(b.dns.as_ref().map(|d| d.resolution_time_ms)),
+    ));
+    fields.push(field_diff(
+        "tls_handshake",
+        "ms",
+        Lower,
+        Metric::plain(a.tls.as_ref().map(|t| t.handshake_time_ms)),
+        Metric::plain(b.tls.as_ref().map(|t| t.handshake_time_ms)),
+    ));
+
+    // Drop metrics neither run measured
+    fields.retain(|f| f.a.is_some() || f.b.is_some());
+
+    ResultDiff {
+        a: RunRef {
+            timestamp_utc: a.timestamp_utc.clone(),
+            meas_id: a.meas_id.clone(),
+        },
+        b: RunRef {
+            timestamp_utc: b.timestamp_utc.clone(),
+            meas_id: b.meas_id.clone(),
+        },
+        fields,
+    }
+}
+
+fn fmt_opt(v: Option<f64>, precision: usize) -> String {
+    v.map(|v| format!("{:.*}", precision, v))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Render a diff as an aligned text table.
+pub fn format_table(diff: &ResultDiff) -> String {
+    let mut out = format!(
+        "a: {} ({})\nb: {} ({})\n\n",
+        diff.a.timestamp_utc, diff.a.meas_id, diff.b.timestamp_utc, diff.b.meas_id
+    );
+    out.push_str(&format!(
+        "{:<34} {:>10} {:>10} {:>10} {:>9}  {}\n",
+        "field", "a", "b", "delta", "delta %", "hint"
+    ));
+    for f in &diff.fields {
+        let hint = match (f.significance, f.improved) {
+            (Some(Significance::Noise), _) => "noise",
+            (Some(Significance::Possible), Some(true)) => "better?",
+            (Some(Significance::Possible), Some(false)) => "worse?",
+            (Some(Significance::Significant), Some(true)) => "better",
+            (Some(Significance::Significant), Some(false)) => "worse",
+            _ => "",
+        };
+        out.push_str(&format!(
+            "{:<34} {:>10} {:>10} {:>10} {:>9}  {}\n",
+            format!("{} ({})", f.field, f.unit),
+            fmt_opt(f.a, 1),
+            fmt_opt(f.b, 1),
+            f.delta
+                .map(|d| format!("{:+.1}", d))
+                .unwrap_or_else(|| "-".to_string()),
+            f.delta_pct
+                .map(|d| format!("{:+.1}%", d))
+                .unwrap_or_else(|| "-".to_string()),
+            hint
+        ));
+    }
+    out
+}
+
+/// Entry point for the `diff` subcommand.
+pub fn run(a: &Path, b: &Path, format: DiffFormat) -> Result<()> {
+    let ra = crate::storage::load_run(a)?;
+    let rb = crate::storage::load_run(b)?;
+    let diff = diff_results(&ra, &rb);
+    match format {
+        DiffFormat::Json => println!("{}", serde_json::to_string_pretty(&diff)?),
+        DiffFormat::Table => print!("{}", format_table(&diff)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metric(value: f64, p25: f64, p75: f64) -> Metric {
+        Metric {
+            value: Some(value),
+            spread: Some((p25, p75)),
+        }
+    }
+
+    #[test]
+    fn test_field_diff_significance() {
+        let d = field_diff(
+            "download",
+            "Mbps",
+            Better::Higher,
+            metric(100.0, 95.0, 105.0),
+            metric(150.0, 140.0, 160.0),
+        );
+        assert!((d.delta_pct.unwrap() - 50.0).abs() < 1e-9);
+        assert_eq!(d.improved, Some(true));
+        assert_eq!(d.significance, Some(Significance::Significant));
+
+        let d = field_diff(
+            "idle_latency",
+            "ms",
+            Better::Lower,
+            metric(20.0, 10.0, 30.0),
+            metric(25.0, 15.0, 35.0),
+        );
+        assert_eq!(d.improved, Some(false));
+        assert_eq!(d.significance, Some(Significance::Possible));
+
+        let d = field_diff(
+            "upload",
+            "Mbps",
+            Better::Higher,
+            Metric::plain(Some(50.0)),
+            Metric::plain(Some(51.0)),
+        );
+        assert_eq!(d.significance, Some(Significance::Noise));
+    }
+}
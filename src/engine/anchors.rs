@@ -0,0 +1,187 @@
+//! Reference anchor latency module
+//!
+//! Probes a set of reference anchors (public resolvers, the default gateway, a VPN hub)
+//! alongside the idle latency phase. Comparing them localizes where delay originates:
+//! a slow gateway points at the LAN/Wi-Fi, a slow resolver but fast gateway at the ISP.
+
+use crate::engine::scheduler::ProbeScheduler;
+use crate::engine::wait_if_paused_or_cancelled;
+use crate::model::{AnchorLatency, LatencySummary};
+use crate::stats::{latency_summary_from_samples, OnlineStats};
+use anyhow::{Context, Result};
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{atomic::AtomicBool, Arc};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+/// Port used when an anchor is given without one
+const DEFAULT_ANCHOR_PORT: u16 = 443;
+
+/// Special anchor name resolved to the default gateway
+const GATEWAY_ANCHOR: &str = "gateway";
+
+/// Probe all anchors concurrently for `total_duration`.
+pub async fn run_anchor_probes(
+    anchors: &[String],
+    total_duration: Duration,
+    interval_ms: u64,
+    timeout_ms: u64,
+    scheduler: &ProbeScheduler,
+    paused: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+) -> Vec<AnchorLatency> {
+    let probes = anchors.iter().map(|target| {
+        let paused = paused.clone();
+        let cancel = cancel.clone();
+        async move {
+            match resolve_anchor(target).await {
+                Ok(addr) => AnchorLatency {
+                    target: target.clone(),
+                    address: Some(addr.to_string()),
+                    latency: probe_anchor(
+                        addr,
+                        total_duration,
+                        interval_ms,
+                        timeout_ms,
+                        scheduler,
+                        &paused,
+                        &cancel,
+                    )
+                    .await,
+                    error: None,
+                },
+                Err(e) => AnchorLatency {
+                    target: target.clone(),
+                    address: None,
+                    latency: LatencySummary::failed(),
+                    error: Some(format!("{e:#}")),
+                },
+            }
+        }
+    });
+    futures::future::join_all(probes).await
+}
+
+async fn probe_anchor(
+    addr: SocketAddr,
+    total_duration: Duration,
+    interval_ms: u64,
+    timeout_ms: u64,
+    scheduler: &ProbeScheduler,
+    paused: &AtomicBool,
+    cancel: &AtomicBool,
+) -> LatencySummary {
+    let start = Instant::now();
+    let mut sent = 0u64;
+    let mut received = 0u64;
+    let mut samples = Vec::<f64>::new();
+    let mut online = OnlineStats::default();
+
+    while start.elapsed() < total_duration {
+        if wait_if_paused_or_cancelled(paused, cancel).await {
+            break;
+        }
+
+        scheduler.acquire().await;
+        sent += 1;
+        if let Some(ms) = tcp_connect_rtt_ms(addr, timeout_ms).await {
+            received += 1;
+            samples.push(ms);
+            online.push(ms);
+        }
+
+        tokio::time::sleep(Duration::from_millis(interval_ms)).await;
+    }
+
+    latency_summary_from_samples(sent, received, &samples, online.stddev())
+}
+
+/// Time a TCP handshake to `addr`. A refused connection still completes a round trip
+/// (SYN -> RST), so it counts as a sample; only timeouts and other errors are losses.
+async fn tcp_connect_rtt_ms(addr: SocketAddr, timeout_ms: u64) -> Option<f64> {
+    let start = Instant::now();
+    let res = tokio::time::timeout(Duration::from_millis(timeout_ms), TcpStream::connect(addr))
+        .await
+        .ok()?;
+    let ms = start.elapsed().as_secs_f64() * 1000.0;
+    match res {
+        Ok(_) => Some(ms),
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => Some(ms),
+        Err(_) => None,
+    }
+}
+
+/// Resolve an anchor spec ("gateway", "1.1.1.1", "[2606:4700::1111]:53", "vpn.example.com:443").
+async fn resolve_anchor(target: &str) -> Result<SocketAddr> {
+    if target.eq_ignore_ascii_case(GATEWAY_ANCHOR) {
+        let gw = crate::network::get_default_gateway().context("no default gateway found")?;
+        let ip: IpAddr = gw
+            .parse()
+            .with_context(|| format!("invalid gateway address '{}'", gw))?;
+        return Ok(SocketAddr::new(ip, DEFAULT_ANCHOR_PORT));
+    }
+
+    let (host, port) = parse_anchor(target)?;
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+    let addr = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .with_context(|| format!("DNS lookup failed for {}", host))?
+        .next()
+        .with_context(|| format!("no addresses found for {}", host))?;
+    Ok(addr)
+}
+
+/// Split an anchor spec into host and port.
+fn parse_anchor(target: &str) -> Result<(String, u16)> {
+    anyhow::ensure!(!target.is_empty(), "empty anchor");
+
+    // Bare IPv6 address
+    if let Ok(IpAddr::V6(v6)) = target.parse::<IpAddr>() {
+        return Ok((v6.to_string(), DEFAULT_ANCHOR_PORT));
+    }
+    // Bracketed IPv6 with optional port
+    if let Some(rest) = target.strip_prefix('[') {
+        let (host, after) = rest.split_once(']').context("unterminated '[' in anchor")?;
+        let port = match after.strip_prefix(':') {
+            Some(p) => p.parse().context("invalid port in anchor")?,
+            None => DEFAULT_ANCHOR_PORT,
+        };
+        return Ok((host.to_string(), port));
+    }
+    match target.rsplit_once(':') {
+        Some((host, p)) => Ok((
+            host.to_string(),
+            p.parse().context("invalid port in anchor")?,
+        )),
+        None => Ok((target.to_string(), DEFAULT_ANCHOR_PORT)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_anchor() {
+        assert_eq!(
+            parse_anchor("1.1.1.1").unwrap(),
+            ("1.1.1.1".to_string(), 443)
+        );
+        assert_eq!(
+            parse_anchor("vpn.example.com:8443").unwrap(),
+            ("vpn.example.com".to_string(), 8443)
+        );
+        assert_eq!(
+            parse_anchor("[2606:4700::1111]:53").unwrap(),
+            ("2606:4700::1111".to_string(), 53)
+        );
+        assert_eq!(
+            parse_anchor("2606:4700::1111").unwrap(),
+            ("2606:4700::1111".to_string(), 443)
+        );
+        assert!(parse_anchor("host:notaport").is_err());
+    }
+}
@@ -0,0 +1,374 @@
+//! Measurement backends.
+//!
+//! The throughput, latency and diagnostic code talks to the server through
+//! `CloudflareClient`, which asks its `SpeedTestBackend` where to download from, where to
+//! upload to, how to probe latency and how to read the server's metadata. Supporting
+//! another kind of server means implementing the trait and returning it from
+//! `for_config`; the measurement machinery stays the same.
+
+use anyhow::{Context, Result};
+use reqwest::Url;
+use std::sync::Arc;
+
+use crate::model::{Backend, RunConfig};
+
+/// How to reach one kind of speed test server.
+pub trait SpeedTestBackend: Send + Sync {
+    /// Short name, as shown in logs
+    fn name(&self) -> &'static str;
+
+    /// URL of a download of `bytes` bytes
+    fn download_url(&self, bytes: u64) -> Url;
+
+    /// URL uploads are POSTed to
+    fn upload_url(&self) -> Url;
+
+    /// URL of one latency probe, sent with GET and read to the end; `during` names the
+    /// phase loading the connection, if any
+    fn latency_probe_url(&self, during: Option<&str>) -> Url;
+
+    /// Server metadata (`clientIp`, `colo`, `city`, `country`, `asn`) carried in the
+    /// headers of a download response; an empty object when there is none
+    fn meta_from_response(&self, resp: &reqwest::Response) -> serde_json::Value;
+
+    /// JSON metadata endpoint, when the server has one
+    fn meta_url(&self) -> Option<Url> {
+        None
+    }
+
+    /// The response of `meta_url` in the keys `meta_from_response` uses
+    fn meta_from_json(&self, body: serde_json::Value) -> serde_json::Value {
+        body
+    }
+
+    /// `key=value` trace endpoint in the format of `/cdn-cgi/trace`, when the server has one
+    fn trace_url(&self) -> Option<Url> {
+        None
+    }
+
+    /// STUN/TURN credentials endpoint, when the server has one
+    fn turn_url(&self) -> Option<Url> {
+        None
+    }
+
+    /// List of the server's locations, when it has one
+    fn locations_url(&self) -> Option<Url> {
+        None
+    }
+}
+
+/// The backend `cfg` asks for.
+pub fn for_config(cfg: &RunConfig) -> Result<Arc<dyn SpeedTestBackend>> {
+    Ok(match cfg.backend {
+        Backend::Cloudflare => Arc::new(Cloudflare::new(cfg)?),
+        Backend::Librespeed => Arc::new(LibreSpeed::new(cfg)?),
+        Backend::Lan => anyhow::bail!("LAN tests do not go over HTTP"),
+    })
+}
+
+/// speed.cloudflare.com and Workers serving the same endpoints, possibly under other paths
+/// (`--down-path`, `--up-path`).
+pub struct Cloudflare {
+    base_url: Url,
+    meas_id: String,
+    /// Data center every request asks for (`--colo`)
+    colo: Option<String>,
+    /// `--down-path`, `{bytes}` not yet filled in
+    down_path: String,
+    up_path: String,
+}
+
+impl Cloudflare {
+    pub fn new(cfg: &RunConfig) -> Result<Self> {
+        Ok(Self {
+            base_url: Url::parse(&cfg.base_url).context("invalid base_url")?,
+            meas_id: cfg.meas_id.clone(),
+            colo: cfg.colo.clone(),
+            down_path: cfg.down_path.clone(),
+            up_path: cfg.up_path.clone(),
+        })
+    }
+
+    /// `path` on the test server, asking for the `--colo` data center with the `cf.colo`
+    /// override when one is set.
+    fn endpoint(&self, path: &str) -> Url {
+        let mut url = self.base_url.join(path).expect("join endpoint path");
+        if let Some(colo) = &self.colo {
+            url.query_pairs_mut().append_pair("cf.colo", colo);
+        }
+        url
+    }
+
+    /// Download of `bytes` from `down_path`: in the path when it has a `{bytes}`, else in
+    /// the `bytes` query parameter.
+    fn down(&self, bytes: u64) -> Url {
+        if self.down_path.contains("{bytes}") {
+            return self.endpoint(&self.down_path.replace("{bytes}", &bytes.to_string()));
+        }
+        let mut url = self.endpoint(&self.down_path);
+        url.query_pairs_mut()
+            .append_pair("bytes", &bytes.to_string());
+        url
+    }
+}
+
+impl SpeedTestBackend for Cloudflare {
+    fn name(&self) -> &'static str {
+        "cloudflare"
+    }
+
+    fn download_url(&self, bytes: u64) -> Url {
+        let mut url = self.down(bytes);
+        url.query_pairs_mut().append_pair("measId", &self.meas_id);
+        url
+    }
+
+    fn upload_url(&self) -> Url {
+        let mut url = self.endpoint(&self.up_path);
+        url.query_pairs_mut().append_pair("measId", &self.meas_id);
+        url
+    }
+
+    fn latency_probe_url(&self, during: Option<&str>) -> Url {
+        let mut url = self.down(0);
+        match during {
+            Some(d) => url.query_pairs_mut().append_pair("during", d),
+            None => url.query_pairs_mut().append_pair("measId", &self.meas_id),
+        };
+        url
+    }
+
+    fn meta_from_response(&self, resp: &reqwest::Response) -> serde_json::Value {
+        let headers = resp.headers();
+        let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok());
+        let mut meta = serde_json::Map::new();
+
+        // cf-meta-* headers (preferred, contains all info)
+        for (name, key) in [
+            ("cf-meta-ip", "clientIp"),
+            ("cf-meta-colo", "colo"),
+            ("cf-meta-city", "city"),
+            ("cf-meta-country", "country"),
+        ] {
+            if let Some(value) = header(name) {
+                meta.insert(key.to_string(), value.into());
+            }
+        }
+        if let Some(asn) = header("cf-meta-asn") {
+            // Try parsing as number first, fall back to string
+            let asn = match asn.parse::<i64>() {
+                Ok(n) => n.into(),
+                Err(_) => asn.into(),
+            };
+            meta.insert("asn".to_string(), asn);
+        }
+
+        // Fallback to CF-Connecting-IP and CF-RAY if cf-meta-* headers not available
+        if !meta.contains_key("clientIp") {
+            if let Some(ip) = header("cf-connecting-ip") {
+                meta.insert("clientIp".to_string(), ip.into());
+            }
+        }
+        if !meta.contains_key("colo") {
+            if let Some(colo) = header("cf-ray").and_then(|ray| ray.split('-').nth(1)) {
+                meta.insert("colo".to_string(), colo.into());
+            }
+        }
+
+        serde_json::Value::Object(meta)
+    }
+
+    fn meta_url(&self) -> Option<Url> {
+        let mut url = self.endpoint("/meta");
+        url.query_pairs_mut().append_pair("measId", &self.meas_id);
+        Some(url)
+    }
+
+    fn trace_url(&self) -> Option<Url> {
+        Some(self.endpoint("/cdn-cgi/trace"))
+    }
+
+    fn turn_url(&self) -> Option<Url> {
+        self.base_url.join("/__turn").ok()
+    }
+
+    fn locations_url(&self) -> Option<Url> {
+        self.base_url.join("/locations").ok()
+    }
+}
+
+/// Size of one `garbage.php` chunk; LibreSpeed downloads are whole chunks
+const LIBRESPEED_CHUNK: u64 = 1 << 20;
+
+/// Most chunks `garbage.php` sends in one response
+const LIBRESPEED_MAX_CHUNKS: u64 = 1024;
+
+/// LibreSpeed servers: `garbage.php` downloads, `empty.php` uploads and pings, and
+/// `getIP.php` for the client's address and ISP, all in the directory of the base URL.
+pub struct LibreSpeed {
+    /// The directory of the PHP endpoints, with a trailing slash
+    base_url: Url,
+}
+
+impl LibreSpeed {
+    pub fn new(cfg: &RunConfig) -> Result<Self> {
+        anyhow::ensure!(
+            cfg.colo.is_none(),
+            "--colo needs the cloudflare backend; LibreSpeed servers have no colos"
+        );
+        let base_url = Url::parse(&cfg.base_url).context("invalid base_url")?;
+        Ok(Self::in_directory(base_url))
+    }
+
+    /// The server whose endpoints are in the directory `base_url` names, with or without
+    /// a trailing slash
+    fn in_directory(mut base_url: Url) -> Self {
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        Self { base_url }
+    }
+
+    fn endpoint(&self, name: &str) -> Url {
+        self.base_url.join(name).expect("join endpoint name")
+    }
+}
+
+impl SpeedTestBackend for LibreSpeed {
+    fn name(&self) -> &'static str {
+        "librespeed"
+    }
+
+    fn download_url(&self, bytes: u64) -> Url {
+        if bytes == 0 {
+            return self.endpoint("empty.php");
+        }
+        let chunks = bytes
+            .div_ceil(LIBRESPEED_CHUNK)
+            .clamp(1, LIBRESPEED_MAX_CHUNKS);
+        let mut url = self.endpoint("garbage.php");
+        url.query_pairs_mut()
+            .append_pair("ckSize", &chunks.to_string());
+        url
+    }
+
+    fn upload_url(&self) -> Url {
+        self.endpoint("empty.php")
+    }
+
+    fn latency_probe_url(&self, _during: Option<&str>) -> Url {
+        self.endpoint("empty.php")
+    }
+
+    fn meta_from_response(&self, _resp: &reqwest::Response) -> serde_json::Value {
+        // LibreSpeed puts nothing about the client or server in its headers
+        serde_json::Value::Object(Default::default())
+    }
+
+    fn meta_url(&self) -> Option<Url> {
+        let mut url = self.endpoint("getIP.php");
+        url.query_pairs_mut().append_pair("isp", "true");
+        Some(url)
+    }
+
+    /// `{"processedString": "IP - ISP, country", "rawIspInfo": {ipinfo.io's answer}}`;
+    /// `rawIspInfo` is empty when the server looks up no ISP information.
+    fn meta_from_json(&self, body: serde_json::Value) -> serde_json::Value {
+        let info = |key: &str| {
+            body.get("rawIspInfo")
+                .and_then(|i| i.get(key))
+                .and_then(|v| v.as_str())
+                .filter(|s| !s.is_empty())
+        };
+        let processed = body.get("processedString").and_then(|v| v.as_str());
+        let mut meta = serde_json::Map::new();
+
+        let ip = info("ip").or_else(|| processed?.split(" - ").next());
+        if let Some(ip) = ip.map(str::trim).filter(|s| !s.is_empty()) {
+            meta.insert("clientIp".to_string(), ip.into());
+        }
+        for key in ["city", "country"] {
+            if let Some(value) = info(key) {
+                meta.insert(key.to_string(), value.into());
+            }
+        }
+        // ipinfo.io's "org" is "AS13335 Cloudflare, Inc."
+        if let Some((asn, org)) = info("org").and_then(|o| o.split_once(' ')) {
+            if let Some(asn) = asn.strip_prefix("AS").and_then(|n| n.parse::<i64>().ok()) {
+                meta.insert("asn".to_string(), asn.into());
+                meta.insert("asOrganization".to_string(), org.into());
+            }
+        }
+
+        serde_json::Value::Object(meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend(down_path: &str, colo: Option<&str>) -> Cloudflare {
+        Cloudflare {
+            base_url: Url::parse("https://speed.example/").unwrap(),
+            meas_id: "42".to_string(),
+            colo: colo.map(str::to_string),
+            down_path: down_path.to_string(),
+            up_path: "/__up".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_cloudflare_urls() {
+        let b = backend("/__down", Some("AMS"));
+        assert_eq!(
+            b.download_url(100).as_str(),
+            "https://speed.example/__down?cf.colo=AMS&bytes=100&measId=42"
+        );
+        assert_eq!(
+            b.latency_probe_url(Some("download")).as_str(),
+            "https://speed.example/__down?cf.colo=AMS&bytes=0&during=download"
+        );
+        assert_eq!(
+            b.upload_url().as_str(),
+            "https://speed.example/__up?cf.colo=AMS&measId=42"
+        );
+
+        let b = backend("/down/{bytes}", None);
+        assert_eq!(
+            b.download_url(100).as_str(),
+            "https://speed.example/down/100?measId=42"
+        );
+    }
+
+    #[test]
+    fn test_librespeed_urls_and_meta() {
+        let b = LibreSpeed::in_directory(Url::parse("https://speed.example/backend").unwrap());
+        // Whole megabyte chunks, rounded up
+        assert_eq!(
+            b.download_url(3 * LIBRESPEED_CHUNK + 1).as_str(),
+            "https://speed.example/backend/garbage.php?ckSize=4"
+        );
+        assert_eq!(
+            b.download_url(0).as_str(),
+            "https://speed.example/backend/empty.php"
+        );
+        assert_eq!(b.latency_probe_url(Some("download")), b.upload_url());
+
+        let meta = b.meta_from_json(serde_json::json!({
+            "processedString": "192.0.2.7 - Example ISP, NL",
+            "rawIspInfo": { "ip": "192.0.2.7", "city": "Amsterdam", "country": "NL", "org": "AS64496 Example ISP" },
+        }));
+        assert_eq!(meta["clientIp"], "192.0.2.7");
+        assert_eq!(meta["asn"], 64496);
+        assert_eq!(meta["asOrganization"], "Example ISP");
+        assert_eq!(meta["country"], "NL");
+
+        // Without an ISP lookup only the address is known
+        let meta = b.meta_from_json(serde_json::json!({
+            "processedString": "192.0.2.7",
+            "rawIspInfo": "",
+        }));
+        assert_eq!(meta, serde_json::json!({ "clientIp": "192.0.2.7" }));
+    }
+}
@@ -0,0 +1,114 @@
+//! Incremental assembly of a run result
+//!
+//! The engine records each phase's outcome as soon as the phase ends and emits a
+//! `TestEvent::PhaseResult` snapshot, so consumers can publish download numbers while
+//! the upload is still running instead of waiting for the final `RunResult`.
+
+use crate::model::{LatencySummary, Phase, RunConfig, RunResult, TestEvent, SCHEMA_VERSION};
+
+pub struct RunResultBuilder {
+    result: RunResult,
+}
+
+impl RunResultBuilder {
+    /// Start a result carrying the run's identity; measurements are filled in per phase.
+    pub fn new(cfg: &RunConfig) -> Self {
+        Self {
+            result: RunResult {
+                schema_version: SCHEMA_VERSION,
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                timestamp_utc: now_rfc3339(),
+                base_url: cfg.base_url.clone(),
+                backend: cfg.backend,
+                meas_id: cfg.meas_id.clone(),
+                comments: cfg.comments.clone(),
+                partial: false,
+                sync_start: cfg
+                    .sync_start
+                    .map(|t| humantime::format_rfc3339_millis(t).to_string()),
+                context: cfg.context.clone(),
+                tags: cfg.tags.clone(),
+                meta: None,
+                server: None,
+                proxy: cfg.proxy.as_deref().map(crate::network::redact_proxy_url),
+                pinned_ip: crate::engine::cloudflare::pinned_endpoint(cfg)
+                    .map(|(_, addr)| addr.ip().to_string()),
+                unreachable_endpoints: Vec::new(),
+                requested_colo: cfg.colo.clone(),
+                dscp: cfg.dscp.map(crate::engine::sockets::describe_dscp),
+                captive_portal: false,
+                idle_latency: LatencySummary::skipped(),
+                ttfb: None,
+                download: Default::default(),
+                upload: Default::default(),
+                loaded_latency_download: LatencySummary::skipped(),
+                loaded_latency_upload: LatencySummary::skipped(),
+                loaded_latency_download_segments: None,
+                loaded_latency_upload_segments: None,
+                turn: None,
+                experimental_udp: None,
+                udp_error: None,
+                udp_skipped: None,
+                udp_throughput: None,
+                // Network information - will be populated by TUI when available
+                ip: None,
+                colo: None,
+                asn: None,
+                as_org: None,
+                interface_name: None,
+                network_name: None,
+                is_wireless: None,
+                vpn_detected: false,
+                wireless_link: None,
+                interface_mac: None,
+                link_speed_mbps: None,
+                local_ipv4: None,
+                local_ipv6: None,
+                external_ipv4: None,
+                external_ipv6: None,
+                nat_type: None,
+                dns: None,
+                tls: None,
+                timing_breakdown: None,
+                ip_comparison: None,
+                traceroute: None,
+                mtu: None,
+                anchors: Vec::new(),
+                icmp_latency: None,
+                comparisons: Vec::new(),
+                modem: None,
+                soak: None,
+                wireless_events: Vec::new(),
+                protocols: Vec::new(),
+                measurements: Vec::new(),
+                concurrency_sweep: None,
+                protocol_comparison: Vec::new(),
+                bufferbloat: None,
+                aim: None,
+                energy: Vec::new(),
+            },
+        }
+    }
+
+    /// Apply the outcome of `phase` and return the snapshot event for it.
+    pub fn record(&mut self, phase: Phase, update: impl FnOnce(&mut RunResult)) -> TestEvent {
+        update(&mut self.result);
+        TestEvent::PhaseResult {
+            phase,
+            snapshot: Box::new(self.result.clone()),
+        }
+    }
+
+    /// The complete result, timestamped at the end of the run.
+    pub fn finish(mut self) -> RunResult {
+        self.result.timestamp_utc = now_rfc3339();
+        self.result
+    }
+}
+
+/// The current UTC time as RFC 3339, the format of `RunResult::timestamp_utc`
+pub fn now_rfc3339() -> String {
+    time::OffsetDateTime::now_utc()
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_else(|_| "now".into())
+}
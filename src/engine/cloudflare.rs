@@ -1,170 +1,162 @@
 use anyhow::{Context, Result};
 use reqwest::Url;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
 use std::time::Duration;
 
-use crate::model::RunConfig;
+use crate::engine::backend::{self, SpeedTestBackend};
+use crate::engine::har::{self, HarRecorder};
+use crate::engine::sockets::{SocketLayer, SocketOptions};
+use crate::engine::tls::NegotiatedTls;
+use crate::model::{HttpVersion, RunConfig};
 
+/// HTTP client of the test traffic, sending it where its `SpeedTestBackend` says.
 #[derive(Clone)]
 pub struct CloudflareClient {
-    pub base_url: Url,
-    pub meas_id: String,
+    /// Where the downloads, uploads and probes go, and how the server's metadata is read
+    pub backend: Arc<dyn SpeedTestBackend>,
     pub http: reqwest::Client,
+    /// Client of the latency probes, on connections of their own
+    probe_http: reqwest::Client,
+    /// System default congestion control, for when `--congestion` is not given
+    default_congestion: Option<String>,
+    /// Version set on every request; needed for HTTP/3, which reqwest never negotiates
+    version: Option<reqwest::Version>,
+    /// HTTP versions of the measurement responses since the last `take`
+    pub protocols: Arc<ProtocolTally>,
+    /// TLS version and cipher suite the connections of `http` negotiated
+    pub tls: Arc<NegotiatedTls>,
+    /// Whether `http` goes through a proxy: `--proxy`, or the system proxy the CLI put
+    /// in its place
+    pub via_proxy: bool,
+    /// Recorder for `--export-har`, shared by every client of the run
+    pub har: Option<Arc<HarRecorder>>,
+    /// Sets socket options such as `--congestion` on the connections of `http` and
+    /// records them
+    sockets: SocketLayer,
+}
+
+/// Counts responses per negotiated HTTP version.
+#[derive(Default)]
+pub struct ProtocolTally(Mutex<BTreeMap<String, u64>>);
+
+impl ProtocolTally {
+    pub fn record(&self, version: reqwest::Version) {
+        *self
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(format!("{:?}", version))
+            .or_default() += 1;
+    }
+
+    /// Return the counts so far and start over (one tally per phase).
+    pub fn take(&self) -> BTreeMap<String, u64> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
 }
 
 impl CloudflareClient {
     pub fn new(cfg: &RunConfig) -> Result<Self> {
-        let base_url = Url::parse(&cfg.base_url).context("invalid base_url")?;
+        let backend = backend::for_config(cfg)?;
+
+        let sockets = SocketLayer::new(SocketOptions::for_traffic(cfg))?;
+        let tls = Arc::new(NegotiatedTls::new());
+        let (http, version) = build_http(cfg, &sockets, Some(tls.clone()))?;
+        // Kept apart so the TCP statistics of the traffic leave the probes out
+        let probe_http = build_http(
+            cfg,
+            &SocketLayer::new(SocketOptions::for_probes(cfg))?,
+            None,
+        )?
+        .0;
+
+        let via_proxy = proxy(cfg)?.is_some();
+        // Only TCP connections made directly use the system default
+        let default_congestion = if !via_proxy && version.is_none() {
+            crate::engine::sockets::default_congestion()
+        } else {
+            None
+        };
 
-        let mut default_headers = reqwest::header::HeaderMap::new();
-        default_headers.insert(
-            reqwest::header::REFERER,
-            "https://speed.cloudflare.com/".parse().unwrap(),
-        );
+        Ok(Self {
+            backend,
+            http,
+            probe_http,
+            default_congestion,
+            version,
+            protocols: Arc::default(),
+            tls,
+            via_proxy,
+            har: None,
+            sockets,
+        })
+    }
 
-        let mut builder = reqwest::Client::builder()
-            .user_agent(cfg.user_agent.clone())
-            .default_headers(default_headers)
-            .timeout(Duration::from_secs(30))
-            .tcp_keepalive(Duration::from_secs(15));
-
-        // Configure binding to interface or source IP if specified
-        if let Some(ref iface) = cfg.interface {
-            use crate::engine::network_bind;
-            match network_bind::get_interface_ip(iface) {
-                Ok(ip) => {
-                    builder = builder.local_address(ip);
-                    eprintln!(
-                        "Binding HTTP connections to interface {} (IP: {})",
-                        iface, ip
-                    );
-                }
-                Err(e) => {
-                    return Err(anyhow::anyhow!(
-                        "Failed to get IP address for interface {}: {}",
-                        iface,
-                        e
-                    ));
-                }
-            }
-        } else if let Some(ref source_ip) = cfg.source_ip {
-            // Bind to specific source IP address
-            match source_ip.parse::<std::net::IpAddr>() {
-                Ok(ip) => {
-                    builder = builder.local_address(ip);
-                    eprintln!("Binding HTTP connections to source IP: {}", ip);
-                }
-                Err(e) => {
-                    return Err(anyhow::anyhow!(
-                        "Invalid source IP address format '{}': {}",
-                        source_ip,
-                        e
-                    ));
-                }
-            }
+    /// TCP congestion control of the test connections, as far as it is known
+    pub fn congestion(&self) -> Option<String> {
+        if self.sockets.options().is_empty() {
+            self.default_congestion.clone()
+        } else {
+            self.sockets.congestion()
         }
+    }
 
-        // Load custom certificate if provided
-        if let Some(ref cert_path) = cfg.certificate_path {
-            // Check file extension
-            let ext = cert_path
-                .extension()
-                .and_then(|e| e.to_str())
-                .map(|e| e.to_lowercase());
-
-            let valid_extensions = ["pem", "crt", "cer", "der"];
-            if let Some(ref ext) = ext {
-                if !valid_extensions.contains(&ext.as_str()) {
-                    return Err(anyhow::anyhow!(
-                        "Invalid certificate file extension '{}'. Expected one of: {}",
-                        ext,
-                        valid_extensions.join(", ")
-                    ));
-                }
-            } else {
-                return Err(anyhow::anyhow!(
-                    "Certificate file has no extension. Expected one of: {}",
-                    valid_extensions.join(", ")
-                ));
-            }
+    /// Local and peer address of every connection of the test traffic so far
+    pub(crate) fn connections(&self) -> Vec<(std::net::SocketAddr, std::net::SocketAddr)> {
+        self.sockets.connections()
+    }
 
-            let cert_data = std::fs::read(cert_path).with_context(|| {
-                format!("failed to read certificate from {}", cert_path.display())
-            })?;
-
-            // Parse based on file extension
-            let cert = match ext.as_deref() {
-                Some("der") => reqwest::Certificate::from_der(&cert_data).with_context(|| {
-                    format!(
-                        "failed to parse DER certificate from {}",
-                        cert_path.display()
-                    )
-                })?,
-                _ => reqwest::Certificate::from_pem(&cert_data).with_context(|| {
-                    format!(
-                        "failed to parse PEM certificate from {}",
-                        cert_path.display()
-                    )
-                })?,
-            };
-
-            builder = builder.add_root_certificate(cert);
-        }
+    pub fn get(&self, url: Url) -> reqwest::RequestBuilder {
+        self.with_version(self.http.get(url))
+    }
 
-        // Configure proxy if specified
-        if let Some(ref proxy_url) = cfg.proxy {
-            let proxy = reqwest::Proxy::all(proxy_url).with_context(|| {
-                format!(
-                    "invalid proxy URL '{}'. Expected format: [protocol://]host[:port]",
-                    proxy_url
-                )
-            })?;
-            builder = builder.proxy(proxy);
-        }
+    pub fn post(&self, url: Url) -> reqwest::RequestBuilder {
+        self.with_version(self.http.post(url))
+    }
+
+    fn probe_get(&self, url: Url) -> reqwest::RequestBuilder {
+        self.with_version(self.probe_http.get(url))
+    }
 
-        let http = builder.build().context("failed to build http client")?;
+    /// Send a request built by `get` or `post`, recording it for `--export-har`.
+    pub async fn send(&self, req: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+        har::send(self.har.as_ref(), req).await
+    }
 
-        Ok(Self {
-            base_url,
-            meas_id: cfg.meas_id.clone(),
-            http,
-        })
+    fn with_version(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.version {
+            Some(v) => req.version(v),
+            None => req,
+        }
     }
 
-    pub fn down_url(&self) -> Url {
-        self.base_url.join("/__down").expect("join __down")
+    pub fn down_url(&self, bytes: u64) -> Url {
+        self.backend.download_url(bytes)
     }
 
     pub fn up_url(&self) -> Url {
-        self.base_url.join("/__up").expect("join __up")
+        self.backend.upload_url()
     }
 
-
     pub async fn probe_latency_ms(
         &self,
         during: Option<&str>,
         timeout_ms: u64,
     ) -> Result<(f64, Option<serde_json::Value>)> {
-        let mut url = self.down_url();
-        {
-            let mut qp = url.query_pairs_mut();
-            qp.append_pair("bytes", "0");
-            if let Some(d) = during {
-                qp.append_pair("during", d);
-            } else {
-                qp.append_pair("measId", &self.meas_id);
-            }
-        }
-
+        let url = self.backend.latency_probe_url(during);
         let start = std::time::Instant::now();
         let resp = self
-            .http
-            .get(url)
-            .timeout(Duration::from_millis(timeout_ms))
-            .send()
+            .send(
+                self.probe_get(url)
+                    .timeout(Duration::from_millis(timeout_ms)),
+            )
             .await?;
+        self.protocols.record(resp.version());
 
         // Extract meta from headers before consuming body
-        let meta = self.extract_meta_from_response(&resp);
+        let meta = self.backend.meta_from_response(&resp);
         let has_meta = !meta.as_object().map(|m| m.is_empty()).unwrap_or(true);
 
         // Consume body to keep behavior consistent
@@ -173,132 +165,389 @@ impl CloudflareClient {
         Ok((elapsed, if has_meta { Some(meta) } else { None }))
     }
 
-    pub fn extract_meta_from_response(&self, resp: &reqwest::Response) -> serde_json::Value {
-        let mut meta = serde_json::Map::new();
+    /// Time until the response headers of a one-byte download arrive, leaving out the
+    /// body that `probe_latency_ms` also waits for.
+    pub async fn probe_ttfb_ms(&self, timeout_ms: u64) -> Result<f64> {
+        let url = self.down_url(1);
+        let start = std::time::Instant::now();
+        let resp = self
+            .send(
+                self.probe_get(url)
+                    .timeout(Duration::from_millis(timeout_ms)),
+            )
+            .await?
+            .error_for_status()?;
+        let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+        self.protocols.record(resp.version());
+        let _ = resp.bytes().await;
+        Ok(elapsed)
+    }
+}
 
-        // Extract from cf-meta-* headers (preferred, contains all info)
-        if let Some(ip) = resp
-            .headers()
-            .get("cf-meta-ip")
-            .and_then(|h| h.to_str().ok())
-        {
-            meta.insert(
-                "clientIp".to_string(),
-                serde_json::Value::String(ip.to_string()),
-            );
-        }
+/// The HTTP client for the test traffic, with `sockets` setting options on its connections.
+fn build_http(
+    cfg: &RunConfig,
+    sockets: &SocketLayer,
+    sessions: Option<Arc<NegotiatedTls>>,
+) -> Result<(reqwest::Client, Option<reqwest::Version>)> {
+    let mut default_headers = reqwest::header::HeaderMap::new();
+    default_headers.insert(
+        reqwest::header::REFERER,
+        "https://speed.cloudflare.com/".parse().unwrap(),
+    );
+    default_headers.insert(
+        reqwest::header::USER_AGENT,
+        cfg.user_agent.parse().context("invalid user agent")?,
+    );
+    if let Some(token) = &cfg.endpoint_auth_token {
+        let mut value = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token))
+            .context("invalid endpoint auth token")?;
+        value.set_sensitive(true);
+        default_headers.insert(reqwest::header::AUTHORIZATION, value);
+    }
+    // --header values replace these defaults; a repeated name is sent repeatedly
+    for (name, _) in &cfg.headers {
+        default_headers.remove(name.as_str());
+    }
+    for (name, value) in &cfg.headers {
+        default_headers.append(
+            reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("invalid header name '{}'", name))?,
+            value
+                .parse()
+                .with_context(|| format!("invalid value for header '{}'", name))?,
+        );
+    }
 
-        if let Some(colo) = resp
-            .headers()
-            .get("cf-meta-colo")
-            .and_then(|h| h.to_str().ok())
-        {
-            meta.insert(
-                "colo".to_string(),
-                serde_json::Value::String(colo.to_string()),
+    let mut builder = reqwest::Client::builder()
+        .default_headers(default_headers)
+        .timeout(Duration::from_secs(30))
+        .tcp_keepalive(Duration::from_secs(15));
+
+    // Our own TLS configuration, so the session cache can note what the connections
+    // negotiated; ALPN as reqwest would offer it
+    let mut tls = tls_config(cfg)?;
+    tls.alpn_protocols = match cfg.http_version {
+        HttpVersion::Auto => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        HttpVersion::Http1 => vec![b"http/1.1".to_vec()],
+        HttpVersion::Http2 => vec![b"h2".to_vec()],
+        HttpVersion::Http3 => vec![b"h3".to_vec()],
+    };
+    if let Some(sessions) = sessions {
+        tls.resumption = rustls::client::Resumption::store(sessions);
+    }
+    builder = builder.use_preconfigured_tls(tls);
+
+    builder = connect_directly(builder, cfg)?;
+    builder = builder.connector_layer(sockets.clone());
+
+    let mut version = None;
+    match cfg.http_version {
+        HttpVersion::Auto => {}
+        HttpVersion::Http1 => builder = builder.http1_only(),
+        HttpVersion::Http2 => builder = builder.http2_prior_knowledge(),
+        HttpVersion::Http3 => {
+            // QUIC goes around HTTP and SOCKS proxies alike, including a system proxy
+            anyhow::ensure!(
+                proxy(cfg)?.is_none(),
+                "HTTP/3 cannot be used through a proxy; use --no-proxy or another --protocol"
             );
+            if !sockets.options().is_empty() {
+                anyhow::bail!(
+                    "{} cannot be applied to HTTP/3 traffic; use another --protocol",
+                    sockets.options().flags()
+                );
+            }
+            builder = http3(builder)?;
+            version = Some(reqwest::Version::HTTP_3);
         }
+    }
 
-        if let Some(city) = resp
-            .headers()
-            .get("cf-meta-city")
-            .and_then(|h| h.to_str().ok())
-        {
-            meta.insert(
-                "city".to_string(),
-                serde_json::Value::String(city.to_string()),
-            );
-        }
+    let http = builder.build().context("failed to build http client")?;
+    Ok((http, version))
+}
 
-        if let Some(country) = resp
+/// Look for a captive portal or an intercepting proxy by fetching `url`, which answers
+/// with an empty 204. Returns what came back instead when the response was tampered
+/// with; an error means the check was inconclusive (e.g. the URL is unreachable).
+pub async fn detect_captive_portal(cfg: &RunConfig, url: &str) -> Result<Option<String>> {
+    let builder = reqwest::Client::builder()
+        .user_agent(&cfg.user_agent)
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(Duration::from_secs(5));
+    let http = connect_directly(builder, cfg)?
+        .build()
+        .context("failed to build http client")?;
+    let resp = http.get(url).send().await?;
+    let status = resp.status();
+    if status == reqwest::StatusCode::NO_CONTENT {
+        return Ok(None);
+    }
+    if status.is_redirection() {
+        let location = resp
             .headers()
-            .get("cf-meta-country")
-            .and_then(|h| h.to_str().ok())
-        {
-            meta.insert(
-                "country".to_string(),
-                serde_json::Value::String(country.to_string()),
-            );
-        }
+            .get(reqwest::header::LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .unwrap_or("-")
+            .to_string();
+        return Ok(Some(format!("redirected to {}", location)));
+    }
+    anyhow::ensure!(status.is_success(), "unexpected HTTP {}", status);
+    // Portals answer with their login page
+    let body = resp.bytes().await.map(|b| b.len()).unwrap_or(0);
+    Ok(Some(format!(
+        "HTTP {} with {} bytes instead of an empty 204",
+        status.as_u16(),
+        body
+    )))
+}
 
-        if let Some(asn) = resp
-            .headers()
-            .get("cf-meta-asn")
-            .and_then(|h| h.to_str().ok())
-        {
-            // Try parsing as number first, fall back to string
-            if let Ok(asn_num) = asn.parse::<i64>() {
-                meta.insert("asn".to_string(), serde_json::Value::Number(asn_num.into()));
-            } else {
-                meta.insert(
-                    "asn".to_string(),
-                    serde_json::Value::String(asn.to_string()),
+/// Binding, name resolution, address family and proxy of connections reqwest opens itself
+fn connect_directly(
+    mut builder: reqwest::ClientBuilder,
+    cfg: &RunConfig,
+) -> Result<reqwest::ClientBuilder> {
+    // Configure binding to interface or source IP if specified
+    if let Some(ref iface) = cfg.interface {
+        use crate::engine::network_bind;
+        match network_bind::get_interface_ip(iface) {
+            Ok(ip) => {
+                builder = builder.local_address(ip);
+                tracing::info!(
+                    "Binding HTTP connections to interface {} (IP: {})",
+                    iface,
+                    ip
                 );
             }
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "Failed to get IP address for interface {}: {}",
+                    iface,
+                    e
+                ));
+            }
         }
-
-        // Fallback to CF-Connecting-IP and CF-RAY if cf-meta-* headers not available
-        if !meta.contains_key("clientIp") {
-            if let Some(ip) = resp
-                .headers()
-                .get("cf-connecting-ip")
-                .or_else(|| resp.headers().get("CF-Connecting-IP"))
-                .and_then(|h| h.to_str().ok())
-            {
-                meta.insert(
-                    "clientIp".to_string(),
-                    serde_json::Value::String(ip.to_string()),
-                );
+    } else if let Some(ref source_ip) = cfg.source_ip {
+        // Bind to specific source IP address
+        match source_ip.parse::<std::net::IpAddr>() {
+            Ok(ip) => {
+                builder = builder.local_address(ip);
+                tracing::info!("Binding HTTP connections to source IP: {}", ip);
+            }
+            Err(e) => {
+                return Err(anyhow::anyhow!(
+                    "Invalid source IP address format '{}': {}",
+                    source_ip,
+                    e
+                ));
             }
         }
+    }
 
-        if !meta.contains_key("colo") {
-            if let Some(ray) = resp
-                .headers()
-                .get("cf-ray")
-                .or_else(|| resp.headers().get("CF-RAY"))
-                .and_then(|h| h.to_str().ok())
-            {
-                if let Some(colo) = ray.split('-').nth(1) {
-                    meta.insert(
-                        "colo".to_string(),
-                        serde_json::Value::String(colo.to_string()),
-                    );
-                }
-            }
+    // `-4`/`-6`: an unspecified local address of one family makes the connector skip
+    // the other family's addresses
+    if cfg.interface.is_none() && cfg.source_ip.is_none() {
+        if cfg.ipv4_only {
+            builder =
+                builder.local_address(std::net::IpAddr::from(std::net::Ipv4Addr::UNSPECIFIED));
+        } else if cfg.ipv6_only {
+            builder =
+                builder.local_address(std::net::IpAddr::from(std::net::Ipv6Addr::UNSPECIFIED));
         }
+    }
 
-        serde_json::Value::Object(meta)
+    if let Some(resolver) = crate::engine::resolver::DnsResolver::from_config(cfg)? {
+        builder = builder.dns_resolver(Arc::new(resolver));
+    }
+    if let Some((host, addr)) = pinned_endpoint(cfg) {
+        builder = builder.resolve(&host, addr);
     }
+
+    builder = match proxy(cfg)? {
+        Some(proxy) => builder.proxy(proxy),
+        None => builder.no_proxy(),
+    };
+
+    Ok(builder)
 }
 
-pub async fn fetch_meta_from_response(client: &CloudflareClient) -> Result<serde_json::Value> {
-    // Try to get meta info from a test request response headers
-    let mut url = client.down_url();
-    url.query_pairs_mut()
-        .append_pair("bytes", "0")
-        .append_pair("measId", &client.meas_id);
+/// The proxy connections go through: `--proxy`, an HTTP or SOCKS URL, or the system
+/// proxy the CLI resolved into it. Never picked up from the environment here, so every
+/// check of whether traffic is proxied agrees with what the client does.
+pub(crate) fn proxy(cfg: &RunConfig) -> Result<Option<reqwest::Proxy>> {
+    cfg.proxy
+        .as_deref()
+        .map(|proxy_url| {
+            reqwest::Proxy::all(proxy_url).with_context(|| {
+                format!(
+                    "invalid proxy URL '{}'. Expected format: [protocol://]host[:port]",
+                    proxy_url
+                )
+            })
+        })
+        .transpose()
+}
 
-    let resp = client.http.get(url).send().await?;
+/// Roots for TLS connections: the built-in ones plus `--certificate`. The handshake
+/// diagnostic, DoH and DoT trust them as the test client does.
+pub(crate) fn root_store(cfg: &RunConfig) -> Result<rustls::RootCertStore> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::CertificateDer;
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let Some((cert_path, data, der)) = read_custom_certificate(cfg)? else {
+        return Ok(roots);
+    };
+    let certs = if der {
+        vec![CertificateDer::from(data)]
+    } else {
+        CertificateDer::pem_slice_iter(&data)
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| {
+                format!(
+                    "failed to parse PEM certificate from {}",
+                    cert_path.display()
+                )
+            })?
+    };
+    anyhow::ensure!(
+        !certs.is_empty(),
+        "no certificate found in {}",
+        cert_path.display()
+    );
+    for cert in certs {
+        roots
+            .add(cert)
+            .with_context(|| format!("failed to parse certificate from {}", cert_path.display()))?;
+    }
+    Ok(roots)
+}
+
+/// The `--certificate` file, its contents and whether it is DER rather than PEM, going by
+/// its extension
+fn read_custom_certificate(cfg: &RunConfig) -> Result<Option<(&Path, Vec<u8>, bool)>> {
+    let Some(ref cert_path) = cfg.certificate_path else {
+        return Ok(None);
+    };
+    // Check file extension
+    let ext = cert_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+
+    let valid_extensions = ["pem", "crt", "cer", "der"];
+    if let Some(ref ext) = ext {
+        if !valid_extensions.contains(&ext.as_str()) {
+            return Err(anyhow::anyhow!(
+                "Invalid certificate file extension '{}'. Expected one of: {}",
+                ext,
+                valid_extensions.join(", ")
+            ));
+        }
+    } else {
+        return Err(anyhow::anyhow!(
+            "Certificate file has no extension. Expected one of: {}",
+            valid_extensions.join(", ")
+        ));
+    }
+
+    let cert_data = std::fs::read(cert_path)
+        .with_context(|| format!("failed to read certificate from {}", cert_path.display()))?;
+    Ok(Some((cert_path, cert_data, ext.as_deref() == Some("der"))))
+}
+
+/// The host of the base URL and the address `--resolve` pins it to, if an entry matches
+/// its host and port.
+pub(crate) fn pinned_endpoint(cfg: &RunConfig) -> Option<(String, std::net::SocketAddr)> {
+    let url = Url::parse(&cfg.base_url).ok()?;
+    let host = url.host_str()?.to_ascii_lowercase();
+    let port = url.port_or_known_default()?;
+    let pin = cfg
+        .resolve
+        .iter()
+        .find(|pin| pin.host == host && pin.port == port)?;
+    Some((host, std::net::SocketAddr::new(pin.ip, port)))
+}
+
+/// TLS configuration of the test client and the timing breakdown: `root_store` roots,
+/// and the `--client-cert` identity when one is given. ALPN is left to the caller.
+pub(crate) fn tls_config(cfg: &RunConfig) -> Result<rustls::ClientConfig> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    crate::engine::tls::ensure_crypto_provider();
+    let builder = rustls::ClientConfig::builder().with_root_certificates(root_store(cfg)?);
+    Ok(match client_identity_pem(cfg)? {
+        Some(pem) => {
+            let certs = CertificateDer::pem_slice_iter(&pem)
+                .collect::<Result<Vec<_>, _>>()
+                .context("failed to parse the client certificate")?;
+            let key =
+                PrivateKeyDer::from_pem_slice(&pem).context("failed to parse the client key")?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .context("invalid client certificate")?
+        }
+        None => builder.with_no_client_auth(),
+    })
+}
+
+/// The `--client-cert` and `--client-key` files as one PEM buffer
+fn client_identity_pem(cfg: &RunConfig) -> Result<Option<Vec<u8>>> {
+    let (Some(cert_path), Some(key_path)) = (&cfg.client_cert_path, &cfg.client_key_path) else {
+        return Ok(None);
+    };
+    let mut pem = std::fs::read(cert_path).with_context(|| {
+        format!(
+            "failed to read client certificate from {}",
+            cert_path.display()
+        )
+    })?;
+    pem.push(b'\n');
+    pem.extend(
+        std::fs::read(key_path)
+            .with_context(|| format!("failed to read client key from {}", key_path.display()))?,
+    );
+    Ok(Some(pem))
+}
 
-    Ok(client.extract_meta_from_response(&resp))
+#[cfg(feature = "http3")]
+fn http3(builder: reqwest::ClientBuilder) -> Result<reqwest::ClientBuilder> {
+    Ok(builder.http3_prior_knowledge())
+}
+
+#[cfg(not(feature = "http3"))]
+fn http3(_builder: reqwest::ClientBuilder) -> Result<reqwest::ClientBuilder> {
+    anyhow::bail!(
+        "this build has no HTTP/3 support; rebuild with RUSTFLAGS=\"--cfg reqwest_unstable\" cargo build --features http3"
+    )
+}
+
+pub async fn fetch_meta_from_response(client: &CloudflareClient) -> Result<serde_json::Value> {
+    // Try to get meta info from a test request response headers
+    let resp = client.send(client.get(client.down_url(0))).await?;
+    Ok(client.backend.meta_from_response(&resp))
 }
 
 pub async fn fetch_meta(client: &CloudflareClient) -> Result<serde_json::Value> {
-    let mut url = client.base_url.join("/meta").context("join /meta")?;
-    // Try with measId parameter
-    url.query_pairs_mut().append_pair("measId", &client.meas_id);
-    let v: serde_json::Value = client.http.get(url).send().await?.json().await?;
-    Ok(v)
+    let url = client
+        .backend
+        .meta_url()
+        .with_context(|| format!("the {} backend has no meta endpoint", client.backend.name()))?;
+    let v: serde_json::Value = client.send(client.get(url)).await?.json().await?;
+    Ok(client.backend.meta_from_json(v))
 }
 
 /// Parse the /cdn-cgi/trace endpoint which returns key=value pairs
 pub async fn fetch_trace(client: &CloudflareClient) -> Result<serde_json::Value> {
-    let url = client
-        .base_url
-        .join("/cdn-cgi/trace")
-        .context("join /cdn-cgi/trace")?;
-    let text = client.http.get(url).send().await?.text().await?;
+    let url = client.backend.trace_url().with_context(|| {
+        format!(
+            "the {} backend has no trace endpoint",
+            client.backend.name()
+        )
+    })?;
+    let text = client.send(client.get(url)).await?.text().await?;
 
     let mut meta = serde_json::Map::new();
     for line in text.lines() {
@@ -336,13 +585,40 @@ pub async fn fetch_trace(client: &CloudflareClient) -> Result<serde_json::Value>
     Ok(serde_json::Value::Object(meta))
 }
 
-pub async fn fetch_locations(client: &CloudflareClient) -> Result<serde_json::Value> {
-    let url = client
-        .base_url
-        .join("/locations")
-        .context("join /locations")?;
-    let v: serde_json::Value = client.http.get(url).send().await?.json().await?;
-    Ok(v)
+/// Whether the endpoint lacks the path `resp` answered for, as self-hosted Workers often
+/// lack `/__turn` and `/locations`
+fn not_provided(resp: &reqwest::Response) -> bool {
+    matches!(
+        resp.status(),
+        reqwest::StatusCode::NOT_FOUND
+            | reqwest::StatusCode::METHOD_NOT_ALLOWED
+            | reqwest::StatusCode::NOT_IMPLEMENTED
+    )
+}
+
+/// STUN/TURN servers and short-lived relay credentials the browser test uses; None when
+/// the endpoint has no `/__turn`.
+pub async fn fetch_turn(client: &CloudflareClient) -> Result<Option<crate::model::TurnInfo>> {
+    let Some(url) = client.backend.turn_url() else {
+        return Ok(None);
+    };
+    let resp = client.send(client.get(url)).await?;
+    if not_provided(&resp) {
+        return Ok(None);
+    }
+    Ok(Some(resp.error_for_status()?.json().await?))
+}
+
+/// The colos listed by `/locations`; None when the endpoint has no such list.
+pub async fn fetch_locations(client: &CloudflareClient) -> Result<Option<serde_json::Value>> {
+    let Some(url) = client.backend.locations_url() else {
+        return Ok(None);
+    };
+    let resp = client.send(client.get(url)).await?;
+    if not_provided(&resp) {
+        return Ok(None);
+    }
+    Ok(Some(resp.error_for_status()?.json().await?))
 }
 
 pub fn map_colo_to_server(locations: &serde_json::Value, colo: &str) -> Option<String> {
@@ -1,15 +1,43 @@
 //! DNS resolution time measurement module
 
-use crate::model::DnsSummary;
+use crate::engine::resolver::{is_server_failure, DnsResolver};
+use crate::model::{DnsSummary, EncryptedDnsSummary, RunConfig};
 use anyhow::{Context, Result};
 use std::net::IpAddr;
 use std::time::Instant;
 use tokio::net::lookup_host;
 
-/// Measure DNS resolution time for a given hostname.
+/// Zone signed with valid DNSSEC records, which a validating resolver marks authenticated
+const DNSSEC_SIGNED: &str = "cloudflare.com";
+
+/// Zone whose signatures are deliberately broken, which a validating resolver refuses
+const DNSSEC_BROKEN: &str = "dnssec-failed.org";
+
+/// Measure DNS resolution time for a given hostname, with `resolver` when given and the
+/// system resolver otherwise. An encrypted (DoH/DoT) `resolver` is timed separately,
+/// after a plain lookup with the system resolver to compare it with. With `dnssec`, also
+/// checks whether that resolver (or the system's first nameserver) validates DNSSEC.
 ///
 /// Returns a `DnsSummary` containing the resolution time and resolved IP addresses.
-pub async fn measure_dns_resolution(hostname: &str) -> Result<DnsSummary> {
+pub(crate) async fn measure_dns_resolution(
+    hostname: &str,
+    resolver: Option<&DnsResolver>,
+    dnssec: bool,
+) -> Result<DnsSummary> {
+    let (dnssec_validating, dnssec_detail) = if dnssec {
+        match resolver.cloned().or_else(DnsResolver::system) {
+            Some(resolver) => check_dnssec(&resolver).await,
+            None => (None, "no nameserver to check".to_string()),
+        }
+    } else {
+        (None, String::new())
+    };
+
+    let (resolver, encrypted) = match resolver {
+        Some(r) if r.encryption().is_some() => (None, Some(r)),
+        plain => (plain, None),
+    };
+
     // Get system DNS servers
     let dns_servers = get_system_dns_servers();
 
@@ -21,18 +49,24 @@ pub async fn measure_dns_resolution(hostname: &str) -> Result<DnsSummary> {
     };
 
     let start = Instant::now();
-    let addrs: Vec<std::net::SocketAddr> = lookup_host(&lookup_target)
-        .await
-        .with_context(|| format!("DNS lookup failed for {}", hostname))?
-        .collect();
+    let ips: Vec<IpAddr> = match resolver {
+        Some(resolver) => resolver
+            .lookup(hostname)
+            .await
+            .with_context(|| format!("DNS lookup failed for {}", hostname))?,
+        None => lookup_host(&lookup_target)
+            .await
+            .with_context(|| format!("DNS lookup failed for {}", hostname))?
+            .map(|addr| addr.ip())
+            .collect(),
+    };
     let elapsed = start.elapsed();
 
     let mut ipv4_count = 0;
     let mut ipv6_count = 0;
     let mut resolved_ips = Vec::new();
 
-    for addr in &addrs {
-        let ip = addr.ip();
+    for &ip in &ips {
         resolved_ips.push(ip.to_string());
         match ip {
             IpAddr::V4(_) => ipv4_count += 1,
@@ -44,6 +78,11 @@ pub async fn measure_dns_resolution(hostname: &str) -> Result<DnsSummary> {
     resolved_ips.sort();
     resolved_ips.dedup();
 
+    let encrypted = match encrypted {
+        Some(resolver) => Some(measure_encrypted(hostname, resolver).await?),
+        None => None,
+    };
+
     Ok(DnsSummary {
         hostname: hostname.to_string(),
         resolution_time_ms: elapsed.as_secs_f64() * 1000.0,
@@ -51,6 +90,57 @@ pub async fn measure_dns_resolution(hostname: &str) -> Result<DnsSummary> {
         ipv4_count,
         ipv6_count,
         dns_servers,
+        resolver: resolver.map(DnsResolver::describe),
+        encrypted,
+        dnssec_validating,
+        dnssec_detail: (!dnssec_detail.is_empty()).then_some(dnssec_detail),
+    })
+}
+
+/// Whether `resolver` validates DNSSEC: it resolves the signed zone but refuses the one
+/// with broken signatures (SERVFAIL). `None` when either lookup fails otherwise, which
+/// shows nothing either way. The detail says what the answers were.
+async fn check_dnssec(resolver: &DnsResolver) -> (Option<bool>, String) {
+    let (signed, broken) = tokio::join!(
+        resolver.authenticated(DNSSEC_SIGNED),
+        resolver.authenticated(DNSSEC_BROKEN)
+    );
+    let signed = match signed {
+        Ok(true) => "authenticated",
+        Ok(false) => "resolved without the AD flag",
+        Err(e) => return (None, format!("{} lookup failed: {:#}", DNSSEC_SIGNED, e)),
+    };
+    match broken {
+        Err(e) if is_server_failure(&e) => (
+            Some(true),
+            format!("{} {}, {} refused", DNSSEC_SIGNED, signed, DNSSEC_BROKEN),
+        ),
+        Ok(_) => (
+            Some(false),
+            format!("{} resolved despite its broken signatures", DNSSEC_BROKEN),
+        ),
+        Err(e) => (None, format!("{} lookup failed: {:#}", DNSSEC_BROKEN, e)),
+    }
+}
+
+async fn measure_encrypted(hostname: &str, resolver: &DnsResolver) -> Result<EncryptedDnsSummary> {
+    let protocol = resolver.encryption().unwrap_or_default();
+    let start = Instant::now();
+    let ips = resolver
+        .lookup(hostname)
+        .await
+        .with_context(|| format!("{} lookup failed for {}", protocol, hostname))?;
+    let elapsed = start.elapsed();
+
+    let mut resolved_ips: Vec<String> = ips.iter().map(IpAddr::to_string).collect();
+    resolved_ips.sort();
+    resolved_ips.dedup();
+
+    Ok(EncryptedDnsSummary {
+        protocol: protocol.to_string(),
+        resolver: resolver.describe(),
+        resolution_time_ms: elapsed.as_secs_f64() * 1000.0,
+        resolved_ips,
     })
 }
 
@@ -58,7 +148,7 @@ pub async fn measure_dns_resolution(hostname: &str) -> Result<DnsSummary> {
 ///
 /// On Linux/macOS: Parses /etc/resolv.conf
 /// On Windows: Uses ipconfig command
-fn get_system_dns_servers() -> Vec<String> {
+pub(crate) fn get_system_dns_servers() -> Vec<String> {
     #[cfg(any(target_os = "linux", target_os = "macos"))]
     {
         get_dns_from_resolv_conf()
@@ -206,20 +296,35 @@ pub fn extract_hostname(url: &str) -> Option<String> {
         .and_then(|u| u.host_str().map(|s| s.to_string()))
 }
 
-/// Fetch external IPv4 and IPv6 addresses by making requests to Cloudflare.
+/// Fetch external IPv4 and IPv6 addresses by making requests to Cloudflare, skipping the
+/// family `-4`/`-6` leave out.
 /// Returns (ipv4, ipv6) - either may be None if not available.
-pub async fn fetch_external_ips(base_url: &str) -> (Option<String>, Option<String>) {
+pub async fn fetch_external_ips(cfg: &RunConfig) -> (Option<String>, Option<String>) {
+    let base_url = &cfg.base_url;
     let hostname = match extract_hostname(base_url) {
         Some(h) => h,
         None => return (None, None),
     };
 
     // Resolve to get IPv4 and IPv6 addresses
-    let url = format!("{}/__down?bytes=0", base_url);
+    let url = match crate::engine::backend::for_config(cfg) {
+        Ok(backend) => backend.download_url(0).to_string(),
+        Err(_) => return (None, None),
+    };
+    let token = cfg.endpoint_auth_token.as_deref();
 
+    let fetch = |version: IpVersion, skip: bool| {
+        let (url, hostname) = (&url, &hostname);
+        async move {
+            if skip {
+                return None;
+            }
+            fetch_external_ip_version(url, hostname, token, version).await
+        }
+    };
     let (ipv4, ipv6) = tokio::join!(
-        fetch_external_ip_version(&url, &hostname, IpVersion::V4),
-        fetch_external_ip_version(&url, &hostname, IpVersion::V6)
+        fetch(IpVersion::V4, cfg.ipv6_only),
+        fetch(IpVersion::V6, cfg.ipv4_only)
     );
 
     (ipv4, ipv6)
@@ -234,6 +339,7 @@ enum IpVersion {
 async fn fetch_external_ip_version(
     url: &str,
     hostname: &str,
+    token: Option<&str>,
     version: IpVersion,
 ) -> Option<String> {
     use std::net::SocketAddr;
@@ -260,7 +366,11 @@ async fn fetch_external_ip_version(
         .ok()?;
 
     // Make request and extract IP from response headers
-    let resp = client.get(url).send().await.ok()?;
+    let mut req = client.get(url);
+    if let Some(token) = token {
+        req = req.bearer_auth(token);
+    }
+    let resp = req.send().await.ok()?;
 
     // Extract IP from cf-meta-ip header
     resp.headers()
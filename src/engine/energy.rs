@@ -0,0 +1,120 @@
+//! Package energy sampling via Intel RAPL
+//!
+//! On Linux the powercap interface exposes a cumulative energy counter per CPU
+//! package (`/sys/class/powercap/intel-rapl:N/energy_uj`). Reading it before and after
+//! a throughput phase gives the energy the host spent moving the data, reported as
+//! Joules per gigabyte. The counters are usually readable by root only; without
+//! access (or on other platforms) no energy figures are recorded.
+
+use crate::model::{Phase, PhaseEnergy};
+
+/// One RAPL package counter
+struct Counter {
+    path: std::path::PathBuf,
+    /// Value at which the counter wraps back to zero, in microjoules
+    max_uj: u64,
+}
+
+/// Readable package counters of this host
+pub(crate) struct EnergyMeter {
+    counters: Vec<Counter>,
+}
+
+/// Counter values at one point in time, in the same order as the meter's counters
+pub(crate) struct EnergySample(Vec<u64>);
+
+impl EnergyMeter {
+    /// Find readable package counters; None when RAPL is unavailable.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn detect() -> Option<Self> {
+        let mut counters = Vec::new();
+        for entry in std::fs::read_dir("/sys/class/powercap").ok()?.flatten() {
+            let dir = entry.path();
+            // Top-level zones are packages ("intel-rapl:0"); "intel-rapl:0:1" are subzones
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with("intel-rapl:") || name.matches(':').count() != 1 {
+                continue;
+            }
+            let path = dir.join("energy_uj");
+            if read_u64(&path).is_none() {
+                continue;
+            }
+            let max_uj = read_u64(&dir.join("max_energy_range_uj")).unwrap_or(u64::MAX);
+            counters.push(Counter { path, max_uj });
+        }
+        (!counters.is_empty()).then_some(Self { counters })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn detect() -> Option<Self> {
+        None
+    }
+
+    pub(crate) fn sample(&self) -> Option<EnergySample> {
+        self.counters
+            .iter()
+            .map(|c| read_u64(&c.path))
+            .collect::<Option<Vec<_>>>()
+            .map(EnergySample)
+    }
+
+    /// Energy used by `phase` between two samples, normalized by the bytes moved.
+    pub(crate) fn phase_energy(
+        &self,
+        phase: Phase,
+        start: &EnergySample,
+        end: &EnergySample,
+        bytes: u64,
+    ) -> PhaseEnergy {
+        let uj: u64 = self
+            .counters
+            .iter()
+            .zip(start.0.iter().zip(&end.0))
+            .map(|(c, (s, e))| counter_delta(*s, *e, c.max_uj))
+            .sum();
+        let joules = uj as f64 / 1e6;
+        PhaseEnergy {
+            phase,
+            joules,
+            bytes,
+            joules_per_gb: (bytes > 0).then(|| joules / (bytes as f64 / 1e9)),
+        }
+    }
+}
+
+/// Difference between two readings of a counter that wraps at `max_uj`
+fn counter_delta(start: u64, end: u64, max_uj: u64) -> u64 {
+    if end >= start {
+        end - start
+    } else {
+        max_uj.saturating_sub(start) + end
+    }
+}
+
+fn read_u64(path: &std::path::Path) -> Option<u64> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phase_energy() {
+        let meter = EnergyMeter {
+            counters: vec![Counter {
+                path: "/nonexistent".into(),
+                max_uj: 1_000_000,
+            }],
+        };
+        // Counter wrapped: 100_000 up to the wrap point plus 400_000 after it
+        let e = meter.phase_energy(
+            Phase::Download,
+            &EnergySample(vec![900_000]),
+            &EnergySample(vec![400_000]),
+            500_000_000,
+        );
+        assert!((e.joules - 0.5).abs() < 1e-9);
+        assert!((e.joules_per_gb.unwrap() - 1.0).abs() < 1e-9);
+    }
+}
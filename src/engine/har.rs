@@ -0,0 +1,313 @@
+//! HAR export of the test traffic (`--export-har`)
+//!
+//! Every request made through `CloudflareClient::send` is recorded once its response
+//! body has been read to the end or dropped, so the receive time and size cover what was
+//! actually transferred. Timings before the request was sent (DNS, connect, TLS) are not
+//! visible through reqwest and are reported as -1, which HAR viewers treat as unknown.
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use reqwest::{RequestBuilder, Response, ResponseBuilderExt};
+use serde::Serialize;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll};
+use std::time::Instant;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Entries of one run, in the order their responses completed
+#[derive(Default)]
+pub struct HarRecorder {
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl HarRecorder {
+    /// Write the recorded entries as a HAR 1.2 document.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let entries = self.entries.lock().unwrap();
+        let har = Har {
+            log: Log {
+                version: "1.2",
+                creator: Creator {
+                    name: env!("CARGO_PKG_NAME"),
+                    version: env!("CARGO_PKG_VERSION"),
+                },
+                entries: &entries,
+            },
+        };
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        serde_json::to_writer(std::io::BufWriter::new(file), &har)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    pub fn count(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    fn push(&self, entry: Entry) {
+        self.entries.lock().unwrap().push(entry);
+    }
+}
+
+/// Send `req`, recording it in `har` when given.
+pub async fn send(
+    har: Option<&Arc<HarRecorder>>,
+    req: RequestBuilder,
+) -> reqwest::Result<Response> {
+    let Some(har) = har else {
+        return req.send().await;
+    };
+    let (client, request) = req.build_split();
+    let request = request?;
+    let started = OffsetDateTime::now_utc();
+    let start = Instant::now();
+    let har_request = HarRequest {
+        method: request.method().to_string(),
+        url: request.url().to_string(),
+        http_version: String::new(),
+        headers: headers(request.headers()),
+        query_string: request
+            .url()
+            .query_pairs()
+            .map(|(name, value)| NameValue {
+                name: name.into_owned(),
+                value: value.into_owned(),
+            })
+            .collect(),
+        cookies: Vec::new(),
+        headers_size: -1,
+        body_size: request
+            .body()
+            .and_then(|b| b.as_bytes())
+            .map(|b| b.len() as i64)
+            .unwrap_or(-1),
+    };
+    let mut entry = Entry {
+        started_date_time: started.format(&Rfc3339).unwrap_or_default(),
+        time: 0.0,
+        request: har_request,
+        response: HarResponse::default(),
+        cache: Cache {},
+        timings: Timings {
+            blocked: -1.0,
+            dns: -1.0,
+            connect: -1.0,
+            ssl: -1.0,
+            send: 0.0,
+            wait: 0.0,
+            receive: 0.0,
+        },
+        server_ip_address: None,
+        error: None,
+    };
+
+    let resp = match client.execute(request).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            entry.timings.wait = ms_since(start);
+            entry.time = entry.timings.wait;
+            entry.error = Some(crate::report::describe_request_error(&e));
+            har.push(entry);
+            return Err(e);
+        }
+    };
+    entry.timings.wait = ms_since(start);
+    let version = format!("{:?}", resp.version());
+    entry.request.http_version = version.clone();
+    entry.server_ip_address = resp.remote_addr().map(|a| a.ip().to_string());
+    entry.response = HarResponse {
+        status: resp.status().as_u16(),
+        status_text: resp
+            .status()
+            .canonical_reason()
+            .unwrap_or_default()
+            .to_string(),
+        http_version: version,
+        headers: headers(resp.headers()),
+        cookies: Vec::new(),
+        content: Content {
+            size: 0,
+            mime_type: resp
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("x-unknown")
+                .to_string(),
+        },
+        redirect_url: String::new(),
+        headers_size: -1,
+        body_size: 0,
+    };
+
+    // Swap the body for one that completes the entry when it ends or is dropped. The URL
+    // lives in a response extension that the conversion to `http::Response` drops.
+    let url = resp.url().clone();
+    let (mut parts, body) = http::Response::from(resp).into_parts();
+    let inner = Response::from(http::Response::new(body))
+        .bytes_stream()
+        .boxed();
+    if let Ok(with_url) = http::Response::builder().url(url).body(()) {
+        parts.extensions.extend(with_url.into_parts().0.extensions);
+    }
+    let tracked = TrackedBody {
+        inner,
+        recorder: har.clone(),
+        entry: Some(entry),
+        headers_at: Instant::now(),
+        start,
+        bytes: 0,
+    };
+    Ok(Response::from(http::Response::from_parts(
+        parts,
+        reqwest::Body::wrap_stream(tracked),
+    )))
+}
+
+fn ms_since(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+fn headers(map: &reqwest::header::HeaderMap) -> Vec<NameValue> {
+    map.iter()
+        .map(|(name, value)| NameValue {
+            name: name.to_string(),
+            value: String::from_utf8_lossy(value.as_bytes()).into_owned(),
+        })
+        .collect()
+}
+
+/// Response body that counts what is read and records the entry at the end
+struct TrackedBody {
+    inner: BoxStream<'static, reqwest::Result<Bytes>>,
+    recorder: Arc<HarRecorder>,
+    entry: Option<Entry>,
+    headers_at: Instant,
+    start: Instant,
+    bytes: u64,
+}
+
+impl TrackedBody {
+    fn finish(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.timings.receive = ms_since(self.headers_at);
+            entry.time = ms_since(self.start);
+            entry.response.content.size = self.bytes as i64;
+            entry.response.body_size = self.bytes as i64;
+            self.recorder.push(entry);
+        }
+    }
+}
+
+impl Stream for TrackedBody {
+    type Item = reqwest::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        let item = self.inner.poll_next_unpin(cx);
+        match &item {
+            Poll::Ready(Some(Ok(chunk))) => self.bytes += chunk.len() as u64,
+            Poll::Ready(_) => self.finish(),
+            Poll::Pending => {}
+        }
+        item
+    }
+}
+
+impl Drop for TrackedBody {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[derive(Serialize)]
+struct Har<'a> {
+    log: Log<'a>,
+}
+
+#[derive(Serialize)]
+struct Log<'a> {
+    version: &'static str,
+    creator: Creator,
+    entries: &'a [Entry],
+}
+
+#[derive(Serialize)]
+struct Creator {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    started_date_time: String,
+    time: f64,
+    request: HarRequest,
+    response: HarResponse,
+    cache: Cache,
+    timings: Timings,
+    #[serde(rename = "serverIPAddress", skip_serializing_if = "Option::is_none")]
+    server_ip_address: Option<String>,
+    /// Transport error for requests that got no response
+    #[serde(rename = "_error", skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarRequest {
+    method: String,
+    url: String,
+    http_version: String,
+    headers: Vec<NameValue>,
+    query_string: Vec<NameValue>,
+    cookies: Vec<NameValue>,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct HarResponse {
+    status: u16,
+    status_text: String,
+    http_version: String,
+    headers: Vec<NameValue>,
+    cookies: Vec<NameValue>,
+    content: Content,
+    #[serde(rename = "redirectURL")]
+    redirect_url: String,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Content {
+    size: i64,
+    mime_type: String,
+}
+
+#[derive(Serialize)]
+struct Cache {}
+
+#[derive(Serialize)]
+struct Timings {
+    blocked: f64,
+    dns: f64,
+    connect: f64,
+    ssl: f64,
+    send: f64,
+    wait: f64,
+    receive: f64,
+}
+
+#[derive(Serialize)]
+struct NameValue {
+    name: String,
+    value: String,
+}
@@ -1,211 +1,226 @@
 //! IPv4 vs IPv6 comparison module
 //!
-//! Runs abbreviated speed tests on both IPv4 and IPv6 to compare performance.
-
-use crate::model::{IpVersionComparison, IpVersionResult};
-use anyhow::Result;
-use reqwest::Url;
-use std::net::{IpAddr, SocketAddr};
-use std::time::{Duration, Instant};
-use tokio::net::lookup_host;
+//! Runs abbreviated speed tests on both IPv4 and IPv6 to compare performance. Each
+//! family gets a client of its own, bound the way `-4`/`-6` bind the main test, and goes
+//! through the same latency, download and upload code as the main test, shortened. Their
+//! progress is reported as `TestEvent::IpComparisonTick`s under the family's label.
+
+use crate::engine::cloudflare::CloudflareClient;
+use crate::engine::latency::{run_latency_probes, ProbeOptions};
+use crate::engine::resolver::{resolve_endpoint, DnsResolver};
+use crate::engine::scheduler::ProbeScheduler;
+use crate::engine::throughput::{
+    run_download_with_loaded_latency, run_upload_with_loaded_latency, DataBudget,
+};
+use crate::model::{IpVersionComparison, IpVersionResult, Phase, RunConfig, TestEvent};
+use anyhow::{Context, Result};
+use std::sync::{atomic::AtomicBool, Arc};
+use std::time::Duration;
+use tokio::sync::mpsc;
 
 /// Duration for each abbreviated speed test (download/upload)
 const TEST_DURATION: Duration = Duration::from_secs(3);
 
+/// Duration of the idle latency probes before each family's download
+const LATENCY_DURATION: Duration = Duration::from_secs(1);
+
 /// Run IPv4 vs IPv6 comparison tests.
 ///
 /// Resolves the hostname to both IPv4 and IPv6 addresses, then runs
 /// abbreviated speed tests on each protocol.
-pub async fn compare_ip_versions(base_url: &str, user_agent: &str) -> Result<IpVersionComparison> {
-    let url = Url::parse(base_url)?;
-    let hostname = url
-        .host_str()
-        .ok_or_else(|| anyhow::anyhow!("No host in URL"))?;
-    let port = url.port_or_known_default().unwrap_or(443);
-
-    // Resolve hostname to get both IPv4 and IPv6 addresses
-    let lookup_target = format!("{}:{}", hostname, port);
-    let addrs: Vec<SocketAddr> = lookup_host(&lookup_target).await?.collect();
-
-    let mut ipv4_addr: Option<IpAddr> = None;
-    let mut ipv6_addr: Option<IpAddr> = None;
-
-    for addr in addrs {
-        match addr.ip() {
-            ip @ IpAddr::V4(_) if ipv4_addr.is_none() => ipv4_addr = Some(ip),
-            ip @ IpAddr::V6(_) if ipv6_addr.is_none() => ipv6_addr = Some(ip),
-            _ => {}
-        }
-        if ipv4_addr.is_some() && ipv6_addr.is_some() {
-            break;
-        }
+pub async fn compare_ip_versions(
+    cfg: &RunConfig,
+    event_tx: &mpsc::Sender<TestEvent>,
+    scheduler: Arc<ProbeScheduler>,
+    budget: Arc<DataBudget>,
+    paused: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+) -> Result<IpVersionComparison> {
+    let (host, port) =
+        crate::engine::tls::extract_host_port(&cfg.base_url).context("No host in URL")?;
+    let resolver = DnsResolver::from_config(cfg)?;
+
+    let mut results = Vec::new();
+    for (label, ipv4) in [("IPv4", true), ("IPv6", false)] {
+        let family_cfg = RunConfig {
+            ipv4_only: ipv4,
+            ipv6_only: !ipv4,
+            download_duration: TEST_DURATION,
+            upload_duration: TEST_DURATION,
+            ..cfg.clone()
+        };
+        let ip = match resolve_endpoint(&family_cfg, resolver.as_ref(), &host, port).await {
+            Ok(addrs) => addrs[0].ip(),
+            Err(_) => {
+                results.push(unavailable(
+                    "N/A".to_string(),
+                    format!("No {} address resolved", label),
+                ));
+                continue;
+            }
+        };
+        let family = Family {
+            label,
+            cfg: &family_cfg,
+            event_tx,
+            scheduler: scheduler.clone(),
+            budget: budget.clone(),
+            paused: paused.clone(),
+            cancel: cancel.clone(),
+        };
+        let mut result = match family.test().await {
+            Ok(result) => result,
+            Err(e) => unavailable(String::new(), format!("{:#}", e)),
+        };
+        result.ip_address = ip.to_string();
+        results.push(result);
     }
 
-    // Test IPv4
-    let ipv4_result = if let Some(ip) = ipv4_addr {
-        Some(test_ip_version(base_url, hostname, port, ip, user_agent).await)
-    } else {
-        Some(IpVersionResult {
-            ip_address: "N/A".to_string(),
-            download_mbps: 0.0,
-            upload_mbps: 0.0,
-            latency_ms: 0.0,
-            available: false,
-            error: Some("No IPv4 address resolved".to_string()),
-        })
-    };
-
-    // Test IPv6
-    let ipv6_result = if let Some(ip) = ipv6_addr {
-        Some(test_ip_version(base_url, hostname, port, ip, user_agent).await)
-    } else {
-        Some(IpVersionResult {
-            ip_address: "N/A".to_string(),
-            download_mbps: 0.0,
-            upload_mbps: 0.0,
-            latency_ms: 0.0,
-            available: false,
-            error: Some("No IPv6 address resolved".to_string()),
-        })
-    };
-
+    let ipv6_result = results.pop();
+    let ipv4_result = results.pop();
     Ok(IpVersionComparison {
         ipv4_result,
         ipv6_result,
     })
 }
 
-/// Test a specific IP version by forcing requests to that IP.
-async fn test_ip_version(
-    base_url: &str,
-    hostname: &str,
-    port: u16,
-    ip: IpAddr,
-    user_agent: &str,
-) -> IpVersionResult {
-    let socket_addr = SocketAddr::new(ip, port);
-
-    // Build a client that resolves hostname to specific IP
-    let client = match reqwest::Client::builder()
-        .user_agent(user_agent)
-        .timeout(Duration::from_secs(30))
-        .resolve(hostname, socket_addr)
-        .build()
-    {
-        Ok(c) => c,
-        Err(e) => {
-            return IpVersionResult {
-                ip_address: ip.to_string(),
-                download_mbps: 0.0,
-                upload_mbps: 0.0,
-                latency_ms: 0.0,
-                available: false,
-                error: Some(format!("Failed to build client: {}", e)),
-            };
-        }
-    };
-
-    // Measure latency first
-    let latency_ms = match measure_latency(&client, base_url).await {
-        Ok(lat) => lat,
-        Err(e) => {
-            return IpVersionResult {
-                ip_address: ip.to_string(),
-                download_mbps: 0.0,
-                upload_mbps: 0.0,
-                latency_ms: 0.0,
-                available: false,
-                error: Some(format!("Latency test failed: {}", e)),
-            };
-        }
-    };
-
-    // Run abbreviated download test
-    let download_mbps = match run_download_test(&client, base_url, TEST_DURATION).await {
-        Ok(mbps) => mbps,
-        Err(e) => {
-            return IpVersionResult {
-                ip_address: ip.to_string(),
-                download_mbps: 0.0,
-                upload_mbps: 0.0,
-                latency_ms,
-                available: false,
-                error: Some(format!("Download test failed: {}", e)),
-            };
-        }
-    };
-
-    // Run abbreviated upload test
-    let upload_mbps = match run_upload_test(&client, base_url, TEST_DURATION).await {
-        Ok(mbps) => mbps,
-        Err(e) => {
-            return IpVersionResult {
-                ip_address: ip.to_string(),
-                download_mbps,
-                upload_mbps: 0.0,
-                latency_ms,
-                available: true, // download worked
-                error: Some(format!("Upload test failed: {}", e)),
-            };
-        }
-    };
-
+fn unavailable(ip_address: String, error: String) -> IpVersionResult {
     IpVersionResult {
-        ip_address: ip.to_string(),
-        download_mbps,
-        upload_mbps,
-        latency_ms,
-        available: true,
-        error: None,
+        ip_address,
+        download_mbps: 0.0,
+        upload_mbps: 0.0,
+        latency_ms: 0.0,
+        available: false,
+        error: Some(error),
+        idle_latency: None,
+        loaded_latency_download: None,
+        loaded_latency_upload: None,
     }
 }
 
-/// Measure latency to the server.
-async fn measure_latency(client: &reqwest::Client, base_url: &str) -> Result<f64> {
-    let url = format!("{}/__down?bytes=0", base_url);
-    let start = Instant::now();
-    let _resp = client.get(&url).send().await?;
-    Ok(start.elapsed().as_secs_f64() * 1000.0)
+/// One address family's share of the comparison
+struct Family<'a> {
+    /// "IPv4" or "IPv6"
+    label: &'static str,
+    /// The run's configuration restricted to the family, with the shortened durations
+    cfg: &'a RunConfig,
+    event_tx: &'a mpsc::Sender<TestEvent>,
+    scheduler: Arc<ProbeScheduler>,
+    budget: Arc<DataBudget>,
+    paused: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
 }
 
-/// Run abbreviated download test.
-async fn run_download_test(
-    client: &reqwest::Client,
-    base_url: &str,
-    duration: Duration,
-) -> Result<f64> {
-    let url = format!("{}/__down?bytes=5000000", base_url); // 5MB chunks
-    let start = Instant::now();
-    let mut total_bytes: u64 = 0;
-
-    while start.elapsed() < duration {
-        let resp = client.get(&url).send().await?;
-        let bytes = resp.bytes().await?;
-        total_bytes += bytes.len() as u64;
+impl Family<'_> {
+    /// Latency, then download, then upload over a client bound to the family, keeping the
+    /// latency probes of each so the families can be compared under load too.
+    async fn test(&self) -> Result<IpVersionResult> {
+        let client = CloudflareClient::new(self.cfg).context("Failed to build client")?;
+
+        let (leg_tx, forward) = self.forward();
+        let latency = run_latency_probes(
+            &client,
+            ProbeOptions {
+                phase: Phase::IpComparison,
+                during: None,
+                duration: LATENCY_DURATION,
+                interval_ms: self.cfg.probe_interval_ms,
+                timeout_ms: self.cfg.probe_timeout_ms,
+                scheduler: &self.scheduler,
+                paused: self.paused.clone(),
+                cancel: self.cancel.clone(),
+            },
+            &leg_tx,
+        )
+        .await
+        .map(|(latency, _)| latency);
+        let download = run_download_with_loaded_latency(
+            &client,
+            self.cfg,
+            &leg_tx,
+            self.scheduler.clone(),
+            self.budget.clone(),
+            self.paused.clone(),
+            self.cancel.clone(),
+        )
+        .await;
+        let upload = match download {
+            Ok((ref dl, _, _)) if dl.bytes > 0 => Some(
+                run_upload_with_loaded_latency(
+                    &client,
+                    self.cfg,
+                    &leg_tx,
+                    self.scheduler.clone(),
+                    self.budget.clone(),
+                    self.paused.clone(),
+                    self.cancel.clone(),
+                )
+                .await,
+            ),
+            _ => None,
+        };
+        drop(leg_tx);
+        let _ = forward.await;
+
+        let idle_latency = latency.ok();
+        let latency_ms = match idle_latency.as_ref().and_then(|l| l.median_ms) {
+            Some(ms) => ms,
+            None => anyhow::bail!("Latency test failed: no probe answered"),
+        };
+        let (download_mbps, loaded_latency_download) = match download {
+            Ok((dl, loaded, _)) if dl.bytes > 0 => (dl.mbps, loaded),
+            Ok(_) => anyhow::bail!("Download test failed: no data received"),
+            Err(e) => anyhow::bail!("Download test failed: {:#}", e),
+        };
+        let (upload_mbps, loaded_latency_upload, error) = match upload {
+            Some(Ok((ul, loaded, _))) if ul.bytes > 0 => (ul.mbps, Some(loaded), None),
+            Some(Ok(_)) => (
+                0.0,
+                None,
+                Some("Upload test failed: no data sent".to_string()),
+            ),
+            Some(Err(e)) => (0.0, None, Some(format!("Upload test failed: {:#}", e))),
+            None => (0.0, None, None),
+        };
+        Ok(IpVersionResult {
+            ip_address: String::new(),
+            download_mbps,
+            upload_mbps,
+            latency_ms,
+            // Download worked even if the upload did not
+            available: true,
+            error,
+            idle_latency,
+            loaded_latency_download: Some(loaded_latency_download),
+            loaded_latency_upload,
+        })
     }
 
-    let elapsed_secs = start.elapsed().as_secs_f64();
-    let mbps = (total_bytes as f64 * 8.0) / (elapsed_secs * 1_000_000.0);
-    Ok(mbps)
-}
-
-/// Run abbreviated upload test.
-async fn run_upload_test(
-    client: &reqwest::Client,
-    base_url: &str,
-    duration: Duration,
-) -> Result<f64> {
-    let url = format!("{}/__up", base_url);
-    let upload_data = vec![0u8; 5_000_000]; // 5MB chunks
-    let start = Instant::now();
-    let mut total_bytes: u64 = 0;
-
-    while start.elapsed() < duration {
-        let _resp = client.post(&url).body(upload_data.clone()).send().await?;
-        total_bytes += upload_data.len() as u64;
+    /// A channel for the shared measurement code whose events reach `event_tx` as this
+    /// family's progress, and the task forwarding them, which ends with the channel.
+    fn forward(&self) -> (mpsc::Sender<TestEvent>, tokio::task::JoinHandle<()>) {
+        let (leg_tx, mut leg_rx) = mpsc::channel::<TestEvent>(256);
+        let forward_tx = self.event_tx.clone();
+        let label = self.label;
+        let forward = tokio::spawn(async move {
+            while let Some(ev) = leg_rx.recv().await {
+                let ev = match ev {
+                    TestEvent::ThroughputTick {
+                        phase, bps_instant, ..
+                    } => TestEvent::IpComparisonTick {
+                        family: label.to_string(),
+                        phase,
+                        bps_instant,
+                    },
+                    TestEvent::Info { message } => TestEvent::Info {
+                        message: format!("{} {}", label, message),
+                    },
+                    // Per-connection rates and probes belong to the main phases' charts
+                    _ => continue,
+                };
+                forward_tx.send(ev).await.ok();
+            }
+        });
+        (leg_tx, forward)
     }
-
-    let elapsed_secs = start.elapsed().as_secs_f64();
-    let mbps = (total_bytes as f64 * 8.0) / (elapsed_secs * 1_000_000.0);
-    Ok(mbps)
 }
@@ -0,0 +1,568 @@
+//! LAN tests between two instances of this binary (`serve --lan`, `--lan HOST:PORT`).
+//!
+//! Every test opens its own TCP connection to the peer and names what it wants in a
+//! first line, `cfspeed-lan/1 COMMAND [ARG]`:
+//!
+//! - `PING`: the peer echoes what it receives, one byte per latency probe
+//! - `SEND MS`: the peer sends for MS milliseconds, then closes (download)
+//! - `RECV`: the peer reads until the client closes its side, then answers with the
+//!   bytes it received (upload)
+//! - `UDP`: the peer answers with a token; the client then sends datagrams carrying the
+//!   token and a sequence number to the same port over UDP, then `DONE`, and the peer
+//!   answers with `RECEIVED BYTES OUT_OF_ORDER`
+//!
+//! The run produces an ordinary `RunResult`, with `backend` "lan" and a `lan://` base
+//! URL, so LAN results sit next to internet ones in the history.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+
+use crate::engine::builder::RunResultBuilder;
+use crate::engine::throughput::throughput_summary;
+use crate::engine::{wait_if_paused_or_cancelled, EngineControl};
+use crate::model::{
+    LatencySummary, Phase, RunConfig, RunResult, TestEvent, ThroughputSummary, UdpThroughputSummary,
+};
+use crate::stats::{latency_summary_from_samples, OnlineStats};
+
+/// Port `serve --lan` listens on and `--lan` connects to when none is given
+pub const DEFAULT_PORT: u16 = 7879;
+
+/// First word of every request line
+const PROTOCOL: &str = "cfspeed-lan/1";
+
+/// Size of the writes of a download or upload
+const BLOCK: usize = 128 * 1024;
+
+/// Longest `SEND` the server honors
+const MAX_SEND: Duration = Duration::from_secs(300);
+
+/// UDP payload per datagram: token, sequence number and padding
+const UDP_PACKET_SIZE: usize = 1200;
+
+/// Time to connect to the peer and to get an answer to a request
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval of the throughput ticks
+const TICK: Duration = Duration::from_millis(200);
+
+/// Serve LAN tests on `listen` (TCP and UDP) until interrupted.
+pub async fn serve(listen: &str) -> Result<()> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("failed to listen on {}", listen))?;
+    let addr = listener.local_addr()?;
+    let udp = Arc::new(
+        UdpSocket::bind(addr)
+            .await
+            .with_context(|| format!("failed to listen on {} (UDP)", addr))?,
+    );
+    let streams = Arc::new(UdpStreams::default());
+    tokio::spawn(count_datagrams(udp, streams.clone()));
+
+    println!("Serving LAN tests on {} (TCP and UDP)", addr);
+    println!("Run the other side with: --lan {}", addr);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        let streams = streams.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &streams).await {
+                tracing::debug!("LAN client {}: {:#}", peer, e);
+            }
+        });
+    }
+}
+
+/// Datagrams received per `UDP` token: received, payload bytes, out of order, highest
+/// sequence number
+#[derive(Default)]
+struct UdpStreams {
+    next_token: AtomicU64,
+    counts: Mutex<HashMap<u64, [u64; 4]>>,
+}
+
+async fn count_datagrams(sock: Arc<UdpSocket>, streams: Arc<UdpStreams>) {
+    let mut buf = [0u8; 2048];
+    while let Ok(n) = sock.recv(&mut buf).await {
+        let (Some(token), Some(seq)) = (
+            buf.get(..8).and_then(|b| b.try_into().ok()),
+            buf.get(8..16).and_then(|b| b.try_into().ok()),
+        ) else {
+            continue;
+        };
+        let (token, seq) = (u64::from_be_bytes(token), u64::from_be_bytes(seq));
+        let mut counts = streams.counts.lock().unwrap();
+        // Datagrams of unknown or finished streams are dropped
+        let Some([received, bytes, out_of_order, highest]) = counts.get_mut(&token) else {
+            continue;
+        };
+        *received += 1;
+        *bytes += n as u64;
+        if seq < *highest {
+            *out_of_order += 1;
+        } else {
+            *highest = seq;
+        }
+    }
+}
+
+async fn handle(stream: TcpStream, streams: &UdpStreams) -> Result<()> {
+    stream.set_nodelay(true).ok();
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    tokio::time::timeout(CONNECT_TIMEOUT, stream.read_line(&mut line))
+        .await
+        .context("no request")??;
+    let mut words = line.split_whitespace();
+    anyhow::ensure!(words.next() == Some(PROTOCOL), "not a LAN test request");
+    match (words.next(), words.next()) {
+        (Some("PING"), _) => {
+            let mut buf = [0u8; 64];
+            loop {
+                let n = stream.read(&mut buf).await?;
+                if n == 0 {
+                    return Ok(());
+                }
+                stream.get_mut().write_all(&buf[..n]).await?;
+            }
+        }
+        (Some("SEND"), Some(ms)) => {
+            let duration = Duration::from_millis(ms.parse()?).min(MAX_SEND);
+            let block = vec![0u8; BLOCK];
+            let start = Instant::now();
+            while start.elapsed() < duration {
+                stream.get_mut().write_all(&block).await?;
+            }
+            stream.get_mut().shutdown().await?;
+        }
+        (Some("RECV"), _) => {
+            let mut buf = vec![0u8; BLOCK];
+            let mut received = 0u64;
+            loop {
+                let n = stream.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                received += n as u64;
+            }
+            stream
+                .get_mut()
+                .write_all(format!("{}\n", received).as_bytes())
+                .await?;
+        }
+        (Some("UDP"), _) => {
+            let token = streams.next_token.fetch_add(1, Ordering::Relaxed);
+            streams.counts.lock().unwrap().insert(token, [0; 4]);
+            stream
+                .get_mut()
+                .write_all(format!("{}\n", token).as_bytes())
+                .await?;
+            // The client says when it has sent everything, or goes away
+            line.clear();
+            stream.read_line(&mut line).await.ok();
+            let counts = streams.counts.lock().unwrap().remove(&token);
+            let [received, bytes, out_of_order, _] = counts.unwrap_or_default();
+            stream
+                .get_mut()
+                .write_all(format!("{} {} {}\n", received, bytes, out_of_order).as_bytes())
+                .await?;
+        }
+        _ => anyhow::bail!("unknown LAN test request {:?}", line.trim()),
+    }
+    Ok(())
+}
+
+/// A connection to the peer with `command` sent
+async fn open(peer: &str, command: &str) -> Result<TcpStream> {
+    let mut stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(peer))
+        .await
+        .with_context(|| format!("connecting to {} timed out", peer))?
+        .with_context(|| format!("failed to connect to {}; is `serve --lan` running?", peer))?;
+    stream.set_nodelay(true).ok();
+    stream
+        .write_all(format!("{} {}\n", PROTOCOL, command).as_bytes())
+        .await?;
+    Ok(stream)
+}
+
+/// One line of answer from the peer
+async fn answer(stream: &mut BufReader<TcpStream>) -> Result<String> {
+    let mut line = String::new();
+    tokio::time::timeout(CONNECT_TIMEOUT, stream.read_line(&mut line))
+        .await
+        .context("the peer did not answer")??;
+    anyhow::ensure!(!line.is_empty(), "the peer closed the connection");
+    Ok(line.trim().to_string())
+}
+
+/// Flags of the run the phases watch
+struct Flags {
+    paused: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+}
+
+/// Run the LAN test against the peer in `cfg.base_url` (`lan://HOST:PORT`).
+pub async fn run(
+    cfg: &RunConfig,
+    event_tx: mpsc::Sender<TestEvent>,
+    mut control_rx: mpsc::Receiver<EngineControl>,
+) -> Result<RunResult> {
+    let peer = cfg
+        .base_url
+        .strip_prefix("lan://")
+        .context("LAN tests need a lan://HOST:PORT base URL")?
+        .trim_end_matches('/')
+        .to_string();
+
+    let flags = Flags {
+        paused: Arc::new(AtomicBool::new(false)),
+        cancel: Arc::new(AtomicBool::new(false)),
+    };
+    let (paused, cancel) = (flags.paused.clone(), flags.cancel.clone());
+    let control_handle = tokio::spawn(async move {
+        while let Some(msg) = control_rx.recv().await {
+            match msg {
+                EngineControl::Pause(p) => paused.store(p, Ordering::Relaxed),
+                EngineControl::Cancel => {
+                    cancel.store(true, Ordering::Relaxed);
+                    break;
+                }
+            }
+        }
+    });
+
+    // Fail early, before any phase starts, when nothing answers. There is no metadata:
+    // the address facing the peer says nothing about the internet connection.
+    open(&peer, "PING").await?;
+
+    let phases = cfg.phases;
+    let mut results = RunResultBuilder::new(cfg);
+
+    if phases.idle_latency {
+        event_tx
+            .send(TestEvent::PhaseStarted {
+                phase: Phase::IdleLatency,
+            })
+            .await
+            .ok();
+        let idle_latency = ping(
+            &peer,
+            Phase::IdleLatency,
+            None,
+            cfg.idle_latency_duration,
+            cfg,
+            &event_tx,
+            &flags,
+        )
+        .await?;
+        let snapshot = results.record(Phase::IdleLatency, |r| r.idle_latency = idle_latency);
+        event_tx.send(snapshot).await.ok();
+    }
+
+    for (phase, enabled, duration) in [
+        (Phase::Download, phases.download, cfg.download_duration),
+        (Phase::Upload, phases.upload, cfg.upload_duration),
+    ] {
+        if !enabled || flags.cancel.load(Ordering::Relaxed) {
+            continue;
+        }
+        event_tx.send(TestEvent::PhaseStarted { phase }).await.ok();
+        let (summary, loaded) = tokio::join!(
+            transfer(&peer, phase, duration, cfg, &event_tx, &flags),
+            ping(&peer, phase, Some(phase), duration, cfg, &event_tx, &flags),
+        );
+        let (summary, loaded) = (
+            summary?,
+            loaded.unwrap_or_else(|_| LatencySummary::failed()),
+        );
+        let snapshot = results.record(phase, |r| {
+            if phase == Phase::Download {
+                r.download = summary;
+                r.loaded_latency_download = loaded;
+            } else {
+                r.upload = summary;
+                r.loaded_latency_upload = loaded;
+            }
+            r.bufferbloat = crate::stats::bufferbloat(
+                &r.idle_latency,
+                &r.loaded_latency_download,
+                &r.loaded_latency_upload,
+            );
+        });
+        event_tx.send(snapshot).await.ok();
+    }
+
+    if let Some(duration) = cfg.udp_throughput {
+        if !flags.cancel.load(Ordering::Relaxed) {
+            event_tx
+                .send(TestEvent::PhaseStarted {
+                    phase: Phase::UdpThroughput,
+                })
+                .await
+                .ok();
+            let mbps = cfg.udp_throughput_mbps;
+            let server = format!("lan://{}", peer);
+            let summary = udp_stream(&peer, duration, mbps, cfg)
+                .await
+                .unwrap_or_else(|e| {
+                    UdpThroughputSummary::failed(&server, mbps, Some(format!("{e:#}")))
+                });
+            let snapshot =
+                results.record(Phase::UdpThroughput, |r| r.udp_throughput = Some(summary));
+            event_tx.send(snapshot).await.ok();
+        }
+    }
+
+    event_tx
+        .send(TestEvent::PhaseStarted {
+            phase: Phase::Summary,
+        })
+        .await
+        .ok();
+    control_handle.abort();
+
+    let mut result = results.finish();
+    result.partial = flags.cancel.load(Ordering::Relaxed);
+    Ok(result)
+}
+
+/// Echo a byte off the peer every probe interval for `duration`.
+async fn ping(
+    peer: &str,
+    phase: Phase,
+    during: Option<Phase>,
+    duration: Duration,
+    cfg: &RunConfig,
+    event_tx: &mpsc::Sender<TestEvent>,
+    flags: &Flags,
+) -> Result<LatencySummary> {
+    let mut stream = open(peer, "PING").await?;
+    let timeout = Duration::from_millis(cfg.probe_timeout_ms);
+    let (mut sent, mut samples, mut online) = (0u64, Vec::new(), OnlineStats::default());
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        if wait_if_paused_or_cancelled(&flags.paused, &flags.cancel).await {
+            break;
+        }
+        sent += 1;
+        let sent_at = Instant::now();
+        let mut byte = [0u8; 1];
+        let echoed = tokio::time::timeout(timeout, async {
+            stream.write_all(&[1]).await?;
+            stream.read_exact(&mut byte).await
+        })
+        .await;
+        let rtt_ms = match echoed {
+            Ok(Ok(_)) => Some(sent_at.elapsed().as_secs_f64() * 1000.0),
+            Ok(Err(e)) => return Err(e).context("latency probe connection lost"),
+            // A late echo would be read as the next probe's; start over
+            Err(_) => {
+                stream = open(peer, "PING").await?;
+                None
+            }
+        };
+        if let Some(ms) = rtt_ms {
+            samples.push(ms);
+            online.push(ms);
+        }
+        event_tx
+            .send(TestEvent::LatencySample {
+                phase,
+                during,
+                rtt_ms,
+                ok: rtt_ms.is_some(),
+            })
+            .await
+            .ok();
+        tokio::time::sleep(Duration::from_millis(cfg.probe_interval_ms)).await;
+    }
+    Ok(latency_summary_from_samples(
+        sent,
+        samples.len() as u64,
+        &samples,
+        online.stddev(),
+    ))
+}
+
+/// Download (`SEND`) or upload (`RECV`) over `cfg.concurrency` connections for
+/// `duration`, ticking the rate as it goes.
+async fn transfer(
+    peer: &str,
+    phase: Phase,
+    duration: Duration,
+    cfg: &RunConfig,
+    event_tx: &mpsc::Sender<TestEvent>,
+    flags: &Flags,
+) -> Result<ThroughputSummary> {
+    let upload = phase == Phase::Upload;
+    let command = if upload {
+        "RECV".to_string()
+    } else {
+        format!("SEND {}", duration.as_millis())
+    };
+    let total = Arc::new(AtomicU64::new(0));
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut workers = Vec::new();
+    for _ in 0..cfg.concurrency.max(1) {
+        let stream = open(peer, &command).await?;
+        let (total, stop) = (total.clone(), stop.clone());
+        let paused = flags.paused.clone();
+        workers.push(tokio::spawn(async move {
+            if upload {
+                send_until(stream, &total, &stop, &paused).await
+            } else {
+                receive_until(stream, &total, &stop).await
+            }
+        }));
+    }
+
+    let start = Instant::now();
+    let (mut last_bytes, mut samples) = (0u64, Vec::new());
+    while start.elapsed() < duration && !flags.cancel.load(Ordering::Relaxed) {
+        tokio::time::sleep(TICK).await;
+        let bytes = total.load(Ordering::Relaxed);
+        let bps = bytes.saturating_sub(last_bytes) as f64 / TICK.as_secs_f64();
+        last_bytes = bytes;
+        samples.push(bps * 8.0 / 1e6);
+        event_tx
+            .send(TestEvent::ThroughputTick {
+                phase,
+                bytes_total: bytes,
+                bps_instant: bps,
+            })
+            .await
+            .ok();
+    }
+    stop.store(true, Ordering::Relaxed);
+    let elapsed = start.elapsed();
+
+    // Uploads count what the peer received rather than what left the socket buffers
+    let mut acknowledged = 0u64;
+    for worker in workers {
+        acknowledged += worker.await.context("transfer task failed")??;
+    }
+    let produced = total.load(Ordering::Relaxed);
+    let bytes = if upload { acknowledged } else { produced };
+    let mut summary = throughput_summary(bytes, elapsed, &samples);
+    summary.elapsed_ms = Some(elapsed.as_millis() as u64);
+    if upload {
+        summary.acknowledged_bytes = Some(acknowledged);
+    }
+    Ok(summary)
+}
+
+/// Upload until `stop`, then the bytes the peer says it received
+async fn send_until(
+    stream: TcpStream,
+    total: &AtomicU64,
+    stop: &AtomicBool,
+    paused: &AtomicBool,
+) -> Result<u64> {
+    let mut stream = BufReader::new(stream);
+    let block = vec![0u8; BLOCK];
+    while !stop.load(Ordering::Relaxed) {
+        if paused.load(Ordering::Relaxed) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            continue;
+        }
+        stream.get_mut().write_all(&block).await?;
+        total.fetch_add(BLOCK as u64, Ordering::Relaxed);
+    }
+    stream.get_mut().shutdown().await?;
+    answer(&mut stream)
+        .await?
+        .parse()
+        .context("unexpected answer to an upload")
+}
+
+/// Count what the peer sends until it closes or `stop`
+async fn receive_until(mut stream: TcpStream, total: &AtomicU64, stop: &AtomicBool) -> Result<u64> {
+    let mut buf = vec![0u8; BLOCK];
+    while !stop.load(Ordering::Relaxed) {
+        match tokio::time::timeout(TICK, stream.read(&mut buf)).await {
+            Ok(Ok(0)) => break,
+            Ok(Ok(n)) => {
+                total.fetch_add(n as u64, Ordering::Relaxed);
+            }
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => {}
+        }
+    }
+    Ok(0)
+}
+
+/// Pace `UDP_PACKET_SIZE` datagrams to the peer at `mbps` for `duration` and ask it what
+/// arrived.
+async fn udp_stream(
+    peer: &str,
+    duration: Duration,
+    mbps: f64,
+    cfg: &RunConfig,
+) -> Result<UdpThroughputSummary> {
+    let control = open(peer, "UDP").await?;
+    let target: SocketAddr = control.peer_addr()?;
+    let mut control = BufReader::new(control);
+    let token: u64 = answer(&mut control)
+        .await?
+        .parse()
+        .context("unexpected answer to a UDP request")?;
+
+    let bind: SocketAddr = if target.is_ipv4() {
+        "0.0.0.0:0".parse()?
+    } else {
+        "[::]:0".parse()?
+    };
+    let sock = UdpSocket::bind(bind).await?;
+    sock.connect(target).await?;
+
+    let mut summary = UdpThroughputSummary {
+        packet_size: UDP_PACKET_SIZE as u16,
+        ..UdpThroughputSummary::failed(&format!("lan://{}", peer), mbps, None)
+    };
+    let packet_bits = (UDP_PACKET_SIZE * 8) as f64;
+    let mut data = vec![0u8; UDP_PACKET_SIZE];
+    data[..8].copy_from_slice(&token.to_be_bytes());
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        let due = (start.elapsed().as_secs_f64() * mbps * 1e6 / packet_bits) as u64 + 1;
+        while summary.sent < due {
+            summary.sent += 1;
+            data[8..16].copy_from_slice(&summary.sent.to_be_bytes());
+            // A full send buffer drops the datagram, which then counts as lost
+            sock.send(&data).await.ok();
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    let sending = start.elapsed();
+    // Stragglers get as long as a loss probe waits for its answer
+    tokio::time::sleep(Duration::from_millis(cfg.udp_timeout_ms)).await;
+
+    control.get_mut().write_all(b"DONE\n").await?;
+    let counts = answer(&mut control).await?;
+    let counts: Vec<u64> = counts
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .context("unexpected UDP counts")?;
+    let [received, bytes, out_of_order] = counts[..] else {
+        anyhow::bail!("unexpected UDP counts");
+    };
+    summary.duration_ms = sending.as_millis() as u64;
+    summary.received = received;
+    summary.out_of_order = out_of_order;
+    if summary.sent > 0 {
+        summary.loss = summary.sent.saturating_sub(received) as f64 / summary.sent as f64;
+    }
+    summary.goodput_mbps = bytes as f64 * 8.0 / sending.as_secs_f64().max(1e-9) / 1e6;
+    Ok(summary)
+}
@@ -1,28 +1,80 @@
 use crate::engine::cloudflare::CloudflareClient;
+use crate::engine::scheduler::ProbeScheduler;
 use crate::engine::wait_if_paused_or_cancelled;
-use crate::model::{LatencySummary, Phase, TestEvent};
-use crate::stats::{latency_summary_from_samples, OnlineStats};
+use crate::model::{LatencySegments, LatencySummary, Phase, TestEvent};
+use crate::stats::{latency_segments, latency_summary_from_samples, OnlineStats};
 use anyhow::Result;
 use std::sync::{atomic::AtomicBool, Arc};
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
-pub async fn run_latency_probes(
+/// Requests made by `run_ttfb_probes`
+const TTFB_PROBES: u64 = 5;
+
+/// Time to first byte of a few one-byte downloads at the start of the run.
+pub async fn run_ttfb_probes(
     client: &CloudflareClient,
-    phase: Phase,
-    during: Option<Phase>,
-    total_duration: Duration,
     interval_ms: u64,
     timeout_ms: u64,
-    event_tx: &mpsc::Sender<TestEvent>,
+    scheduler: &ProbeScheduler,
     paused: Arc<AtomicBool>,
     cancel: Arc<AtomicBool>,
-) -> Result<LatencySummary> {
+) -> LatencySummary {
+    let mut sent = 0u64;
+    let mut samples = Vec::<f64>::new();
+    let mut online = OnlineStats::default();
+    for _ in 0..TTFB_PROBES {
+        if wait_if_paused_or_cancelled(&paused, &cancel).await {
+            break;
+        }
+        scheduler.acquire().await;
+        sent += 1;
+        if let Ok(ms) = client.probe_ttfb_ms(timeout_ms).await {
+            samples.push(ms);
+            online.push(ms);
+        }
+        tokio::time::sleep(Duration::from_millis(interval_ms)).await;
+    }
+    latency_summary_from_samples(sent, samples.len() as u64, &samples, online.stddev())
+}
+
+/// What `run_latency_probes` measures, for how long and how often
+pub struct ProbeOptions<'a> {
+    /// Phase the samples are reported under
+    pub phase: Phase,
+    /// Transfer phase the probes run alongside, if any
+    pub during: Option<Phase>,
+    pub duration: Duration,
+    pub interval_ms: u64,
+    pub timeout_ms: u64,
+    pub scheduler: &'a ProbeScheduler,
+    pub paused: Arc<AtomicBool>,
+    pub cancel: Arc<AtomicBool>,
+}
+
+/// Probe latency for `opts.duration`, returning the overall summary and the summaries
+/// of the early, middle and late thirds of the probe sequence.
+pub async fn run_latency_probes(
+    client: &CloudflareClient,
+    opts: ProbeOptions<'_>,
+    event_tx: &mpsc::Sender<TestEvent>,
+) -> Result<(LatencySummary, LatencySegments)> {
+    let ProbeOptions {
+        phase,
+        during,
+        duration: total_duration,
+        interval_ms,
+        timeout_ms,
+        scheduler,
+        paused,
+        cancel,
+    } = opts;
     let start = Instant::now();
     let mut sent = 0u64;
     let mut received = 0u64;
     let mut samples = Vec::<f64>::new();
     let mut online = OnlineStats::default();
+    let mut probes = Vec::<Option<f64>>::new();
     let mut meta_sent = false;
 
     while start.elapsed() < total_duration {
@@ -30,6 +82,7 @@ pub async fn run_latency_probes(
             break;
         }
 
+        scheduler.acquire().await;
         sent += 1;
         let during_str = during.and_then(|p| p.as_query_str());
 
@@ -39,6 +92,7 @@ pub async fn run_latency_probes(
                 received += 1;
                 samples.push(ms);
                 online.push(ms);
+                probes.push(Some(ms));
 
                 // Extract meta from first successful response
                 if !meta_sent && phase == Phase::IdleLatency {
@@ -59,6 +113,7 @@ pub async fn run_latency_probes(
                     .ok();
             }
             Err(_) => {
+                probes.push(None);
                 event_tx
                     .send(TestEvent::LatencySample {
                         phase,
@@ -74,10 +129,8 @@ pub async fn run_latency_probes(
         tokio::time::sleep(Duration::from_millis(interval_ms)).await;
     }
 
-    Ok(latency_summary_from_samples(
-        sent,
-        received,
-        &samples,
-        online.stddev(),
+    Ok((
+        latency_summary_from_samples(sent, received, &samples, online.stddev()),
+        latency_segments(&probes),
     ))
 }
@@ -1,24 +1,91 @@
-mod cloudflare;
+mod anchors;
+pub mod backend;
+pub mod builder;
+pub mod cloudflare;
 pub mod dns;
+mod energy;
+mod har;
 pub mod ip_comparison;
+pub mod lan;
 mod latency;
+pub mod modem;
+mod mtu;
 mod network_bind;
+mod ping;
+mod protocol_comparison;
+pub mod resolver;
+mod scheduler;
+mod soak;
+pub mod sockets;
+mod sweep;
+mod tcp_info;
 mod throughput;
+mod timing;
 pub mod tls;
 pub mod traceroute;
+mod traceroute_unprivileged;
+mod turn_relay;
 mod turn_udp;
+mod wireless;
 
 use crate::model::{
-    DnsSummary, IpVersionComparison, Phase, RunConfig, RunResult, TestEvent, TlsSummary,
+    Backend, Comparison, DnsSummary, IpVersionComparison, LatencySummary, MtuSummary, Phase,
+    PhaseEnergy, PhaseProtocol, RequestTiming, RunConfig, RunResult, TestEvent, TlsSummary,
     TracerouteSummary,
 };
 use anyhow::Result;
+use std::future::Future;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
 use std::time::Duration;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// How late a synchronized start may be before a warning is emitted
+const SYNC_START_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// How long a `--base-url` gets to answer before the next one is tried
+const ENDPOINT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The first of `base_url` and the fallbacks that answers a one-byte download, and the
+/// ones before it, which did not.
+async fn select_endpoint(
+    cfg: &RunConfig,
+    event_tx: &mpsc::Sender<TestEvent>,
+) -> Result<(String, Vec<String>)> {
+    let mut unreachable = Vec::new();
+    let mut errors = Vec::new();
+    for url in std::iter::once(&cfg.base_url).chain(&cfg.fallback_urls) {
+        let candidate = RunConfig {
+            base_url: url.clone(),
+            ..cfg.clone()
+        };
+        let answered = match cloudflare::CloudflareClient::new(&candidate) {
+            Ok(client) => {
+                client
+                    .probe_ttfb_ms(ENDPOINT_TIMEOUT.as_millis() as u64)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        match answered {
+            Ok(_) => return Ok((url.clone(), unreachable)),
+            Err(e) => {
+                event_tx
+                    .send(TestEvent::Info {
+                        message: format!("{} did not answer: {:#}", url, e),
+                    })
+                    .await
+                    .ok();
+                errors.push(format!("{} ({:#})", url, e));
+                unreachable.push(url.clone());
+            }
+        }
+    }
+    anyhow::bail!("none of the base URLs answered: {}", errors.join("; "))
+}
 
 /// Check if paused, wait while paused, and return true if cancelled.
 /// Returns true if the caller should break out of its loop.
@@ -29,6 +96,153 @@ pub(crate) async fn wait_if_paused_or_cancelled(paused: &AtomicBool, cancel: &At
     cancel.load(Ordering::Relaxed)
 }
 
+/// Await `fut`, giving up with an error once the run deadline (if any) has passed.
+/// Used to keep a hung phase from wedging the whole run when `--max-run-time` is set.
+pub(crate) async fn until_deadline<T>(
+    deadline: Option<Instant>,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match deadline {
+        Some(d) => tokio::time::timeout_at(d, fut)
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("max run time exceeded"))),
+        None => fut.await,
+    }
+}
+
+/// Tasks running alongside a test, aborted when dropped. Dropping a `JoinHandle` alone
+/// leaves its task running.
+struct BackgroundTasks(Vec<tokio::task::JoinHandle<()>>);
+
+impl Drop for BackgroundTasks {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
+/// Await `fut` unless `cancel` is set first; `None` when cancelled.
+async fn unless_cancelled<T>(cancel: &AtomicBool, fut: impl Future<Output = T>) -> Option<T> {
+    tokio::pin!(fut);
+    loop {
+        tokio::select! {
+            out = &mut fut => return Some(out),
+            _ = tokio::time::sleep(Duration::from_millis(100)) => {
+                if cancel.load(Ordering::Relaxed) {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Close out the HTTP version tally for `phase` and announce the phase's protocol details.
+async fn phase_protocol(
+    client: &cloudflare::CloudflareClient,
+    phase: Phase,
+    event_tx: &mpsc::Sender<TestEvent>,
+) -> PhaseProtocol {
+    let (tls_version, cipher_suite) = client.tls.last().unzip();
+    let protocol = PhaseProtocol {
+        phase,
+        http_versions: client.protocols.take(),
+        tls_version,
+        cipher_suite: cipher_suite.flatten(),
+        via_proxy: client.via_proxy,
+    };
+    event_tx
+        .send(TestEvent::Protocol {
+            protocol: protocol.clone(),
+        })
+        .await
+        .ok();
+    protocol
+}
+
+/// Time a fresh request at the start of `phase` (`measure_timing`) and announce it.
+async fn phase_timing(
+    cfg: &RunConfig,
+    phase: Phase,
+    event_tx: &mpsc::Sender<TestEvent>,
+) -> Option<RequestTiming> {
+    if !cfg.measure_timing {
+        return None;
+    }
+    match timing::measure_request_timing(cfg, phase).await {
+        Ok(timing) => {
+            event_tx
+                .send(TestEvent::DiagnosticTiming {
+                    timing: timing.clone(),
+                })
+                .await
+                .ok();
+            Some(timing)
+        }
+        Err(e) => {
+            event_tx
+                .send(TestEvent::Info {
+                    message: format!("{:?}: timing breakdown failed: {:#}", phase, e),
+                })
+                .await
+                .ok();
+            None
+        }
+    }
+}
+
+/// Sleep until `start_at`, or until the run is cancelled; say so when it already passed.
+async fn wait_for_sync_start(
+    start_at: std::time::SystemTime,
+    cancel: &AtomicBool,
+    event_tx: &mpsc::Sender<TestEvent>,
+) {
+    match start_at.duration_since(std::time::SystemTime::now()) {
+        Ok(wait) => {
+            event_tx
+                .send(TestEvent::Info {
+                    message: format!(
+                        "Waiting {:.1}s for synchronized start at {}",
+                        wait.as_secs_f64(),
+                        humantime::format_rfc3339_millis(start_at)
+                    ),
+                })
+                .await
+                .ok();
+            let start = Instant::now() + wait;
+            while Instant::now() < start && !cancel.load(Ordering::Relaxed) {
+                tokio::time::sleep_until(start.min(Instant::now() + Duration::from_millis(100)))
+                    .await;
+            }
+        }
+        Err(e) if e.duration() > SYNC_START_TOLERANCE => {
+            event_tx
+                .send(TestEvent::Info {
+                    message: format!(
+                        "Synchronized start time passed {:.1}s ago; starting now",
+                        e.duration().as_secs_f64()
+                    ),
+                })
+                .await
+                .ok();
+        }
+        Err(_) => {}
+    }
+}
+
+/// Energy spent since `start` for `phase`, when RAPL counters are readable.
+fn phase_energy(
+    meter: Option<&energy::EnergyMeter>,
+    phase: Phase,
+    start: Option<energy::EnergySample>,
+    bytes: u64,
+) -> Option<PhaseEnergy> {
+    let meter = meter?;
+    let end = meter.sample()?;
+    Some(meter.phase_energy(phase, &start?, &end, bytes))
+}
+
+/// Sent to a running `TestEngine` on its control channel
 #[derive(Debug, Clone)]
 pub enum EngineControl {
     /// Pause (true) or resume (false) the running test
@@ -37,6 +251,7 @@ pub enum EngineControl {
     Cancel,
 }
 
+/// One speed test run, configured up front and consumed by `run`.
 pub struct TestEngine {
     cfg: RunConfig,
 }
@@ -46,24 +261,136 @@ impl TestEngine {
         Self { cfg }
     }
 
+    /// Run the phases `cfg` selects, reporting progress on `event_tx` and obeying
+    /// `control_rx`, and return the result. Dropping the event receiver does not stop the
+    /// run; a cancelled run returns what it measured with `partial` set.
     pub async fn run(
-        self,
+        mut self,
         event_tx: mpsc::Sender<TestEvent>,
         mut control_rx: mpsc::Receiver<EngineControl>,
     ) -> Result<RunResult> {
-        let client = cloudflare::CloudflareClient::new(&self.cfg)?;
+        tracing::debug!(
+            "Starting run {} against {} ({} connections)",
+            self.cfg.meas_id,
+            self.cfg.base_url,
+            self.cfg.concurrency
+        );
+
+        // The LAN test talks to another instance of this binary, not over HTTP
+        if self.cfg.backend == Backend::Lan {
+            return lan::run(&self.cfg, event_tx, control_rx).await;
+        }
+
+        // With fallbacks, settle on a base URL that answers before anything else uses one
+        let mut unreachable_endpoints = Vec::new();
+        if !self.cfg.fallback_urls.is_empty() {
+            let (base_url, unreachable) = select_endpoint(&self.cfg, &event_tx).await?;
+            if !unreachable.is_empty() {
+                event_tx
+                    .send(TestEvent::Info {
+                        message: format!("Testing against {}", base_url),
+                    })
+                    .await
+                    .ok();
+            }
+            self.cfg.base_url = base_url;
+            unreachable_endpoints = unreachable;
+        }
+        let mut client = cloudflare::CloudflareClient::new(&self.cfg)?;
+        client.har = self
+            .cfg
+            .export_har
+            .as_ref()
+            .map(|_| Arc::new(har::HarRecorder::default()));
 
         let paused = Arc::new(AtomicBool::new(false));
         let cancel = Arc::new(AtomicBool::new(false));
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let scheduler = Arc::new(scheduler::ProbeScheduler::default());
+        let budget = Arc::new(throughput::DataBudget::new(self.cfg.max_total_bytes));
+
+        let paused2 = paused.clone();
+        let cancel2 = cancel.clone();
+        let control_handle = tokio::spawn(async move {
+            while let Some(msg) = control_rx.recv().await {
+                match msg {
+                    EngineControl::Pause(p) => paused2.store(p, Ordering::Relaxed),
+                    EngineControl::Cancel => {
+                        cancel2.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                }
+            }
+        });
+
+        // Watchdog: cancel the run once --max-run-time elapses. Phases notice the cancel
+        // flag and wind down; anything that could block is bounded by `deadline`.
+        let deadline = self.cfg.max_run_time.map(|limit| Instant::now() + limit);
+        let watchdog_handle = deadline.map(|d| {
+            let cancel2 = cancel.clone();
+            let timed_out2 = timed_out.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep_until(d).await;
+                timed_out2.store(true, Ordering::Relaxed);
+                cancel2.store(true, Ordering::Relaxed);
+            })
+        });
+
+        // Aborted however the run ends, also by an early `bail!` or `?`
+        let background = BackgroundTasks(
+            std::iter::once(control_handle)
+                .chain(watchdog_handle)
+                .collect(),
+        );
+
+        // A captive portal answers the test requests with its login page, which would be
+        // measured as if it were the test traffic. Checked once cancelling and the
+        // watchdog are in place, so neither waits for its timeout.
+        let mut captive_portal = false;
+        if let Some(url) = self.cfg.captive_portal_url.as_deref() {
+            let check = until_deadline(deadline, cloudflare::detect_captive_portal(&self.cfg, url));
+            match unless_cancelled(&cancel, check).await {
+                // Cancelled: the phases notice and end the run
+                None | Some(Ok(None)) => {}
+                Some(Ok(Some(seen))) if self.cfg.allow_captive_portal => {
+                    captive_portal = true;
+                    event_tx
+                        .send(TestEvent::Info {
+                            message: format!(
+                                "Captive portal detected ({} {}); results will not be meaningful",
+                                url, seen
+                            ),
+                        })
+                        .await
+                        .ok();
+                }
+                Some(Ok(Some(seen))) => anyhow::bail!(
+                    "captive portal or intercepting proxy detected: {} {}. Log in to the \
+                     network first, or pass --allow-captive-portal to test anyway",
+                    url,
+                    seen
+                ),
+                Some(Err(e)) => tracing::debug!("Captive portal check inconclusive: {:#}", e),
+            }
+        }
+
+        // Follow Wi-Fi roams/channel switches so dips can be attributed to them
+        let wireless_monitor = wireless::WirelessMonitor::start(
+            self.cfg.interface.as_deref(),
+            &event_tx,
+            Instant::now(),
+        )
+        .await;
 
         // Try to get meta from multiple sources in order of preference:
         // 1. /meta endpoint (may have full details)
         // 2. /cdn-cgi/trace endpoint (reliable source for colo, ip, country)
         // 3. Response headers (fallback)
-        let mut meta: Option<serde_json::Value> = match cloudflare::fetch_meta(&client).await {
-            Ok(v) if !v.as_object().map(|m| m.is_empty()).unwrap_or(true) => Some(v),
-            _ => None,
-        };
+        let mut meta: Option<serde_json::Value> =
+            match until_deadline(deadline, cloudflare::fetch_meta(&client)).await {
+                Ok(v) if !v.as_object().map(|m| m.is_empty()).unwrap_or(true) => Some(v),
+                _ => None,
+            };
 
         // If meta is empty or missing colo, try /cdn-cgi/trace
         let has_colo = meta
@@ -73,7 +400,8 @@ impl TestEngine {
             .is_some();
 
         if !has_colo {
-            if let Ok(trace_meta) = cloudflare::fetch_trace(&client).await {
+            if let Ok(trace_meta) = until_deadline(deadline, cloudflare::fetch_trace(&client)).await
+            {
                 if !trace_meta.as_object().map(|m| m.is_empty()).unwrap_or(true) {
                     // Merge trace_meta into meta
                     if let Some(ref mut existing) = meta {
@@ -95,10 +423,15 @@ impl TestEngine {
 
         // Final fallback to response headers
         if meta.is_none() {
-            meta = cloudflare::fetch_meta_from_response(&client).await.ok();
+            meta = until_deadline(deadline, cloudflare::fetch_meta_from_response(&client))
+                .await
+                .ok();
         }
 
-        let locations = cloudflare::fetch_locations(&client).await.ok();
+        let locations = until_deadline(deadline, cloudflare::fetch_locations(&client))
+            .await
+            .ok()
+            .flatten();
         let server = meta
             .as_ref()
             .and_then(|m: &serde_json::Value| {
@@ -110,6 +443,35 @@ impl TestEngine {
                     .and_then(|loc| cloudflare::map_colo_to_server(loc, colo))
             });
 
+        // `cf.colo` is only a request; make sure it was the asked-for colo that answered
+        if let Some(requested) = self.cfg.colo.as_deref() {
+            let served = meta
+                .as_ref()
+                .and_then(|m| m.get("colo"))
+                .and_then(|v| v.as_str());
+            match served {
+                Some(served) if served.eq_ignore_ascii_case(requested) => {
+                    event_tx
+                        .send(TestEvent::Info {
+                            message: format!("Testing against colo {}", requested),
+                        })
+                        .await
+                        .ok();
+                }
+                Some(served) => anyhow::bail!(
+                    "requested colo {} but {} answered: the cf.colo override was not honored \
+                     (the colo may not exist or may not serve this network)",
+                    requested,
+                    served
+                ),
+                None => anyhow::bail!(
+                    "requested colo {} but could not tell which colo answered, so the override \
+                     cannot be confirmed",
+                    requested
+                ),
+            }
+        }
+
         // Send meta info early so TUI can display server/colo/ip immediately
         if let Some(ref m) = meta {
             event_tx
@@ -118,26 +480,28 @@ impl TestEngine {
                 .ok();
         }
 
-        // Control listener.
-        let paused2 = paused.clone();
-        let cancel2 = cancel.clone();
-        let control_handle = tokio::spawn(async move {
-            while let Some(msg) = control_rx.recv().await {
-                match msg {
-                    EngineControl::Pause(p) => paused2.store(p, Ordering::Relaxed),
-                    EngineControl::Cancel => {
-                        cancel2.store(true, Ordering::Relaxed);
-                        break;
-                    }
+        let mut results = builder::RunResultBuilder::new(&self.cfg);
+
+        // Like curl's, `--resolve` entries only apply to the host and port they name
+        if !self.cfg.resolve.is_empty() {
+            let message = match cloudflare::pinned_endpoint(&self.cfg) {
+                Some((host, addr)) => format!("Pinned {} to {}", host, addr.ip()),
+                None => {
+                    "No --resolve entry matches the base URL's host and port; resolving it normally"
+                        .to_string()
                 }
-            }
-        });
+            };
+            event_tx.send(TestEvent::Info { message }).await.ok();
+        }
 
+        // Control listener.
         // Run diagnostic tests before the main speed test
         let mut dns_summary: Option<DnsSummary> = None;
         let mut tls_summary: Option<TlsSummary> = None;
         let mut ip_comparison_result: Option<IpVersionComparison> = None;
+        let mut comparisons: Vec<Comparison> = Vec::new();
         let mut traceroute_summary: Option<TracerouteSummary> = None;
+        let mut mtu_summary: Option<MtuSummary> = None;
         let mut external_ipv4: Option<String> = None;
         let mut external_ipv6: Option<String> = None;
 
@@ -151,7 +515,12 @@ impl TestEngine {
                     .await
                     .ok();
 
-                match dns::measure_dns_resolution(&hostname).await {
+                let measure = async {
+                    let resolver = resolver::DnsResolver::from_config(&self.cfg)?;
+                    dns::measure_dns_resolution(&hostname, resolver.as_ref(), self.cfg.check_dnssec)
+                        .await
+                };
+                match until_deadline(deadline, measure).await {
                     Ok(summary) => {
                         event_tx
                             .send(TestEvent::DiagnosticDns {
@@ -183,7 +552,12 @@ impl TestEngine {
                     .await
                     .ok();
 
-                match tls::measure_tls_handshake(&hostname, port).await {
+                match until_deadline(
+                    deadline,
+                    tls::measure_tls_handshake(&self.cfg, &hostname, port),
+                )
+                .await
+                {
                     Ok(summary) => {
                         event_tx
                             .send(TestEvent::DiagnosticTls {
@@ -206,8 +580,8 @@ impl TestEngine {
         }
 
         // Fetch external IPs (runs in parallel, part of default diagnostics)
-        if self.cfg.measure_dns {
-            let (v4, v6) = dns::fetch_external_ips(&self.cfg.base_url).await;
+        if self.cfg.measure_dns && !cancel.load(Ordering::Relaxed) {
+            let (v4, v6) = dns::fetch_external_ips(&self.cfg).await;
             external_ipv4 = v4.clone();
             external_ipv6 = v6.clone();
             event_tx
@@ -218,6 +592,12 @@ impl TestEngine {
 
         // IPv4 vs IPv6 comparison
         if self.cfg.compare_ip_versions {
+            event_tx
+                .send(TestEvent::PhaseStarted {
+                    phase: Phase::IpComparison,
+                })
+                .await
+                .ok();
             event_tx
                 .send(TestEvent::Info {
                     message: "Comparing IPv4 vs IPv6 performance...".to_string(),
@@ -225,15 +605,27 @@ impl TestEngine {
                 .await
                 .ok();
 
-            match ip_comparison::compare_ip_versions(&self.cfg.base_url, &self.cfg.user_agent).await
+            match until_deadline(
+                deadline,
+                ip_comparison::compare_ip_versions(
+                    &self.cfg,
+                    &event_tx,
+                    scheduler.clone(),
+                    budget.clone(),
+                    paused.clone(),
+                    cancel.clone(),
+                ),
+            )
+            .await
             {
                 Ok(comparison) => {
                     event_tx
                         .send(TestEvent::DiagnosticIpComparison {
-                            comparison: comparison.clone(),
+                            comparison: Box::new(comparison.clone()),
                         })
                         .await
                         .ok();
+                    comparisons.push(crate::comparison::from_ip_versions(&comparison));
                     ip_comparison_result = Some(comparison);
                 }
                 Err(e) => {
@@ -260,8 +652,11 @@ impl TestEngine {
                     .await
                     .ok();
 
-                match traceroute::run_traceroute(&hostname, self.cfg.traceroute_max_hops, &event_tx)
-                    .await
+                match until_deadline(
+                    deadline,
+                    traceroute::run_traceroute(&hostname, &self.cfg, &event_tx),
+                )
+                .await
                 {
                     Ok(summary) => {
                         event_tx
@@ -284,96 +679,549 @@ impl TestEngine {
             }
         }
 
-        event_tx
-            .send(TestEvent::PhaseStarted {
-                phase: Phase::IdleLatency,
-            })
-            .await
-            .ok();
+        // Path MTU
+        if self.cfg.measure_mtu && !cancel.load(Ordering::Relaxed) {
+            event_tx
+                .send(TestEvent::Info {
+                    message: "Discovering the path MTU...".to_string(),
+                })
+                .await
+                .ok();
 
-        let idle_latency = latency::run_latency_probes(
-            &client,
-            Phase::IdleLatency,
-            None,
-            self.cfg.idle_latency_duration,
-            self.cfg.probe_interval_ms,
-            self.cfg.probe_timeout_ms,
-            &event_tx,
-            paused.clone(),
-            cancel.clone(),
-        )
-        .await?;
+            match until_deadline(deadline, mtu::discover(&self.cfg)).await {
+                Ok(summary) => {
+                    event_tx
+                        .send(TestEvent::DiagnosticMtu {
+                            summary: summary.clone(),
+                        })
+                        .await
+                        .ok();
+                    mtu_summary = Some(summary);
+                }
+                Err(e) => {
+                    event_tx
+                        .send(TestEvent::Info {
+                            message: format!("Path MTU discovery failed: {:#}", e),
+                        })
+                        .await
+                        .ok();
+                }
+            }
+        }
 
-        event_tx
-            .send(TestEvent::PhaseStarted {
-                phase: Phase::Download,
-            })
-            .await
-            .ok();
+        let mut phases = self.cfg.phases;
+        // UDP cannot go through a SOCKS proxy, so the probe would bypass it
+        if phases.packet_loss
+            && self
+                .cfg
+                .proxy
+                .as_deref()
+                .is_some_and(crate::network::is_socks_proxy)
+        {
+            phases.packet_loss = false;
+            let reason = "UDP does not go through the SOCKS proxy".to_string();
+            event_tx
+                .send(TestEvent::Info {
+                    message: format!("Packet loss probe skipped: {}", reason),
+                })
+                .await
+                .ok();
+            // Nothing was measured, so the snapshot is not published
+            results.record(Phase::PacketLoss, |r| r.udp_skipped = Some(reason));
+        }
+        // Synchronized start: wait for the agreed instant so probes on other machines
+        // load the network at the same time. Diagnostics take a different time on each
+        // probe, so the wait comes after them, right before the first measured phase.
+        if let Some(start_at) = self.cfg.sync_start {
+            wait_for_sync_start(start_at, &cancel, &event_tx).await;
+        }
 
-        let (download, loaded_latency_download) = throughput::run_download_with_loaded_latency(
-            &client,
-            &self.cfg,
-            &event_tx,
-            paused.clone(),
-            cancel.clone(),
-        )
-        .await?;
+        // Skipped phases keep their "not measured" defaults, which serialize as null
+        let mut ttfb = None;
+        let (idle_latency, anchor_latencies, icmp_latency, idle_timing) = if phases.idle_latency {
+            event_tx
+                .send(TestEvent::PhaseStarted {
+                    phase: Phase::IdleLatency,
+                })
+                .await
+                .ok();
 
-        event_tx
-            .send(TestEvent::PhaseStarted {
-                phase: Phase::Upload,
-            })
-            .await
-            .ok();
+            let summary = latency::run_ttfb_probes(
+                &client,
+                self.cfg.probe_interval_ms,
+                self.cfg.probe_timeout_ms,
+                &scheduler,
+                paused.clone(),
+                cancel.clone(),
+            )
+            .await;
+            if let Some(median) = summary.median_ms {
+                event_tx
+                    .send(TestEvent::Info {
+                        message: format!(
+                            "TTFB: {:.1} ms median over {} request(s)",
+                            median, summary.received
+                        ),
+                    })
+                    .await
+                    .ok();
+            }
+            ttfb = Some(summary);
 
-        // Prefetch DNS for STUN server during upload to eliminate delay before packet loss phase
-        let stun_dns_handle = tokio::spawn(async move {
-            tokio::net::lookup_host(("turn.cloudflare.com", 3478_u16))
-                .await
-                .ok()
-                .and_then(|mut addrs| addrs.next())
+            // Reference anchors and the ICMP ping run alongside the idle latency phase
+            let (idle_latency, anchor_latencies, icmp_latency, idle_timing) = tokio::join!(
+                latency::run_latency_probes(
+                    &client,
+                    latency::ProbeOptions {
+                        phase: Phase::IdleLatency,
+                        during: None,
+                        duration: self.cfg.idle_latency_duration,
+                        interval_ms: self.cfg.probe_interval_ms,
+                        timeout_ms: self.cfg.probe_timeout_ms,
+                        scheduler: &scheduler,
+                        paused: paused.clone(),
+                        cancel: cancel.clone(),
+                    },
+                    &event_tx,
+                ),
+                anchors::run_anchor_probes(
+                    &self.cfg.anchors,
+                    self.cfg.idle_latency_duration,
+                    self.cfg.probe_interval_ms,
+                    self.cfg.probe_timeout_ms,
+                    &scheduler,
+                    paused.clone(),
+                    cancel.clone(),
+                ),
+                async {
+                    if !self.cfg.icmp_ping {
+                        return None;
+                    }
+                    let icmp = ping::run_icmp_probes(
+                        &self.cfg,
+                        self.cfg.idle_latency_duration,
+                        self.cfg.probe_interval_ms,
+                        self.cfg.probe_timeout_ms,
+                        &scheduler,
+                        paused.clone(),
+                        cancel.clone(),
+                    )
+                    .await;
+                    Some(icmp)
+                },
+                phase_timing(&self.cfg, Phase::IdleLatency, &event_tx)
+            );
+            (idle_latency?.0, anchor_latencies, icmp_latency, idle_timing)
+        } else {
+            (LatencySummary::skipped(), Vec::new(), None, None)
+        };
+
+        if let Some(icmp) = &icmp_latency {
+            let message = match (&icmp.error, icmp.latency.median_ms, idle_latency.median_ms) {
+                (Some(e), _, _) => format!("ICMP ping failed: {}", e),
+                (None, Some(ms), Some(http)) => format!(
+                    "ICMP ping {}: {:.1} ms median (loss {:.1}%), HTTP {:.1} ms",
+                    icmp.target,
+                    ms,
+                    icmp.latency.loss * 100.0,
+                    http
+                ),
+                (None, Some(ms), None) => format!(
+                    "ICMP ping {}: {:.1} ms median (loss {:.1}%)",
+                    icmp.target,
+                    ms,
+                    icmp.latency.loss * 100.0
+                ),
+                (None, None, _) => format!("ICMP ping {}: no response", icmp.target),
+            };
+            event_tx.send(TestEvent::Info { message }).await.ok();
+        }
+
+        for anchor in &anchor_latencies {
+            let message = match (&anchor.error, anchor.latency.median_ms) {
+                (Some(e), _) => format!("Anchor {}: {}", anchor.target, e),
+                (None, Some(ms)) => format!(
+                    "Anchor {}: {:.1} ms median (loss {:.1}%)",
+                    anchor.target,
+                    ms,
+                    anchor.latency.loss * 100.0
+                ),
+                (None, None) => format!("Anchor {}: no response", anchor.target),
+            };
+            event_tx.send(TestEvent::Info { message }).await.ok();
+        }
+
+        let idle_protocol = if phases.idle_latency {
+            Some(phase_protocol(&client, Phase::IdleLatency, &event_tx).await)
+        } else {
+            None
+        };
+        let snapshot = results.record(Phase::IdleLatency, |r| {
+            r.meta = meta;
+            r.captive_portal = captive_portal;
+            r.unreachable_endpoints = unreachable_endpoints;
+            r.server = server;
+            r.external_ipv4 = external_ipv4;
+            r.external_ipv6 = external_ipv6;
+            r.dns = dns_summary;
+            r.tls = tls_summary;
+            if let Some(timing) = idle_timing {
+                r.timing_breakdown
+                    .get_or_insert_with(Default::default)
+                    .requests
+                    .push(timing);
+            }
+            r.ip_comparison = ip_comparison_result;
+            r.traceroute = traceroute_summary;
+            r.mtu = mtu_summary;
+            r.comparisons = comparisons;
+            r.idle_latency = idle_latency;
+            r.ttfb = ttfb;
+            r.anchors = anchor_latencies;
+            r.icmp_latency = icmp_latency;
+            r.protocols.extend(idle_protocol);
         });
+        event_tx.send(snapshot).await.ok();
 
-        let (upload, loaded_latency_upload) = throughput::run_upload_with_loaded_latency(
-            &client,
-            &self.cfg,
-            &event_tx,
-            paused,
-            cancel.clone(),
-        )
-        .await?;
+        let energy_meter = if phases.download || phases.upload {
+            energy::EnergyMeter::detect()
+        } else {
+            None
+        };
 
-        event_tx
-            .send(TestEvent::PhaseStarted {
-                phase: Phase::PacketLoss,
+        if phases.download {
+            event_tx
+                .send(TestEvent::PhaseStarted {
+                    phase: Phase::Download,
+                })
+                .await
+                .ok();
+
+            let dl_energy_start = energy_meter.as_ref().and_then(|m| m.sample());
+            // The timed request runs alongside the phase's first requests
+            let (phase_result, download_timing) = tokio::join!(
+                throughput::run_download_with_loaded_latency(
+                    &client,
+                    &self.cfg,
+                    &event_tx,
+                    scheduler.clone(),
+                    budget.clone(),
+                    paused.clone(),
+                    cancel.clone(),
+                ),
+                phase_timing(&self.cfg, Phase::Download, &event_tx)
+            );
+            let (download, loaded_latency_download, download_segments) = phase_result?;
+
+            let download_protocol = phase_protocol(&client, Phase::Download, &event_tx).await;
+            let download_energy = phase_energy(
+                energy_meter.as_ref(),
+                Phase::Download,
+                dl_energy_start,
+                download.bytes,
+            );
+            let snapshot = results.record(Phase::Download, |r| {
+                r.protocols.push(download_protocol);
+                if let Some(timing) = download_timing {
+                    r.timing_breakdown
+                        .get_or_insert_with(Default::default)
+                        .requests
+                        .push(timing);
+                }
+                r.energy.extend(download_energy);
+                r.download = download;
+                r.loaded_latency_download = loaded_latency_download;
+                r.loaded_latency_download_segments = Some(download_segments);
+                r.bufferbloat = crate::stats::bufferbloat(
+                    &r.idle_latency,
+                    &r.loaded_latency_download,
+                    &r.loaded_latency_upload,
+                );
+            });
+            event_tx.send(snapshot).await.ok();
+        }
+
+        // Fetch the relay credentials and resolve the STUN server during upload to
+        // eliminate delay before the packet loss phase
+        let stun_dns_handle = phases.packet_loss.then(|| {
+            let cfg = self.cfg.clone();
+            let client = client.clone();
+            tokio::spawn(async move {
+                let info = match cloudflare::fetch_turn(&client).await {
+                    Ok(Some(info)) => Some(info),
+                    Ok(None) => {
+                        tracing::debug!("No /__turn on the endpoint; using the public STUN server");
+                        None
+                    }
+                    Err(e) => {
+                        tracing::debug!("Fetching /__turn failed: {:#}", e);
+                        None
+                    }
+                };
+                let info = info
+                    .filter(|info| !info.urls.is_empty())
+                    .unwrap_or_else(|| crate::model::TurnInfo {
+                        urls: vec!["stun:turn.cloudflare.com:3478".to_string()],
+                        username: None,
+                        credential: None,
+                    });
+                let addr = match info.urls.iter().find(|u| u.starts_with("stun:")) {
+                    Some(url) => turn_udp::resolve_target(url, &cfg).await.ok(),
+                    None => None,
+                };
+                (info, addr)
             })
-            .await
-            .ok();
+        });
 
-        let mut experimental_udp = None;
-        let mut udp_error = None;
+        if phases.upload {
+            event_tx
+                .send(TestEvent::PhaseStarted {
+                    phase: Phase::Upload,
+                })
+                .await
+                .ok();
 
-        let info = crate::model::TurnInfo {
-            urls: vec!["stun:turn.cloudflare.com:3478".to_string()],
-            username: None,
-            credential: None,
-        };
+            let ul_energy_start = energy_meter.as_ref().and_then(|m| m.sample());
+            // The timed request runs alongside the phase's first requests
+            let (phase_result, upload_timing) = tokio::join!(
+                throughput::run_upload_with_loaded_latency(
+                    &client,
+                    &self.cfg,
+                    &event_tx,
+                    scheduler.clone(),
+                    budget.clone(),
+                    paused.clone(),
+                    cancel.clone(),
+                ),
+                phase_timing(&self.cfg, Phase::Upload, &event_tx)
+            );
+            let (upload, loaded_latency_upload, upload_segments) = phase_result?;
 
-        // Use prefetched DNS if available
-        let pre_resolved = stun_dns_handle.await.ok().flatten();
+            let upload_protocol = phase_protocol(&client, Phase::Upload, &event_tx).await;
+            let upload_energy = phase_energy(
+                energy_meter.as_ref(),
+                Phase::Upload,
+                ul_energy_start,
+                upload.bytes,
+            );
+            let snapshot = results.record(Phase::Upload, |r| {
+                r.protocols.push(upload_protocol);
+                if let Some(timing) = upload_timing {
+                    r.timing_breakdown
+                        .get_or_insert_with(Default::default)
+                        .requests
+                        .push(timing);
+                }
+                r.energy.extend(upload_energy);
+                r.upload = upload;
+                r.loaded_latency_upload = loaded_latency_upload;
+                r.loaded_latency_upload_segments = Some(upload_segments);
+                r.bufferbloat = crate::stats::bufferbloat(
+                    &r.idle_latency,
+                    &r.loaded_latency_download,
+                    &r.loaded_latency_upload,
+                );
+            });
+            event_tx.send(snapshot).await.ok();
+        }
+
+        if self.cfg.ramped && (phases.download || phases.upload) {
+            event_tx
+                .send(TestEvent::PhaseStarted {
+                    phase: Phase::Ramped,
+                })
+                .await
+                .ok();
+            let measurements = throughput::run_ramped(
+                &client,
+                &self.cfg,
+                &event_tx,
+                budget.clone(),
+                paused.clone(),
+                cancel.clone(),
+            )
+            .await;
+            let snapshot = results.record(Phase::Ramped, |r| r.measurements = measurements);
+            event_tx.send(snapshot).await.ok();
+        }
 
-        match turn_udp::run_udp_like_loss_probe(&info, &self.cfg, &event_tx, pre_resolved).await {
-            Ok(udp) => {
-                experimental_udp = Some(udp);
+        if !self.cfg.sweep_concurrency.is_empty() && phases.download {
+            event_tx
+                .send(TestEvent::PhaseStarted {
+                    phase: Phase::ConcurrencySweep,
+                })
+                .await
+                .ok();
+            let sweep = sweep::run_concurrency_sweep(
+                &client,
+                &self.cfg,
+                &event_tx,
+                scheduler.clone(),
+                budget.clone(),
+                paused.clone(),
+                cancel.clone(),
+            )
+            .await;
+            let snapshot = results.record(Phase::ConcurrencySweep, |r| {
+                r.concurrency_sweep = Some(sweep)
+            });
+            event_tx.send(snapshot).await.ok();
+        }
+
+        if self.cfg.compare_protocols && phases.download {
+            event_tx
+                .send(TestEvent::PhaseStarted {
+                    phase: Phase::ProtocolComparison,
+                })
+                .await
+                .ok();
+            let protocols = protocol_comparison::run_protocol_comparison(
+                &self.cfg,
+                client.har.as_ref(),
+                &event_tx,
+                budget.clone(),
+                paused.clone(),
+                cancel.clone(),
+            )
+            .await;
+            let snapshot = results.record(Phase::ProtocolComparison, |r| {
+                r.comparisons
+                    .extend(crate::comparison::from_protocols(&protocols));
+                r.protocol_comparison = protocols;
+            });
+            event_tx.send(snapshot).await.ok();
+        }
+
+        if let Some(stun_dns_handle) = stun_dns_handle {
+            event_tx
+                .send(TestEvent::PhaseStarted {
+                    phase: Phase::PacketLoss,
+                })
+                .await
+                .ok();
+
+            let (info, pre_resolved) = stun_dns_handle.await.unwrap_or_else(|_| {
+                let info = crate::model::TurnInfo {
+                    urls: vec!["stun:turn.cloudflare.com:3478".to_string()],
+                    username: None,
+                    credential: None,
+                };
+                (info, None)
+            });
+
+            match until_deadline(
+                deadline,
+                turn_udp::run_udp_like_loss_probe(
+                    &info,
+                    &self.cfg,
+                    &event_tx,
+                    &scheduler,
+                    pre_resolved,
+                ),
+            )
+            .await
+            {
+                Ok(mut udp) => {
+                    let relay_url =
+                        turn_relay::pick_relay_url(&info).filter(|_| udp.latency.sent > 0);
+                    if let Some(url) = relay_url {
+                        event_tx
+                            .send(TestEvent::Info {
+                                message: format!(
+                                    "Measuring packet loss through the TURN relay {url}"
+                                ),
+                            })
+                            .await
+                            .ok();
+                        match until_deadline(
+                            deadline,
+                            turn_relay::run_relay_probe(&info, url, &self.cfg, &scheduler),
+                        )
+                        .await
+                        {
+                            Ok(relay) => udp.relay = Some(relay),
+                            Err(e) => udp.relay_error = Some(format!("{e:#}")),
+                        }
+                    }
+                    let snapshot = results.record(Phase::PacketLoss, |r| {
+                        // The credential is short-lived, but still not worth storing
+                        r.turn = Some(crate::model::TurnInfo {
+                            credential: None,
+                            ..info.clone()
+                        });
+                        r.experimental_udp = Some(udp);
+                    });
+                    event_tx.send(snapshot).await.ok();
+                }
+                Err(e) => {
+                    let msg = format!("UDP probe failed: {e:#}");
+                    let snapshot = results.record(Phase::PacketLoss, |r| {
+                        r.udp_error = Some(msg.clone());
+                    });
+                    event_tx.send(snapshot).await.ok();
+                    event_tx.send(TestEvent::Info { message: msg }).await.ok();
+                }
             }
-            Err(e) => {
-                let msg = format!("UDP probe failed: {e:#}");
-                udp_error = Some(msg.clone());
+
+            if let Some(duration) = self.cfg.udp_throughput {
+                match turn_relay::pick_relay_url(&info) {
+                    Some(url) if !cancel.load(Ordering::Relaxed) => {
+                        event_tx
+                            .send(TestEvent::PhaseStarted {
+                                phase: Phase::UdpThroughput,
+                            })
+                            .await
+                            .ok();
+                        let mbps = self.cfg.udp_throughput_mbps;
+                        let summary = until_deadline(
+                            deadline,
+                            turn_relay::run_relay_throughput(&info, url, &self.cfg, duration, mbps),
+                        )
+                        .await
+                        .unwrap_or_else(|e| {
+                            crate::model::UdpThroughputSummary::failed(
+                                url,
+                                mbps,
+                                Some(format!("{e:#}")),
+                            )
+                        });
+                        let snapshot = results
+                            .record(Phase::UdpThroughput, |r| r.udp_throughput = Some(summary));
+                        event_tx.send(snapshot).await.ok();
+                    }
+                    Some(_) => {}
+                    None => {
+                        event_tx
+                            .send(TestEvent::Info {
+                                message: "UDP throughput skipped: /__turn offered no TURN relay credentials"
+                                    .to_string(),
+                            })
+                            .await
+                            .ok();
+                    }
+                }
+            }
+        }
+
+        // The soak phase extends a throughput test; latency and loss-only runs skip it
+        if let Some(soak_duration) = self.cfg.soak.filter(|_| phases.download || phases.upload) {
+            if !cancel.load(Ordering::Relaxed) {
                 event_tx
-                    .send(TestEvent::Info { message: msg })
+                    .send(TestEvent::PhaseStarted { phase: Phase::Soak })
                     .await
                     .ok();
+                let report = soak::run_soak(
+                    &client,
+                    &self.cfg,
+                    soak_duration,
+                    &event_tx,
+                    scheduler.clone(),
+                    paused.clone(),
+                    cancel.clone(),
+                )
+                .await;
+                let snapshot = results.record(Phase::Soak, |r| r.soak = Some(report));
+                event_tx.send(snapshot).await.ok();
             }
         }
 
@@ -388,45 +1236,73 @@ impl TestEngine {
         // In Tokio, dropping a JoinHandle does NOT cancel the task - it continues running!
         // This was causing high CPU usage when idle because the task was still waiting
         // on control_rx.recv().await even after the test completed.
-        control_handle.abort();
-        // Don't await the aborted task - just let it be cleaned up
-
-        Ok(RunResult {
-            version: Some(env!("CARGO_PKG_VERSION").to_string()),
-            timestamp_utc: time::OffsetDateTime::now_utc()
-                .format(&time::format_description::well_known::Rfc3339)
-                .unwrap_or_else(|_| "now".into()),
-            base_url: self.cfg.base_url.clone(),
-            meas_id: self.cfg.meas_id.clone(),
-            comments: self.cfg.comments.clone(),
-            meta,
-            server,
-            idle_latency,
-            download,
-            upload,
-            loaded_latency_download,
-            loaded_latency_upload,
-            turn: None,
-            experimental_udp,
-            udp_error,
-            // Network information - will be populated by TUI when available
-            ip: None,
-            colo: None,
-            asn: None,
-            as_org: None,
-            interface_name: None,
-            network_name: None,
-            is_wireless: None,
-            interface_mac: None,
-            local_ipv4: None,
-            local_ipv6: None,
-            external_ipv4,
-            external_ipv6,
-            // Diagnostic results
-            dns: dns_summary,
-            tls: tls_summary,
-            ip_comparison: ip_comparison_result,
-            traceroute: traceroute_summary,
-        })
+        drop(background);
+
+        let wireless_events = wireless_monitor
+            .map(wireless::WirelessMonitor::stop)
+            .unwrap_or_default();
+
+        // Cancelled by the user or the --max-run-time watchdog
+        let partial = cancel.load(Ordering::Relaxed);
+        if partial {
+            let message = if timed_out.load(Ordering::Relaxed) {
+                "Max run time exceeded; returning partial results"
+            } else {
+                "Cancelled; returning partial results"
+            };
+            event_tx
+                .send(TestEvent::Info {
+                    message: message.to_string(),
+                })
+                .await
+                .ok();
+        }
+
+        if let (Some(path), Some(har)) = (&self.cfg.export_har, &client.har) {
+            let message = match har.write(path) {
+                Ok(()) => format!("Wrote {} request(s) to {}", har.count(), path.display()),
+                Err(e) => format!("HAR export failed: {e:#}"),
+            };
+            event_tx.send(TestEvent::Info { message }).await.ok();
+        }
+
+        let mut result = results.finish();
+        result.partial = partial;
+        result.wireless_events = wireless_events;
+        result.aim = crate::aim::scores(&result);
+
+        if let Some(target) = &self.cfg.modem {
+            let stats = modem::scrape(target, &self.cfg.modem_metrics).await;
+            for e in &stats.errors {
+                event_tx
+                    .send(TestEvent::Info {
+                        message: format!("Modem stats: {}", e),
+                    })
+                    .await
+                    .ok();
+            }
+            result.modem = Some(stats);
+        }
+
+        // External context: hook output first, explicit --context pairs take precedence
+        if let Some(hook) = &self.cfg.context_hook {
+            match crate::context::run_hook(hook, &result).await {
+                Ok(extra) => {
+                    for (k, v) in extra {
+                        result.context.entry(k).or_insert(v);
+                    }
+                }
+                Err(e) => {
+                    event_tx
+                        .send(TestEvent::Info {
+                            message: format!("Context hook failed: {e:#}"),
+                        })
+                        .await
+                        .ok();
+                }
+            }
+        }
+
+        Ok(result)
     }
 }
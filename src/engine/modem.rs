@@ -0,0 +1,217 @@
+//! Modem/router line statistics
+//!
+//! Scrapes the status page of the local modem after the run and extracts line-level
+//! metrics (SNR, power, sync rate, FEC errors) so speed drops can be correlated with
+//! line degradation. Each metric is `NAME=regex:PATTERN` (first capture group of the
+//! first match) or `NAME=json:path.to.value` for JSON status endpoints. Presets cover
+//! the usual DOCSIS and DSL pages and can be extended or overridden per metric.
+
+use crate::model::ModemStats;
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Upper bound for fetching the status page
+const MODEM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Built-in status page scraper
+struct ModemPreset {
+    name: &'static str,
+    url: &'static str,
+    /// (metric name, extractor spec)
+    metrics: &'static [(&'static str, &'static str)],
+}
+
+const PRESETS: &[ModemPreset] = &[
+    ModemPreset {
+        name: "docsis",
+        url: "http://192.168.100.1/",
+        metrics: &[
+            (
+                "downstream_snr_db",
+                r"regex:(?i)SNR[^0-9-]{0,60}?(-?\d+(?:\.\d+)?)\s*dB",
+            ),
+            (
+                "downstream_power_dbmv",
+                r"regex:(?i)Power[^0-9-]{0,60}?(-?\d+(?:\.\d+)?)\s*dBmV",
+            ),
+            (
+                "corrected_codewords",
+                r"regex:(?i)Corrected[^0-9]{0,60}?(\d+)",
+            ),
+            (
+                "uncorrectable_codewords",
+                r"regex:(?i)Uncorrect(?:ed|ables?)[^0-9]{0,60}?(\d+)",
+            ),
+        ],
+    },
+    ModemPreset {
+        name: "dsl",
+        url: "http://192.168.1.1/",
+        metrics: &[
+            (
+                "downstream_sync_kbps",
+                r"regex:(?i)Down(?:stream)?[^0-9]{0,60}?(\d+(?:\.\d+)?)\s*kbps",
+            ),
+            (
+                "upstream_sync_kbps",
+                r"regex:(?i)Up(?:stream)?[^0-9]{0,60}?(\d+(?:\.\d+)?)\s*kbps",
+            ),
+            (
+                "snr_margin_db",
+                r"regex:(?i)(?:SNR|Noise) Margin[^0-9-]{0,60}?(-?\d+(?:\.\d+)?)",
+            ),
+            ("fec_errors", r"regex:(?i)FEC[^0-9]{0,60}?(\d+)"),
+            ("crc_errors", r"regex:(?i)CRC[^0-9]{0,60}?(\d+)"),
+        ],
+    },
+];
+
+enum Extractor {
+    Regex(Regex),
+    JsonPath(Vec<String>),
+}
+
+/// Parse a `--modem-metric` value (`NAME=regex:PATTERN` or `NAME=json:PATH`).
+pub fn parse_metric(s: &str) -> Result<(String, String), String> {
+    let (name, spec) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=regex:PATTERN or NAME=json:PATH, got '{}'", s))?;
+    parse_extractor(spec).map_err(|e| format!("{e:#}"))?;
+    Ok((name.to_string(), spec.to_string()))
+}
+
+fn parse_extractor(spec: &str) -> Result<Extractor> {
+    if let Some(pattern) = spec.strip_prefix("regex:") {
+        let re = Regex::new(pattern).with_context(|| format!("invalid regex '{}'", pattern))?;
+        anyhow::ensure!(
+            re.captures_len() > 1,
+            "regex '{}' needs a capture group",
+            pattern
+        );
+        Ok(Extractor::Regex(re))
+    } else if let Some(path) = spec.strip_prefix("json:") {
+        Ok(Extractor::JsonPath(
+            path.split('.').map(str::to_string).collect(),
+        ))
+    } else {
+        anyhow::bail!("metric spec must start with 'regex:' or 'json:'")
+    }
+}
+
+/// Resolve `--modem` (preset name or URL) and `--modem-metric` overrides into the
+/// status page URL and metric specs.
+fn resolve(target: &str, overrides: &[(String, String)]) -> (String, BTreeMap<String, String>) {
+    let mut metrics = BTreeMap::new();
+    let url = match PRESETS.iter().find(|p| p.name == target) {
+        Some(preset) => {
+            for (name, spec) in preset.metrics {
+                metrics.insert(name.to_string(), spec.to_string());
+            }
+            preset.url.to_string()
+        }
+        None => target.to_string(),
+    };
+    for (name, spec) in overrides {
+        metrics.insert(name.clone(), spec.clone());
+    }
+    (url, metrics)
+}
+
+/// Scrape line statistics. Failures are reported in the result rather than failing the run.
+pub async fn scrape(target: &str, overrides: &[(String, String)]) -> ModemStats {
+    let (url, specs) = resolve(target, overrides);
+    let mut stats = ModemStats {
+        source: url.clone(),
+        metrics: BTreeMap::new(),
+        errors: Vec::new(),
+    };
+
+    let body = match fetch(&url).await {
+        Ok(b) => b,
+        Err(e) => {
+            stats.errors.push(format!("{e:#}"));
+            return stats;
+        }
+    };
+    let json = serde_json::from_str::<serde_json::Value>(&body).ok();
+
+    for (name, spec) in specs {
+        let value = match parse_extractor(&spec) {
+            Ok(Extractor::Regex(re)) => re
+                .captures(&body)
+                .and_then(|c| c.get(1))
+                .and_then(|m| m.as_str().parse::<f64>().ok()),
+            Ok(Extractor::JsonPath(path)) => json.as_ref().and_then(|j| json_path(j, &path)),
+            Err(e) => {
+                stats.errors.push(format!("{}: {e:#}", name));
+                continue;
+            }
+        };
+        match value {
+            Some(v) => {
+                stats.metrics.insert(name, v);
+            }
+            None => stats.errors.push(format!("{}: not found", name)),
+        }
+    }
+    stats
+}
+
+async fn fetch(url: &str) -> Result<String> {
+    // Modem admin pages live on the LAN and commonly use self-signed certificates
+    let client = reqwest::Client::builder()
+        .no_proxy()
+        .danger_accept_invalid_certs(true)
+        .timeout(MODEM_TIMEOUT)
+        .build()?;
+    let resp = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("failed to fetch modem status from {}", url))?
+        .error_for_status()?;
+    Ok(resp.text().await?)
+}
+
+/// Follow a dotted path (object keys or array indices) to a numeric value.
+fn json_path(value: &serde_json::Value, path: &[String]) -> Option<f64> {
+    let mut cur = value;
+    for key in path {
+        cur = match cur {
+            serde_json::Value::Array(a) => a.get(key.parse::<usize>().ok()?)?,
+            other => other.get(key.as_str())?,
+        };
+    }
+    match cur {
+        serde_json::Value::Number(n) => n.as_f64(),
+        serde_json::Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extractors() {
+        let html = "<tr><td>SNR</td><td>38.6 dB</td></tr><tr><td>Uncorrectables</td><td>12</td>";
+        let Extractor::Regex(re) = parse_extractor(PRESETS[0].metrics[0].1).unwrap() else {
+            panic!("expected regex");
+        };
+        assert_eq!(&re.captures(html).unwrap()[1], "38.6");
+
+        let json: serde_json::Value =
+            serde_json::from_str(r#"{"line": {"channels": [{"snr": "41.2"}]}}"#).unwrap();
+        let path: Vec<String> = ["line", "channels", "0", "snr"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(json_path(&json, &path), Some(41.2));
+
+        assert!(parse_metric("snr=regex:no group").is_err());
+        assert!(parse_metric("snr=xpath://td").is_err());
+    }
+}
@@ -0,0 +1,424 @@
+//! Path MTU discovery toward the test endpoint (`--mtu`)
+//!
+//! Binary-searches the largest packet that gets through with the don't-fragment bit set,
+//! between the size every path must carry (576 bytes for IPv4, 1280 for IPv6) and the
+//! MTU of the local route. The probes are ICMP echo requests to the endpoint, over the
+//! traceroute's raw or unprivileged ICMP sockets; when neither can be opened or the endpoint
+//! does not answer pings, padded STUN binding requests to Cloudflare's STUN server are
+//! used instead. A TCP connection to the endpoint then shows the segment size the
+//! server's SYN-ACK allowed, which gives away MSS clamping on the path. Linux only.
+
+use crate::engine::resolver;
+#[cfg(target_os = "linux")]
+use crate::engine::traceroute;
+use crate::model::{MtuSummary, RunConfig};
+use anyhow::{Context, Result};
+use std::net::SocketAddr;
+#[cfg_attr(not(target_os = "linux"), allow(unused_imports))]
+use std::time::{Duration, Instant};
+
+/// Search ceiling; anything above jumbo frames is not worth probing
+const MAX_MTU: u16 = 9000;
+
+/// How long to wait for the reply to one probe
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const PROBE_TIMEOUT: Duration = Duration::from_millis(800);
+
+/// Probes sent per size before it counts as too big
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const ATTEMPTS: usize = 2;
+
+/// Fallback target for the UDP probes
+const STUN_SERVER: (&str, u16) = ("turn.cloudflare.com", 3478);
+
+/// Find the path MTU toward the base URL's host and check the MSS on a TCP connection.
+pub(crate) async fn discover(cfg: &RunConfig) -> Result<MtuSummary> {
+    let (target, bind) = resolver::probe_endpoint(cfg).await?;
+    let stun = tokio::net::lookup_host(STUN_SERVER)
+        .await
+        .ok()
+        .and_then(|mut addrs| addrs.find(|a| a.is_ipv4() == target.is_ipv4()));
+
+    let probe = tokio::task::spawn_blocking(move || probe_path(target, stun, bind))
+        .await
+        .context("path MTU probe task failed")??;
+
+    let header = if target.is_ipv4() { 20 } else { 40 };
+    // TCP header without options
+    let expected_mss = probe.path_mtu - header - 20;
+    let mss = server_mss(target, bind).await;
+    Ok(MtuSummary {
+        target: target.ip().to_string(),
+        method: probe.method.to_string(),
+        path_mtu: probe.path_mtu,
+        local_mtu: probe.local_mtu,
+        fragmentation_needed: probe
+            .local_mtu
+            .is_some_and(|local| probe.path_mtu < local.min(MAX_MTU)),
+        mss,
+        expected_mss,
+        mss_clamped: mss.map(|mss| mss < expected_mss),
+        probes: probe.probes,
+    })
+}
+
+/// Outcome of the search
+struct PathProbe {
+    method: &'static str,
+    path_mtu: u16,
+    local_mtu: Option<u16>,
+    probes: u32,
+}
+
+#[cfg(not(target_os = "linux"))]
+fn probe_path(
+    _target: SocketAddr,
+    _stun: Option<SocketAddr>,
+    _bind: Option<SocketAddr>,
+) -> Result<PathProbe> {
+    anyhow::bail!("path MTU discovery is only supported on Linux")
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn server_mss(_target: SocketAddr, _bind: Option<SocketAddr>) -> Option<u16> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn probe_path(
+    target: SocketAddr,
+    stun: Option<SocketAddr>,
+    bind: Option<SocketAddr>,
+) -> Result<PathProbe> {
+    let floor = if target.is_ipv4() { 576 } else { 1280 };
+    let local_mtu = route_mtu(target, bind);
+    let ceiling = local_mtu.unwrap_or(1500).min(MAX_MTU);
+
+    let icmp_error = match Prober::icmp(target, bind) {
+        Ok(mut prober) => match search(&mut prober, floor, ceiling) {
+            Ok(path_mtu) => {
+                return Ok(PathProbe {
+                    method: "icmp",
+                    path_mtu,
+                    local_mtu,
+                    probes: prober.sent,
+                })
+            }
+            Err(e) => e,
+        },
+        Err(e) => e,
+    };
+    tracing::debug!("ICMP path MTU probe failed: {:#}", icmp_error);
+
+    let stun =
+        stun.with_context(|| format!("{:#}; no STUN server address to fall back to", icmp_error))?;
+    let mut prober = Prober::stun(stun, bind)?;
+    let path_mtu = search(&mut prober, floor, ceiling)
+        .with_context(|| format!("ICMP: {:#}; UDP", icmp_error))?;
+    Ok(PathProbe {
+        method: "udp",
+        path_mtu,
+        local_mtu,
+        probes: prober.sent,
+    })
+}
+
+/// Largest size from `floor` to `ceiling` that fits, in the prober's steps.
+#[cfg(target_os = "linux")]
+fn search(prober: &mut Prober, floor: u16, ceiling: u16) -> Result<u16> {
+    let step = prober.step();
+    let ceiling = ceiling / step * step;
+    // Most paths carry the full local MTU, which settles it in one probe
+    if prober.fits(ceiling)? {
+        return Ok(ceiling);
+    }
+    anyhow::ensure!(
+        prober.fits(floor)?,
+        "no reply to {}-byte probes from {}",
+        floor,
+        prober.target
+    );
+    let (mut fits, mut too_big) = (floor, ceiling);
+    while too_big - fits > step {
+        let mid = fits + (too_big - fits) / step / 2 * step;
+        if prober.fits(mid)? {
+            fits = mid;
+        } else {
+            too_big = mid;
+        }
+    }
+    Ok(fits)
+}
+
+#[cfg(target_os = "linux")]
+enum Kind {
+    /// ICMP echo; raw sockets also return the IPv4 header
+    Icmp { raw: bool },
+    /// STUN binding requests with a PADDING attribute
+    Stun,
+}
+
+/// Socket that sends packets of a given size with don't-fragment set
+#[cfg(target_os = "linux")]
+struct Prober {
+    socket: socket2::Socket,
+    target: SocketAddr,
+    kind: Kind,
+    sent: u32,
+    sequence: u16,
+}
+
+#[cfg(target_os = "linux")]
+impl Prober {
+    fn icmp(target: SocketAddr, bind: Option<SocketAddr>) -> Result<Self> {
+        let (socket, raw) = traceroute::open_icmp_socket(&target.ip())?;
+        Self::open(
+            socket,
+            SocketAddr::new(target.ip(), 0),
+            bind,
+            Kind::Icmp { raw },
+        )
+    }
+
+    fn stun(target: SocketAddr, bind: Option<SocketAddr>) -> Result<Self> {
+        use socket2::{Domain, Protocol, Socket, Type};
+
+        let socket = Socket::new(
+            Domain::for_address(target),
+            Type::DGRAM,
+            Some(Protocol::UDP),
+        )?;
+        Self::open(socket, target, bind, Kind::Stun)
+    }
+
+    fn open(
+        socket: socket2::Socket,
+        target: SocketAddr,
+        bind: Option<SocketAddr>,
+        kind: Kind,
+    ) -> Result<Self> {
+        if let Some(bind) = bind {
+            socket.bind(&bind.into())?;
+        }
+        socket.connect(&target.into())?;
+        // Set DF and ignore any cached path MTU, so every size goes out as asked
+        let (level, name, value) = if target.is_ipv4() {
+            (
+                libc::IPPROTO_IP,
+                libc::IP_MTU_DISCOVER,
+                libc::IP_PMTUDISC_PROBE,
+            )
+        } else {
+            (
+                libc::IPPROTO_IPV6,
+                libc::IPV6_MTU_DISCOVER,
+                libc::IPV6_PMTUDISC_PROBE,
+            )
+        };
+        set_int_option(&socket, level, name, value).context("cannot set don't-fragment")?;
+        socket.set_read_timeout(Some(PROBE_TIMEOUT))?;
+        Ok(Self {
+            socket,
+            target,
+            kind,
+            sent: 0,
+            sequence: rand::random(),
+        })
+    }
+
+    /// Sizes the prober can produce are multiples of this
+    fn step(&self) -> u16 {
+        match self.kind {
+            Kind::Icmp { .. } => 1,
+            // STUN attributes are padded to four bytes
+            Kind::Stun => 4,
+        }
+    }
+
+    /// IP header plus the ICMP or UDP header in front of the payload
+    fn overhead(&self) -> usize {
+        let ip = if self.target.is_ipv4() { 20 } else { 40 };
+        ip + 8
+    }
+
+    /// Whether a `size`-byte packet gets a reply.
+    fn fits(&mut self, size: u16) -> Result<bool> {
+        let payload = usize::from(size)
+            .checked_sub(self.overhead())
+            .context("probe size below the headers")?;
+        let mut buf = vec![0u8; 65536];
+        for _ in 0..ATTEMPTS {
+            self.sequence = self.sequence.wrapping_add(1);
+            let packet = self.packet(payload);
+            self.sent += 1;
+            match self.socket.send(&packet) {
+                Ok(_) => {}
+                // Bigger than the local route allows
+                Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) => return Ok(false),
+                Err(e) => return Err(e).context("cannot send probe"),
+            }
+            let deadline = Instant::now() + PROBE_TIMEOUT;
+            while Instant::now() < deadline {
+                match std::io::Read::read(&mut &self.socket, &mut buf) {
+                    Ok(n) if self.is_reply(&buf[..n]) => return Ok(true),
+                    Ok(_) => {}
+                    Err(e)
+                        if matches!(
+                            e.kind(),
+                            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        break
+                    }
+                    // A "fragmentation needed" reported back; the size did not fit
+                    Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) => return Ok(false),
+                    Err(e) => return Err(e).context("cannot receive probe reply"),
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn packet(&self, payload: usize) -> Vec<u8> {
+        match self.kind {
+            Kind::Icmp { .. } => traceroute::build_echo_request(
+                self.target.is_ipv6(),
+                std::process::id() as u16,
+                self.sequence,
+                payload,
+            ),
+            Kind::Stun => {
+                // Binding request, cookie, transaction id, PADDING attribute
+                let padding = payload - 24;
+                let mut packet = Vec::with_capacity(payload);
+                packet.extend([0x00, 0x01]);
+                packet.extend(((4 + padding) as u16).to_be_bytes());
+                packet.extend([0x21, 0x12, 0xA4, 0x42]);
+                packet.extend([0u8; 10]);
+                packet.extend(self.sequence.to_be_bytes());
+                packet.extend([0x00, 0x26]);
+                packet.extend((padding as u16).to_be_bytes());
+                packet.resize(payload, 0);
+                packet
+            }
+        }
+    }
+
+    fn is_reply(&self, buf: &[u8]) -> bool {
+        match self.kind {
+            Kind::Icmp { raw } => {
+                traceroute::is_echo_reply(buf, self.target.is_ipv6(), raw, self.sequence)
+            }
+            // Any response to the request counts, an error for the padding included
+            Kind::Stun => {
+                buf.len() >= 20
+                    && buf[4..8] == [0x21, 0x12, 0xA4, 0x42]
+                    && buf[18..20] == self.sequence.to_be_bytes()
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_int_option(
+    socket: &socket2::Socket,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::last_os_error())
+    }
+}
+
+/// MTU the kernel would use toward `target`: the interface's, or a path MTU it has
+/// already learned.
+#[cfg(target_os = "linux")]
+fn route_mtu(target: SocketAddr, bind: Option<SocketAddr>) -> Option<u16> {
+    use std::os::fd::AsRawFd;
+
+    let socket = std::net::UdpSocket::bind(bind.unwrap_or_else(|| {
+        if target.is_ipv4() {
+            (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+        }
+    }))
+    .ok()?;
+    socket.connect(SocketAddr::new(target.ip(), 9)).ok()?;
+    let (level, name) = if target.is_ipv4() {
+        (libc::IPPROTO_IP, libc::IP_MTU)
+    } else {
+        (libc::IPPROTO_IPV6, libc::IPV6_MTU)
+    };
+    let mut mtu: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &mut mtu as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (rc == 0).then(|| mtu.clamp(0, i32::from(u16::MAX)) as u16)
+}
+
+/// Largest segment the server lets this side send, TCP options not subtracted.
+#[cfg(target_os = "linux")]
+async fn server_mss(target: SocketAddr, bind: Option<SocketAddr>) -> Option<u16> {
+    use std::os::fd::AsRawFd;
+
+    /// `TCPI_OPT_TIMESTAMPS` from linux/tcp.h
+    const TIMESTAMPS: u8 = 1;
+    /// Bytes the timestamp option takes from every segment, padding included
+    const TIMESTAMPS_LEN: u32 = 12;
+
+    let socket = if target.is_ipv4() {
+        tokio::net::TcpSocket::new_v4()
+    } else {
+        tokio::net::TcpSocket::new_v6()
+    }
+    .ok()?;
+    if let Some(bind) = bind {
+        socket.bind(bind).ok()?;
+    }
+    let stream = tokio::time::timeout(Duration::from_secs(5), socket.connect(target))
+        .await
+        .ok()?
+        .ok()?;
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut libc::tcp_info as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return None;
+    }
+    // The kernel's send MSS already leaves room for the timestamp option
+    let mut mss = info.tcpi_snd_mss;
+    if info.tcpi_options & TIMESTAMPS != 0 {
+        mss += TIMESTAMPS_LEN;
+    }
+    u16::try_from(mss).ok()
+}
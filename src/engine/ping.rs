@@ -0,0 +1,141 @@
+//! ICMP echo latency to the test endpoint (`--icmp-ping`)
+//!
+//! Pings the endpoint alongside the idle latency phase, so the network-layer round trip
+//! can be compared with the HTTP probes: the difference is time spent in the server and
+//! the HTTP stack rather than on the path. Uses the traceroute's ICMP sockets, raw when
+//! privileges allow and an unprivileged ping socket otherwise.
+
+use crate::engine::scheduler::ProbeScheduler;
+use crate::engine::{resolver, traceroute, wait_if_paused_or_cancelled};
+use crate::model::{IcmpLatency, LatencySummary, RunConfig};
+use crate::stats::{latency_summary_from_samples, OnlineStats};
+use anyhow::Result;
+use std::net::SocketAddr;
+use std::sync::{atomic::AtomicBool, Arc};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+/// Data bytes per echo request, as sent by `ping`
+const PAYLOAD: usize = 56;
+
+/// Ping the endpoint every `interval_ms` for `total_duration`.
+pub async fn run_icmp_probes(
+    cfg: &RunConfig,
+    total_duration: Duration,
+    interval_ms: u64,
+    timeout_ms: u64,
+    scheduler: &ProbeScheduler,
+    paused: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+) -> IcmpLatency {
+    let pinger = match resolver::probe_endpoint(cfg).await {
+        Ok((target, bind)) => Pinger::open(target, bind).map_err(|e| (Some(target), e)),
+        Err(e) => Err((None, e)),
+    };
+    match pinger {
+        Ok(mut pinger) => IcmpLatency {
+            target: pinger.target.ip().to_string(),
+            socket: Some(if pinger.raw { "raw" } else { "unprivileged" }.to_string()),
+            latency: pinger
+                .run(
+                    total_duration,
+                    interval_ms,
+                    timeout_ms,
+                    scheduler,
+                    &paused,
+                    &cancel,
+                )
+                .await,
+            error: None,
+        },
+        Err((target, e)) => IcmpLatency {
+            target: target.map(|t| t.ip().to_string()).unwrap_or_default(),
+            socket: None,
+            latency: LatencySummary::failed(),
+            error: Some(format!("{e:#}")),
+        },
+    }
+}
+
+struct Pinger {
+    socket: UdpSocket,
+    target: SocketAddr,
+    raw: bool,
+    sequence: u16,
+}
+
+impl Pinger {
+    fn open(target: SocketAddr, bind: Option<SocketAddr>) -> Result<Self> {
+        let (socket, raw) = traceroute::open_icmp_socket(&target.ip())?;
+        if let Some(bind) = bind {
+            socket.bind(&bind.into())?;
+        }
+        // Only replies from the endpoint reach a connected socket
+        socket.connect(&SocketAddr::new(target.ip(), 0).into())?;
+        socket.set_nonblocking(true)?;
+        // Datagram semantics are all tokio's UDP socket relies on
+        let socket = UdpSocket::from_std(std::net::UdpSocket::from(socket))?;
+        Ok(Self {
+            socket,
+            target,
+            raw,
+            sequence: rand::random(),
+        })
+    }
+
+    async fn run(
+        &mut self,
+        total_duration: Duration,
+        interval_ms: u64,
+        timeout_ms: u64,
+        scheduler: &ProbeScheduler,
+        paused: &AtomicBool,
+        cancel: &AtomicBool,
+    ) -> LatencySummary {
+        let start = Instant::now();
+        let mut sent = 0u64;
+        let mut received = 0u64;
+        let mut samples = Vec::<f64>::new();
+        let mut online = OnlineStats::default();
+
+        while start.elapsed() < total_duration {
+            if wait_if_paused_or_cancelled(paused, cancel).await {
+                break;
+            }
+
+            scheduler.acquire().await;
+            sent += 1;
+            if let Some(ms) = self.ping(Duration::from_millis(timeout_ms)).await {
+                received += 1;
+                samples.push(ms);
+                online.push(ms);
+            }
+
+            tokio::time::sleep(Duration::from_millis(interval_ms)).await;
+        }
+
+        latency_summary_from_samples(sent, received, &samples, online.stddev())
+    }
+
+    /// Round trip of one echo request; `None` when no reply came within `timeout`.
+    async fn ping(&mut self, timeout: Duration) -> Option<f64> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let ipv6 = self.target.is_ipv6();
+        let packet =
+            traceroute::build_echo_request(ipv6, std::process::id() as u16, self.sequence, PAYLOAD);
+        let start = Instant::now();
+        self.socket.send(&packet).await.ok()?;
+        let mut buf = [0u8; 1500];
+        tokio::time::timeout(timeout, async {
+            loop {
+                let n = self.socket.recv(&mut buf).await.ok()?;
+                if traceroute::is_echo_reply(&buf[..n], ipv6, self.raw, self.sequence) {
+                    return Some(start.elapsed().as_secs_f64() * 1000.0);
+                }
+            }
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+}
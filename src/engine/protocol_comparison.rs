@@ -0,0 +1,172 @@
+//! HTTP protocol comparison (`--compare-protocols`)
+//!
+//! Runs a short download over HTTP/1.1, HTTP/2 and, in builds with the `http3` feature,
+//! HTTP/3 against the same endpoint, each with its own client so no connection is
+//! shared between versions. Every version gets the configured number of connections
+//! for a fixed time; the throughput and median time to first byte are recorded.
+//! Downloads come out of the `--max-total-bytes` budget like the main phases.
+
+use crate::engine::cloudflare::CloudflareClient;
+use crate::engine::har::HarRecorder;
+use crate::engine::throughput::{report_budget_spent, DataBudget};
+use crate::engine::wait_if_paused_or_cancelled;
+use crate::model::{HttpVersion, ProtocolResult, RunConfig, TestEvent};
+use crate::report::describe_request_error;
+use anyhow::Result;
+use futures::StreamExt;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Download time per protocol
+const DURATION: Duration = Duration::from_secs(4);
+
+/// Payload per request: small enough that every connection completes several requests
+const BYTES_PER_REQ: u64 = 5_000_000;
+
+fn versions() -> Vec<HttpVersion> {
+    let mut versions = vec![HttpVersion::Http1, HttpVersion::Http2];
+    if cfg!(feature = "http3") {
+        versions.push(HttpVersion::Http3);
+    }
+    versions
+}
+
+pub async fn run_protocol_comparison(
+    cfg: &RunConfig,
+    har: Option<&Arc<HarRecorder>>,
+    event_tx: &mpsc::Sender<TestEvent>,
+    budget: Arc<DataBudget>,
+    paused: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+) -> Vec<ProtocolResult> {
+    let mut out = Vec::new();
+    for protocol in versions() {
+        if wait_if_paused_or_cancelled(&paused, &cancel).await {
+            break;
+        }
+        if budget.is_spent() {
+            report_budget_spent(event_tx, "Protocol comparison", cfg).await;
+            break;
+        }
+        let result = match measure(cfg, har, &budget, protocol).await {
+            Ok(result) => result,
+            Err(e) => ProtocolResult {
+                protocol,
+                negotiated: None,
+                available: false,
+                mbps: None,
+                ttfb_ms: None,
+                requests: 0,
+                error: Some(format!("{:#}", e)),
+            },
+        };
+        let message = match (&result.mbps, &result.error) {
+            (Some(mbps), _) => format!(
+                "{}: {:.2} Mbps, TTFB {} ms",
+                protocol.label(),
+                mbps,
+                result
+                    .ttfb_ms
+                    .map(|v| format!("{:.1}", v))
+                    .unwrap_or_else(|| "-".to_string())
+            ),
+            (None, Some(e)) => format!("{}: unavailable ({})", protocol.label(), e),
+            (None, None) => format!("{}: unavailable", protocol.label()),
+        };
+        event_tx.send(TestEvent::Info { message }).await.ok();
+        out.push(result);
+    }
+    out
+}
+
+async fn measure(
+    cfg: &RunConfig,
+    har: Option<&Arc<HarRecorder>>,
+    budget: &DataBudget,
+    protocol: HttpVersion,
+) -> Result<ProtocolResult> {
+    let mut client = CloudflareClient::new(&RunConfig {
+        http_version: protocol,
+        ..cfg.clone()
+    })?;
+    client.har = har.cloned();
+    let total = AtomicU64::new(0);
+    let ttfbs = Mutex::new(Vec::<f64>::new());
+    let last_error = Mutex::new(None::<String>);
+
+    let start = Instant::now();
+    let deadline = start + DURATION;
+    let worker = || async {
+        while Instant::now() < deadline {
+            let reserved = budget.reserve(BYTES_PER_REQ);
+            if reserved == 0 {
+                break;
+            }
+            let mut received = 0;
+            // Requests are cut off mid-transfer at the deadline
+            let request = async {
+                let url = client.down_url(reserved);
+                let sent = Instant::now();
+                let resp = match client.send(client.get(url)).await {
+                    Ok(resp) if resp.status().is_success() => resp,
+                    Ok(resp) => {
+                        *last_error.lock().unwrap() = Some(format!("HTTP {}", resp.status()));
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        return;
+                    }
+                    Err(e) => {
+                        *last_error.lock().unwrap() = Some(describe_request_error(&e));
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        return;
+                    }
+                };
+                ttfbs
+                    .lock()
+                    .unwrap()
+                    .push(sent.elapsed().as_secs_f64() * 1000.0);
+                client.protocols.record(resp.version());
+                let mut stream = resp.bytes_stream();
+                while let Some(Ok(chunk)) = stream.next().await {
+                    received += chunk.len() as u64;
+                    total.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                }
+            };
+            let _ = tokio::time::timeout_at(deadline, request).await;
+            budget.release(reserved.saturating_sub(received));
+        }
+    };
+    let workers = (0..cfg.concurrency.max(1)).map(|_| worker());
+    futures::future::join_all(workers).await;
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let bytes = total.load(Ordering::Relaxed);
+    let ttfbs = ttfbs.into_inner().unwrap();
+    if bytes == 0 {
+        let error = last_error
+            .into_inner()
+            .unwrap()
+            .unwrap_or_else(|| "no data received".to_string());
+        anyhow::bail!(error);
+    }
+    // The version most responses used
+    let negotiated = client
+        .protocols
+        .take()
+        .into_iter()
+        .max_by_key(|(_, n)| *n)
+        .map(|(v, _)| v);
+    Ok(ProtocolResult {
+        protocol,
+        negotiated,
+        available: true,
+        mbps: Some(bytes as f64 * 8.0 / elapsed / 1_000_000.0),
+        ttfb_ms: crate::metrics::percentile(&ttfbs, 0.5),
+        requests: ttfbs.len() as u64,
+        error: None,
+    })
+}
@@ -0,0 +1,479 @@
+//! Name resolution through a chosen DNS server (`--dns-server`, `--doh`, `--dot`)
+//!
+//! A stub resolver in place of the system one, on hickory's DNS client: queries go to
+//! the server over UDP (again over TCP when the answer comes back truncated), as
+//! DNS-over-HTTPS (RFC 8484) or over TLS (RFC 7858). It resolves the test traffic (as
+//! reqwest's resolver), the DNS diagnostic and the request timing breakdown; other
+//! lookups (IP comparison, traceroute, the DoH and DoT servers' own names) still use the
+//! system resolver. It also answers the PTR and TXT queries behind the traceroute hop
+//! names and ASNs, falling back to the system's first nameserver for those. Encrypted
+//! queries go straight to the server, never through `--proxy`.
+
+use crate::model::RunConfig;
+use anyhow::{Context, Result};
+use hickory_resolver::config::{NameServerConfig, NameServerConfigGroup, ResolverOpts};
+use hickory_resolver::name_server::{NameServerPool, TokioConnectionProvider};
+use hickory_resolver::proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_resolver::proto::rr::{Name, RData, RecordType};
+use hickory_resolver::proto::xfer::{
+    DnsHandle, DnsRequest, DnsRequestOptions, DnsResponse, FirstAnswer, Protocol, RetryDnsHandle,
+};
+use hickory_resolver::proto::{ProtoError, ProtoErrorKind};
+use rustls::pki_types::ServerName;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+/// Wait this long for each UDP answer before asking again
+const UDP_TIMEOUT: Duration = Duration::from_secs(2);
+/// Encrypted queries may have to connect and shake hands first
+const TLS_TIMEOUT: Duration = Duration::from_secs(5);
+/// Ask again this many times when no answer comes
+const RETRIES: usize = 2;
+const DOT_PORT: u16 = 853;
+
+type Servers = RetryDnsHandle<NameServerPool<TokioConnectionProvider>>;
+
+#[derive(Clone)]
+pub(crate) struct DnsResolver {
+    server: Server,
+    /// Set up on first use, once the server's own name has been looked up
+    servers: Arc<OnceCell<Servers>>,
+}
+
+#[derive(Clone)]
+enum Server {
+    /// Plain DNS to `--dns-server`
+    Udp(SocketAddr),
+    /// DNS-over-HTTPS to `--doh`
+    Https {
+        url: reqwest::Url,
+        tls: rustls::ClientConfig,
+    },
+    /// DNS-over-TLS to `--dot`
+    Tls {
+        host: String,
+        port: u16,
+        tls: rustls::ClientConfig,
+    },
+}
+
+impl DnsResolver {
+    /// The resolver `--dns-server`, `--doh` or `--dot` asks for; `None` to use the system
+    /// resolver.
+    pub(crate) fn from_config(cfg: &RunConfig) -> Result<Option<Self>> {
+        let server = if let Some(server) = cfg.dns_server {
+            Server::Udp(server)
+        } else if let Some(ref url) = cfg.doh_url {
+            let url =
+                reqwest::Url::parse(url).with_context(|| format!("invalid DoH URL '{}'", url))?;
+            Server::Https {
+                url,
+                tls: tls_config(cfg)?,
+            }
+        } else if let Some(ref server) = cfg.dot_server {
+            let (host, port) = parse_dot_server(server).map_err(anyhow::Error::msg)?;
+            Server::Tls {
+                host,
+                port,
+                tls: tls_config(cfg)?,
+            }
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(Self::new(server)))
+    }
+
+    /// Plain DNS to the first nameserver the system is configured with, for the queries
+    /// the system resolver cannot make (PTR, TXT).
+    pub(crate) fn system() -> Option<Self> {
+        let server = crate::engine::dns::get_system_dns_servers()
+            .first()?
+            .parse::<IpAddr>()
+            .ok()?;
+        Some(Self::new(Server::Udp(SocketAddr::new(server, 53))))
+    }
+
+    fn new(server: Server) -> Self {
+        Self {
+            server,
+            servers: Arc::default(),
+        }
+    }
+
+    /// How the resolver is shown in results, e.g. "9.9.9.9:53",
+    /// "https://cloudflare-dns.com/dns-query" or "tls://1.1.1.1:853"
+    pub(crate) fn describe(&self) -> String {
+        match &self.server {
+            Server::Udp(server) => server.to_string(),
+            Server::Https { url, .. } => url.to_string(),
+            Server::Tls { host, port, .. } => format!("tls://{}", join_host_port(host, *port)),
+        }
+    }
+
+    /// "DoH" or "DoT" for the encrypted transports, `None` for plain DNS
+    pub(crate) fn encryption(&self) -> Option<&'static str> {
+        match self.server {
+            Server::Udp(_) => None,
+            Server::Https { .. } => Some("DoH"),
+            Server::Tls { .. } => Some("DoT"),
+        }
+    }
+
+    /// Resolve `host` to its IPv4 and IPv6 addresses, IPv4 first.
+    pub(crate) async fn lookup(&self, host: &str) -> Result<Vec<IpAddr>> {
+        let name = parse_name(host)?;
+        let (v4, v6) = tokio::join!(
+            self.exchange(name.clone(), RecordType::A),
+            self.exchange(name, RecordType::AAAA)
+        );
+        let mut ips = Vec::new();
+        let mut error = None;
+        for answer in [v4, v6] {
+            match answer {
+                Ok(answer) => ips.extend(answer.answers().iter().filter_map(|record| {
+                    match record.data() {
+                        RData::A(a) => Some(IpAddr::V4(a.0)),
+                        RData::AAAA(aaaa) => Some(IpAddr::V6(aaaa.0)),
+                        // CNAMEs and the like; recursive servers include the records they
+                        // point to
+                        _ => None,
+                    }
+                })),
+                Err(e) => error = Some(e),
+            }
+        }
+        match error {
+            Some(e) if ips.is_empty() => Err(e),
+            _ if ips.is_empty() => anyhow::bail!("{} has no addresses", host),
+            _ => Ok(ips),
+        }
+    }
+
+    /// Name `ip` points back to (its PTR record), if any.
+    pub(crate) async fn reverse(&self, ip: &IpAddr) -> Result<Option<String>> {
+        let answer = match self.exchange(Name::from(*ip), RecordType::PTR).await {
+            Err(e) if is_empty_answer(&e) => return Ok(None),
+            answer => answer?,
+        };
+        Ok(answer
+            .answers()
+            .iter()
+            .find_map(|record| match record.data() {
+                RData::PTR(ptr) => Some(ptr.0.to_utf8().trim_end_matches('.').to_string()),
+                _ => None,
+            }))
+    }
+
+    /// TXT records of `name`, the character strings of each joined together.
+    pub(crate) async fn txt(&self, name: &str) -> Result<Vec<String>> {
+        let answer = match self.exchange(parse_name(name)?, RecordType::TXT).await {
+            Err(e) if is_empty_answer(&e) => return Ok(Vec::new()),
+            answer => answer?,
+        };
+        Ok(answer
+            .answers()
+            .iter()
+            .filter_map(|record| match record.data() {
+                RData::TXT(txt) => Some(
+                    txt.txt_data()
+                        .iter()
+                        .map(|s| String::from_utf8_lossy(s))
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Whether the server marks `host`'s A records as authenticated by DNSSEC (the AD
+    /// bit). A validating server answers a name whose signatures fail to verify with
+    /// SERVFAIL, see `is_server_failure`.
+    pub(crate) async fn authenticated(&self, host: &str) -> Result<bool> {
+        let answer = self.exchange(parse_name(host)?, RecordType::A).await?;
+        Ok(answer.authentic_data())
+    }
+
+    /// Send a `rtype` query for `name` to the server. Answers without records and error
+    /// codes come back as errors.
+    async fn exchange(&self, name: Name, rtype: RecordType) -> Result<DnsResponse> {
+        let mut query = Message::new();
+        query
+            .add_query(Query::query(name, rtype))
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            // Authenticated data wanted (RFC 6840)
+            .set_authentic_data(true);
+        let request = DnsRequest::new(query, DnsRequestOptions::default());
+        let servers = self.servers().await?;
+        servers
+            .send(request)
+            .first_answer()
+            .await
+            .with_context(|| format!("DNS query to {} failed", self.describe()))
+    }
+
+    /// The connections to the server, set up on first use
+    async fn servers(&self) -> Result<&Servers> {
+        self.servers
+            .get_or_try_init(|| async {
+                let mut opts = ResolverOpts::default();
+                let servers = match &self.server {
+                    Server::Udp(server) => {
+                        opts.timeout = UDP_TIMEOUT;
+                        NameServerConfigGroup::from_ips_clear(&[server.ip()], server.port(), true)
+                    }
+                    Server::Https { url, tls } => {
+                        opts.timeout = TLS_TIMEOUT;
+                        opts.tls_config = tls.clone();
+                        let host = url.host_str().unwrap_or_default();
+                        let port = url.port_or_known_default().unwrap_or(443);
+                        let mut servers = encrypted_servers(host, port, Protocol::Https).await?;
+                        for server in &mut servers {
+                            server.http_endpoint = Some(url.path().to_string());
+                        }
+                        servers.into()
+                    }
+                    Server::Tls { host, port, tls } => {
+                        opts.timeout = TLS_TIMEOUT;
+                        opts.tls_config = tls.clone();
+                        encrypted_servers(host, *port, Protocol::Tls).await?.into()
+                    }
+                };
+                let pool =
+                    NameServerPool::from_config(servers, opts, TokioConnectionProvider::default());
+                Ok(RetryDnsHandle::new(pool, RETRIES))
+            })
+            .await
+    }
+}
+
+fn parse_name(host: &str) -> Result<Name> {
+    Name::from_ascii(host).with_context(|| format!("invalid host name '{}'", host))
+}
+
+/// Whether `error` is a server's answer that the name has no such records (NOERROR
+/// without records, or NXDOMAIN)
+fn is_empty_answer(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<ProtoError>().map(ProtoError::kind),
+        Some(ProtoErrorKind::NoRecordsFound {
+            response_code: ResponseCode::NoError | ResponseCode::NXDomain,
+            ..
+        })
+    )
+}
+
+/// Whether `error` is a SERVFAIL answer, which a validating server gives for names whose
+/// signatures fail to verify
+pub(crate) fn is_server_failure(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<ProtoError>().map(ProtoError::kind),
+        Some(ProtoErrorKind::NoRecordsFound {
+            response_code: ResponseCode::ServFail,
+            ..
+        })
+    )
+}
+
+/// A DoH or DoT server at each address of `host`, which its certificate is checked
+/// against. The server's own name is looked up with the system resolver.
+async fn encrypted_servers(
+    host: &str,
+    port: u16,
+    protocol: Protocol,
+) -> Result<Vec<NameServerConfig>> {
+    let host = host.trim_matches(['[', ']']);
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("cannot reach DNS server {}", join_host_port(host, port)))?
+        .collect();
+    anyhow::ensure!(!addrs.is_empty(), "{} has no addresses", host);
+    Ok(addrs
+        .into_iter()
+        .map(|addr| {
+            let mut server = NameServerConfig::new(addr, protocol);
+            server.tls_dns_name = Some(host.to_string());
+            server.trust_negative_responses = true;
+            server
+        })
+        .collect())
+}
+
+/// TLS settings for DoH and DoT: the built-in roots plus `--certificate`
+fn tls_config(cfg: &RunConfig) -> Result<rustls::ClientConfig> {
+    crate::engine::tls::ensure_crypto_provider();
+    Ok(rustls::ClientConfig::builder()
+        .with_root_certificates(crate::engine::cloudflare::root_store(cfg)?)
+        .with_no_client_auth())
+}
+
+fn join_host_port(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Addresses to connect to for `host`, the way the test traffic resolves it: the
+/// `--resolve` pin, then IP literals, then `resolver` or the system resolver, keeping
+/// only the family `-4`/`-6` allow.
+pub(crate) async fn resolve_endpoint(
+    cfg: &RunConfig,
+    resolver: Option<&DnsResolver>,
+    host: &str,
+    port: u16,
+) -> Result<Vec<SocketAddr>> {
+    if let Some((pinned_host, addr)) = crate::engine::cloudflare::pinned_endpoint(cfg) {
+        if pinned_host.eq_ignore_ascii_case(host) && addr.port() == port {
+            return Ok(vec![addr]);
+        }
+    }
+    let ips: Vec<IpAddr> = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => vec![ip],
+        Err(_) => match resolver {
+            Some(resolver) => resolver.lookup(host).await?,
+            None => tokio::net::lookup_host((host, port))
+                .await
+                .with_context(|| format!("DNS lookup failed for {}", host))?
+                .map(|addr| addr.ip())
+                .collect(),
+        },
+    };
+    let addrs: Vec<SocketAddr> = ips
+        .into_iter()
+        .filter(|ip| cfg.allows_address(ip))
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect();
+    anyhow::ensure!(!addrs.is_empty(), "no usable address for {}", host);
+    Ok(addrs)
+}
+
+/// The endpoint address for probes that open their own sockets (path MTU, ICMP ping),
+/// resolved like the test traffic, and the `--interface`/`--source` address to bind to.
+pub(crate) async fn probe_endpoint(cfg: &RunConfig) -> Result<(SocketAddr, Option<SocketAddr>)> {
+    let (host, port) =
+        crate::engine::tls::extract_host_port(&cfg.base_url).context("base URL has no host")?;
+    let bind = crate::engine::network_bind::resolve_bind_address(
+        cfg.interface.as_ref(),
+        cfg.source_ip.as_ref(),
+    )?;
+    let resolver = DnsResolver::from_config(cfg)?;
+    let target = resolve_endpoint(cfg, resolver.as_ref(), &host, port)
+        .await?
+        .into_iter()
+        // A bound source address only reaches its own family
+        .find(|addr| bind.is_none_or(|b| b.is_ipv4() == addr.is_ipv4()))
+        .with_context(|| format!("no address of {} matches the source address", host))?;
+    Ok((target, bind))
+}
+
+impl reqwest::dns::Resolve for DnsResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let ips = resolver.lookup(name.as_str()).await?;
+            let addrs: reqwest::dns::Addrs =
+                Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+/// Parse a `--dns-server` value: an IP address with an optional port (53 by default).
+pub fn parse_server(s: &str) -> Result<SocketAddr, String> {
+    let s = s.trim();
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    s.trim_matches(['[', ']'])
+        .parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, 53))
+        .map_err(|_| format!("expected IP[:PORT], got '{}'", s))
+}
+
+/// Parse a `--dot` value: a host name or IP address with an optional port (853 by
+/// default). The certificate is checked against the host as given.
+pub fn parse_dot_server(s: &str) -> Result<(String, u16), String> {
+    let s = s.trim();
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok((addr.ip().to_string(), addr.port()));
+    }
+    if let Ok(ip) = s.trim_matches(['[', ']']).parse::<IpAddr>() {
+        return Ok((ip.to_string(), DOT_PORT));
+    }
+    let (host, port) = match s.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| format!("invalid port in '{}'", s))?,
+        ),
+        None => (s, DOT_PORT),
+    };
+    if host.is_empty() || ServerName::try_from(host).is_err() {
+        return Err(format!("expected HOST[:PORT], got '{}'", s));
+    }
+    Ok((host.to_string(), port))
+}
+
+/// Name in the reverse tree for `ip`: "1.2.0.192.in-addr.arpa" or the nibbles of an IPv6
+/// address under "ip6.arpa"
+pub(crate) fn reverse_name(ip: &IpAddr) -> String {
+    Name::from(*ip).to_utf8().trim_end_matches('.').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reverse_name() {
+        assert_eq!(
+            reverse_name(&"192.0.2.1".parse().unwrap()),
+            "1.2.0.192.in-addr.arpa"
+        );
+        assert_eq!(
+            reverse_name(&"2001:db8::1".parse().unwrap()),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn test_parse_server() {
+        assert_eq!(parse_server("9.9.9.9"), Ok("9.9.9.9:53".parse().unwrap()));
+        assert_eq!(
+            parse_server("127.0.0.1:5353"),
+            Ok("127.0.0.1:5353".parse().unwrap())
+        );
+        assert_eq!(
+            parse_server("2620:fe::fe"),
+            Ok("[2620:fe::fe]:53".parse().unwrap())
+        );
+        assert!(parse_server("dns.quad9.net").is_err());
+    }
+
+    #[test]
+    fn test_parse_dot_server() {
+        assert_eq!(
+            parse_dot_server("one.one.one.one"),
+            Ok(("one.one.one.one".to_string(), 853))
+        );
+        assert_eq!(
+            parse_dot_server("dns.example:8853"),
+            Ok(("dns.example".to_string(), 8853))
+        );
+        assert_eq!(
+            parse_dot_server("1.1.1.1"),
+            Ok(("1.1.1.1".to_string(), 853))
+        );
+        assert_eq!(
+            parse_dot_server("[2606:4700:4700::1111]:853"),
+            Ok(("2606:4700:4700::1111".to_string(), 853))
+        );
+        assert!(parse_dot_server("dns.example:port").is_err());
+        assert!(parse_dot_server("").is_err());
+    }
+}
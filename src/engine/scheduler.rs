@@ -0,0 +1,73 @@
+//! Shared probe scheduler
+//!
+//! Latency, anchor and UDP loss probes draw their send slots from one token bucket
+//! (capacity one, refilled every `min_gap`), so no two probes leave within a few
+//! milliseconds of each other. Back-to-back probes queue behind one another on a
+//! constrained uplink and would show up as self-induced jitter.
+
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Minimum spacing between any two probes of a run
+pub const PROBE_MIN_GAP: Duration = Duration::from_millis(5);
+
+pub struct ProbeScheduler {
+    min_gap: Duration,
+    /// Earliest instant at which the next token is available
+    next_slot: Mutex<Option<Instant>>,
+}
+
+impl ProbeScheduler {
+    pub fn new(min_gap: Duration) -> Self {
+        Self {
+            min_gap,
+            next_slot: Mutex::new(None),
+        }
+    }
+
+    /// Wait until this probe may be sent.
+    pub async fn acquire(&self) {
+        let slot = self.reserve(Instant::now());
+        tokio::time::sleep_until(slot).await;
+    }
+
+    /// Take the next token: the returned slot is `now` if a token is available, otherwise
+    /// the instant it becomes available. Later callers queue behind earlier ones.
+    fn reserve(&self, now: Instant) -> Instant {
+        let mut next = self.next_slot.lock().unwrap_or_else(|e| e.into_inner());
+        let slot = next.map_or(now, |n| n.max(now));
+        *next = Some(slot + self.min_gap);
+        slot
+    }
+}
+
+impl Default for ProbeScheduler {
+    fn default() -> Self {
+        Self::new(PROBE_MIN_GAP)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve_spaces_concurrent_probes() {
+        let sched = ProbeScheduler::new(Duration::from_millis(5));
+        let t0 = Instant::now();
+
+        // Three probes wanting to fire at the same instant are spread out
+        assert_eq!(sched.reserve(t0), t0);
+        assert_eq!(sched.reserve(t0), t0 + Duration::from_millis(5));
+        assert_eq!(sched.reserve(t0), t0 + Duration::from_millis(10));
+
+        // A probe arriving after the queue has drained fires immediately
+        let later = t0 + Duration::from_millis(100);
+        assert_eq!(sched.reserve(later), later);
+        assert_eq!(
+            sched.reserve(later + Duration::from_millis(2)),
+            later + Duration::from_millis(5)
+        );
+    }
+}
@@ -0,0 +1,376 @@
+//! Soak test module
+//!
+//! Keeps the link saturated in one direction for an extended period (`--soak 1h`) and
+//! records per-minute throughput and loaded latency aggregates. Only the per-minute
+//! buckets are kept, so memory stays bounded however long the soak runs. Workers back
+//! off on server-side limits (429/503, connection resets) instead of giving up, so the
+//! load survives request caps and transient failures.
+
+use crate::engine::cloudflare::CloudflareClient;
+use crate::engine::scheduler::ProbeScheduler;
+use crate::engine::throughput::upload_body;
+use crate::engine::wait_if_paused_or_cancelled;
+use crate::model::{Phase, RunConfig, SoakMinute, SoakReport, TestEvent};
+use crate::report::{describe_request_error, ErrorAggregator};
+use crate::stats::latency_summary_from_samples;
+use futures::StreamExt;
+use reqwest::StatusCode;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Length of one aggregation bucket
+const BUCKET: Duration = Duration::from_secs(60);
+
+/// Throughput sampling interval
+const TICK: Duration = Duration::from_secs(1);
+
+/// Retry delay after a failed request, doubled per consecutive failure up to the max
+const BACKOFF_MIN: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// Latency samples of the bucket in progress
+#[derive(Default)]
+struct LatencyBucket {
+    sent: u64,
+    samples: Vec<f64>,
+}
+
+/// Per-second throughput of the bucket in progress
+struct ThroughputBucket {
+    start: Instant,
+    bytes: u64,
+    min_mbps: f64,
+    max_mbps: f64,
+}
+
+impl ThroughputBucket {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            bytes: 0,
+            min_mbps: f64::INFINITY,
+            max_mbps: 0.0,
+        }
+    }
+}
+
+fn backoff(consecutive_failures: u32) -> Duration {
+    BACKOFF_MIN
+        .saturating_mul(1 << consecutive_failures.min(6))
+        .min(BACKOFF_MAX)
+}
+
+pub async fn run_soak(
+    client: &CloudflareClient,
+    cfg: &RunConfig,
+    duration: Duration,
+    event_tx: &mpsc::Sender<TestEvent>,
+    scheduler: Arc<ProbeScheduler>,
+    paused: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+) -> SoakReport {
+    let direction = cfg.soak_direction;
+    let stop = Arc::new(AtomicBool::new(false));
+    let total = Arc::new(AtomicU64::new(0));
+    let errors = Arc::new(AtomicU64::new(0));
+    let failures = Arc::new(ErrorAggregator::default());
+
+    let mut handles = Vec::new();
+    for _ in 0..cfg.concurrency {
+        let http = client.clone();
+        let url = if direction == Phase::Upload {
+            client.up_url()
+        } else {
+            client.down_url(cfg.download_bytes_per_req)
+        };
+        let upload_bytes = cfg.upload_bytes_per_req;
+        let stop2 = stop.clone();
+        let total2 = total.clone();
+        let errors2 = errors.clone();
+        let failures2 = failures.clone();
+        let paused2 = paused.clone();
+
+        handles.push(tokio::spawn(async move {
+            let mut consecutive_failures = 0u32;
+            while !stop2.load(Ordering::Relaxed) {
+                if paused2.load(Ordering::Relaxed) {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    continue;
+                }
+                let req = if direction == Phase::Upload {
+                    http.post(url.clone()).body(upload_body(
+                        upload_bytes,
+                        total2.clone(),
+                        paused2.clone(),
+                    ))
+                } else {
+                    http.get(url.clone())
+                };
+                let failure = match http.send(req).await {
+                    Ok(resp) if resp.status().is_success() => {
+                        let mut stream = resp.bytes_stream();
+                        while let Some(chunk) = stream.next().await {
+                            let Ok(b) = chunk else { break };
+                            if direction != Phase::Upload {
+                                total2.fetch_add(b.len() as u64, Ordering::Relaxed);
+                            }
+                            if stop2.load(Ordering::Relaxed) || paused2.load(Ordering::Relaxed) {
+                                break;
+                            }
+                        }
+                        None
+                    }
+                    Ok(resp) => Some(match resp.status() {
+                        StatusCode::TOO_MANY_REQUESTS => "HTTP 429 (server rate limit)".to_string(),
+                        status => format!("HTTP {}", status),
+                    }),
+                    Err(e) => Some(describe_request_error(&e)),
+                };
+
+                match failure {
+                    None => consecutive_failures = 0,
+                    Some(msg) => {
+                        errors2.fetch_add(1, Ordering::Relaxed);
+                        failures2.record(msg);
+                        tokio::time::sleep(backoff(consecutive_failures)).await;
+                        consecutive_failures += 1;
+                    }
+                }
+            }
+        }));
+    }
+
+    // Loaded latency probes; samples are collected per bucket
+    let latency = Arc::new(Mutex::new(LatencyBucket::default()));
+    let lat_handle = {
+        let client = client.clone();
+        let latency = latency.clone();
+        let stop2 = stop.clone();
+        let scheduler = scheduler.clone();
+        let interval = Duration::from_millis(cfg.probe_interval_ms);
+        let timeout_ms = cfg.probe_timeout_ms;
+        tokio::spawn(async move {
+            while !stop2.load(Ordering::Relaxed) {
+                scheduler.acquire().await;
+                let rtt = client
+                    .probe_latency_ms(direction.as_query_str(), timeout_ms)
+                    .await
+                    .ok()
+                    .map(|(ms, _)| ms);
+                {
+                    let mut b = latency.lock().unwrap_or_else(|e| e.into_inner());
+                    b.sent += 1;
+                    b.samples.extend(rtt);
+                }
+                tokio::time::sleep(interval).await;
+            }
+        })
+    };
+
+    let start = Instant::now();
+    let mut minutes: Vec<SoakMinute> = Vec::new();
+    let mut bucket = ThroughputBucket::new();
+    let mut bucket_errors_start = 0u64;
+    let mut last_bytes = 0u64;
+    let mut last_t = Instant::now();
+    let mut stall_seconds = 0u64;
+
+    let mut close_bucket = |bucket: &ThroughputBucket, errors_now: u64, errors_start: u64| {
+        let secs = bucket.start.elapsed().as_secs_f64().max(1e-9);
+        let lat = std::mem::take(&mut *latency.lock().unwrap_or_else(|e| e.into_inner()));
+        let received = lat.samples.len() as u64;
+        let minute = SoakMinute {
+            minute: minutes.len() as u32 + 1,
+            duration_ms: (secs * 1000.0) as u64,
+            mean_mbps: (bucket.bytes as f64 * 8.0) / secs / 1_000_000.0,
+            min_mbps: if bucket.min_mbps.is_finite() {
+                bucket.min_mbps
+            } else {
+                0.0
+            },
+            max_mbps: bucket.max_mbps,
+            latency: latency_summary_from_samples(lat.sent, received, &lat.samples, None),
+            errors: errors_now.saturating_sub(errors_start),
+        };
+        minutes.push(minute);
+        minutes.last().cloned()
+    };
+
+    while start.elapsed() < duration {
+        if wait_if_paused_or_cancelled(&paused, &cancel).await {
+            break;
+        }
+        tokio::time::sleep(TICK.min(duration.saturating_sub(start.elapsed()))).await;
+
+        let now_total = total.load(Ordering::Relaxed);
+        let dt = last_t.elapsed().as_secs_f64().max(1e-9);
+        let dbytes = now_total.saturating_sub(last_bytes);
+        let bps_instant = dbytes as f64 / dt;
+        let mbps_instant = bps_instant * 8.0 / 1_000_000.0;
+        last_t = Instant::now();
+        last_bytes = now_total;
+
+        if dbytes == 0 {
+            stall_seconds += 1;
+        }
+        bucket.bytes += dbytes;
+        bucket.min_mbps = bucket.min_mbps.min(mbps_instant);
+        bucket.max_mbps = bucket.max_mbps.max(mbps_instant);
+
+        event_tx
+            .send(TestEvent::ThroughputTick {
+                phase: Phase::Soak,
+                bytes_total: now_total,
+                bps_instant,
+            })
+            .await
+            .ok();
+        for line in failures.drain(false) {
+            event_tx
+                .send(TestEvent::Info {
+                    message: format!("Soak: {}", line),
+                })
+                .await
+                .ok();
+        }
+
+        if bucket.start.elapsed() >= BUCKET {
+            let errors_now = errors.load(Ordering::Relaxed);
+            if let Some(m) = close_bucket(&bucket, errors_now, bucket_errors_start) {
+                event_tx
+                    .send(TestEvent::Info {
+                        message: format_minute(&m),
+                    })
+                    .await
+                    .ok();
+            }
+            bucket = ThroughputBucket::new();
+            bucket_errors_start = errors_now;
+        }
+    }
+
+    stop.store(true, Ordering::Relaxed);
+    for h in handles {
+        h.abort();
+    }
+    lat_handle.abort();
+
+    // Keep a trailing partial bucket unless it is too short to mean anything
+    let errors_total = errors.load(Ordering::Relaxed);
+    if bucket.start.elapsed() >= TICK * 10 {
+        close_bucket(&bucket, errors_total, bucket_errors_start);
+    }
+
+    summarize(
+        direction,
+        start.elapsed(),
+        total.load(Ordering::Relaxed),
+        stall_seconds,
+        errors_total,
+        minutes,
+    )
+}
+
+/// One-line minute report, e.g. "Soak minute 3: 94.2 Mbps (88.0-97.1), latency med 41.0 ms".
+pub fn format_minute(m: &SoakMinute) -> String {
+    format!(
+        "Soak minute {}: {:.1} Mbps ({:.1}-{:.1}), latency med {} ms, {} error(s)",
+        m.minute,
+        m.mean_mbps,
+        m.min_mbps,
+        m.max_mbps,
+        m.latency
+            .median_ms
+            .map(|v| format!("{:.1}", v))
+            .unwrap_or_else(|| "-".to_string()),
+        m.errors
+    )
+}
+
+fn summarize(
+    direction: Phase,
+    elapsed: Duration,
+    bytes: u64,
+    stall_seconds: u64,
+    errors: u64,
+    minutes: Vec<SoakMinute>,
+) -> SoakReport {
+    let secs = elapsed.as_secs_f64().max(1e-9);
+    let mean_mbps = (bytes as f64 * 8.0) / secs / 1_000_000.0;
+
+    let means: Vec<f64> = minutes.iter().map(|m| m.mean_mbps).collect();
+    let min_minute_mbps = means.iter().copied().reduce(f64::min);
+    let max_minute_mbps = means.iter().copied().reduce(f64::max);
+    // Coefficient of variation of the minute means: how stable the link was over time
+    let cv_pct = crate::stats::cv_pct(&means);
+    let minute_medians: Vec<f64> = minutes.iter().filter_map(|m| m.latency.median_ms).collect();
+    let latency_median_ms =
+        crate::metrics::compute_metrics(&minute_medians).map(|(_, med, _, _)| med);
+    let latency_worst_minute_ms = minute_medians.iter().copied().reduce(f64::max);
+
+    SoakReport {
+        direction,
+        duration_ms: elapsed.as_millis() as u64,
+        bytes,
+        mean_mbps,
+        min_minute_mbps,
+        max_minute_mbps,
+        cv_pct,
+        stall_seconds,
+        latency_median_ms,
+        latency_worst_minute_ms,
+        errors,
+        minutes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minute(n: u32, mbps: f64, lat: f64) -> SoakMinute {
+        SoakMinute {
+            minute: n,
+            duration_ms: 60_000,
+            mean_mbps: mbps,
+            min_mbps: mbps,
+            max_mbps: mbps,
+            latency: latency_summary_from_samples(2, 2, &[lat, lat], None),
+            errors: 0,
+        }
+    }
+
+    #[test]
+    fn test_summarize_stability() {
+        let report = summarize(
+            Phase::Download,
+            Duration::from_secs(180),
+            3 * 60 * 100_000_000 / 8,
+            2,
+            1,
+            vec![
+                minute(1, 100.0, 20.0),
+                minute(2, 80.0, 40.0),
+                minute(3, 120.0, 30.0),
+            ],
+        );
+        assert!((report.mean_mbps - 100.0).abs() < 1e-6);
+        assert_eq!(report.min_minute_mbps, Some(80.0));
+        assert_eq!(report.max_minute_mbps, Some(120.0));
+        assert!((report.cv_pct.unwrap() - 20.0).abs() < 1e-6);
+        assert_eq!(report.latency_median_ms, Some(30.0));
+        assert_eq!(report.latency_worst_minute_ms, Some(40.0));
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        assert_eq!(backoff(0), BACKOFF_MIN);
+        assert_eq!(backoff(1), BACKOFF_MIN * 2);
+        assert_eq!(backoff(20), BACKOFF_MAX);
+    }
+}
@@ -0,0 +1,626 @@
+//! Socket options for the engine's own connections (`--dscp`, `--congestion`,
+//! `--send-buffer`/`--recv-buffer`)
+//!
+//! reqwest has no hook for options on the sockets it opens, so a connector layer sets them
+//! on each connection as soon as it is established, finding its socket among the
+//! process's descriptors by its addresses. The options thus take effect after the TCP and
+//! TLS handshakes, before any request is sent; `SO_RCVBUF` set that late cannot widen
+//! the receive window beyond the window scale the SYN announced. Through a proxy they
+//! apply to the connection to the proxy. The layer also remembers the addresses of every
+//! connection, so `tcp_info` can find the sockets of one client again.
+
+use crate::model::RunConfig;
+use anyhow::{Context as _, Result};
+use hyper_util::client::legacy::connect::Connection;
+use socket2::SockRef;
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+#[cfg(unix)]
+use std::os::fd::RawFd;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// DSCP code points by name (RFC 2474, 2597, 3246, 5865, 8622)
+const DSCP_NAMES: &[(&str, u8)] = &[
+    ("CS0", 0),
+    ("LE", 1),
+    ("CS1", 8),
+    ("AF11", 10),
+    ("AF12", 12),
+    ("AF13", 14),
+    ("CS2", 16),
+    ("AF21", 18),
+    ("AF22", 20),
+    ("AF23", 22),
+    ("CS3", 24),
+    ("AF31", 26),
+    ("AF32", 28),
+    ("AF33", 30),
+    ("CS4", 32),
+    ("AF41", 34),
+    ("AF42", 36),
+    ("AF43", 38),
+    ("CS5", 40),
+    ("VA", 44),
+    ("EF", 46),
+    ("CS6", 48),
+    ("CS7", 56),
+];
+
+/// Options set on the sockets of marked traffic
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct SocketOptions {
+    /// DSCP code point for the IP header (0-63)
+    pub dscp: Option<u8>,
+    /// TCP congestion control algorithm, e.g. "bbr"; ignored on UDP sockets
+    pub congestion: Option<String>,
+    /// Requested `SO_SNDBUF`, in bytes
+    pub send_buffer: Option<u64>,
+    /// Requested `SO_RCVBUF`, in bytes
+    pub recv_buffer: Option<u64>,
+}
+
+impl SocketOptions {
+    /// Options for the throughput connections
+    pub(crate) fn for_traffic(cfg: &RunConfig) -> Self {
+        Self {
+            dscp: None,
+            congestion: cfg.congestion.clone(),
+            send_buffer: cfg.send_buffer,
+            recv_buffer: cfg.recv_buffer,
+        }
+    }
+
+    /// Options for the latency probes and the UDP probe
+    pub(crate) fn for_probes(cfg: &RunConfig) -> Self {
+        Self {
+            dscp: cfg.dscp,
+            congestion: cfg.congestion.clone(),
+            send_buffer: cfg.send_buffer,
+            recv_buffer: cfg.recv_buffer,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The flags that asked for these options, for error messages
+    pub(crate) fn flags(&self) -> String {
+        let mut flags = Vec::new();
+        if self.dscp.is_some() {
+            flags.push("--dscp");
+        }
+        if self.congestion.is_some() {
+            flags.push("--congestion");
+        }
+        if self.send_buffer.is_some() {
+            flags.push("--send-buffer");
+        }
+        if self.recv_buffer.is_some() {
+            flags.push("--recv-buffer");
+        }
+        flags.join(", ")
+    }
+
+    /// Set the options on `socket`, which talks IPv6 when `ipv6` is set.
+    pub(crate) fn apply(&self, socket: SockRef<'_>, ipv6: bool) -> std::io::Result<()> {
+        if let Some(dscp) = self.dscp {
+            // The DS field is the upper six bits of the former ToS byte
+            let tos = u32::from(dscp) << 2;
+            if ipv6 {
+                set_traffic_class(&socket, tos)?;
+            } else {
+                socket.set_tos(tos)?;
+            }
+        }
+        if let Some(name) = &self.congestion {
+            if socket.r#type()? == socket2::Type::STREAM {
+                set_congestion(&socket, name)?;
+            }
+        }
+        // The kernel takes an int and caps it at its own maximum anyway
+        if let Some(bytes) = self.send_buffer {
+            socket.set_send_buffer_size(bytes.min(i32::MAX as u64 / 2) as usize)?;
+        }
+        if let Some(bytes) = self.recv_buffer {
+            socket.set_recv_buffer_size(bytes.min(i32::MAX as u64 / 2) as usize)?;
+        }
+        Ok(())
+    }
+
+    /// Log the buffer sizes `socket` actually got, which the kernel may have capped
+    /// (`net.core.wmem_max`/`rmem_max` on Linux) or, on Linux, doubled for bookkeeping.
+    pub(crate) fn log_buffers(&self, socket: SockRef<'_>, what: &str) {
+        if self.send_buffer.is_none() && self.recv_buffer.is_none() {
+            return;
+        }
+        let show = |requested: Option<u64>, effective: std::io::Result<usize>| {
+            let effective = effective
+                .map(|bytes| bytes.to_string())
+                .unwrap_or_else(|_| "unknown".to_string());
+            match requested {
+                Some(bytes) => format!("{} (requested {})", effective, bytes),
+                None => effective,
+            }
+        };
+        tracing::debug!(
+            "{} socket buffers: send {}, receive {} bytes",
+            what,
+            show(self.send_buffer, socket.send_buffer_size()),
+            show(self.recv_buffer, socket.recv_buffer_size())
+        );
+    }
+
+    /// Try the options on a scratch socket, so that an unknown or disallowed value fails
+    /// the run up front instead of every connection.
+    fn check(&self) -> Result<()> {
+        let socket = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        self.apply(SockRef::from(&socket), false)
+            .map_err(|e| match &self.congestion {
+                Some(name) => anyhow::Error::new(e).context(format!(
+                    "cannot use TCP congestion control '{}'{}",
+                    name,
+                    available_congestion()
+                        .map(|names| format!(" (available: {})", names))
+                        .unwrap_or_default()
+                )),
+                None => anyhow::Error::new(e).context("cannot set the socket options"),
+            })
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+fn set_congestion(socket: &SockRef<'_>, name: &str) -> std::io::Result<()> {
+    socket.set_tcp_congestion(name.as_bytes())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+fn set_congestion(_socket: &SockRef<'_>, _name: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "choosing the TCP congestion control is not supported on this platform",
+    ))
+}
+
+/// The congestion control algorithm `socket` ended up with
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+fn congestion_of(socket: SockRef<'_>) -> Option<String> {
+    let name = socket.tcp_congestion().ok()?;
+    let name = String::from_utf8_lossy(&name);
+    let name = name.trim_end_matches('\0');
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+fn congestion_of(_socket: SockRef<'_>) -> Option<String> {
+    None
+}
+
+/// Algorithms the kernel has loaded, space separated (Linux)
+fn available_congestion() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/net/ipv4/tcp_available_congestion_control")
+        .ok()
+        .map(|names| names.trim().to_string())
+}
+
+/// The congestion control new TCP connections get by default (Linux)
+pub(crate) fn default_congestion() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/net/ipv4/tcp_congestion_control")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// Parse a `--congestion` value: an algorithm name as the kernel knows it, e.g. bbr or cubic.
+pub fn parse_congestion(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    let valid = !s.is_empty()
+        && s.len() < 16
+        && s.chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-');
+    if valid {
+        Ok(s.to_string())
+    } else {
+        Err(format!(
+            "expected a congestion control algorithm name such as bbr or cubic, got '{}'",
+            s
+        ))
+    }
+}
+
+#[cfg(unix)]
+fn set_traffic_class(socket: &SockRef<'_>, tclass: u32) -> std::io::Result<()> {
+    socket.set_tclass_v6(tclass)
+}
+
+#[cfg(not(unix))]
+fn set_traffic_class(_socket: &SockRef<'_>, _tclass: u32) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "DSCP marking of IPv6 traffic is not supported on this platform",
+    ))
+}
+
+/// Parse a `--dscp` value: a code point name such as EF or AF41, or a number from 0 to 63.
+pub fn parse_dscp(s: &str) -> Result<u8, String> {
+    let s = s.trim();
+    if let Some(&(_, value)) = DSCP_NAMES
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(s))
+    {
+        return Ok(value);
+    }
+    match s.parse::<u8>() {
+        Ok(value) if value < 64 => Ok(value),
+        _ => Err(format!(
+            "expected a DSCP name (EF, AF11-AF43, CS0-CS7, LE, VA) or 0-63, got '{}'",
+            s
+        )),
+    }
+}
+
+/// How a code point is shown in results, e.g. "EF (46)"
+pub fn describe_dscp(value: u8) -> String {
+    match DSCP_NAMES.iter().find(|(_, v)| *v == value) {
+        Some((name, _)) => format!("{} ({})", name, value),
+        None => value.to_string(),
+    }
+}
+
+/// Connector layer setting `SocketOptions` on every connection reqwest opens, right after
+/// it is established, and recording their addresses.
+#[derive(Clone)]
+pub(crate) struct SocketLayer {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    options: SocketOptions,
+    /// Congestion control the latest connection ended up with
+    congestion: Mutex<Option<String>>,
+    /// The effective buffer sizes have been logged
+    logged_buffers: AtomicBool,
+    /// Local and peer address of every connection opened so far
+    connections: Mutex<Vec<(SocketAddr, SocketAddr)>>,
+}
+
+impl SocketLayer {
+    /// A layer setting `options`, which may be empty to only record the connections.
+    pub(crate) fn new(options: SocketOptions) -> Result<Self> {
+        if !options.is_empty() {
+            anyhow::ensure!(
+                cfg!(unix),
+                "{} is not supported on this platform",
+                options.flags()
+            );
+            options.check()?;
+        }
+        Ok(Self {
+            shared: Arc::new(Shared {
+                options,
+                congestion: Mutex::new(None),
+                logged_buffers: AtomicBool::new(false),
+                connections: Mutex::new(Vec::new()),
+            }),
+        })
+    }
+
+    pub(crate) fn options(&self) -> &SocketOptions {
+        &self.shared.options
+    }
+
+    /// Congestion control of the latest connection, as the kernel reports it
+    pub(crate) fn congestion(&self) -> Option<String> {
+        self.shared
+            .congestion
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Local and peer address of every connection opened through the layer, including
+    /// ones closed since
+    pub(crate) fn connections(&self) -> Vec<(SocketAddr, SocketAddr)> {
+        self.shared
+            .connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl<S> Layer<S> for SocketLayer {
+    type Service = WithSocketOptions<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        WithSocketOptions {
+            inner,
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+/// The connector wrapped by `SocketLayer`
+#[derive(Clone)]
+pub(crate) struct WithSocketOptions<S> {
+    inner: S,
+    shared: Arc<Shared>,
+}
+
+impl<S, R> Service<R> for WithSocketOptions<S>
+where
+    S: Service<R, Error = BoxError>,
+    S::Response: Connection + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        let shared = self.shared.clone();
+        let connecting = self.inner.call(req);
+        Box::pin(async move {
+            let conn = connecting.await?;
+            shared.configure(&conn)?;
+            Ok(conn)
+        })
+    }
+}
+
+impl Shared {
+    /// Record the addresses of `conn` and set the options on its socket, which the caller
+    /// keeps open meanwhile.
+    fn configure(&self, conn: &impl Connection) -> Result<()> {
+        let mut extras = http::Extensions::new();
+        conn.connected().get_extras(&mut extras);
+        let info = extras.get::<hyper_util::client::legacy::connect::HttpInfo>();
+        if let Some(info) = info {
+            self.connections
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push((info.local_addr(), info.remote_addr()));
+        }
+        if self.options.is_empty() {
+            return Ok(());
+        }
+        self.apply(info.context("the connection has no socket addresses")?)
+    }
+
+    #[cfg(unix)]
+    fn apply(&self, info: &hyper_util::client::legacy::connect::HttpInfo) -> Result<()> {
+        use std::os::fd::BorrowedFd;
+
+        let fd = find_socket(info.local_addr(), info.remote_addr()).with_context(|| {
+            format!(
+                "cannot find the socket of the connection to {} to set {}",
+                info.remote_addr(),
+                self.options.flags()
+            )
+        })?;
+        // SAFETY: the descriptor is the open connection's, which outlives the borrow
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        self.options
+            .apply(SockRef::from(&fd), info.remote_addr().is_ipv6())
+            .with_context(|| format!("cannot set {}", self.options.flags()))?;
+
+        if let Some(name) = congestion_of(SockRef::from(&fd)) {
+            *self
+                .congestion
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some(name);
+        }
+        if !self.logged_buffers.swap(true, Ordering::Relaxed) {
+            self.options
+                .log_buffers(SockRef::from(&fd), "TCP connection");
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn apply(&self, _info: &hyper_util::client::legacy::connect::HttpInfo) -> Result<()> {
+        // `SocketLayer::new` refuses options here
+        Ok(())
+    }
+}
+
+/// The descriptor of this process's TCP connection from `local` to `peer`. reqwest does
+/// not hand out its sockets, so the process's descriptors are searched for it.
+#[cfg(unix)]
+pub(crate) fn find_socket(local: SocketAddr, peer: SocketAddr) -> Option<RawFd> {
+    use std::os::fd::BorrowedFd;
+
+    let dir = if cfg!(target_os = "linux") {
+        "/proc/self/fd"
+    } else {
+        "/dev/fd"
+    };
+    std::fs::read_dir(dir).ok()?.flatten().find_map(|entry| {
+        let fd = entry.file_name().to_str()?.parse::<RawFd>().ok()?;
+        // A descriptor closed since the directory was read only makes the calls fail
+        let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+        let socket = SockRef::from(&borrowed);
+        let is = |addr: std::io::Result<socket2::SockAddr>, want: SocketAddr| {
+            addr.ok().and_then(|a| a.as_socket()) == Some(want)
+        };
+        (is(socket.local_addr(), local) && is(socket.peer_addr(), peer)).then_some(fd)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dscp() {
+        assert_eq!(parse_dscp("EF"), Ok(46));
+        assert_eq!(parse_dscp("af41"), Ok(34));
+        assert_eq!(parse_dscp("CS1"), Ok(8));
+        assert_eq!(parse_dscp("10"), Ok(10));
+        assert!(parse_dscp("64").is_err());
+        assert!(parse_dscp("AF44").is_err());
+        assert_eq!(describe_dscp(46), "EF (46)");
+        assert_eq!(describe_dscp(5), "5");
+    }
+
+    #[test]
+    fn test_dscp_is_set_on_the_socket() {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let options = SocketOptions {
+            dscp: Some(46),
+            ..Default::default()
+        };
+        options.apply(SockRef::from(&socket), false).unwrap();
+        assert_eq!(SockRef::from(&socket).tos().unwrap(), 46 << 2);
+    }
+
+    /// Make a request through a client with `options`. Returns the client, which pools the
+    /// connection, the server's end of it, and the client's socket.
+    #[cfg(unix)]
+    async fn request_with(
+        options: SocketOptions,
+    ) -> (SocketLayer, reqwest::Client, tokio::net::TcpStream, RawFd) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let server = listener.local_addr().unwrap();
+        let serving = tokio::spawn(async move {
+            let (mut stream, client) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut buf = [0u8; 1024];
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            (stream, client)
+        });
+
+        let layer = SocketLayer::new(options).unwrap();
+        let http = reqwest::Client::builder()
+            .no_proxy()
+            .connector_layer(layer.clone())
+            .build()
+            .unwrap();
+        http.get(format!("http://{}/", server))
+            .send()
+            .await
+            .unwrap();
+        let (stream, client) = serving.await.unwrap();
+        let fd = find_socket(client, server).expect("the client's socket");
+        (layer, http, stream, fd)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_dscp_is_set_on_connections() {
+        let (_layer, _http, _server, fd) = request_with(SocketOptions {
+            dscp: Some(46),
+            ..Default::default()
+        })
+        .await;
+        let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) };
+        assert_eq!(SockRef::from(&fd).tos().unwrap(), 46 << 2);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_connections_are_recorded() {
+        let (layer, _http, _server, fd) = request_with(SocketOptions::default()).await;
+        let connections = layer.connections();
+        assert_eq!(connections.len(), 1);
+        let (local, peer) = connections[0];
+        assert_eq!(find_socket(local, peer), Some(fd));
+    }
+
+    #[test]
+    fn test_parse_congestion() {
+        assert_eq!(parse_congestion("bbr"), Ok("bbr".to_string()));
+        assert_eq!(parse_congestion(" cubic "), Ok("cubic".to_string()));
+        assert!(parse_congestion("").is_err());
+        assert!(parse_congestion("BBR; rm").is_err());
+    }
+
+    #[test]
+    fn test_buffers_are_set_on_the_socket() {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let options = SocketOptions {
+            send_buffer: Some(64 * 1024),
+            recv_buffer: Some(64 * 1024),
+            ..Default::default()
+        };
+        options.apply(SockRef::from(&socket), false).unwrap();
+        // Linux doubles the value; either way it is at least what was asked for
+        let socket = SockRef::from(&socket);
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_buffers_are_set_on_connections() {
+        // Above the usual defaults and below the usual `net.core.*mem_max`
+        let bytes = 200 * 1024;
+        let (_layer, _http, _server, fd) = request_with(SocketOptions {
+            send_buffer: Some(bytes),
+            recv_buffer: Some(bytes),
+            ..Default::default()
+        })
+        .await;
+        let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) };
+        let socket = SockRef::from(&fd);
+        assert!(socket.send_buffer_size().unwrap() >= bytes as usize);
+        assert!(socket.recv_buffer_size().unwrap() >= bytes as usize);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_congestion_is_set_on_the_socket() {
+        // reno is built into every kernel and always allowed
+        let socket = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let options = SocketOptions {
+            congestion: Some("reno".to_string()),
+            ..Default::default()
+        };
+        options.apply(SockRef::from(&socket), false).unwrap();
+        assert_eq!(
+            congestion_of(SockRef::from(&socket)).as_deref(),
+            Some("reno")
+        );
+        assert!(SocketOptions {
+            congestion: Some("no_such_cc".to_string()),
+            ..Default::default()
+        }
+        .check()
+        .is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_congestion_is_set_on_connections() {
+        let (layer, _http, _server, fd) = request_with(SocketOptions {
+            congestion: Some("reno".to_string()),
+            ..Default::default()
+        })
+        .await;
+        let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(fd) };
+        assert_eq!(congestion_of(SockRef::from(&fd)).as_deref(), Some("reno"));
+        assert_eq!(layer.congestion().as_deref(), Some("reno"));
+    }
+}
@@ -0,0 +1,147 @@
+//! Connection-count sweep (`--sweep-concurrency 1,2,4,8`)
+//!
+//! Repeats the download phase once per connection count and compares the results: when
+//! throughput keeps rising with more connections something limits each flow (receive
+//! window, per-flow shaping, a long path); when it is flat from the start the link itself
+//! is the bottleneck.
+
+use crate::engine::cloudflare::CloudflareClient;
+use crate::engine::scheduler::ProbeScheduler;
+use crate::engine::throughput::{run_download_with_loaded_latency, DataBudget};
+use crate::model::{
+    ConcurrencyLevel, ConcurrencySweep, Phase, RunConfig, SweepBottleneck, TestEvent,
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use tokio::sync::mpsc;
+
+/// Throughput has to grow by this factor from the fewest to the best connection count
+/// to count as a per-flow limit
+const PER_FLOW_GAIN: f64 = 1.5;
+
+pub async fn run_concurrency_sweep(
+    client: &CloudflareClient,
+    cfg: &RunConfig,
+    event_tx: &mpsc::Sender<TestEvent>,
+    scheduler: Arc<ProbeScheduler>,
+    budget: Arc<DataBudget>,
+    paused: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+) -> ConcurrencySweep {
+    let mut levels = Vec::new();
+    for &connections in &cfg.sweep_concurrency {
+        if cancel.load(Ordering::Relaxed) || budget.is_spent() {
+            break;
+        }
+        let level_cfg = RunConfig {
+            concurrency: connections,
+            ..cfg.clone()
+        };
+        // Report the level's progress under its own phase so it is not mistaken for the
+        // regular download
+        let (level_tx, mut level_rx) = mpsc::channel::<TestEvent>(256);
+        let forward_tx = event_tx.clone();
+        let forward = tokio::spawn(async move {
+            while let Some(ev) = level_rx.recv().await {
+                let ev = match ev {
+                    TestEvent::ThroughputTick {
+                        bytes_total,
+                        bps_instant,
+                        ..
+                    } => TestEvent::ThroughputTick {
+                        phase: Phase::ConcurrencySweep,
+                        bytes_total,
+                        bps_instant,
+                    },
+                    TestEvent::StreamTick { worker_id, bps, .. } => TestEvent::StreamTick {
+                        phase: Phase::ConcurrencySweep,
+                        worker_id,
+                        bps,
+                    },
+                    TestEvent::LatencySample { .. } => continue,
+                    other => other,
+                };
+                forward_tx.send(ev).await.ok();
+            }
+        });
+
+        let result = run_download_with_loaded_latency(
+            client,
+            &level_cfg,
+            &level_tx,
+            scheduler.clone(),
+            budget.clone(),
+            paused.clone(),
+            cancel.clone(),
+        )
+        .await;
+        drop(level_tx);
+        let _ = forward.await;
+
+        let message = match result {
+            Ok((download, loaded_latency, _)) => {
+                let message = format!(
+                    "Sweep: {} connection(s): {:.2} Mbps",
+                    connections, download.mbps
+                );
+                levels.push(ConcurrencyLevel {
+                    connections,
+                    mbps: download.mbps,
+                    bytes: download.bytes,
+                    duration_ms: download.duration_ms,
+                    loaded_latency_median_ms: loaded_latency.median_ms,
+                });
+                message
+            }
+            Err(e) => format!("Sweep: {} connection(s) failed: {:#}", connections, e),
+        };
+        event_tx.send(TestEvent::Info { message }).await.ok();
+    }
+
+    ConcurrencySweep {
+        bottleneck: bottleneck(&levels),
+        levels,
+    }
+}
+
+/// Compare the fewest connections with the best level; `None` with fewer than two levels.
+pub fn bottleneck(levels: &[ConcurrencyLevel]) -> Option<SweepBottleneck> {
+    let fewest = levels.iter().min_by_key(|l| l.connections)?;
+    let best = levels.iter().max_by(|a, b| a.mbps.total_cmp(&b.mbps))?;
+    if levels.len() < 2 || fewest.mbps <= 0.0 {
+        return None;
+    }
+    Some(
+        if best.connections > fewest.connections && best.mbps >= fewest.mbps * PER_FLOW_GAIN {
+            SweepBottleneck::PerFlow
+        } else {
+            SweepBottleneck::Aggregate
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(connections: usize, mbps: f64) -> ConcurrencyLevel {
+        ConcurrencyLevel {
+            connections,
+            mbps,
+            bytes: 0,
+            duration_ms: 0,
+            loaded_latency_median_ms: None,
+        }
+    }
+
+    #[test]
+    fn test_bottleneck() {
+        let per_flow = [level(1, 40.0), level(4, 150.0), level(8, 180.0)];
+        assert_eq!(bottleneck(&per_flow), Some(SweepBottleneck::PerFlow));
+        let aggregate = [level(1, 95.0), level(4, 100.0), level(8, 98.0)];
+        assert_eq!(bottleneck(&aggregate), Some(SweepBottleneck::Aggregate));
+        assert_eq!(bottleneck(&per_flow[..1]), None);
+    }
+}
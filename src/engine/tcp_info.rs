@@ -0,0 +1,223 @@
+//! Kernel TCP statistics for the throughput connections (Linux)
+//!
+//! reqwest does not hand out its sockets, so at the end of a throughput phase the
+//! sockets of the connections the test client recorded (see `sockets::SocketLayer`) are
+//! looked up among the process's file descriptors by their addresses, and `TCP_INFO` is
+//! read from each. The latency probes use a client of their own and are not included.
+//! Connections that moved no data in the phase's direction during the last second (idle
+//! pooled ones) are left out. Nothing is collected through a proxy, where the statistics
+//! would describe the proxy leg, or over HTTP/3.
+
+use crate::engine::cloudflare::CloudflareClient;
+use crate::model::{RunConfig, TcpConnectionInfo, TcpInfoSummary};
+use std::net::SocketAddr;
+
+/// Which way the phase moves data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Receiving,
+    Sending,
+}
+
+/// A connection counts as part of the phase if it moved data this recently
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const ACTIVE_WITHIN_MS: u32 = 1000;
+
+/// Read `TCP_INFO` from the open connections of `client`; `None` when none were found,
+/// the traffic goes through a proxy or the platform has no `TCP_INFO`.
+pub(crate) fn snapshot(
+    cfg: &RunConfig,
+    client: &CloudflareClient,
+    direction: Direction,
+) -> Option<TcpInfoSummary> {
+    if cfg.proxy.is_some() {
+        return None;
+    }
+    let connections = connections(&client.connections(), direction);
+    (!connections.is_empty()).then(|| summarize(connections))
+}
+
+fn summarize(connections: Vec<TcpConnectionInfo>) -> TcpInfoSummary {
+    let rtts: Vec<f64> = connections.iter().map(|c| c.rtt_ms).collect();
+    let sent: u64 = connections.iter().filter_map(|c| c.bytes_sent).sum();
+    let retransmitted: u64 = connections.iter().filter_map(|c| c.bytes_retrans).sum();
+    TcpInfoSummary {
+        rtt_ms: crate::metrics::percentile(&rtts, 0.5),
+        min_rtt_ms: connections
+            .iter()
+            .filter_map(|c| c.min_rtt_ms)
+            .min_by(f64::total_cmp),
+        retransmit_pct: (sent > 0).then(|| retransmitted as f64 / sent as f64 * 100.0),
+        connections,
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connections(
+    _addrs: &[(SocketAddr, SocketAddr)],
+    _direction: Direction,
+) -> Vec<TcpConnectionInfo> {
+    Vec::new()
+}
+
+#[cfg(target_os = "linux")]
+fn connections(addrs: &[(SocketAddr, SocketAddr)], direction: Direction) -> Vec<TcpConnectionInfo> {
+    use std::os::fd::BorrowedFd;
+
+    addrs
+        .iter()
+        // Connections closed since are no longer found
+        .filter_map(|(local, peer)| crate::engine::sockets::find_socket(*local, *peer))
+        .filter_map(|fd| {
+            // The descriptor belongs to this process; one closed since it was found only
+            // makes the call fail.
+            let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+            read_tcp_info(&fd, direction)
+        })
+        .collect()
+}
+
+/// `struct tcp_info` from linux/tcp.h up to `tcpi_bytes_retrans`; libc's copy stops at
+/// `tcpi_total_retrans`, before the pacing and delivery rates
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct RawTcpInfo {
+    state: u8,
+    ca_state: u8,
+    retransmits: u8,
+    probes: u8,
+    backoff: u8,
+    options: u8,
+    wscale: u8,
+    flags: u8,
+    rto: u32,
+    ato: u32,
+    snd_mss: u32,
+    rcv_mss: u32,
+    unacked: u32,
+    sacked: u32,
+    lost: u32,
+    retrans: u32,
+    fackets: u32,
+    last_data_sent: u32,
+    last_ack_sent: u32,
+    last_data_recv: u32,
+    last_ack_recv: u32,
+    pmtu: u32,
+    rcv_ssthresh: u32,
+    rtt: u32,
+    rttvar: u32,
+    snd_ssthresh: u32,
+    snd_cwnd: u32,
+    advmss: u32,
+    reordering: u32,
+    rcv_rtt: u32,
+    rcv_space: u32,
+    total_retrans: u32,
+    pacing_rate: u64,
+    max_pacing_rate: u64,
+    bytes_acked: u64,
+    bytes_received: u64,
+    segs_out: u32,
+    segs_in: u32,
+    notsent_bytes: u32,
+    min_rtt: u32,
+    data_segs_in: u32,
+    data_segs_out: u32,
+    delivery_rate: u64,
+    busy_time: u64,
+    rwnd_limited: u64,
+    sndbuf_limited: u64,
+    delivered: u32,
+    delivered_ce: u32,
+    bytes_sent: u64,
+    bytes_retrans: u64,
+}
+
+#[cfg(target_os = "linux")]
+fn read_tcp_info(fd: &std::os::fd::BorrowedFd, direction: Direction) -> Option<TcpConnectionInfo> {
+    use std::mem::{offset_of, size_of};
+    use std::os::fd::AsRawFd;
+
+    const TCP_ESTABLISHED: u8 = 1;
+
+    let mut raw = RawTcpInfo::default();
+    let mut len = size_of::<RawTcpInfo>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut raw as *mut RawTcpInfo as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rc != 0 || raw.state != TCP_ESTABLISHED {
+        return None;
+    }
+    let idle_ms = match direction {
+        Direction::Receiving => raw.last_data_recv,
+        Direction::Sending => raw.last_data_sent,
+    };
+    if idle_ms > ACTIVE_WITHIN_MS {
+        return None;
+    }
+
+    // Older kernels fill in a shorter struct; fields past its end stay unset
+    let len = len as usize;
+    macro_rules! field {
+        ($name:ident) => {
+            (len >= offset_of!(RawTcpInfo, $name) + size_of_val(&raw.$name)).then_some(raw.$name)
+        };
+    }
+    let nonzero = |v: Option<u64>| v.filter(|v| *v > 0);
+    Some(TcpConnectionInfo {
+        rtt_ms: raw.rtt as f64 / 1000.0,
+        rtt_var_ms: raw.rttvar as f64 / 1000.0,
+        min_rtt_ms: field!(min_rtt)
+            .filter(|v| *v > 0)
+            .map(|v| v as f64 / 1000.0),
+        cwnd: raw.snd_cwnd,
+        mss: raw.snd_mss,
+        retransmits: raw.total_retrans,
+        pacing_rate_bps: nonzero(field!(pacing_rate)).map(|v| v.saturating_mul(8)),
+        delivery_rate_bps: nonzero(field!(delivery_rate)).map(|v| v.saturating_mul(8)),
+        bytes_received: field!(bytes_received),
+        bytes_sent: field!(bytes_sent),
+        bytes_retrans: field!(bytes_retrans),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(rtt_ms: f64, sent: u64, retrans: u64) -> TcpConnectionInfo {
+        TcpConnectionInfo {
+            rtt_ms,
+            rtt_var_ms: 0.0,
+            min_rtt_ms: Some(rtt_ms / 2.0),
+            cwnd: 10,
+            mss: 1448,
+            retransmits: 0,
+            pacing_rate_bps: None,
+            delivery_rate_bps: None,
+            bytes_received: None,
+            bytes_sent: Some(sent),
+            bytes_retrans: Some(retrans),
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let summary = summarize(vec![
+            connection(10.0, 1_000_000, 10_000),
+            connection(30.0, 1_000_000, 30_000),
+            connection(20.0, 2_000_000, 0),
+        ]);
+        assert_eq!(summary.rtt_ms, Some(20.0));
+        assert_eq!(summary.min_rtt_ms, Some(5.0));
+        assert_eq!(summary.retransmit_pct, Some(1.0));
+    }
+}
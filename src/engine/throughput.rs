@@ -1,7 +1,13 @@
 use crate::engine::cloudflare::CloudflareClient;
-use crate::engine::latency::run_latency_probes;
+use crate::engine::latency::{run_latency_probes, ProbeOptions};
+use crate::engine::scheduler::ProbeScheduler;
+use crate::engine::tcp_info;
 use crate::engine::wait_if_paused_or_cancelled;
-use crate::model::{LatencySummary, Phase, RunConfig, TestEvent, ThroughputSummary};
+use crate::model::{
+    LatencySegments, LatencySummary, PayloadMeasurement, Phase, RunConfig, SteadyWindow, TestEvent,
+    ThroughputSummary,
+};
+use crate::report::{describe_request_error, ErrorAggregator};
 use anyhow::{Context, Result};
 use bytes::Bytes;
 use futures::{stream, StreamExt};
@@ -17,8 +23,125 @@ use tokio::time::Instant;
 /// Chunk size for upload stream generation (64 KB)
 const UPLOAD_CHUNK_SIZE: u64 = 64 * 1024;
 const MIN_DOWNLOAD_BYTES_PER_REQ: u64 = 100_000;
+/// Report upload byte discrepancies above this percentage
+const ACK_DISCREPANCY_WARN_PCT: f64 = 1.0;
+/// `--adaptive`: stability is judged over this many ticks (200 ms each)
+const ADAPTIVE_WINDOW_TICKS: usize = 10;
+/// `--adaptive`: each rate is taken over this many ticks, which evens out socket buffering
+const ADAPTIVE_RATE_TICKS: usize = 5;
+/// `--adaptive`: ticks during this initial slow start are ignored, unless `--warmup` is set
+const ADAPTIVE_WARMUP: Duration = Duration::from_secs(1);
+
+/// Bytes the download and upload workers may still transfer (`--max-total-bytes`), shared
+/// by both phases. Workers reserve the size of each request up front so the limit is never
+/// overshot, and hand back what a failed or interrupted download did not use.
+pub struct DataBudget {
+    limit: Option<u64>,
+    used: AtomicU64,
+}
+
+impl DataBudget {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    /// Take up to `want` bytes; 0 once the budget is spent.
+    pub(crate) fn reserve(&self, want: u64) -> u64 {
+        let Some(limit) = self.limit else {
+            return want;
+        };
+        let mut granted = 0;
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                granted = want.min(limit.saturating_sub(used));
+                Some(used + granted)
+            });
+        granted
+    }
+
+    pub(crate) fn release(&self, unused: u64) {
+        if self.limit.is_some() {
+            self.used.fetch_sub(unused, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn is_spent(&self) -> bool {
+        self.limit
+            .is_some_and(|limit| self.used.load(Ordering::Relaxed) >= limit)
+    }
+}
+
+/// The coefficient of variation of the throughput across the last ticks, once it is below
+/// `threshold` (`--adaptive`). Each tick's rate is taken over the `ADAPTIVE_RATE_TICKS`
+/// before it, since single ticks swing with socket buffering, especially on upload.
+fn stable_cv(threshold: f64, warmup: Duration, samples: &[(Instant, u64)]) -> Option<f64> {
+    let (t0, _) = *samples.first()?;
+    let from = samples
+        .iter()
+        .position(|(t, _)| t.duration_since(t0) >= warmup)?;
+    let rates: Vec<f64> = samples[from..]
+        .windows(ADAPTIVE_RATE_TICKS + 1)
+        .map(|w| {
+            let ((t_start, b_start), (t_end, b_end)) = (w[0], w[ADAPTIVE_RATE_TICKS]);
+            b_end.saturating_sub(b_start) as f64
+                / t_end.duration_since(t_start).as_secs_f64().max(1e-9)
+        })
+        .collect();
+    let window = rates.get(rates.len().checked_sub(ADAPTIVE_WINDOW_TICKS)?..)?;
+    crate::stats::cv_pct(window).filter(|cv| *cv < threshold)
+}
+
+fn stream_totals(streams: &[Arc<AtomicU64>]) -> Vec<u64> {
+    streams.iter().map(|s| s.load(Ordering::Relaxed)).collect()
+}
 
-fn throughput_summary(bytes: u64, duration: Duration, mbps_samples: &[f64]) -> ThroughputSummary {
+/// Emit the rate of each worker since the previous tick.
+async fn send_stream_ticks(
+    event_tx: &mpsc::Sender<TestEvent>,
+    phase: Phase,
+    now: &[u64],
+    last: &[u64],
+    dt: f64,
+) {
+    for (worker_id, (now, last)) in now.iter().zip(last).enumerate() {
+        event_tx
+            .send(TestEvent::StreamTick {
+                phase,
+                worker_id,
+                bps: now.saturating_sub(*last) as f64 / dt,
+            })
+            .await
+            .ok();
+    }
+}
+
+/// Tell the user a phase ended early because the data budget ran out.
+pub(crate) async fn report_budget_spent(
+    event_tx: &mpsc::Sender<TestEvent>,
+    label: &str,
+    cfg: &RunConfig,
+) {
+    event_tx
+        .send(TestEvent::Info {
+            message: format!(
+                "{}: data budget of {} bytes reached, stopping early",
+                label,
+                cfg.max_total_bytes.unwrap_or_default()
+            ),
+        })
+        .await
+        .ok();
+}
+
+pub(crate) fn throughput_summary(
+    bytes: u64,
+    duration: Duration,
+    mbps_samples: &[f64],
+) -> ThroughputSummary {
     // Compute metrics using the same method as metrics.rs for consistency
     let fallback_mbps = || {
         let secs = duration.as_secs_f64().max(1e-9);
@@ -40,67 +163,229 @@ fn throughput_summary(bytes: u64, duration: Duration, mbps_samples: &[f64]) -> T
         median_mbps: Some(median_mbps),
         p25_mbps: Some(p25_mbps),
         p75_mbps: Some(p75_mbps),
+        acknowledged_bytes: None,
+        ack_discrepancy_pct: None,
+        elapsed_ms: None,
+        stabilized: false,
+        stream_bytes: Vec::new(),
+        steady_window: None,
+        tcp_info: None,
+        congestion: None,
+    }
+}
+
+/// Generate an upload body as a bounded stream of bytes.
+/// We count bytes into `total` as we *produce* chunks for reqwest. This is a close
+/// approximation of bytes put on the wire and produces stable realtime Mbps for the UI.
+/// No chunks are produced while `paused` is set.
+pub(crate) fn upload_body(
+    bytes_per_req: u64,
+    total: Arc<AtomicU64>,
+    paused: Arc<AtomicBool>,
+) -> reqwest::Body {
+    let chunk = Bytes::from(vec![0u8; UPLOAD_CHUNK_SIZE as usize]);
+
+    let full = bytes_per_req / UPLOAD_CHUNK_SIZE;
+    let tail = bytes_per_req % UPLOAD_CHUNK_SIZE;
+
+    let sizes =
+        std::iter::repeat_n(UPLOAD_CHUNK_SIZE, full as usize).chain((tail > 0).then_some(tail));
+    let body_stream = stream::iter(sizes).then(move |size| {
+        let chunk = chunk.slice(..size as usize);
+        let total = total.clone();
+        let paused = paused.clone();
+        async move {
+            while paused.load(Ordering::Relaxed) {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            total.fetch_add(size, Ordering::Relaxed);
+            Ok::<Bytes, std::io::Error>(chunk)
+        }
+    });
+
+    reqwest::Body::wrap_stream(body_stream)
+}
+
+/// Percentage of produced bytes the server did not acknowledge.
+fn ack_discrepancy_pct(produced: u64, acknowledged: u64) -> Option<f64> {
+    if produced == 0 {
+        return None;
     }
+    Some((produced.saturating_sub(acknowledged) as f64) * 100.0 / produced as f64)
 }
 
+/// Part of a phase selected by `--steady-window`
+#[derive(Debug, PartialEq)]
+struct SteadyEstimate {
+    bytes: u64,
+    duration: Duration,
+    /// Index of the first tick sample whose rate lies inside the window
+    first_tick: usize,
+}
+
+/// Select the window the phase's figures are computed from; `None` means the whole
+/// phase, either by choice or because the window would be too short.
 fn estimate_steady_window(
     samples: &[(Instant, u64)],
     total_duration: Duration,
-) -> Option<(u64, Duration)> {
+    warmup: Option<Duration>,
+    window: SteadyWindow,
+) -> Option<SteadyEstimate> {
     if samples.len() < 2 {
         return None;
     }
-    let ignore = total_duration.mul_f64(0.20).max(Duration::from_secs(1));
-    let t0 = samples[0].0 + ignore;
-    let start_idx = samples.iter().position(|(t, _)| *t >= t0).unwrap_or(0);
-    let (t_start, b_start) = samples[start_idx];
+    let warmup = warmup.unwrap_or_else(|| total_duration.mul_f64(0.20).max(Duration::from_secs(1)));
     let (t_end, b_end) = *samples.last().unwrap();
+    let after_warmup = samples[0].0 + warmup;
+    let from = match window {
+        SteadyWindow::Full => return None,
+        SteadyWindow::Trimmed => after_warmup,
+        SteadyWindow::Last(d) => t_end
+            .checked_sub(d)
+            .map_or(after_warmup, |t| t.max(after_warmup)),
+    };
+    let start_idx = samples.iter().position(|(t, _)| *t >= from).unwrap_or(0);
+    let (t_start, b_start) = samples[start_idx];
     let dt = t_end.saturating_duration_since(t_start);
-    if dt.as_millis() < 200 {
+    let bytes = b_end.saturating_sub(b_start);
+    // A window that saw no progress (e.g. an upload whose bodies were all taken up by
+    // socket buffers during the warm-up) says nothing about the steady rate
+    if dt.as_millis() < 200 || bytes == 0 {
         return None;
     }
-    Some((b_end.saturating_sub(b_start), dt))
+    Some(SteadyEstimate {
+        bytes,
+        duration: dt,
+        first_tick: start_idx + 1,
+    })
+}
+
+/// Summarize a phase over the window `--warmup`/`--steady-window` select.
+fn steady_summary(
+    samples: &[(Instant, u64)],
+    mbps_samples: &[f64],
+    bytes_total: u64,
+    duration: Duration,
+    cfg: &RunConfig,
+) -> ThroughputSummary {
+    let (mut summary, window) =
+        match estimate_steady_window(samples, duration, cfg.warmup, cfg.steady_window) {
+            Some(w) => (
+                throughput_summary(
+                    w.bytes,
+                    w.duration,
+                    mbps_samples.get(w.first_tick..).unwrap_or_default(),
+                ),
+                cfg.steady_window,
+            ),
+            None => (
+                throughput_summary(bytes_total, duration, mbps_samples),
+                SteadyWindow::Full,
+            ),
+        };
+    summary.steady_window = Some(window);
+    summary
+}
+
+/// `wait_if_paused_or_cancelled`, adding the time spent paused to `paused_for`.
+async fn wait_while_paused(
+    paused: &AtomicBool,
+    cancel: &AtomicBool,
+    paused_for: &mut Duration,
+) -> bool {
+    if !paused.load(Ordering::Relaxed) {
+        return cancel.load(Ordering::Relaxed);
+    }
+    let since = Instant::now();
+    let cancelled = wait_if_paused_or_cancelled(paused, cancel).await;
+    *paused_for += since.elapsed();
+    cancelled
+}
+
+/// Emit aggregated worker errors as info events (see `crate::report`).
+async fn report_failures(
+    event_tx: &mpsc::Sender<TestEvent>,
+    label: &str,
+    failures: &ErrorAggregator,
+    force: bool,
+) {
+    for line in failures.drain(force) {
+        event_tx
+            .send(TestEvent::Info {
+                message: format!("{}: {}", label, line),
+            })
+            .await
+            .ok();
+    }
+}
+
+/// Wait for throughput workers to finish. When the run was cancelled (e.g. by the
+/// max-run-time watchdog) a worker may be stuck on an unresponsive request, so abort instead.
+async fn join_workers(handles: Vec<tokio::task::JoinHandle<()>>, cancel: &AtomicBool) {
+    for h in handles {
+        if cancel.load(Ordering::Relaxed) {
+            h.abort();
+        } else {
+            let _ = h.await;
+        }
+    }
 }
 
 pub async fn run_download_with_loaded_latency(
     client: &CloudflareClient,
     cfg: &RunConfig,
     event_tx: &mpsc::Sender<TestEvent>,
+    scheduler: Arc<ProbeScheduler>,
+    budget: Arc<DataBudget>,
     paused: Arc<AtomicBool>,
     cancel: Arc<AtomicBool>,
-) -> Result<(ThroughputSummary, LatencySummary)> {
+) -> Result<(ThroughputSummary, LatencySummary, LatencySegments)> {
     let stop = Arc::new(AtomicBool::new(false));
-    let total = Arc::new(AtomicU64::new(0));
+    // Bytes per worker (connection); the phase total is their sum
+    let streams: Vec<Arc<AtomicU64>> = (0..cfg.concurrency).map(|_| Arc::default()).collect();
     let errors = Arc::new(AtomicU64::new(0));
+    let failures = Arc::new(ErrorAggregator::default());
 
     let mut handles = Vec::new();
-    for _ in 0..cfg.concurrency {
-        let http = client.http.clone();
-        let base_url = client.down_url();
-        let meas_id = client.meas_id.clone();
+    for stream in &streams {
+        let http = client.clone();
+        let protocols = client.protocols.clone();
         let mut bytes_per_req = cfg.download_bytes_per_req;
         let stop2 = stop.clone();
-        let total2 = total.clone();
+        let stream2 = stream.clone();
         let errors2 = errors.clone();
+        let failures2 = failures.clone();
+        let budget2 = budget.clone();
         let ev_dl = event_tx.clone();
+        let paused2 = paused.clone();
 
         handles.push(tokio::spawn(async move {
             while !stop2.load(Ordering::Relaxed) {
-                let mut url = base_url.clone();
-                url.query_pairs_mut()
-                    .append_pair("measId", &meas_id)
-                    .append_pair("bytes", &bytes_per_req.to_string());
+                if paused2.load(Ordering::Relaxed) {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    continue;
+                }
+                let reserved = budget2.reserve(bytes_per_req);
+                if reserved == 0 {
+                    break;
+                }
+                let url = http.down_url(reserved);
 
-                let resp = match http.get(url).send().await {
+                let resp = match http.send(http.get(url)).await {
                     Ok(r) => r,
-                    Err(_) => {
+                    Err(e) => {
+                        budget2.release(reserved);
                         errors2.fetch_add(1, Ordering::Relaxed);
+                        failures2.record(describe_request_error(&e));
                         continue;
                     }
                 };
 
+                protocols.record(resp.version());
                 if !resp.status().is_success() {
+                    budget2.release(reserved);
                     errors2.fetch_add(1, Ordering::Relaxed);
+                    failures2.record(format!("HTTP {}", resp.status()));
                     if resp.status() == StatusCode::TOO_MANY_REQUESTS {
                         let next = (bytes_per_req / 2).max(MIN_DOWNLOAD_BYTES_PER_REQ);
                         if next < bytes_per_req {
@@ -119,39 +404,49 @@ pub async fn run_download_with_loaded_latency(
                     continue;
                 }
 
+                let mut received = 0u64;
                 let mut stream = resp.bytes_stream();
                 while let Some(chunk) = stream.next().await {
                     let Ok(b) = chunk else { break };
-                    total2.fetch_add(b.len() as u64, Ordering::Relaxed);
-                    if stop2.load(Ordering::Relaxed) {
+                    received += b.len() as u64;
+                    stream2.fetch_add(b.len() as u64, Ordering::Relaxed);
+                    // Dropping the response on pause stops the server from sending more;
+                    // a new request starts on resume
+                    if stop2.load(Ordering::Relaxed) || paused2.load(Ordering::Relaxed) {
                         break;
                     }
                 }
+                budget2.release(reserved.saturating_sub(received));
             }
         }));
     }
 
     // Loaded latency task (during download).
-    let (lat_tx, mut lat_rx) = mpsc::channel::<LatencySummary>(1);
+    let (lat_tx, mut lat_rx) = mpsc::channel::<(LatencySummary, LatencySegments)>(1);
     let client2 = client.clone();
     let ev2 = event_tx.clone();
     let paused2 = paused.clone();
-    let cancel2 = cancel.clone();
+    // Ends the probes with the phase, which may stop early (cancelled, budget spent)
+    let lat_stop = Arc::new(AtomicBool::new(false));
+    let lat_stop2 = lat_stop.clone();
     let cfg2 = cfg.clone();
     let lat_handle = tokio::spawn(async move {
         let res = run_latency_probes(
             &client2,
-            Phase::Download,
-            Some(Phase::Download),
-            cfg2.download_duration,
-            cfg2.probe_interval_ms,
-            cfg2.probe_timeout_ms,
+            ProbeOptions {
+                phase: Phase::Download,
+                during: Some(Phase::Download),
+                duration: cfg2.download_duration,
+                interval_ms: cfg2.probe_interval_ms,
+                timeout_ms: cfg2.probe_timeout_ms,
+                scheduler: &scheduler,
+                paused: paused2,
+                cancel: lat_stop2,
+            },
             &ev2,
-            paused2,
-            cancel2,
         )
         .await
-        .unwrap_or_else(|_| LatencySummary::failed());
+        .unwrap_or_else(|_| (LatencySummary::failed(), LatencySegments::default()));
         let _ = lat_tx.send(res).await;
     });
 
@@ -160,20 +455,28 @@ pub async fn run_download_with_loaded_latency(
     let mut last_t = Instant::now();
     let mut samples: Vec<(Instant, u64)> = Vec::with_capacity(256);
     let mut mbps_samples: Vec<f64> = Vec::with_capacity(256);
-
-    while start.elapsed() < cfg.download_duration {
-        if wait_if_paused_or_cancelled(&paused, &cancel).await {
+    let mut last_streams = vec![0u64; streams.len()];
+    let mut budget_spent = false;
+    let mut stabilized = false;
+    // Time spent paused; the phase runs for its duration of unpaused time
+    let mut paused_for = Duration::ZERO;
+
+    while start.elapsed() - paused_for < cfg.download_duration {
+        if wait_while_paused(&paused, &cancel, &mut paused_for).await {
             break;
         }
 
-        let now_total = total.load(Ordering::Relaxed);
-        let dt = last_t.elapsed().as_secs_f64().max(1e-9);
+        // Sample times are shifted back by the pauses so they form one continuous timeline
+        let now = Instant::now() - paused_for;
+        let stream_totals = stream_totals(&streams);
+        let now_total: u64 = stream_totals.iter().sum();
+        let dt = (now - last_t).as_secs_f64().max(1e-9);
         let dbytes = now_total.saturating_sub(last_bytes);
         let bps_instant = (dbytes as f64) / dt;
         let mbps_instant = (bps_instant * 8.0) / 1_000_000.0;
-        last_t = Instant::now();
+        last_t = now;
         last_bytes = now_total;
-        samples.push((Instant::now(), now_total));
+        samples.push((now, now_total));
         mbps_samples.push(mbps_instant);
 
         event_tx
@@ -184,17 +487,49 @@ pub async fn run_download_with_loaded_latency(
             })
             .await
             .ok();
+        send_stream_ticks(event_tx, Phase::Download, &stream_totals, &last_streams, dt).await;
+        last_streams = stream_totals;
+        report_failures(event_tx, "Download", &failures, false).await;
+
+        // Workers only finish on their own once the budget is spent
+        if budget.is_spent() && handles.iter().all(|h| h.is_finished()) {
+            budget_spent = true;
+            break;
+        }
+        if let Some(cv) = cfg.adaptive_cv_pct.and_then(|threshold| {
+            stable_cv(threshold, cfg.warmup.unwrap_or(ADAPTIVE_WARMUP), &samples)
+        }) {
+            stabilized = true;
+            event_tx
+                .send(TestEvent::Info {
+                    message: format!(
+                        "Download: throughput stable (CV {:.1}%), ending after {:.1}s",
+                        cv,
+                        (start.elapsed() - paused_for).as_secs_f64()
+                    ),
+                })
+                .await
+                .ok();
+            break;
+        }
 
         tokio::time::sleep(Duration::from_millis(200)).await;
     }
 
+    let elapsed = start.elapsed() - paused_for;
+    // Read while the workers' connections are still open
+    let tcp_info = tcp_info::snapshot(cfg, client, tcp_info::Direction::Receiving);
     stop.store(true, Ordering::Relaxed);
-    for h in handles {
-        let _ = h.await;
+    lat_stop.store(true, Ordering::Relaxed);
+    join_workers(handles, &cancel).await;
+    if budget_spent {
+        report_budget_spent(event_tx, "Download", cfg).await;
     }
 
-    let duration = start.elapsed();
-    let bytes_total = total.load(Ordering::Relaxed);
+    let duration = start.elapsed() - paused_for;
+    let stream_bytes = stream_totals(&streams);
+    let bytes_total = stream_bytes.iter().sum();
+    report_failures(event_tx, "Download", &failures, true).await;
     let error_count = errors.load(Ordering::Relaxed);
     if error_count > 0 {
         event_tx
@@ -204,12 +539,15 @@ pub async fn run_download_with_loaded_latency(
             .await
             .ok();
     }
-    let (bytes, window) =
-        estimate_steady_window(&samples, duration).unwrap_or((bytes_total, duration));
-    let dl = throughput_summary(bytes, window, &mbps_samples);
+    let mut dl = steady_summary(&samples, &mbps_samples, bytes_total, duration, cfg);
+    dl.elapsed_ms = Some(elapsed.as_millis() as u64);
+    dl.stabilized = stabilized;
+    dl.stream_bytes = stream_bytes;
+    dl.tcp_info = tcp_info;
+    dl.congestion = client.congestion();
 
     // Wait for latency results with a timeout to prevent indefinite hangs
-    let loaded_latency = tokio::time::timeout(Duration::from_secs(30), lat_rx.recv())
+    let (loaded_latency, segments) = tokio::time::timeout(Duration::from_secs(30), lat_rx.recv())
         .await
         .context("timed out waiting for loaded latency results")?
         .context("loaded latency task ended unexpectedly")?;
@@ -217,88 +555,98 @@ pub async fn run_download_with_loaded_latency(
     // Ensure the latency probe task has completed
     let _ = lat_handle.await;
 
-    Ok((dl, loaded_latency))
+    Ok((dl, loaded_latency, segments))
 }
 
 pub async fn run_upload_with_loaded_latency(
     client: &CloudflareClient,
     cfg: &RunConfig,
     event_tx: &mpsc::Sender<TestEvent>,
+    scheduler: Arc<ProbeScheduler>,
+    budget: Arc<DataBudget>,
     paused: Arc<AtomicBool>,
     cancel: Arc<AtomicBool>,
-) -> Result<(ThroughputSummary, LatencySummary)> {
+) -> Result<(ThroughputSummary, LatencySummary, LatencySegments)> {
     let stop = Arc::new(AtomicBool::new(false));
-    let total = Arc::new(AtomicU64::new(0));
+    // Bytes per worker (connection); the phase total is their sum
+    let streams: Vec<Arc<AtomicU64>> = (0..cfg.concurrency).map(|_| Arc::default()).collect();
     let errors = Arc::new(AtomicU64::new(0));
+    let failures = Arc::new(ErrorAggregator::default());
+    let acked = Arc::new(AtomicU64::new(0));
 
     let mut handles = Vec::new();
-    for _ in 0..cfg.concurrency {
-        let http = client.http.clone();
-        let mut url = client.up_url();
-        url.query_pairs_mut().append_pair("measId", &client.meas_id);
+    for stream in &streams {
+        let http = client.clone();
+        let protocols = client.protocols.clone();
+        let url = client.up_url();
         let stop2 = stop.clone();
-        let total2 = total.clone();
+        let stream2 = stream.clone();
         let errors2 = errors.clone();
+        let failures2 = failures.clone();
+        let acked2 = acked.clone();
+        let budget2 = budget.clone();
         let bytes_per_req = cfg.upload_bytes_per_req;
+        let paused2 = paused.clone();
 
         handles.push(tokio::spawn(async move {
             while !stop2.load(Ordering::Relaxed) {
-                // Generate upload body as a bounded stream of bytes.
-                // We count bytes as we *produce* chunks for reqwest. This is a close approximation
-                // of bytes put on the wire and produces stable realtime Mbps for the UI.
-                let chunk = Bytes::from(vec![0u8; UPLOAD_CHUNK_SIZE as usize]);
-
-                let full = bytes_per_req / UPLOAD_CHUNK_SIZE;
-                let tail = bytes_per_req % UPLOAD_CHUNK_SIZE;
-
-                let total2a = total2.clone();
-                let chunk_full = chunk.clone();
-                let s_full = stream::iter(0..full).map(move |_| {
-                    total2a.fetch_add(UPLOAD_CHUNK_SIZE, Ordering::Relaxed);
-                    Ok::<Bytes, std::io::Error>(chunk_full.clone())
-                });
-
-                let body_stream = if tail == 0 {
-                    s_full.boxed()
-                } else {
-                    let total2b = total2.clone();
-                    let chunk_tail = chunk.slice(..tail as usize);
-                    let s_tail = stream::once(async move {
-                        total2b.fetch_add(tail, Ordering::Relaxed);
-                        Ok::<Bytes, std::io::Error>(chunk_tail)
-                    });
-                    s_full.chain(s_tail).boxed()
-                };
-
-                let body = reqwest::Body::wrap_stream(body_stream);
-                if http.post(url.clone()).body(body).send().await.is_err() {
-                    errors2.fetch_add(1, Ordering::Relaxed);
+                if paused2.load(Ordering::Relaxed) {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    continue;
+                }
+                // Not handed back on failure: part of the body may have been sent
+                let bytes_per_req = budget2.reserve(bytes_per_req);
+                if bytes_per_req == 0 {
+                    break;
+                }
+                let body = upload_body(bytes_per_req, stream2.clone(), paused2.clone());
+                match http.send(http.post(url.clone()).body(body)).await {
+                    // The server only answers once it has read the whole body, so a
+                    // successful status acknowledges every byte of this request.
+                    Ok(resp) if resp.status().is_success() => {
+                        protocols.record(resp.version());
+                        let _ = resp.bytes().await;
+                        acked2.fetch_add(bytes_per_req, Ordering::Relaxed);
+                    }
+                    Ok(resp) => {
+                        errors2.fetch_add(1, Ordering::Relaxed);
+                        failures2.record(format!("HTTP {}", resp.status()));
+                    }
+                    Err(e) => {
+                        errors2.fetch_add(1, Ordering::Relaxed);
+                        failures2.record(describe_request_error(&e));
+                    }
                 }
             }
         }));
     }
 
     // Loaded latency task (during upload).
-    let (lat_tx, mut lat_rx) = mpsc::channel::<LatencySummary>(1);
+    let (lat_tx, mut lat_rx) = mpsc::channel::<(LatencySummary, LatencySegments)>(1);
     let client2 = client.clone();
     let ev2 = event_tx.clone();
     let paused2 = paused.clone();
-    let cancel2 = cancel.clone();
+    // Ends the probes with the phase, which may stop early (cancelled, budget spent)
+    let lat_stop = Arc::new(AtomicBool::new(false));
+    let lat_stop2 = lat_stop.clone();
     let cfg2 = cfg.clone();
     let lat_handle = tokio::spawn(async move {
         let res = run_latency_probes(
             &client2,
-            Phase::Upload,
-            Some(Phase::Upload),
-            cfg2.upload_duration,
-            cfg2.probe_interval_ms,
-            cfg2.probe_timeout_ms,
+            ProbeOptions {
+                phase: Phase::Upload,
+                during: Some(Phase::Upload),
+                duration: cfg2.upload_duration,
+                interval_ms: cfg2.probe_interval_ms,
+                timeout_ms: cfg2.probe_timeout_ms,
+                scheduler: &scheduler,
+                paused: paused2,
+                cancel: lat_stop2,
+            },
             &ev2,
-            paused2,
-            cancel2,
         )
         .await
-        .unwrap_or_else(|_| LatencySummary::failed());
+        .unwrap_or_else(|_| (LatencySummary::failed(), LatencySegments::default()));
         let _ = lat_tx.send(res).await;
     });
 
@@ -307,20 +655,28 @@ pub async fn run_upload_with_loaded_latency(
     let mut last_t = Instant::now();
     let mut samples: Vec<(Instant, u64)> = Vec::with_capacity(256);
     let mut mbps_samples: Vec<f64> = Vec::with_capacity(256);
-
-    while start.elapsed() < cfg.upload_duration {
-        if wait_if_paused_or_cancelled(&paused, &cancel).await {
+    let mut last_streams = vec![0u64; streams.len()];
+    let mut budget_spent = false;
+    let mut stabilized = false;
+    // Time spent paused; the phase runs for its duration of unpaused time
+    let mut paused_for = Duration::ZERO;
+
+    while start.elapsed() - paused_for < cfg.upload_duration {
+        if wait_while_paused(&paused, &cancel, &mut paused_for).await {
             break;
         }
 
-        let now_total = total.load(Ordering::Relaxed);
-        let dt = last_t.elapsed().as_secs_f64().max(1e-9);
+        // Sample times are shifted back by the pauses so they form one continuous timeline
+        let now = Instant::now() - paused_for;
+        let stream_totals = stream_totals(&streams);
+        let now_total: u64 = stream_totals.iter().sum();
+        let dt = (now - last_t).as_secs_f64().max(1e-9);
         let dbytes = now_total.saturating_sub(last_bytes);
         let bps_instant = (dbytes as f64) / dt;
         let mbps_instant = (bps_instant * 8.0) / 1_000_000.0;
-        last_t = Instant::now();
+        last_t = now;
         last_bytes = now_total;
-        samples.push((Instant::now(), now_total));
+        samples.push((now, now_total));
         mbps_samples.push(mbps_instant);
 
         event_tx
@@ -331,17 +687,49 @@ pub async fn run_upload_with_loaded_latency(
             })
             .await
             .ok();
+        send_stream_ticks(event_tx, Phase::Upload, &stream_totals, &last_streams, dt).await;
+        last_streams = stream_totals;
+        report_failures(event_tx, "Upload", &failures, false).await;
+
+        // Workers only finish on their own once the budget is spent
+        if budget.is_spent() && handles.iter().all(|h| h.is_finished()) {
+            budget_spent = true;
+            break;
+        }
+        if let Some(cv) = cfg.adaptive_cv_pct.and_then(|threshold| {
+            stable_cv(threshold, cfg.warmup.unwrap_or(ADAPTIVE_WARMUP), &samples)
+        }) {
+            stabilized = true;
+            event_tx
+                .send(TestEvent::Info {
+                    message: format!(
+                        "Upload: throughput stable (CV {:.1}%), ending after {:.1}s",
+                        cv,
+                        (start.elapsed() - paused_for).as_secs_f64()
+                    ),
+                })
+                .await
+                .ok();
+            break;
+        }
 
         tokio::time::sleep(Duration::from_millis(200)).await;
     }
 
+    let elapsed = start.elapsed() - paused_for;
+    // Read while the workers' connections are still open
+    let tcp_info = tcp_info::snapshot(cfg, client, tcp_info::Direction::Sending);
     stop.store(true, Ordering::Relaxed);
-    for h in handles {
-        let _ = h.await;
+    lat_stop.store(true, Ordering::Relaxed);
+    join_workers(handles, &cancel).await;
+    if budget_spent {
+        report_budget_spent(event_tx, "Upload", cfg).await;
     }
 
-    let duration = start.elapsed();
-    let bytes_total = total.load(Ordering::Relaxed);
+    let duration = start.elapsed() - paused_for;
+    let stream_bytes = stream_totals(&streams);
+    let bytes_total = stream_bytes.iter().sum();
+    report_failures(event_tx, "Upload", &failures, true).await;
     let error_count = errors.load(Ordering::Relaxed);
     if error_count > 0 {
         event_tx
@@ -351,12 +739,32 @@ pub async fn run_upload_with_loaded_latency(
             .await
             .ok();
     }
-    let (bytes, window) =
-        estimate_steady_window(&samples, duration).unwrap_or((bytes_total, duration));
-    let up = throughput_summary(bytes, window, &mbps_samples);
+    let mut up = steady_summary(&samples, &mbps_samples, bytes_total, duration, cfg);
+    up.elapsed_ms = Some(elapsed.as_millis() as u64);
+    up.stabilized = stabilized;
+    up.stream_bytes = stream_bytes;
+    up.tcp_info = tcp_info;
+    up.congestion = client.congestion();
+
+    // Reconcile client-side produced bytes against server-acknowledged requests
+    let acked_total = acked.load(Ordering::Relaxed);
+    let discrepancy_pct = ack_discrepancy_pct(bytes_total, acked_total);
+    up.acknowledged_bytes = Some(acked_total);
+    up.ack_discrepancy_pct = discrepancy_pct;
+    if let Some(pct) = discrepancy_pct.filter(|p| *p > ACK_DISCREPANCY_WARN_PCT) {
+        event_tx
+            .send(TestEvent::Info {
+                message: format!(
+                    "Upload: {:.1}% of sent bytes were not acknowledged by the server",
+                    pct
+                ),
+            })
+            .await
+            .ok();
+    }
 
     // Wait for latency results with a timeout to prevent indefinite hangs
-    let loaded_latency = tokio::time::timeout(Duration::from_secs(30), lat_rx.recv())
+    let (loaded_latency, segments) = tokio::time::timeout(Duration::from_secs(30), lat_rx.recv())
         .await
         .context("timed out waiting for loaded latency results")?
         .context("loaded latency task ended unexpectedly")?;
@@ -364,5 +772,219 @@ pub async fn run_upload_with_loaded_latency(
     // Ensure the latency probe task has completed
     let _ = lat_handle.await;
 
-    Ok((up, loaded_latency))
+    Ok((up, loaded_latency, segments))
+}
+
+/// Payload sizes and request counts of the website's download measurements
+const RAMP_DOWNLOAD: [(u64, u32); 4] = [
+    (100_000, 10),
+    (1_000_000, 8),
+    (10_000_000, 6),
+    (25_000_000, 4),
+];
+
+/// Payload sizes and request counts of the website's upload measurements
+const RAMP_UPLOAD: [(u64, u32); 4] = [
+    (100_000, 8),
+    (1_000_000, 6),
+    (10_000_000, 4),
+    (25_000_000, 4),
+];
+
+/// Like the website, larger payloads are skipped once a size takes this long per request
+const RAMP_STOP_AFTER: Duration = Duration::from_secs(1);
+
+/// Run the staged payload-size measurements: sequential requests of growing size,
+/// timed individually, download sizes first, then upload. Stops at the first request
+/// the data budget cannot cover in full.
+pub async fn run_ramped(
+    client: &CloudflareClient,
+    cfg: &RunConfig,
+    event_tx: &mpsc::Sender<TestEvent>,
+    budget: Arc<DataBudget>,
+    paused: Arc<AtomicBool>,
+    cancel: Arc<AtomicBool>,
+) -> Vec<PayloadMeasurement> {
+    let mut out = Vec::new();
+    for (direction, plan) in [
+        (Phase::Download, RAMP_DOWNLOAD),
+        (Phase::Upload, RAMP_UPLOAD),
+    ] {
+        for (bytes, count) in plan {
+            let mut mbps = Vec::new();
+            let mut latency = Vec::new();
+            let mut durations = Vec::new();
+            let mut errors = 0;
+            for _ in 0..count {
+                if wait_if_paused_or_cancelled(&paused, &cancel).await {
+                    return out;
+                }
+                let reserved = budget.reserve(bytes);
+                if reserved < bytes {
+                    budget.release(reserved);
+                    report_budget_spent(event_tx, "Ramped", cfg).await;
+                    return out;
+                }
+                match timed_request(client, direction, bytes).await {
+                    Ok((ttfb, total)) => {
+                        // The server answers an upload only after reading the body, so
+                        // its time to first byte is the transfer time
+                        let transfer = if direction == Phase::Upload {
+                            ttfb
+                        } else {
+                            latency.push(ttfb.as_secs_f64() * 1000.0);
+                            total - ttfb
+                        };
+                        let secs = transfer.as_secs_f64().max(1e-6);
+                        mbps.push(bytes as f64 * 8.0 / secs / 1_000_000.0);
+                        durations.push(total.as_secs_f64());
+                    }
+                    Err(_) => errors += 1,
+                }
+            }
+
+            let m = PayloadMeasurement {
+                direction,
+                bytes,
+                count: mbps.len() as u32,
+                errors,
+                median_mbps: crate::metrics::percentile(&mbps, 0.5),
+                p90_mbps: crate::metrics::percentile(&mbps, 0.9),
+                median_latency_ms: crate::metrics::percentile(&latency, 0.5),
+            };
+            event_tx
+                .send(TestEvent::Info {
+                    message: format!(
+                        "{:?} {} kB x{}: {} Mbps median",
+                        direction,
+                        bytes / 1000,
+                        m.count,
+                        m.median_mbps
+                            .map(|v| format!("{:.1}", v))
+                            .unwrap_or_else(|| "-".to_string())
+                    ),
+                })
+                .await
+                .ok();
+            out.push(m);
+
+            let slow = crate::metrics::percentile(&durations, 0.5)
+                .is_none_or(|d| d > RAMP_STOP_AFTER.as_secs_f64());
+            if slow {
+                break;
+            }
+        }
+    }
+    out
+}
+
+/// One measurement request; returns the time to the response headers and the total time.
+async fn timed_request(
+    client: &CloudflareClient,
+    direction: Phase,
+    bytes: u64,
+) -> Result<(Duration, Duration)> {
+    let start = Instant::now();
+    let resp = if direction == Phase::Upload {
+        let url = client.up_url();
+        client
+            .send(client.post(url).body(vec![0u8; bytes as usize]))
+            .await?
+    } else {
+        let url = client.down_url(bytes);
+        client.send(client.get(url)).await?
+    };
+    let ttfb = start.elapsed();
+    client.protocols.record(resp.version());
+    anyhow::ensure!(resp.status().is_success(), "HTTP {}", resp.status());
+    resp.bytes().await?;
+    Ok((ttfb, start.elapsed()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_is_never_overshot() {
+        let budget = DataBudget::new(Some(25));
+        assert_eq!(budget.reserve(10), 10);
+        assert_eq!(budget.reserve(10), 10);
+        assert_eq!(budget.reserve(10), 5);
+        assert!(budget.is_spent());
+        // A failed request hands its reservation back
+        budget.release(5);
+        assert!(!budget.is_spent());
+        assert_eq!(budget.reserve(10), 5);
+        assert_eq!(budget.reserve(10), 0);
+
+        let unlimited = DataBudget::new(None);
+        assert_eq!(unlimited.reserve(10), 10);
+        assert!(!unlimited.is_spent());
+    }
+
+    #[test]
+    fn test_steady_window() {
+        // 10 s at 1 MB per 200 ms tick
+        let t0 = Instant::now();
+        let samples: Vec<(Instant, u64)> = (1..=50)
+            .map(|i| (t0 + Duration::from_millis(200 * i), i * 1_000_000))
+            .collect();
+        let phase = Duration::from_secs(10);
+        let estimate = |warmup, window| estimate_steady_window(&samples, phase, warmup, window);
+
+        // Default warm-up: 20% of the phase, the first tick at 200 ms plus 2 s
+        let trimmed = estimate(None, SteadyWindow::Trimmed).unwrap();
+        assert_eq!(trimmed.first_tick, 11);
+        assert_eq!(trimmed.duration, Duration::from_millis(7800));
+        let warmup = estimate(Some(Duration::from_secs(3)), SteadyWindow::Trimmed).unwrap();
+        assert_eq!(warmup.first_tick, 16);
+        let last = estimate(None, SteadyWindow::Last(Duration::from_secs(5))).unwrap();
+        assert_eq!(last.bytes, 25_000_000);
+        assert_eq!(last.duration, Duration::from_secs(5));
+        // Never reaching back into the warm-up
+        let long = estimate(None, SteadyWindow::Last(Duration::from_secs(60))).unwrap();
+        assert_eq!(long, trimmed);
+        assert!(estimate(None, SteadyWindow::Full).is_none());
+        // Nothing moved after the warm-up: falls back to the whole phase
+        let stalled: Vec<(Instant, u64)> = samples
+            .iter()
+            .map(|(t, b)| (*t, (*b).min(5_000_000)))
+            .collect();
+        assert!(estimate_steady_window(&stalled, phase, None, SteadyWindow::Trimmed).is_none());
+    }
+
+    #[test]
+    fn test_stable_cv() {
+        let t0 = Instant::now();
+        let ticks = |n: u64, bytes: &dyn Fn(u64) -> u64| -> Vec<(Instant, u64)> {
+            (0..n)
+                .map(|i| (t0 + Duration::from_millis(200 * i), bytes(i)))
+                .collect()
+        };
+        // Steady 1 MB per tick after a slow start
+        let steady = ticks(20, &|i| i.saturating_sub(2) * 1_000_000);
+        assert!(stable_cv(5.0, ADAPTIVE_WARMUP, &steady).is_some());
+        // Not enough ticks after warm-up yet
+        assert!(stable_cv(5.0, ADAPTIVE_WARMUP, &steady[..19]).is_none());
+        // Still accelerating
+        let ramping = ticks(20, &|i| i * i * 100_000);
+        assert!(stable_cv(5.0, ADAPTIVE_WARMUP, &ramping).is_none());
+        // Swinging between 2 and 0.5 MB per tick every second: the average since the
+        // warm-up settles, the rate does not
+        let swinging = ticks(40, &|i| {
+            (1..=i)
+                .map(|k| if (k / 5) % 2 == 1 { 2_000_000 } else { 500_000 })
+                .sum()
+        });
+        assert!(stable_cv(5.0, ADAPTIVE_WARMUP, &swinging).is_none());
+        // Per-tick noise of up to 20% evens out over each rate's second
+        let noise = [1.0, 1.2, 0.8, 1.1, 0.9];
+        let noisy = ticks(40, &|i| {
+            (1..=i)
+                .map(|k| (noise[(k * 3 % 5) as usize] * 1_000_000.0) as u64)
+                .sum()
+        });
+        assert!(stable_cv(5.0, ADAPTIVE_WARMUP, &noisy).is_some());
+    }
 }
@@ -0,0 +1,172 @@
+//! Per-request timing breakdown
+//!
+//! reqwest reports neither when a connection was opened nor how long each step took, so
+//! the breakdown comes from a request made by hand: resolve the server, connect, run the
+//! TLS handshake and send an HTTP/1.1 `GET __down?bytes=0`, timing each step up to the
+//! first response byte. It runs once as the latency probes start and once alongside the
+//! first requests of the download and upload phases, always over a fresh connection.
+
+use crate::engine::backend;
+use crate::engine::network_bind;
+use crate::engine::resolver::DnsResolver;
+use crate::model::{Phase, RequestTiming, RunConfig};
+use anyhow::{Context, Result};
+use reqwest::Url;
+use rustls::pki_types::ServerName;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio_rustls::TlsConnector;
+
+/// Give up on a single step after this long
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+pub async fn measure_request_timing(cfg: &RunConfig, phase: Phase) -> Result<RequestTiming> {
+    if cfg.proxy.is_some() {
+        anyhow::bail!("not measured through a proxy");
+    }
+    let url = Url::parse(&cfg.base_url).context("invalid base_url")?;
+    let host = url.host_str().context("base_url has no host")?.to_string();
+    let port = url.port_or_known_default().unwrap_or(443);
+    let start = Instant::now();
+
+    // DNS, skipped for IP literals and `--resolve` pins
+    let pinned = crate::engine::cloudflare::pinned_endpoint(cfg).map(|(_, addr)| addr.ip());
+    let (addr, dns_ms) = match host
+        .trim_matches(['[', ']'])
+        .parse::<IpAddr>()
+        .ok()
+        .or(pinned)
+    {
+        Some(ip) => (SocketAddr::new(ip, port), None),
+        None => {
+            let lookup = async {
+                match DnsResolver::from_config(cfg)? {
+                    Some(resolver) => resolver.lookup(&host).await,
+                    None => Ok(tokio::net::lookup_host((host.as_str(), port))
+                        .await?
+                        .map(|a| a.ip())
+                        .collect()),
+                }
+            };
+            let ips = tokio::time::timeout(STEP_TIMEOUT, lookup)
+                .await
+                .context("DNS lookup timed out")?
+                .with_context(|| format!("DNS lookup failed for {}", host))?;
+            let ip = ips
+                .into_iter()
+                .find(|ip| cfg.allows_address(ip))
+                .with_context(|| format!("no usable address for {}", host))?;
+            (SocketAddr::new(ip, port), Some(ms(start.elapsed())))
+        }
+    };
+
+    let connect_start = Instant::now();
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    if let Some(bind) =
+        network_bind::resolve_bind_address(cfg.interface.as_ref(), cfg.source_ip.as_ref())?
+    {
+        socket.bind(bind)?;
+    }
+    let tcp = tokio::time::timeout(STEP_TIMEOUT, socket.connect(addr))
+        .await
+        .context("TCP connect timed out")?
+        .with_context(|| format!("TCP connect to {} failed", addr))?;
+    tcp.set_nodelay(true).ok();
+    let connect_ms = ms(connect_start.elapsed());
+
+    let target = backend::for_config(cfg)?.download_url(0);
+    // The headers CloudflareClient sends, with the same --header overrides
+    let auth = cfg
+        .endpoint_auth_token
+        .as_ref()
+        .map(|token| format!("Bearer {}", token));
+    let defaults = [
+        ("User-Agent", cfg.user_agent.as_str()),
+        ("Referer", "https://speed.cloudflare.com/"),
+    ];
+    let headers: String = defaults
+        .into_iter()
+        .chain(auth.as_deref().map(|auth| ("Authorization", auth)))
+        .filter(|(name, _)| {
+            !cfg.headers
+                .iter()
+                .any(|(h, _)| h.eq_ignore_ascii_case(name))
+        })
+        .chain(cfg.headers.iter().map(|(n, v)| (n.as_str(), v.as_str())))
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect();
+    let request = format!(
+        "GET {}?{} HTTP/1.1\r\nHost: {}\r\n{}Connection: close\r\n\r\n",
+        target.path(),
+        target.query().unwrap_or_default(),
+        match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.clone(),
+        },
+        headers
+    );
+
+    let (tls_ms, ttfb) = if url.scheme() == "https" {
+        let tls_start = Instant::now();
+        let tls = tokio::time::timeout(STEP_TIMEOUT, tls_handshake(cfg, &host, tcp))
+            .await
+            .context("TLS handshake timed out")??;
+        let tls_ms = ms(tls_start.elapsed());
+        (Some(tls_ms), first_byte(tls, &request).await?)
+    } else {
+        (None, first_byte(tcp, &request).await?)
+    };
+
+    Ok(RequestTiming {
+        phase,
+        dns_ms,
+        connect_ms,
+        tls_ms,
+        ttfb_ms: ms(ttfb),
+        total_ms: ms(start.elapsed()),
+    })
+}
+
+async fn tls_handshake(
+    cfg: &RunConfig,
+    host: &str,
+    tcp: TcpStream,
+) -> Result<tokio_rustls::client::TlsStream<TcpStream>> {
+    let mut config = crate::engine::cloudflare::tls_config(cfg)?;
+    // The request below is plain HTTP/1.1
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    let server_name: ServerName<'static> = host
+        .to_string()
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Invalid DNS name: {}", host))?;
+    TlsConnector::from(Arc::new(config))
+        .connect(server_name, tcp)
+        .await
+        .with_context(|| format!("TLS handshake failed with {}", host))
+}
+
+/// Send the request and wait for the first byte of the response.
+async fn first_byte<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    request: &str,
+) -> Result<Duration> {
+    let sent = Instant::now();
+    stream.write_all(request.as_bytes()).await?;
+    let mut byte = [0u8; 1];
+    let n = tokio::time::timeout(STEP_TIMEOUT, stream.read(&mut byte))
+        .await
+        .context("no response")??;
+    anyhow::ensure!(n == 1, "connection closed before the response");
+    Ok(sent.elapsed())
+}
@@ -1,42 +1,135 @@
 //! TLS handshake time measurement module
 
-use crate::model::TlsSummary;
+use crate::model::{RunConfig, TlsSummary};
 use anyhow::{Context, Result};
+use rustls::client::{
+    ClientSessionMemoryCache, ClientSessionStore, Tls12ClientSessionValue, Tls13ClientSessionValue,
+};
 use rustls::pki_types::ServerName;
-use std::sync::Arc;
+use rustls::{NamedGroup, ProtocolVersion};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::time::Instant;
 use tokio::net::TcpStream;
 use tokio_rustls::TlsConnector;
 
 /// Install the ring crypto provider if not already installed.
-fn ensure_crypto_provider() {
+pub(crate) fn ensure_crypto_provider() {
     // Install the ring provider as the default crypto provider.
     // This is safe to call multiple times - it will be a no-op if already installed.
     let _ = rustls::crypto::ring::default_provider().install_default();
 }
 
-/// Measure TLS handshake time for a given hostname.
+/// Session cache of the test traffic's connections that notes what they negotiated, as
+/// reqwest does not expose a connection's TLS state. The sessions rustls stores for
+/// resumption tell the protocol version; TLS 1.3 tickets tell the cipher suite too.
+#[derive(Debug)]
+pub struct NegotiatedTls {
+    cache: ClientSessionMemoryCache,
+    /// Protocol version and cipher suite of the latest stored session
+    last: Mutex<Option<(String, Option<String>)>>,
+}
+
+impl NegotiatedTls {
+    pub fn new() -> Self {
+        Self {
+            cache: ClientSessionMemoryCache::new(256),
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Protocol version and cipher suite of the latest connection the server gave a
+    /// session to; `None` before any has. The cipher suite is unknown with TLS 1.2.
+    pub fn last(&self) -> Option<(String, Option<String>)> {
+        self.last
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn note(&self, version: ProtocolVersion, cipher_suite: Option<String>) {
+        *self.last.lock().unwrap_or_else(PoisonError::into_inner) =
+            Some((format!("{:?}", version), cipher_suite));
+    }
+}
+
+impl Default for NegotiatedTls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientSessionStore for NegotiatedTls {
+    fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup) {
+        self.cache.set_kx_hint(server_name, group)
+    }
+
+    fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<NamedGroup> {
+        self.cache.kx_hint(server_name)
+    }
+
+    fn set_tls12_session(&self, server_name: ServerName<'static>, value: Tls12ClientSessionValue) {
+        self.note(ProtocolVersion::TLSv1_2, None);
+        self.cache.set_tls12_session(server_name, value)
+    }
+
+    fn tls12_session(&self, server_name: &ServerName<'_>) -> Option<Tls12ClientSessionValue> {
+        self.cache.tls12_session(server_name)
+    }
+
+    fn remove_tls12_session(&self, server_name: &ServerName<'static>) {
+        self.cache.remove_tls12_session(server_name)
+    }
+
+    fn insert_tls13_ticket(
+        &self,
+        server_name: ServerName<'static>,
+        value: Tls13ClientSessionValue,
+    ) {
+        self.note(
+            ProtocolVersion::TLSv1_3,
+            Some(format!("{:?}", value.suite().common.suite)),
+        );
+        self.cache.insert_tls13_ticket(server_name, value)
+    }
+
+    fn take_tls13_ticket(
+        &self,
+        server_name: &ServerName<'static>,
+    ) -> Option<Tls13ClientSessionValue> {
+        self.cache.take_tls13_ticket(server_name)
+    }
+}
+
+/// Measure TLS handshake time for a given hostname, connecting to the `--resolve` pin if
+/// any and otherwise to the first of its addresses `-4`/`-6` allow.
 ///
 /// This measures only the TLS handshake, not including TCP connection time.
 /// Returns a `TlsSummary` with handshake time, protocol version, and cipher suite.
-pub async fn measure_tls_handshake(hostname: &str, port: u16) -> Result<TlsSummary> {
+pub async fn measure_tls_handshake(
+    cfg: &RunConfig,
+    hostname: &str,
+    port: u16,
+) -> Result<TlsSummary> {
     // Ensure the crypto provider is installed
     ensure_crypto_provider();
 
-    // Create root certificate store from webpki-roots
-    let mut root_store = rustls::RootCertStore::empty();
-    root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-
-    // Build TLS client config
+    // Build TLS client config, trusting `--certificate` like the test client
     let config = rustls::ClientConfig::builder()
-        .with_root_certificates(root_store)
+        .with_root_certificates(crate::engine::cloudflare::root_store(cfg)?)
         .with_no_client_auth();
 
     let connector = TlsConnector::from(Arc::new(config));
 
     // First establish TCP connection (we don't time this)
-    let addr = format!("{}:{}", hostname, port);
-    let tcp_stream = TcpStream::connect(&addr)
+    let addr = match crate::engine::cloudflare::pinned_endpoint(cfg) {
+        Some((_, addr)) => addr,
+        None => tokio::net::lookup_host((hostname, port))
+            .await
+            .with_context(|| format!("DNS lookup failed for {}", hostname))?
+            .find(|addr| cfg.allows_address(&addr.ip()))
+            .with_context(|| format!("no usable address for {}", hostname))?,
+    };
+    let tcp_stream = TcpStream::connect(addr)
         .await
         .with_context(|| format!("TCP connection failed to {}", addr))?;
 
@@ -1,56 +1,177 @@
 //! Traceroute functionality module
 //!
 //! Provides traceroute functionality to measure network path to Cloudflare edge.
-//! Uses raw ICMP sockets when available (requires CAP_NET_RAW or root),
-//! with fallback to system traceroute command.
-
-use crate::model::{TestEvent, TracerouteHop, TracerouteSummary};
+//! Uses raw ICMP sockets when available (requires CAP_NET_RAW or root), then UDP
+//! probes over ordinary sockets (Linux), with fallback to system traceroute command.
+//! `--traceroute-paris` keeps the flow identifiers constant across probes, so ECMP load
+//! balancing cannot mix hops of different paths into one trace; `--traceroute-paths`
+//! traces several such flows to enumerate the paths.
+//! Hops are then named by reverse DNS and placed in their autonomous system and country
+//! through Team Cymru's IP-to-ASN service, which answers over DNS.
+
+use super::resolver::DnsResolver;
+use super::traceroute_unprivileged::{self, TCP_PORT, UDP_BASE_PORT};
+use crate::model::{
+    RunConfig, TestEvent, TracerouteHop, TraceroutePath, TracerouteProtocol, TracerouteSummary,
+};
 use anyhow::{Context, Result};
 use pnet_packet::icmp::IcmpTypes;
 use socket2::{Domain, Protocol, Socket, Type};
-use std::io::ErrorKind;
-use std::mem::MaybeUninit;
-use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
 use std::process::Command;
 use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 
 /// Number of probes per hop
-const PROBES_PER_HOP: usize = 3;
+pub(crate) const PROBES_PER_HOP: usize = 3;
 
 /// Timeout for each probe
-const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Gap between consecutive probes; a burst would trip ICMP rate limits on routers
+pub(crate) const PROBE_GAP: Duration = Duration::from_millis(5);
+
+/// Responder and round trip in milliseconds of each probe at one TTL
+pub(crate) type HopReplies = [Option<(IpAddr, f64)>; PROBES_PER_HOP];
+
+/// How long the name and ASN lookups for the hops may take together
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// ICMPv6 echo message types (RFC 4443)
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
 
 /// Run traceroute to the destination.
 ///
-/// Tries raw ICMP first, falls back to system traceroute if that fails.
+/// With `--traceroute-protocol auto`, tries raw ICMP first, then UDP over ordinary
+/// sockets, and falls back to system traceroute if both fail. An explicit protocol is
+/// used alone.
 pub async fn run_traceroute(
     destination: &str,
-    max_hops: u8,
+    cfg: &RunConfig,
     event_tx: &mpsc::Sender<TestEvent>,
 ) -> Result<TracerouteSummary> {
     // Resolve destination to IP
-    let ip = resolve_destination(destination)?;
+    let ip = resolve_destination(destination, cfg)?;
+    let mut summary = probe_path(destination, &ip, cfg, event_tx).await?;
+    if cfg.traceroute_paths > 1 {
+        summary.paths = trace_other_flows(&ip, &summary, cfg).await;
+    }
+    let hops = summary.hops.iter_mut().chain(
+        summary
+            .paths
+            .iter_mut()
+            .flat_map(|path| path.hops.iter_mut()),
+    );
+    enrich_hops(hops.collect(), cfg).await;
+
+    for hop in &summary.hops {
+        let _ = event_tx
+            .send(TestEvent::TracerouteHop {
+                hop_number: hop.hop_number,
+                hop: hop.clone(),
+            })
+            .await;
+    }
+    Ok(summary)
+}
+
+/// Probe the path with the method `--traceroute-protocol` asks for.
+async fn probe_path(
+    destination: &str,
+    ip: &IpAddr,
+    cfg: &RunConfig,
+    event_tx: &mpsc::Sender<TestEvent>,
+) -> Result<TracerouteSummary> {
+    let max_hops = cfg.traceroute_max_hops;
+    let paris = cfg.traceroute_paris || cfg.traceroute_paths > 1;
+    // The first Paris flow
+    let icmp_flow = paris.then_some(0);
+
+    match cfg.traceroute_protocol {
+        TracerouteProtocol::Icmp => {
+            return run_icmp_traceroute(ip, max_hops, icmp_flow, Some(event_tx)).await
+        }
+        TracerouteProtocol::Tcp if paris => {
+            anyhow::bail!("Paris traceroute needs ICMP or UDP probes")
+        }
+        TracerouteProtocol::Udp | TracerouteProtocol::Tcp => {
+            let tcp = cfg.traceroute_protocol == TracerouteProtocol::Tcp;
+            return run_unprivileged_traceroute(ip, tcp, max_hops, paris, Some(event_tx)).await;
+        }
+        TracerouteProtocol::Auto => {}
+    }
 
     // Try raw ICMP first
-    match run_icmp_traceroute(&ip, max_hops, event_tx).await {
+    let icmp_error = match run_icmp_traceroute(ip, max_hops, icmp_flow, Some(event_tx)).await {
         Ok(summary) => return Ok(summary),
+        Err(e) => e,
+    };
+    match run_unprivileged_traceroute(ip, false, max_hops, paris, Some(event_tx)).await {
+        Ok(summary) => {
+            let _ = event_tx
+                .send(TestEvent::Info {
+                    message: format!("ICMP traceroute unavailable ({}), used UDP", icmp_error),
+                })
+                .await;
+            return Ok(summary);
+        }
+        // The system command cannot hold the flow constant
+        Err(e) if paris => anyhow::bail!(
+            "ICMP traceroute unavailable ({}), UDP traceroute unavailable ({})",
+            icmp_error,
+            e
+        ),
         Err(e) => {
             // Send info about fallback
             let _ = event_tx
                 .send(TestEvent::Info {
-                    message: format!("ICMP traceroute unavailable ({}), using system command", e),
+                    message: format!(
+                        "ICMP traceroute unavailable ({}), UDP traceroute unavailable ({}), using system command",
+                        icmp_error, e
+                    ),
                 })
                 .await;
         }
     }
 
     // Fall back to system traceroute
-    run_system_traceroute(destination, &ip, max_hops, event_tx).await
+    run_system_traceroute(destination, ip, max_hops).await
+}
+
+/// Trace the further flows `--traceroute-paths` asks for, at once and with the method
+/// the first flow used. Flows that fail are left out.
+async fn trace_other_flows(
+    ip: &IpAddr,
+    first: &TracerouteSummary,
+    cfg: &RunConfig,
+) -> Vec<TraceroutePath> {
+    let max_hops = cfg.traceroute_max_hops;
+    let icmp = first.method.as_deref() == Some("icmp");
+    let flows = (1..u16::from(cfg.traceroute_paths)).map(|flow| async move {
+        let summary = if icmp {
+            run_icmp_traceroute(ip, max_hops, Some(flow), None).await
+        } else {
+            run_unprivileged_traceroute(ip, false, max_hops, true, None).await
+        };
+        let summary = summary.ok()?;
+        Some(TraceroutePath {
+            flow: summary.flow?,
+            hops: summary.hops,
+            completed: summary.completed,
+        })
+    });
+    futures::future::join_all(flows)
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
 }
 
-/// Resolve destination hostname to IP address.
-fn resolve_destination(destination: &str) -> Result<IpAddr> {
+/// Resolve destination hostname to an IP address `-4`/`-6` allow.
+fn resolve_destination(destination: &str, cfg: &RunConfig) -> Result<IpAddr> {
     // Try to parse as IP first
     if let Ok(ip) = destination.parse::<IpAddr>() {
         return Ok(ip);
@@ -60,17 +181,24 @@ fn resolve_destination(destination: &str) -> Result<IpAddr> {
     let addr = format!("{}:0", destination)
         .to_socket_addrs()
         .with_context(|| format!("Failed to resolve {}", destination))?
-        .next()
+        .find(|addr| cfg.allows_address(&addr.ip()))
         .ok_or_else(|| anyhow::anyhow!("No addresses found for {}", destination))?;
 
     Ok(addr.ip())
 }
 
 /// Run traceroute using raw ICMP sockets (requires elevated privileges).
+///
+/// Probes for all TTLs are in flight at once, a few milliseconds apart, and replies are
+/// matched to their probe by the ICMP id and sequence number quoted back in them, so
+/// the whole path takes about one probe timeout rather than one per hop. With a Paris
+/// `flow`, the id is offset by it and every probe carries the same checksum. Replies
+/// are reported to `live` as they arrive.
 async fn run_icmp_traceroute(
     destination: &IpAddr,
     max_hops: u8,
-    event_tx: &mpsc::Sender<TestEvent>,
+    flow: Option<u16>,
+    live: Option<&mpsc::Sender<TestEvent>>,
 ) -> Result<TracerouteSummary> {
     // Check if we're dealing with IPv4 - IPv6 traceroute is more complex
     let dest_v4 = match destination {
@@ -85,108 +213,374 @@ async fn run_icmp_traceroute(
     // Try to create raw ICMP socket
     let socket = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))
         .context("Failed to create raw ICMP socket (need CAP_NET_RAW or root)")?;
+    socket.set_nonblocking(true)?;
+    // Datagram semantics are all tokio's UDP socket relies on
+    let socket = UdpSocket::from_std(std::net::UdpSocket::from(socket))?;
+    let dest_addr = SocketAddr::new(IpAddr::V4(dest_v4), 0);
+
+    let id = (std::process::id() as u16).wrapping_add(flow.unwrap_or(0));
+    let mut probes = Probes::new(id, dest_v4, max_hops, live);
+
+    // One round of all TTLs per probe, reading replies while pacing the next probe
+    for probe_num in 0..PROBES_PER_HOP {
+        for ttl in 1..=max_hops {
+            if probes.reached.is_some_and(|hop| ttl > hop) {
+                break;
+            }
+            socket2::SockRef::from(&socket).set_ttl(u32::from(ttl))?;
+            let icmp_seq = (u16::from(ttl) << 8) | (probe_num as u16);
+            let packet = if flow.is_some() {
+                build_paris_packet(probes.id, icmp_seq)
+            } else {
+                build_icmp_packet(probes.id, icmp_seq)
+            };
+            if socket.send_to(&packet, dest_addr).await.is_ok() {
+                probes.sent_at[usize::from(ttl) - 1][probe_num] = Some(Instant::now());
+            }
+            read_replies(&socket, &mut probes, PROBE_GAP).await;
+        }
+    }
 
-    socket.set_read_timeout(Some(PROBE_TIMEOUT))?;
-    socket.set_nonblocking(false)?;
-
-    let mut hops = Vec::new();
-    let mut completed = false;
-
-    for ttl in 1..=max_hops {
-        socket.set_ttl(ttl as u32)?;
+    // Late replies get one probe timeout
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    while probes.pending() && Instant::now() < deadline {
+        read_replies(&socket, &mut probes, Duration::from_millis(50)).await;
+    }
 
-        let mut rtts = Vec::new();
-        let mut hop_ip: Option<IpAddr> = None;
-        let mut timeout = false;
+    Ok(TracerouteSummary {
+        destination: destination.to_string(),
+        hops: hops_from_replies(&probes.replies, probes.reached),
+        completed: probes.reached.is_some(),
+        method: Some("icmp".into()),
+        flow: flow.map(|_| id),
+        paths: Vec::new(),
+    })
+}
 
-        for probe_num in 0..PROBES_PER_HOP {
-            let icmp_id = std::process::id() as u16;
-            let icmp_seq = ((ttl as u16) << 8) | (probe_num as u16);
+/// Run traceroute with UDP datagrams to high ports or TCP SYNs to port 443 over
+/// ordinary sockets, which needs no privileges (Linux only). `paris` holds the UDP flow
+/// constant, each call tracing a new one.
+async fn run_unprivileged_traceroute(
+    destination: &IpAddr,
+    tcp: bool,
+    max_hops: u8,
+    paris: bool,
+    live: Option<&mpsc::Sender<TestEvent>>,
+) -> Result<TracerouteSummary> {
+    let port = if tcp { TCP_PORT } else { UDP_BASE_PORT };
+    let target = SocketAddr::new(*destination, port);
+    let (replies, reached, flow) = if paris {
+        let (replies, reached, flow) =
+            traceroute_unprivileged::trace_paris(target, max_hops, live).await?;
+        (replies, reached, Some(flow))
+    } else {
+        let (replies, reached) =
+            traceroute_unprivileged::trace(target, tcp, max_hops, live).await?;
+        (replies, reached, None)
+    };
 
-            // Build ICMP echo request packet
-            let packet = build_icmp_packet(icmp_id, icmp_seq);
+    Ok(TracerouteSummary {
+        destination: destination.to_string(),
+        hops: hops_from_replies(&replies, reached),
+        completed: reached.is_some(),
+        method: Some(if tcp { "tcp" } else { "udp" }.into()),
+        flow,
+        paths: Vec::new(),
+    })
+}
 
-            let dest_addr = SocketAddr::new(IpAddr::V4(dest_v4), 0);
+/// Report a reply matched to its probe while the trace runs, for live views. Dropped
+/// rather than waited on when the receiver lags; the hops follow in full anyway.
+pub(crate) fn report_reply(
+    live: Option<&mpsc::Sender<TestEvent>>,
+    ttl: u8,
+    from: IpAddr,
+    rtt_ms: f64,
+) {
+    if let Some(tx) = live {
+        let _ = tx.try_send(TestEvent::TracerouteReply {
+            hop_number: ttl,
+            ip_address: from.to_string(),
+            rtt_ms,
+        });
+    }
+}
 
-            let start = Instant::now();
-            if socket.send_to(&packet, &dest_addr.into()).is_err() {
-                continue;
+/// Turn the replies per TTL into hops up to the destination (or every TTL when it was
+/// not reached).
+fn hops_from_replies(replies: &[HopReplies], reached: Option<u8>) -> Vec<TracerouteHop> {
+    let last = reached.map_or(replies.len(), usize::from);
+    (1..)
+        .zip(&replies[..last])
+        .map(|(ttl, replies)| {
+            // RTTs stay in probe order; the first responder names the hop
+            let hop_ip = replies.iter().flatten().map(|(ip, _)| *ip).next();
+            TracerouteHop {
+                hop_number: ttl,
+                ip_address: hop_ip.map(|ip| ip.to_string()),
+                hostname: None,
+                rtt_ms: replies.iter().flatten().map(|(_, rtt)| *rtt).collect(),
+                timeout: hop_ip.is_none(),
+                asn: None,
+                as_name: None,
+                country: None,
             }
+        })
+        .collect()
+}
 
-            // Wait for reply using MaybeUninit buffer
-            let mut recv_buf: [MaybeUninit<u8>; 512] =
-                unsafe { MaybeUninit::uninit().assume_init() };
-            match socket.recv_from(&mut recv_buf) {
-                Ok((len, from)) => {
-                    let rtt = start.elapsed().as_secs_f64() * 1000.0;
-                    rtts.push(rtt);
-
-                    // Extract source IP from reply
-                    let from_addr: SocketAddr = from.as_socket().unwrap_or(dest_addr);
-                    if hop_ip.is_none() {
-                        hop_ip = Some(from_addr.ip());
-                    }
-
-                    // Check if we've reached the destination
-                    if from_addr.ip() == IpAddr::V4(dest_v4) {
-                        completed = true;
-                    }
-
-                    // Check ICMP type to see if we should continue
-                    if len >= 20 + 8 {
-                        // IP header + ICMP header
-                        // Safe to read since we received at least 28 bytes
-                        let icmp_type = unsafe { recv_buf[20].assume_init() };
-                        if icmp_type == IcmpTypes::EchoReply.0 {
-                            completed = true;
-                        }
-                    }
-                }
-                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
-                    timeout = true;
-                }
-                Err(_) => {
-                    timeout = true;
-                }
+/// Fill in the reverse DNS name of each hop, and the ASN, AS name and country of those
+/// with public addresses. Each address is looked up once, at the `--dns-server`/`--doh`/
+/// `--dot` resolver or the system's first nameserver; whatever has not answered within
+/// `LOOKUP_TIMEOUT` stays empty.
+async fn enrich_hops(hops: Vec<&mut TracerouteHop>, cfg: &RunConfig) {
+    let resolver = match DnsResolver::from_config(cfg) {
+        Ok(Some(resolver)) => resolver,
+        _ => match DnsResolver::system() {
+            Some(resolver) => resolver,
+            None => return,
+        },
+    };
+    let resolver = &resolver;
+
+    let mut ips: Vec<IpAddr> = hops
+        .iter()
+        .filter_map(|hop| hop.ip_address.as_deref()?.parse().ok())
+        .collect();
+    ips.sort_unstable();
+    ips.dedup();
+
+    let lookups = ips.iter().map(|&ip| async move {
+        let name = async { resolver.reverse(&ip).await.ok().flatten() };
+        let origin = async {
+            if !is_public(&ip) {
+                return None;
             }
+            let records = resolver.txt(&cymru_origin_name(&ip)).await.ok()?;
+            records.iter().find_map(|txt| parse_cymru_origin(txt))
+        };
+        let (name, origin) = tokio::join!(name, origin);
+        (ip, (name, origin))
+    });
+    let Ok(found) = tokio::time::timeout(LOOKUP_TIMEOUT, futures::future::join_all(lookups)).await
+    else {
+        return;
+    };
+    let found: HashMap<IpAddr, _> = found.into_iter().collect();
+
+    // AS names, once per ASN
+    let mut asns: Vec<u32> = found
+        .values()
+        .filter_map(|(_, origin)| origin.as_ref().map(|(asn, _)| *asn))
+        .collect();
+    asns.sort_unstable();
+    asns.dedup();
+    let names = asns.iter().map(|asn| async move {
+        let records = resolver.txt(&format!("AS{asn}.asn.cymru.com")).await.ok()?;
+        let name = records.iter().find_map(|txt| parse_cymru_as_name(txt))?;
+        Some((*asn, name))
+    });
+    let as_names: HashMap<u32, String> =
+        tokio::time::timeout(LOOKUP_TIMEOUT, futures::future::join_all(names))
+            .await
+            .map(|names| names.into_iter().flatten().collect())
+            .unwrap_or_default();
+
+    for hop in hops {
+        let ip = hop.ip_address.as_deref().and_then(|ip| ip.parse().ok());
+        let Some((name, origin)) = ip.and_then(|ip: IpAddr| found.get(&ip)) else {
+            continue;
+        };
+        hop.hostname = name.clone();
+        if let Some((asn, country)) = origin {
+            hop.asn = Some(*asn);
+            hop.as_name = as_names.get(asn).cloned();
+            hop.country = country.clone();
         }
+    }
+}
 
-        let hop = TracerouteHop {
-            hop_number: ttl,
-            ip_address: hop_ip.map(|ip| ip.to_string()),
-            hostname: hop_ip.and_then(|ip| resolve_hostname(&ip)),
-            rtt_ms: rtts,
-            timeout: timeout && hop_ip.is_none(),
-        };
+/// Whether `ip` is routed on the internet, and so has an origin AS
+fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            let shared = a == 100 && (64..128).contains(&b);
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || shared)
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            let unique_local = first & 0xfe00 == 0xfc00;
+            let link_local = first & 0xffc0 == 0xfe80;
+            !(v6.is_loopback() || v6.is_unspecified() || unique_local || link_local)
+        }
+    }
+}
 
-        // Send hop event
-        let _ = event_tx
-            .send(TestEvent::TracerouteHop {
-                hop_number: ttl,
-                hop: hop.clone(),
-            })
-            .await;
+/// Team Cymru origin query for `ip`: "1.2.0.192.origin.asn.cymru.com", or the nibbles
+/// under "origin6.asn.cymru.com" for IPv6
+fn cymru_origin_name(ip: &IpAddr) -> String {
+    let reverse = super::resolver::reverse_name(ip);
+    match ip {
+        IpAddr::V4(_) => reverse.replace("in-addr.arpa", "origin.asn.cymru.com"),
+        IpAddr::V6(_) => reverse.replace("ip6.arpa", "origin6.asn.cymru.com"),
+    }
+}
 
-        hops.push(hop);
+/// ASN and registered country from an origin record,
+/// "13335 | 104.16.0.0/13 | US | arin | 2014-03-28". Prefixes announced by several
+/// ASes list them all; the first is kept.
+fn parse_cymru_origin(txt: &str) -> Option<(u32, Option<String>)> {
+    let fields: Vec<&str> = txt.split('|').map(str::trim).collect();
+    let asn = fields.first()?.split_whitespace().next()?.parse().ok()?;
+    let country = fields
+        .get(2)
+        .filter(|country| !country.is_empty())
+        .map(|country| country.to_string());
+    Some((asn, country))
+}
+
+/// AS name from an AS record, "13335 | US | arin | 2010-07-14 | CLOUDFLARENET, US"
+fn parse_cymru_as_name(txt: &str) -> Option<String> {
+    let name = txt.split('|').nth(4)?.trim();
+    (!name.is_empty()).then(|| name.to_string())
+}
 
-        if completed {
-            break;
+/// Probes in flight, indexed by TTL - 1 and probe number
+struct Probes<'a> {
+    id: u16,
+    destination: Ipv4Addr,
+    sent_at: Vec<[Option<Instant>; PROBES_PER_HOP]>,
+    replies: Vec<HopReplies>,
+    /// Lowest TTL the destination answered at
+    reached: Option<u8>,
+    live: Option<&'a mpsc::Sender<TestEvent>>,
+}
+
+impl<'a> Probes<'a> {
+    fn new(
+        id: u16,
+        destination: Ipv4Addr,
+        max_hops: u8,
+        live: Option<&'a mpsc::Sender<TestEvent>>,
+    ) -> Self {
+        Self {
+            id,
+            destination,
+            sent_at: vec![[None; PROBES_PER_HOP]; usize::from(max_hops)],
+            replies: vec![[None; PROBES_PER_HOP]; usize::from(max_hops)],
+            reached: None,
+            live,
         }
     }
 
-    Ok(TracerouteSummary {
-        destination: destination.to_string(),
-        hops,
-        completed,
-    })
+    /// Match a reply read from the raw socket to the probe it answers.
+    fn record(&mut self, buf: &[u8], from: IpAddr) {
+        let Some((icmp_type, seq)) = probe_sequence(buf, self.id) else {
+            return;
+        };
+        let ttl = (seq >> 8) as u8;
+        let probe = usize::from(seq & 0xff);
+        let index = usize::from(ttl).wrapping_sub(1);
+        let Some(sent) = self
+            .sent_at
+            .get(index)
+            .and_then(|probes| probes.get(probe))
+            .copied()
+            .flatten()
+        else {
+            return;
+        };
+        let rtt = sent.elapsed().as_secs_f64() * 1000.0;
+        self.replies[index][probe] = Some((from, rtt));
+        report_reply(self.live, ttl, from, rtt);
+        if icmp_type == IcmpTypes::EchoReply.0 || from == IpAddr::V4(self.destination) {
+            self.reached = Some(self.reached.map_or(ttl, |hop| hop.min(ttl)));
+        }
+    }
+
+    /// Whether a probe up to the destination (or the last hop) still awaits its reply
+    fn pending(&self) -> bool {
+        let last = usize::from(self.reached.unwrap_or(self.sent_at.len() as u8));
+        self.sent_at[..last]
+            .iter()
+            .zip(&self.replies)
+            .any(|(sent, replies)| {
+                sent.iter()
+                    .zip(replies)
+                    .any(|(s, r)| s.is_some() && r.is_none())
+            })
+    }
+}
+
+/// Record the replies arriving within `wait`.
+async fn read_replies(socket: &UdpSocket, probes: &mut Probes<'_>, wait: Duration) {
+    let until = tokio::time::Instant::now() + wait;
+    let mut buf = [0u8; 512];
+    while let Ok(Ok((len, from))) = tokio::time::timeout_at(until, socket.recv_from(&mut buf)).await
+    {
+        probes.record(&buf[..len], from.ip());
+    }
+}
+
+/// ICMP type of a reply read from a raw IPv4 socket, and the sequence number of the echo
+/// request with identifier `id` it answers: echo replies carry it directly, time
+/// exceeded and unreachable messages in the datagram they quote.
+fn probe_sequence(buf: &[u8], id: u16) -> Option<(u8, u16)> {
+    let header = usize::from(buf.first()? & 0x0f) * 4;
+    let icmp = buf.get(header..)?;
+    let icmp_type = *icmp.first()?;
+    let request = if icmp_type == IcmpTypes::EchoReply.0 {
+        icmp
+    } else if icmp_type == IcmpTypes::TimeExceeded.0
+        || icmp_type == IcmpTypes::DestinationUnreachable.0
+    {
+        let quoted = icmp.get(8..)?;
+        quoted.get(usize::from(quoted.first()? & 0x0f) * 4..)?
+    } else {
+        return None;
+    };
+    if request.get(4..6)? != id.to_be_bytes() {
+        return None;
+    }
+    Some((
+        icmp_type,
+        u16::from_be_bytes([*request.get(6)?, *request.get(7)?]),
+    ))
 }
 
 /// Build an ICMP echo request packet.
 fn build_icmp_packet(id: u16, seq: u16) -> Vec<u8> {
-    let mut packet = vec![0u8; 64];
+    build_echo_request(false, id, seq, 56)
+}
+
+/// Build an echo request for a Paris flow: the first two payload bytes carry the
+/// complement of `seq`, which keeps the checksum (that ICMP load balancing hashes on)
+/// the same for every probe with identifier `id`.
+fn build_paris_packet(id: u16, seq: u16) -> Vec<u8> {
+    let mut packet = build_echo_request(false, id, seq, 56);
+    packet[8..10].copy_from_slice(&(!seq).to_be_bytes());
+    packet[2..4].fill(0);
+    let checksum = calculate_icmp_checksum(&packet);
+    packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    packet
+}
+
+/// Build an echo request with `payload` bytes of data. ICMPv6 checksums cover a
+/// pseudo-header and are left to the kernel; ping sockets also set their own identifier.
+pub(crate) fn build_echo_request(ipv6: bool, id: u16, seq: u16, payload: usize) -> Vec<u8> {
+    let mut packet = vec![0u8; 8 + payload];
 
     // ICMP header
-    packet[0] = IcmpTypes::EchoRequest.0; // Type
+    packet[0] = if ipv6 {
+        ICMPV6_ECHO_REQUEST
+    } else {
+        IcmpTypes::EchoRequest.0
+    }; // Type
     packet[1] = 0; // Code
     packet[2] = 0; // Checksum (will be calculated)
     packet[3] = 0;
@@ -196,18 +590,53 @@ fn build_icmp_packet(id: u16, seq: u16) -> Vec<u8> {
     packet[7] = (seq & 0xff) as u8;
 
     // Payload (timestamp and padding)
-    for i in 8..64 {
-        packet[i] = (i - 8) as u8;
+    for (i, byte) in packet.iter_mut().enumerate().skip(8) {
+        *byte = (i - 8) as u8;
     }
 
     // Calculate checksum
-    let checksum = calculate_icmp_checksum(&packet);
-    packet[2] = (checksum >> 8) as u8;
-    packet[3] = (checksum & 0xff) as u8;
+    if !ipv6 {
+        let checksum = calculate_icmp_checksum(&packet);
+        packet[2] = (checksum >> 8) as u8;
+        packet[3] = (checksum & 0xff) as u8;
+    }
 
     packet
 }
 
+/// Whether `buf`, as read from a socket opened by `open_icmp_socket`, is the echo reply
+/// with sequence number `seq`. Raw IPv4 sockets deliver the IP header too.
+pub(crate) fn is_echo_reply(buf: &[u8], ipv6: bool, raw: bool, seq: u16) -> bool {
+    let buf = if raw && !ipv6 {
+        let header = usize::from(buf.first().map_or(0, |b| b & 0x0f)) * 4;
+        buf.get(header..).unwrap_or_default()
+    } else {
+        buf
+    };
+    let reply = if ipv6 {
+        ICMPV6_ECHO_REPLY
+    } else {
+        IcmpTypes::EchoReply.0
+    };
+    buf.len() >= 8 && buf[0] == reply && buf[6..8] == seq.to_be_bytes()
+}
+
+/// Open an ICMP socket for `ip`'s family: a raw one when privileges allow (CAP_NET_RAW
+/// or root), otherwise an unprivileged ping socket (`net.ipv4.ping_group_range` on
+/// Linux, always on macOS). Also returns whether the socket is raw.
+pub(crate) fn open_icmp_socket(ip: &IpAddr) -> Result<(Socket, bool)> {
+    let (domain, protocol) = match ip {
+        IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+        IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+    };
+    if let Ok(socket) = Socket::new(domain, Type::RAW, Some(protocol)) {
+        return Ok((socket, true));
+    }
+    let socket = Socket::new(domain, Type::DGRAM, Some(protocol))
+        .context("cannot open an ICMP socket (need CAP_NET_RAW or ping_group_range)")?;
+    Ok((socket, false))
+}
+
 /// Calculate ICMP checksum.
 fn calculate_icmp_checksum(data: &[u8]) -> u16 {
     let mut sum: u32 = 0;
@@ -229,19 +658,11 @@ fn calculate_icmp_checksum(data: &[u8]) -> u16 {
     !sum as u16
 }
 
-/// Try to resolve an IP address to a hostname.
-fn resolve_hostname(_ip: &IpAddr) -> Option<String> {
-    // Skip hostname resolution for now to keep it simple
-    // In production, we'd want async reverse DNS resolution
-    None
-}
-
 /// Fall back to system traceroute command.
 async fn run_system_traceroute(
     destination: &str,
     destination_ip: &IpAddr,
     max_hops: u8,
-    event_tx: &mpsc::Sender<TestEvent>,
 ) -> Result<TracerouteSummary> {
     // Clone strings to avoid lifetime issues with spawn_blocking
     let dest = destination.to_string();
@@ -278,7 +699,7 @@ async fn run_system_traceroute(
         .context("Failed to execute traceroute command")?;
 
     let stdout = String::from_utf8_lossy(&output.stdout);
-    let hops = parse_traceroute_output(&stdout, event_tx).await;
+    let hops = parse_traceroute_output(&stdout);
 
     let completed = hops
         .last()
@@ -289,14 +710,14 @@ async fn run_system_traceroute(
         destination: destination.to_string(),
         hops,
         completed,
+        method: Some("system".into()),
+        flow: None,
+        paths: Vec::new(),
     })
 }
 
 /// Parse traceroute command output into hop structures.
-async fn parse_traceroute_output(
-    output: &str,
-    event_tx: &mpsc::Sender<TestEvent>,
-) -> Vec<TracerouteHop> {
+fn parse_traceroute_output(output: &str) -> Vec<TracerouteHop> {
     let mut hops = Vec::new();
 
     for line in output.lines() {
@@ -317,12 +738,6 @@ async fn parse_traceroute_output(
         // Windows: "  1    <1 ms    <1 ms    <1 ms  192.168.1.1"
 
         if let Some(hop) = parse_hop_line(line) {
-            let _ = event_tx
-                .send(TestEvent::TracerouteHop {
-                    hop_number: hop.hop_number,
-                    hop: hop.clone(),
-                })
-                .await;
             hops.push(hop);
         }
     }
@@ -348,6 +763,9 @@ fn parse_hop_line(line: &str) -> Option<TracerouteHop> {
             hostname: None,
             rtt_ms: Vec::new(),
             timeout: true,
+            asn: None,
+            as_name: None,
+            country: None,
         });
     }
 
@@ -395,5 +813,88 @@ fn parse_hop_line(line: &str) -> Option<TracerouteHop> {
         hostname: None,
         rtt_ms: rtts,
         timeout: false,
+        asn: None,
+        as_name: None,
+        country: None,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_echo_request_checksum() {
+        let packet = build_echo_request(false, 1, 1, 0);
+        assert_eq!(packet, [8, 0, 0xf7, 0xfd, 0, 1, 0, 1]);
+        // A packet with its checksum in place sums to zero
+        assert_eq!(calculate_icmp_checksum(&packet), 0);
+        assert_eq!(build_icmp_packet(1, 1).len(), 64);
+
+        // Paris probes of one flow share their checksum
+        let first = build_paris_packet(7, 0x0100);
+        let later = build_paris_packet(7, 0x1e02);
+        assert_eq!(first[2..4], later[2..4]);
+        assert_eq!(calculate_icmp_checksum(&later), 0);
+        assert_ne!(first[2..4], build_paris_packet(8, 0x0100)[2..4]);
+    }
+
+    #[test]
+    fn test_probe_sequence() {
+        let request = build_icmp_packet(0x1234, 0x0502);
+        let mut ip_header = vec![0u8; 20];
+        ip_header[0] = 0x45;
+
+        // Echo reply from the destination
+        let mut reply = ip_header.clone();
+        reply.extend(&request);
+        reply[20] = IcmpTypes::EchoReply.0;
+        assert_eq!(probe_sequence(&reply, 0x1234), Some((0, 0x0502)));
+        assert_eq!(probe_sequence(&reply, 0x4321), None);
+
+        // Time exceeded quoting the IP header and start of the request
+        let mut exceeded = ip_header.clone();
+        exceeded.extend([IcmpTypes::TimeExceeded.0, 0, 0, 0, 0, 0, 0, 0]);
+        exceeded.extend(&ip_header);
+        exceeded.extend(&request[..8]);
+        assert_eq!(probe_sequence(&exceeded, 0x1234), Some((11, 0x0502)));
+
+        let (live, mut replies) = mpsc::channel(8);
+        let mut probes = Probes::new(0x1234, Ipv4Addr::new(192, 0, 2, 1), 8, Some(&live));
+        probes.sent_at[4][2] = Some(Instant::now());
+        assert!(probes.pending());
+        probes.record(&exceeded, "198.51.100.1".parse().unwrap());
+        assert!(probes.replies[4][2].is_some());
+        assert_eq!(probes.reached, None);
+        assert!(!probes.pending());
+        assert!(matches!(
+            replies.try_recv(),
+            Ok(TestEvent::TracerouteReply { hop_number: 5, ref ip_address, .. })
+                if ip_address == "198.51.100.1"
+        ));
+    }
+
+    #[test]
+    fn test_cymru_records() {
+        assert_eq!(
+            cymru_origin_name(&"104.16.1.2".parse().unwrap()),
+            "2.1.16.104.origin.asn.cymru.com"
+        );
+        assert_eq!(
+            parse_cymru_origin("13335 | 104.16.0.0/13 | US | arin | 2014-03-28"),
+            Some((13335, Some("US".to_string())))
+        );
+        assert_eq!(
+            parse_cymru_origin("64500 64501 | 192.0.2.0/24 |  | other |"),
+            Some((64500, None))
+        );
+        assert_eq!(parse_cymru_origin("garbage"), None);
+        assert_eq!(
+            parse_cymru_as_name("13335 | US | arin | 2010-07-14 | CLOUDFLARENET, US"),
+            Some("CLOUDFLARENET, US".to_string())
+        );
+        assert!(!is_public(&"100.64.0.1".parse().unwrap()));
+        assert!(!is_public(&"fd00::1".parse().unwrap()));
+        assert!(is_public(&"2606:4700::1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,533 @@
+//! Traceroute over ordinary UDP and TCP sockets
+//!
+//! Raw ICMP sockets need CAP_NET_RAW, but on Linux a socket with `IP_RECVERR` set is
+//! told about the ICMP errors its own packets provoke, including which router sent
+//! them (as `tracepath` does). Each probe gets a socket of its own with the TTL set,
+//! so the error queue it fills needs no matching against ids or ports.
+//!
+//! UDP probes go to high ports from 33434 up and end at the port unreachable the
+//! destination answers with; TCP probes are SYNs that end when the destination
+//! completes or refuses the handshake.
+//!
+//! Paris mode (`--traceroute-paris`) sends every UDP probe from one socket to one port
+//! instead, and tells the replies apart by the probe's payload, which routers quote back.
+
+use super::traceroute::{report_reply, HopReplies, PROBES_PER_HOP, PROBE_GAP, PROBE_TIMEOUT};
+use crate::model::TestEvent;
+use anyhow::Result;
+use std::net::{IpAddr, SocketAddr};
+use tokio::sync::mpsc;
+
+/// First destination port of the UDP probes, as in classic traceroute
+pub const UDP_BASE_PORT: u16 = 33434;
+
+/// Destination port of the TCP probes
+pub const TCP_PORT: u16 = 443;
+
+/// UDP payload length of each probe
+const PAYLOAD_LEN: usize = 32;
+
+/// Probe the path to `destination` with UDP datagrams (to `destination`'s port and up,
+/// one port per probe) or TCP SYNs (to `destination`'s port). Returns the replies per
+/// TTL and the lowest TTL the destination answered at; `live` hears of each as it comes.
+#[cfg(target_os = "linux")]
+pub async fn trace(
+    destination: SocketAddr,
+    tcp: bool,
+    max_hops: u8,
+    live: Option<&mpsc::Sender<TestEvent>>,
+) -> Result<(Vec<HopReplies>, Option<u8>)> {
+    use std::time::{Duration, Instant};
+
+    let mut replies = vec![[None; PROBES_PER_HOP]; usize::from(max_hops)];
+    let mut reached: Option<u8> = None;
+    // TTL, probe number, socket and send time of the probes awaiting an answer
+    let mut in_flight = Vec::new();
+
+    for probe_num in 0..PROBES_PER_HOP {
+        for ttl in 1..=max_hops {
+            if reached.is_some_and(|hop| ttl > hop) {
+                break;
+            }
+            let mut addr = destination;
+            if !tcp {
+                let index = (usize::from(ttl) - 1) * PROBES_PER_HOP + probe_num;
+                addr.set_port(destination.port().wrapping_add(index as u16));
+            }
+            match send_probe(addr, tcp, ttl) {
+                Ok(socket) => in_flight.push((ttl, probe_num, socket, Instant::now())),
+                // The first probe shows whether this works here at all
+                Err(e) if ttl == 1 && probe_num == 0 => return Err(e),
+                Err(_) => {}
+            }
+            tokio::time::sleep(PROBE_GAP).await;
+            collect(
+                &mut in_flight,
+                destination.ip(),
+                tcp,
+                &mut replies,
+                &mut reached,
+                live,
+            );
+        }
+    }
+
+    // Late replies get one probe timeout
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    while !in_flight.is_empty() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        collect(
+            &mut in_flight,
+            destination.ip(),
+            tcp,
+            &mut replies,
+            &mut reached,
+            live,
+        );
+    }
+
+    Ok((replies, reached))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn trace(
+    _destination: SocketAddr,
+    _tcp: bool,
+    _max_hops: u8,
+    _live: Option<&mpsc::Sender<TestEvent>>,
+) -> Result<(Vec<HopReplies>, Option<u8>)> {
+    anyhow::bail!("UDP and TCP traceroute need Linux (IP_RECVERR)")
+}
+
+/// Paris traceroute over UDP: all probes leave one socket for `destination`, so the
+/// addresses and ports ECMP load balancers hash on stay the same across TTLs, and the
+/// payload keeps the UDP checksum constant too. Every hop then lies on the one path
+/// this flow takes. Returns the replies per TTL, the lowest TTL the destination answered
+/// at, and the source port that identifies the flow.
+#[cfg(target_os = "linux")]
+pub async fn trace_paris(
+    destination: SocketAddr,
+    max_hops: u8,
+    live: Option<&mpsc::Sender<TestEvent>>,
+) -> Result<(Vec<HopReplies>, Option<u8>, u16)> {
+    use std::time::{Duration, Instant};
+
+    let socket = open_socket(destination, false)?;
+    let bind_addr: SocketAddr = if destination.is_ipv4() {
+        (std::net::Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    socket.bind(&bind_addr.into())?;
+    let flow = socket
+        .local_addr()?
+        .as_socket()
+        .map_or(0, |addr| addr.port());
+
+    let mut sent_at = vec![[None; PROBES_PER_HOP]; usize::from(max_hops)];
+    let mut replies = vec![[None; PROBES_PER_HOP]; usize::from(max_hops)];
+    let mut reached: Option<u8> = None;
+
+    for probe_num in 0..PROBES_PER_HOP {
+        for ttl in 1..=max_hops {
+            if reached.is_some_and(|hop| ttl > hop) {
+                break;
+            }
+            set_hop_limit(&socket, destination, ttl)?;
+            let payload = paris_payload(ttl, probe_num);
+            // A pending ICMP error fails one send; the error queue keeps its details
+            let sent = socket
+                .send_to(&payload, &destination.into())
+                .or_else(|_| socket.send_to(&payload, &destination.into()));
+            if sent.is_ok() {
+                sent_at[usize::from(ttl) - 1][probe_num] = Some(Instant::now());
+            }
+            tokio::time::sleep(PROBE_GAP).await;
+            collect_paris(
+                &socket,
+                destination.ip(),
+                &sent_at,
+                &mut replies,
+                &mut reached,
+                live,
+            );
+        }
+    }
+
+    // Late replies get one probe timeout
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    let pending = |replies: &[HopReplies], reached: Option<u8>| {
+        let last = reached.map_or(sent_at.len(), usize::from);
+        sent_at[..last].iter().zip(replies).any(|(sent, replies)| {
+            sent.iter()
+                .zip(replies)
+                .any(|(s, r)| s.is_some() && r.is_none())
+        })
+    };
+    while pending(&replies, reached) && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        collect_paris(
+            &socket,
+            destination.ip(),
+            &sent_at,
+            &mut replies,
+            &mut reached,
+            live,
+        );
+    }
+
+    Ok((replies, reached, flow))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn trace_paris(
+    _destination: SocketAddr,
+    _max_hops: u8,
+    _live: Option<&mpsc::Sender<TestEvent>>,
+) -> Result<(Vec<HopReplies>, Option<u8>, u16)> {
+    anyhow::bail!("UDP traceroute needs Linux (IP_RECVERR)")
+}
+
+/// Payload of a Paris probe: the TTL and probe number, then their complement, which
+/// keeps the one's complement sum (and so the UDP checksum) the same for every probe.
+fn paris_payload(ttl: u8, probe_num: usize) -> [u8; PAYLOAD_LEN] {
+    let word = u16::from_be_bytes([ttl, probe_num as u8]);
+    let mut payload = [0u8; PAYLOAD_LEN];
+    payload[..2].copy_from_slice(&word.to_be_bytes());
+    payload[2..4].copy_from_slice(&(!word).to_be_bytes());
+    payload
+}
+
+/// TTL and probe number of the Paris probe whose payload a router quoted back
+fn paris_probe(payload: &[u8]) -> Option<(u8, usize)> {
+    let word = u16::from_be_bytes([*payload.first()?, *payload.get(1)?]);
+    let check = u16::from_be_bytes([*payload.get(2)?, *payload.get(3)?]);
+    (check == !word).then_some((payload[0], usize::from(payload[1])))
+}
+
+/// Record the ICMP errors queued on the Paris socket against the probes they quote.
+#[cfg(target_os = "linux")]
+fn collect_paris(
+    socket: &socket2::Socket,
+    destination: IpAddr,
+    sent_at: &[[Option<std::time::Instant>; PROBES_PER_HOP]],
+    replies: &mut [HopReplies],
+    reached: &mut Option<u8>,
+    live: Option<&mpsc::Sender<TestEvent>>,
+) {
+    while let Some(error) = read_error_queue(socket) {
+        let (Some(from), Some((ttl, probe_num))) = (error.offender, paris_probe(&error.payload))
+        else {
+            continue;
+        };
+        let index = usize::from(ttl).wrapping_sub(1);
+        let Some(sent) = sent_at
+            .get(index)
+            .and_then(|probes| probes.get(probe_num))
+            .copied()
+            .flatten()
+        else {
+            continue;
+        };
+        let rtt = rtt_ms(&sent, error.received);
+        replies[index][probe_num] = Some((from, rtt));
+        report_reply(live, ttl, from, rtt);
+        if from == destination {
+            *reached = Some(reached.map_or(ttl, |hop| hop.min(ttl)));
+        }
+    }
+}
+
+/// Round trip of a probe sent at `sent`, up to when the kernel received its answer if
+/// that is known, rather than when the answer was polled for
+#[cfg(target_os = "linux")]
+fn rtt_ms(sent: &std::time::Instant, received: Option<std::time::SystemTime>) -> f64 {
+    let queued = received
+        .and_then(|received| std::time::SystemTime::now().duration_since(received).ok())
+        .unwrap_or_default();
+    sent.elapsed().saturating_sub(queued).as_secs_f64() * 1000.0
+}
+
+/// Record the answers that arrived for the probes in flight, dropping answered probes
+/// and those beyond the destination.
+#[cfg(target_os = "linux")]
+fn collect(
+    in_flight: &mut Vec<(u8, usize, socket2::Socket, std::time::Instant)>,
+    destination: IpAddr,
+    tcp: bool,
+    replies: &mut [HopReplies],
+    reached: &mut Option<u8>,
+    live: Option<&mpsc::Sender<TestEvent>>,
+) {
+    in_flight.retain(|(ttl, probe_num, socket, sent)| {
+        let Some((from, received)) = answer(socket, destination, tcp) else {
+            return reached.is_none_or(|hop| *ttl <= hop);
+        };
+        let index = usize::from(*ttl) - 1;
+        let rtt = rtt_ms(sent, received);
+        replies[index][*probe_num] = Some((from, rtt));
+        report_reply(live, *ttl, from, rtt);
+        if from == destination {
+            *reached = Some(reached.map_or(*ttl, |hop| hop.min(*ttl)));
+        }
+        false
+    });
+}
+
+/// Open a socket with `ttl` and `IP_RECVERR`, and send a UDP datagram or start a TCP
+/// handshake to `addr` from it.
+#[cfg(target_os = "linux")]
+fn send_probe(addr: SocketAddr, tcp: bool, ttl: u8) -> Result<socket2::Socket> {
+    let socket = open_socket(addr, tcp)?;
+    set_hop_limit(&socket, addr, ttl)?;
+
+    if tcp {
+        match socket.connect(&addr.into()) {
+            Err(e) if e.raw_os_error() != Some(libc::EINPROGRESS) => return Err(e.into()),
+            _ => {}
+        }
+    } else {
+        socket.send_to(&[0u8; PAYLOAD_LEN], &addr.into())?;
+    }
+    Ok(socket)
+}
+
+/// Nonblocking UDP or TCP socket for `addr`'s family with `IP_RECVERR` set
+#[cfg(target_os = "linux")]
+fn open_socket(addr: SocketAddr, tcp: bool) -> Result<socket2::Socket> {
+    use anyhow::Context;
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let (kind, protocol) = if tcp {
+        (Type::STREAM, Protocol::TCP)
+    } else {
+        (Type::DGRAM, Protocol::UDP)
+    };
+    let socket = Socket::new(Domain::for_address(addr), kind, Some(protocol))?;
+    socket.set_nonblocking(true)?;
+    let (level, option) = if addr.is_ipv4() {
+        (libc::SOL_IP, libc::IP_RECVERR)
+    } else {
+        (libc::SOL_IPV6, libc::IPV6_RECVERR)
+    };
+    set_int_option(&socket, level, option).context("Failed to enable IP_RECVERR")?;
+    // Arrival times for the queued errors; without them RTTs include the polling delay
+    set_int_option(&socket, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS).ok();
+    Ok(socket)
+}
+
+/// Set the TTL (IPv4) or hop limit (IPv6) of the packets `socket` sends to `addr`
+#[cfg(target_os = "linux")]
+fn set_hop_limit(socket: &socket2::Socket, addr: SocketAddr, ttl: u8) -> Result<()> {
+    if addr.is_ipv4() {
+        socket.set_ttl(u32::from(ttl))?;
+    } else {
+        socket.set_unicast_hops_v6(u32::from(ttl))?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn set_int_option(socket: &socket2::Socket, level: libc::c_int, option: libc::c_int) -> Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let on: libc::c_int = 1;
+    // SAFETY: the option value is a c_int that outlives the call
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            (&on as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+/// Who answered the probe sent from `socket`, if anyone has yet, and when the answer
+/// arrived if the kernel says: the sender of an ICMP error in the socket's error queue,
+/// or for TCP the destination once the handshake completes or is refused.
+#[cfg(target_os = "linux")]
+fn answer(
+    socket: &socket2::Socket,
+    destination: IpAddr,
+    tcp: bool,
+) -> Option<(IpAddr, Option<std::time::SystemTime>)> {
+    while let Some(error) = read_error_queue(socket) {
+        if let Some(offender) = error.offender {
+            return Some((offender, error.received));
+        }
+    }
+    if tcp {
+        // A SYN-ACK connects the socket, a RST refuses it
+        let refused = matches!(
+            socket.take_error(),
+            Ok(Some(e)) if e.raw_os_error() == Some(libc::ECONNREFUSED)
+        );
+        if refused || socket.peer_addr().is_ok() {
+            return Some((destination, None));
+        }
+    }
+    None
+}
+
+/// An error taken off a socket's error queue
+#[cfg(target_os = "linux")]
+struct QueuedError {
+    /// Node that sent the ICMP error; `None` for errors raised locally
+    offender: Option<IpAddr>,
+    /// Start of the payload of the packet that caused it
+    payload: Vec<u8>,
+    /// When the ICMP error arrived (`SO_TIMESTAMPNS`)
+    received: Option<std::time::SystemTime>,
+}
+
+/// Take the next error off `socket`'s error queue; `None` once it is empty.
+#[cfg(target_os = "linux")]
+fn read_error_queue(socket: &socket2::Socket) -> Option<QueuedError> {
+    use std::os::fd::AsRawFd;
+
+    let mut data = [0u8; 64];
+    let mut control = [0u8; 512];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+    // SAFETY: msghdr is plain data; the buffers it points at outlive the recvmsg call
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = control.len() as _;
+    let flags = libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT;
+    // SAFETY: see above
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, flags) };
+    if len < 0 {
+        return None;
+    }
+    let mut error = QueuedError {
+        offender: None,
+        payload: data[..len as usize].to_vec(),
+        received: None,
+    };
+
+    let control = &control[..msg.msg_controllen as usize];
+    // SAFETY: the CMSG_* macros walk the control buffer the kernel just filled in
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        let recverr = (header.cmsg_level == libc::SOL_IP && header.cmsg_type == libc::IP_RECVERR)
+            || (header.cmsg_level == libc::SOL_IPV6 && header.cmsg_type == libc::IPV6_RECVERR);
+        let start =
+            unsafe { libc::CMSG_DATA(cmsg) }.cast_const() as usize - control.as_ptr() as usize;
+        let end = (cmsg as usize - control.as_ptr() as usize) + header.cmsg_len as usize;
+        let data = control.get(start..end).unwrap_or_default();
+        if recverr {
+            error.offender = offender(data);
+        } else if header.cmsg_level == libc::SOL_SOCKET
+            && header.cmsg_type == libc::SCM_TIMESTAMPNS
+            && data.len() >= std::mem::size_of::<libc::timespec>()
+        {
+            // SAFETY: length checked; timespec is plain integers
+            let ts: libc::timespec = unsafe { std::ptr::read_unaligned(data.as_ptr().cast()) };
+            error.received = Some(
+                std::time::UNIX_EPOCH
+                    + std::time::Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32),
+            );
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    Some(error)
+}
+
+/// Sender of the ICMP error described by an `IP_RECVERR` control message: a
+/// `sock_extended_err` followed by the offender's socket address (`SO_EE_OFFENDER`).
+#[cfg(target_os = "linux")]
+fn offender(data: &[u8]) -> Option<IpAddr> {
+    let size = std::mem::size_of::<libc::sock_extended_err>();
+    if data.len() < size {
+        return None;
+    }
+    // SAFETY: length checked above; the struct is plain integers
+    let err: libc::sock_extended_err = unsafe { std::ptr::read_unaligned(data.as_ptr().cast()) };
+    if err.ee_origin != libc::SO_EE_ORIGIN_ICMP && err.ee_origin != libc::SO_EE_ORIGIN_ICMP6 {
+        return None;
+    }
+    let addr = &data[size..];
+    let family = u16::from_ne_bytes([*addr.first()?, *addr.get(1)?]);
+    match libc::c_int::from(family) {
+        // sockaddr_in: family, port, then the address
+        libc::AF_INET => {
+            let ip: [u8; 4] = addr.get(4..8)?.try_into().ok()?;
+            Some(IpAddr::from(ip))
+        }
+        // sockaddr_in6: family, port, flow info, then the address
+        libc::AF_INET6 => {
+            let ip: [u8; 16] = addr.get(8..24)?.try_into().ok()?;
+            Some(IpAddr::from(ip))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offender() {
+        let size = std::mem::size_of::<libc::sock_extended_err>();
+        let mut data = vec![0u8; size];
+        // ee_errno, then ee_origin
+        data[4] = libc::SO_EE_ORIGIN_ICMP;
+        data.extend((libc::AF_INET as u16).to_ne_bytes());
+        data.extend([0, 0, 192, 0, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(offender(&data), Some("192.0.2.1".parse().unwrap()));
+
+        // Errors raised locally name no router
+        data[4] = 1;
+        assert_eq!(offender(&data), None);
+        assert_eq!(offender(&data[..size - 1]), None);
+    }
+
+    #[test]
+    fn test_paris_payload() {
+        let first = paris_payload(1, 0);
+        let later = paris_payload(17, 2);
+        assert_eq!(paris_probe(&later), Some((17, 2)));
+        assert_eq!(paris_probe(&later[..3]), None);
+        assert_eq!(paris_probe(&[0u8; 4]), None);
+        // The same one's complement sum, so the same UDP checksum
+        let sum = |payload: &[u8]| {
+            payload
+                .chunks(2)
+                .map(|w| u32::from(u16::from_be_bytes([w[0], w[1]])))
+                .sum::<u32>()
+        };
+        assert_eq!(sum(&first), sum(&later));
+    }
+
+    #[tokio::test]
+    async fn test_trace_loopback() {
+        // Nothing listens on these ports, so both probes are answered at the first hop
+        let udp = SocketAddr::from(([127, 0, 0, 1], UDP_BASE_PORT));
+        let (replies, reached) = trace(udp, false, 4, None).await.unwrap();
+        assert_eq!(reached, Some(1));
+        assert!(replies[0].iter().all(|reply| reply.is_some()));
+
+        let (replies, reached, flow) = trace_paris(udp, 4, None).await.unwrap();
+        assert_eq!(reached, Some(1));
+        assert!(replies[0].iter().all(|reply| reply.is_some()));
+        assert_ne!(flow, 0);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let (_, reached) = trace(listener.local_addr().unwrap(), true, 4, None)
+            .await
+            .unwrap();
+        assert_eq!(reached, Some(1));
+    }
+}
@@ -0,0 +1,603 @@
+//! Packet loss through the TURN relay (RFC 8656)
+//!
+//! The browser test measures loss over a WebRTC connection that Cloudflare's TURN
+//! server relays back to the same page. This does the same without WebRTC: it makes two
+//! allocations with the credentials from `/__turn`, sends numbered packets from the
+//! first to the second's relayed address and has the second echo them back, so every
+//! round trip crosses the relay twice. Loss and jitter then include the relay's own
+//! forwarding, which a binding request answered by the server directly does not.
+//!
+//! `--udp-throughput` uses the same pair of allocations for a one-way paced stream
+//! instead, to see how much UDP gets through where TCP backs off on loss.
+
+use crate::engine::scheduler::ProbeScheduler;
+use crate::engine::turn_udp::{
+    decode_address, encode_xor_address, open_probe_socket, probe_params, resolve_target,
+    MAGIC_COOKIE,
+};
+use crate::model::{RunConfig, TurnInfo, TurnRelaySummary, UdpThroughputSummary};
+use crate::stats::{latency_summary_from_samples, OnlineStats};
+use anyhow::{Context, Result};
+use rand::RngCore;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+const ALLOCATE: u16 = 0x0003;
+const REFRESH: u16 = 0x0004;
+const CREATE_PERMISSION: u16 = 0x0008;
+const SEND_INDICATION: u16 = 0x0016;
+const DATA_INDICATION: u16 = 0x0017;
+const SUCCESS: u16 = 0x0100;
+const ERROR: u16 = 0x0110;
+
+const ATTR_USERNAME: u16 = 0x0006;
+const ATTR_MESSAGE_INTEGRITY: u16 = 0x0008;
+const ATTR_ERROR_CODE: u16 = 0x0009;
+const ATTR_LIFETIME: u16 = 0x000D;
+const ATTR_XOR_PEER_ADDRESS: u16 = 0x0012;
+const ATTR_DATA: u16 = 0x0013;
+const ATTR_REALM: u16 = 0x0014;
+const ATTR_NONCE: u16 = 0x0015;
+const ATTR_XOR_RELAYED_ADDRESS: u16 = 0x0016;
+const ATTR_REQUESTED_TRANSPORT: u16 = 0x0019;
+
+/// Requests are retransmitted this many times before the relay counts as unreachable
+const REQUEST_ATTEMPTS: usize = 3;
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// The `turn:` URL of `turn` usable over UDP, when it also carries credentials.
+pub fn pick_relay_url(turn: &TurnInfo) -> Option<&str> {
+    turn.username.as_ref()?;
+    turn.credential.as_ref()?;
+    turn.urls
+        .iter()
+        .filter(|u| u.starts_with("turn:"))
+        .find(|u| match u.split_once("?transport=") {
+            Some((_, transport)) => transport.eq_ignore_ascii_case("udp"),
+            None => true,
+        })
+        .map(String::as_str)
+}
+
+/// Bytes a Send indication adds around its data: header, XOR-PEER-ADDRESS (IPv4) and
+/// the DATA attribute header
+const SEND_OVERHEAD: usize = 20 + 12 + 4;
+/// UDP payload of each datagram of the throughput stream
+const STREAM_PACKET_SIZE: usize = 1200;
+
+/// Bounce `cfg.udp_packets` packets through two allocations on the relay at `url`.
+pub async fn run_relay_probe(
+    turn: &TurnInfo,
+    url: &str,
+    cfg: &RunConfig,
+    scheduler: &ProbeScheduler,
+) -> Result<TurnRelaySummary> {
+    let (mut probe, mut echo) = open_pair(turn, url, cfg).await?;
+    let result = bounce(&probe, &echo, cfg, scheduler).await;
+    probe.release().await;
+    echo.release().await;
+
+    let latency = result?;
+    Ok(TurnRelaySummary {
+        server: url.to_string(),
+        relayed_addresses: vec![probe.relayed.to_string(), echo.relayed.to_string()],
+        out_of_order: latency.1,
+        latency: latency.0,
+    })
+}
+
+/// Push 1200 byte datagrams, about what a video call sends, from one allocation on the
+/// relay at `url` to another at `mbps` for `duration`, and count what arrives.
+pub async fn run_relay_throughput(
+    turn: &TurnInfo,
+    url: &str,
+    cfg: &RunConfig,
+    duration: Duration,
+    mbps: f64,
+) -> Result<UdpThroughputSummary> {
+    let (mut sender, mut receiver) = open_pair(turn, url, cfg).await?;
+    let mut summary = UdpThroughputSummary {
+        packet_size: STREAM_PACKET_SIZE as u16,
+        ..UdpThroughputSummary::failed(url, mbps, None)
+    };
+    // Stragglers get as long as a loss probe waits for its answer
+    let grace = Duration::from_millis(cfg.udp_timeout_ms);
+    stream(&sender, &receiver, duration, mbps, grace, &mut summary).await;
+    sender.release().await;
+    receiver.release().await;
+    Ok(summary)
+}
+
+async fn stream(
+    sender: &Allocation,
+    receiver: &Allocation,
+    duration: Duration,
+    mbps: f64,
+    grace: Duration,
+    summary: &mut UdpThroughputSummary,
+) {
+    let start = tokio::time::Instant::now();
+    let stop = start + duration + grace;
+    let sock = receiver.sock.clone();
+    let counting = tokio::spawn(async move {
+        // Received, payload bytes, out of order, last arrival
+        let (mut received, mut bytes, mut out_of_order) = (0u64, 0u64, 0u64);
+        let mut highest = 0u64;
+        let mut last = None;
+        let mut buf = [0u8; 1500];
+        while let Ok(Ok(n)) = tokio::time::timeout_at(stop, sock.recv(&mut buf)).await {
+            let Some(data) = Message::decode(&buf[..n])
+                .filter(|m| m.kind == DATA_INDICATION)
+                .and_then(|m| m.attr(ATTR_DATA).map(<[u8]>::to_vec))
+            else {
+                continue;
+            };
+            let Some(seq) = data.get(..8).and_then(|d| d.try_into().ok()) else {
+                continue;
+            };
+            let seq = u64::from_be_bytes(seq);
+            received += 1;
+            bytes += data.len() as u64;
+            if seq < highest {
+                out_of_order += 1;
+            } else {
+                highest = seq;
+            }
+            last = Some(tokio::time::Instant::now());
+        }
+        (received, bytes, out_of_order, last)
+    });
+
+    // Send whatever the rate allows by now, then catch up after the next timer tick
+    let packet_bits = (STREAM_PACKET_SIZE * 8) as f64;
+    let mut data = vec![0u8; STREAM_PACKET_SIZE - SEND_OVERHEAD];
+    while start.elapsed() < duration {
+        let due = (start.elapsed().as_secs_f64() * mbps * 1e6 / packet_bits) as u64 + 1;
+        while summary.sent < due {
+            summary.sent += 1;
+            data[..8].copy_from_slice(&summary.sent.to_be_bytes());
+            let packet = Message::send_indication(receiver.relayed, &data);
+            // A full send buffer drops the datagram, which then counts as lost
+            sender.sock.send(&packet.encode(None)).await.ok();
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    let (received, bytes, out_of_order, last) = counting.await.unwrap_or_default();
+    summary.duration_ms = start.elapsed().saturating_sub(grace).as_millis() as u64;
+    summary.received = received;
+    summary.out_of_order = out_of_order;
+    if summary.sent > 0 {
+        summary.loss = summary.sent.saturating_sub(received) as f64 / summary.sent as f64;
+    }
+    if let Some(last) = last {
+        let secs = (last - start).as_secs_f64();
+        if secs > 0.0 {
+            summary.goodput_mbps = bytes as f64 * 8.0 / secs / 1e6;
+        }
+    }
+}
+
+/// Two allocations on the relay at `url`, each permitted to send to the other
+async fn open_pair(
+    turn: &TurnInfo,
+    url: &str,
+    cfg: &RunConfig,
+) -> Result<(Allocation, Allocation)> {
+    let server = resolve_target(url, cfg).await?;
+    let username = turn.username.clone().context("/__turn has no username")?;
+    let credential = turn
+        .credential
+        .clone()
+        .context("/__turn has no credential")?;
+
+    let mut first = Allocation::open(cfg, server, &username, &credential).await?;
+    let mut second = match Allocation::open(cfg, server, &username, &credential).await {
+        Ok(second) => second,
+        Err(e) => {
+            first.release().await;
+            return Err(e);
+        }
+    };
+    // Relays only forward data from peers with a permission
+    let mut permitted = first.create_permission(second.relayed).await;
+    if permitted.is_ok() {
+        permitted = second.create_permission(first.relayed).await;
+    }
+    if let Err(e) = permitted {
+        first.release().await;
+        second.release().await;
+        return Err(e);
+    }
+    Ok((first, second))
+}
+
+async fn bounce(
+    probe: &Allocation,
+    echo: &Allocation,
+    cfg: &RunConfig,
+    scheduler: &ProbeScheduler,
+) -> Result<(crate::model::LatencySummary, u64)> {
+    let params = probe_params(cfg);
+    let timeout = Duration::from_millis(params.timeout_ms);
+    let interval = Duration::from_millis(params.interval_ms);
+    // Sized so the datagrams leaving here match the direct probe's; the first 8 bytes
+    // carry the sequence number
+    let data_len = usize::from(params.packet_size)
+        .saturating_sub(SEND_OVERHEAD)
+        .max(8);
+
+    // The echo side returns every packet to whichever relayed address it came from
+    let echo_sock = echo.sock.clone();
+    let echo_task = tokio::spawn(async move {
+        let mut buf = [0u8; 1500];
+        while let Ok(n) = echo_sock.recv(&mut buf).await {
+            let Some(msg) = Message::decode(&buf[..n]) else {
+                continue;
+            };
+            if msg.kind != DATA_INDICATION {
+                continue;
+            }
+            let (Some(peer), Some(data)) = (msg.peer_address(), msg.attr(ATTR_DATA)) else {
+                continue;
+            };
+            let reply = Message::send_indication(peer, data);
+            echo_sock.send(&reply.encode(None)).await.ok();
+        }
+    });
+
+    let mut sent = 0u64;
+    let mut received = 0u64;
+    let mut samples = Vec::<f64>::new();
+    let mut online = OnlineStats::default();
+    let mut out_of_order = 0u64;
+    let mut buf = [0u8; 1500];
+
+    for seq in 1..=params.packets {
+        scheduler.acquire().await;
+        sent += 1;
+        let mut data = seq.to_be_bytes().to_vec();
+        data.resize(data_len, 0);
+        let packet = Message::send_indication(echo.relayed, &data);
+        let start = Instant::now();
+        probe.sock.send(&packet.encode(None)).await.ok();
+
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let Ok(Ok(n)) = tokio::time::timeout_at(deadline, probe.sock.recv(&mut buf)).await
+            else {
+                break;
+            };
+            let Some(echoed) = Message::decode(&buf[..n])
+                .filter(|m| m.kind == DATA_INDICATION)
+                .and_then(|m| m.attr(ATTR_DATA).and_then(|d| d.get(..8)?.try_into().ok()))
+                .map(u64::from_be_bytes)
+            else {
+                continue;
+            };
+            if echoed == seq {
+                let ms = start.elapsed().as_secs_f64() * 1000.0;
+                received += 1;
+                samples.push(ms);
+                online.push(ms);
+                break;
+            }
+            // A packet given up on earlier arrived after all
+            if echoed < seq {
+                out_of_order += 1;
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+    echo_task.abort();
+
+    let latency = latency_summary_from_samples(sent, received, &samples, online.stddev());
+    Ok((latency, out_of_order))
+}
+
+/// One allocation on the relay, with the long-term credential state to refresh it
+struct Allocation {
+    sock: Arc<UdpSocket>,
+    username: String,
+    key: Vec<u8>,
+    realm: String,
+    nonce: String,
+    relayed: SocketAddr,
+}
+
+impl Allocation {
+    async fn open(
+        cfg: &RunConfig,
+        server: SocketAddr,
+        username: &str,
+        credential: &str,
+    ) -> Result<Self> {
+        let sock = Arc::new(open_probe_socket(cfg, server).await?);
+        let transport = [17, 0, 0, 0];
+
+        // The unauthenticated first attempt is answered with the realm and a nonce
+        let mut first = Message::new(ALLOCATE);
+        first.attrs = vec![(ATTR_REQUESTED_TRANSPORT, transport.to_vec())];
+        let challenge = request(&sock, first, None).await?;
+        anyhow::ensure!(
+            challenge.kind == ALLOCATE | ERROR && challenge.error_code() == Some(401),
+            "TURN server did not ask for credentials ({})",
+            challenge.describe_error()
+        );
+        let realm = challenge.string_attr(ATTR_REALM).context("no REALM")?;
+        let nonce = challenge.string_attr(ATTR_NONCE).context("no NONCE")?;
+        let key = md5::compute(format!("{username}:{realm}:{credential}"))
+            .0
+            .to_vec();
+
+        let mut allocation = Self {
+            sock,
+            username: username.to_string(),
+            key,
+            realm,
+            nonce,
+            relayed: server,
+        };
+        let response = allocation
+            .authenticated(
+                ALLOCATE,
+                vec![(ATTR_REQUESTED_TRANSPORT, transport.to_vec())],
+            )
+            .await
+            .context("TURN allocation failed")?;
+        allocation.relayed = response
+            .attr(ATTR_XOR_RELAYED_ADDRESS)
+            .and_then(|v| decode_address(v, &response.txid, true))
+            .context("allocation has no XOR-RELAYED-ADDRESS")?;
+        Ok(allocation)
+    }
+
+    async fn create_permission(&mut self, peer: SocketAddr) -> Result<()> {
+        // Permissions are per IP address; the port only has to be well-formed
+        let txid = random_txid();
+        let value = encode_xor_address(peer, &txid);
+        self.authenticated_with(
+            txid,
+            CREATE_PERMISSION,
+            vec![(ATTR_XOR_PEER_ADDRESS, value)],
+        )
+        .await
+        .context("TURN CreatePermission failed")?;
+        Ok(())
+    }
+
+    /// Delete the allocation; it would expire on its own, so failures are ignored.
+    async fn release(&mut self) {
+        let lifetime = 0u32.to_be_bytes().to_vec();
+        self.authenticated(REFRESH, vec![(ATTR_LIFETIME, lifetime)])
+            .await
+            .ok();
+    }
+
+    async fn authenticated(&mut self, kind: u16, attrs: Vec<(u16, Vec<u8>)>) -> Result<Message> {
+        self.authenticated_with(random_txid(), kind, attrs).await
+    }
+
+    /// Send an authenticated request, retrying once with a fresh nonce when it went stale.
+    async fn authenticated_with(
+        &mut self,
+        txid: [u8; 12],
+        kind: u16,
+        attrs: Vec<(u16, Vec<u8>)>,
+    ) -> Result<Message> {
+        for _ in 0..2 {
+            let mut msg = Message {
+                kind,
+                txid,
+                attrs: attrs.clone(),
+            };
+            msg.attrs.extend([
+                (ATTR_USERNAME, self.username.clone().into_bytes()),
+                (ATTR_REALM, self.realm.clone().into_bytes()),
+                (ATTR_NONCE, self.nonce.clone().into_bytes()),
+            ]);
+            let response = request(&self.sock, msg, Some(&self.key)).await?;
+            if response.kind == kind | SUCCESS {
+                return Ok(response);
+            }
+            match (response.error_code(), response.string_attr(ATTR_NONCE)) {
+                (Some(438), Some(nonce)) => self.nonce = nonce,
+                _ => anyhow::bail!("{}", response.describe_error()),
+            }
+        }
+        anyhow::bail!("TURN server keeps rejecting the nonce")
+    }
+}
+
+/// Send `msg` until a response with its transaction id arrives.
+async fn request(sock: &UdpSocket, msg: Message, key: Option<&[u8]>) -> Result<Message> {
+    let packet = msg.encode(key);
+    let mut buf = [0u8; 1500];
+    for _ in 0..REQUEST_ATTEMPTS {
+        sock.send(&packet).await?;
+        let deadline = tokio::time::Instant::now() + REQUEST_TIMEOUT;
+        while let Ok(n) = tokio::time::timeout_at(deadline, sock.recv(&mut buf)).await {
+            if let Some(response) = Message::decode(&buf[..n?]).filter(|m| m.txid == msg.txid) {
+                return Ok(response);
+            }
+        }
+    }
+    anyhow::bail!("no answer from the TURN server")
+}
+
+fn random_txid() -> [u8; 12] {
+    let mut txid = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut txid);
+    txid
+}
+
+/// A STUN message with its attributes in order
+#[derive(Debug, Clone)]
+struct Message {
+    kind: u16,
+    txid: [u8; 12],
+    attrs: Vec<(u16, Vec<u8>)>,
+}
+
+impl Message {
+    fn new(kind: u16) -> Self {
+        Self {
+            kind,
+            txid: random_txid(),
+            attrs: Vec::new(),
+        }
+    }
+
+    fn send_indication(peer: SocketAddr, data: &[u8]) -> Self {
+        let mut msg = Self::new(SEND_INDICATION);
+        msg.attrs = vec![
+            (ATTR_XOR_PEER_ADDRESS, encode_xor_address(peer, &msg.txid)),
+            (ATTR_DATA, data.to_vec()),
+        ];
+        msg
+    }
+
+    /// Wire format, ending in MESSAGE-INTEGRITY (HMAC-SHA1 keyed with `key`) if given.
+    fn encode(&self, key: Option<&[u8]>) -> Vec<u8> {
+        let mut out = Vec::with_capacity(128);
+        out.extend_from_slice(&self.kind.to_be_bytes());
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(&MAGIC_COOKIE);
+        out.extend_from_slice(&self.txid);
+        for (kind, value) in &self.attrs {
+            out.extend_from_slice(&kind.to_be_bytes());
+            out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            out.extend_from_slice(value);
+            out.resize(out.len().next_multiple_of(4), 0);
+        }
+        if let Some(key) = key {
+            // The length covers the integrity attribute, which covers everything before it
+            let len = out.len() - 20 + 24;
+            out[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+            let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key);
+            let tag = ring::hmac::sign(&key, &out);
+            out.extend_from_slice(&ATTR_MESSAGE_INTEGRITY.to_be_bytes());
+            out.extend_from_slice(&20u16.to_be_bytes());
+            out.extend_from_slice(tag.as_ref());
+        } else {
+            let len = out.len() - 20;
+            out[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+        }
+        out
+    }
+
+    fn decode(buf: &[u8]) -> Option<Self> {
+        if buf.get(4..8)? != MAGIC_COOKIE {
+            return None;
+        }
+        let kind = u16::from_be_bytes([buf[0], buf[1]]);
+        let len = usize::from(u16::from_be_bytes([buf[2], buf[3]]));
+        let body = buf.get(20..20 + len)?;
+        let mut attrs = Vec::new();
+        let mut at = 0;
+        while at + 4 <= body.len() {
+            let attr = u16::from_be_bytes([body[at], body[at + 1]]);
+            let size = usize::from(u16::from_be_bytes([body[at + 2], body[at + 3]]));
+            attrs.push((attr, body.get(at + 4..at + 4 + size)?.to_vec()));
+            at += 4 + size.next_multiple_of(4);
+        }
+        Some(Self {
+            kind,
+            txid: buf[8..20].try_into().ok()?,
+            attrs,
+        })
+    }
+
+    fn attr(&self, kind: u16) -> Option<&[u8]> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    fn string_attr(&self, kind: u16) -> Option<String> {
+        self.attr(kind)
+            .map(|v| String::from_utf8_lossy(v).into_owned())
+    }
+
+    fn peer_address(&self) -> Option<SocketAddr> {
+        decode_address(self.attr(ATTR_XOR_PEER_ADDRESS)?, &self.txid, true)
+    }
+
+    /// Class and number of an ERROR-CODE attribute, e.g. 401 or 438.
+    fn error_code(&self) -> Option<u16> {
+        let value = self.attr(ATTR_ERROR_CODE)?;
+        Some(u16::from(*value.get(2)? & 0x07) * 100 + u16::from(*value.get(3)?))
+    }
+
+    fn describe_error(&self) -> String {
+        match self.error_code() {
+            Some(code) => {
+                let reason = self
+                    .attr(ATTR_ERROR_CODE)
+                    .and_then(|v| v.get(4..))
+                    .map(String::from_utf8_lossy)
+                    .unwrap_or_default();
+                format!("TURN error {code} {reason}").trim_end().to_string()
+            }
+            None => format!("unexpected STUN message type {:#06x}", self.kind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_round_trip() {
+        let peer: SocketAddr = "198.51.100.7:49152".parse().unwrap();
+        let msg = Message::send_indication(peer, b"payload");
+        let encoded = msg.encode(Some(b"key"));
+        // Header, XOR-PEER-ADDRESS, DATA padded to 8 bytes, MESSAGE-INTEGRITY
+        assert_eq!(encoded.len(), 20 + 12 + 12 + 24);
+        assert_eq!(
+            usize::from(u16::from_be_bytes([encoded[2], encoded[3]])),
+            48
+        );
+
+        let decoded = Message::decode(&encoded).unwrap();
+        assert_eq!(decoded.kind, SEND_INDICATION);
+        assert_eq!(decoded.peer_address(), Some(peer));
+        assert_eq!(decoded.attr(ATTR_DATA), Some(&b"payload"[..]));
+        let tag = ring::hmac::sign(
+            &ring::hmac::Key::new(ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, b"key"),
+            &encoded[..encoded.len() - 24],
+        );
+        assert_eq!(decoded.attr(ATTR_MESSAGE_INTEGRITY), Some(tag.as_ref()));
+
+        let mut error = Message::new(ALLOCATE | ERROR);
+        error.attrs = vec![(ATTR_ERROR_CODE, b"\0\0\x04\x26Stale Nonce".to_vec())];
+        let error = Message::decode(&error.encode(None)).unwrap();
+        assert_eq!(error.error_code(), Some(438));
+        assert_eq!(error.describe_error(), "TURN error 438 Stale Nonce");
+    }
+
+    #[test]
+    fn test_pick_relay_url() {
+        let mut turn = TurnInfo {
+            urls: vec![
+                "stun:turn.example:3478".to_string(),
+                "turn:turn.example:443?transport=tcp".to_string(),
+                "turn:turn.example:3478?transport=udp".to_string(),
+            ],
+            username: None,
+            credential: None,
+        };
+        assert_eq!(pick_relay_url(&turn), None);
+        turn.username = Some("user".to_string());
+        turn.credential = Some("secret".to_string());
+        assert_eq!(
+            pick_relay_url(&turn),
+            Some("turn:turn.example:3478?transport=udp")
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::engine::network_bind;
-use crate::model::{ExperimentalUdpSummary, RunConfig, TestEvent, TurnInfo};
+use crate::engine::scheduler::ProbeScheduler;
+use crate::model::{ExperimentalUdpSummary, RunConfig, TestEvent, TurnInfo, UdpProbeParams};
 use crate::stats::{latency_summary_from_samples, OnlineStats};
 use anyhow::{Context, Result};
 use rand::RngCore;
@@ -63,22 +64,45 @@ fn quality_label(loss_pct: f64) -> &'static str {
     }
 }
 
+pub(crate) const MAGIC_COOKIE: [u8; 4] = [0x21, 0x12, 0xA4, 0x42];
+
+/// Probe settings of `cfg`, with the packet size rounded to what a STUN request can be:
+/// the bare 20 byte header, or at least 24 bytes with a PADDING attribute.
+pub(crate) fn probe_params(cfg: &RunConfig) -> UdpProbeParams {
+    let packet_size = match cfg.udp_packet_size {
+        0..=20 => 20,
+        size => size.max(24).next_multiple_of(4),
+    };
+    UdpProbeParams {
+        packets: cfg.udp_packets,
+        packet_size,
+        interval_ms: cfg.udp_interval_ms,
+        timeout_ms: cfg.udp_timeout_ms,
+    }
+}
+
 // Minimal STUN binding request (RFC5389):
 // - type: 0x0001
-// - length: 0
+// - length: 0, or 4 + padding when padded to `size`
 // - magic cookie: 0x2112A442
 // - transaction id: 12 bytes random
-fn build_stun_binding_request(txid: [u8; 12]) -> [u8; 20] {
-    let mut b = [0u8; 20];
+// - PADDING attribute (RFC 5780) filling the request up to `size` bytes
+fn build_stun_binding_request(txid: [u8; 12], size: u16) -> Vec<u8> {
+    let mut b = vec![0u8; 20];
     b[0] = 0x00;
     b[1] = 0x01;
-    b[2] = 0x00;
-    b[3] = 0x00;
     b[4] = 0x21;
     b[5] = 0x12;
     b[6] = 0xA4;
     b[7] = 0x42;
     b[8..20].copy_from_slice(&txid);
+    if size > 20 {
+        let padding = size - 24;
+        b[2..4].copy_from_slice(&(4 + padding).to_be_bytes());
+        b.extend([0x00, 0x26]);
+        b.extend(padding.to_be_bytes());
+        b.resize(usize::from(size), 0);
+    }
     b
 }
 
@@ -97,6 +121,59 @@ fn is_stun_binding_response(buf: &[u8], txid: [u8; 12]) -> bool {
     buf[8..20] == txid
 }
 
+/// The reflexive transport address of a binding response: XOR-MAPPED-ADDRESS, or the
+/// older MAPPED-ADDRESS (RFC 5389 section 15.1-15.2).
+fn stun_mapped_address(buf: &[u8]) -> Option<SocketAddr> {
+    let len = usize::from(u16::from_be_bytes([*buf.get(2)?, *buf.get(3)?]));
+    let attrs = buf.get(20..20 + len)?;
+    let mut mapped = None;
+    let mut at = 0;
+    while at + 4 <= attrs.len() {
+        let kind = u16::from_be_bytes([attrs[at], attrs[at + 1]]);
+        let size = usize::from(u16::from_be_bytes([attrs[at + 2], attrs[at + 3]]));
+        let value = attrs.get(at + 4..at + 4 + size)?;
+        match kind {
+            0x0020 => return decode_address(value, &buf[8..20], true),
+            0x0001 => mapped = decode_address(value, &buf[8..20], false),
+            _ => {}
+        }
+        at += 4 + size.div_ceil(4) * 4;
+    }
+    mapped
+}
+
+/// Decode a (XOR-)MAPPED-ADDRESS style attribute value; XOR-ed ones are keyed with the
+/// magic cookie and, for IPv6, the transaction id.
+pub(crate) fn decode_address(value: &[u8], txid: &[u8], xor: bool) -> Option<SocketAddr> {
+    // Reserved, family, port, address
+    let mut port = u16::from_be_bytes([*value.get(2)?, *value.get(3)?]);
+    let mut ip = value.get(4..)?.to_vec();
+    if xor {
+        port ^= 0x2112;
+        let key: Vec<u8> = MAGIC_COOKIE.iter().chain(txid).copied().collect();
+        ip.iter_mut().zip(key).for_each(|(b, k)| *b ^= k);
+    }
+    let ip = match (value[1], ip.len()) {
+        (1, 4) => std::net::IpAddr::from(<[u8; 4]>::try_from(ip).ok()?),
+        (2, 16) => std::net::IpAddr::from(<[u8; 16]>::try_from(ip).ok()?),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Encode `addr` as an XOR-*-ADDRESS attribute value for a message with `txid`.
+pub(crate) fn encode_xor_address(addr: SocketAddr, txid: &[u8]) -> Vec<u8> {
+    let (family, ip) = match addr.ip() {
+        std::net::IpAddr::V4(ip) => (1, ip.octets().to_vec()),
+        std::net::IpAddr::V6(ip) => (2, ip.octets().to_vec()),
+    };
+    let mut value = vec![0, family];
+    value.extend_from_slice(&(addr.port() ^ 0x2112).to_be_bytes());
+    let key = MAGIC_COOKIE.iter().chain(txid);
+    value.extend(ip.iter().zip(key).map(|(b, k)| b ^ k));
+    value
+}
+
 fn pick_stun_target(turn: &TurnInfo) -> Option<String> {
     // Prefer stun: URLs. If none, try turn: with udp transport (might still answer binding).
     for u in &turn.urls {
@@ -136,22 +213,18 @@ fn parse_host_port(url: &str) -> Result<(String, u16)> {
     Ok((host.to_string(), port))
 }
 
-pub async fn run_udp_like_loss_probe(
-    turn: &TurnInfo,
-    cfg: &RunConfig,
-    event_tx: &mpsc::Sender<TestEvent>,
-    pre_resolved: Option<SocketAddr>,
-) -> Result<ExperimentalUdpSummary> {
-    let target_url = pick_stun_target(turn).context("no stun/turn url in /__turn")?;
-    let (host, port) = parse_host_port(&target_url)?;
-
-    let addr: SocketAddr = if let Some(a) = pre_resolved {
-        a
-    } else {
-        let mut addrs = tokio::net::lookup_host((host.as_str(), port)).await?;
-        addrs.next().context("dns returned no addresses")?
-    };
+/// Resolve the host of a `stun:` or `turn:` URL to an address the run may use.
+pub(crate) async fn resolve_target(url: &str, cfg: &RunConfig) -> Result<SocketAddr> {
+    let (host, port) = parse_host_port(url)?;
+    let mut addrs = tokio::net::lookup_host((host.as_str(), port)).await?;
+    addrs
+        .find(|a| cfg.allows_address(&a.ip()))
+        .context("dns returned no usable addresses")
+}
 
+/// UDP socket connected to `addr`, bound like the run's other connections and carrying
+/// the probe socket options (`--dscp`, buffers).
+pub(crate) async fn open_probe_socket(cfg: &RunConfig, addr: SocketAddr) -> Result<UdpSocket> {
     // Bind UDP socket to interface or source IP if specified
     let sock = if cfg.interface.is_some() || cfg.source_ip.is_some() {
         let bind_addr =
@@ -200,20 +273,50 @@ pub async fn run_udp_like_loss_probe(
             UdpSocket::from_std(std_socket)?
         } else {
             // Bind to appropriate address family based on target
-            let bind_addr = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+            let bind_addr = if addr.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
             UdpSocket::bind(bind_addr).await?
         }
     } else {
         // Bind ephemeral UDP - match target address family
-        let bind_addr = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let bind_addr = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
         UdpSocket::bind(bind_addr).await?
     };
 
+    let options = crate::engine::sockets::SocketOptions::for_probes(cfg);
+    options
+        .apply(socket2::SockRef::from(&sock), addr.is_ipv6())
+        .context("failed to set the socket options of the UDP probe")?;
+    options.log_buffers(socket2::SockRef::from(&sock), "UDP probe");
     sock.connect(addr).await?;
+    Ok(sock)
+}
+
+pub async fn run_udp_like_loss_probe(
+    turn: &TurnInfo,
+    cfg: &RunConfig,
+    event_tx: &mpsc::Sender<TestEvent>,
+    scheduler: &ProbeScheduler,
+    pre_resolved: Option<SocketAddr>,
+) -> Result<ExperimentalUdpSummary> {
+    let target_url = pick_stun_target(turn).context("no stun/turn url in /__turn")?;
+    let addr = match pre_resolved {
+        Some(a) => a,
+        None => resolve_target(&target_url, cfg).await?,
+    };
+    let sock = open_probe_socket(cfg, addr).await?;
 
-    let timeout = Duration::from_millis(600);
-    let interval = Duration::from_millis(80);
-    let attempts = cfg.udp_packets;
+    let params = probe_params(cfg);
+    let timeout = Duration::from_millis(params.timeout_ms);
+    let interval = Duration::from_millis(params.interval_ms);
+    let attempts = params.packets;
 
     let mut sent = 0u64;
     let mut received = 0u64;
@@ -224,14 +327,16 @@ pub async fn run_udp_like_loss_probe(
     let mut txid_to_seq: HashMap<[u8; 12], u64> = HashMap::new();
     let mut next_expected_seq: u64 = 1;
     let mut out_of_order: u64 = 0;
+    let mut mapped_address = None;
 
     for seq in 1..=attempts {
+        scheduler.acquire().await;
         sent += 1;
 
         let mut txid = [0u8; 12];
         rand::thread_rng().fill_bytes(&mut txid);
         txid_to_seq.insert(txid, seq);
-        let pkt = build_stun_binding_request(txid);
+        let pkt = build_stun_binding_request(txid, params.packet_size);
 
         let start = std::time::Instant::now();
         let _ = sock.send(&pkt).await;
@@ -244,6 +349,9 @@ pub async fn run_udp_like_loss_probe(
                 let ms = start.elapsed().as_secs_f64() * 1000.0;
                 samples.push(ms);
                 online.push(ms);
+                if mapped_address.is_none() {
+                    mapped_address = stun_mapped_address(&buf[..n]);
+                }
 
                 // Check for out-of-order: if this packet's seq < expected, it's reordered
                 if let Some(&pkt_seq) = txid_to_seq.get(&txid) {
@@ -314,5 +422,52 @@ pub async fn run_udp_like_loss_probe(
         out_of_order_pct,
         mos,
         quality_label: label.to_string(),
+        mapped_address: mapped_address.map(|a| a.to_string()),
+        relay: None,
+        relay_error: None,
+        params: Some(params),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_padded_binding_request() {
+        let txid = [7u8; 12];
+        assert_eq!(build_stun_binding_request(txid, 20).len(), 20);
+        let padded = build_stun_binding_request(txid, 100);
+        assert_eq!(padded.len(), 100);
+        // Message length covers the PADDING attribute and its 76 byte value
+        assert_eq!(u16::from_be_bytes([padded[2], padded[3]]), 80);
+        assert_eq!(&padded[20..24], &[0x00, 0x26, 0x00, 76]);
+        assert_eq!(&padded[8..20], &txid);
+    }
+
+    #[test]
+    fn test_xor_address_round_trip() {
+        let txid = [9u8; 12];
+        for addr in ["192.0.2.1:32853", "[2001:db8::1]:3478"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let value = encode_xor_address(addr, &txid);
+            assert_eq!(decode_address(&value, &txid, true), Some(addr));
+        }
+    }
+
+    #[test]
+    fn test_stun_mapped_address() {
+        // Binding success response with a SOFTWARE attribute before an
+        // XOR-MAPPED-ADDRESS of 192.0.2.1:32853 (RFC 5769 section 2.2)
+        let mut buf = vec![0x01, 0x01, 0x00, 0x14, 0x21, 0x12, 0xA4, 0x42];
+        buf.extend_from_slice(&[7; 12]);
+        buf.extend_from_slice(&[0x80, 0x22, 0x00, 0x03, b'c', b'f', b's', 0x00]);
+        buf.extend_from_slice(&[0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47]);
+        buf.extend_from_slice(&[0xe1, 0x12, 0xa6, 0x43]);
+        assert_eq!(
+            stun_mapped_address(&buf),
+            Some("192.0.2.1:32853".parse().unwrap())
+        );
+        assert_eq!(stun_mapped_address(&buf[..24]), None);
+    }
+}
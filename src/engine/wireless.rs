@@ -0,0 +1,246 @@
+//! Wi-Fi roaming and channel-switch monitor
+//!
+//! While a test runs on a wireless interface, nl80211 link events are followed via
+//! `iw event -t` so that sudden throughput or latency dips can be attributed to the
+//! station roaming between access points (or the AP changing channel) rather than to
+//! the ISP. Only Linux exposes these events; elsewhere the monitor is a no-op.
+
+use crate::model::{TestEvent, WirelessEvent, WirelessEventKind};
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// Running event monitor; call `stop` to collect what it saw. Dropping it, as a run
+/// that fails early does, stops it too.
+pub(crate) struct WirelessMonitor {
+    events: Arc<Mutex<Vec<WirelessEvent>>>,
+    task: JoinHandle<()>,
+}
+
+impl WirelessMonitor {
+    /// Start monitoring `interface` (or the default interface) if it is wireless.
+    /// Returns None when there is nothing to monitor.
+    pub(crate) async fn start(
+        interface: Option<&str>,
+        event_tx: &mpsc::Sender<TestEvent>,
+        run_start: Instant,
+    ) -> Option<Self> {
+        let iface = match interface {
+            Some(i) => i.to_string(),
+            None => crate::network::get_default_interface()?,
+        };
+        if crate::network::check_if_wireless(&iface) != Some(true) {
+            return None;
+        }
+
+        match spawn_iw_event(iface, event_tx.clone(), run_start) {
+            Ok(monitor) => Some(monitor),
+            Err(e) => {
+                event_tx
+                    .send(TestEvent::Info {
+                        message: format!("Wi-Fi event monitor unavailable: {e:#}"),
+                    })
+                    .await
+                    .ok();
+                None
+            }
+        }
+    }
+
+    /// Stop monitoring and return the recorded events in order.
+    pub(crate) fn stop(self) -> Vec<WirelessEvent> {
+        // Aborting drops the child handle, which kills `iw` (kill_on_drop)
+        self.task.abort();
+        std::mem::take(&mut *self.events.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl Drop for WirelessMonitor {
+    fn drop(&mut self) {
+        // The task holds a sender of the run's events and `iw event` never exits on its
+        // own, so left running it would keep the event channel open forever
+        self.task.abort();
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn spawn_iw_event(
+    iface: String,
+    event_tx: mpsc::Sender<TestEvent>,
+    run_start: Instant,
+) -> anyhow::Result<WirelessMonitor> {
+    use anyhow::Context;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    let mut child = tokio::process::Command::new("iw")
+        .args(["event", "-t"])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to run 'iw event'")?;
+    let stdout = child.stdout.take().context("no stdout from 'iw event'")?;
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events2 = events.clone();
+    let task = tokio::spawn(async move {
+        let _child = child;
+        let mut lines = BufReader::new(stdout).lines();
+        let mut bssid: Option<String> = None;
+        while let Ok(Some(line)) = lines.next_line().await {
+            let Some(link) = parse_iw_event(&line, &iface) else {
+                continue;
+            };
+            let (kind, bssid_now, freq_mhz) = match link {
+                LinkEvent::Connected(new) => {
+                    let kind = match bssid.replace(new.clone()) {
+                        Some(old) if old != new => WirelessEventKind::Roam,
+                        _ => WirelessEventKind::Connect,
+                    };
+                    (kind, Some(new), None)
+                }
+                LinkEvent::Disconnected => {
+                    bssid = None;
+                    (WirelessEventKind::Disconnect, None, None)
+                }
+                LinkEvent::ChannelSwitch(freq) => (WirelessEventKind::ChannelSwitch, None, freq),
+            };
+            let event = WirelessEvent {
+                t_ms: run_start.elapsed().as_millis() as u64,
+                interface: iface.clone(),
+                kind,
+                bssid: bssid_now,
+                freq_mhz,
+            };
+            events2
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(event.clone());
+            event_tx.send(TestEvent::Wireless { event }).await.ok();
+        }
+    });
+
+    Ok(WirelessMonitor { events, task })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn spawn_iw_event(
+    _iface: String,
+    _event_tx: mpsc::Sender<TestEvent>,
+    _run_start: Instant,
+) -> anyhow::Result<WirelessMonitor> {
+    anyhow::bail!("roaming events are only available on Linux")
+}
+
+/// Link-level change parsed from one line of `iw event` output
+#[cfg(target_os = "linux")]
+#[derive(Debug, PartialEq)]
+enum LinkEvent {
+    Connected(String),
+    Disconnected,
+    ChannelSwitch(Option<u32>),
+}
+
+/// Parse a line such as
+/// `1700000000.123456: wlan0 (phy #0): connected to 11:22:33:44:55:66`,
+/// ignoring events for other interfaces.
+#[cfg(target_os = "linux")]
+fn parse_iw_event(line: &str, iface: &str) -> Option<LinkEvent> {
+    // Drop the timestamp printed by `-t`
+    let line = match line.split_once(": ") {
+        Some((ts, rest)) if ts.parse::<f64>().is_ok() => rest,
+        _ => line,
+    };
+    let rest = line.strip_prefix(iface)?.strip_prefix(" (")?;
+    let (_, msg) = rest.split_once("): ")?;
+
+    if msg.starts_with("connected to") || msg.starts_with("roamed") {
+        msg.split_whitespace()
+            .find(|w| is_mac(w))
+            .map(|b| LinkEvent::Connected(b.to_ascii_lowercase()))
+    } else if msg.starts_with("disconnected") {
+        Some(LinkEvent::Disconnected)
+    } else if msg.contains("channel switch") || msg.starts_with("ch_switch") {
+        // "channel switch (freq=5180 MHz, width: 80 MHz, ...)"
+        let freq = msg
+            .split_once("freq")
+            .map(|(_, f)| f.trim_start_matches(['=', ':', ' ']))
+            .and_then(|f| {
+                f.split(|c: char| !c.is_ascii_digit())
+                    .next()
+                    .and_then(|d| d.parse().ok())
+            });
+        Some(LinkEvent::ChannelSwitch(freq))
+    } else {
+        None
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_mac(s: &str) -> bool {
+    let s = s.trim_end_matches([',', ')']);
+    s.len() == 17
+        && s.split(':')
+            .all(|p| p.len() == 2 && p.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dropped_monitor_closes_the_event_channel() {
+        let (event_tx, mut event_rx) = mpsc::channel::<TestEvent>(1);
+        // Stands in for the `iw event` reader, which never ends by itself
+        let task = tokio::spawn(async move {
+            let _event_tx = event_tx;
+            std::future::pending::<()>().await
+        });
+        let monitor = WirelessMonitor {
+            events: Arc::default(),
+            task,
+        };
+
+        // A run that fails after starting the monitor returns without calling `stop`
+        drop(monitor);
+        let closed = tokio::time::timeout(std::time::Duration::from_secs(5), event_rx.recv());
+        assert!(closed.await.expect("event channel left open").is_none());
+    }
+
+    #[test]
+    fn test_parse_iw_event() {
+        assert_eq!(
+            parse_iw_event(
+                "1700000000.123456: wlan0 (phy #0): connected to 11:22:33:44:55:AA",
+                "wlan0"
+            ),
+            Some(LinkEvent::Connected("11:22:33:44:55:aa".to_string()))
+        );
+        assert_eq!(
+            parse_iw_event(
+                "wlan0 (phy #0): disconnected (by AP) reason: 8: Disassociated",
+                "wlan0"
+            ),
+            Some(LinkEvent::Disconnected)
+        );
+        assert_eq!(
+            parse_iw_event(
+                "1700000001.5: wlan0 (phy #0): channel switch (freq=5180 MHz, width: 80 MHz)",
+                "wlan0"
+            ),
+            Some(LinkEvent::ChannelSwitch(Some(5180)))
+        );
+        assert_eq!(
+            parse_iw_event(
+                "1700000000.1: wlan1 (phy #1): connected to 11:22:33:44:55:66",
+                "wlan0"
+            ),
+            None
+        );
+        assert_eq!(
+            parse_iw_event("1700000000.1: wlan0 (phy #0): scan started", "wlan0"),
+            None
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Importing results from other sources (`history import`)
+//!
+//! Accepts this tool's own output (a single result, a JSON array such as
+//! `history query --json`, or NDJSON from `history export --format ndjson`) and the
+//! results exported from speed.cloudflare.com. The website reports the measurement
+//! summary of the `@cloudflare/speedtest` library (throughput in bit/s, latency and jitter
+//! in ms, packet loss as a fraction), which is mapped onto the matching `RunResult`
+//! fields; everything the website does not measure is left empty.
+
+use crate::model::{
+    ExperimentalUdpSummary, LatencySummary, RunResult, ThroughputSummary, SCHEMA_VERSION,
+};
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::path::Path;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+const WEBSITE_URL: &str = "https://speed.cloudflare.com";
+
+/// Parse every result in `path`, in file order.
+pub fn load(path: &Path) -> Result<Vec<RunResult>> {
+    let data = std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))?;
+    let records = match serde_json::from_str::<Value>(&data) {
+        Ok(Value::Array(items)) => items,
+        // A single result, or a wrapper object holding a list of them
+        Ok(Value::Object(map)) if map.contains_key("download") => vec![Value::Object(map)],
+        Ok(Value::Object(map)) => ["results", "history"]
+            .iter()
+            .find_map(|k| map.get(*k).and_then(Value::as_array))
+            .cloned()
+            .with_context(|| format!("{}: no results found", path.display()))?,
+        Ok(_) => anyhow::bail!("{}: expected a JSON object or array", path.display()),
+        // Not a single document: one JSON object per line
+        Err(_) => data
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .enumerate()
+            .map(|(i, l)| {
+                serde_json::from_str(l)
+                    .with_context(|| format!("{}: line {} is not JSON", path.display(), i + 1))
+            })
+            .collect::<Result<_>>()?,
+    };
+    records
+        .into_iter()
+        .enumerate()
+        .map(|(i, record)| {
+            convert(record).with_context(|| format!("{}: record {}", path.display(), i + 1))
+        })
+        .collect()
+}
+
+fn convert(record: Value) -> Result<RunResult> {
+    // Our own results always carry the measurement summaries as objects
+    if record.get("meas_id").is_some() && record.get("download").is_some_and(Value::is_object) {
+        let mut run = crate::storage::parse_run(record)?;
+        // Both end up in the run's file name
+        run.meas_id = file_safe(&run.meas_id);
+        run.timestamp_utc = parse_timestamp(&Value::String(run.timestamp_utc))?;
+        return Ok(run);
+    }
+    from_website(&record)
+}
+
+/// Convert one speed.cloudflare.com result. The summary may sit at the top level or
+/// under `summary`.
+fn from_website(record: &Value) -> Result<RunResult> {
+    let summary = record.get("summary").unwrap_or(record);
+    let num = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|k| summary.get(*k).or_else(|| record.get(*k)))
+            .and_then(Value::as_f64)
+    };
+    let text = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|k| record.get(*k))
+            .and_then(|v| match v {
+                Value::String(s) => Some(s.clone()),
+                Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            })
+    };
+
+    let download = num(&["download"]);
+    let upload = num(&["upload"]);
+    anyhow::ensure!(
+        download.is_some() || upload.is_some(),
+        "not a speed test result (no download or upload speed)"
+    );
+    let timestamp = ["date", "timestamp", "time"]
+        .iter()
+        .find_map(|k| record.get(*k))
+        .context("missing date of the test")
+        .and_then(parse_timestamp)?;
+
+    let latency = |median: Option<f64>, jitter: Option<f64>| LatencySummary {
+        median_ms: median,
+        jitter_ms: jitter,
+        ..Default::default()
+    };
+    let throughput = |bps: Option<f64>| ThroughputSummary {
+        mbps: bps.unwrap_or(0.0) / 1_000_000.0,
+        ..Default::default()
+    };
+    // Stable across re-imports, so importing the same file twice replaces the runs. The
+    // id ends up in the run's file name.
+    let meas_id = text(&["measurementId", "measId", "id"])
+        .map(|id| file_safe(&id))
+        .unwrap_or_else(|| {
+            format!(
+                "imported-{}",
+                timestamp.replace(|c: char| !c.is_ascii_digit(), "")
+            )
+        });
+
+    let mut run: RunResult = serde_json::from_value(json!({
+        "timestamp_utc": timestamp,
+        "base_url": WEBSITE_URL,
+        "meas_id": meas_id,
+        "idle_latency": latency(num(&["latency"]), num(&["jitter"])),
+        "download": throughput(download),
+        "upload": throughput(upload),
+        "loaded_latency_download": latency(
+            num(&["downLoadedLatency"]),
+            num(&["downLoadedJitter"])
+        ),
+        "loaded_latency_upload": latency(num(&["upLoadedLatency"]), num(&["upLoadedJitter"])),
+    }))?;
+    run.schema_version = SCHEMA_VERSION;
+    run.colo = text(&["colo"]);
+    run.asn = text(&["asn"]);
+    run.ip = text(&["ip", "clientIp"]);
+    if let Some(loss) = num(&["packetLoss"]) {
+        run.experimental_udp = Some(ExperimentalUdpSummary {
+            target: Some(WEBSITE_URL.to_string()),
+            latency: LatencySummary {
+                loss,
+                ..Default::default()
+            },
+            out_of_order: 0,
+            out_of_order_pct: 0.0,
+            mos: None,
+            quality_label: String::new(),
+            mapped_address: None,
+            relay: None,
+            relay_error: None,
+            params: None,
+        });
+    }
+    Ok(run)
+}
+
+/// `id` with everything but ASCII letters, digits and dashes replaced, for a file name
+fn file_safe(id: &str) -> String {
+    id.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_")
+}
+
+/// RFC 3339 text or a Unix timestamp in milliseconds, as an RFC 3339 UTC string.
+fn parse_timestamp(v: &Value) -> Result<String> {
+    let t = match v {
+        Value::String(s) => {
+            OffsetDateTime::parse(s, &Rfc3339).with_context(|| format!("invalid date '{}'", s))?
+        }
+        Value::Number(n) => {
+            let ms = n.as_i64().context("invalid timestamp")?;
+            OffsetDateTime::from_unix_timestamp_nanos(i128::from(ms) * 1_000_000)
+                .context("timestamp out of range")?
+        }
+        _ => anyhow::bail!("invalid date {}", v),
+    };
+    Ok(t.to_offset(time::UtcOffset::UTC).format(&Rfc3339)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_website() {
+        let run = convert(json!({
+            "date": "2026-03-01T12:00:00+01:00",
+            "download": 94_200_000.0,
+            "upload": 19_800_000.0,
+            "latency": 12.5,
+            "jitter": 1.5,
+            "downLoadedLatency": 40.0,
+            "packetLoss": 0.01,
+            "colo": "AMS",
+        }))
+        .unwrap();
+        assert_eq!(run.timestamp_utc, "2026-03-01T11:00:00Z");
+        assert!((run.download.mbps - 94.2).abs() < 1e-9);
+        assert_eq!(run.idle_latency.median_ms, Some(12.5));
+        assert_eq!(run.loaded_latency_download.median_ms, Some(40.0));
+        assert_eq!(run.colo.as_deref(), Some("AMS"));
+        assert_eq!(run.experimental_udp.unwrap().latency.loss, 0.01);
+
+        assert!(convert(json!({ "download": 1.0 })).is_err());
+        assert!(convert(json!({ "date": 0, "colo": "AMS" })).is_err());
+    }
+
+    #[test]
+    fn test_own_result_cannot_escape_the_runs_directory() {
+        let mut record = json!({
+            "timestamp_utc": "2026-03-01T12:00:00+01:00",
+            "base_url": WEBSITE_URL,
+            "meas_id": "../../.bashrc",
+            "idle_latency": LatencySummary::default(),
+            "download": { "bytes": 1, "duration_ms": 1, "mbps": 8.0 },
+            "upload": { "bytes": 1, "duration_ms": 1, "mbps": 8.0 },
+        });
+        let run = convert(record.clone()).unwrap();
+        assert_eq!(run.meas_id, "_______bashrc");
+        assert_eq!(run.timestamp_utc, "2026-03-01T11:00:00Z");
+
+        record["timestamp_utc"] = json!("../../etc/passwd");
+        assert!(convert(record).is_err());
+    }
+}
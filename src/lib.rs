@@ -0,0 +1,54 @@
+//! Cloudflare speed test engine, as used by the `cloudflare-speed-cli` binary.
+//!
+//! A run is described by a [`RunConfig`], carried out by a [`TestEngine`] that reports its
+//! progress as [`TestEvent`]s on a channel, and ends in a [`RunResult`]. The same result
+//! is what the command line prints as JSON and what [`storage`] keeps in the history.
+//!
+//! ```no_run
+//! use cloudflare_speed_cli::{EngineControl, RunConfig, TestEngine, TestEvent};
+//! use tokio::sync::mpsc;
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let cfg = RunConfig {
+//!     download_duration: std::time::Duration::from_secs(5),
+//!     ..RunConfig::default()
+//! };
+//! let (event_tx, mut event_rx) = mpsc::channel::<TestEvent>(1024);
+//! // Keep the sender to pause or cancel the run; dropping it is fine too
+//! let (_control_tx, control_rx) = mpsc::channel::<EngineControl>(16);
+//! let run = tokio::spawn(TestEngine::new(cfg).run(event_tx, control_rx));
+//! while let Some(event) = event_rx.recv().await {
+//!     if let TestEvent::PhaseStarted { phase } = event {
+//!         println!("{:?}", phase);
+//!     }
+//! }
+//! let result = run.await??;
+//! println!("{:.1} Mbit/s down", result.download.mbps);
+//! cloudflare_speed_cli::storage::save_run(&result, Default::default())?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! `engine`, `model`, `stats` and `storage` are the public API. The other modules are
+//! shared with the binary and may change between releases.
+
+pub mod engine;
+pub mod model;
+pub mod stats;
+pub mod storage;
+
+#[doc(hidden)]
+pub mod aim;
+#[doc(hidden)]
+pub mod comparison;
+#[doc(hidden)]
+pub mod context;
+#[doc(hidden)]
+pub mod metrics;
+#[doc(hidden)]
+pub mod network;
+#[doc(hidden)]
+pub mod report;
+
+pub use engine::{EngineControl, TestEngine};
+pub use model::{RunConfig, RunResult, TestEvent};
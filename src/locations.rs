@@ -0,0 +1,185 @@
+//! Cloudflare data centers (`locations`).
+//!
+//! Lists the colos from `/locations`, nearest first when the service reports where the
+//! client is, and estimates the latency to the nearest few by probing each through the
+//! `cf.colo` override `--colo` uses.
+
+use crate::engine::cloudflare::{self, CloudflareClient};
+use crate::model::RunConfig;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Latency probes sent to each colo; the fastest counts, as the first opens the connection
+const PROBES: usize = 3;
+
+/// Mean radius of the Earth, for great-circle distances
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// One Cloudflare data center
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Location {
+    /// IATA code, as `--colo` takes it
+    pub iata: String,
+    #[serde(default)]
+    pub city: String,
+    /// ISO country code
+    #[serde(default, alias = "cca2")]
+    pub country: String,
+    #[serde(default)]
+    pub region: String,
+    #[serde(default)]
+    pub lat: Option<f64>,
+    #[serde(default)]
+    pub lon: Option<f64>,
+    /// Great-circle distance from the client, when both positions are known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distance_km: Option<f64>,
+    /// Fastest of the latency probes sent through `cf.colo`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    /// Colo that answered the probes, when not the one asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub served_by: Option<String>,
+}
+
+/// The colos known to the service, nearest first, with the nearest `probe` of them probed
+/// for latency.
+pub async fn fetch(cfg: &RunConfig, probe: usize) -> Result<Vec<Location>> {
+    let client = CloudflareClient::new(cfg)?;
+    let raw = cloudflare::fetch_locations(&client)
+        .await
+        .context("failed to fetch /locations")?
+        .context("the endpoint has no /locations list")?;
+    let mut locations: Vec<Location> =
+        serde_json::from_value(raw).context("unexpected /locations response")?;
+
+    // Where the client is: the service's own estimate, else the colo serving it
+    let meta = cloudflare::fetch_meta(&client).await.ok();
+    let position = meta.as_ref().and_then(|m| {
+        let coordinate = |key: &str| match m.get(key)? {
+            serde_json::Value::String(s) => s.parse().ok(),
+            v => v.as_f64(),
+        };
+        coordinate("latitude")
+            .zip(coordinate("longitude"))
+            .or_else(|| {
+                let colo = m.get("colo")?.as_str()?;
+                let here = locations.iter().find(|l| l.iata == colo)?;
+                here.lat.zip(here.lon)
+            })
+    });
+    rank(&mut locations, position);
+
+    let probed = futures::future::join_all(
+        locations
+            .iter()
+            .take(probe)
+            .map(|l| probe_colo(cfg, &l.iata)),
+    )
+    .await;
+    for (location, (latency_ms, served_by)) in locations.iter_mut().zip(probed) {
+        location.latency_ms = latency_ms;
+        location.served_by = served_by.filter(|s| !s.eq_ignore_ascii_case(&location.iata));
+    }
+    Ok(locations)
+}
+
+/// Fill in the distances from `position` and sort nearest first; by code when the position
+/// is unknown.
+fn rank(locations: &mut [Location], position: Option<(f64, f64)>) {
+    for location in locations.iter_mut() {
+        location.distance_km = position
+            .zip(location.lat.zip(location.lon))
+            .map(|(from, to)| distance_km(from, to));
+    }
+    locations.sort_by(|a, b| match (a.distance_km, b.distance_km) {
+        (Some(x), Some(y)) => x.total_cmp(&y),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.iata.cmp(&b.iata),
+    });
+}
+
+/// Great-circle (haversine) distance between two (latitude, longitude) points in degrees
+fn distance_km((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Fastest of `PROBES` latency probes asking for `iata`, and the colo that answered them.
+async fn probe_colo(cfg: &RunConfig, iata: &str) -> (Option<f64>, Option<String>) {
+    let cfg = RunConfig {
+        colo: Some(iata.to_string()),
+        ..cfg.clone()
+    };
+    let Ok(client) = CloudflareClient::new(&cfg) else {
+        return (None, None);
+    };
+    let mut fastest: Option<f64> = None;
+    let mut served_by = None;
+    for _ in 0..PROBES {
+        let Ok((ms, meta)) = client.probe_latency_ms(None, cfg.probe_timeout_ms).await else {
+            continue;
+        };
+        fastest = Some(fastest.map_or(ms, |f| f.min(ms)));
+        if let Some(colo) = meta
+            .as_ref()
+            .and_then(|m| m.get("colo"))
+            .and_then(|v| v.as_str())
+        {
+            served_by = Some(colo.to_string());
+        }
+    }
+    (fastest, served_by)
+}
+
+/// `locations`: print the colos as a table, or as JSON.
+pub async fn run(cfg: &RunConfig, probe: usize, json: bool) -> Result<()> {
+    let locations = fetch(cfg, probe).await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&locations)?);
+        return Ok(());
+    }
+    for l in &locations {
+        let distance = l
+            .distance_km
+            .map(|d| format!("{:.0} km", d))
+            .unwrap_or_default();
+        let latency = match (l.latency_ms, &l.served_by) {
+            (Some(ms), Some(other)) => format!("{:.1} ms (answered by {})", ms, other),
+            (Some(ms), None) => format!("{:.1} ms", ms),
+            (None, _) => String::new(),
+        };
+        println!(
+            "{:<4} {:<24} {:<3} {:<16} {:>9}  {}",
+            l.iata, l.city, l.country, l.region, distance, latency
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rank_nearest_first() {
+        let mut locations: Vec<Location> = serde_json::from_value(serde_json::json!([
+            { "iata": "SIN", "city": "Singapore", "cca2": "SG", "lat": 1.35, "lon": 103.99 },
+            { "iata": "XYZ", "city": "Nowhere" },
+            { "iata": "AMS", "city": "Amsterdam", "cca2": "NL", "lat": 52.31, "lon": 4.76 },
+        ]))
+        .unwrap();
+        // From Frankfurt
+        rank(&mut locations, Some((50.03, 8.57)));
+        let order: Vec<&str> = locations.iter().map(|l| l.iata.as_str()).collect();
+        assert_eq!(order, ["AMS", "SIN", "XYZ"]);
+        let ams = locations[0].distance_km.unwrap();
+        assert!((350.0..400.0).contains(&ams), "{ams}");
+        assert_eq!(locations[0].country, "NL");
+        assert!(locations[2].distance_km.is_none());
+    }
+}
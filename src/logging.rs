@@ -0,0 +1,148 @@
+//! Diagnostic logging (`-v`, `-vv`, `--quiet`)
+//!
+//! Messages go through `tracing` and are written to stderr, one line each without
+//! timestamps. While the dashboard owns the terminal they are kept in a bounded buffer
+//! instead, shown on the dashboard's info line and printed once the terminal is restored.
+//! Under systemd, when stderr goes to the journal, they are sent to journald with their
+//! fields and level instead.
+
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tracing_subscriber::filter::{LevelFilter, Targets};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
+
+/// Lines kept while capturing; older ones are dropped
+const CAPTURE_LIMIT: usize = 500;
+
+struct Capture {
+    lines: VecDeque<String>,
+    /// Number of lines captured so far, including dropped ones
+    total: u64,
+}
+
+/// `Some` while the dashboard is running
+static CAPTURE: Mutex<Option<Capture>> = Mutex::new(None);
+
+/// Set when messages go to journald
+static JOURNAL: AtomicBool = AtomicBool::new(false);
+
+/// Install the global subscriber. Without flags info and above is shown; `-v` adds debug,
+/// `-vv` trace along with debug output of the HTTP and TLS libraries, and `--quiet` (or
+/// `--silent`) leaves only errors.
+pub fn init(verbose: u8, quiet: bool) {
+    let (ours, libraries) = match (quiet, verbose) {
+        (true, _) => (LevelFilter::ERROR, LevelFilter::ERROR),
+        (false, 0) => (LevelFilter::INFO, LevelFilter::WARN),
+        (false, 1) => (LevelFilter::DEBUG, LevelFilter::WARN),
+        (false, _) => (LevelFilter::TRACE, LevelFilter::DEBUG),
+    };
+    let filter = Targets::new()
+        .with_target(env!("CARGO_CRATE_NAME"), ours)
+        .with_default(libraries);
+    #[cfg(target_os = "linux")]
+    if crate::systemd::stderr_is_journal() {
+        // Fields keep their own names (DOWNLOAD_MBPS rather than F_DOWNLOAD_MBPS)
+        if let Ok(layer) = tracing_journald::layer() {
+            let layer = layer.with_field_prefix(None);
+            let _ = tracing_subscriber::registry()
+                .with(layer.with_filter(filter))
+                .try_init();
+            JOURNAL.store(true, Ordering::Relaxed);
+            return;
+        }
+    }
+    let layer = tracing_subscriber::fmt::layer()
+        .without_time()
+        .with_target(verbose > 0)
+        .with_writer(LogWriter);
+    let _ = tracing_subscriber::registry()
+        .with(layer.with_filter(filter))
+        .try_init();
+}
+
+/// Whether messages go to journald rather than stderr
+pub fn to_journal() -> bool {
+    JOURNAL.load(Ordering::Relaxed)
+}
+
+/// Start keeping log lines in memory instead of writing them to stderr.
+pub fn start_capture() {
+    *CAPTURE.lock().unwrap() = Some(Capture {
+        lines: VecDeque::new(),
+        total: 0,
+    });
+}
+
+/// Stop capturing and return the lines kept, oldest first.
+pub fn end_capture() -> Vec<String> {
+    CAPTURE
+        .lock()
+        .unwrap()
+        .take()
+        .map(|c| c.lines.into())
+        .unwrap_or_default()
+}
+
+/// The newest captured line and its sequence number, to tell whether it is new.
+pub fn latest() -> Option<(u64, String)> {
+    let guard = CAPTURE.lock().unwrap();
+    let capture = guard.as_ref()?;
+    capture
+        .lines
+        .back()
+        .map(|line| (capture.total, line.clone()))
+}
+
+#[derive(Clone, Copy)]
+struct LogWriter;
+
+impl<'a> MakeWriter<'a> for LogWriter {
+    type Writer = LineWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LineWriter(Vec::new())
+    }
+}
+
+/// Buffers one formatted event and hands it to the capture or stderr when dropped
+struct LineWriter(Vec<u8>);
+
+impl Write for LineWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LineWriter {
+    fn drop(&mut self) {
+        if self.0.is_empty() {
+            return;
+        }
+        let mut guard = CAPTURE.lock().unwrap();
+        match guard.as_mut() {
+            Some(capture) => {
+                let text = String::from_utf8_lossy(&self.0);
+                for line in text.lines().filter(|l| !l.trim().is_empty()) {
+                    if capture.lines.len() == CAPTURE_LIMIT {
+                        capture.lines.pop_front();
+                    }
+                    capture.lines.push_back(line.trim().to_string());
+                    capture.total += 1;
+                }
+            }
+            None => {
+                let _ = std::io::stderr().write_all(&self.0);
+            }
+        }
+    }
+}
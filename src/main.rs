@@ -1,23 +1,45 @@
+mod api;
+mod calibrate;
+mod chat;
 mod cli;
-mod engine;
-mod metrics;
-mod model;
-mod network;
-mod stats;
-mod storage;
+mod config;
+mod diff;
+mod import;
+mod locations;
+mod logging;
+mod monitor;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod notify;
+mod schedule;
+mod sinks;
+mod sync;
+mod systemd;
+mod thresholds;
 #[cfg(feature = "tui")]
 mod tui;
 #[cfg(feature = "tui")]
 mod update;
 
 use anyhow::Result;
-use clap::Parser;
+#[cfg(feature = "tui")]
+use cloudflare_speed_cli::aim;
+use cloudflare_speed_cli::{
+    comparison, context, engine, metrics, model, network, report, stats, storage,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = cli::Cli::parse();
+    let args = config::parse_args()?;
+    logging::init(args.verbose, args.quiet || args.silent);
     let is_silent = args.silent;
-    let is_non_tui = args.silent || args.json || args.text;
+    let is_non_tui = args.silent
+        || args.json
+        || args.text
+        || args.events_ndjson
+        || args.calibrate
+        || args.interval.is_some()
+        || args.command.is_some();
 
     match cli::run(args).await {
         Ok(()) => {
@@ -27,6 +49,10 @@ async fn main() -> Result<()> {
             Ok(())
         }
         Err(e) => {
+            if let Some(failed) = e.downcast_ref::<thresholds::ThresholdError>() {
+                eprintln!("{}", failed.to_json());
+                std::process::exit(thresholds::EXIT_CODE);
+            }
             if is_silent {
                 println!("{}", e);
                 std::process::exit(1);
@@ -17,6 +17,17 @@ pub fn compute_metrics(samples: &[f64]) -> Option<(f64, f64, f64, f64)> {
     Some((mean, median, p25, p75))
 }
 
+/// Percentile (0.0-1.0) of the samples, using the same index rule as `compute_metrics`.
+pub fn percentile(samples: &[f64], p: f64) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let idx = ((sorted.len() as f64 * p) as usize).min(sorted.len() - 1);
+    Some(sorted[idx])
+}
+
 /// Compute jitter (standard deviation) from latency samples.
 pub fn compute_jitter(samples: &[f64]) -> Option<f64> {
     if samples.len() < 2 {
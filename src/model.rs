@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
-use std::time::Duration;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
 
 mod loss_percent_serde {
     use serde::{Deserialize, Deserializer, Serializer};
@@ -23,6 +24,9 @@ mod loss_percent_serde {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RunConfig {
     pub base_url: String,
+    /// Base URLs to fail over to, in order, when `base_url` does not answer
+    #[serde(default)]
+    pub fallback_urls: Vec<String>,
     pub meas_id: String,
     #[serde(default)]
     pub comments: Option<String>,
@@ -37,21 +41,480 @@ pub struct RunConfig {
     pub upload_duration: Duration,
     pub probe_interval_ms: u64,
     pub probe_timeout_ms: u64,
+    /// Hard limit for the whole run, enforced by the engine watchdog
+    #[serde(default, with = "humantime_serde")]
+    pub max_run_time: Option<Duration>,
+    /// Cap on the bytes transferred by the download and upload phases together
+    #[serde(default)]
+    pub max_total_bytes: Option<u64>,
+    /// Connection counts to repeat the download phase with
+    #[serde(default)]
+    pub sweep_concurrency: Vec<usize>,
+    /// Run a short download over each HTTP version after the main phases
+    #[serde(default)]
+    pub compare_protocols: bool,
+    /// Record the measurement requests into a HAR file at this path
+    #[serde(default)]
+    pub export_har: Option<std::path::PathBuf>,
+    /// Initial part of download and upload left out of their figures; 20% of the phase
+    /// (at least 1s) when unset
+    #[serde(default, with = "humantime_serde")]
+    pub warmup: Option<Duration>,
+    #[serde(default)]
+    pub steady_window: SteadyWindow,
+    /// End download and upload early once the throughput's coefficient of variation over
+    /// the last ticks falls below this percentage
+    #[serde(default)]
+    pub adaptive_cv_pct: Option<f64>,
     pub user_agent: String,
     pub experimental: bool,
     pub interface: Option<String>,
     pub source_ip: Option<String>,
     pub proxy: Option<String>,
     pub certificate_path: Option<std::path::PathBuf>,
+    /// `--client-cert`/`--client-key`: identity for mutual TLS, both PEM
+    pub client_cert_path: Option<std::path::PathBuf>,
+    pub client_key_path: Option<std::path::PathBuf>,
+    /// DNS server to resolve the test endpoint with instead of the system resolver
+    #[serde(default)]
+    pub dns_server: Option<std::net::SocketAddr>,
+    /// DNS-over-HTTPS endpoint to resolve the test endpoint with (`--doh`)
+    #[serde(default)]
+    pub doh_url: Option<String>,
+    /// DNS-over-TLS server ("HOST:PORT") to resolve the test endpoint with (`--dot`)
+    #[serde(default)]
+    pub dot_server: Option<String>,
+    /// Extra headers sent with every test request (`--header`)
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    /// Kind of server at `base_url` (`--backend`)
+    #[serde(default)]
+    pub backend: Backend,
+    /// Bearer token sent with every test request (`--endpoint-auth-token`)
+    #[serde(default)]
+    pub endpoint_auth_token: Option<String>,
+    /// Path of the download endpoint; a `{bytes}` in it stands for the size, which is
+    /// otherwise the `bytes` query parameter (`--down-path`)
+    #[serde(default = "default_down_path")]
+    pub down_path: String,
+    /// Path of the upload endpoint (`--up-path`)
+    #[serde(default = "default_up_path")]
+    pub up_path: String,
+    /// `--resolve` overrides; the one for the base URL's host and port pins the endpoint
+    #[serde(default)]
+    pub resolve: Vec<ResolveOverride>,
+    /// Cloudflare data center to test against (`--colo`), as an uppercase IATA code
+    #[serde(default)]
+    pub colo: Option<String>,
+    /// DSCP code point set on the latency and UDP probes (`--dscp`)
+    #[serde(default)]
+    pub dscp: Option<u8>,
+    /// TCP congestion control for the engine's connections (`--congestion`)
+    #[serde(default)]
+    pub congestion: Option<String>,
+    /// Socket send buffer for the engine's connections, in bytes (`--send-buffer`)
+    #[serde(default)]
+    pub send_buffer: Option<u64>,
+    /// Socket receive buffer for the engine's connections, in bytes (`--recv-buffer`)
+    #[serde(default)]
+    pub recv_buffer: Option<u64>,
     // Diagnostic options
     pub measure_dns: bool,
+    /// Check whether the resolver validates DNSSEC along with the DNS measurement
+    #[serde(default)]
+    pub check_dnssec: bool,
     pub measure_tls: bool,
+    /// Break a fresh request down into DNS, connect, TLS and TTFB per phase
+    pub measure_timing: bool,
     pub compare_ip_versions: bool,
     pub traceroute: bool,
     pub traceroute_max_hops: u8,
+    /// How traceroute probes the path (`--traceroute-protocol`)
+    #[serde(default)]
+    pub traceroute_protocol: TracerouteProtocol,
+    /// Keep the flow identifiers constant across traceroute probes (`--traceroute-paris`)
+    #[serde(default)]
+    pub traceroute_paris: bool,
+    /// Number of Paris traceroute flows to trace (`--traceroute-paths`)
+    #[serde(default = "default_traceroute_paths")]
+    pub traceroute_paths: u8,
+    /// Discover the path MTU toward the endpoint (`--mtu`)
+    #[serde(default)]
+    pub measure_mtu: bool,
     pub ipv4_only: bool,
     pub ipv6_only: bool,
     pub udp_packets: u64,
+    /// UDP payload size of each packet loss probe, in bytes
+    #[serde(default = "default_udp_packet_size")]
+    pub udp_packet_size: u16,
+    /// Pause between packet loss probes
+    #[serde(default = "default_udp_interval_ms")]
+    pub udp_interval_ms: u64,
+    /// How long a packet loss probe waits for its answer
+    #[serde(default = "default_udp_timeout_ms")]
+    pub udp_timeout_ms: u64,
+    /// Length of the UDP stream through the TURN relay, if any
+    #[serde(default, with = "humantime_serde")]
+    pub udp_throughput: Option<Duration>,
+    /// Rate the UDP stream is paced at, in Mbit/s
+    #[serde(default = "default_udp_throughput_mbps")]
+    pub udp_throughput_mbps: f64,
+    /// Reference anchors probed during the idle phase (host[:port] or "gateway")
+    #[serde(default)]
+    pub anchors: Vec<String>,
+    /// Ping the endpoint over ICMP during the idle phase (`--icmp-ping`)
+    #[serde(default)]
+    pub icmp_ping: bool,
+    /// URL answering with an empty 204, fetched before the phases to detect captive
+    /// portals; `None` skips the check
+    #[serde(default)]
+    pub captive_portal_url: Option<String>,
+    /// Keep testing behind a detected captive portal instead of failing the run
+    #[serde(default)]
+    pub allow_captive_portal: bool,
+    /// Wall-clock instant to start the run at, shared by all probes of a synchronized test
+    #[serde(default, with = "humantime_serde")]
+    pub sync_start: Option<SystemTime>,
+    /// Static key-value context attached to the result
+    #[serde(default)]
+    pub context: BTreeMap<String, String>,
+    /// User labels for grouping runs (from --tag)
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// Command whose output is attached to the result as additional context
+    #[serde(default)]
+    pub context_hook: Option<String>,
+    /// Modem status page to scrape: preset name ("docsis", "dsl") or URL
+    #[serde(default)]
+    pub modem: Option<String>,
+    /// Additional or overriding modem metrics as (name, "regex:..." | "json:...")
+    #[serde(default)]
+    pub modem_metrics: Vec<(String, String)>,
+    /// Length of the soak phase run after the regular test, if any
+    #[serde(default, with = "humantime_serde")]
+    pub soak: Option<Duration>,
+    /// Direction saturated during the soak phase (Download or Upload)
+    #[serde(default = "default_soak_direction")]
+    pub soak_direction: Phase,
+    /// Run the website's staged payload-size measurements after the upload phase
+    #[serde(default)]
+    pub ramped: bool,
+    /// Phases to measure (`--no-upload`, `--latency-only`, light runs in monitor mode)
+    #[serde(default)]
+    pub phases: PhaseSelection,
+    /// HTTP version used for the measurement requests
+    #[serde(default)]
+    pub http_version: HttpVersion,
+}
+
+impl RunConfig {
+    /// Whether `-4`/`-6` let the run use `ip`
+    pub fn allows_address(&self, ip: &std::net::IpAddr) -> bool {
+        !(self.ipv4_only && ip.is_ipv6() || self.ipv6_only && ip.is_ipv4())
+    }
+}
+
+/// A `--resolve HOST:PORT:ADDR` entry, as in curl
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResolveOverride {
+    pub host: String,
+    pub port: u16,
+    pub ip: std::net::IpAddr,
+}
+
+impl std::fmt::Display for ResolveOverride {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.ip {
+            std::net::IpAddr::V4(ip) => write!(f, "{}:{}:{}", self.host, self.port, ip),
+            std::net::IpAddr::V6(ip) => write!(f, "{}:{}:[{}]", self.host, self.port, ip),
+        }
+    }
+}
+
+/// Kind of server the test runs against (`--backend`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// speed.cloudflare.com, or a Worker serving its endpoints
+    #[default]
+    Cloudflare,
+    /// A LibreSpeed server (`garbage.php`, `empty.php`, `getIP.php`)
+    Librespeed,
+    /// Another instance of this binary running `serve --lan` (`--lan`)
+    Lan,
+}
+
+impl Backend {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Backend::Cloudflare => "Cloudflare",
+            Backend::Librespeed => "LibreSpeed",
+            Backend::Lan => "LAN",
+        }
+    }
+}
+
+/// HTTP version requested for the measurement requests (`--protocol`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HttpVersion {
+    /// Whatever ALPN negotiates (HTTP/2 with Cloudflare, HTTP/1.1 through most proxies)
+    #[default]
+    Auto,
+    Http1,
+    Http2,
+    /// QUIC; needs the `http3` build feature
+    Http3,
+}
+
+impl HttpVersion {
+    pub fn label(&self) -> &'static str {
+        match self {
+            HttpVersion::Auto => "auto",
+            HttpVersion::Http1 => "HTTP/1.1",
+            HttpVersion::Http2 => "HTTP/2",
+            HttpVersion::Http3 => "HTTP/3",
+        }
+    }
+}
+
+/// Probes traceroute sends (`--traceroute-protocol`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TracerouteProtocol {
+    /// Raw ICMP when privileged, then UDP over ordinary sockets, then the system command
+    #[default]
+    Auto,
+    /// ICMP echo over a raw socket; needs CAP_NET_RAW or root
+    Icmp,
+    /// UDP datagrams to high ports
+    Udp,
+    /// TCP SYNs to port 443
+    Tcp,
+}
+
+/// Which part of a throughput phase its figures are computed from (`--steady-window`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum SteadyWindow {
+    /// Everything after the warm-up
+    #[default]
+    Trimmed,
+    /// The last stretch of the phase, never reaching back into the warm-up
+    Last(Duration),
+    /// The whole phase, warm-up included
+    Full,
+}
+
+impl std::fmt::Display for SteadyWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SteadyWindow::Trimmed => f.write_str("trimmed"),
+            SteadyWindow::Last(d) => write!(f, "last-{}", humantime::format_duration(*d)),
+            SteadyWindow::Full => f.write_str("full"),
+        }
+    }
+}
+
+impl std::str::FromStr for SteadyWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trimmed" => Ok(SteadyWindow::Trimmed),
+            "full" => Ok(SteadyWindow::Full),
+            _ => {
+                let d = s
+                    .strip_prefix("last-")
+                    .ok_or_else(|| format!("expected trimmed, full or last-DURATION, got '{s}'"))?;
+                match humantime::parse_duration(d) {
+                    Ok(d) if !d.is_zero() => Ok(SteadyWindow::Last(d)),
+                    Ok(_) => Err("the window must be longer than zero".to_string()),
+                    Err(e) => Err(format!("invalid duration '{d}': {e}")),
+                }
+            }
+        }
+    }
+}
+
+impl From<SteadyWindow> for String {
+    fn from(w: SteadyWindow) -> String {
+        w.to_string()
+    }
+}
+
+impl TryFrom<String> for SteadyWindow {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Which of the main phases a run measures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhaseSelection {
+    pub idle_latency: bool,
+    pub download: bool,
+    pub upload: bool,
+    pub packet_loss: bool,
+}
+
+impl Default for PhaseSelection {
+    fn default() -> Self {
+        Self {
+            idle_latency: true,
+            download: true,
+            upload: true,
+            packet_loss: true,
+        }
+    }
+}
+
+impl PhaseSelection {
+    pub fn latency_only() -> Self {
+        Self {
+            idle_latency: true,
+            download: false,
+            upload: false,
+            packet_loss: false,
+        }
+    }
+
+    pub fn packet_loss_only() -> Self {
+        Self {
+            idle_latency: false,
+            download: false,
+            upload: false,
+            packet_loss: true,
+        }
+    }
+}
+
+fn default_soak_direction() -> Phase {
+    Phase::Download
+}
+
+fn default_udp_packet_size() -> u16 {
+    20
+}
+
+fn default_udp_interval_ms() -> u64 {
+    80
+}
+
+fn default_udp_timeout_ms() -> u64 {
+    600
+}
+
+fn default_down_path() -> String {
+    "/__down".to_string()
+}
+
+fn default_up_path() -> String {
+    "/__up".to_string()
+}
+
+fn default_traceroute_paths() -> u8 {
+    1
+}
+
+fn default_udp_throughput_mbps() -> f64 {
+    20.0
+}
+
+/// Generate a random measurement ID for the speed test.
+pub fn gen_meas_id() -> String {
+    use rand::RngCore;
+    let mut b = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut b);
+    u64::from_le_bytes(b).to_string()
+}
+
+/// The configuration the command line builds when given no options: a full test against
+/// speed.cloudflare.com with the DNS, TLS and timing diagnostics, under a fresh
+/// measurement ID. Programs embedding the engine start from it and change what they need.
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "https://speed.cloudflare.com".to_string(),
+            fallback_urls: Vec::new(),
+            meas_id: gen_meas_id(),
+            comments: None,
+            download_bytes_per_req: 10_000_000,
+            upload_bytes_per_req: 5_000_000,
+            concurrency: 6,
+            idle_latency_duration: Duration::from_secs(2),
+            download_duration: Duration::from_secs(10),
+            upload_duration: Duration::from_secs(10),
+            probe_interval_ms: 250,
+            probe_timeout_ms: 800,
+            max_run_time: None,
+            max_total_bytes: None,
+            sweep_concurrency: Vec::new(),
+            compare_protocols: false,
+            export_har: None,
+            warmup: None,
+            steady_window: SteadyWindow::default(),
+            adaptive_cv_pct: None,
+            user_agent: format!("cloudflare-speed-cli/{}", env!("CARGO_PKG_VERSION")),
+            experimental: false,
+            interface: None,
+            source_ip: None,
+            proxy: None,
+            certificate_path: None,
+            client_cert_path: None,
+            client_key_path: None,
+            dns_server: None,
+            doh_url: None,
+            dot_server: None,
+            headers: Vec::new(),
+            backend: Backend::default(),
+            endpoint_auth_token: None,
+            down_path: default_down_path(),
+            up_path: default_up_path(),
+            resolve: Vec::new(),
+            colo: None,
+            dscp: None,
+            congestion: None,
+            send_buffer: None,
+            recv_buffer: None,
+            measure_dns: true,
+            check_dnssec: false,
+            measure_tls: true,
+            measure_timing: true,
+            compare_ip_versions: false,
+            traceroute: false,
+            traceroute_max_hops: 30,
+            traceroute_protocol: TracerouteProtocol::default(),
+            traceroute_paris: false,
+            traceroute_paths: default_traceroute_paths(),
+            measure_mtu: false,
+            ipv4_only: false,
+            ipv6_only: false,
+            udp_packets: 50,
+            udp_packet_size: default_udp_packet_size(),
+            udp_interval_ms: default_udp_interval_ms(),
+            udp_timeout_ms: default_udp_timeout_ms(),
+            udp_throughput: None,
+            udp_throughput_mbps: default_udp_throughput_mbps(),
+            anchors: Vec::new(),
+            icmp_ping: false,
+            captive_portal_url: Some("http://cp.cloudflare.com/generate_204".to_string()),
+            allow_captive_portal: false,
+            sync_start: None,
+            context: BTreeMap::new(),
+            tags: BTreeMap::new(),
+            context_hook: None,
+            modem: None,
+            modem_metrics: Vec::new(),
+            soak: None,
+            soak_direction: default_soak_direction(),
+            ramped: false,
+            phases: PhaseSelection::default(),
+            http_version: HttpVersion::default(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -60,6 +523,18 @@ pub enum Phase {
     Download,
     Upload,
     PacketLoss,
+    /// Paced UDP stream through the TURN relay (`--udp-throughput`)
+    UdpThroughput,
+    /// Extended saturation run (`--soak`)
+    Soak,
+    /// Staged payload-size measurements (`--ramped`)
+    Ramped,
+    /// Download repeated at several connection counts (`--sweep-concurrency`)
+    ConcurrencySweep,
+    /// Short download per HTTP version (`--compare-protocols`)
+    ProtocolComparison,
+    /// Short test over IPv4 and then IPv6 (`--compare-ip-versions`)
+    IpComparison,
     Summary,
 }
 
@@ -74,7 +549,10 @@ impl Phase {
     }
 }
 
+/// Progress reported by the engine while a run is going. Serialized (for
+/// `--events-ndjson`) as an object whose `type` field names the variant.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum TestEvent {
     PhaseStarted {
         phase: Phase,
@@ -90,6 +568,12 @@ pub enum TestEvent {
         bytes_total: u64,
         bps_instant: f64,
     },
+    /// Rate of one connection (worker) since the previous throughput tick
+    StreamTick {
+        phase: Phase,
+        worker_id: usize,
+        bps: f64,
+    },
     UdpLossProgress {
         sent: u64,
         received: u64,
@@ -109,8 +593,26 @@ pub enum TestEvent {
     DiagnosticTls {
         summary: TlsSummary,
     },
+    DiagnosticTiming {
+        timing: RequestTiming,
+    },
     DiagnosticIpComparison {
-        comparison: IpVersionComparison,
+        // Boxed, each family carries its idle and loaded latency summaries
+        comparison: Box<IpVersionComparison>,
+    },
+    /// Throughput of one family's download or upload in `--compare-ip-versions`, as
+    /// `ThroughputTick` reports the main phases; `family` is "IPv4" or "IPv6"
+    IpComparisonTick {
+        family: String,
+        phase: Phase,
+        bps_instant: f64,
+    },
+    /// A traceroute probe answered, as the reply arrives; the hop's `TracerouteHop`
+    /// follows once the trace is done and the hop named
+    TracerouteReply {
+        hop_number: u8,
+        ip_address: String,
+        rtt_ms: f64,
     },
     TracerouteHop {
         hop_number: u8,
@@ -119,10 +621,57 @@ pub enum TestEvent {
     TracerouteComplete {
         summary: TracerouteSummary,
     },
+    DiagnosticMtu {
+        summary: MtuSummary,
+    },
     ExternalIps {
         ipv4: Option<String>,
         ipv6: Option<String>,
     },
+    /// Roam, channel switch or (dis)connect observed on the wireless interface
+    Wireless {
+        event: WirelessEvent,
+    },
+    /// Negotiated protocol details of the connections used by a finished phase
+    Protocol {
+        protocol: PhaseProtocol,
+    },
+    /// Everything measured so far, emitted when `phase` has finished
+    PhaseResult {
+        phase: Phase,
+        snapshot: Box<RunResult>,
+    },
+}
+
+/// Serializes a phase summary as `null` when the phase was skipped (nothing measured),
+/// and reads `null` back as the "not measured" value.
+mod phase_summary_serde {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub trait PhaseSummary: Sized {
+        fn was_measured(&self) -> bool;
+        fn not_measured() -> Self;
+    }
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: PhaseSummary + Serialize,
+        S: Serializer,
+    {
+        if value.was_measured() {
+            value.serialize(serializer)
+        } else {
+            serializer.serialize_none()
+        }
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: PhaseSummary + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_else(T::not_measured))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -157,6 +706,15 @@ impl Default for LatencySummary {
     }
 }
 
+/// Loaded latency split by probe sequence position into the first, middle and final
+/// third of a throughput phase, showing how queues build up over the phase.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencySegments {
+    pub early: LatencySummary,
+    pub mid: LatencySummary,
+    pub late: LatencySummary,
+}
+
 impl LatencySummary {
     /// Create a LatencySummary representing a failed/empty measurement
     pub fn failed() -> Self {
@@ -165,9 +723,46 @@ impl LatencySummary {
             ..Default::default()
         }
     }
+
+    /// A phase that did not run: no probes, and so no loss either, unlike `failed`
+    pub fn skipped() -> Self {
+        Self::default()
+    }
+
+    /// Whether probes were sent; imported results carry a median without probe counts
+    pub fn was_measured(&self) -> bool {
+        self.sent > 0 || self.median_ms.is_some()
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl phase_summary_serde::PhaseSummary for LatencySummary {
+    fn was_measured(&self) -> bool {
+        LatencySummary::was_measured(self)
+    }
+
+    fn not_measured() -> Self {
+        Self::skipped()
+    }
+}
+
+impl ThroughputSummary {
+    /// Whether the phase ran and transferred anything
+    pub fn was_measured(&self) -> bool {
+        self.bytes > 0 || self.duration_ms > 0 || self.mbps > 0.0
+    }
+}
+
+impl phase_summary_serde::PhaseSummary for ThroughputSummary {
+    fn was_measured(&self) -> bool {
+        ThroughputSummary::was_measured(self)
+    }
+
+    fn not_measured() -> Self {
+        Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ThroughputSummary {
     pub bytes: u64,
     pub duration_ms: u64,
@@ -176,6 +771,65 @@ pub struct ThroughputSummary {
     pub median_mbps: Option<f64>,
     pub p25_mbps: Option<f64>,
     pub p75_mbps: Option<f64>,
+    /// Bytes the server acknowledged with a successful response (upload only)
+    #[serde(default)]
+    pub acknowledged_bytes: Option<u64>,
+    /// Share of client-produced bytes that were not acknowledged, in percent (upload only)
+    #[serde(default)]
+    pub ack_discrepancy_pct: Option<f64>,
+    /// How long the phase actually ran; shorter than configured when it ended early
+    #[serde(default)]
+    pub elapsed_ms: Option<u64>,
+    /// The phase ended early because throughput had stabilized (`--adaptive`)
+    #[serde(default)]
+    pub stabilized: bool,
+    /// Bytes moved by each connection over the whole phase, in worker order
+    #[serde(default)]
+    pub stream_bytes: Vec<u64>,
+    /// Part of the phase the figures above were computed from; `full` also when the
+    /// configured window held too few samples
+    #[serde(default)]
+    pub steady_window: Option<SteadyWindow>,
+    /// Kernel TCP statistics of the phase's connections at its end (Linux only)
+    #[serde(default)]
+    pub tcp_info: Option<TcpInfoSummary>,
+    /// TCP congestion control the phase's connections used, e.g. "bbr"; unknown over
+    /// HTTP/3 and through a proxy
+    #[serde(default)]
+    pub congestion: Option<String>,
+}
+
+/// `TCP_INFO` of the connections a throughput phase used. High retransmits point at a
+/// loss-limited path; an RTT well above the minimum with few retransmits at queueing
+/// or a latency-limited one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TcpInfoSummary {
+    pub connections: Vec<TcpConnectionInfo>,
+    /// Median smoothed RTT across the connections
+    pub rtt_ms: Option<f64>,
+    /// Lowest RTT any connection has seen
+    pub min_rtt_ms: Option<f64>,
+    /// Retransmitted share of the bytes this side sent, in percent; for downloads this
+    /// only covers requests and ACKs, the server's retransmits are not visible here
+    pub retransmit_pct: Option<f64>,
+}
+
+/// Kernel TCP statistics for one connection; optional fields need a newer kernel
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TcpConnectionInfo {
+    pub rtt_ms: f64,
+    pub rtt_var_ms: f64,
+    pub min_rtt_ms: Option<f64>,
+    /// Congestion window, in segments
+    pub cwnd: u32,
+    pub mss: u32,
+    /// Segments retransmitted over the connection's lifetime
+    pub retransmits: u32,
+    pub pacing_rate_bps: Option<u64>,
+    pub delivery_rate_bps: Option<u64>,
+    pub bytes_received: Option<u64>,
+    pub bytes_sent: Option<u64>,
+    pub bytes_retrans: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -201,31 +855,170 @@ pub struct ExperimentalUdpSummary {
     /// Quality label based on packet loss: Excellent/Good/Acceptable/Poor/Bad
     #[serde(default)]
     pub quality_label: String,
+    /// Public address and port the STUN server saw the probe come from
+    #[serde(default)]
+    pub mapped_address: Option<String>,
+    /// The same probe relayed through a TURN allocation, as the browser test measures
+    #[serde(default)]
+    pub relay: Option<TurnRelaySummary>,
+    /// Why the relayed probe failed, when `/__turn` offered credentials for one
+    #[serde(default)]
+    pub relay_error: Option<String>,
+    /// Settings the probe ran with; absent in imported results
+    #[serde(default)]
+    pub params: Option<UdpProbeParams>,
 }
 
+/// Paced UDP stream through the TURN relay (`--udp-throughput`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UdpThroughputSummary {
+    /// The `turn:` URL allocated on
+    pub server: String,
+    /// Rate the stream was paced at
+    pub target_mbps: f64,
+    pub duration_ms: u64,
+    /// UDP payload bytes per datagram
+    pub packet_size: u16,
+    pub sent: u64,
+    pub received: u64,
+    #[serde(with = "loss_percent_serde")]
+    pub loss: f64,
+    /// Payload that arrived, per second from the first send to the last arrival
+    pub goodput_mbps: f64,
+    /// Datagrams that arrived after one with a higher sequence number
+    pub out_of_order: u64,
+    /// Why no stream could be sent, e.g. no allocation
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl UdpThroughputSummary {
+    /// Nothing sent toward `server`, for the reason in `error`
+    pub fn failed(server: &str, target_mbps: f64, error: Option<String>) -> Self {
+        Self {
+            server: server.to_string(),
+            target_mbps,
+            duration_ms: 0,
+            packet_size: 0,
+            sent: 0,
+            received: 0,
+            loss: 0.0,
+            goodput_mbps: 0.0,
+            out_of_order: 0,
+            error,
+        }
+    }
+}
+
+/// Packet count, size and timing of the packet loss probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UdpProbeParams {
+    pub packets: u64,
+    /// UDP payload bytes per probe, after rounding to whole STUN attributes
+    pub packet_size: u16,
+    pub interval_ms: u64,
+    pub timeout_ms: u64,
+}
+
+/// Round trips from one TURN allocation to another and back through the relay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TurnRelaySummary {
+    /// The `turn:` URL allocated on
+    pub server: String,
+    /// Relayed transport addresses of the sending and the echoing allocation
+    pub relayed_addresses: Vec<String>,
+    pub latency: LatencySummary,
+    /// Echoes that arrived after their probe had been given up on
+    #[serde(default)]
+    pub out_of_order: u64,
+}
+
+/// Layout version of serialized `RunResult`s. Bump it together with a migration in
+/// `storage::migrate_run` whenever stored results need upgrading to parse.
+pub const SCHEMA_VERSION: u32 = 2;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RunResult {
+    /// `SCHEMA_VERSION` the document was written with; 0 for results saved before
+    /// versioning
+    #[serde(default)]
+    pub schema_version: u32,
     #[serde(default)]
     pub version: Option<String>,
     #[serde(default)]
     pub timestamp_utc: String,
     pub base_url: String,
+    /// Kind of server at `base_url`
+    #[serde(default)]
+    pub backend: Backend,
     pub meas_id: String,
     #[serde(default)]
     pub comments: Option<String>,
+    /// True when the run was cut short (e.g. by `--max-run-time`) and holds partial results
+    #[serde(default)]
+    pub partial: bool,
+    /// Agreed start time (RFC 3339) when the run was part of a synchronized multi-probe test
+    #[serde(default)]
+    pub sync_start: Option<String>,
+    /// External key-value context (from --context and --context-hook)
+    #[serde(default)]
+    pub context: BTreeMap<String, String>,
+    /// User labels such as location or VPN on/off (from --tag)
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
     pub meta: Option<serde_json::Value>,
     #[serde(default)]
     pub server: Option<String>,
+    /// Proxy the traffic went through (credentials removed), if any
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Address `--resolve` pinned the test endpoint to, if any
+    #[serde(default)]
+    pub pinned_ip: Option<String>,
+    /// Base URLs tried before `base_url`, which did not answer
+    #[serde(default)]
+    pub unreachable_endpoints: Vec<String>,
+    /// Data center `--colo` asked for; `colo` is the one that served the run
+    #[serde(default)]
+    pub requested_colo: Option<String>,
+    /// DSCP marking of the latency and UDP probes, e.g. "EF (46)"
+    #[serde(default)]
+    pub dscp: Option<String>,
+    /// True when a captive portal or intercepting proxy tampered with the connectivity
+    /// check and the run went ahead anyway (`--allow-captive-portal`)
+    #[serde(default)]
+    pub captive_portal: bool,
+    /// The phase summaries are `null` for phases the run skipped
+    #[serde(with = "phase_summary_serde")]
     pub idle_latency: LatencySummary,
+    /// Time to first byte of a few one-byte downloads before the idle latency probes,
+    /// which unlike those does not include reading the body
+    #[serde(default)]
+    pub ttfb: Option<LatencySummary>,
+    #[serde(with = "phase_summary_serde")]
     pub download: ThroughputSummary,
+    #[serde(with = "phase_summary_serde")]
     pub upload: ThroughputSummary,
+    #[serde(with = "phase_summary_serde")]
     pub loaded_latency_download: LatencySummary,
+    #[serde(with = "phase_summary_serde")]
     pub loaded_latency_upload: LatencySummary,
+    #[serde(default)]
+    pub loaded_latency_download_segments: Option<LatencySegments>,
+    #[serde(default)]
+    pub loaded_latency_upload_segments: Option<LatencySegments>,
     pub turn: Option<TurnInfo>,
     pub experimental_udp: Option<ExperimentalUdpSummary>,
     /// Error message when TURN fetch or UDP probe failed (for UI display)
     #[serde(skip, default)]
     pub udp_error: Option<String>,
+    /// Why the UDP packet loss probe did not run although selected (e.g. a SOCKS proxy,
+    /// which only carries the TCP traffic)
+    #[serde(default)]
+    pub udp_skipped: Option<String>,
+    /// Paced UDP stream through the TURN relay (`--udp-throughput`)
+    #[serde(default)]
+    pub udp_throughput: Option<UdpThroughputSummary>,
     // Network information
     #[serde(default)]
     pub ip: Option<String>,
@@ -241,8 +1034,17 @@ pub struct RunResult {
     pub network_name: Option<String>,
     #[serde(default)]
     pub is_wireless: Option<bool>,
+    /// The test ran over a VPN or tunnel interface
+    #[serde(default)]
+    pub vpn_detected: bool,
+    /// Signal, channel and PHY rate of a wireless interface
+    #[serde(default)]
+    pub wireless_link: Option<WirelessLink>,
     #[serde(default)]
     pub interface_mac: Option<String>,
+    /// Negotiated link speed of a wired interface in Mbit/s
+    #[serde(default)]
+    pub link_speed_mbps: Option<u64>,
     #[serde(default)]
     pub local_ipv4: Option<String>,
     #[serde(default)]
@@ -251,15 +1053,74 @@ pub struct RunResult {
     pub external_ipv4: Option<String>,
     #[serde(default)]
     pub external_ipv6: Option<String>,
+    /// Address translation between this host and the internet, from the local, STUN
+    /// mapped and `cf-meta-ip` addresses
+    #[serde(default)]
+    pub nat_type: Option<NatType>,
     // Diagnostic results
     #[serde(default)]
     pub dns: Option<DnsSummary>,
     #[serde(default)]
     pub tls: Option<TlsSummary>,
     #[serde(default)]
+    pub timing_breakdown: Option<TimingBreakdown>,
+    #[serde(default)]
     pub ip_comparison: Option<IpVersionComparison>,
     #[serde(default)]
     pub traceroute: Option<TracerouteSummary>,
+    /// Path MTU and MSS toward the endpoint (`--mtu`)
+    #[serde(default)]
+    pub mtu: Option<MtuSummary>,
+    /// Idle latency to each configured reference anchor
+    #[serde(default)]
+    pub anchors: Vec<AnchorLatency>,
+    /// ICMP echo latency to the endpoint during the idle phase (`--icmp-ping`)
+    #[serde(default)]
+    pub icmp_latency: Option<IcmpLatency>,
+    /// Structured results of the comparison modes (IP version, interface, ...)
+    #[serde(default)]
+    pub comparisons: Vec<Comparison>,
+    /// Modem/router line statistics captured after the run
+    #[serde(default)]
+    pub modem: Option<ModemStats>,
+    /// Stability report of the soak phase
+    #[serde(default)]
+    pub soak: Option<SoakReport>,
+    /// Wi-Fi roams and channel switches seen while the test was running
+    #[serde(default)]
+    pub wireless_events: Vec<WirelessEvent>,
+    /// HTTP/TLS details per measurement phase
+    #[serde(default)]
+    pub protocols: Vec<PhaseProtocol>,
+    /// Per-payload-size results of the staged measurements (`--ramped`)
+    #[serde(default)]
+    pub measurements: Vec<PayloadMeasurement>,
+    /// Download throughput per connection count (`--sweep-concurrency`)
+    #[serde(default)]
+    pub concurrency_sweep: Option<ConcurrencySweep>,
+    /// Short download per HTTP version (`--compare-protocols`)
+    #[serde(default)]
+    pub protocol_comparison: Vec<ProtocolResult>,
+    /// Latency increase under load, graded A+ to F
+    #[serde(default)]
+    pub bufferbloat: Option<Bufferbloat>,
+    /// How well the connection suits streaming, gaming and video calls
+    #[serde(default)]
+    pub aim: Option<AimScores>,
+    /// Host package energy per throughput phase (Linux with RAPL access only)
+    #[serde(default)]
+    pub energy: Vec<PhaseEnergy>,
+}
+
+impl RunResult {
+    /// Tags as `key=value` pairs separated by commas
+    pub fn tags_text(&self) -> String {
+        self.tags
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
 }
 
 // ============================================================================
@@ -277,6 +1138,34 @@ pub struct DnsSummary {
     /// System DNS servers used for resolution
     #[serde(default)]
     pub dns_servers: Vec<String>,
+    /// Resolver the name was looked up with (e.g. "9.9.9.9:53"); `None` for the system
+    /// resolver
+    #[serde(default)]
+    pub resolver: Option<String>,
+    /// The same lookup through the `--doh`/`--dot` resolver, timed alongside the plain
+    /// DNS one above
+    #[serde(default)]
+    pub encrypted: Option<EncryptedDnsSummary>,
+    /// Whether the resolver validates DNSSEC (`--dnssec`); `None` when not checked or
+    /// the check was inconclusive
+    #[serde(default)]
+    pub dnssec_validating: Option<bool>,
+    /// What the DNSSEC check saw, e.g. "dnssec-failed.org resolved despite its broken
+    /// signatures"
+    #[serde(default)]
+    pub dnssec_detail: Option<String>,
+}
+
+/// Resolution time through an encrypted DNS resolver
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedDnsSummary {
+    /// "DoH" or "DoT"
+    pub protocol: String,
+    /// DoH URL or "tls://HOST:PORT"
+    pub resolver: String,
+    /// Including the connection and TLS handshake to the resolver
+    pub resolution_time_ms: f64,
+    pub resolved_ips: Vec<String>,
 }
 
 /// Summary of TLS handshake time measurement
@@ -287,6 +1176,43 @@ pub struct TlsSummary {
     pub cipher_suite: Option<String>,
 }
 
+/// Timing of one request over a fresh connection per phase
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimingBreakdown {
+    pub requests: Vec<RequestTiming>,
+}
+
+/// Where the time of a single request went, in milliseconds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestTiming {
+    /// Phase the request was made at the start of
+    pub phase: Phase,
+    /// `None` when the server is addressed by IP
+    pub dns_ms: Option<f64>,
+    pub connect_ms: f64,
+    /// `None` over plain HTTP
+    pub tls_ms: Option<f64>,
+    /// From sending the request to the first response byte
+    pub ttfb_ms: f64,
+    pub total_ms: f64,
+}
+
+impl RequestTiming {
+    /// e.g. "DNS 12.0 / connect 8.1 / TLS 16.4 / TTFB 9.2 ms"
+    pub fn breakdown(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(dns) = self.dns_ms {
+            parts.push(format!("DNS {:.1}", dns));
+        }
+        parts.push(format!("connect {:.1}", self.connect_ms));
+        if let Some(tls) = self.tls_ms {
+            parts.push(format!("TLS {:.1}", tls));
+        }
+        parts.push(format!("TTFB {:.1}", self.ttfb_ms));
+        format!("{} ms", parts.join(" / "))
+    }
+}
+
 /// Comparison of IPv4 vs IPv6 performance
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IpVersionComparison {
@@ -303,6 +1229,529 @@ pub struct IpVersionResult {
     pub latency_ms: f64,
     pub available: bool,
     pub error: Option<String>,
+    /// Idle latency probes before the download
+    #[serde(default)]
+    pub idle_latency: Option<LatencySummary>,
+    /// Latency probes while downloading
+    #[serde(default)]
+    pub loaded_latency_download: Option<LatencySummary>,
+    /// Latency probes while uploading
+    #[serde(default)]
+    pub loaded_latency_upload: Option<LatencySummary>,
+}
+
+impl IpVersionResult {
+    fn loaded(&self) -> impl Iterator<Item = &LatencySummary> {
+        [&self.loaded_latency_download, &self.loaded_latency_upload]
+            .into_iter()
+            .flatten()
+    }
+
+    /// Median latency of the worse of the two loaded phases
+    pub fn loaded_latency_ms(&self) -> Option<f64> {
+        self.loaded().filter_map(|l| l.median_ms).reduce(f64::max)
+    }
+
+    /// Jitter of the worse of the two loaded phases
+    pub fn loaded_jitter_ms(&self) -> Option<f64> {
+        self.loaded().filter_map(|l| l.jitter_ms).reduce(f64::max)
+    }
+
+    /// Share of all the family's latency probes, idle and loaded, that went unanswered
+    pub fn loss(&self) -> Option<f64> {
+        let (sent, received) = self
+            .loaded()
+            .chain(self.idle_latency.as_ref())
+            .fold((0, 0), |(s, r), l| (s + l.sent, r + l.received));
+        (sent > 0).then(|| 1.0 - received as f64 / sent as f64)
+    }
+}
+
+/// What a comparison sets against each other: the comparison modes that produce one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ComparisonKind {
+    /// `--compare-ip-versions`
+    IpVersion,
+    /// `--compare-protocols`
+    Protocol,
+}
+
+/// One side of a comparison
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComparisonSide {
+    /// Human readable label (e.g. "IPv4", "HTTP/2")
+    pub label: String,
+    pub download_mbps: Option<f64>,
+    pub upload_mbps: Option<f64>,
+    pub latency_ms: Option<f64>,
+    /// Median latency under load
+    #[serde(default)]
+    pub loaded_latency_ms: Option<f64>,
+    /// Jitter under load
+    #[serde(default)]
+    pub jitter_ms: Option<f64>,
+    /// Latency probe loss, as a fraction
+    #[serde(default)]
+    pub loss: Option<f64>,
+    pub available: bool,
+    pub error: Option<String>,
+}
+
+/// Relative change of the candidate against the baseline, in percent
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ComparisonDeltas {
+    pub download_pct: Option<f64>,
+    pub upload_pct: Option<f64>,
+    pub latency_pct: Option<f64>,
+    #[serde(default)]
+    pub loaded_latency_pct: Option<f64>,
+    #[serde(default)]
+    pub jitter_pct: Option<f64>,
+    /// Difference in probe loss, in percentage points
+    #[serde(default)]
+    pub loss_pp: Option<f64>,
+}
+
+/// Side-by-side comparison of two measurement variants
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Comparison {
+    pub kind: ComparisonKind,
+    pub baseline: ComparisonSide,
+    pub candidate: ComparisonSide,
+    pub deltas: ComparisonDeltas,
+    /// Label of the better side; None when tied or not comparable
+    pub winner: Option<String>,
+}
+
+/// Idle latency to a single reference anchor (TCP connect round-trip time)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnchorLatency {
+    /// Anchor as given on the command line (e.g. "1.1.1.1", "gateway")
+    pub target: String,
+    /// Resolved address that was probed
+    pub address: Option<String>,
+    pub latency: LatencySummary,
+    pub error: Option<String>,
+}
+
+/// ICMP echo latency to the test endpoint, next to the HTTP idle latency
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IcmpLatency {
+    /// Address pinged
+    pub target: String,
+    /// "raw" or "unprivileged" (ping socket)
+    pub socket: Option<String>,
+    pub latency: LatencySummary,
+    pub error: Option<String>,
+}
+
+/// Throughput and loaded latency aggregated over one minute of a soak run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SoakMinute {
+    /// 1-based minute index
+    pub minute: u32,
+    /// Bucket length (the final bucket may be shorter than a minute)
+    pub duration_ms: u64,
+    pub mean_mbps: f64,
+    /// Lowest and highest one-second throughput within the minute
+    pub min_mbps: f64,
+    pub max_mbps: f64,
+    pub latency: LatencySummary,
+    pub errors: u64,
+}
+
+/// Stability report of a soak run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SoakReport {
+    pub direction: Phase,
+    pub duration_ms: u64,
+    pub bytes: u64,
+    pub mean_mbps: f64,
+    pub min_minute_mbps: Option<f64>,
+    pub max_minute_mbps: Option<f64>,
+    /// Coefficient of variation of the per-minute means, in percent
+    pub cv_pct: Option<f64>,
+    /// Seconds in which no bytes were transferred at all
+    pub stall_seconds: u64,
+    /// Median and worst of the per-minute loaded latency medians
+    pub latency_median_ms: Option<f64>,
+    pub latency_worst_minute_ms: Option<f64>,
+    pub errors: u64,
+    pub minutes: Vec<SoakMinute>,
+}
+
+/// Line-level statistics scraped from the modem/router status page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModemStats {
+    /// Status page URL that was scraped
+    pub source: String,
+    pub metrics: BTreeMap<String, f64>,
+    /// Fetch failures and metrics that could not be extracted
+    #[serde(default)]
+    pub errors: Vec<String>,
+}
+
+/// Negotiated protocol details of the measurement connections during one phase
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseProtocol {
+    pub phase: Phase,
+    /// Responses per HTTP version, e.g. {"HTTP/2.0": 120}
+    pub http_versions: BTreeMap<String, u64>,
+    /// TLS version and cipher suite the measurement connections last negotiated, from
+    /// the sessions the server gave them; the cipher suite is known for TLS 1.3 only
+    pub tls_version: Option<String>,
+    pub cipher_suite: Option<String>,
+    pub via_proxy: bool,
+}
+
+impl PhaseProtocol {
+    /// The HTTP version that served most requests
+    pub fn http_version(&self) -> Option<&str> {
+        self.http_versions
+            .iter()
+            .max_by_key(|(_, n)| **n)
+            .map(|(v, _)| v.as_str())
+    }
+
+    /// True when requests fell back to HTTP/1.x (e.g. through a proxy)
+    pub fn is_http1(&self) -> bool {
+        self.http_version().is_some_and(|v| v.starts_with("HTTP/1"))
+    }
+
+    /// e.g. "HTTP/2.0, TLSv1_3 TLS13_AES_256_GCM_SHA384 via proxy"
+    pub fn describe(&self) -> String {
+        let mut out = self.http_version().unwrap_or("-").to_string();
+        if let Some(tls) = &self.tls_version {
+            out.push_str(&format!(", {}", tls));
+            if let Some(cipher) = &self.cipher_suite {
+                out.push_str(&format!(" {}", cipher));
+            }
+        }
+        if self.via_proxy {
+            out.push_str(" via proxy");
+        }
+        out
+    }
+}
+
+/// Sequential requests of one payload size, as run by the speed.cloudflare.com website
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PayloadMeasurement {
+    /// Download or Upload
+    pub direction: Phase,
+    pub bytes: u64,
+    /// Requests that completed successfully
+    pub count: u32,
+    pub errors: u32,
+    pub median_mbps: Option<f64>,
+    /// The website reports the 90th percentile of the larger payloads
+    pub p90_mbps: Option<f64>,
+    /// Median time from sending the request to the response headers (downloads only;
+    /// the server answers an upload after reading the whole body)
+    pub median_latency_ms: Option<f64>,
+}
+
+/// Download throughput at one connection count
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcurrencyLevel {
+    pub connections: usize,
+    pub mbps: f64,
+    pub bytes: u64,
+    pub duration_ms: u64,
+    pub loaded_latency_median_ms: Option<f64>,
+}
+
+/// Download over one HTTP version, as measured by `--compare-protocols`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolResult {
+    pub protocol: HttpVersion,
+    /// Version the responses actually used (a proxy may downgrade the request)
+    pub negotiated: Option<String>,
+    pub available: bool,
+    pub mbps: Option<f64>,
+    /// Median time from sending a request to its response headers
+    pub ttfb_ms: Option<f64>,
+    pub requests: u64,
+    pub error: Option<String>,
+}
+
+/// Whether more connections raise throughput
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SweepBottleneck {
+    /// Throughput grows with the connection count: something limits each flow (TCP
+    /// window, per-flow shaping, a long path)
+    PerFlow,
+    /// A few connections already reach the maximum: the link itself is the limit
+    Aggregate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcurrencySweep {
+    pub levels: Vec<ConcurrencyLevel>,
+    pub bottleneck: Option<SweepBottleneck>,
+}
+
+/// Bufferbloat grade on the Waveform scale (latency increase under load)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum BufferbloatGrade {
+    #[serde(rename = "A+")]
+    APlus,
+    A,
+    B,
+    C,
+    D,
+    F,
+}
+
+impl BufferbloatGrade {
+    pub fn label(&self) -> &'static str {
+        match self {
+            BufferbloatGrade::APlus => "A+",
+            BufferbloatGrade::A => "A",
+            BufferbloatGrade::B => "B",
+            BufferbloatGrade::C => "C",
+            BufferbloatGrade::D => "D",
+            BufferbloatGrade::F => "F",
+        }
+    }
+}
+
+/// Increase of loaded over idle latency (p75) and the grade of the worse direction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bufferbloat {
+    pub grade: BufferbloatGrade,
+    pub download_increase_ms: Option<f64>,
+    pub upload_increase_ms: Option<f64>,
+}
+
+/// Spread of one metric over repeated runs
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Aggregate {
+    pub mean: f64,
+    pub median: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Summary of back-to-back runs (`--count`); metrics a run did not measure are skipped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepeatSummary {
+    /// Runs that completed and are aggregated
+    pub runs: usize,
+    /// Runs that failed and are left out
+    #[serde(default)]
+    pub failed: usize,
+    pub download_mbps: Option<Aggregate>,
+    pub upload_mbps: Option<Aggregate>,
+    pub idle_latency_ms: Option<Aggregate>,
+}
+
+/// AIM rating of one experience, worst to best
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AimRating {
+    Bad,
+    Poor,
+    Average,
+    Good,
+    Great,
+}
+
+impl AimRating {
+    pub fn label(&self) -> &'static str {
+        match self {
+            AimRating::Bad => "Bad",
+            AimRating::Poor => "Poor",
+            AimRating::Average => "Average",
+            AimRating::Good => "Good",
+            AimRating::Great => "Great",
+        }
+    }
+}
+
+/// Address translation between the host and the internet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NatType {
+    /// The host has a public address
+    None,
+    /// A single NAT, usually the home router
+    Nat,
+    /// Two private hops in front of the internet, e.g. a router behind the ISP's modem
+    DoubleNat,
+    /// Carrier-grade NAT: the ISP shares public addresses (RFC 6598 space or address pooling)
+    Cgnat,
+}
+
+impl NatType {
+    pub fn label(&self) -> &'static str {
+        match self {
+            NatType::None => "None (public address)",
+            NatType::Nat => "NAT",
+            NatType::DoubleNat => "Double NAT",
+            NatType::Cgnat => "CGNAT",
+        }
+    }
+}
+
+/// "Your connection is good for..." scores as shown by speed.cloudflare.com
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AimScores {
+    pub streaming: AimRating,
+    pub gaming: AimRating,
+    /// Real-time communication (video conferencing)
+    pub rtc: AimRating,
+}
+
+/// Energy the host spent during one throughput phase
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PhaseEnergy {
+    pub phase: Phase,
+    pub joules: f64,
+    /// Bytes transferred during the phase
+    pub bytes: u64,
+    pub joules_per_gb: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WirelessEventKind {
+    /// Associated with a different access point than before
+    Roam,
+    /// Associated after having been disconnected (or for the first time)
+    Connect,
+    Disconnect,
+    /// The access point moved the link to another channel
+    ChannelSwitch,
+}
+
+/// A wireless link event, timestamped relative to the start of the run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WirelessEvent {
+    pub t_ms: u64,
+    pub interface: String,
+    pub kind: WirelessEventKind,
+    /// BSSID of the access point after a roam/connect
+    pub bssid: Option<String>,
+    /// Operating frequency after a channel switch
+    pub freq_mhz: Option<u32>,
+}
+
+impl WirelessEvent {
+    /// Human-readable one-liner, e.g. "Wi-Fi roam to 11:22:33:44:55:66 on wlan0 at +12.3s".
+    pub fn describe(&self) -> String {
+        let what = match self.kind {
+            WirelessEventKind::Roam => "roam",
+            WirelessEventKind::Connect => "connect",
+            WirelessEventKind::Disconnect => "disconnect",
+            WirelessEventKind::ChannelSwitch => "channel switch",
+        };
+        let target = match (&self.bssid, self.freq_mhz) {
+            (Some(bssid), _) => format!(" to {}", bssid),
+            (None, Some(freq)) => format!(" to {} MHz", freq),
+            (None, None) => String::new(),
+        };
+        format!(
+            "Wi-Fi {}{} on {} at +{:.1}s",
+            what,
+            target,
+            self.interface,
+            self.t_ms as f64 / 1000.0
+        )
+    }
+}
+
+/// Radio link of a wireless interface when the run finished
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WirelessLink {
+    /// Access point the station is associated with
+    pub bssid: Option<String>,
+    /// Received signal strength (RSSI)
+    pub signal_dbm: Option<i32>,
+    pub freq_mhz: Option<u32>,
+    pub channel: Option<u32>,
+    /// "2.4 GHz", "5 GHz" or "6 GHz"
+    pub band: Option<String>,
+    /// PHY rate the station transmits at
+    pub tx_bitrate_mbps: Option<f64>,
+    /// PHY rate the station receives at
+    pub rx_bitrate_mbps: Option<f64>,
+}
+
+impl WirelessLink {
+    /// Human-readable one-liner, e.g. "-52 dBm, channel 36 (5 GHz), 866.7/780.0 Mbit/s rx/tx".
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(signal) = self.signal_dbm {
+            parts.push(format!("{} dBm", signal));
+        }
+        match (self.channel, self.band.as_deref()) {
+            (Some(channel), Some(band)) => parts.push(format!("channel {} ({})", channel, band)),
+            (Some(channel), None) => parts.push(format!("channel {}", channel)),
+            (None, Some(band)) => parts.push(band.to_string()),
+            (None, None) => {}
+        }
+        match (self.rx_bitrate_mbps, self.tx_bitrate_mbps) {
+            (Some(rx), Some(tx)) => parts.push(format!("{:.1}/{:.1} Mbit/s rx/tx", rx, tx)),
+            (Some(rx), None) => parts.push(format!("{:.1} Mbit/s rx", rx)),
+            (None, Some(tx)) => parts.push(format!("{:.1} Mbit/s tx", tx)),
+            (None, None) => {}
+        }
+        if parts.is_empty() {
+            "-".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+/// Path MTU toward the endpoint and the MSS its server allows
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MtuSummary {
+    /// Address the probes went to
+    pub target: String,
+    /// "icmp" (echo requests to the endpoint) or "udp" (STUN requests to Cloudflare)
+    pub method: String,
+    /// Largest packet that got through with don't-fragment set, in bytes
+    pub path_mtu: u16,
+    /// MTU of the local route, the search ceiling
+    pub local_mtu: Option<u16>,
+    /// Packets sized for the local route are too big for the path, so they are
+    /// fragmented or depend on path MTU discovery working
+    pub fragmentation_needed: bool,
+    /// Segment size the server's SYN-ACK allowed, in bytes
+    pub mss: Option<u16>,
+    /// Segment size the path MTU allows
+    pub expected_mss: u16,
+    /// The MSS is below what the path MTU allows, so something clamps it
+    pub mss_clamped: Option<bool>,
+    /// Probe packets sent
+    pub probes: u32,
+}
+
+impl MtuSummary {
+    /// e.g. "1492 bytes via ICMP (local 1500, fragmentation needed), MSS 1452"
+    pub fn describe(&self) -> String {
+        let mut notes = Vec::new();
+        if let Some(local) = self.local_mtu {
+            notes.push(format!("local {}", local));
+        }
+        if self.fragmentation_needed {
+            notes.push("fragmentation needed".to_string());
+        }
+        let mut line = format!("{} bytes via {}", self.path_mtu, self.method.to_uppercase());
+        if !notes.is_empty() {
+            line.push_str(&format!(" ({})", notes.join(", ")));
+        }
+        if let Some(mss) = self.mss {
+            line.push_str(&format!(", MSS {}", mss));
+            if self.mss_clamped == Some(true) {
+                line.push_str(&format!(" (clamped, path allows {})", self.expected_mss));
+            }
+        }
+        line
+    }
 }
 
 /// Summary of traceroute results
@@ -311,6 +1760,71 @@ pub struct TracerouteSummary {
     pub destination: String,
     pub hops: Vec<TracerouteHop>,
     pub completed: bool,
+    /// How the path was probed: "icmp", "udp", "tcp" or "system"
+    #[serde(default)]
+    pub method: Option<String>,
+    /// Flow held constant across the probes in Paris mode: the ICMP identifier or UDP
+    /// source port
+    #[serde(default)]
+    pub flow: Option<u16>,
+    /// The further flows `--traceroute-paths` traced, each a Paris traceroute of its own
+    #[serde(default)]
+    pub paths: Vec<TraceroutePath>,
+}
+
+/// One more flow of a multi-path (`--traceroute-paths`) traceroute
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceroutePath {
+    /// ICMP identifier or UDP source port of the flow
+    pub flow: u16,
+    pub hops: Vec<TracerouteHop>,
+    pub completed: bool,
+}
+
+impl TracerouteSummary {
+    /// Autonomous systems the path crosses, in order: "AS7922 COMCAST-7922 → AS13335
+    /// CLOUDFLARENET". Shows where traffic leaves the ISP; `None` before any hop's ASN is
+    /// known.
+    pub fn as_path(&self) -> Option<String> {
+        let mut path: Vec<(u32, String)> = Vec::new();
+        for hop in &self.hops {
+            let Some(asn) = hop.asn else {
+                continue;
+            };
+            if path.last().is_some_and(|(last, _)| *last == asn) {
+                continue;
+            }
+            path.push((asn, hop.as_label()?));
+        }
+        if path.is_empty() {
+            return None;
+        }
+        let labels: Vec<String> = path.into_iter().map(|(_, label)| label).collect();
+        Some(labels.join(" → "))
+    }
+
+    /// Hop numbers at which the traced flows were answered from different addresses,
+    /// i.e. where ECMP load balancing splits the path
+    pub fn ecmp_hops(&self) -> Vec<u8> {
+        let mut addresses: BTreeMap<u8, Vec<&str>> = BTreeMap::new();
+        let hops = self
+            .hops
+            .iter()
+            .chain(self.paths.iter().flat_map(|path| &path.hops));
+        for hop in hops {
+            if let Some(ref ip) = hop.ip_address {
+                let seen = addresses.entry(hop.hop_number).or_default();
+                if !seen.contains(&ip.as_str()) {
+                    seen.push(ip);
+                }
+            }
+        }
+        addresses
+            .into_iter()
+            .filter(|(_, seen)| seen.len() > 1)
+            .map(|(hop, _)| hop)
+            .collect()
+    }
 }
 
 /// A single hop in a traceroute
@@ -321,4 +1835,38 @@ pub struct TracerouteHop {
     pub hostname: Option<String>,
     pub rtt_ms: Vec<f64>,
     pub timeout: bool,
+    /// Autonomous system announcing the address, from Team Cymru's IP-to-ASN service
+    #[serde(default)]
+    pub asn: Option<u32>,
+    /// Name of that autonomous system, e.g. "CLOUDFLARENET, US"
+    #[serde(default)]
+    pub as_name: Option<String>,
+    /// Country the address is registered in (ISO 3166 code)
+    #[serde(default)]
+    pub country: Option<String>,
+}
+
+impl TracerouteHop {
+    /// "AS13335 CLOUDFLARENET": the AS name without the country Team Cymru appends
+    pub fn as_label(&self) -> Option<String> {
+        let asn = self.asn?;
+        Some(
+            match self.as_name.as_deref().and_then(|n| n.split(',').next()) {
+                Some(name) => format!("AS{asn} {}", name.trim()),
+                None => format!("AS{asn}"),
+            },
+        )
+    }
+
+    /// Address, name and network of the hop for one line of output:
+    /// "104.16.1.2 (host.example) AS13335 CLOUDFLARENET US", or "*" for a timeout
+    pub fn describe(&self) -> String {
+        let mut parts = vec![self.ip_address.clone().unwrap_or_else(|| "*".to_string())];
+        if let Some(ref hostname) = self.hostname {
+            parts.push(format!("({hostname})"));
+        }
+        parts.extend(self.as_label());
+        parts.extend(self.country.clone());
+        parts.join(" ")
+    }
 }
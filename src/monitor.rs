@@ -0,0 +1,140 @@
+//! Continuous monitoring (`--interval` / `--schedule`)
+//!
+//! Runs the engine over and over, either every interval or at the times of a cron
+//! schedule, publishing every result to the configured sinks
+//! (history by default). With `--full-every N` only every Nth run saturates the link;
+//! the runs in between measure idle latency only, which keeps the data usage of
+//! long-running probes low. A failed run is reported and the loop carries on, so a
+//! network outage shows up as a gap instead of ending the monitor. With `--alert-*`
+//! thresholds, a result that starts or stops missing them is logged, raised as a
+//! desktop notification with `--notify` and posted to the configured chat services.
+//! Under a `Type=notify` systemd unit the loop reports readiness once it starts.
+
+use crate::cli::{build_config, fmt_opt, Cli};
+use crate::engine::builder::now_rfc3339;
+use crate::model::RunResult;
+use crate::schedule::Schedule;
+use crate::thresholds::{Thresholds, Watch};
+use anyhow::Result;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Entry point for `--interval` and headless `--schedule`.
+pub async fn run(args: Cli) -> Result<()> {
+    let interval = args.interval.map(Duration::from);
+    let full_every = u64::from(args.full_every.max(1));
+    let sinks = crate::sinks::configured(&args, args.json && !args.silent, args.auto_save);
+    let mut alerts = Watch::new(Thresholds::alerts_from_args(&args));
+    let chats = crate::chat::targets(&args);
+
+    let cadence = match (interval, &args.schedule) {
+        (Some(interval), _) => format!("a test every {}", humantime::format_duration(interval)),
+        (None, Some(schedule)) => format!("tests at \"{}\"", schedule.expr()),
+        (None, None) => anyhow::bail!("monitor mode needs --interval or --schedule"),
+    };
+    if !args.silent {
+        eprintln!(
+            "Monitoring: {}{} (Ctrl-C to stop)",
+            cadence,
+            if full_every > 1 {
+                format!(", full test every {} runs", full_every)
+            } else {
+                String::new()
+            }
+        );
+    }
+    crate::systemd::ready();
+
+    for n in 0u64.. {
+        // A schedule waits for its first slot; an interval starts right away
+        if let Some(schedule) = &args.schedule {
+            if !wait_for_next(schedule, &args).await? {
+                return Ok(());
+            }
+        }
+
+        let started = Instant::now();
+        let full = n % full_every == 0;
+        let mut cfg = build_config(&args);
+        if !full {
+            cfg.phases = crate::model::PhaseSelection::latency_only();
+        }
+
+        let outcome = tokio::select! {
+            r = crate::cli::run_quiet(&args, cfg) => r,
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        };
+        match outcome {
+            Ok(result) => {
+                let line = summary_line(&result, full);
+                crate::systemd::status(&line);
+                if !args.json && !args.silent {
+                    println!("{}", line);
+                }
+                if let Err(e) = crate::cli::publish(&sinks, &result, args.silent).await {
+                    tracing::error!("{:#}", e);
+                }
+                if let Some(transition) = alerts.update(&result, full) {
+                    tracing::warn!("{} {}", result.timestamp_utc, transition);
+                    if args.notify {
+                        crate::notify::alert(&transition, &result);
+                    }
+                    crate::chat::alert(&chats, &transition, &result).await;
+                }
+            }
+            // Keep monitoring through outages; the next run may succeed
+            Err(e) => {
+                crate::systemd::status(&format!("{} run failed", now_rfc3339()));
+                tracing::error!("{} run failed: {:#}", now_rfc3339(), e);
+            }
+        }
+
+        if let Some(interval) = interval {
+            tokio::select! {
+                _ = tokio::time::sleep_until(started + interval) => {}
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sleep until the schedule's next slot. Returns false when interrupted.
+async fn wait_for_next(schedule: &Schedule, args: &Cli) -> Result<bool> {
+    let now = time::OffsetDateTime::now_utc();
+    let Some(next) = schedule.next_from_now() else {
+        anyhow::bail!("schedule \"{}\" never fires", schedule.expr());
+    };
+    if !args.json && !args.silent {
+        println!("Next run: {}", crate::schedule::format_time(next));
+    }
+    let wait = (next - now).try_into().unwrap_or(Duration::ZERO);
+    Ok(tokio::select! {
+        _ = tokio::time::sleep(wait) => true,
+        _ = tokio::signal::ctrl_c() => false,
+    })
+}
+
+/// One line per run, e.g.
+/// `2026-01-01T10:00:00Z full  idle 12.1 ms  DL 94.2 Mbps  UL 19.8 Mbps  loss 0.0%`
+fn summary_line(r: &RunResult, full: bool) -> String {
+    let mut line = format!(
+        "{} {:<5} idle {} ms",
+        r.timestamp_utc,
+        if full { "full" } else { "light" },
+        fmt_opt(r.idle_latency.median_ms)
+    );
+    if full {
+        line.push_str(&format!(
+            "  DL {:.1} Mbps  UL {:.1} Mbps",
+            r.download.mbps, r.upload.mbps
+        ));
+        if let Some(udp) = &r.experimental_udp {
+            line.push_str(&format!("  loss {:.1}%", udp.latency.loss * 100.0));
+        }
+    }
+    if r.partial {
+        line.push_str("  (partial)");
+    }
+    line
+}
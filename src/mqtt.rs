@@ -0,0 +1,184 @@
+//! MQTT publishing with Home Assistant discovery (`--mqtt`)
+//!
+//! Each result is published as one retained JSON message on `<topic>/state`. With
+//! discovery enabled, retained config messages under `homeassistant/sensor/...` point
+//! Home Assistant at fields of that state message, so the sensors show up on their own
+//! and keep their last value across restarts. Every publish opens a short-lived
+//! connection, which suits monitor mode where runs are minutes apart.
+
+use crate::model::{Phase, RunResult};
+use crate::sinks::MqttTarget;
+use anyhow::{Context, Result};
+use rumqttc::{AsyncClient, Event, MqttOptions, Outgoing, Packet, QoS, Transport};
+use serde_json::json;
+use std::time::Duration;
+
+/// Home Assistant's default discovery prefix
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// Upper bound for connecting and getting every message acknowledged
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Sensors announced to Home Assistant: state key, name, unit, device class
+const SENSORS: &[(&str, &str, &str, Option<&str>)] = &[
+    ("download_mbps", "Download", "Mbit/s", Some("data_rate")),
+    ("upload_mbps", "Upload", "Mbit/s", Some("data_rate")),
+    ("idle_latency_ms", "Idle latency", "ms", Some("duration")),
+    ("idle_jitter_ms", "Jitter", "ms", Some("duration")),
+    (
+        "loaded_latency_download_ms",
+        "Latency under download",
+        "ms",
+        Some("duration"),
+    ),
+    (
+        "loaded_latency_upload_ms",
+        "Latency under upload",
+        "ms",
+        Some("duration"),
+    ),
+    ("loss_pct", "Packet loss", "%", None),
+];
+
+/// Publish a result (and discovery configs) and wait for the broker to acknowledge them.
+pub async fn publish(target: &MqttTarget, result: &RunResult) -> Result<()> {
+    let mut messages = vec![(state_topic(&target.topic), state_payload(result))];
+    if target.discovery {
+        messages.extend(discovery_messages(&target.topic));
+    }
+    send(target, messages, true).await
+}
+
+/// Publish the snapshot taken after `phase` to `<topic>/phase`. It is not retained, being
+/// news only while the run lasts; the state topic keeps the last complete result.
+pub async fn publish_phase(target: &MqttTarget, phase: Phase, snapshot: &RunResult) -> Result<()> {
+    let mut payload = state_payload(snapshot);
+    payload["phase"] = json!(phase);
+    let topic = format!("{}/phase", target.topic.trim_end_matches('/'));
+    send(target, vec![(topic, payload)], false).await
+}
+
+/// Publish `messages` over a fresh connection and wait until the broker acknowledged them.
+async fn send(
+    target: &MqttTarget,
+    messages: Vec<(String, serde_json::Value)>,
+    retain: bool,
+) -> Result<()> {
+    let (client, mut eventloop) = AsyncClient::new(options(&target.url)?, messages.len() + 1);
+    let mut pending = messages.len();
+    for (topic, payload) in messages {
+        client
+            .publish(topic, QoS::AtLeastOnce, retain, payload.to_string())
+            .await?;
+    }
+
+    let exchange = async {
+        let mut disconnecting = false;
+        loop {
+            match eventloop.poll().await.context("MQTT connection failed")? {
+                Event::Incoming(Packet::PubAck(_)) => {
+                    pending = pending.saturating_sub(1);
+                    if pending == 0 && !disconnecting {
+                        client.disconnect().await?;
+                        disconnecting = true;
+                    }
+                }
+                Event::Outgoing(Outgoing::Disconnect) => return Ok(()),
+                _ => {}
+            }
+        }
+    };
+    tokio::time::timeout(PUBLISH_TIMEOUT, exchange)
+        .await
+        .context("timed out publishing to the MQTT broker")?
+}
+
+fn options(url: &str) -> Result<MqttOptions> {
+    let parsed = reqwest::Url::parse(url).with_context(|| format!("invalid MQTT URL '{}'", url))?;
+    let tls = match parsed.scheme() {
+        "mqtt" | "tcp" => false,
+        "mqtts" | "ssl" => true,
+        other => anyhow::bail!(
+            "unsupported MQTT scheme '{}' (use mqtt:// or mqtts://)",
+            other
+        ),
+    };
+    let host = parsed
+        .host_str()
+        .with_context(|| format!("MQTT URL '{}' has no host", url))?;
+    let port = parsed.port().unwrap_or(if tls { 8883 } else { 1883 });
+
+    let client_id = format!("cloudflare-speed-cli-{}", std::process::id());
+    let mut opts = MqttOptions::new(client_id, host, port);
+    opts.set_keep_alive(Duration::from_secs(30));
+    if !parsed.username().is_empty() {
+        opts.set_credentials(parsed.username(), parsed.password().unwrap_or_default());
+    }
+    if tls {
+        opts.set_transport(Transport::tls_with_default_config());
+    }
+    Ok(opts)
+}
+
+fn state_topic(topic: &str) -> String {
+    format!("{}/state", topic.trim_end_matches('/'))
+}
+
+/// The flat JSON document Home Assistant's value templates read from.
+fn state_payload(r: &RunResult) -> serde_json::Value {
+    let loss_pct = match &r.experimental_udp {
+        Some(udp) if udp.latency.sent > 0 => Some(udp.latency.loss * 100.0),
+        _ => (r.idle_latency.sent > 0).then_some(r.idle_latency.loss * 100.0),
+    };
+    json!({
+        "timestamp": r.timestamp_utc,
+        "download_mbps": r.download.mbps,
+        "upload_mbps": r.upload.mbps,
+        "idle_latency_ms": r.idle_latency.median_ms,
+        "idle_jitter_ms": r.idle_latency.jitter_ms,
+        "loaded_latency_download_ms": r.loaded_latency_download.median_ms,
+        "loaded_latency_upload_ms": r.loaded_latency_upload.median_ms,
+        "loss_pct": loss_pct,
+        "bufferbloat": r.bufferbloat.as_ref().map(|b| b.grade.label()),
+        "colo": r.colo,
+        "asn": r.asn,
+        "interface": r.interface_name,
+        "network": r.network_name,
+        "partial": r.partial,
+    })
+}
+
+/// Discovery config messages, one per sensor, grouped under one device per base topic.
+fn discovery_messages(topic: &str) -> Vec<(String, serde_json::Value)> {
+    let node_id: String = topic
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let device = json!({
+        "identifiers": [node_id],
+        "name": "Cloudflare Speed Test",
+        "model": "cloudflare-speed-cli",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    });
+    SENSORS
+        .iter()
+        .map(|(key, name, unit, class)| {
+            let mut config = json!({
+                "name": name,
+                "unique_id": format!("{}_{}", node_id, key),
+                "state_topic": state_topic(topic),
+                "value_template": format!("{{{{ value_json.{} }}}}", key),
+                "unit_of_measurement": unit,
+                "state_class": "measurement",
+                "device": device,
+            });
+            if let Some(class) = class {
+                config["device_class"] = json!(class);
+            }
+            (
+                format!("{}/sensor/{}/{}/config", DISCOVERY_PREFIX, node_id, key),
+                config,
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,66 @@
+//! Desktop notifications (`--notify`, or `n` in the dashboard)
+//!
+//! One when a run finishes, and in monitor mode when results start or stop missing the
+//! `--alert-*` thresholds. Builds without the `notify` feature, and systems without a
+//! notification service, log a warning instead.
+
+use crate::cli::fmt_opt;
+use crate::model::RunResult;
+use crate::thresholds::Transition;
+
+/// Tell the desktop that `runs` finished (one, or several with `--count`).
+pub fn finished(runs: &[RunResult]) {
+    let Some(last) = runs.last() else {
+        return;
+    };
+    let title = match runs.len() {
+        1 => "Speed test finished".to_string(),
+        n => format!("{} speed tests finished", n),
+    };
+    show(&title, &summary(last));
+}
+
+/// Tell the desktop that monitor results started or stopped missing a threshold.
+pub fn alert(transition: &Transition, run: &RunResult) {
+    let title = match transition {
+        Transition::Breached(_) => "Speed test alert",
+        Transition::Recovered => "Speed test recovered",
+    };
+    show(title, &format!("{}\n{}", transition, summary(run)));
+}
+
+/// e.g. `DL 94.2 Mbps, UL 19.8 Mbps, idle 12.1 ms`, leaving out phases that did not run
+fn summary(r: &RunResult) -> String {
+    let mut parts = Vec::new();
+    if r.download.bytes > 0 {
+        parts.push(format!("DL {:.1} Mbps", r.download.mbps));
+    }
+    if r.upload.bytes > 0 {
+        parts.push(format!("UL {:.1} Mbps", r.upload.mbps));
+    }
+    parts.push(format!("idle {} ms", fmt_opt(r.idle_latency.median_ms)));
+    let mut line = parts.join(", ");
+    if r.partial {
+        line.push_str(" (partial)");
+    }
+    line
+}
+
+fn show(title: &str, body: &str) {
+    #[cfg(feature = "notify")]
+    {
+        let shown = notify_rust::Notification::new()
+            .appname("cloudflare-speed-cli")
+            .summary(title)
+            .body(body)
+            .show();
+        if let Err(e) = shown {
+            tracing::warn!("Desktop notification failed: {}", e);
+        }
+    }
+    #[cfg(not(feature = "notify"))]
+    {
+        let _ = (title, body);
+        tracing::warn!("This build has no desktop notifications; rebuild with --features notify");
+    }
+}
@@ -0,0 +1,131 @@
+//! Aggregation of repeated identical error messages.
+//!
+//! Workers that hit the same failure over and over (DNS failure, connection refused)
+//! would otherwise flood text output. Messages are reported once when first seen and
+//! then summarized per window, e.g. "connection refused ×184 in 10 s".
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Default aggregation window for repeated messages
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(10);
+
+struct Entry {
+    message: String,
+    count: u64,
+    first: Instant,
+    announced: bool,
+}
+
+/// Collects messages and yields each distinct one once, plus a repeat summary per window.
+pub struct ErrorAggregator {
+    window: Duration,
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl Default for ErrorAggregator {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW)
+    }
+}
+
+impl ErrorAggregator {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Record one occurrence of `message`.
+    pub fn record(&self, message: impl Into<String>) {
+        let message = message.into();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(e) = entries.iter_mut().find(|e| e.message == message) {
+            e.count += 1;
+        } else {
+            entries.push(Entry {
+                message,
+                count: 1,
+                first: Instant::now(),
+                announced: false,
+            });
+        }
+    }
+
+    /// Return lines to report: messages seen for the first time, and a repeat summary
+    /// for every message whose window has elapsed. With `force`, all windows are closed.
+    pub fn drain(&self, force: bool) -> Vec<String> {
+        let mut out = Vec::new();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain_mut(|e| {
+            if !e.announced {
+                out.push(e.message.clone());
+                e.announced = true;
+            }
+            let elapsed = e.first.elapsed();
+            let expired = force || elapsed >= self.window;
+            if expired && e.count > 1 {
+                out.push(format_repeated(&e.message, e.count, elapsed));
+            }
+            !expired
+        });
+        out
+    }
+}
+
+/// Format a repeated message, e.g. "connection refused ×184 in 10 s".
+pub fn format_repeated(message: &str, count: u64, elapsed: Duration) -> String {
+    format!("{} ×{} in {:.0} s", message, count, elapsed.as_secs_f64())
+}
+
+/// Describe a request error without per-request details (URLs, query strings) so that
+/// identical failures aggregate into one line.
+pub fn describe_request_error(err: &reqwest::Error) -> String {
+    if err.is_timeout() {
+        return "request timed out".to_string();
+    }
+    if let Some(status) = err.status() {
+        return format!("HTTP {}", status);
+    }
+    // The innermost source carries the useful part ("Connection refused (os error 111)")
+    let mut source: &dyn std::error::Error = err;
+    while let Some(next) = source.source() {
+        source = next;
+    }
+    let root = source.to_string();
+    if err.is_connect() {
+        format!("connection failed: {}", root)
+    } else {
+        root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drain_reports_first_then_summary() {
+        let agg = ErrorAggregator::new(Duration::from_secs(60));
+        agg.record("connection refused");
+        agg.record("connection refused");
+        assert_eq!(agg.drain(false), vec!["connection refused".to_string()]);
+
+        agg.record("connection refused");
+        assert!(agg.drain(false).is_empty());
+
+        let lines = agg.drain(true);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("connection refused ×3 in"));
+        assert!(agg.drain(true).is_empty());
+    }
+
+    #[test]
+    fn test_single_occurrence_has_no_summary() {
+        let agg = ErrorAggregator::new(Duration::from_secs(60));
+        agg.record("dns failure");
+        assert_eq!(agg.drain(true), vec!["dns failure".to_string()]);
+        assert!(agg.drain(true).is_empty());
+    }
+}
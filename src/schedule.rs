@@ -0,0 +1,295 @@
+//! Cron-style schedules for monitor mode (`--schedule "0 */2 * * *"`)
+//!
+//! Standard five-field expressions (minute, hour, day of month, month, day of week)
+//! with `*`, lists, ranges and steps. As in cron, when both day fields are restricted
+//! a day matching either one qualifies. Times are evaluated in the local timezone when
+//! it can be determined, otherwise in UTC. They are wall-clock times, so a daily run
+//! stays at the same hour across daylight saving changes; a time the clocks skip does
+//! not run that day, and a time they repeat runs the first time only.
+
+use time::{Duration, OffsetDateTime, PrimitiveDateTime, UtcOffset};
+
+/// How far ahead to look for a matching time before giving up (e.g. "0 0 31 2 *")
+const MAX_LOOKAHEAD_DAYS: i64 = 366 * 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    expr: String,
+    minutes: u64,
+    hours: u32,
+    /// Bits 1-31
+    days_of_month: u32,
+    /// Bits 1-12
+    months: u16,
+    /// Bits 0-6, Sunday = 0
+    days_of_week: u8,
+    dom_restricted: bool,
+    dow_restricted: bool,
+}
+
+/// Parse one field into a bitmask of the allowed values.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((r, s)) => {
+                let step: u32 = s
+                    .parse()
+                    .map_err(|_| format!("invalid step '{}' in '{}'", s, field))?;
+                if step == 0 {
+                    return Err(format!("step must be positive in '{}'", field));
+                }
+                (r, step)
+            }
+            None => (part, 1),
+        };
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (parse_value(a, field)?, parse_value(b, field)?)
+        } else {
+            let v = parse_value(range, field)?;
+            // "5/15" means from 5 to the end in steps of 15
+            (v, if step > 1 { max } else { v })
+        };
+        if lo < min || hi > max || lo > hi {
+            return Err(format!(
+                "'{}' is outside {}-{} in '{}'",
+                range, min, max, field
+            ));
+        }
+        for v in (lo..=hi).step_by(step as usize) {
+            mask |= 1 << v;
+        }
+    }
+    Ok(mask)
+}
+
+fn parse_value(s: &str, field: &str) -> Result<u32, String> {
+    s.parse()
+        .map_err(|_| format!("invalid value '{}' in '{}'", s, field))
+}
+
+/// Parse a `--schedule` value.
+pub fn parse(expr: &str) -> Result<Schedule, String> {
+    let fields: Vec<&str> = expr.split_whitespace().collect();
+    let [minute, hour, dom, month, dow] = fields[..] else {
+        return Err(format!(
+            "expected 5 fields (minute hour day-of-month month day-of-week), got {}",
+            fields.len()
+        ));
+    };
+    let mut days_of_week = parse_field(dow, 0, 7)?;
+    // Both 0 and 7 mean Sunday
+    if days_of_week & (1 << 7) != 0 {
+        days_of_week = (days_of_week | 1) & 0x7f;
+    }
+    Ok(Schedule {
+        expr: expr.split_whitespace().collect::<Vec<_>>().join(" "),
+        minutes: parse_field(minute, 0, 59)?,
+        hours: parse_field(hour, 0, 23)? as u32,
+        days_of_month: parse_field(dom, 1, 31)? as u32,
+        months: parse_field(month, 1, 12)? as u16,
+        days_of_week: days_of_week as u8,
+        dom_restricted: dom != "*",
+        dow_restricted: dow != "*",
+    })
+}
+
+impl Schedule {
+    pub fn expr(&self) -> &str {
+        &self.expr
+    }
+
+    fn day_matches(&self, t: OffsetDateTime) -> bool {
+        let dom = self.days_of_month & (1 << t.day()) != 0;
+        let dow = self.days_of_week & (1 << t.weekday().number_days_from_sunday()) != 0;
+        match (self.dom_restricted, self.dow_restricted) {
+            (true, true) => dom || dow,
+            (true, false) => dom,
+            (false, true) => dow,
+            (false, false) => true,
+        }
+    }
+
+    /// First matching minute strictly after `after`, in `after`'s offset.
+    pub fn next_after(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        let limit = after + Duration::days(MAX_LOOKAHEAD_DAYS);
+        let mut t =
+            after.replace_second(0).ok()?.replace_nanosecond(0).ok()? + Duration::minutes(1);
+        while t < limit {
+            if self.months & (1 << u8::from(t.month())) == 0 {
+                // Jump to midnight on the first of the next month
+                let first = t.replace_day(1).ok()?.replace_time(time::Time::MIDNIGHT);
+                t = first + Duration::days(32);
+                t = t.replace_day(1).ok()?;
+            } else if !self.day_matches(t) {
+                t = t.replace_time(time::Time::MIDNIGHT) + Duration::days(1);
+            } else if self.hours & (1 << t.hour()) == 0 {
+                t = t.replace_minute(0).ok()? + Duration::hours(1);
+            } else if self.minutes & (1 << t.minute()) == 0 {
+                t += Duration::minutes(1);
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+
+    /// Next run after now, in local time when known.
+    pub fn next_from_now(&self) -> Option<OffsetDateTime> {
+        self.next_local_after(OffsetDateTime::now_utc(), local_offset_at)
+    }
+
+    /// First matching wall-clock time strictly after `after` in the timezone described by
+    /// `offset_at`, in the offset in effect then.
+    fn next_local_after(
+        &self,
+        after: OffsetDateTime,
+        offset_at: impl Fn(OffsetDateTime) -> UtcOffset + Copy,
+    ) -> Option<OffsetDateTime> {
+        // Wall-clock times are searched written as UTC, then placed in the timezone
+        let local = after.to_offset(offset_at(after));
+        let mut wall = PrimitiveDateTime::new(local.date(), local.time()).assume_utc();
+        loop {
+            wall = self.next_after(wall)?;
+            let instant =
+                local_instant(PrimitiveDateTime::new(wall.date(), wall.time()), offset_at);
+            if let Some(t) = instant.filter(|t| *t > after) {
+                return Some(t);
+            }
+        }
+    }
+}
+
+/// The instant a local wall-clock time falls on, the earlier one when the clocks go back;
+/// None when they skip it. Assumes offset changes are more than a day apart.
+fn local_instant(
+    wall: PrimitiveDateTime,
+    offset_at: impl Fn(OffsetDateTime) -> UtcOffset,
+) -> Option<OffsetDateTime> {
+    let day = Duration::days(1);
+    [wall.assume_utc() - day, wall.assume_utc() + day]
+        .into_iter()
+        .map(|t| wall.assume_offset(offset_at(t)))
+        .filter(|t| offset_at(*t) == t.offset())
+        .min()
+}
+
+/// The local timezone's offset from UTC at `t`, UTC when unknown. Read from the system
+/// timezone through chrono: `time` refuses to look it up on Linux once other threads are
+/// running, which is always the case under the tokio runtime.
+pub fn local_offset_at(t: OffsetDateTime) -> UtcOffset {
+    use chrono::{Offset, TimeZone};
+    chrono::Local
+        .timestamp_opt(t.unix_timestamp(), 0)
+        .earliest()
+        .and_then(|local| {
+            UtcOffset::from_whole_seconds(local.offset().fix().local_minus_utc()).ok()
+        })
+        .unwrap_or(UtcOffset::UTC)
+}
+
+pub fn now_local() -> OffsetDateTime {
+    let now = OffsetDateTime::now_utc();
+    now.to_offset(local_offset_at(now))
+}
+
+/// e.g. "2026-01-01 14:00 +01:00"
+pub fn format_time(t: OffsetDateTime) -> String {
+    let offset = t.offset();
+    let offset = if offset.is_utc() {
+        "UTC".to_string()
+    } else {
+        format!(
+            "{:+03}:{:02}",
+            offset.whole_hours(),
+            offset.minutes_past_hour().abs()
+        )
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} {}",
+        t.year(),
+        u8::from(t.month()),
+        t.day(),
+        t.hour(),
+        t.minute(),
+        offset
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_next_after() {
+        let every_two_hours = parse("0 */2 * * *").unwrap();
+        assert_eq!(
+            every_two_hours.next_after(datetime!(2026-03-01 13:05 UTC)),
+            Some(datetime!(2026-03-01 14:00 UTC))
+        );
+        // Strictly after: a run exactly on the hour schedules the next slot
+        assert_eq!(
+            every_two_hours.next_after(datetime!(2026-03-01 14:00 UTC)),
+            Some(datetime!(2026-03-01 16:00 UTC))
+        );
+
+        // Weekdays at 08:30; 2026-03-07 is a Saturday
+        let weekdays = parse("30 8 * * 1-5").unwrap();
+        assert_eq!(
+            weekdays.next_after(datetime!(2026-03-06 09:00 UTC)),
+            Some(datetime!(2026-03-09 08:30 UTC))
+        );
+
+        // Month rollover
+        let first_of_month = parse("15 3 1 * *").unwrap();
+        assert_eq!(
+            first_of_month.next_after(datetime!(2026-12-31 23:59 UTC)),
+            Some(datetime!(2027-01-01 03:15 UTC))
+        );
+
+        assert!(parse("0 0 31 2 *")
+            .unwrap()
+            .next_after(datetime!(2026-01-01 0:00 UTC))
+            .is_none());
+        assert!(parse("* * *").is_err());
+        assert!(parse("60 * * * *").is_err());
+        assert!(parse("*/0 * * * *").is_err());
+    }
+
+    #[test]
+    fn test_next_local_after_keeps_wall_clock_across_dst() {
+        // Central European time: +01:00, +02:00 from 2026-03-29 to 2026-10-25
+        let cet = |t: OffsetDateTime| {
+            let summer =
+                datetime!(2026-03-29 01:00 UTC) <= t && t < datetime!(2026-10-25 01:00 UTC);
+            UtcOffset::from_hms(if summer { 2 } else { 1 }, 0, 0).unwrap()
+        };
+        let daily = parse("0 3 * * *").unwrap();
+        assert_eq!(
+            daily.next_local_after(datetime!(2026-03-28 12:00 UTC), cet),
+            Some(datetime!(2026-03-29 03:00 +2))
+        );
+        assert_eq!(
+            daily.next_local_after(datetime!(2026-10-24 12:00 UTC), cet),
+            Some(datetime!(2026-10-25 03:00 +1))
+        );
+
+        // 02:30 does not exist on 2026-03-29 and happens twice on 2026-10-25
+        let half_past_two = parse("30 2 * * *").unwrap();
+        assert_eq!(
+            half_past_two.next_local_after(datetime!(2026-03-28 12:00 UTC), cet),
+            Some(datetime!(2026-03-30 02:30 +2))
+        );
+        assert_eq!(
+            half_past_two.next_local_after(datetime!(2026-10-24 12:00 UTC), cet),
+            Some(datetime!(2026-10-25 02:30 +2))
+        );
+        assert_eq!(
+            half_past_two.next_local_after(datetime!(2026-10-25 00:45 UTC), cet),
+            Some(datetime!(2026-10-26 02:30 +1))
+        );
+    }
+}
@@ -0,0 +1,777 @@
+//! Output sinks: everything that happens to a finished run result.
+//!
+//! Printing, exporting, saving to history and (later) pushing to external systems are
+//! all `Sink`s configured from the command line. The CLI and the TUI publish to the
+//! same list, so a new integration only needs a `SinkSpec` variant and an impl here.
+
+use crate::cli::Cli;
+use crate::model::{Phase, RunResult};
+use crate::storage::HistoryOptions;
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use std::path::PathBuf;
+
+/// What a sink reports after publishing a result
+#[derive(Debug, Default)]
+pub struct Published {
+    /// Short status line, e.g. "Saved: /path/to/run.json"
+    pub message: Option<String>,
+    /// Non-fatal degradation worth surfacing (e.g. low disk space)
+    pub warning: Option<String>,
+}
+
+pub trait Sink: Send + Sync {
+    /// Name used in error messages
+    fn name(&self) -> String;
+
+    fn publish<'a>(&'a self, result: &'a RunResult) -> BoxFuture<'a, Result<Published>>;
+
+    /// Receive the snapshot taken when `phase` finished. Sinks that only care about the
+    /// final result keep the default no-op.
+    fn publish_phase<'a>(
+        &'a self,
+        _phase: Phase,
+        _snapshot: &'a RunResult,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// The sinks of a run, built once from their specs
+pub struct Sinks(Vec<(SinkSpec, Box<dyn Sink>)>);
+
+impl Sinks {
+    pub fn new(specs: Vec<SinkSpec>) -> Self {
+        Self(
+            specs
+                .into_iter()
+                .map(|spec| {
+                    let sink = spec.build();
+                    (spec, sink)
+                })
+                .collect(),
+        )
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&SinkSpec, &dyn Sink)> {
+        self.0.iter().map(|(spec, sink)| (spec, sink.as_ref()))
+    }
+
+    /// Keep only the sinks whose spec passes `keep`.
+    pub fn retain(&mut self, keep: impl Fn(&SinkSpec) -> bool) {
+        self.0.retain(|(spec, _)| keep(spec));
+    }
+
+    /// Forward a phase snapshot to every sink, returning the failures as display lines.
+    pub async fn publish_phase(&self, phase: Phase, snapshot: &RunResult) -> Vec<String> {
+        let mut failures = Vec::new();
+        for (_, sink) in self.iter() {
+            if let Err(e) = sink.publish_phase(phase, snapshot).await {
+                failures.push(format!("{} failed: {e:#}", sink.name()));
+            }
+        }
+        failures
+    }
+}
+
+/// A configured sink, as given to `--sink` or implied by the legacy flags.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SinkSpec {
+    /// Pretty JSON on stdout
+    StdoutJson,
+    /// Pretty JSON written to a file (overwritten each run)
+    FileJson(PathBuf),
+    /// CSV written to a file with a header row (overwritten each run)
+    FileCsv(PathBuf),
+    /// CSV row appended to a file, header written when the file is new
+    CsvAppend(PathBuf),
+    /// The run history directory used by the TUI and `diff`
+    History(HistoryOptions),
+    /// InfluxDB line protocol, appended to a file or POSTed to a write endpoint URL
+    Influx {
+        target: String,
+        token: Option<String>,
+    },
+    /// Retained JSON message on an MQTT broker, with Home Assistant discovery
+    Mqtt(MqttTarget),
+    /// HTTP POST of the result JSON, or of a body rendered from a template
+    Webhook {
+        url: String,
+        template: Option<String>,
+        /// `Content-Type` of a templated body; JSON when not given
+        content_type: Option<String>,
+        /// Also POST the snapshot taken after each phase
+        phases: bool,
+    },
+    /// Summary message in a chat channel
+    Chat(ChatTarget),
+}
+
+/// A chat service that results and alerts are posted to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatTarget {
+    /// Slack incoming webhook URL
+    Slack(String),
+    /// Discord channel webhook URL
+    Discord(String),
+    /// Telegram bot token and the chat it writes to
+    Telegram { token: String, chat_id: String },
+}
+
+/// Where `--mqtt` publishes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MqttTarget {
+    /// mqtt://[user:pass@]host[:port], or mqtts:// for TLS
+    pub url: String,
+    /// Base topic; the result goes to `<topic>/state`
+    pub topic: String,
+    /// Publish Home Assistant discovery configs alongside the state
+    pub discovery: bool,
+    /// Also publish the snapshot taken after each phase, to `<topic>/phase`
+    pub phases: bool,
+}
+
+/// Base topic when `--mqtt-topic` is not given
+pub const MQTT_DEFAULT_TOPIC: &str = "cloudflare-speed-cli";
+
+/// Parse a `--sink` value: `stdout-json`, `history`, `file-json:PATH`, `file-csv:PATH`,
+/// `csv-append:PATH`, `influx:PATH|URL`, `mqtt:URL` or `webhook:URL`.
+pub fn parse_spec(s: &str) -> Result<SinkSpec, String> {
+    let (kind, arg) = match s.split_once(':') {
+        Some((k, a)) => (k, Some(a)),
+        None => (s, None),
+    };
+    let path = || match arg {
+        Some(p) if !p.is_empty() => Ok(PathBuf::from(p)),
+        _ => Err(format!(
+            "sink '{}' needs a path, e.g. {}:results.out",
+            kind, kind
+        )),
+    };
+    match kind {
+        "stdout-json" => Ok(SinkSpec::StdoutJson),
+        "history" => Ok(SinkSpec::History(HistoryOptions::default())),
+        "file-json" => path().map(SinkSpec::FileJson),
+        "file-csv" => path().map(SinkSpec::FileCsv),
+        "csv-append" => path().map(SinkSpec::CsvAppend),
+        "influx" => path().map(|p| SinkSpec::Influx {
+            target: p.to_string_lossy().into_owned(),
+            token: None,
+        }),
+        "mqtt" => path().map(|p| {
+            SinkSpec::Mqtt(MqttTarget {
+                url: p.to_string_lossy().into_owned(),
+                topic: MQTT_DEFAULT_TOPIC.to_string(),
+                discovery: true,
+                phases: false,
+            })
+        }),
+        "webhook" => path().map(|p| SinkSpec::Webhook {
+            url: p.to_string_lossy().into_owned(),
+            template: None,
+            content_type: None,
+            phases: false,
+        }),
+        _ => Err(format!(
+            "unknown sink '{}' (expected stdout-json, history, file-json:PATH, file-csv:PATH, csv-append:PATH, influx:PATH|URL, mqtt:URL or webhook:URL)",
+            kind
+        )),
+    }
+}
+
+/// Sinks for a run: `--export-json`/`--export-csv`/`--export-influx` first, then `--sink`
+/// entries, then stdout JSON and history as requested by the caller's mode.
+pub fn configured(args: &Cli, stdout_json: bool, history: bool) -> Sinks {
+    let mut specs = Vec::new();
+    if let Some(p) = &args.export_json {
+        specs.push(SinkSpec::FileJson(p.clone()));
+    }
+    if let Some(p) = &args.export_csv {
+        specs.push(SinkSpec::FileCsv(p.clone()));
+    }
+    if let Some(target) = &args.export_influx {
+        specs.push(SinkSpec::Influx {
+            target: target.clone(),
+            token: None,
+        });
+    }
+    if let Some(url) = &args.mqtt {
+        specs.push(SinkSpec::Mqtt(MqttTarget {
+            url: url.clone(),
+            topic: MQTT_DEFAULT_TOPIC.to_string(),
+            discovery: true,
+            phases: false,
+        }));
+    }
+    if let Some(url) = &args.webhook_url {
+        specs.push(SinkSpec::Webhook {
+            url: url.clone(),
+            template: None,
+            content_type: None,
+            phases: false,
+        });
+    }
+    if !args.chat_alerts_only {
+        specs.extend(crate::chat::targets(args).into_iter().map(SinkSpec::Chat));
+    }
+    specs.extend(args.sinks.iter().cloned());
+    // The token is given once and applies to every Influx endpoint
+    let influx_token = args
+        .influx_token
+        .clone()
+        .or_else(|| std::env::var("INFLUX_TOKEN").ok());
+    for spec in &mut specs {
+        match spec {
+            SinkSpec::Influx { token, .. } => *token = influx_token.clone(),
+            SinkSpec::Mqtt(target) => {
+                target.topic = args.mqtt_topic.clone();
+                target.discovery = args.mqtt_discovery;
+                target.phases = args.publish_phases;
+            }
+            SinkSpec::Webhook {
+                template,
+                content_type,
+                phases,
+                ..
+            } => {
+                *template = args.webhook_template.clone();
+                *content_type = args.webhook_content_type.clone();
+                *phases = args.publish_phases;
+            }
+            SinkSpec::History(options) => *options = args.history_options(),
+            _ => {}
+        }
+    }
+    if stdout_json {
+        specs.push(SinkSpec::StdoutJson);
+    }
+    if history {
+        specs.push(SinkSpec::History(args.history_options()));
+    }
+    // The same sink twice would only duplicate output
+    let mut seen = Vec::new();
+    specs.retain(|s| {
+        let dup = seen.contains(s);
+        seen.push(s.clone());
+        !dup
+    });
+    Sinks::new(specs)
+}
+
+impl SinkSpec {
+    fn build(&self) -> Box<dyn Sink> {
+        match self {
+            SinkSpec::StdoutJson => Box::new(StdoutJson),
+            SinkSpec::FileJson(p) => Box::new(FileJson(p.clone())),
+            SinkSpec::FileCsv(p) => Box::new(FileCsv {
+                path: p.clone(),
+                append: false,
+            }),
+            SinkSpec::CsvAppend(p) => Box::new(FileCsv {
+                path: p.clone(),
+                append: true,
+            }),
+            SinkSpec::History(options) => Box::new(History(*options)),
+            SinkSpec::Influx { target, token } => Box::new(Influx {
+                target: target.clone(),
+                token: token.clone(),
+            }),
+            SinkSpec::Mqtt(target) => Box::new(Mqtt(target.clone())),
+            SinkSpec::Webhook {
+                url,
+                template,
+                content_type,
+                phases,
+            } => Box::new(Webhook {
+                url: url.clone(),
+                template: template.clone(),
+                content_type: content_type
+                    .clone()
+                    .unwrap_or_else(|| JSON_CONTENT_TYPE.to_string()),
+                phases: *phases,
+            }),
+            SinkSpec::Chat(target) => Box::new(Chat(target.clone())),
+        }
+    }
+}
+
+struct StdoutJson;
+
+impl Sink for StdoutJson {
+    fn name(&self) -> String {
+        "stdout-json".to_string()
+    }
+
+    fn publish<'a>(&'a self, result: &'a RunResult) -> BoxFuture<'a, Result<Published>> {
+        Box::pin(async move {
+            println!("{}", serde_json::to_string_pretty(result)?);
+            Ok(Published::default())
+        })
+    }
+}
+
+struct FileJson(PathBuf);
+
+impl Sink for FileJson {
+    fn name(&self) -> String {
+        format!("file-json:{}", self.0.display())
+    }
+
+    fn publish<'a>(&'a self, result: &'a RunResult) -> BoxFuture<'a, Result<Published>> {
+        Box::pin(async move {
+            crate::storage::export_json(&self.0, result)?;
+            Ok(Published {
+                message: Some(format!("Exported JSON: {}", self.0.display())),
+                warning: None,
+            })
+        })
+    }
+}
+
+struct FileCsv {
+    path: PathBuf,
+    append: bool,
+}
+
+impl Sink for FileCsv {
+    fn name(&self) -> String {
+        let kind = if self.append {
+            "csv-append"
+        } else {
+            "file-csv"
+        };
+        format!("{}:{}", kind, self.path.display())
+    }
+
+    fn publish<'a>(&'a self, result: &'a RunResult) -> BoxFuture<'a, Result<Published>> {
+        Box::pin(async move {
+            if self.append {
+                crate::storage::append_csv(&self.path, result)?;
+            } else {
+                crate::storage::export_csv(&self.path, result)?;
+            }
+            Ok(Published {
+                message: Some(format!("Exported CSV: {}", self.path.display())),
+                warning: None,
+            })
+        })
+    }
+}
+
+struct History(HistoryOptions);
+
+impl Sink for History {
+    fn name(&self) -> String {
+        "history".to_string()
+    }
+
+    fn publish<'a>(&'a self, result: &'a RunResult) -> BoxFuture<'a, Result<Published>> {
+        Box::pin(async move {
+            let saved = crate::storage::save_run(result, self.0.compression)?;
+            // The run is saved either way; a failed prune is only worth a warning
+            let pruned = crate::storage::prune(&self.0.retention)
+                .err()
+                .map(|e| format!("pruning history failed: {e:#}"));
+            Ok(Published {
+                message: Some(format!("Saved: {}", saved.path.display())),
+                warning: saved.warning.or(pruned),
+            })
+        })
+    }
+}
+
+struct Influx {
+    target: String,
+    token: Option<String>,
+}
+
+impl Influx {
+    fn is_url(&self) -> bool {
+        self.target.starts_with("http://") || self.target.starts_with("https://")
+    }
+}
+
+impl Sink for Influx {
+    fn name(&self) -> String {
+        format!("influx:{}", self.target)
+    }
+
+    fn publish<'a>(&'a self, result: &'a RunResult) -> BoxFuture<'a, Result<Published>> {
+        Box::pin(async move {
+            if !self.is_url() {
+                let path = PathBuf::from(&self.target);
+                crate::storage::export_influx(&path, result)?;
+                return Ok(Published {
+                    message: Some(format!("Exported Influx: {}", path.display())),
+                    warning: None,
+                });
+            }
+
+            let client = reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()?;
+            let mut req = client
+                .post(&self.target)
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body(crate::storage::influx_line(result));
+            if let Some(token) = &self.token {
+                req = req.header("Authorization", format!("Token {}", token));
+            }
+            let resp = req.send().await.context("send to InfluxDB")?;
+            let status = resp.status();
+            if !status.is_success() {
+                let body = resp.text().await.unwrap_or_default();
+                anyhow::bail!("InfluxDB answered {}: {}", status, body.trim());
+            }
+            Ok(Published {
+                message: Some(format!("Sent to InfluxDB: {}", self.target)),
+                warning: None,
+            })
+        })
+    }
+}
+
+struct Mqtt(MqttTarget);
+
+impl Sink for Mqtt {
+    fn name(&self) -> String {
+        format!("mqtt:{}", crate::network::redact_proxy_url(&self.0.url))
+    }
+
+    fn publish<'a>(&'a self, result: &'a RunResult) -> BoxFuture<'a, Result<Published>> {
+        Box::pin(async move {
+            #[cfg(feature = "mqtt")]
+            {
+                crate::mqtt::publish(&self.0, result).await?;
+                Ok(Published {
+                    message: Some(format!("Published to MQTT: {}/state", self.0.topic)),
+                    warning: None,
+                })
+            }
+            #[cfg(not(feature = "mqtt"))]
+            {
+                let _ = result;
+                anyhow::bail!("this build has no MQTT support; rebuild with --features mqtt")
+            }
+        })
+    }
+
+    fn publish_phase<'a>(
+        &'a self,
+        phase: Phase,
+        snapshot: &'a RunResult,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if !self.0.phases {
+                return Ok(());
+            }
+            #[cfg(feature = "mqtt")]
+            {
+                crate::mqtt::publish_phase(&self.0, phase, snapshot).await
+            }
+            #[cfg(not(feature = "mqtt"))]
+            {
+                let _ = (phase, snapshot);
+                anyhow::bail!("this build has no MQTT support; rebuild with --features mqtt")
+            }
+        })
+    }
+}
+
+struct Chat(ChatTarget);
+
+impl Sink for Chat {
+    fn name(&self) -> String {
+        self.0.name().to_string()
+    }
+
+    fn publish<'a>(&'a self, result: &'a RunResult) -> BoxFuture<'a, Result<Published>> {
+        Box::pin(async move {
+            self.0.post(&crate::chat::summary(result)).await?;
+            Ok(Published {
+                message: Some(format!("Posted to {}", self.0.name())),
+                warning: None,
+            })
+        })
+    }
+}
+
+/// Attempts per webhook delivery; network errors, 429 and 5xx answers are retried
+const WEBHOOK_ATTEMPTS: u32 = 3;
+
+/// `Content-Type` of webhook bodies unless `--webhook-content-type` says otherwise
+const JSON_CONTENT_TYPE: &str = "application/json";
+
+struct Webhook {
+    url: String,
+    template: Option<String>,
+    content_type: String,
+    phases: bool,
+}
+
+impl Webhook {
+    /// The body for `result`; a phase snapshot carries the phase in a `phase` field.
+    fn body(&self, result: &RunResult, phase: Option<Phase>) -> Result<String> {
+        let mut value = serde_json::to_value(result)?;
+        if let Some(phase) = phase {
+            value["phase"] = serde_json::to_value(phase)?;
+        }
+        // Strings go into a JSON body escaped, to land inside its quotes
+        let json = self.content_type.contains("json");
+        Ok(match &self.template {
+            // `@file` reads the template from a file, as in curl
+            Some(t) => match t.strip_prefix('@') {
+                Some(path) => {
+                    let t = std::fs::read_to_string(path)
+                        .with_context(|| format!("read webhook template {}", path))?;
+                    render_template(&t, &value, json)
+                }
+                None => render_template(t, &value, json),
+            },
+            None => serde_json::to_string(&value)?,
+        })
+    }
+
+    fn content_type(&self) -> &str {
+        match self.template {
+            Some(_) => &self.content_type,
+            None => JSON_CONTENT_TYPE,
+        }
+    }
+}
+
+/// Replace `{{path.to.field}}` with that field of the result: strings verbatim, or with
+/// JSON string escapes when `json` is set; other values as JSON, missing fields as empty.
+/// `{{json}}` is the whole result.
+fn render_template(template: &str, value: &serde_json::Value, json: bool) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let key = rest[start + 2..start + end].trim();
+        let field = if key == "json" {
+            Some(value)
+        } else {
+            key.split('.').try_fold(value, |v, k| v.get(k))
+        };
+        match field {
+            Some(serde_json::Value::String(s)) if json => {
+                let quoted = serde_json::Value::String(s.clone()).to_string();
+                out.push_str(&quoted[1..quoted.len() - 1]);
+            }
+            Some(serde_json::Value::String(s)) => out.push_str(s),
+            Some(serde_json::Value::Null) | None => {}
+            Some(other) => out.push_str(&other.to_string()),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+impl Sink for Webhook {
+    fn name(&self) -> String {
+        format!("webhook:{}", crate::network::redact_proxy_url(&self.url))
+    }
+
+    fn publish<'a>(&'a self, result: &'a RunResult) -> BoxFuture<'a, Result<Published>> {
+        Box::pin(async move {
+            let body = self.body(result, None)?;
+            post(&self.url, self.content_type(), body, "webhook").await?;
+            Ok(Published {
+                message: Some(format!("Webhook delivered: {}", self.name())),
+                warning: None,
+            })
+        })
+    }
+
+    fn publish_phase<'a>(
+        &'a self,
+        phase: Phase,
+        snapshot: &'a RunResult,
+    ) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if self.phases {
+                let body = self.body(snapshot, Some(phase))?;
+                post(&self.url, self.content_type(), body, "webhook").await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// POST a JSON body, retrying network errors, 429 and 5xx answers.
+pub(crate) async fn post_json(url: &str, body: String, what: &str) -> Result<()> {
+    post(url, JSON_CONTENT_TYPE, body, what).await
+}
+
+/// POST a body, retrying network errors, 429 and 5xx answers. Errors leave the URL out,
+/// since webhook URLs and bot API paths carry their credentials.
+async fn post(url: &str, content_type: &str, body: String, what: &str) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()?;
+
+    let mut attempt = 1;
+    loop {
+        let outcome = client
+            .post(url)
+            .header("Content-Type", content_type)
+            .header(
+                "User-Agent",
+                format!("cloudflare-speed-cli/{}", env!("CARGO_PKG_VERSION")),
+            )
+            .body(body.clone())
+            .send()
+            .await;
+        let retryable = match outcome {
+            Ok(resp) if resp.status().is_success() => return Ok(()),
+            Ok(resp) => {
+                let status = resp.status();
+                let err = anyhow::anyhow!("{} answered {}", what, status);
+                (status.is_server_error() || status.as_u16() == 429, err)
+            }
+            Err(e) => (
+                true,
+                anyhow::Error::new(e.without_url()).context(format!("send {}", what)),
+            ),
+        };
+        match retryable {
+            (true, _) if attempt < WEBHOOK_ATTEMPTS => {
+                tokio::time::sleep(std::time::Duration::from_secs(2 * u64::from(attempt))).await;
+                attempt += 1;
+            }
+            (_, err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        assert_eq!(parse_spec("stdout-json"), Ok(SinkSpec::StdoutJson));
+        assert_eq!(
+            parse_spec("history"),
+            Ok(SinkSpec::History(HistoryOptions::default()))
+        );
+        assert_eq!(
+            parse_spec("csv-append:/tmp/runs.csv"),
+            Ok(SinkSpec::CsvAppend(PathBuf::from("/tmp/runs.csv")))
+        );
+        assert_eq!(
+            parse_spec("influx:http://localhost:8086/api/v2/write?org=home&bucket=speed"),
+            Ok(SinkSpec::Influx {
+                target: "http://localhost:8086/api/v2/write?org=home&bucket=speed".into(),
+                token: None,
+            })
+        );
+        assert!(parse_spec("file-json").is_err());
+        assert!(parse_spec("webhook").is_err());
+        assert!(parse_spec("carrier-pigeon").is_err());
+    }
+
+    #[test]
+    fn test_render_template() {
+        let value = serde_json::json!({
+            "download": { "mbps": 94.5 },
+            "colo": "AMS",
+            "asn": null,
+        });
+        assert_eq!(
+            render_template(
+                r#"{"text": "{{colo}}: {{ download.mbps }} Mbps{{asn}}{{missing.x}}"}"#,
+                &value,
+                true
+            ),
+            r#"{"text": "AMS: 94.5 Mbps"}"#
+        );
+        assert_eq!(render_template("{{json}}", &value, true), value.to_string());
+
+        // Quotes, backslashes and newlines in strings must not break a JSON body
+        let value = serde_json::json!({ "network_name": "Bob's \"Wi-Fi\"\\\n5G" });
+        let body = render_template(r#"{"text": "{{network_name}}"}"#, &value, true);
+        let parsed: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(parsed["text"], value["network_name"]);
+        assert_eq!(
+            render_template("net={{network_name}}", &value, false),
+            "net=Bob's \"Wi-Fi\"\\\n5G"
+        );
+    }
+
+    /// Accept one HTTP request on `listener`, answer 204 and return its body.
+    async fn receive_one(listener: tokio::net::TcpListener) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut data = Vec::new();
+        loop {
+            let mut buf = [0u8; 4096];
+            let n = stream.read(&mut buf).await.unwrap();
+            data.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&data).into_owned();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|l| {
+                        l.to_ascii_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|v| v.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                if body.len() >= length {
+                    stream
+                        .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                        .await
+                        .unwrap();
+                    return body.to_string();
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webhook_receives_phase_snapshots() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let receiving = tokio::spawn(receive_one(listener));
+
+        let snapshot: RunResult = serde_json::from_value(serde_json::json!({
+            "timestamp_utc": "2026-01-01T00:00:00Z",
+            "base_url": "https://speed.cloudflare.com",
+            "meas_id": "1",
+            "idle_latency": null,
+            "download": { "bytes": 1, "duration_ms": 1, "mbps": 94.5 },
+            "upload": null,
+            "loaded_latency_download": null,
+            "loaded_latency_upload": null,
+        }))
+        .unwrap();
+        let sinks = Sinks::new(vec![
+            // Without --publish-phases a webhook only gets the final result
+            SinkSpec::Webhook {
+                url: "http://127.0.0.1:9/unused".into(),
+                template: None,
+                content_type: None,
+                phases: false,
+            },
+            SinkSpec::Webhook {
+                url,
+                template: None,
+                content_type: None,
+                phases: true,
+            },
+        ]);
+        let failures = sinks.publish_phase(Phase::Download, &snapshot).await;
+        assert!(failures.is_empty(), "{failures:?}");
+
+        let body: serde_json::Value = serde_json::from_str(&receiving.await.unwrap()).unwrap();
+        assert_eq!(body["phase"], "Download");
+        assert_eq!(body["download"]["mbps"], 94.5);
+    }
+}
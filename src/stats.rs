@@ -1,4 +1,17 @@
-use crate::model::LatencySummary;
+use crate::model::{
+    Aggregate, Bufferbloat, BufferbloatGrade, LatencySegments, LatencySummary, RepeatSummary,
+    RunResult,
+};
+
+/// Upper bounds (exclusive, in ms of added latency) for each bufferbloat grade, as
+/// used by the Waveform bufferbloat test; anything above the last one is an F.
+const BUFFERBLOAT_GRADES: [(f64, BufferbloatGrade); 5] = [
+    (5.0, BufferbloatGrade::APlus),
+    (30.0, BufferbloatGrade::A),
+    (60.0, BufferbloatGrade::B),
+    (200.0, BufferbloatGrade::C),
+    (400.0, BufferbloatGrade::D),
+];
 
 #[derive(Debug, Default, Clone)]
 pub struct OnlineStats {
@@ -82,3 +95,152 @@ pub fn latency_summary_from_samples(
         }
     }
 }
+
+/// Split probe outcomes (in send order, `None` for lost probes) into thirds and
+/// summarize each one.
+pub fn latency_segments(probes: &[Option<f64>]) -> LatencySegments {
+    let n = probes.len();
+    let summarize = |part: &[Option<f64>]| {
+        let samples: Vec<f64> = part.iter().flatten().copied().collect();
+        latency_summary_from_samples(part.len() as u64, samples.len() as u64, &samples, None)
+    };
+    LatencySegments {
+        early: summarize(&probes[..n / 3]),
+        mid: summarize(&probes[n / 3..2 * n / 3]),
+        late: summarize(&probes[2 * n / 3..]),
+    }
+}
+
+/// Grade the latency added under load: loaded p75 minus idle p75 for each direction,
+/// graded by the worse one. None without an idle baseline or any loaded samples.
+pub fn bufferbloat(
+    idle: &LatencySummary,
+    loaded_download: &LatencySummary,
+    loaded_upload: &LatencySummary,
+) -> Option<Bufferbloat> {
+    let idle_p75 = idle.p75_ms?;
+    let increase = |loaded: &LatencySummary| loaded.p75_ms.map(|p| (p - idle_p75).max(0.0));
+    let download_increase_ms = increase(loaded_download);
+    let upload_increase_ms = increase(loaded_upload);
+
+    let worst = download_increase_ms
+        .into_iter()
+        .chain(upload_increase_ms)
+        .fold(None, |acc: Option<f64>, v| {
+            Some(acc.map_or(v, |a| a.max(v)))
+        })?;
+    let grade = BUFFERBLOAT_GRADES
+        .iter()
+        .find(|(bound, _)| worst < *bound)
+        .map_or(BufferbloatGrade::F, |(_, g)| *g);
+
+    Some(Bufferbloat {
+        grade,
+        download_increase_ms,
+        upload_increase_ms,
+    })
+}
+
+/// Coefficient of variation (sample standard deviation over mean) in percent; `None` with
+/// fewer than two values or a mean of zero.
+pub fn cv_pct(values: &[f64]) -> Option<f64> {
+    if values.len() < 2 {
+        return None;
+    }
+    let m = values.iter().sum::<f64>() / values.len() as f64;
+    let var = values.iter().map(|v| (v - m).powi(2)).sum::<f64>() / (values.len() - 1) as f64;
+    (m > 0.0).then(|| var.sqrt() / m * 100.0)
+}
+
+/// Mean, median, min and max of `values`; `None` when empty.
+pub fn aggregate(values: &[f64]) -> Option<Aggregate> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let n = sorted.len();
+    let median = if n.is_multiple_of(2) {
+        (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+    } else {
+        sorted[n / 2]
+    };
+    Some(Aggregate {
+        mean: sorted.iter().sum::<f64>() / n as f64,
+        median,
+        min: sorted[0],
+        max: sorted[n - 1],
+    })
+}
+
+/// Aggregate download, upload and idle latency over the repeated runs that completed;
+/// `failed` more did not.
+pub fn repeat_summary(runs: &[RunResult], failed: usize) -> RepeatSummary {
+    let collect =
+        |f: fn(&RunResult) -> Option<f64>| -> Vec<f64> { runs.iter().filter_map(f).collect() };
+    RepeatSummary {
+        runs: runs.len(),
+        failed,
+        download_mbps: aggregate(&collect(|r| {
+            (r.download.bytes > 0).then_some(r.download.mbps)
+        })),
+        upload_mbps: aggregate(&collect(|r| (r.upload.bytes > 0).then_some(r.upload.mbps))),
+        idle_latency_ms: aggregate(&collect(|r| r.idle_latency.median_ms)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_segments_split_into_thirds() {
+        let probes = [
+            Some(10.0),
+            Some(12.0),
+            Some(20.0),
+            None,
+            Some(40.0),
+            Some(44.0),
+            Some(42.0),
+        ];
+        let seg = latency_segments(&probes);
+        assert_eq!(seg.early.sent, 2);
+        assert_eq!(seg.mid.sent, 2);
+        assert_eq!(seg.mid.received, 1);
+        assert_eq!(seg.late.sent, 3);
+        assert_eq!(seg.early.median_ms, Some(12.0));
+        assert_eq!(seg.late.median_ms, Some(42.0));
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let a = aggregate(&[30.0, 10.0, 20.0, 40.0]).unwrap();
+        assert_eq!(a.mean, 25.0);
+        assert_eq!(a.median, 25.0);
+        assert_eq!((a.min, a.max), (10.0, 40.0));
+        assert_eq!(aggregate(&[7.0]).unwrap().median, 7.0);
+        assert!(aggregate(&[]).is_none());
+    }
+
+    #[test]
+    fn test_bufferbloat_grade() {
+        let p75 = |v: Option<f64>| LatencySummary {
+            p75_ms: v,
+            ..Default::default()
+        };
+        let b = bufferbloat(&p75(Some(20.0)), &p75(Some(65.0)), &p75(Some(22.0))).unwrap();
+        assert_eq!(b.grade, BufferbloatGrade::B);
+        assert_eq!(b.download_increase_ms, Some(45.0));
+        assert_eq!(b.upload_increase_ms, Some(2.0));
+
+        // Loaded latency below idle counts as no increase
+        let b = bufferbloat(&p75(Some(20.0)), &p75(Some(18.0)), &p75(None)).unwrap();
+        assert_eq!(b.grade, BufferbloatGrade::APlus);
+
+        assert!(bufferbloat(&p75(None), &p75(Some(50.0)), &p75(Some(50.0))).is_none());
+        assert!(bufferbloat(&p75(Some(20.0)), &p75(None), &p75(None)).is_none());
+        let b = bufferbloat(&p75(Some(20.0)), &p75(Some(900.0)), &p75(None)).unwrap();
+        assert_eq!(b.grade, BufferbloatGrade::F);
+    }
+}
@@ -1,7 +1,13 @@
-use crate::model::RunResult;
+use crate::model::{LatencySummary, RunResult, ThroughputSummary, SCHEMA_VERSION};
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::migrate_from_json;
+
 /// Get the base directory for storing application data.
 fn base_dir() -> PathBuf {
     dirs::data_local_dir()
@@ -20,24 +26,217 @@ pub fn ensure_dirs() -> Result<()> {
     Ok(())
 }
 
-pub fn save_run(result: &RunResult) -> Result<PathBuf> {
+/// Summary rows of the JSON store, one JSON object per line, so listing history does
+/// not need to open every run file
+fn index_path() -> PathBuf {
+    runs_dir().join("index.jsonl")
+}
+
+/// One row of the history index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub timestamp_utc: String,
+    pub meas_id: String,
+    pub download_mbps: f64,
+    pub upload_mbps: f64,
+    pub idle_median_ms: Option<f64>,
+    pub interface_name: Option<String>,
+    pub path: PathBuf,
+}
+
+impl HistoryEntry {
+    fn new(result: &RunResult, path: PathBuf) -> Self {
+        Self {
+            timestamp_utc: result.timestamp_utc.clone(),
+            meas_id: result.meas_id.clone(),
+            download_mbps: result.download.mbps,
+            upload_mbps: result.upload.mbps,
+            idle_median_ms: result.idle_latency.median_ms,
+            interface_name: result.interface_name.clone(),
+            path,
+        }
+    }
+}
+
+/// Compression of saved run files (`--compress`). Reading detects the format from the
+/// file contents, so histories with mixed formats load transparently.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+impl Compression {
+    const ALL: [Compression; 3] = [Compression::None, Compression::Gzip, Compression::Zstd];
+
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::None => "json",
+            Compression::Gzip => "json.gz",
+            Compression::Zstd => "json.zst",
+        }
+    }
+
+    fn encode(self, data: Vec<u8>) -> Result<Vec<u8>> {
+        use std::io::Write;
+
+        Ok(match self {
+            Compression::None => data,
+            Compression::Gzip => {
+                let mut enc =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                enc.write_all(&data)?;
+                enc.finish().context("gzip run")?
+            }
+            Compression::Zstd => zstd::encode_all(data.as_slice(), 0).context("zstd run")?,
+        })
+    }
+}
+
+/// Decompress file contents if they are gzip or zstd.
+fn decode(data: Vec<u8>) -> Result<Vec<u8>> {
+    use std::io::Read;
+
+    if data.starts_with(GZIP_MAGIC) {
+        let mut out = Vec::new();
+        flate2::read::GzDecoder::new(data.as_slice())
+            .read_to_end(&mut out)
+            .context("gunzip run")?;
+        Ok(out)
+    } else if data.starts_with(ZSTD_MAGIC) {
+        zstd::decode_all(data.as_slice()).context("unzstd run")
+    } else {
+        Ok(data)
+    }
+}
+
+/// Whether `path` is a saved run: `.json`, `.json.gz` or `.json.zst`.
+fn is_run_file(path: &Path) -> bool {
+    path.file_name().and_then(|n| n.to_str()).is_some_and(|n| {
+        Compression::ALL
+            .iter()
+            .any(|c| n.ends_with(&format!(".{}", c.extension())))
+    })
+}
+
+/// Free space to keep in reserve beyond the run file itself
+const MIN_FREE_SPACE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Outcome of saving a run.
+pub struct SavedRun {
+    pub path: PathBuf,
+    /// Set when low disk space forced heavy artifacts to be dropped
+    pub warning: Option<String>,
+}
+
+/// Save a run to the runs directory. When free space is low, the raw server metadata and
+/// traceroute hops are dropped and the JSON is written compact, with a warning, rather
+/// than failing the save; only when even that does not fit is an error returned.
+pub fn save_run(result: &RunResult, compression: Compression) -> Result<SavedRun> {
+    #[cfg(feature = "sqlite")]
+    if let Some(db) = sqlite::HistoryDb::open_existing()? {
+        return db.save(result);
+    }
+
     ensure_dirs()?;
-    let path = get_run_path(result)?;
-    let data = serde_json::to_vec_pretty(result)?;
+    let path = run_path(result, compression)?;
+    let mut data = compression.encode(serde_json::to_vec_pretty(result)?)?;
+    let mut warning = None;
+
+    if let Some(free) = free_space(&runs_dir()) {
+        if free < data.len() as u64 + MIN_FREE_SPACE_BYTES {
+            let mut slim = result.clone();
+            slim.meta = None;
+            if let Some(tr) = slim.traceroute.as_mut() {
+                tr.hops.clear();
+            }
+            data = compression.encode(serde_json::to_vec(&slim)?)?;
+            anyhow::ensure!(
+                free >= data.len() as u64,
+                "not enough disk space to save run ({} bytes free in {})",
+                free,
+                runs_dir().display()
+            );
+            warning = Some(format!(
+                "low disk space ({} MiB free): saved without raw metadata and traceroute hops",
+                free / (1024 * 1024)
+            ));
+        }
+    }
+
     std::fs::write(&path, data).context("write run json")?;
-    Ok(path)
+    // A re-save in another format replaces the earlier file
+    for other in Compression::ALL.into_iter().filter(|c| *c != compression) {
+        let _ = std::fs::remove_file(run_path(result, other)?);
+    }
+    // A missing index is rebuilt from the run files on the next listing
+    if index_path().exists() {
+        append_index(&HistoryEntry::new(result, path.clone()))?;
+    }
+    Ok(SavedRun { path, warning })
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `dir`, if known.
+#[cfg(unix)]
+fn free_space(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn free_space(_dir: &Path) -> Option<u64> {
+    None
 }
 
-pub fn get_run_path(result: &RunResult) -> Result<PathBuf> {
+/// The file of a run in the runs directory. The name is made of the run's timestamp and
+/// measurement id, which an imported file could set to anything, so a name that would
+/// reach outside the directory is refused.
+fn run_path(result: &RunResult, compression: Compression) -> Result<PathBuf> {
     let ts = &result.timestamp_utc;
     let safe_ts = ts.replace(':', "-").replace('T', "_");
-    Ok(runs_dir().join(format!("run-{safe_ts}-{}.json", result.meas_id)))
+    let name = format!(
+        "run-{safe_ts}-{}.{}",
+        result.meas_id,
+        compression.extension()
+    );
+    anyhow::ensure!(
+        !name.contains(['/', '\\', '\0']) && !name.contains(".."),
+        "invalid run file name '{}' (from the timestamp and measurement id)",
+        name
+    );
+    Ok(runs_dir().join(name))
 }
 
 pub fn delete_run(result: &RunResult) -> Result<()> {
-    let path = get_run_path(result)?;
-    if path.exists() {
-        std::fs::remove_file(&path).context("delete run file")?;
+    #[cfg(feature = "sqlite")]
+    if let Some(db) = sqlite::HistoryDb::open_existing()? {
+        return db.delete(result);
+    }
+
+    let paths = Compression::ALL
+        .into_iter()
+        .map(|c| run_path(result, c))
+        .collect::<Result<Vec<_>>>()?;
+    for path in &paths {
+        if path.exists() {
+            std::fs::remove_file(path).context("delete run file")?;
+        }
+    }
+    if index_path().exists() {
+        let mut entries = read_index()?.unwrap_or_default();
+        entries.retain(|e| !paths.contains(&e.path));
+        write_index(&entries)?;
     }
     Ok(())
 }
@@ -52,15 +251,104 @@ pub fn export_json(path: &Path, result: &RunResult) -> Result<()> {
     Ok(())
 }
 
+/// Column header shared by CSV exports and appends
+const CSV_HEADER: &str = "timestamp_utc,base_url,meas_id,comments,server,download_mbps,upload_mbps,idle_mean_ms,idle_median_ms,idle_p25_ms,idle_p75_ms,idle_loss,dl_loaded_mean_ms,dl_loaded_median_ms,dl_loaded_p25_ms,dl_loaded_p75_ms,dl_loaded_loss,ul_loaded_mean_ms,ul_loaded_median_ms,ul_loaded_p25_ms,ul_loaded_p75_ms,ul_loaded_loss,ip,colo,asn,as_org,interface_name,network_name,is_wireless,interface_mac,local_ipv4,local_ipv6,external_ipv4,external_ipv6,dns_resolution_ms,dns_ipv4_count,dns_ipv6_count,dns_servers,tls_handshake_ms,tls_protocol,tls_cipher,ipv4_download_mbps,ipv4_upload_mbps,ipv4_latency_ms,ipv6_download_mbps,ipv6_upload_mbps,ipv6_latency_ms,traceroute_hops,anchors,comparisons,modem,proxy\n";
+
 pub fn export_csv(path: &Path, result: &RunResult) -> Result<()> {
     // Create parent directories if they don't exist
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent).context("create export directory")?;
     }
-    let mut out = String::new();
-    // Header row with all fields including diagnostics
-    out.push_str("timestamp_utc,base_url,meas_id,comments,server,download_mbps,upload_mbps,idle_mean_ms,idle_median_ms,idle_p25_ms,idle_p75_ms,idle_loss,dl_loaded_mean_ms,dl_loaded_median_ms,dl_loaded_p25_ms,dl_loaded_p75_ms,dl_loaded_loss,ul_loaded_mean_ms,ul_loaded_median_ms,ul_loaded_p25_ms,ul_loaded_p75_ms,ul_loaded_loss,ip,colo,asn,as_org,interface_name,network_name,is_wireless,interface_mac,local_ipv4,local_ipv6,external_ipv4,external_ipv6,dns_resolution_ms,dns_ipv4_count,dns_ipv6_count,dns_servers,tls_handshake_ms,tls_protocol,tls_cipher,ipv4_download_mbps,ipv4_upload_mbps,ipv4_latency_ms,ipv6_download_mbps,ipv6_upload_mbps,ipv6_latency_ms,traceroute_hops\n");
+    let data = format!("{}{}", CSV_HEADER, csv_row(result));
+    std::fs::write(path, data).context("write export csv")?;
+    Ok(())
+}
+
+/// Append one row to a CSV file, writing the header first if the file is new or empty.
+pub fn append_csv(path: &Path, result: &RunResult) -> Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context("create export directory")?;
+    }
+    let needs_header = std::fs::metadata(path)
+        .map(|m| m.len() == 0)
+        .unwrap_or(true);
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context("open csv for append")?;
+    if needs_header {
+        file.write_all(CSV_HEADER.as_bytes())
+            .context("write csv header")?;
+    }
+    file.write_all(csv_row(result).as_bytes())
+        .context("append csv row")?;
+    Ok(())
+}
+
+/// File format of `history export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// One row per run, same columns as `--export-csv`
+    Csv,
+    /// One full result JSON object per line
+    Ndjson,
+}
+
+/// Write the newest `limit` saved runs (every run with `None`) to a single file, oldest
+/// first. Returns how many runs were written.
+pub fn export_history(path: &Path, format: ExportFormat, limit: Option<usize>) -> Result<usize> {
+    use std::io::Write;
 
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context("create export directory")?;
+    }
+    let file = std::fs::File::create(path).with_context(|| format!("create {}", path.display()))?;
+    let mut out = std::io::BufWriter::new(file);
+    if format == ExportFormat::Csv {
+        out.write_all(CSV_HEADER.as_bytes())?;
+    }
+    let limit = limit.unwrap_or(usize::MAX);
+
+    #[cfg(feature = "sqlite")]
+    if let Some(db) = sqlite::HistoryDb::open_existing()? {
+        let runs = db.query(&RunQuery {
+            limit,
+            ..Default::default()
+        })?;
+        for run in runs.iter().rev() {
+            write_export_row(&mut out, format, run)?;
+        }
+        out.flush().context("write export")?;
+        return Ok(runs.len());
+    }
+
+    // Runs are read one at a time so large histories don't need to fit in memory
+    let entries = load_index()?;
+    let entries = &entries[..entries.len().min(limit)];
+    for entry in entries.iter().rev() {
+        write_export_row(&mut out, format, &load_run(&entry.path)?)?;
+    }
+    out.flush().context("write export")?;
+    Ok(entries.len())
+}
+
+fn write_export_row(
+    out: &mut impl std::io::Write,
+    format: ExportFormat,
+    run: &RunResult,
+) -> Result<()> {
+    match format {
+        ExportFormat::Csv => out.write_all(csv_row(run).as_bytes())?,
+        ExportFormat::Ndjson => writeln!(out, "{}", serde_json::to_string(run)?)?,
+    }
+    Ok(())
+}
+
+/// Format one result as a CSV row matching `CSV_HEADER`.
+fn csv_row(result: &RunResult) -> String {
     // Extract diagnostic values
     let dns_resolution_ms = result.dns.as_ref().map(|d| d.resolution_time_ms);
     let dns_ipv4_count = result.dns.as_ref().map(|d| d.ipv4_count);
@@ -117,30 +405,59 @@ pub fn export_csv(path: &Path, result: &RunResult) -> Result<()> {
     // Traceroute hop count
     let traceroute_hops = result.traceroute.as_ref().map(|t| t.hops.len());
 
-    out.push_str(&format!(
-        "{},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{:.3},{:.3},{:.6},{:.3},{:.3},{:.3},{:.3},{:.6},{:.3},{:.3},{:.3},{:.3},{:.6},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
+    // Anchor medians as "target=ms" pairs
+    let anchors = result
+        .anchors
+        .iter()
+        .map(|a| match a.latency.median_ms {
+            Some(ms) => format!("{}={:.3}", a.target, ms),
+            None => format!("{}=", a.target),
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+    let comparisons = result
+        .comparisons
+        .iter()
+        .map(crate::comparison::summarize)
+        .collect::<Vec<_>>()
+        .join("; ");
+    // Modem line metrics as "name=value" pairs
+    let modem = result
+        .modem
+        .as_ref()
+        .map(|m| {
+            m.metrics
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+                .join("; ")
+        })
+        .unwrap_or_default();
+
+    format!(
+        "{},{},{},{},{},{},{},{:.3},{:.3},{:.3},{:.3},{},{:.3},{:.3},{:.3},{:.3},{},{:.3},{:.3},{:.3},{:.3},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}\n",
         csv_escape(&result.timestamp_utc),
         csv_escape(&result.base_url),
         csv_escape(&result.meas_id),
         csv_escape(result.comments.as_deref().unwrap_or("")),
         csv_escape(result.server.as_deref().unwrap_or("")),
-        result.download.mbps,
-        result.upload.mbps,
+        csv_mbps(&result.download),
+        csv_mbps(&result.upload),
         result.idle_latency.mean_ms.unwrap_or(f64::NAN),
         result.idle_latency.median_ms.unwrap_or(f64::NAN),
         result.idle_latency.p25_ms.unwrap_or(f64::NAN),
         result.idle_latency.p75_ms.unwrap_or(f64::NAN),
-        result.idle_latency.loss,
+        csv_loss(&result.idle_latency),
         result.loaded_latency_download.mean_ms.unwrap_or(f64::NAN),
         result.loaded_latency_download.median_ms.unwrap_or(f64::NAN),
         result.loaded_latency_download.p25_ms.unwrap_or(f64::NAN),
         result.loaded_latency_download.p75_ms.unwrap_or(f64::NAN),
-        result.loaded_latency_download.loss,
+        csv_loss(&result.loaded_latency_download),
         result.loaded_latency_upload.mean_ms.unwrap_or(f64::NAN),
         result.loaded_latency_upload.median_ms.unwrap_or(f64::NAN),
         result.loaded_latency_upload.p25_ms.unwrap_or(f64::NAN),
         result.loaded_latency_upload.p75_ms.unwrap_or(f64::NAN),
-        result.loaded_latency_upload.loss,
+        csv_loss(&result.loaded_latency_upload),
         csv_escape(result.ip.as_deref().unwrap_or("")),
         csv_escape(result.colo.as_deref().unwrap_or("")),
         csv_escape(result.asn.as_deref().unwrap_or("")),
@@ -168,9 +485,11 @@ pub fn export_csv(path: &Path, result: &RunResult) -> Result<()> {
         ipv6_upload.map(|v| format!("{:.3}", v)).unwrap_or_default(),
         ipv6_latency.map(|v| format!("{:.3}", v)).unwrap_or_default(),
         traceroute_hops.map(|v| v.to_string()).unwrap_or_default(),
-    ));
-    std::fs::write(path, out).context("write export csv")?;
-    Ok(())
+        csv_escape(&anchors),
+        csv_escape(&comparisons),
+        csv_escape(&modem),
+        csv_escape(result.proxy.as_deref().unwrap_or("")),
+    )
 }
 
 /// Escape a string for CSV format (handles commas, quotes, and newlines).
@@ -182,29 +501,588 @@ fn csv_escape(s: &str) -> String {
     }
 }
 
+/// Measurement name used in InfluxDB line protocol
+const INFLUX_MEASUREMENT: &str = "speedtest";
+
+/// Append one result to a file in InfluxDB line protocol.
+pub fn export_influx(path: &Path, result: &RunResult) -> Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context("create export directory")?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context("open influx export")?;
+    file.write_all(influx_line(result).as_bytes())
+        .context("append influx line")?;
+    Ok(())
+}
+
+/// Format one result as an InfluxDB line protocol point (newline-terminated), tagged
+/// with interface, colo and ASN and timestamped in nanoseconds.
+pub fn influx_line(result: &RunResult) -> String {
+    let mut line = INFLUX_MEASUREMENT.to_string();
+    for (key, value) in [
+        ("interface", &result.interface_name),
+        ("network", &result.network_name),
+        ("colo", &result.colo),
+        ("asn", &result.asn),
+    ] {
+        if let Some(v) = value.as_deref().filter(|v| !v.is_empty()) {
+            line.push_str(&format!(",{}={}", key, influx_escape_tag(v)));
+        }
+    }
+
+    // Phases that did not run are left out rather than reported as zero or total loss
+    let mut fields = vec![format!("partial={}", result.partial)];
+    for (key, summary) in [("download", &result.download), ("upload", &result.upload)] {
+        if summary.was_measured() {
+            fields.push(format!("{}_mbps={}", key, summary.mbps));
+            fields.push(format!("{}_bytes={}i", key, summary.bytes));
+        }
+    }
+    if result.idle_latency.was_measured() {
+        fields.push(format!("idle_loss={}", result.idle_latency.loss));
+    }
+    for (key, value) in [
+        ("idle_latency_ms", result.idle_latency.median_ms),
+        ("idle_jitter_ms", result.idle_latency.jitter_ms),
+        (
+            "loaded_latency_download_ms",
+            result.loaded_latency_download.median_ms,
+        ),
+        (
+            "loaded_latency_upload_ms",
+            result.loaded_latency_upload.median_ms,
+        ),
+        (
+            "udp_loss",
+            result.experimental_udp.as_ref().map(|u| u.latency.loss),
+        ),
+    ] {
+        // Line protocol has no null; missing metrics are left out of the point
+        if let Some(v) = value.filter(|v| v.is_finite()) {
+            fields.push(format!("{}={}", key, v));
+        }
+    }
+    line.push(' ');
+    line.push_str(&fields.join(","));
+
+    if let Some(ns) = humantime::parse_rfc3339_weak(&result.timestamp_utc)
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+    {
+        line.push_str(&format!(" {}", ns.as_nanos()));
+    }
+    line.push('\n');
+    line
+}
+
+/// Throughput for a CSV row; empty when the phase did not run
+fn csv_mbps(summary: &ThroughputSummary) -> String {
+    if summary.was_measured() {
+        format!("{:.3}", summary.mbps)
+    } else {
+        String::new()
+    }
+}
+
+/// Probe loss fraction for a CSV row; empty when the phase did not run
+fn csv_loss(summary: &LatencySummary) -> String {
+    if summary.was_measured() {
+        format!("{:.6}", summary.loss)
+    } else {
+        String::new()
+    }
+}
+
+/// Escape commas, equals signs and spaces in a line protocol tag value.
+fn influx_escape_tag(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            ',' | '=' | ' ' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' | '\r' => out.push_str("\\ "),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// The newest `limit` saved runs.
 pub fn load_recent(limit: usize) -> Result<Vec<RunResult>> {
+    load_page(0, limit)
+}
+
+/// Saved runs `offset..offset + limit`, newest first. Only those runs are read in full.
+pub fn load_page(offset: usize, limit: usize) -> Result<Vec<RunResult>> {
+    #[cfg(feature = "sqlite")]
+    if let Some(db) = sqlite::HistoryDb::open_existing()? {
+        return db.query(&RunQuery {
+            offset,
+            limit,
+            ..Default::default()
+        });
+    }
+
+    load_index()?
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .map(|e| load_run(&e.path))
+        .collect()
+}
+
+/// Summary of every run in the JSON store, newest first. The index is checked against
+/// the run files and rebuilt when they disagree (e.g. runs saved by an older version or
+/// removed by hand).
+pub fn load_index() -> Result<Vec<HistoryEntry>> {
     ensure_dirs()?;
-    let dir = runs_dir();
-    let mut entries: Vec<(std::time::SystemTime, PathBuf)> = Vec::new();
-    for e in std::fs::read_dir(&dir).context("read runs dir")? {
-        let e = e?;
-        let p = e.path();
-        if p.extension().and_then(|e| e.to_str()) != Some("json") {
+    let mut on_disk = std::collections::HashSet::new();
+    for e in std::fs::read_dir(runs_dir()).context("read runs dir")? {
+        let p = e?.path();
+        if is_run_file(&p) {
+            on_disk.insert(p);
+        }
+    }
+
+    if let Some(mut entries) = read_index()? {
+        // Re-saving a run appends a second row for the same file; keep the latest
+        let mut seen = std::collections::HashSet::new();
+        entries.reverse();
+        entries.retain(|e| seen.insert(e.path.clone()));
+        if seen == on_disk {
+            entries.sort_by(|a, b| b.timestamp_utc.cmp(&a.timestamp_utc));
+            return Ok(entries);
+        }
+    }
+
+    let mut entries = Vec::with_capacity(on_disk.len());
+    for p in on_disk {
+        entries.push(HistoryEntry::new(&load_run(&p)?, p));
+    }
+    entries.sort_by(|a, b| b.timestamp_utc.cmp(&a.timestamp_utc));
+    write_index(&entries)?;
+    Ok(entries)
+}
+
+/// Index rows in file order; None when there is no usable index.
+fn read_index() -> Result<Option<Vec<HistoryEntry>>> {
+    let data = match std::fs::read_to_string(index_path()) {
+        Ok(d) => d,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context("read history index"),
+    };
+    // A damaged index is not an error, just a reason to rebuild it
+    Ok(data
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<HistoryEntry>, _>>()
+        .ok())
+}
+
+fn write_index(entries: &[HistoryEntry]) -> Result<()> {
+    let mut data = String::new();
+    for e in entries {
+        data.push_str(&serde_json::to_string(e)?);
+        data.push('\n');
+    }
+    // Write then rename, so a crash never leaves a half-written index behind
+    let tmp = index_path().with_extension("jsonl.tmp");
+    std::fs::write(&tmp, data).context("write history index")?;
+    std::fs::rename(&tmp, index_path()).context("replace history index")?;
+    Ok(())
+}
+
+fn append_index(entry: &HistoryEntry) -> Result<()> {
+    use std::io::Write;
+
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(index_path())
+        .context("open history index")?;
+    writeln!(file, "{}", serde_json::to_string(entry)?).context("append history index")?;
+    Ok(())
+}
+
+/// How runs are written to the history (`--compress`, `--keep-days`, `--keep-runs`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HistoryOptions {
+    pub compression: Compression,
+    pub retention: Retention,
+}
+
+/// How much history to keep (`--keep-days`, `--keep-runs`); both limits apply.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    pub keep_days: Option<u64>,
+    pub keep_runs: Option<usize>,
+}
+
+impl Retention {
+    pub fn is_unlimited(&self) -> bool {
+        self.keep_days.is_none() && self.keep_runs.is_none()
+    }
+
+    /// Runs with an older `timestamp_utc` are pruned
+    fn cutoff(&self) -> Option<String> {
+        let days = i64::try_from(self.keep_days?).unwrap_or(i64::MAX / 86_400);
+        let t = time::OffsetDateTime::now_utc().checked_sub(time::Duration::days(days))?;
+        t.format(&time::format_description::well_known::Rfc3339)
+            .ok()
+    }
+}
+
+/// Rewrite every saved run file with `compression`. Returns how many files changed.
+pub fn recompress(compression: Compression) -> Result<usize> {
+    let mut entries = load_index()?;
+    let mut changed = 0;
+    for entry in &mut entries {
+        let run = load_run(&entry.path)?;
+        let target = run_path(&run, compression)?;
+        if target == entry.path {
             continue;
         }
-        let m = e.metadata()?;
-        let mt = m.modified().unwrap_or(std::time::SystemTime::UNIX_EPOCH);
-        entries.push((mt, p));
+        let data =
+            std::fs::read(&entry.path).with_context(|| format!("read {}", entry.path.display()))?;
+        let data = compression.encode(decode(data)?)?;
+        std::fs::write(&target, data).with_context(|| format!("write {}", target.display()))?;
+        std::fs::remove_file(&entry.path)
+            .with_context(|| format!("delete {}", entry.path.display()))?;
+        entry.path = target;
+        changed += 1;
     }
-    entries.sort_by_key(|(t, _)| *t);
-    entries.reverse();
+    write_index(&entries)?;
+    Ok(changed)
+}
+
+/// Delete saved runs beyond the retention limits. Returns how many were removed.
+pub fn prune(retention: &Retention) -> Result<usize> {
+    if retention.is_unlimited() {
+        return Ok(0);
+    }
+    let cutoff = retention.cutoff();
+
+    #[cfg(feature = "sqlite")]
+    if let Some(db) = sqlite::HistoryDb::open_existing()? {
+        return db.prune(cutoff.as_deref(), retention.keep_runs);
+    }
+
+    let entries = load_index()?;
+    let keep_runs = retention.keep_runs.unwrap_or(usize::MAX);
+    let (kept, expired): (Vec<_>, Vec<_>) = entries.into_iter().enumerate().partition(|(i, e)| {
+        *i < keep_runs
+            && cutoff
+                .as_deref()
+                .is_none_or(|c| e.timestamp_utc.as_str() >= c)
+    });
+    if expired.is_empty() {
+        return Ok(0);
+    }
+    for (_, e) in &expired {
+        match std::fs::remove_file(&e.path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                return Err(err).with_context(|| format!("delete {}", e.path.display()));
+            }
+            _ => {}
+        }
+    }
+    let kept: Vec<_> = kept.into_iter().map(|(_, e)| e).collect();
+    write_index(&kept)?;
+    Ok(expired.len())
+}
+
+/// Filters for `history query`, combined with AND. Dates compare against the RFC 3339
+/// `timestamp_utc`, so a plain date like `2026-01-31` works too.
+#[derive(Debug, Clone, Default)]
+pub struct RunQuery {
+    /// Runs at or after this time
+    pub since: Option<String>,
+    /// Runs strictly before this time
+    pub before: Option<String>,
+    pub interface: Option<String>,
+    pub colo: Option<String>,
+    /// Runs with or without a detected VPN
+    pub vpn: Option<bool>,
+    /// Number of matching runs to skip
+    pub offset: usize,
+    pub limit: usize,
+}
+
+impl RunQuery {
+    /// Date and interface filters, which the history index can answer
+    fn matches_entry(&self, e: &HistoryEntry) -> bool {
+        self.since
+            .as_deref()
+            .is_none_or(|t| e.timestamp_utc.as_str() >= t)
+            && self
+                .before
+                .as_deref()
+                .is_none_or(|t| e.timestamp_utc.as_str() < t)
+            && self
+                .interface
+                .as_ref()
+                .is_none_or(|i| e.interface_name.as_ref() == Some(i))
+    }
+}
+
+/// Saved runs matching `query`, newest first. Indexed with the SQLite backend; the JSON
+/// store reads every run.
+pub fn query_runs(query: &RunQuery) -> Result<Vec<RunResult>> {
+    #[cfg(feature = "sqlite")]
+    if let Some(db) = sqlite::HistoryDb::open_existing()? {
+        return db.query(query);
+    }
+
+    // The index narrows by date and interface before any run file is opened
+    let mut runs = Vec::new();
+    for entry in load_index()?.iter().filter(|e| query.matches_entry(e)) {
+        if runs.len() >= query.offset.saturating_add(query.limit) {
+            break;
+        }
+        let run = load_run(&entry.path)?;
+        if query
+            .colo
+            .as_ref()
+            .is_none_or(|c| run.colo.as_ref() == Some(c))
+            && query.vpn.is_none_or(|v| run.vpn_detected == v)
+        {
+            runs.push(run);
+        }
+    }
+    Ok(runs.into_iter().skip(query.offset).collect())
+}
+
+/// Load a single saved or exported run result.
+pub fn load_run(path: &Path) -> Result<RunResult> {
+    let data = std::fs::read(path).with_context(|| format!("read {}", path.display()))?;
+    let data = decode(data).with_context(|| format!("decompress {}", path.display()))?;
+    let doc = serde_json::from_slice(&data).with_context(|| format!("parse {}", path.display()))?;
+    parse_run(doc).with_context(|| format!("parse {}", path.display()))
+}
+
+/// Parse a stored result, upgrading documents written with an older schema first.
+pub fn parse_run(mut doc: serde_json::Value) -> Result<RunResult> {
+    let version = migrate_run(&mut doc)?;
+    serde_json::from_value(doc).with_context(|| {
+        if version > SCHEMA_VERSION {
+            format!(
+                "result was written by a newer version (schema {}, this build reads up to {})",
+                version, SCHEMA_VERSION
+            )
+        } else {
+            "invalid result".to_string()
+        }
+    })
+}
+
+/// Upgrade `doc` in place to `SCHEMA_VERSION`, one version at a time. Returns the
+/// version it was stored with.
+fn migrate_run(doc: &mut serde_json::Value) -> Result<u32> {
+    let obj = doc.as_object_mut().context("result is not a JSON object")?;
+    let stored = obj
+        .get("schema_version")
+        .and_then(serde_json::Value::as_u64)
+        .map_or(0, |v| u32::try_from(v).unwrap_or(u32::MAX));
+    for from in stored..SCHEMA_VERSION {
+        match from {
+            // Before versioning: early releases did not write the loaded latency
+            // summaries or the optional sections that are now required keys
+            0 => {
+                for key in ["loaded_latency_download", "loaded_latency_upload"] {
+                    if !obj.contains_key(key) {
+                        obj.insert(key.into(), serde_json::to_value(LatencySummary::skipped())?);
+                    }
+                }
+                for key in ["meta", "turn", "experimental_udp"] {
+                    obj.entry(key).or_insert(serde_json::Value::Null);
+                }
+            }
+            // Version 2 writes skipped phases as null; version 1 results parse as they are
+            1 => {}
+            _ => unreachable!("no migration from schema {}", from),
+        }
+    }
+    if stored < SCHEMA_VERSION {
+        obj.insert("schema_version".into(), SCHEMA_VERSION.into());
+    }
+    Ok(stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_run_migrates_unversioned() {
+        let run = parse_run(serde_json::json!({
+            "timestamp_utc": "2025-01-01T00:00:00Z",
+            "base_url": "https://speed.cloudflare.com",
+            "meas_id": "1",
+            "idle_latency": LatencySummary::default(),
+            "download": { "bytes": 1, "duration_ms": 1, "mbps": 8.0 },
+            "upload": { "bytes": 1, "duration_ms": 1, "mbps": 8.0 },
+        }))
+        .unwrap();
+        assert_eq!(run.schema_version, SCHEMA_VERSION);
+        assert!(!run.loaded_latency_download.was_measured());
+        assert!(parse_run(serde_json::json!({ "schema_version": SCHEMA_VERSION + 1 })).is_err());
+    }
+
+    #[test]
+    fn test_skipped_phases_are_null() {
+        let run = parse_run(serde_json::json!({
+            "schema_version": SCHEMA_VERSION,
+            "timestamp_utc": "2025-01-01T00:00:00Z",
+            "base_url": "https://speed.cloudflare.com",
+            "meas_id": "1",
+            "idle_latency": null,
+            "download": { "bytes": 1, "duration_ms": 1, "mbps": 8.0 },
+            "upload": null,
+            "loaded_latency_download": null,
+            "loaded_latency_upload": null,
+            "meta": null,
+        }))
+        .unwrap();
+        assert_eq!(run.upload.bytes, 0);
+        // Skipped is not the same as every probe lost
+        assert_eq!(run.idle_latency.loss, 0.0);
+
+        let doc = serde_json::to_value(&run).unwrap();
+        assert!(doc["upload"].is_null());
+        assert!(doc["idle_latency"].is_null());
+        assert_eq!(doc["download"]["bytes"], 1);
+    }
+
+    #[test]
+    fn test_skipped_phases_are_empty_in_csv() {
+        let run = parse_run(serde_json::json!({
+            "schema_version": SCHEMA_VERSION,
+            "timestamp_utc": "2025-01-01T00:00:00Z",
+            "base_url": "https://speed.cloudflare.com",
+            "meas_id": "1",
+            "idle_latency": { "sent": 10, "received": 5, "loss": 50.0 },
+            "download": { "bytes": 1, "duration_ms": 1, "mbps": 8.0 },
+            "upload": null,
+            "loaded_latency_download": { "sent": 4, "received": 0, "loss": 100.0 },
+            "loaded_latency_upload": null,
+        }))
+        .unwrap();
+        let row = csv_row(&run);
+        let header: Vec<&str> = CSV_HEADER.trim_end().split(',').collect();
+        let values: Vec<&str> = row.trim_end().split(',').collect();
+        let field = |name: &str| values[header.iter().position(|h| *h == name).unwrap()];
+        assert_eq!(field("download_mbps"), "8.000");
+        assert_eq!(field("upload_mbps"), "");
+        assert_eq!(field("idle_loss"), "0.500000");
+        // Every probe lost is a failure, not a skipped phase
+        assert_eq!(field("dl_loaded_loss"), "1.000000");
+        assert_eq!(field("ul_loaded_loss"), "");
+    }
+
+    #[test]
+    fn test_csv_header_keeps_the_original_columns_in_place() {
+        // Spreadsheets and `--csv-append` files read columns by position; new columns
+        // only ever go at the end
+        let original = "timestamp_utc,base_url,meas_id,comments,server,download_mbps,\
+                        upload_mbps,idle_mean_ms,idle_median_ms,idle_p25_ms,idle_p75_ms,\
+                        idle_loss,dl_loaded_mean_ms,dl_loaded_median_ms,dl_loaded_p25_ms,\
+                        dl_loaded_p75_ms,dl_loaded_loss,ul_loaded_mean_ms,ul_loaded_median_ms,\
+                        ul_loaded_p25_ms,ul_loaded_p75_ms,ul_loaded_loss,ip,colo,asn,as_org,\
+                        interface_name,network_name,is_wireless,interface_mac,local_ipv4,\
+                        local_ipv6,external_ipv4,external_ipv6,dns_resolution_ms,\
+                        dns_ipv4_count,dns_ipv6_count,dns_servers,tls_handshake_ms,\
+                        tls_protocol,tls_cipher,ipv4_download_mbps,ipv4_upload_mbps,\
+                        ipv4_latency_ms,ipv6_download_mbps,ipv6_upload_mbps,ipv6_latency_ms,\
+                        traceroute_hops,";
+        assert!(CSV_HEADER.starts_with(original));
+
+        let run = parse_run(serde_json::json!({
+            "timestamp_utc": "2025-01-01T00:00:00Z",
+            "base_url": "https://speed.cloudflare.com",
+            "meas_id": "1",
+            "idle_latency": LatencySummary::default(),
+            "download": { "bytes": 1, "duration_ms": 1, "mbps": 8.0 },
+            "upload": { "bytes": 1, "duration_ms": 1, "mbps": 8.0 },
+        }))
+        .unwrap();
+        let row = csv_row(&run);
+        assert_eq!(
+            row.trim_end().split(',').count(),
+            CSV_HEADER.trim_end().split(',').count()
+        );
+    }
+
+    #[test]
+    fn test_run_path_stays_in_the_runs_directory() {
+        let mut run = parse_run(serde_json::json!({
+            "timestamp_utc": "2025-01-01T00:00:00.5Z",
+            "base_url": "https://speed.cloudflare.com",
+            "meas_id": "1",
+            "idle_latency": LatencySummary::default(),
+            "download": { "bytes": 1, "duration_ms": 1, "mbps": 8.0 },
+            "upload": { "bytes": 1, "duration_ms": 1, "mbps": 8.0 },
+        }))
+        .unwrap();
+        let path = run_path(&run, Compression::None).unwrap();
+        assert_eq!(path.parent(), Some(runs_dir().as_path()));
+
+        run.meas_id = "../../.bashrc".into();
+        assert!(run_path(&run, Compression::None).is_err());
+        run.meas_id = "1".into();
+        run.timestamp_utc = "x/../../y".into();
+        assert!(run_path(&run, Compression::None).is_err());
+    }
+
+    #[test]
+    fn test_influx_escape_tag() {
+        assert_eq!(influx_escape_tag("eth0"), "eth0");
+        assert_eq!(
+            influx_escape_tag("Home WiFi,5G=fast"),
+            "Home\\ WiFi\\,5G\\=fast"
+        );
+    }
+
+    #[test]
+    fn test_influx_line_leaves_out_unmeasured_phases() {
+        let run = parse_run(serde_json::json!({
+            "schema_version": SCHEMA_VERSION,
+            "timestamp_utc": "2025-01-01T00:00:00Z",
+            "base_url": "https://speed.cloudflare.com",
+            "meas_id": "1",
+            "partial": true,
+            "colo": "AMS",
+            "idle_latency": null,
+            "download": { "bytes": 1000, "duration_ms": 1, "mbps": 8.0 },
+            "upload": null,
+            "loaded_latency_download": null,
+            "loaded_latency_upload": null,
+        }))
+        .unwrap();
+        assert_eq!(
+            influx_line(&run),
+            "speedtest,colo=AMS partial=true,download_mbps=8,download_bytes=1000i \
+             1735689600000000000\n"
+        );
 
-    let mut out = Vec::new();
-    for (_, p) in entries.into_iter().take(limit) {
-        let data = std::fs::read(&p).with_context(|| format!("read {}", p.display()))?;
-        let r: RunResult =
-            serde_json::from_slice(&data).with_context(|| format!("parse {}", p.display()))?;
-        out.push(r);
+        let mut run = run;
+        run.partial = false;
+        run.upload = run.download.clone();
+        run.idle_latency.sent = 10;
+        run.idle_latency.loss = 0.1;
+        run.idle_latency.median_ms = Some(12.5);
+        let line = influx_line(&run);
+        let fields = line.split(' ').nth(1).unwrap();
+        assert_eq!(
+            fields,
+            "partial=false,download_mbps=8,download_bytes=1000i,upload_mbps=8,\
+             upload_bytes=1000i,idle_loss=0.1,idle_latency_ms=12.5"
+        );
     }
-    Ok(out)
 }
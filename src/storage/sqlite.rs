@@ -0,0 +1,186 @@
+//! SQLite run history (`sqlite` feature)
+//!
+//! Once `history migrate-sqlite` has created `history.db` next to the `runs` directory,
+//! saving, loading and deleting runs go through the database instead of the JSON files.
+//! Each row keeps the full result as JSON plus the columns queries filter on, which are
+//! indexed so history lookups stay fast with many thousands of runs.
+
+use super::{base_dir, runs_dir, RunQuery, SavedRun};
+use crate::model::RunResult;
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::path::PathBuf;
+
+const DB_FILE: &str = "history.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    timestamp_utc TEXT NOT NULL,
+    meas_id TEXT NOT NULL,
+    interface_name TEXT,
+    network_name TEXT,
+    colo TEXT,
+    download_mbps REAL NOT NULL,
+    upload_mbps REAL NOT NULL,
+    idle_latency_ms REAL,
+    result TEXT NOT NULL,
+    PRIMARY KEY (timestamp_utc, meas_id)
+);
+CREATE INDEX IF NOT EXISTS runs_interface ON runs (interface_name, timestamp_utc);
+CREATE INDEX IF NOT EXISTS runs_colo ON runs (colo, timestamp_utc);
+";
+
+pub(super) fn db_path() -> PathBuf {
+    base_dir().join(DB_FILE)
+}
+
+pub(super) struct HistoryDb {
+    conn: Connection,
+}
+
+impl HistoryDb {
+    /// The database, if history has been migrated to SQLite.
+    pub(super) fn open_existing() -> Result<Option<Self>> {
+        if !db_path().exists() {
+            return Ok(None);
+        }
+        Self::open().map(Some)
+    }
+
+    fn open() -> Result<Self> {
+        std::fs::create_dir_all(base_dir()).context("create data dir")?;
+        let path = db_path();
+        let conn = Connection::open(&path)
+            .with_context(|| format!("open history database {}", path.display()))?;
+        conn.execute_batch(SCHEMA)
+            .context("create history schema")?;
+        Ok(Self { conn })
+    }
+
+    pub(super) fn save(&self, result: &RunResult) -> Result<SavedRun> {
+        insert(&self.conn, result, true).context("insert run")?;
+        Ok(SavedRun {
+            path: db_path(),
+            warning: None,
+        })
+    }
+
+    pub(super) fn delete(&self, result: &RunResult) -> Result<()> {
+        self.conn
+            .execute(
+                "DELETE FROM runs WHERE timestamp_utc = ?1 AND meas_id = ?2",
+                params![result.timestamp_utc, result.meas_id],
+            )
+            .context("delete run")?;
+        Ok(())
+    }
+
+    /// Newest first, filtered by `query`.
+    pub(super) fn query(&self, query: &RunQuery) -> Result<Vec<RunResult>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT result FROM runs
+             WHERE (?1 IS NULL OR timestamp_utc >= ?1)
+               AND (?2 IS NULL OR timestamp_utc < ?2)
+               AND (?3 IS NULL OR interface_name = ?3)
+               AND (?4 IS NULL OR colo = ?4)
+               AND (?5 IS NULL OR coalesce(json_extract(result, '$.vpn_detected'), 0) = ?5)
+             ORDER BY timestamp_utc DESC
+             LIMIT ?6 OFFSET ?7",
+        )?;
+        let limit = i64::try_from(query.limit).unwrap_or(i64::MAX);
+        let offset = i64::try_from(query.offset).unwrap_or(i64::MAX);
+        let rows = stmt.query_map(
+            params![
+                query.since,
+                query.before,
+                query.interface,
+                query.colo,
+                query.vpn,
+                limit,
+                offset
+            ],
+            |row| row.get::<_, String>(0),
+        )?;
+        let mut out = Vec::new();
+        for json in rows {
+            let doc = serde_json::from_str(&json?).context("parse stored run")?;
+            out.push(super::parse_run(doc).context("parse stored run")?);
+        }
+        Ok(out)
+    }
+
+    /// Delete runs older than `cutoff` and beyond the newest `keep_runs`.
+    pub(super) fn prune(&self, cutoff: Option<&str>, keep_runs: Option<usize>) -> Result<usize> {
+        let mut removed = 0;
+        if let Some(cutoff) = cutoff {
+            removed += self
+                .conn
+                .execute("DELETE FROM runs WHERE timestamp_utc < ?1", params![cutoff])
+                .context("prune old runs")?;
+        }
+        if let Some(keep) = keep_runs {
+            let keep = i64::try_from(keep).unwrap_or(i64::MAX);
+            removed += self
+                .conn
+                .execute(
+                    "DELETE FROM runs WHERE rowid NOT IN
+                     (SELECT rowid FROM runs ORDER BY timestamp_utc DESC LIMIT ?1)",
+                    params![keep],
+                )
+                .context("prune excess runs")?;
+        }
+        Ok(removed)
+    }
+}
+
+/// Insert a run; an existing row for the same run is replaced or kept as is.
+fn insert(conn: &Connection, result: &RunResult, replace: bool) -> Result<bool> {
+    let verb = if replace {
+        "INSERT OR REPLACE"
+    } else {
+        "INSERT OR IGNORE"
+    };
+    let changed = conn.execute(
+        &format!(
+            "{} INTO runs (timestamp_utc, meas_id, interface_name, network_name, colo, download_mbps, upload_mbps, idle_latency_ms, result)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            verb
+        ),
+        params![
+            result.timestamp_utc,
+            result.meas_id,
+            result.interface_name,
+            result.network_name,
+            result.colo,
+            result.download.mbps,
+            result.upload.mbps,
+            result.idle_latency.median_ms,
+            serde_json::to_string(result)?,
+        ],
+    )?;
+    Ok(changed > 0)
+}
+
+/// Import every JSON run into the database, creating it (and so switching history over
+/// to SQLite) if needed. Runs already in the database are skipped, so it can be re-run.
+/// Returns the database path and the number of runs imported.
+pub fn migrate_from_json() -> Result<(PathBuf, usize)> {
+    let mut db = HistoryDb::open()?;
+    let tx = db.conn.transaction()?;
+    let mut imported = 0;
+    let dir = runs_dir();
+    if dir.exists() {
+        for entry in std::fs::read_dir(&dir).context("read runs dir")? {
+            let path = entry?.path();
+            if !super::is_run_file(&path) {
+                continue;
+            }
+            let run = super::load_run(&path)?;
+            if insert(&tx, &run, false).with_context(|| format!("import {}", path.display()))? {
+                imported += 1;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok((db_path(), imported))
+}
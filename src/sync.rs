@@ -0,0 +1,140 @@
+//! Time-synchronized starts across multiple probes.
+//!
+//! Every probe is given the same start instant, either directly (`--sync-start
+//! 2025-01-01T12:00:00Z`) or from a coordinator (`--sync-start http://host:7878`) started
+//! with the `coordinate` subcommand. Probes rely on their own clocks, so they should be
+//! NTP-synchronized for the runs to line up.
+
+use anyhow::{Context, Result};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Resolve a `--sync-start` value (RFC 3339 timestamp or coordinator URL) to an instant.
+pub async fn resolve_start(spec: &str) -> Result<SystemTime> {
+    if spec.starts_with("http://") || spec.starts_with("https://") {
+        let client = reqwest::Client::builder()
+            .no_proxy()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        let body = client
+            .get(spec)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("failed to reach sync coordinator {}", spec))?
+            .text()
+            .await
+            .context("failed to read start time from sync coordinator")?;
+        return parse_start(body.trim());
+    }
+    parse_start(spec)
+}
+
+fn parse_start(s: &str) -> Result<SystemTime> {
+    humantime::parse_rfc3339_weak(s)
+        .with_context(|| format!("invalid sync start time '{}' (expected RFC 3339)", s))
+}
+
+/// Serve a start time `delay` from now to probes until that moment arrives.
+pub async fn run_coordinator(listen: &str, delay: Duration) -> Result<()> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("failed to listen on {}", listen))?;
+    let start_at = SystemTime::now() + delay;
+    let start_str = humantime::format_rfc3339_millis(start_at).to_string();
+    let deadline = tokio::time::Instant::now() + delay;
+
+    let addr = listener.local_addr()?;
+    let info = crate::network::gather_network_info(None);
+    let local = if addr.is_ipv4() {
+        info.local_ipv4
+    } else {
+        info.local_ipv6
+    };
+    println!("Synchronized start at {}", start_str);
+    println!(
+        "Run probes with: {}",
+        probe_hint(addr, local.and_then(|ip| ip.parse().ok()))
+    );
+
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        start_str.len(),
+        start_str
+    );
+    while let Ok(accepted) = tokio::time::timeout_at(deadline, listener.accept()).await {
+        let (mut stream, peer) = match accepted {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        println!("Probe {} joined", peer);
+        let response = response.clone();
+        tokio::spawn(async move {
+            // The request itself does not matter; every GET receives the start time
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).await;
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+        });
+    }
+    println!("Start time reached; coordinator exiting.");
+    Ok(())
+}
+
+/// The `--sync-start` option probes should pass to reach a coordinator bound to `addr`.
+/// A wildcard address is swapped for `local`, this machine's address on the default
+/// interface, or left as a placeholder when that is unknown.
+fn probe_hint(addr: SocketAddr, local: Option<IpAddr>) -> String {
+    if !addr.ip().is_unspecified() {
+        return format!("--sync-start http://{}/", addr);
+    }
+    match local {
+        Some(ip) => format!("--sync-start http://{}/", SocketAddr::new(ip, addr.port())),
+        None => format!(
+            "--sync-start http://HOST:{}/ (HOST being an address of this machine; listening on {})",
+            addr.port(),
+            addr
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_start() {
+        let t = parse_start("2025-01-01T12:00:00Z").unwrap();
+        assert_eq!(
+            t.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs(),
+            1_735_732_800
+        );
+        assert!(parse_start("tomorrow").is_err());
+    }
+
+    #[test]
+    fn test_probe_hint() {
+        let any: SocketAddr = "0.0.0.0:7878".parse().unwrap();
+        assert_eq!(
+            probe_hint(any, Some("192.168.1.20".parse().unwrap())),
+            "--sync-start http://192.168.1.20:7878/"
+        );
+        assert_eq!(
+            probe_hint(any, None),
+            "--sync-start http://HOST:7878/ (HOST being an address of this machine; listening on 0.0.0.0:7878)"
+        );
+        assert_eq!(
+            probe_hint(
+                "[::]:7878".parse().unwrap(),
+                Some("2001:db8::7".parse().unwrap())
+            ),
+            "--sync-start http://[2001:db8::7]:7878/"
+        );
+        assert_eq!(
+            probe_hint("10.0.0.5:7878".parse().unwrap(), None),
+            "--sync-start http://10.0.0.5:7878/"
+        );
+    }
+}
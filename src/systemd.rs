@@ -0,0 +1,383 @@
+//! systemd integration
+//!
+//! In a `Type=notify` unit, monitor mode and `serve --api` report readiness once they are up,
+//! feed the watchdog when `WatchdogSec=` is set, and show the latest result as the unit's
+//! status line. When stderr is connected to the journal, log messages are sent to journald
+//! instead, and every result is logged with its metrics as fields (`DOWNLOAD_MBPS`,
+//! `IDLE_LATENCY_MS`, ...) for `journalctl` to filter on. Outside systemd all of this does
+//! nothing. `--install-systemd` writes a oneshot service and a timer for periodic tests.
+
+use crate::cli::Cli;
+use crate::model::RunResult;
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Unit name for `--install-systemd`; the timer has the same stem
+const UNIT: &str = "cloudflare-speed-cli";
+
+/// Time between tests when `--install-systemd` is given without `--interval`
+const DEFAULT_PERIOD: Duration = Duration::from_secs(3600);
+
+/// Options whose values are or may contain credentials. `--install-systemd` passes them
+/// through an environment file only the owner can read rather than `ExecStart=`, which
+/// anyone can see with `systemctl show`.
+const SECRET_OPTIONS: &[&str] = &[
+    "--endpoint-auth-token",
+    "--influx-token",
+    "--webhook-url",
+    "--slack-webhook",
+    "--discord-webhook",
+    "--telegram-bot-token",
+    "--mqtt",
+    "--proxy",
+];
+
+/// Tell systemd the service is up, and start feeding its watchdog if it has one.
+pub fn ready() {
+    #[cfg(target_os = "linux")]
+    {
+        use sd_notify::NotifyState;
+        if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+            tracing::warn!("sd_notify READY failed: {}", e);
+            return;
+        }
+        let mut usec = 0;
+        if sd_notify::watchdog_enabled(false, &mut usec) {
+            // Ping at twice the rate systemd expects, as sd_watchdog_enabled(3) advises
+            let period = Duration::from_micros(usec) / 2;
+            tracing::debug!("Feeding the systemd watchdog every {:?}", period);
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(period);
+                loop {
+                    ticker.tick().await;
+                    let _ = sd_notify::notify(false, &[NotifyState::Watchdog]);
+                }
+            });
+        }
+    }
+}
+
+/// Set the status line `systemctl status` shows.
+pub fn status(text: &str) {
+    #[cfg(target_os = "linux")]
+    {
+        let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Status(text)]);
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = text;
+}
+
+/// Whether stderr is a journal stream: systemd sets `JOURNAL_STREAM` to its device and
+/// inode, which also tells a service's stderr apart from one inherited by a child that
+/// redirected it.
+#[cfg(target_os = "linux")]
+pub fn stderr_is_journal() -> bool {
+    use std::os::fd::AsRawFd;
+    let Ok(stream) = std::env::var("JOURNAL_STREAM") else {
+        return false;
+    };
+    let Some((dev, ino)) = stream.split_once(':') else {
+        return false;
+    };
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    // SAFETY: stderr is a valid descriptor and `stat` is a properly sized buffer
+    let rc = unsafe { libc::fstat(std::io::stderr().as_raw_fd(), &mut stat) };
+    rc == 0 && dev.parse().ok() == Some(stat.st_dev) && ino.parse().ok() == Some(stat.st_ino)
+}
+
+/// Log a finished run with its metrics as journal fields. Only done when logging to the
+/// journal, where the plain text output lacks them; the terminal has the summary already.
+pub fn log_result(r: &RunResult) {
+    if !crate::logging::to_journal() {
+        return;
+    }
+    let measured = |mbps: f64, bytes: u64| (bytes > 0).then_some(mbps);
+    tracing::info!(
+        meas_id = %r.meas_id,
+        download_mbps = measured(r.download.mbps, r.download.bytes),
+        upload_mbps = measured(r.upload.mbps, r.upload.bytes),
+        idle_latency_ms = r.idle_latency.median_ms,
+        jitter_ms = r.idle_latency.jitter_ms,
+        loss_pct = crate::thresholds::loss_pct(r),
+        colo = r.colo.as_deref(),
+        asn = r.asn.as_deref(),
+        interface = r.interface_name.as_deref(),
+        partial = r.partial,
+        "Speed test finished"
+    );
+}
+
+/// `--install-systemd`: write a service running one test with the other options given,
+/// and a timer starting it every `--interval`. Installed for the current user, or
+/// system-wide when run as root; enabling the timer is left to the user. Credentials go
+/// to an environment file next to the configuration file.
+pub fn install(args: &Cli) -> Result<()> {
+    let exe = std::env::current_exe().context("locate the executable")?;
+    let period = args.interval.map(Duration::from).unwrap_or(DEFAULT_PERIOD);
+    // The options this run was given, minus the ones that describe the installation
+    let Forwarded {
+        args: mut run_args,
+        env,
+    } = forwarded_args(std::env::args_os().skip(1));
+    // A timer has no terminal for the dashboard
+    if !(args.text || args.json) {
+        run_args.insert(0, "--text".into());
+    }
+
+    let system = is_root();
+    let dir = if system {
+        PathBuf::from("/etc/systemd/system")
+    } else {
+        dirs::config_dir()
+            .context("no user configuration directory")?
+            .join("systemd")
+            .join("user")
+    };
+    let env_file = if env.is_empty() {
+        None
+    } else if system {
+        Some(PathBuf::from("/etc").join(UNIT).join("systemd.env"))
+    } else {
+        Some(crate::config::path().with_file_name("systemd.env"))
+    };
+
+    let mut files = vec![
+        (
+            dir.join(format!("{}.service", UNIT)),
+            service_unit(&exe, &run_args, env_file.as_deref()),
+        ),
+        (dir.join(format!("{}.timer", UNIT)), timer_unit(period)),
+    ];
+    if let Some(path) = &env_file {
+        files.push((path.clone(), environment_file(&env)));
+    }
+    for (path, contents) in files {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("create {}", parent.display()))?;
+        }
+        write_private(&path, &contents).with_context(|| format!("write {}", path.display()))?;
+        println!("Wrote {}", path.display());
+    }
+
+    let systemctl = if system {
+        "systemctl"
+    } else {
+        "systemctl --user"
+    };
+    println!(
+        "Enable it with: {0} daemon-reload && {0} enable --now {1}.timer",
+        systemctl, UNIT
+    );
+    if !system {
+        println!("To keep it running while you are logged out: loginctl enable-linger");
+    }
+    Ok(())
+}
+
+/// The command line of the service, split into plain arguments and `CFSPEED_*`
+/// variables for the credentials
+#[derive(Debug, Default, PartialEq)]
+struct Forwarded {
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+}
+
+/// Command line arguments without `--install-systemd` and `--interval`, with the
+/// `SECRET_OPTIONS` moved to the environment
+fn forwarded_args(args: impl Iterator<Item = OsString>) -> Forwarded {
+    let mut out = Forwarded::default();
+    let mut args = args.map(|a| a.to_string_lossy().into_owned());
+    while let Some(arg) = args.next() {
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        match name {
+            "--install-systemd" => {}
+            "--interval" => {
+                if inline.is_none() {
+                    args.next();
+                }
+            }
+            _ if SECRET_OPTIONS.contains(&name) => {
+                if let Some(value) = inline.or_else(|| args.next()) {
+                    let var = format!("CFSPEED_{}", name[2..].replace('-', "_").to_uppercase());
+                    out.env.push((var, value));
+                }
+            }
+            _ => out.args.push(arg),
+        }
+    }
+    out
+}
+
+fn service_unit(exe: &Path, args: &[String], env_file: Option<&Path>) -> String {
+    let command: Vec<String> = std::iter::once(exe.to_string_lossy().into_owned())
+        .chain(args.iter().cloned())
+        .map(|a| exec_arg(&a))
+        .collect();
+    let env_file = env_file
+        .map(|path| format!("EnvironmentFile={}\n", exec_arg(&path.to_string_lossy())))
+        .unwrap_or_default();
+    format!(
+        "[Unit]\n\
+         Description=Cloudflare speed test\n\
+         Documentation={}\n\
+         Wants=network-online.target\n\
+         After=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         {}\
+         ExecStart={}\n",
+        env!("CARGO_PKG_HOMEPAGE"),
+        env_file,
+        command.join(" ")
+    )
+}
+
+/// `EnvironmentFile=` contents; values are double-quoted, with the characters systemd
+/// would unescape or expand escaped
+fn environment_file(env: &[(String, String)]) -> String {
+    env.iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('$', "\\$")
+                .replace('`', "\\`")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"\n", name, value)
+        })
+        .collect()
+}
+
+/// Write `path` readable and writable by its owner only, also when it already existed.
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(contents.as_bytes())
+}
+
+fn timer_unit(period: Duration) -> String {
+    let period = humantime::format_duration(period);
+    format!(
+        "[Unit]\n\
+         Description=Cloudflare speed test every {0}\n\
+         \n\
+         [Timer]\n\
+         OnBootSec=5min\n\
+         OnUnitActiveSec={0}\n\
+         \n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+        period
+    )
+}
+
+/// One `ExecStart=` word: `%` and `$` escaped from specifier and variable expansion, and
+/// double quotes around anything systemd would otherwise split or unescape.
+fn exec_arg(arg: &str) -> String {
+    let arg = arg.replace('%', "%%").replace('$', "$$");
+    let plain = !arg.is_empty()
+        && !arg.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';'));
+    if plain {
+        return arg;
+    }
+    let quoted = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", quoted)
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwarded_args() {
+        let args = [
+            "--install-systemd",
+            "--interval",
+            "15m",
+            "--json",
+            "--interval=30m",
+            "--tag",
+            "site=home",
+            "--telegram-bot-token",
+            "123:abc",
+            "--slack-webhook=https://hooks.slack.com/services/x",
+        ]
+        .map(OsString::from);
+        let forwarded = forwarded_args(args.into_iter());
+        assert_eq!(forwarded.args, ["--json", "--tag", "site=home"]);
+        assert_eq!(
+            forwarded.env,
+            [
+                ("CFSPEED_TELEGRAM_BOT_TOKEN".into(), "123:abc".into()),
+                (
+                    "CFSPEED_SLACK_WEBHOOK".into(),
+                    "https://hooks.slack.com/services/x".into()
+                ),
+            ]
+        );
+        assert_eq!(
+            environment_file(&[("CFSPEED_PROXY".into(), r#"http://u:p"w\$@x"#.into())]),
+            "CFSPEED_PROXY=\"http://u:p\\\"w\\\\\\$@x\"\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("cfspeed-private-{}", std::process::id()));
+        std::fs::write(&path, "old").unwrap();
+        write_private(&path, "secret").unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "secret");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_service_unit() {
+        let unit = service_unit(
+            Path::new("/opt/speed test/cloudflare-speed-cli"),
+            &["--text".into(), "--comments=50% off; $5".into()],
+            Some(Path::new("/etc/cloudflare-speed-cli/systemd.env")),
+        );
+        assert!(unit.contains(
+            "ExecStart=\"/opt/speed test/cloudflare-speed-cli\" --text \"--comments=50%% off; $$5\"\n"
+        ));
+        assert!(unit.contains("Type=oneshot\n"));
+        assert!(unit.contains("EnvironmentFile=/etc/cloudflare-speed-cli/systemd.env\n"));
+        assert!(timer_unit(Duration::from_secs(5400)).contains("OnUnitActiveSec=1h 30m\n"));
+    }
+}
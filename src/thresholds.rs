@@ -0,0 +1,344 @@
+//! Pass/fail thresholds for scripted runs (`--fail-below-download` and friends)
+//!
+//! When a result misses a threshold the process exits with [`EXIT_CODE`] and prints a
+//! JSON document to stderr naming every failed check, so CI jobs and cron wrappers can
+//! tell an SLA violation apart from a test that could not run at all (exit code 1).
+//!
+//! Monitor mode never exits on a result; its thresholds (`--alert-below-download` and
+//! friends) are followed by a [`Watch`] instead, which reports when results start or
+//! stop missing them.
+
+use crate::cli::Cli;
+use crate::model::RunResult;
+use serde::Serialize;
+use serde_json::json;
+
+/// Exit code for a run that completed but missed a threshold
+pub const EXIT_CODE: i32 = 3;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Thresholds {
+    pub min_download_mbps: Option<f64>,
+    pub min_upload_mbps: Option<f64>,
+    pub max_latency_ms: Option<f64>,
+    pub max_loss_pct: Option<f64>,
+}
+
+/// One failed check
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure {
+    pub metric: &'static str,
+    /// `below` or `above`: which side of the threshold fails
+    pub condition: &'static str,
+    pub threshold: f64,
+    /// `None` when the metric was not measured
+    pub actual: Option<f64>,
+    /// 1-based run number with `--count`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run: Option<usize>,
+}
+
+/// The run finished but missed at least one threshold
+#[derive(Debug)]
+pub struct ThresholdError {
+    pub failures: Vec<Failure>,
+}
+
+impl std::fmt::Display for ThresholdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "threshold failed: {}", describe(&self.failures))
+    }
+}
+
+impl std::error::Error for ThresholdError {}
+
+/// The failed checks as text, e.g. `download_mbps 3.20 is not at least 50`
+fn describe(failures: &[Failure]) -> String {
+    let parts: Vec<String> = failures
+        .iter()
+        .map(|x| {
+            let actual = x
+                .actual
+                .map(|v| format!("{:.2}", v))
+                .unwrap_or_else(|| "not measured".to_string());
+            let run = x.run.map(|r| format!(" (run {})", r)).unwrap_or_default();
+            format!(
+                "{} {} is not {} {}{}",
+                x.metric,
+                actual,
+                if x.condition == "below" {
+                    "at least"
+                } else {
+                    "at most"
+                },
+                x.threshold,
+                run
+            )
+        })
+        .collect();
+    parts.join("; ")
+}
+
+impl ThresholdError {
+    /// The machine-readable form printed on exit
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "error": "threshold_failed",
+            "exit_code": EXIT_CODE,
+            "message": self.to_string(),
+            "failures": self.failures,
+        })
+    }
+}
+
+impl Thresholds {
+    pub fn from_args(args: &Cli) -> Self {
+        Self {
+            min_download_mbps: args.fail_below_download,
+            min_upload_mbps: args.fail_below_upload,
+            max_latency_ms: args.fail_above_latency,
+            max_loss_pct: args.fail_above_loss,
+        }
+    }
+
+    /// The monitor mode thresholds (`--alert-*`)
+    pub fn alerts_from_args(args: &Cli) -> Self {
+        Self {
+            min_download_mbps: args.alert_below_download,
+            min_upload_mbps: args.alert_below_upload,
+            max_latency_ms: args.alert_above_latency,
+            max_loss_pct: args.alert_above_loss,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min_download_mbps.is_none()
+            && self.min_upload_mbps.is_none()
+            && self.max_latency_ms.is_none()
+            && self.max_loss_pct.is_none()
+    }
+
+    /// The failed checks for one result
+    pub fn check(&self, run: &RunResult) -> Vec<Failure> {
+        let measured = |mbps: f64, bytes: u64| (bytes > 0).then_some(mbps);
+        let checks = [
+            (
+                "download_mbps",
+                "below",
+                self.min_download_mbps,
+                measured(run.download.mbps, run.download.bytes),
+            ),
+            (
+                "upload_mbps",
+                "below",
+                self.min_upload_mbps,
+                measured(run.upload.mbps, run.upload.bytes),
+            ),
+            (
+                "latency_ms",
+                "above",
+                self.max_latency_ms,
+                run.idle_latency.median_ms,
+            ),
+            ("loss_pct", "above", self.max_loss_pct, loss_pct(run)),
+        ];
+        checks
+            .into_iter()
+            .filter_map(|(metric, condition, threshold, actual)| {
+                let threshold = threshold?;
+                let ok = match (condition, actual) {
+                    ("below", Some(v)) => v >= threshold,
+                    (_, Some(v)) => v <= threshold,
+                    (_, None) => false,
+                };
+                (!ok).then_some(Failure {
+                    metric,
+                    condition,
+                    threshold,
+                    actual,
+                    run: None,
+                })
+            })
+            .collect()
+    }
+
+    /// Check every result (numbering them when there are several).
+    pub fn enforce(&self, runs: &[RunResult]) -> anyhow::Result<()> {
+        let failures: Vec<Failure> = runs
+            .iter()
+            .enumerate()
+            .flat_map(|(i, run)| {
+                self.check(run).into_iter().map(move |mut f| {
+                    f.run = (runs.len() > 1).then_some(i + 1);
+                    f
+                })
+            })
+            .collect();
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(ThresholdError { failures }.into())
+        }
+    }
+}
+
+/// Packet loss of a result in percent: the UDP probe's when it ran, otherwise that of
+/// the idle latency probes. `None` when neither sent anything.
+pub fn loss_pct(run: &RunResult) -> Option<f64> {
+    let loss = run
+        .experimental_udp
+        .as_ref()
+        .map(|u| &u.latency)
+        .filter(|l| l.sent > 0)
+        .unwrap_or(&run.idle_latency);
+    (loss.sent > 0).then_some(loss.loss * 100.0)
+}
+
+/// What a monitor result changed
+#[derive(Debug, Clone)]
+pub enum Transition {
+    /// These checks started failing (others may have been failing already)
+    Breached(Vec<Failure>),
+    /// Every check passes again
+    Recovered,
+}
+
+/// Follows the results of a monitor, so a threshold that stays missed is reported once
+/// rather than on every run.
+#[derive(Debug, Default)]
+pub struct Watch {
+    thresholds: Thresholds,
+    /// Metrics of the checks failing now
+    failing: Vec<&'static str>,
+}
+
+impl Watch {
+    pub fn new(thresholds: Thresholds) -> Self {
+        Self {
+            thresholds,
+            failing: Vec::new(),
+        }
+    }
+
+    /// Check the next result. Latency-only runs (`full` false) say nothing about
+    /// throughput, so its checks keep their last state.
+    pub fn update(&mut self, run: &RunResult, full: bool) -> Option<Transition> {
+        let is_throughput = |metric: &str| metric.ends_with("_mbps");
+        let mut failures = self.thresholds.check(run);
+        if !full {
+            failures.retain(|f| !is_throughput(f.metric));
+        }
+        let mut failing: Vec<&'static str> = failures.iter().map(|f| f.metric).collect();
+        if !full {
+            failing.extend(self.failing.iter().filter(|m| is_throughput(m)));
+        }
+        let was_failing = std::mem::replace(&mut self.failing, failing);
+        if self.failing.is_empty() {
+            return (!was_failing.is_empty()).then_some(Transition::Recovered);
+        }
+        failures.retain(|f| !was_failing.contains(&f.metric));
+        (!failures.is_empty()).then_some(Transition::Breached(failures))
+    }
+}
+
+impl std::fmt::Display for Transition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transition::Breached(failures) => write!(f, "alert: {}", describe(failures)),
+            Transition::Recovered => write!(f, "recovered: every alert threshold is met"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{LatencySummary, ThroughputSummary};
+
+    #[test]
+    fn test_check() {
+        let run: RunResult = serde_json::from_value(json!({
+            "timestamp_utc": "2026-01-01T00:00:00Z",
+            "base_url": "https://speed.cloudflare.com",
+            "meas_id": "1",
+            "idle_latency": LatencySummary {
+                sent: 10,
+                received: 9,
+                loss: 0.1,
+                median_ms: Some(25.0),
+                ..Default::default()
+            },
+            "download": ThroughputSummary { bytes: 1, mbps: 80.0, ..Default::default() },
+            "upload": ThroughputSummary::default(),
+            "loaded_latency_download": LatencySummary::default(),
+            "loaded_latency_upload": LatencySummary::default(),
+        }))
+        .unwrap();
+
+        let t = Thresholds {
+            min_download_mbps: Some(50.0),
+            max_latency_ms: Some(30.0),
+            ..Default::default()
+        };
+        assert!(t.check(&run).is_empty());
+
+        let t = Thresholds {
+            min_download_mbps: Some(100.0),
+            min_upload_mbps: Some(1.0),
+            max_latency_ms: Some(20.0),
+            max_loss_pct: Some(5.0),
+        };
+        let failed: Vec<_> = t.check(&run).iter().map(|f| f.metric).collect();
+        assert_eq!(
+            failed,
+            ["download_mbps", "upload_mbps", "latency_ms", "loss_pct"]
+        );
+        // Upload was not measured
+        assert_eq!(t.check(&run)[1].actual, None);
+    }
+
+    #[test]
+    fn test_watch_reports_changes_only() {
+        let run = |mbps: f64, latency_ms: f64| -> RunResult {
+            serde_json::from_value(json!({
+                "timestamp_utc": "2026-01-01T00:00:00Z",
+                "base_url": "https://speed.cloudflare.com",
+                "meas_id": "1",
+                "idle_latency": LatencySummary {
+                    median_ms: Some(latency_ms),
+                    ..Default::default()
+                },
+                "download": ThroughputSummary { bytes: 1, mbps, ..Default::default() },
+                "upload": ThroughputSummary::default(),
+                "loaded_latency_download": LatencySummary::default(),
+                "loaded_latency_upload": LatencySummary::default(),
+            }))
+            .unwrap()
+        };
+        let mut watch = Watch::new(Thresholds {
+            min_download_mbps: Some(50.0),
+            max_latency_ms: Some(30.0),
+            ..Default::default()
+        });
+        let metrics = |t: Option<Transition>| match t {
+            Some(Transition::Breached(failures)) => failures.iter().map(|f| f.metric).collect(),
+            Some(Transition::Recovered) => vec!["recovered"],
+            None => Vec::new(),
+        };
+        assert!(watch.update(&run(80.0, 20.0), true).is_none());
+        assert_eq!(
+            metrics(watch.update(&run(10.0, 20.0), true)),
+            ["download_mbps"]
+        );
+        // Still slow: reported once
+        assert!(watch.update(&run(10.0, 20.0), true).is_none());
+        // A latency-only run neither clears nor repeats the download alert
+        assert!(watch.update(&run(0.0, 20.0), false).is_none());
+        assert_eq!(
+            metrics(watch.update(&run(0.0, 40.0), false)),
+            ["latency_ms"]
+        );
+        assert!(watch.update(&run(80.0, 40.0), true).is_none());
+        assert_eq!(metrics(watch.update(&run(80.0, 20.0), true)), ["recovered"]);
+    }
+}
@@ -4,13 +4,13 @@ use ratatui::{
     style::Style,
     text::{Line, Span},
     widgets::canvas::Line as CanvasLine,
-    widgets::{canvas::Canvas, Bar, BarChart, BarGroup, Block, Borders, Chart, Dataset, Paragraph},
+    widgets::{canvas::Canvas, Bar, BarChart, BarGroup, Block, Borders, Chart, Paragraph},
     Frame,
 };
 use std::collections::HashMap;
 
 use super::state::UiState;
-use crate::model::RunResult;
+use crate::model::{LatencySegments, RunResult};
 
 /// Helper function to draw a line on a canvas
 pub fn draw_line(
@@ -122,6 +122,98 @@ pub fn render_box_plot_with_metrics_inside(
     f.render_widget(block, area);
 }
 
+/// Render loaded latency split into early/mid/late thirds (`LatencySegments` of the
+/// finished phase) as three one-line box plots sharing the same scale, so queue build-up
+/// over the phase is visible. Thirds with a Wi-Fi roam or channel switch (`events`) are
+/// flagged, like the marker lines on the throughput charts.
+pub fn render_segment_box_plots(
+    f: &mut Frame,
+    area: Rect,
+    segments: Option<&LatencySegments>,
+    events: [bool; 3],
+    title: Line,
+    color: Color,
+) {
+    let block = Block::default().borders(Borders::ALL).title(title);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let Some(segments) = segments else {
+        f.render_widget(Paragraph::new("Waiting for data..."), inner);
+        return;
+    };
+    let thirds = [
+        ("early", &segments.early),
+        ("mid", &segments.mid),
+        ("late", &segments.late),
+    ];
+    let lo = thirds
+        .iter()
+        .filter_map(|(_, s)| s.min_ms)
+        .fold(f64::INFINITY, f64::min);
+    let hi = thirds
+        .iter()
+        .filter_map(|(_, s)| s.max_ms)
+        .fold(f64::NEG_INFINITY, f64::max);
+    // Label (6) + median value (7) + event flag (2) around the plot
+    let width = (inner.width as usize).saturating_sub(15).max(3);
+    let pos = |v: f64| -> usize {
+        if hi > lo {
+            (((v - lo) / (hi - lo)) * (width - 1) as f64).round() as usize
+        } else {
+            0
+        }
+    };
+
+    let lines: Vec<Line> = thirds
+        .iter()
+        .zip(events)
+        .map(|((label, summary), event)| {
+            let mut spans = vec![Span::styled(
+                format!("{:<6}", label),
+                Style::default().fg(Color::Gray),
+            )];
+            let (Some(min), Some(q1), Some(med), Some(q3), Some(max)) = (
+                summary.min_ms,
+                summary.p25_ms,
+                summary.median_ms,
+                summary.p75_ms,
+                summary.max_ms,
+            ) else {
+                // Every probe of this third was lost
+                spans.push(Span::styled(
+                    "no replies",
+                    Style::default().fg(Color::DarkGray),
+                ));
+                return Line::from(spans);
+            };
+            let (min_at, q1_at, med_at, q3_at, max_at) =
+                (pos(min), pos(q1), pos(med), pos(q3), pos(max));
+            for i in 0..width {
+                let (ch, style) = if i == med_at {
+                    ("┃", Style::default().fg(Color::Yellow))
+                } else if i >= q1_at && i <= q3_at {
+                    ("█", Style::default().fg(color))
+                } else if i >= min_at && i <= max_at {
+                    ("─", Style::default().fg(Color::White))
+                } else {
+                    (" ", Style::default())
+                };
+                spans.push(Span::styled(ch, style));
+            }
+            spans.push(Span::styled(
+                format!(" {:>4.0}ms", med),
+                Style::default().fg(color),
+            ));
+            if event {
+                spans.push(Span::styled(" ⚑", Style::default().fg(Color::Yellow)));
+            }
+            Line::from(spans)
+        })
+        .collect();
+    f.render_widget(Paragraph::new(lines), inner);
+}
+
 /// Helper function to render metrics text (avg, med, p25, p75, and optionally jitter, loss)
 fn render_metrics_text<'a>(
     metrics: (f64, f64, f64, f64),
@@ -152,7 +244,10 @@ fn render_metrics_text<'a>(
         if let Some(l) = loss {
             spans.push(Span::raw(" "));
             spans.push(Span::styled("loss", Style::default().fg(Color::Gray)));
-            spans.push(Span::styled(format!(" {:.1}%", l * 100.0), Style::default().fg(c)));
+            spans.push(Span::styled(
+                format!(" {:.1}%", l * 100.0),
+                Style::default().fg(c),
+            ));
         }
         Line::from(spans)
     } else {
@@ -174,9 +269,7 @@ fn render_metrics_text<'a>(
 pub fn render_chart_with_metrics_inside(
     f: &mut Frame,
     area: Rect,
-    datasets: Vec<Dataset>,
-    x_axis: ratatui::widgets::Axis,
-    y_axis: ratatui::widgets::Axis,
+    chart: Chart,
     title: Line,
     metrics: Option<(f64, f64, f64, f64)>,
     color: Color,
@@ -200,8 +293,7 @@ pub fn render_chart_with_metrics_inside(
         .split(inner);
 
     // Render chart in top area (without its own borders, we'll add them to the whole area)
-    let chart_without_borders = Chart::new(datasets).x_axis(x_axis).y_axis(y_axis);
-    f.render_widget(chart_without_borders, chart_metrics[0]);
+    f.render_widget(chart, chart_metrics[0]);
 
     // Render metrics in bottom area (no jitter or loss for throughput charts)
     if let Some(metrics) = metrics {
@@ -314,7 +406,7 @@ pub fn draw_charts(area: Rect, f: &mut Frame, state: &UiState) {
     // Chart width = chunks[1].width - Y-axis label width (6) - borders (2)
     let available_chart_width = chunks[1].width.saturating_sub(8) as usize;
     // Cap at 200 bars max for performance, but allow wider bars on ultra-wide screens
-    let max_bars = available_chart_width.max(1).min(200);
+    let max_bars = available_chart_width.clamp(1, 200);
 
     // Prepare data for charts: take only as many as can fit, then reverse so oldest is on left, newest on right
     let data_points: Vec<_> = filtered_data
@@ -382,11 +474,9 @@ pub fn draw_charts(area: Rect, f: &mut Frame, state: &UiState) {
 
     // Recalculate bar width for the actual chart area
     let dl_chart_width = dl_layout[1].width.saturating_sub(2) as usize;
-    let dl_bar_width = if num_bars > 0 {
-        (dl_chart_width / num_bars).max(1) as u16
-    } else {
-        1
-    };
+    let dl_bar_width = dl_chart_width
+        .checked_div(num_bars)
+        .map_or(1, |w| w.max(1) as u16);
 
     // Y-axis labels for download - offset by 1 at top/bottom to align with chart's inner area
     let dl_label_layout = Layout::default()
@@ -441,11 +531,9 @@ pub fn draw_charts(area: Rect, f: &mut Frame, state: &UiState) {
 
     // Recalculate bar width for upload chart area
     let ul_chart_width = ul_layout[1].width.saturating_sub(2) as usize;
-    let ul_bar_width = if num_bars > 0 {
-        (ul_chart_width / num_bars).max(1) as u16
-    } else {
-        1
-    };
+    let ul_bar_width = ul_chart_width
+        .checked_div(num_bars)
+        .map_or(1, |w| w.max(1) as u16);
 
     // Y-axis labels for upload - offset by 1 at top/bottom to align with chart's inner area
     let ul_label_layout = Layout::default()
@@ -4,25 +4,195 @@ use ratatui::{
     style::Style,
     symbols,
     text::{Line, Span},
-    widgets::{Axis, Block, Borders, Dataset, GraphType, Paragraph, Sparkline},
+    widgets::{Axis, Block, Borders, Chart, Dataset, GraphType, Paragraph, Sparkline},
     Frame,
 };
 
 use super::charts;
 use super::state::{push_wrapped_status_kv, UiState};
+use crate::model::{AimRating, BufferbloatGrade};
 
 /// Helper function to get the maximum y value from a series of points
 pub fn max_y(points: &[(f64, f64)]) -> f64 {
     points.iter().map(|(_, y)| *y).fold(0.0, |a, b| a.max(b))
 }
 
+/// `--compare-protocols` results, the fastest protocol highlighted
+fn protocols_panel(state: &UiState) -> Paragraph<'static> {
+    let best = state
+        .protocol_results
+        .iter()
+        .filter_map(|p| p.mbps)
+        .fold(0.0, f64::max);
+    let lines: Vec<Line> = state
+        .protocol_results
+        .iter()
+        .map(|p| {
+            let label = Span::styled(
+                format!("{:<9}", p.protocol.label()),
+                Style::default().fg(Color::Gray),
+            );
+            match p.mbps {
+                Some(mbps) => Line::from(vec![
+                    label,
+                    Span::styled(
+                        format!("{:.1} Mbps", mbps),
+                        if mbps >= best {
+                            Style::default().fg(Color::Green)
+                        } else {
+                            Style::default()
+                        },
+                    ),
+                    Span::raw(format!(
+                        "  TTFB {}",
+                        p.ttfb_ms
+                            .map(|v| format!("{:.0} ms", v))
+                            .unwrap_or_else(|| "-".to_string())
+                    )),
+                ]),
+                None => Line::from(vec![
+                    label,
+                    Span::styled("unavailable", Style::default().fg(Color::Red)),
+                ]),
+            }
+        })
+        .collect();
+    Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Protocols"))
+}
+
+/// `--udp-throughput` stream through the TURN relay
+fn udp_throughput_panel(summary: &crate::model::UdpThroughputSummary) -> Paragraph<'static> {
+    let gray = Style::default().fg(Color::Gray);
+    let lines = match &summary.error {
+        Some(e) => vec![Line::from(Span::styled(
+            format!("failed: {e}"),
+            Style::default().fg(Color::Red),
+        ))],
+        None => vec![
+            Line::from(vec![
+                Span::styled("Goodput  ", gray),
+                Span::styled(
+                    format!("{:.1} Mbps", summary.goodput_mbps),
+                    Style::default().fg(Color::Green),
+                ),
+                Span::styled(format!(" of {:.0}", summary.target_mbps), gray),
+            ]),
+            Line::from(vec![
+                Span::styled("Loss     ", gray),
+                Span::styled(
+                    format!("{:.1}%", summary.loss * 100.0),
+                    Style::default().fg(if summary.loss < 0.01 {
+                        Color::Green
+                    } else if summary.loss < 0.05 {
+                        Color::Yellow
+                    } else {
+                        Color::Red
+                    }),
+                ),
+            ]),
+            Line::from(vec![
+                Span::styled("Reorder  ", gray),
+                Span::raw(format!("{} of {}", summary.out_of_order, summary.received)),
+            ]),
+        ],
+    };
+    Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("UDP via TURN"))
+}
+
+/// Vertical marker lines for Wi-Fi events that fall inside a chart's x range
+fn event_markers(marks: &[f64], x_min: f64, x_max: f64, y_max: f64) -> Vec<[(f64, f64); 2]> {
+    marks
+        .iter()
+        .filter(|t| **t >= x_min && **t <= x_max)
+        .map(|t| [(*t, 0.0), (*t, y_max)])
+        .collect()
+}
+
+/// Which thirds of a phase spanning `x_min..=x_max` saw a Wi-Fi event, for marking the
+/// early/mid/late latency plots
+fn event_thirds(marks: &[f64], x_min: f64, x_max: f64) -> [bool; 3] {
+    let mut thirds = [false; 3];
+    if x_max > x_min {
+        for t in marks.iter().filter(|t| **t >= x_min && **t <= x_max) {
+            let i = ((t - x_min) / (x_max - x_min) * 3.0) as usize;
+            thirds[i.min(2)] = true;
+        }
+    }
+    thirds
+}
+
+fn marker_datasets(markers: &[[(f64, f64); 2]]) -> Vec<Dataset<'_>> {
+    markers
+        .iter()
+        .map(|m| {
+            Dataset::default()
+                .graph_type(GraphType::Line)
+                .marker(symbols::Marker::Braille)
+                .style(Style::default().fg(Color::Yellow))
+                .data(m)
+        })
+        .collect()
+}
+
+/// Line colors of the per-connection throughput charts
+const STREAM_COLORS: [Color; 6] = [
+    Color::Green,
+    Color::Cyan,
+    Color::LightMagenta,
+    Color::LightBlue,
+    Color::LightRed,
+    Color::White,
+];
+
+/// The throughput chart's lines and their peak: one line per connection when toggled
+/// with `t`, otherwise the total in `color`.
+fn throughput_datasets<'a>(
+    state: &UiState,
+    total: &'a [(f64, f64)],
+    streams: &'a [Vec<(f64, f64)>],
+    color: Color,
+) -> (Vec<Dataset<'a>>, f64) {
+    if !state.show_streams || streams.is_empty() {
+        let ds = Dataset::default()
+            .graph_type(GraphType::Line)
+            .marker(symbols::Marker::Braille)
+            .style(Style::default().fg(color))
+            .data(total);
+        return (vec![ds], max_y(total));
+    }
+    let peak = streams.iter().map(|s| max_y(s)).fold(0.0, f64::max);
+    let datasets = streams
+        .iter()
+        .enumerate()
+        .map(|(i, points)| {
+            Dataset::default()
+                .graph_type(GraphType::Line)
+                .marker(symbols::Marker::Braille)
+                .style(Style::default().fg(STREAM_COLORS[i % STREAM_COLORS.len()]))
+                .data(points)
+        })
+        .collect();
+    (datasets, peak)
+}
+
+/// Chart title note while per-connection lines are shown
+fn streams_suffix(state: &UiState, streams: &[Vec<(f64, f64)>]) -> String {
+    if state.show_streams && !streams.is_empty() {
+        format!(" - {} connections", streams.len())
+    } else {
+        String::new()
+    }
+}
+
 fn udp_split_bar(sent: u64, received: u64, width: usize) -> Line<'static> {
     let safe_sent = sent.max(1);
     let safe_received = received.min(safe_sent);
     let lost = safe_sent.saturating_sub(safe_received);
     // Ensure any loss shows at least one red segment
     let lost_units = if lost > 0 {
-        (width as f64 * lost as f64 / safe_sent as f64).ceil().max(1.0) as usize
+        (width as f64 * lost as f64 / safe_sent as f64)
+            .ceil()
+            .max(1.0) as usize
     } else {
         0
     };
@@ -37,10 +207,54 @@ fn udp_split_bar(sent: u64, received: u64, width: usize) -> Line<'static> {
         Span::styled(ok_part, Style::default().fg(Color::Green)),
         Span::styled(lost_part, Style::default().fg(Color::Red)),
         Span::raw("] "),
-        Span::styled(format!("ok {} lost {}", safe_received, lost), Style::default().fg(Color::Gray)),
+        Span::styled(
+            format!("ok {} lost {}", safe_received, lost),
+            Style::default().fg(Color::Gray),
+        ),
     ])
 }
 
+/// "Wireless", or "Wired" with the link speed when known
+fn interface_kind(state: &UiState) -> String {
+    if state.is_wireless.unwrap_or(false) {
+        return "Wireless".to_string();
+    }
+    match state.link_speed_mbps {
+        Some(speed) if speed >= 1000 && speed % 1000 == 0 => {
+            format!("Wired, {} Gbit/s", speed / 1000)
+        }
+        Some(speed) => format!("Wired, {} Mbit/s", speed),
+        None => "Wired".to_string(),
+    }
+}
+
+/// "via VPN" after the interface when the test runs through a tunnel
+fn vpn_badge(state: &UiState) -> Span<'static> {
+    if state.vpn_detected {
+        Span::styled(" via VPN", Style::default().fg(Color::Magenta))
+    } else {
+        Span::raw("")
+    }
+}
+
+pub fn aim_rating_color(rating: AimRating) -> Color {
+    match rating {
+        AimRating::Great | AimRating::Good => Color::Green,
+        AimRating::Average => Color::Yellow,
+        AimRating::Poor => Color::Magenta,
+        AimRating::Bad => Color::Red,
+    }
+}
+
+fn bufferbloat_grade_color(grade: BufferbloatGrade) -> Color {
+    match grade {
+        BufferbloatGrade::APlus | BufferbloatGrade::A => Color::Green,
+        BufferbloatGrade::B => Color::Yellow,
+        BufferbloatGrade::C | BufferbloatGrade::D => Color::Magenta,
+        BufferbloatGrade::F => Color::Red,
+    }
+}
+
 /// Get color for quality label based on loss severity
 fn quality_label_color(label: &str) -> Color {
     match label {
@@ -52,10 +266,73 @@ fn quality_label_color(label: &str) -> Color {
     }
 }
 
+/// Number of stored runs shown in the status-bar download trend
+const TREND_RUNS: usize = 20;
+
+/// Change against the average (in percent) highlighted in the download trend
+const TREND_CHANGE_PCT: f64 = 10.0;
+
+/// Text sparkline of the last stored download results on the current network,
+/// oldest first, with the latest value and its change against the average.
+fn download_trend_line(state: &UiState) -> Option<Line<'static>> {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let network = state
+        .network_name
+        .as_deref()
+        .or(state.interface_name.as_deref())?;
+    // History is most recent first
+    let mut values: Vec<f64> = state
+        .history
+        .iter()
+        .filter(|r| {
+            r.network_name.as_deref().or(r.interface_name.as_deref()) == Some(network)
+                && r.download.mbps > 0.0
+        })
+        .take(TREND_RUNS)
+        .map(|r| r.download.mbps)
+        .collect();
+    if values.len() < 2 {
+        return None;
+    }
+    values.reverse();
+
+    let max = values.iter().cloned().fold(0.0_f64, f64::max);
+    let spark: String = values
+        .iter()
+        .map(|v| BARS[((v / max) * (BARS.len() - 1) as f64).round() as usize])
+        .collect();
+    let latest = *values.last().unwrap();
+    let avg = values.iter().sum::<f64>() / values.len() as f64;
+    let change = (latest - avg) / avg * 100.0;
+    let change_color = if change <= -TREND_CHANGE_PCT {
+        Color::Red
+    } else if change >= TREND_CHANGE_PCT {
+        Color::Green
+    } else {
+        Color::Gray
+    };
+
+    Some(Line::from(vec![
+        Span::styled(
+            format!("DL trend ({} runs): ", values.len()),
+            Style::default().fg(Color::Gray),
+        ),
+        Span::styled(spark, Style::default().fg(Color::Cyan)),
+        Span::raw(format!("  {:.1} Mbps ", latest)),
+        Span::styled(
+            format!("({:+.0}% vs avg {:.1})", change, avg),
+            Style::default().fg(change_color),
+        ),
+    ]))
+}
+
 pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
     // Small terminal: keep the compact dashboard (gauges + sparklines).
     // Large terminal: show full charts (like the website) alongside the live cards.
-    if area.height < 28 {
+    // The cutoff went from 28 to 33 rows with the 5-row early/mid/late panel, so the
+    // panels below it keep the room they had.
+    if area.height < 33 {
         return draw_dashboard_compact(area, f, state);
     }
 
@@ -65,9 +342,10 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
             [
                 Constraint::Length(13), // Throughput charts row with metrics (side-by-side)
                 Constraint::Length(10), // Latency box plots with metrics below (idle + loaded DL + loaded UL)
+                Constraint::Length(5),  // Loaded latency early/mid/late box plots (DL + UL)
                 Constraint::Length(3),  // Packet loss (UDP) row
                 Constraint::Min(0),     // Network Information + Keyboard Shortcuts (side-by-side)
-                Constraint::Length(5),  // Status row (full width at bottom)
+                Constraint::Length(6),  // Status row (full width at bottom)
             ]
             .as_ref(),
         )
@@ -85,15 +363,17 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
         let dl_x_max = state.dl_points.last().map(|(x, _)| *x).unwrap_or(0.0);
         let dl_x_min = state.dl_points.first().map(|(x, _)| *x).unwrap_or(0.0);
 
-        let y_dl_max = max_y(&state.dl_points).max(10.0);
-        let y_dl_max = (y_dl_max * 1.10).min(10_000.0);
-
         // Use all download points (they're already filtered to download phase)
-        let dl_ds = Dataset::default()
-            .graph_type(GraphType::Line)
-            .marker(symbols::Marker::Braille)
-            .style(Style::default().fg(Color::Green))
-            .data(&state.dl_points);
+        let (mut dl_datasets, dl_peak) = throughput_datasets(
+            state,
+            &state.dl_points,
+            &state.dl_stream_points,
+            Color::Green,
+        );
+        let y_dl_max = dl_peak.max(10.0);
+        let y_dl_max = (y_dl_max * 1.10).min(10_000.0);
+        let dl_markers = event_markers(&state.wireless_marks, dl_x_min, dl_x_max, y_dl_max);
+        dl_datasets.extend(marker_datasets(&dl_markers));
 
         let dl_values: Vec<f64> = state.dl_points.iter().map(|(_, y)| *y).collect();
         let dl_metrics = crate::metrics::compute_metrics(&dl_values);
@@ -110,13 +390,14 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
             Span::raw(" / avg "),
             Span::styled(format!("{:.0}", dl_avg), Style::default().fg(Color::Green)),
             Span::raw(" Mbps)"),
+            Span::raw(streams_suffix(state, &state.dl_stream_points)),
         ]);
         charts::render_chart_with_metrics_inside(
             f,
             thr_row[0],
-            vec![dl_ds],
-            Axis::default().bounds([dl_x_min, dl_x_max.max(1.0)]),
-            Axis::default().title("Mbps").bounds([0.0, y_dl_max]),
+            Chart::new(dl_datasets)
+                .x_axis(Axis::default().bounds([dl_x_min, dl_x_max.max(1.0)]))
+                .y_axis(Axis::default().title("Mbps").bounds([0.0, y_dl_max])),
             dl_title,
             dl_metrics,
             Color::Green,
@@ -149,15 +430,17 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
         let ul_x_max = state.ul_points.last().map(|(x, _)| *x).unwrap_or(0.0);
         let ul_x_min = state.ul_points.first().map(|(x, _)| *x).unwrap_or(0.0);
 
-        let y_ul_max = max_y(&state.ul_points).max(10.0);
-        let y_ul_max = (y_ul_max * 1.10).min(10_000.0);
-
         // Use all upload points (they're already filtered to upload phase)
-        let ul_ds = Dataset::default()
-            .graph_type(GraphType::Line)
-            .marker(symbols::Marker::Braille)
-            .style(Style::default().fg(Color::Cyan))
-            .data(&state.ul_points);
+        let (mut ul_datasets, ul_peak) = throughput_datasets(
+            state,
+            &state.ul_points,
+            &state.ul_stream_points,
+            Color::Cyan,
+        );
+        let y_ul_max = ul_peak.max(10.0);
+        let y_ul_max = (y_ul_max * 1.10).min(10_000.0);
+        let ul_markers = event_markers(&state.wireless_marks, ul_x_min, ul_x_max, y_ul_max);
+        ul_datasets.extend(marker_datasets(&ul_markers));
 
         let ul_values: Vec<f64> = state.ul_points.iter().map(|(_, y)| *y).collect();
         let ul_metrics = crate::metrics::compute_metrics(&ul_values);
@@ -174,13 +457,14 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
             Span::raw(" / avg "),
             Span::styled(format!("{:.0}", ul_avg), Style::default().fg(Color::Cyan)),
             Span::raw(" Mbps)"),
+            Span::raw(streams_suffix(state, &state.ul_stream_points)),
         ]);
         charts::render_chart_with_metrics_inside(
             f,
             thr_row[1],
-            vec![ul_ds],
-            Axis::default().bounds([ul_x_min, ul_x_max.max(1.0)]),
-            Axis::default().title("Mbps").bounds([0.0, y_ul_max]),
+            Chart::new(ul_datasets)
+                .x_axis(Axis::default().bounds([ul_x_min, ul_x_max.max(1.0)]))
+                .y_axis(Axis::default().title("Mbps").bounds([0.0, y_ul_max])),
             ul_title,
             ul_metrics,
             Color::Cyan,
@@ -227,7 +511,21 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
             .map(|(_, med, _, _)| med)
             .unwrap_or(f64::NAN);
         let jitter = crate::metrics::compute_jitter(&state.idle_latency_samples);
-        let title = Line::from(format!("Idle Latency ({:.0}ms)", median));
+        let mut title = Line::from(format!("Idle Latency ({:.0}ms)", median));
+        if let Some(ttfb) = state.ttfb.as_ref().and_then(|t| t.median_ms) {
+            title.spans.push(Span::raw(format!(" TTFB {:.0}ms", ttfb)));
+        }
+        if let Some(bloat) = state
+            .last_result
+            .as_ref()
+            .and_then(|r| r.bufferbloat.as_ref())
+        {
+            title.spans.push(Span::raw(" Bufferbloat "));
+            title.spans.push(Span::styled(
+                bloat.grade.label(),
+                Style::default().fg(bufferbloat_grade_color(bloat.grade)),
+            ));
+        }
         charts::render_box_plot_with_metrics_inside(
             f,
             lat_row[0],
@@ -306,6 +604,32 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
         f.render_widget(empty, lat_row[2]);
     }
 
+    // Loaded latency over time: early/mid/late thirds of each throughput phase
+    let seg_row = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .split(main[2]);
+    let phase_events = |points: &[(f64, f64)]| match (points.first(), points.last()) {
+        (Some((x_min, _)), Some((x_max, _))) => event_thirds(&state.wireless_marks, *x_min, *x_max),
+        _ => [false; 3],
+    };
+    charts::render_segment_box_plots(
+        f,
+        seg_row[0],
+        state.loaded_dl_segments.as_ref(),
+        phase_events(&state.dl_points),
+        Line::from("Download Latency Over Time"),
+        Color::Green,
+    );
+    charts::render_segment_box_plots(
+        f,
+        seg_row[1],
+        state.loaded_ul_segments.as_ref(),
+        phase_events(&state.ul_points),
+        Line::from("Upload Latency Over Time"),
+        Color::Cyan,
+    );
+
     // Packet loss row (full width) with live progress during measurement
     let (udp_sent, udp_received, udp_total, udp_latest_rtt) = if state.udp_loss_total > 0 {
         (
@@ -343,17 +667,35 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
     let udp_block = Block::default()
         .borders(Borders::ALL)
         .title("Packet Loss (UDP/TURN)");
-    let udp_inner = udp_block.inner(main[2]);
-    f.render_widget(udp_block, main[2]);
+    let udp_inner = udp_block.inner(main[3]);
+    f.render_widget(udp_block, main[3]);
 
-    if let Some(err) = state
+    if let Some(reason) = state
+        .last_result
+        .as_ref()
+        .and_then(|r| r.udp_skipped.as_ref())
+    {
+        f.render_widget(
+            Paragraph::new(Line::from(vec![
+                Span::styled(
+                    "Packet loss probe skipped: ",
+                    Style::default().fg(Color::Gray),
+                ),
+                Span::styled(reason.as_str(), Style::default().fg(Color::Yellow)),
+            ])),
+            udp_inner,
+        );
+    } else if let Some(err) = state
         .last_result
         .as_ref()
         .and_then(|r| r.udp_error.as_ref())
     {
         f.render_widget(
             Paragraph::new(Line::from(vec![
-                Span::styled("Packet loss probe failed: ", Style::default().fg(Color::Gray)),
+                Span::styled(
+                    "Packet loss probe failed: ",
+                    Style::default().fg(Color::Gray),
+                ),
                 Span::styled(err.as_str(), Style::default().fg(Color::Yellow)),
             ])),
             udp_inner,
@@ -376,7 +718,11 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
             .map(|exp| {
                 let label = exp.quality_label.as_str();
                 let mos = exp.mos.map(|m| format!("MOS {:.1}", m)).unwrap_or_default();
-                let jitter = exp.latency.jitter_ms.map(|j| format!("jitter {:.1}ms", j)).unwrap_or_default();
+                let jitter = exp
+                    .latency
+                    .jitter_ms
+                    .map(|j| format!("jitter {:.1}ms", j))
+                    .unwrap_or_default();
                 let reorder = format!("reorder {:.1}%", exp.out_of_order_pct);
                 (label, mos, jitter, reorder)
             })
@@ -423,11 +769,14 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
 
         // Ensure any loss shows at least one red segment
         let lost_units = if lost > 0 {
-            ((lost as f64 / safe_total as f64) * bar_width as f64).ceil().max(1.0) as usize
+            ((lost as f64 / safe_total as f64) * bar_width as f64)
+                .ceil()
+                .max(1.0) as usize
         } else {
             0
         };
-        let recv_units = ((safe_received as f64 / safe_total as f64) * bar_width as f64).floor() as usize;
+        let recv_units =
+            ((safe_received as f64 / safe_total as f64) * bar_width as f64).floor() as usize;
         let pending_units = bar_width.saturating_sub(recv_units + lost_units);
 
         let bar_recv = "█".repeat(recv_units);
@@ -442,7 +791,10 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
         // Show quality label and MOS when test is complete
         if !quality_label.is_empty() {
             let label_color = quality_label_color(quality_label);
-            spans.push(Span::styled(quality_label, Style::default().fg(label_color)));
+            spans.push(Span::styled(
+                quality_label,
+                Style::default().fg(label_color),
+            ));
             if !mos_str.is_empty() {
                 spans.push(Span::raw(" ("));
                 spans.push(Span::styled(&mos_str, Style::default().fg(label_color)));
@@ -455,7 +807,13 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
         spans.extend(vec![
             Span::styled(
                 loss_str,
-                Style::default().fg(if udp_loss_pct == 0.0 { Color::Green } else if udp_loss_pct < 2.5 { Color::Yellow } else { Color::Red }),
+                Style::default().fg(if udp_loss_pct == 0.0 {
+                    Color::Green
+                } else if udp_loss_pct < 2.5 {
+                    Color::Yellow
+                } else {
+                    Color::Red
+                }),
             ),
             Span::raw(" "),
             Span::styled(rtt_display, Style::default().fg(Color::Gray)),
@@ -483,13 +841,13 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
         ]);
 
         if pending > 0 {
-            spans.push(Span::styled(format!(" pending {}", pending), Style::default().fg(Color::DarkGray)));
+            spans.push(Span::styled(
+                format!(" pending {}", pending),
+                Style::default().fg(Color::DarkGray),
+            ));
         }
 
-        f.render_widget(
-            Paragraph::new(Line::from(spans)),
-            udp_inner,
-        );
+        f.render_widget(Paragraph::new(Line::from(spans)), udp_inner);
     } else {
         f.render_widget(
             Paragraph::new("Packet loss probe starts after upload phase..."),
@@ -497,11 +855,23 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
         );
     }
 
-    // Network Information and Keyboard Shortcuts side-by-side
+    // Network Information and Keyboard Shortcuts side-by-side, with the protocol
+    // comparison and UDP throughput in between once they have results
+    let middle_panel = !state.protocol_results.is_empty() || state.udp_throughput.is_some();
+    let info_constraints: &[Constraint] = if !middle_panel {
+        &[Constraint::Percentage(60), Constraint::Percentage(40)]
+    } else {
+        &[
+            Constraint::Percentage(45),
+            Constraint::Percentage(25),
+            Constraint::Percentage(30),
+        ]
+    };
     let info_row = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
-        .split(main[3]);
+        .constraints(info_constraints)
+        .split(main[4]);
+    let shortcuts_area = info_row[info_row.len() - 1];
 
     // Network Information panel (left)
 
@@ -520,13 +890,8 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
         Line::from(vec![
             Span::styled("Interface: ", Style::default().fg(Color::Gray)),
             Span::raw(state.interface_name.as_deref().unwrap_or("-")),
-            Span::raw(" ("),
-            Span::raw(if state.is_wireless.unwrap_or(false) {
-                "Wireless"
-            } else {
-                "Wired"
-            }),
-            Span::raw(")"),
+            Span::raw(format!(" ({})", interface_kind(state))),
+            vpn_badge(state),
         ]),
         Line::from(vec![
             Span::styled("Network: ", Style::default().fg(Color::Gray)),
@@ -534,7 +899,7 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
                 state
                     .network_name
                     .as_deref()
-                    .or_else(|| state.interface_name.as_deref())
+                    .or(state.interface_name.as_deref())
                     .unwrap_or("-"),
             ),
         ]),
@@ -544,6 +909,20 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
         ]),
     ];
 
+    // Weak signal explains many bad wireless results
+    if let Some(ref link) = state.wireless_link {
+        let color = match link.signal_dbm {
+            Some(dbm) if dbm >= -60 => Color::Green,
+            Some(dbm) if dbm >= -70 => Color::Yellow,
+            Some(_) => Color::Red,
+            None => Color::White,
+        };
+        network_lines.push(Line::from(vec![
+            Span::styled("Wi-Fi: ", Style::default().fg(Color::Gray)),
+            Span::styled(link.describe(), Style::default().fg(color)),
+        ]));
+    }
+
     // Only show Certificate line if a certificate is set
     if let Some(ref cert_filename) = state.certificate_filename {
         network_lines.push(Line::from(vec![
@@ -560,6 +939,68 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
         ]));
     }
 
+    // HTTP version per phase; HTTP/1.x (typically a proxy downgrade) is highlighted
+    if !state.protocols.is_empty() {
+        let mut spans = vec![Span::styled("Protocol: ", Style::default().fg(Color::Gray))];
+        for (i, p) in state.protocols.iter().enumerate() {
+            if i > 0 {
+                spans.push(Span::raw(" | "));
+            }
+            let label = match p.phase {
+                crate::model::Phase::IdleLatency => "Idle",
+                crate::model::Phase::Download => "DL",
+                crate::model::Phase::Upload => "UL",
+                _ => "-",
+            };
+            let color = if p.is_http1() {
+                Color::Yellow
+            } else {
+                Color::White
+            };
+            spans.push(Span::raw(format!("{} ", label)));
+            spans.push(Span::styled(
+                p.http_version().unwrap_or("-").to_string(),
+                Style::default().fg(color),
+            ));
+        }
+        network_lines.push(Line::from(spans));
+        if let Some(p) = state.protocols.last() {
+            if let Some(tls) = &p.tls_version {
+                network_lines.push(Line::from(vec![
+                    Span::styled("TLS: ", Style::default().fg(Color::Gray)),
+                    Span::raw(format!(
+                        "{} {}",
+                        tls,
+                        p.cipher_suite.as_deref().unwrap_or("")
+                    )),
+                ]));
+            }
+        }
+    }
+
+    // AIM scores of the finished run
+    if let Some(aim) = state.last_result.as_ref().and_then(|r| r.aim.as_ref()) {
+        let mut spans = vec![Span::styled("Good for: ", Style::default().fg(Color::Gray))];
+        for (i, (name, rating)) in [
+            ("Streaming", aim.streaming),
+            ("Gaming", aim.gaming),
+            ("Video calls", aim.rtc),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            if i > 0 {
+                spans.push(Span::raw(" | "));
+            }
+            spans.push(Span::raw(format!("{} ", name)));
+            spans.push(Span::styled(
+                rating.label(),
+                Style::default().fg(aim_rating_color(rating)),
+            ));
+        }
+        network_lines.push(Line::from(spans));
+    }
+
     network_lines.extend(vec![
         Line::from(vec![
             Span::styled("Server location: ", Style::default().fg(Color::Gray)),
@@ -587,13 +1028,26 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
             Span::styled("External IPv6: ", Style::default().fg(Color::Gray)),
             Span::raw(state.external_ipv6.as_deref().unwrap_or("-")),
         ]),
+        Line::from(vec![
+            Span::styled("NAT: ", Style::default().fg(Color::Gray)),
+            Span::raw(
+                state
+                    .last_result
+                    .as_ref()
+                    .and_then(|r| r.nat_type)
+                    .map_or("-", |nat| nat.label()),
+            ),
+        ]),
     ]);
 
     // Diagnostic results at the end, before the source link
     let has_diagnostics = state.dns_summary.is_some()
         || state.tls_summary.is_some()
+        || !state.request_timings.is_empty()
         || state.ip_comparison.is_some()
-        || state.traceroute_summary.is_some();
+        || !state.ip_comparison_rates.is_empty()
+        || state.traceroute_summary.is_some()
+        || state.mtu_summary.is_some();
 
     if has_diagnostics {
         network_lines.push(Line::from("")); // Separator
@@ -601,8 +1055,34 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
         if let Some(ref dns) = state.dns_summary {
             network_lines.push(Line::from(vec![
                 Span::styled("DNS resolution: ", Style::default().fg(Color::Gray)),
-                Span::raw(format!("{:.2}ms", dns.resolution_time_ms)),
+                Span::raw(match dns.resolver.as_deref() {
+                    Some(resolver) => format!("{:.2}ms via {}", dns.resolution_time_ms, resolver),
+                    None => format!("{:.2}ms", dns.resolution_time_ms),
+                }),
             ]));
+            if let Some(ref encrypted) = dns.encrypted {
+                network_lines.push(Line::from(vec![
+                    Span::styled(
+                        format!("{} resolution: ", encrypted.protocol),
+                        Style::default().fg(Color::Gray),
+                    ),
+                    Span::raw(format!(
+                        "{:.2}ms via {}",
+                        encrypted.resolution_time_ms, encrypted.resolver
+                    )),
+                ]));
+            }
+            if dns.dnssec_detail.is_some() {
+                let (status, color) = match dns.dnssec_validating {
+                    Some(true) => ("validated", Color::Green),
+                    Some(false) => ("not validated", Color::Yellow),
+                    None => ("unknown", Color::Gray),
+                };
+                network_lines.push(Line::from(vec![
+                    Span::styled("DNSSEC: ", Style::default().fg(Color::Gray)),
+                    Span::styled(status, Style::default().fg(color)),
+                ]));
+            }
         }
 
         if let Some(ref tls) = state.tls_summary {
@@ -616,40 +1096,74 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
             ]));
         }
 
+        for timing in &state.request_timings {
+            network_lines.push(Line::from(vec![
+                Span::styled(
+                    format!("Timing ({:?}): ", timing.phase),
+                    Style::default().fg(Color::Gray),
+                ),
+                Span::raw(timing.breakdown()),
+            ]));
+        }
+
         if let Some(ref cmp) = state.ip_comparison {
-            let v4_str = cmp
-                .ipv4_result
-                .as_ref()
-                .map(|r| {
-                    if r.available {
-                        format!("{:.1}Mbps", r.download_mbps)
-                    } else {
-                        "N/A".to_string()
-                    }
-                })
-                .unwrap_or_else(|| "-".to_string());
-            let v6_str = cmp
-                .ipv6_result
-                .as_ref()
-                .map(|r| {
-                    if r.available {
-                        format!("{:.1}Mbps", r.download_mbps)
-                    } else {
-                        "N/A".to_string()
-                    }
-                })
-                .unwrap_or_else(|| "-".to_string());
+            let summary = crate::comparison::summarize(&crate::comparison::from_ip_versions(cmp));
+            // Drop the "IPv4 vs IPv6: " prefix, it is already the label
+            let verdict = summary.split_once(": ").map(|(_, v)| v).unwrap_or(&summary);
             network_lines.push(Line::from(vec![
                 Span::styled("IPv4 vs IPv6: ", Style::default().fg(Color::Gray)),
-                Span::raw(format!("v4:{} v6:{}", v4_str, v6_str)),
+                Span::raw(verdict.to_string()),
+            ]));
+        } else if !state.ip_comparison_rates.is_empty() {
+            // Still running: the latest rate of each family's download and upload
+            let mut families: Vec<(&str, Vec<String>)> = Vec::new();
+            for rate in &state.ip_comparison_rates {
+                let arrow = match rate.phase {
+                    crate::model::Phase::Upload => "↑",
+                    _ => "↓",
+                };
+                let text = format!("{}{:.0}", arrow, rate.mbps);
+                match families.iter_mut().find(|(f, _)| *f == rate.family) {
+                    Some((_, rates)) => rates.push(text),
+                    None => families.push((&rate.family, vec![text])),
+                }
+            }
+            let families: Vec<String> = families
+                .iter()
+                .map(|(family, rates)| format!("{} {}", family, rates.join(" ")))
+                .collect();
+            network_lines.push(Line::from(vec![
+                Span::styled("IPv4 vs IPv6: ", Style::default().fg(Color::Gray)),
+                Span::raw(format!("{} Mbps…", families.join(", "))),
             ]));
         }
 
         if let Some(ref tr) = state.traceroute_summary {
             let status = if tr.completed { "complete" } else { "partial" };
+            let mut traceroute = format!("{} hops ({})", tr.hops.len(), status);
+            if !tr.paths.is_empty() {
+                let split = tr.ecmp_hops();
+                traceroute.push_str(&format!(", {} flows", tr.paths.len() + 1));
+                if let (Some(first), Some(last)) = (split.first(), split.last()) {
+                    traceroute.push_str(&format!(" split at hops {}-{}", first, last));
+                }
+            }
             network_lines.push(Line::from(vec![
                 Span::styled("Traceroute: ", Style::default().fg(Color::Gray)),
-                Span::raw(format!("{} hops ({})", tr.hops.len(), status)),
+                Span::raw(traceroute),
+            ]));
+            if let Some(path) = tr.as_path() {
+                network_lines.push(Line::from(vec![
+                    Span::styled("AS path: ", Style::default().fg(Color::Gray)),
+                    Span::raw(path),
+                ]));
+            }
+        }
+
+        if let Some(ref mtu) = state.mtu_summary {
+            network_lines.push(Line::from(vec![
+                Span::styled("Path MTU: ", Style::default().fg(Color::Gray)),
+                Span::raw(mtu.describe()),
             ]));
         }
     }
@@ -699,6 +1213,21 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
             Span::styled("a", Style::default().fg(Color::Magenta)),
             Span::raw("     Toggle auto-save"),
         ]),
+        Line::from(vec![
+            Span::raw("  "),
+            Span::styled("n", Style::default().fg(Color::Magenta)),
+            Span::raw("     Toggle notifications"),
+        ]),
+        Line::from(vec![
+            Span::raw("  "),
+            Span::styled("c", Style::default().fg(Color::Magenta)),
+            Span::raw("     Edit comment"),
+        ]),
+        Line::from(vec![
+            Span::raw("  "),
+            Span::styled("t", Style::default().fg(Color::Magenta)),
+            Span::raw("     Per-connection charts"),
+        ]),
         Line::from(vec![
             Span::raw("  "),
             Span::styled("tab", Style::default().fg(Color::Magenta)),
@@ -716,7 +1245,21 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
             .borders(Borders::ALL)
             .title("Keyboard Shortcuts"),
     );
-    f.render_widget(shortcuts, info_row[1]);
+    f.render_widget(shortcuts, shortcuts_area);
+
+    match (&state.udp_throughput, state.protocol_results.is_empty()) {
+        (Some(udp), false) => {
+            let middle = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(0), Constraint::Length(5)])
+                .split(info_row[1]);
+            f.render_widget(protocols_panel(state), middle[0]);
+            f.render_widget(udp_throughput_panel(udp), middle[1]);
+        }
+        (Some(udp), true) => f.render_widget(udp_throughput_panel(udp), info_row[1]),
+        (None, false) => f.render_widget(protocols_panel(state), info_row[1]),
+        (None, true) => {}
+    }
 
     // Status panel (full width at bottom)
     let mut status_lines = vec![Line::from(vec![
@@ -735,11 +1278,42 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
                 Style::default().fg(Color::Red)
             },
         ),
+        Span::raw("   "),
+        Span::styled("Notify: ", Style::default().fg(Color::Gray)),
+        Span::styled(
+            if state.notify { "ON" } else { "OFF" },
+            if state.notify {
+                Style::default().fg(Color::Green)
+            } else {
+                Style::default().fg(Color::Gray)
+            },
+        ),
     ])];
+    if let Some(next) = state.next_scheduled_run {
+        status_lines[0].spans.extend([
+            Span::raw("   "),
+            Span::styled("Next run: ", Style::default().fg(Color::Gray)),
+            Span::raw(crate::schedule::format_time(next)),
+        ]);
+    }
 
-    // Custom comments (wrapping to fit status area)
-    if let Some(comments) = state.comments.as_deref() {
-        push_wrapped_status_kv(&mut status_lines, "Comments", comments, main[4].width);
+    // Download history on this network, for context right after a run
+    if let Some(trend) = download_trend_line(state) {
+        status_lines.push(trend);
+    }
+
+    // Custom comments (wrapping to fit status area), or the prompt while editing them
+    if let Some(input) = state.comment_input.as_deref() {
+        status_lines.push(Line::from(vec![
+            Span::styled("Comment: ", Style::default().fg(Color::Cyan)),
+            Span::styled(format!("{}_", input), Style::default().fg(Color::White)),
+            Span::styled(
+                "  (Enter to apply, Esc to cancel)",
+                Style::default().fg(Color::Gray),
+            ),
+        ]));
+    } else if let Some(comments) = state.comments.as_deref() {
+        push_wrapped_status_kv(&mut status_lines, "Comments", comments, main[5].width);
     }
 
     // Info line - split into two lines if it contains a saved path, with wrapping
@@ -752,7 +1326,7 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
 
             // Wrap the path to fit within available width
             // Account for borders (2 chars on each side)
-            let status_area_width = main[4].width.saturating_sub(4);
+            let status_area_width = main[5].width.saturating_sub(4);
             let label_width = label_text.chars().count() as u16;
             let path_chars: Vec<char> = path_str.chars().collect();
             let mut remaining = path_chars.as_slice();
@@ -802,7 +1376,7 @@ pub fn draw_dashboard(area: Rect, f: &mut Frame, state: &UiState) {
 
     let status =
         Paragraph::new(status_lines).block(Block::default().borders(Borders::ALL).title("Status"));
-    f.render_widget(status, main[4]);
+    f.render_widget(status, main[5]);
 }
 
 pub fn draw_dashboard_compact(area: Rect, f: &mut Frame, state: &UiState) {
@@ -928,13 +1502,8 @@ pub fn draw_dashboard_compact(area: Rect, f: &mut Frame, state: &UiState) {
         Line::from(vec![
             Span::styled("Interface: ", Style::default().fg(Color::Gray)),
             Span::raw(state.interface_name.as_deref().unwrap_or("-")),
-            Span::raw(" ("),
-            Span::raw(if state.is_wireless.unwrap_or(false) {
-                "Wireless"
-            } else {
-                "Wired"
-            }),
-            Span::raw(")"),
+            Span::raw(format!(" ({})", interface_kind(state))),
+            vpn_badge(state),
         ]),
         Line::from(vec![
             Span::styled("Network: ", Style::default().fg(Color::Gray)),
@@ -942,7 +1511,7 @@ pub fn draw_dashboard_compact(area: Rect, f: &mut Frame, state: &UiState) {
                 state
                     .network_name
                     .as_deref()
-                    .or_else(|| state.interface_name.as_deref())
+                    .or(state.interface_name.as_deref())
                     .unwrap_or("-"),
             ),
         ]),
@@ -990,6 +1559,9 @@ pub fn draw_dashboard_compact(area: Rect, f: &mut Frame, state: &UiState) {
     if let Some(ref tr) = state.traceroute_summary {
         diag_parts.push(format!("Hops:{}", tr.hops.len()));
     }
+    if let Some(ref mtu) = state.mtu_summary {
+        diag_parts.push(format!("MTU:{}", mtu.path_mtu));
+    }
     if !diag_parts.is_empty() {
         meta_lines.push(Line::from(vec![
             Span::styled("Diag: ", Style::default().fg(Color::Gray)),
@@ -1002,15 +1574,44 @@ pub fn draw_dashboard_compact(area: Rect, f: &mut Frame, state: &UiState) {
         .and_then(|r| r.experimental_udp.as_ref())
     {
         let label_color = quality_label_color(&exp.quality_label);
-        let mos_str = exp.mos.map(|m| format!(" MOS {:.1}", m)).unwrap_or_default();
+        let mos_str = exp
+            .mos
+            .map(|m| format!(" MOS {:.1}", m))
+            .unwrap_or_default();
         meta_lines.push(Line::from(vec![
             Span::styled("UDP: ", Style::default().fg(Color::Gray)),
             Span::styled(&exp.quality_label, Style::default().fg(label_color)),
             Span::styled(mos_str, Style::default().fg(label_color)),
-            Span::styled(format!(" loss {:.1}%", exp.latency.loss * 100.0), Style::default().fg(Color::Yellow)),
-            Span::styled(format!(" reorder {:.1}%", exp.out_of_order_pct), Style::default().fg(Color::Gray)),
+            Span::styled(
+                format!(" loss {:.1}%", exp.latency.loss * 100.0),
+                Style::default().fg(Color::Yellow),
+            ),
+            Span::styled(
+                format!(" reorder {:.1}%", exp.out_of_order_pct),
+                Style::default().fg(Color::Gray),
+            ),
         ]));
         meta_lines.push(udp_split_bar(exp.latency.sent, exp.latency.received, 12));
+        if let Some(relay) = &exp.relay {
+            let jitter = relay
+                .latency
+                .jitter_ms
+                .map(|j| format!(" jitter {:.1}ms", j))
+                .unwrap_or_default();
+            let rtt = relay
+                .latency
+                .median_ms
+                .map(|m| format!(" rtt {:.0}ms", m))
+                .unwrap_or_default();
+            meta_lines.push(Line::from(vec![
+                Span::styled("Relay: ", Style::default().fg(Color::Gray)),
+                Span::styled(
+                    format!("loss {:.1}%", relay.latency.loss * 100.0),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::styled(format!("{jitter}{rtt}"), Style::default().fg(Color::Gray)),
+            ]));
+        }
     }
 
     meta_lines.extend(vec![
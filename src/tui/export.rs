@@ -18,8 +18,11 @@ pub fn enrich_result_with_network_info(r: &RunResult, state: &UiState) -> RunRes
         network_name: state.network_name.clone(),
         is_wireless: state.is_wireless,
         interface_mac: state.interface_mac.clone(),
+        link_speed_mbps: state.link_speed_mbps,
         local_ipv4: state.local_ipv4.clone(),
         local_ipv6: state.local_ipv6.clone(),
+        vpn_detected: state.vpn_detected,
+        wireless_link: state.wireless_link.clone(),
     };
 
     // Use shared enrichment function
@@ -30,6 +33,10 @@ pub fn enrich_result_with_network_info(r: &RunResult, state: &UiState) -> RunRes
     enriched.colo = state.colo.clone();
     enriched.asn = state.asn.clone();
     enriched.as_org = state.as_org.clone();
+    enriched.nat_type = crate::network::detect_nat(&enriched);
+
+    // The comment can be edited in the dashboard until the run is saved
+    enriched.comments = state.comments.clone();
 
     // Server might already be set, but update from state if available
     if enriched.server.is_none() {
@@ -39,25 +46,29 @@ pub fn enrich_result_with_network_info(r: &RunResult, state: &UiState) -> RunRes
 }
 
 /// Save JSON to the default auto-save location.
-pub fn save_result_json(r: &RunResult, state: &UiState) -> Result<std::path::PathBuf> {
+pub fn save_result_json(r: &RunResult, state: &UiState) -> Result<crate::storage::SavedRun> {
     let enriched = enrich_result_with_network_info(r, state);
-    crate::storage::save_run(&enriched)
+    crate::storage::save_run(&enriched, state.compression)
 }
 
 /// Save result and update state.info with the saved path message.
 pub fn save_and_show_path(r: &RunResult, state: &mut UiState) {
     match save_result_json(r, state) {
-        Ok(path) => {
+        Ok(saved) => {
             // Update last_result to the enriched version that was saved
             // This ensures the path computation matches
             let enriched = enrich_result_with_network_info(r, state);
             state.last_result = Some(enriched);
+            let path = saved.path;
             // Verify file exists before showing path
             if path.exists() {
                 state.info = format!("Saved: {}", path.display());
             } else {
                 state.info = format!("Saved (verifying): {}", path.display());
             }
+            if let Some(w) = saved.warning {
+                state.info.push_str(&format!(" ({})", w));
+            }
         }
         Err(e) => {
             state.info = format!("Save failed: {e:#}");
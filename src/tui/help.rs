@@ -37,6 +37,26 @@ pub fn draw_help(area: Rect, f: &mut Frame) {
             Span::styled("a", Style::default().fg(Color::Magenta)),
             Span::raw("           Toggle auto-save"),
         ]),
+        Line::from(vec![
+            Span::raw("  "),
+            Span::styled("n", Style::default().fg(Color::Magenta)),
+            Span::raw("           Toggle desktop notifications when a run finishes"),
+        ]),
+        Line::from(vec![
+            Span::raw("  "),
+            Span::styled("c", Style::default().fg(Color::Magenta)),
+            Span::raw("           Edit comment (dashboard)"),
+        ]),
+        Line::from(vec![
+            Span::raw("  "),
+            Span::styled("t", Style::default().fg(Color::Magenta)),
+            Span::raw("           Per-connection throughput charts (dashboard)"),
+        ]),
+        Line::from(vec![
+            Span::raw("  "),
+            Span::styled("L", Style::default().fg(Color::Magenta)),
+            Span::raw("           Pick the Cloudflare colo for the next runs"),
+        ]),
         Line::from(vec![
             Span::raw("  "),
             Span::styled("tab", Style::default().fg(Color::Magenta)),
@@ -82,6 +102,13 @@ pub fn draw_help(area: Rect, f: &mut Frame) {
             Span::raw("           Refresh history"),
         ]),
         Line::from(""),
+        Line::from("Traceroute tab:"),
+        Line::from(vec![
+            Span::raw("  "),
+            Span::styled("r", Style::default().fg(Color::Magenta)),
+            Span::raw("           Rerun just the traceroute"),
+        ]),
+        Line::from(""),
         Line::from("Repository (update your tool or report issues here):"),
         Line::from(vec![
             Span::raw("  "),
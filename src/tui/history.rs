@@ -10,31 +10,37 @@ use ratatui::{
 
 use super::state::UiState;
 
+/// Whether a run matches the lowercased history filter; an empty filter matches all.
+/// Tags match as `key=value`, so `vpn=on` selects runs tagged with it, and runs over a
+/// detected VPN match "via VPN".
+fn matches_filter(r: &RunResult, filter_lower: &str) -> bool {
+    let matches_field = |opt: &Option<String>| {
+        opt.as_ref()
+            .map(|s| s.to_lowercase().contains(filter_lower))
+            .unwrap_or(false)
+    };
+    filter_lower.is_empty()
+        || matches_field(&r.network_name)
+        || matches_field(&r.interface_name)
+        || matches_field(&r.as_org)
+        || matches_field(&r.colo)
+        || matches_field(&r.comments)
+        || (r.vpn_detected && "via vpn".contains(filter_lower))
+        || r.tags
+            .iter()
+            .any(|(k, v)| format!("{}={}", k, v).to_lowercase().contains(filter_lower))
+}
+
 pub fn show_history(area: Rect, f: &mut Frame, state: &mut UiState) {
     let mut lines: Vec<Line> = Vec::new();
 
-    // Filter history based on filter text (case-insensitive search in network_name, interface_name, as_org, colo)
+    // Filter history based on filter text (case-insensitive search in network_name, interface_name, as_org, colo, comments, tags)
     let filter_lower = state.history_filter.to_lowercase();
-    let filtered_history: Vec<&RunResult> = if state.history_filter.is_empty() {
-        state.history.iter().collect()
-    } else {
-        state
-            .history
-            .iter()
-            .filter(|r| {
-                let matches_field = |opt: &Option<String>| {
-                    opt.as_ref()
-                        .map(|s| s.to_lowercase().contains(&filter_lower))
-                        .unwrap_or(false)
-                };
-                matches_field(&r.network_name)
-                    || matches_field(&r.interface_name)
-                    || matches_field(&r.as_org)
-                    || matches_field(&r.colo)
-                    || matches_field(&r.comments)
-            })
-            .collect()
-    };
+    let filtered_history: Vec<&RunResult> = state
+        .history
+        .iter()
+        .filter(|r| matches_filter(r, &filter_lower))
+        .collect();
 
     // Calculate how many items can fit in the available area
     // Subtract 4 for: controls line, filter line (optional), column headers, borders
@@ -49,7 +55,10 @@ pub fn show_history(area: Rect, f: &mut Frame, state: &mut UiState) {
     };
 
     // Build header line with controls
-    let mut header_spans = vec![Span::raw(format!("History ({}/{}", current_pos, total_count))];
+    let mut header_spans = vec![Span::raw(format!(
+        "History ({}/{}",
+        current_pos, total_count
+    ))];
     if !state.history_filter.is_empty() {
         header_spans.push(Span::styled(
             format!(" filtered from {}", state.history.len()),
@@ -181,6 +190,7 @@ pub fn show_history(area: Rect, f: &mut Frame, state: &mut UiState) {
         Span::styled("UL        ", Style::default().fg(Color::Cyan)), // 10 chars
         Span::styled("Ping      ", Style::default().fg(Color::Gray)), // 10 chars
         Span::styled("Loss     ", Style::default().fg(Color::Yellow)), // 9 chars
+        Span::styled("AIM   ", Style::default().fg(Color::Gray)), // 6 chars
         Span::styled("Interface    ", Style::default().fg(Color::Blue)), // 13 chars
         Span::styled("Network", Style::default().fg(Color::Magenta)),
     ]));
@@ -247,38 +257,30 @@ pub fn show_history(area: Rect, f: &mut Frame, state: &mut UiState) {
                                         time::PrimitiveDateTime::new(date, time).assume_utc();
 
                                     // Get local offset and convert
-                                    match time::UtcOffset::current_local_offset() {
-                                        Ok(local_offset) => {
-                                            let local_dt = utc_dt.to_offset(local_offset);
-                                            let local_date = local_dt.date();
-                                            let local_time = local_dt.time();
-                                            // Format offset as +HH:MM or -HH:MM
-                                            let offset_hours = local_offset.whole_hours();
-                                            let offset_minutes = local_offset.whole_minutes() % 60;
-                                            let offset_sign =
-                                                if offset_hours >= 0 { '+' } else { '-' };
-                                            let offset_str = format!(
-                                                "{}{:02}:{:02}",
-                                                offset_sign,
-                                                offset_hours.abs(),
-                                                offset_minutes.abs()
-                                            );
-                                            format!(
-                                                "{:04}-{:02}-{:02} {:02}:{:02}:{:02} {}",
-                                                local_date.year(),
-                                                local_date.month() as u8,
-                                                local_date.day(),
-                                                local_time.hour(),
-                                                local_time.minute(),
-                                                local_time.second(),
-                                                offset_str
-                                            )
-                                        }
-                                        Err(_) => {
-                                            // Fallback to UTC if local offset can't be determined
-                                            format!("{} {} UTC", date_part, time_part)
-                                        }
-                                    }
+                                    let local_offset = crate::schedule::local_offset_at(utc_dt);
+                                    let local_dt = utc_dt.to_offset(local_offset);
+                                    let local_date = local_dt.date();
+                                    let local_time = local_dt.time();
+                                    // Format offset as +HH:MM or -HH:MM
+                                    let offset_hours = local_offset.whole_hours();
+                                    let offset_minutes = local_offset.whole_minutes() % 60;
+                                    let offset_sign = if offset_hours >= 0 { '+' } else { '-' };
+                                    let offset_str = format!(
+                                        "{}{:02}:{:02}",
+                                        offset_sign,
+                                        offset_hours.abs(),
+                                        offset_minutes.abs()
+                                    );
+                                    format!(
+                                        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} {}",
+                                        local_date.year(),
+                                        local_date.month() as u8,
+                                        local_date.day(),
+                                        local_time.hour(),
+                                        local_time.minute(),
+                                        local_time.second(),
+                                        offset_str
+                                    )
                                 } else {
                                     format!("{} {} UTC", date_part, time_part)
                                 }
@@ -315,7 +317,7 @@ pub fn show_history(area: Rect, f: &mut Frame, state: &mut UiState) {
         let network = r
             .network_name
             .as_deref()
-            .or_else(|| r.interface_name.as_deref())
+            .or(r.interface_name.as_deref())
             .unwrap_or("-");
         let history_loss_text = r
             .experimental_udp
@@ -323,7 +325,26 @@ pub fn show_history(area: Rect, f: &mut Frame, state: &mut UiState) {
             .map(|u| format!("{:.1}%", u.latency.loss * 100.0))
             .unwrap_or_else(|| "-".to_string());
 
-        lines.push(Line::from(vec![
+        // Streaming / gaming / video call badges; older runs are scored on the fly
+        let aim = r.aim.clone().or_else(|| crate::aim::scores(r));
+        let aim_badges: Vec<Span> = match aim {
+            Some(aim) => [aim.streaming, aim.gaming, aim.rtc]
+                .into_iter()
+                .map(|rating| {
+                    Span::styled(
+                        "● ",
+                        if is_selected {
+                            style
+                        } else {
+                            Style::default().fg(super::dashboard::aim_rating_color(rating))
+                        },
+                    )
+                })
+                .collect(),
+            None => vec![Span::styled("-     ", style)],
+        };
+
+        let mut row = vec![
             Span::styled(
                 format!("{:<4}{}", line_num, if is_selected { ">" } else { " " }), // 5 chars total
                 if is_selected {
@@ -368,6 +389,9 @@ pub fn show_history(area: Rect, f: &mut Frame, state: &mut UiState) {
                     Style::default().fg(Color::Yellow)
                 },
             ),
+        ];
+        row.extend(aim_badges);
+        row.extend([
             Span::styled(
                 format!("{:<13}", interface), // 13 chars
                 if is_selected {
@@ -384,7 +408,28 @@ pub fn show_history(area: Rect, f: &mut Frame, state: &mut UiState) {
                     Style::default().fg(Color::Magenta)
                 },
             ),
-        ]));
+        ]);
+        if r.vpn_detected {
+            row.push(Span::styled(
+                " via VPN",
+                if is_selected {
+                    style
+                } else {
+                    Style::default().fg(Color::Magenta)
+                },
+            ));
+        }
+        if !r.tags.is_empty() {
+            row.push(Span::styled(
+                format!("  [{}]", r.tags_text()),
+                if is_selected {
+                    style
+                } else {
+                    Style::default().fg(Color::Gray)
+                },
+            ));
+        }
+        lines.push(Line::from(row));
     }
 
     if state.history.is_empty() {
@@ -441,19 +486,17 @@ pub fn show_history(area: Rect, f: &mut Frame, state: &mut UiState) {
                 break;
             } else {
                 // Need to split - find a good break point
-                let mut char_count = 0;
                 let mut last_sep_pos = None;
                 let mut break_pos = 0;
 
-                for (idx, ch) in remaining.char_indices() {
-                    if char_count >= line_width {
+                for (char_count, (idx, ch)) in remaining.char_indices().enumerate() {
+                    if char_count >= line_width as usize {
                         break;
                     }
                     if ch == '/' || ch == '\\' {
                         last_sep_pos = Some(idx);
                     }
                     break_pos = idx + ch.len_utf8();
-                    char_count += 1;
                 }
 
                 // Prefer breaking at path separator, otherwise break at line width
@@ -499,8 +542,8 @@ pub fn show_history(area: Rect, f: &mut Frame, state: &mut UiState) {
 
     // Render scrollbar on the right edge if there are more items than visible
     if total_count > max_items {
-        let mut scrollbar_state = ScrollbarState::new(total_count.saturating_sub(max_items))
-            .position(scroll_offset);
+        let mut scrollbar_state =
+            ScrollbarState::new(total_count.saturating_sub(max_items)).position(scroll_offset);
         f.render_stateful_widget(
             Scrollbar::new(ScrollbarOrientation::VerticalRight)
                 .begin_symbol(Some("↑"))
@@ -514,31 +557,50 @@ pub fn show_history(area: Rect, f: &mut Frame, state: &mut UiState) {
     }
 }
 
+/// The `--context` values and modem statistics of a run, one per line as in the text
+/// output, followed by a blank line when there are any
+fn context_lines(result: &RunResult) -> Vec<Line<'static>> {
+    let entry = |label: String, value: String| {
+        Line::from(vec![
+            Span::styled(label, Style::default().fg(Color::Cyan)),
+            Span::styled(value, Style::default().fg(Color::White)),
+        ])
+    };
+    let mut lines: Vec<Line> = result
+        .context
+        .iter()
+        .map(|(key, value)| entry(format!("Context {}: ", key), value.clone()))
+        .collect();
+    if let Some(modem) = &result.modem {
+        lines.extend(
+            modem
+                .metrics
+                .iter()
+                .map(|(name, value)| entry(format!("Modem {}: ", name), value.to_string())),
+        );
+        lines.extend(modem.errors.iter().map(|e| {
+            Line::from(Span::styled(
+                format!("Modem: {}", e),
+                Style::default().fg(Color::Red),
+            ))
+        }));
+    }
+    if !lines.is_empty() {
+        lines.push(Line::from(""));
+    }
+    lines
+}
+
 pub fn draw_history_detail(area: Rect, f: &mut Frame, state: &mut UiState) {
     let mut lines: Vec<Line> = Vec::new();
 
     // Get the filtered history to find the correct selected item
     let filter_lower = state.history_filter.to_lowercase();
-    let filtered_history: Vec<&RunResult> = if state.history_filter.is_empty() {
-        state.history.iter().collect()
-    } else {
-        state
-            .history
-            .iter()
-            .filter(|r| {
-                let matches_field = |opt: &Option<String>| {
-                    opt.as_ref()
-                        .map(|s| s.to_lowercase().contains(&filter_lower))
-                        .unwrap_or(false)
-                };
-                matches_field(&r.network_name)
-                    || matches_field(&r.interface_name)
-                    || matches_field(&r.as_org)
-                    || matches_field(&r.colo)
-                    || matches_field(&r.comments)
-            })
-            .collect()
-    };
+    let filtered_history: Vec<&RunResult> = state
+        .history
+        .iter()
+        .filter(|r| matches_filter(r, &filter_lower))
+        .collect();
 
     let effective_selected = state
         .history_selected
@@ -561,15 +623,19 @@ pub fn draw_history_detail(area: Rect, f: &mut Frame, state: &mut UiState) {
         lines.push(Line::from(""));
 
         // Serialize the result to pretty JSON
-        let json_str = serde_json::to_string_pretty(result).unwrap_or_else(|e| format!("Error serializing JSON: {}", e));
+        let json_str = serde_json::to_string_pretty(result)
+            .unwrap_or_else(|e| format!("Error serializing JSON: {}", e));
 
         // Split JSON into lines for display
         let json_lines: Vec<&str> = json_str.lines().collect();
         let total_lines = json_lines.len();
 
+        let context = context_lines(result);
+
         // Calculate available height for JSON content
         // Subtract: 2 borders + 4 header lines (title, blank, network/timestamp, blank)
-        let available_height = (area.height as usize).saturating_sub(6);
+        // + the context and modem lines
+        let available_height = (area.height as usize).saturating_sub(6 + context.len());
 
         // Clamp scroll offset and write back to state so it can't drift
         let max_scroll = total_lines.saturating_sub(available_height);
@@ -597,6 +663,7 @@ pub fn draw_history_detail(area: Rect, f: &mut Frame, state: &mut UiState) {
             Span::styled(scroll_info, Style::default().fg(Color::Gray)),
         ]));
         lines.push(Line::from(""));
+        lines.extend(context);
 
         // Add JSON lines with syntax highlighting
         for line in json_lines.iter().skip(scroll_offset).take(available_height) {
@@ -643,8 +710,7 @@ pub fn draw_history_detail(area: Rect, f: &mut Frame, state: &mut UiState) {
     if let Some((total_lines, available_height, scroll_offset)) = detail_scroll_info {
         if total_lines > available_height {
             let max_scroll = total_lines.saturating_sub(available_height);
-            let mut scrollbar_state = ScrollbarState::new(max_scroll)
-                .position(scroll_offset);
+            let mut scrollbar_state = ScrollbarState::new(max_scroll).position(scroll_offset);
             f.render_stateful_widget(
                 Scrollbar::new(ScrollbarOrientation::VerticalRight)
                     .begin_symbol(Some("↑"))
@@ -0,0 +1,77 @@
+use ratatui::{
+    layout::Rect,
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph},
+    Frame,
+};
+
+use super::state::ColoPicker;
+
+/// Draw the colo picker over `area`: automatic selection, then the colos nearest first
+/// with their distance and, for the nearest few, the probed latency.
+pub fn draw_colo_picker(area: Rect, f: &mut Frame, picker: &ColoPicker, current: Option<&str>) {
+    let width = area.width.min(76);
+    let height = area.height.min(24);
+    let popup = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+    let block = Block::default().borders(Borders::ALL).title(format!(
+        "Colo for the next runs (now: {}) - Enter: select, Esc: close",
+        current.unwrap_or("automatic")
+    ));
+    let inner = block.inner(popup);
+    f.render_widget(Clear, popup);
+    f.render_widget(block, popup);
+
+    let gray = Style::default().fg(Color::Gray);
+    if picker.loading {
+        let message = "Loading the colos and probing the nearest…";
+        f.render_widget(Paragraph::new(Line::styled(message, gray)), inner);
+        return;
+    }
+
+    let mut lines = vec![Line::from(Span::raw(
+        "Automatic (Cloudflare chooses, usually the nearest)",
+    ))];
+    for l in &picker.locations {
+        let distance = l
+            .distance_km
+            .map(|d| format!("{:.0} km", d))
+            .unwrap_or_default();
+        let mut spans = vec![
+            Span::raw(format!("{:<4} {:<24} {:<3} ", l.iata, l.city, l.country)),
+            Span::styled(format!("{:>9}", distance), gray),
+        ];
+        if let Some(ms) = l.latency_ms {
+            let color = if l.served_by.is_some() {
+                // Another colo answered, so the latency is not this one's
+                Color::DarkGray
+            } else {
+                Color::Cyan
+            };
+            spans.push(Span::styled(
+                format!("  {:.1} ms", ms),
+                Style::default().fg(color),
+            ));
+        }
+        if let Some(other) = &l.served_by {
+            spans.push(Span::styled(format!(" (answered by {})", other), gray));
+        }
+        lines.push(Line::from(spans));
+    }
+    let highlight = Style::default()
+        .fg(Color::Yellow)
+        .add_modifier(Modifier::REVERSED);
+    if let Some(line) = lines.get_mut(picker.selected) {
+        *line = line.clone().style(highlight);
+    }
+
+    // Keep the cursor in view
+    let rows = usize::from(inner.height);
+    let scroll = (picker.selected + 1).saturating_sub(rows);
+    f.render_widget(Paragraph::new(lines).scroll((scroll as u16, 0)), inner);
+}
@@ -3,13 +3,15 @@ mod dashboard;
 mod export;
 mod help;
 mod history;
+mod locations;
 mod state;
+mod traceroute;
 
 pub use state::UiState;
 
 use crate::cli::{build_config, Cli};
 use crate::engine::{EngineControl, TestEngine};
-use crate::model::{Phase, RunResult, TestEvent};
+use crate::model::{Phase, RunResult, TestEvent, TracerouteHop};
 use anyhow::{Context, Result};
 use crossterm::{
     event::{Event, EventStream, KeyCode, KeyEventKind, KeyModifiers},
@@ -26,17 +28,28 @@ use ratatui::{
     widgets::{Block, Borders, Tabs},
     Terminal,
 };
-use std::{io, time::Duration, time::Instant};
+use std::{io, sync::Arc, time::Duration, time::Instant};
 use tokio::sync::mpsc;
 
 use charts::draw_charts;
 use dashboard::draw_dashboard;
-use export::{copy_to_clipboard, enrich_result_with_network_info, export_result_csv, export_result_json, save_and_show_path};
+use export::{
+    copy_to_clipboard, enrich_result_with_network_info, export_result_csv, export_result_json,
+    save_and_show_path,
+};
 use help::draw_help;
-use history::{show_history, draw_history_detail};
-use state::update_available_networks;
+use history::{draw_history_detail, show_history};
+use locations::draw_colo_picker;
+use state::{update_available_networks, ColoPicker, IpComparisonRate};
+use traceroute::draw_traceroute;
+
+/// Colos the picker probes for latency, nearest first
+const PICKER_PROBES: usize = 5;
 
-pub async fn run(args: Cli) -> Result<()> {
+pub async fn run(mut args: Cli) -> Result<()> {
+    // Log lines would corrupt the alternate screen; keep them until it is left
+    crate::logging::start_capture();
+    let mut last_log_seq = 0;
     enable_raw_mode().context("enable raw mode")?;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen).ok();
@@ -56,7 +69,10 @@ pub async fn run(args: Cli) -> Result<()> {
     let mut state = UiState {
         phase: Phase::IdleLatency,
         auto_save: args.auto_save,
+        notify: args.notify,
+        compression: args.compress,
         comments: args.comments.clone(),
+        requested_colo: args.colo.clone(),
         ..Default::default()
     };
     state.initial_history_load_size = initial_load;
@@ -65,11 +81,14 @@ pub async fn run(args: Cli) -> Result<()> {
     update_available_networks(&mut state);
 
     // Gather network interface information using shared module
-    let network_info = crate::network::gather_network_info(&args);
+    let network_info = crate::network::gather_network_info(args.interface.as_deref());
     state.interface_name = network_info.interface_name.clone();
     state.network_name = network_info.network_name.clone();
     state.is_wireless = network_info.is_wireless;
+    state.vpn_detected = network_info.vpn_detected;
+    state.wireless_link = network_info.wireless_link.clone();
     state.interface_mac = network_info.interface_mac.clone();
+    state.link_speed_mbps = network_info.link_speed_mbps;
     state.local_ipv4 = network_info.local_ipv4.clone();
     state.local_ipv6 = network_info.local_ipv6.clone();
     state.certificate_filename = args
@@ -91,16 +110,43 @@ pub async fn run(args: Cli) -> Result<()> {
     let mut events = EventStream::new();
     let mut tick = tokio::time::interval(Duration::from_millis(100));
 
+    // Failures of the sinks to take a phase snapshot
+    let (phase_failures_tx, mut phase_failures_rx) = mpsc::unbounded_channel::<Vec<String>>();
+
     // Start first run if test_on_launch is enabled
     let mut run_ctx = if args.test_on_launch {
-        Some(start_run(&args).await?)
+        Some(start_run(&args, state.auto_save, &phase_failures_tx).await?)
     } else {
         None
     };
+    // Traceroute rerun on its own from the traceroute tab
+    let mut traceroute_rx: Option<mpsc::Receiver<TestEvent>> = None;
+    // Status lines of the sinks once a finished run is published
+    let (published_tx, mut published_rx) = mpsc::unbounded_channel::<Vec<String>>();
+    // Colos for the picker, loading in the background
+    let mut locations_rx: Option<mpsc::Receiver<Result<Vec<crate::locations::Location>>>> = None;
+
+    state.next_scheduled_run = args.schedule.as_ref().and_then(|s| s.next_from_now());
 
     let res = loop {
         tokio::select! {
             _ = tick.tick() => {
+                if let Some((seq, line)) = crate::logging::latest().filter(|(seq, _)| *seq != last_log_seq) {
+                    last_log_seq = seq;
+                    state.info = line;
+                }
+                if let (Some(schedule), Some(next)) = (&args.schedule, state.next_scheduled_run) {
+                    if crate::schedule::now_local() >= next {
+                        // A slot that comes up while a run is still going is skipped
+                        let running = run_ctx.as_ref().is_some_and(|ctx| ctx.handle.is_some());
+                        if !running {
+                            state.reset_for_run();
+                            state.info = "Scheduled run started".into();
+                            run_ctx = Some(start_run(&args, state.auto_save, &phase_failures_tx).await?);
+                        }
+                        state.next_scheduled_run = schedule.next_from_now();
+                    }
+                }
                 terminal.draw(|f| draw(f.area(), f, &mut state)).ok();
             }
             Some(status) = update_rx.recv() => {
@@ -140,6 +186,61 @@ pub async fn run(args: Cli) -> Result<()> {
                         continue;
                     }
 
+                    // Handle comment input (dashboard prompt opened with 'c')
+                    if let Some(input) = state.comment_input.as_mut() {
+                        match k.code {
+                            KeyCode::Esc => state.comment_input = None,
+                            KeyCode::Enter => {
+                                let text = state.comment_input.take().unwrap_or_default();
+                                let text = text.trim();
+                                state.comments = (!text.is_empty()).then(|| text.to_string());
+                                state.info = match state.last_result.as_mut() {
+                                    Some(r) => {
+                                        r.comments = state.comments.clone();
+                                        "Comment updated (press 's' to save it with the last run)".into()
+                                    }
+                                    None => "Comment updated".into(),
+                                };
+                            }
+                            KeyCode::Backspace => {
+                                input.pop();
+                            }
+                            KeyCode::Char(c) => input.push(c),
+                            _ => {}
+                        }
+                        continue;
+                    }
+
+                    // Handle the colo picker (opened with 'L')
+                    if let Some(picker) = state.colo_picker.as_mut() {
+                        match k.code {
+                            KeyCode::Esc | KeyCode::Char('q') => state.colo_picker = None,
+                            KeyCode::Up | KeyCode::Char('k') => {
+                                picker.selected = picker.selected.saturating_sub(1);
+                            }
+                            KeyCode::Down | KeyCode::Char('j') => {
+                                picker.selected = (picker.selected + 1).min(picker.locations.len());
+                            }
+                            KeyCode::Enter => {
+                                // Row 0 is automatic selection, the rest the colos in order
+                                let colo = picker
+                                    .selected
+                                    .checked_sub(1)
+                                    .and_then(|i| picker.locations.get(i))
+                                    .map(|l| l.iata.clone());
+                                state.info = match &colo {
+                                    Some(colo) => format!("Next runs test against {} (press 'r' to start one)", colo),
+                                    None => "Next runs let Cloudflare choose the colo".into(),
+                                };
+                                args.colo = colo.clone();
+                                state.requested_colo = colo;
+                                state.colo_picker = None;
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
+
                     // Handle detail view mode (when on history tab and viewing JSON detail)
                     if state.tab == 1 && state.history_detail_view {
                         match k.code {
@@ -182,6 +283,32 @@ pub async fn run(args: Cli) -> Result<()> {
                                 ctx.ctrl_tx.send(EngineControl::Pause(state.paused)).await.ok();
                             }
                         }
+                        (_, KeyCode::Char('L')) => {
+                            state.colo_picker = Some(ColoPicker {
+                                loading: true,
+                                ..Default::default()
+                            });
+                            if locations_rx.is_none() {
+                                let cfg = build_config(&args);
+                                let (tx, rx) = mpsc::channel(1);
+                                tokio::spawn(async move {
+                                    tx.send(crate::locations::fetch(&cfg, PICKER_PROBES).await).await.ok();
+                                });
+                                locations_rx = Some(rx);
+                            }
+                        }
+                        (_, KeyCode::Char('r')) if state.tab == 3 => {
+                            // Rerun just the traceroute (traceroute tab)
+                            if traceroute_rx.is_some() {
+                                state.info = "Traceroute already running".into();
+                            } else {
+                                state.traceroute_summary = None;
+                                state.traceroute_hops.clear();
+                                state.traceroute_running = true;
+                                state.info = "Tracing the path to the server…".into();
+                                traceroute_rx = Some(start_traceroute(&args));
+                            }
+                        }
                         (_, KeyCode::Char('r')) => {
                             // Refresh history (only when on history tab)
                             if state.tab == 1 {
@@ -203,7 +330,7 @@ pub async fn run(args: Cli) -> Result<()> {
 
                                         // Adjust scroll offset if needed
                                         if state.history_scroll_offset >= state.history.len() && !state.history.is_empty() {
-                                            state.history_scroll_offset = state.history.len().saturating_sub(20).max(0);
+                                            state.history_scroll_offset = state.history.len().saturating_sub(20);
                                         }
 
                                         let new_count = state.history.len();
@@ -228,116 +355,84 @@ pub async fn run(args: Cli) -> Result<()> {
                                         let _ = h.await;
                                     }
                                 }
-                                state.last_result = None;
-                                state.run_start = Instant::now();
-                                state.dl_series.clear();
-                                state.ul_series.clear();
-                                state.idle_lat_series.clear();
-                                state.loaded_dl_lat_series.clear();
-                                state.loaded_ul_lat_series.clear();
-                                state.dl_points.clear();
-                                state.ul_points.clear();
-                                state.idle_lat_points.clear();
-                                state.loaded_dl_lat_points.clear();
-                                state.loaded_ul_lat_points.clear();
-                                state.dl_mbps = 0.0;
-                                state.ul_mbps = 0.0;
-                                state.dl_avg_mbps = 0.0;
-                                state.ul_avg_mbps = 0.0;
-                                state.dl_bytes_total = 0;
-                                state.ul_bytes_total = 0;
-                                state.dl_phase_start = None;
-                                state.ul_phase_start = None;
-                                state.idle_latency_samples.clear();
-                                state.loaded_dl_latency_samples.clear();
-                                state.loaded_ul_latency_samples.clear();
-                                state.idle_latency_sent = 0;
-                                state.idle_latency_received = 0;
-                                state.loaded_dl_latency_sent = 0;
-                                state.loaded_dl_latency_received = 0;
-                                state.loaded_ul_latency_sent = 0;
-                                state.loaded_ul_latency_received = 0;
-                                state.phase = Phase::IdleLatency;
-                                state.paused = false;
-                                // Clear UDP loss counters
-                                state.udp_loss_sent = 0;
-                                state.udp_loss_received = 0;
-                                state.udp_loss_total = 0;
-                                state.udp_loss_latest_rtt_ms = None;
-                                // Clear diagnostic results
-                                state.dns_summary = None;
-                                state.tls_summary = None;
-                                state.ip_comparison = None;
-                                state.traceroute_summary = None;
-                                run_ctx = Some(start_run(&args).await?);
+                                state.reset_for_run();
+                                run_ctx = Some(start_run(&args, state.auto_save, &phase_failures_tx).await?);
                             }
                         }
-                        (_, KeyCode::Char('s')) => {
+                        (_, KeyCode::Char('s')) if state.tab == 0 => {
                             // Only save on dashboard (auto-save location)
-                            if state.tab == 0 {
-                                if let Some(r) = state.last_result.clone() {
-                                    save_and_show_path(&r, &mut state);
-                                } else {
-                                    state.info = "No completed run to save yet.".into();
-                                }
+                            if let Some(r) = state.last_result.clone() {
+                                save_and_show_path(&r, &mut state);
+                            } else {
+                                state.info = "No completed run to save yet.".into();
                             }
                         }
                         // Export functions only work in history tab
-                        (_, KeyCode::Char('e')) => {
-                            if state.tab == 1 && !state.history.is_empty() {
-                                if state.history_selected < state.history.len() {
-                                    let r = &state.history[state.history_selected];
-                                    match export_result_json(r, &state) {
-                                        Ok(p) => {
-                                            let path_str = p.to_string_lossy().to_string();
-                                            state.last_exported_path = Some(path_str.clone());
-                                            state.info = format!("Exported JSON: {} (press 'y' to copy path)", p.display());
-                                        }
-                                        Err(e) => {
-                                            state.info = format!("JSON export failed: {e:#}");
-                                        }
-                                    }
+                        (_, KeyCode::Char('e'))
+                            if state.tab == 1
+                                && !state.history.is_empty()
+                                && state.history_selected < state.history.len() =>
+                        {
+                            let r = &state.history[state.history_selected];
+                            match export_result_json(r, &state) {
+                                Ok(p) => {
+                                    let path_str = p.to_string_lossy().to_string();
+                                    state.last_exported_path = Some(path_str.clone());
+                                    state.info = format!("Exported JSON: {} (press 'y' to copy path)", p.display());
+                                }
+                                Err(e) => {
+                                    state.info = format!("JSON export failed: {e:#}");
                                 }
                             }
                         }
-                        (_, KeyCode::Char('c')) => {
-                            if state.tab == 1 && !state.history.is_empty() {
-                                if state.history_selected < state.history.len() {
-                                    let r = &state.history[state.history_selected];
-                                    match export_result_csv(r, &state) {
-                                        Ok(p) => {
-                                            let path_str = p.to_string_lossy().to_string();
-                                            state.last_exported_path = Some(path_str.clone());
-                                            state.info = format!("Exported CSV: {} (press 'y' to copy path)", p.display());
-                                        }
-                                        Err(e) => {
-                                            state.info = format!("CSV export failed: {e:#}");
-                                        }
-                                    }
+                        (_, KeyCode::Char('t')) if state.tab == 0 => {
+                            state.show_streams = !state.show_streams;
+                            state.info = if state.show_streams {
+                                "Throughput charts: per connection".into()
+                            } else {
+                                "Throughput charts: total".into()
+                            };
+                        }
+                        (_, KeyCode::Char('c')) if state.tab == 0 => {
+                            // Edit the comment saved with the current (or next) run
+                            state.comment_input = Some(state.comments.clone().unwrap_or_default());
+                        }
+                        (_, KeyCode::Char('c'))
+                            if state.tab == 1
+                                && !state.history.is_empty()
+                                && state.history_selected < state.history.len() =>
+                        {
+                            let r = &state.history[state.history_selected];
+                            match export_result_csv(r, &state) {
+                                Ok(p) => {
+                                    let path_str = p.to_string_lossy().to_string();
+                                    state.last_exported_path = Some(path_str.clone());
+                                    state.info = format!("Exported CSV: {} (press 'y' to copy path)", p.display());
+                                }
+                                Err(e) => {
+                                    state.info = format!("CSV export failed: {e:#}");
                                 }
                             }
                         }
-                        (_, KeyCode::Char('y')) => {
+                        (_, KeyCode::Char('y')) if state.tab == 1 => {
                             // Copy last exported path to clipboard (yank)
-                            if state.tab == 1 {
-                                if let Some(ref path) = state.last_exported_path {
-                                    match copy_to_clipboard(path) {
-                                        Ok(_) => {
-                                            // Truncate very long paths in the message
-                                            let display_path = if path.len() > 60 {
-                                                format!("{}...", &path[..57])
-                                            } else {
-                                                path.clone()
-                                            };
-                                            state.info = format!("✓ Copied to clipboard: {}", display_path);
-                                        }
-                                        Err(e) => {
-                                            state.info = format!("Clipboard copy failed: {e:#}");
-                                        }
+                            if let Some(ref path) = state.last_exported_path {
+                                match copy_to_clipboard(path) {
+                                    Ok(_) => {
+                                        // Truncate very long paths in the message
+                                        let display_path = if path.len() > 60 {
+                                            format!("{}...", &path[..57])
+                                        } else {
+                                            path.clone()
+                                        };
+                                        state.info = format!("✓ Copied to clipboard: {}", display_path);
+                                    }
+                                    Err(e) => {
+                                        state.info = format!("Clipboard copy failed: {e:#}");
                                     }
-                                } else {
-                                    state.info = "No exported file path to copy. Export a file first (e/c)".into();
                                 }
+                            } else {
+                                state.info = "No exported file path to copy. Export a file first (e/c)".into();
                             }
                         }
                         (_, KeyCode::Char('a')) => {
@@ -348,9 +443,17 @@ pub async fn run(args: Cli) -> Result<()> {
                                 "Auto-save disabled".into()
                             };
                         }
+                        (_, KeyCode::Char('n')) => {
+                            state.notify = !state.notify;
+                            state.info = if state.notify {
+                                "Desktop notifications enabled".into()
+                            } else {
+                                "Desktop notifications disabled".into()
+                            };
+                        }
                         (KeyModifiers::SHIFT, KeyCode::BackTab) => {
                             // Shift+Tab cycles backwards
-                            let new_tab = if state.tab == 0 { 3 } else { state.tab - 1 };
+                            let new_tab = if state.tab == 0 { 4 } else { state.tab - 1 };
                             state.tab = new_tab;
                             if new_tab == 1 {
                                 state.history_selected = 0;
@@ -358,7 +461,7 @@ pub async fn run(args: Cli) -> Result<()> {
                             }
                         }
                         (_, KeyCode::Tab) => {
-                            let new_tab = (state.tab + 1) % 4;
+                            let new_tab = (state.tab + 1) % 5;
                             state.tab = new_tab;
                             // Reset history selection when switching to history tab
                             if new_tab == 1 {
@@ -367,185 +470,163 @@ pub async fn run(args: Cli) -> Result<()> {
                             }
                         }
                         (_, KeyCode::Char('?')) => {
-                            state.tab = 3; // help
+                            state.tab = 4; // help
                         }
                         // History navigation and deletion (only when on History tab)
-                        (_, KeyCode::Up) | (_, KeyCode::Char('k')) => {
-                            if state.tab == 1 && !state.history.is_empty() {
-                                if state.history_selected > 0 {
-                                    state.history_selected -= 1;
-                                }
-                            }
+                        (_, KeyCode::Up) | (_, KeyCode::Char('k'))
+                            if state.tab == 1
+                                && !state.history.is_empty()
+                                && state.history_selected > 0 =>
+                        {
+                            state.history_selected -= 1;
                         }
-                        (_, KeyCode::Down) | (_, KeyCode::Char('j')) => {
-                            if state.tab == 1 && !state.history.is_empty() {
-                                if state.history_selected < state.history.len().saturating_sub(1) {
-                                    state.history_selected += 1;
-
-                                    // Lazy load: if near end of loaded items, load more
-                                    let load_threshold = state.history_loaded_count.saturating_sub(10);
-                                    if state.history_selected >= load_threshold && state.history_loaded_count == state.history.len() {
-                                        let current_count = state.history.len();
-                                        let load_more = current_count.max(20);
-                                        if let Ok(more_history) = crate::storage::load_recent(load_more) {
-                                            let existing_ids: std::collections::HashSet<_> = state.history
-                                                .iter()
-                                                .map(|r| &r.meas_id)
-                                                .collect();
-                                            let new_items: Vec<_> = more_history
-                                                .into_iter()
-                                                .filter(|r| !existing_ids.contains(&r.meas_id))
-                                                .collect();
-                                            if !new_items.is_empty() {
-                                                state.history.extend(new_items);
-                                                state.history_loaded_count = state.history.len();
-                                                update_available_networks(&mut state);
-                                            }
-                                        }
+                        (_, KeyCode::Down) | (_, KeyCode::Char('j'))
+                            if state.tab == 1
+                                && !state.history.is_empty()
+                                && state.history_selected < state.history.len().saturating_sub(1) =>
+                        {
+                            state.history_selected += 1;
+
+                            // Lazy load: if near end of loaded items, load more
+                            let load_threshold = state.history_loaded_count.saturating_sub(10);
+                            if state.history_selected >= load_threshold && state.history_loaded_count == state.history.len() {
+                                let current_count = state.history.len();
+                                let load_more = current_count.max(20);
+                                // Only the next page is read; the index knows the order
+                                if let Ok(new_items) = crate::storage::load_page(current_count, load_more) {
+                                    if !new_items.is_empty() {
+                                        state.history.extend(new_items);
+                                        state.history_loaded_count = state.history.len();
+                                        update_available_networks(&mut state);
                                     }
                                 }
                             }
                         }
-                        (_, KeyCode::PageUp) => {
-                            if state.tab == 1 && !state.history.is_empty() {
-                                let page_size = 20;
-                                state.history_selected = state.history_selected.saturating_sub(page_size);
-                            }
-                        }
-                        (_, KeyCode::PageDown) => {
-                            if state.tab == 1 && !state.history.is_empty() {
-                                let page_size = 20;
-                                let max_idx = state.history.len().saturating_sub(1);
-                                state.history_selected = (state.history_selected + page_size).min(max_idx);
-
-                                // Lazy load if near the end
-                                let load_threshold = state.history_loaded_count.saturating_sub(10);
-                                if state.history_selected >= load_threshold && state.history_loaded_count == state.history.len() {
-                                    let current_count = state.history.len();
-                                    let load_more = current_count.max(20);
-                                    if let Ok(more_history) = crate::storage::load_recent(load_more) {
-                                        let existing_ids: std::collections::HashSet<_> = state.history
-                                            .iter()
-                                            .map(|r| &r.meas_id)
-                                            .collect();
-                                        let new_items: Vec<_> = more_history
-                                            .into_iter()
-                                            .filter(|r| !existing_ids.contains(&r.meas_id))
-                                            .collect();
-                                        if !new_items.is_empty() {
-                                            state.history.extend(new_items);
-                                            state.history_loaded_count = state.history.len();
-                                            update_available_networks(&mut state);
-                                        }
+                        (_, KeyCode::PageUp) if state.tab == 1 && !state.history.is_empty() => {
+                            let page_size = 20;
+                            state.history_selected = state.history_selected.saturating_sub(page_size);
+                        }
+                        (_, KeyCode::PageDown) if state.tab == 1 && !state.history.is_empty() => {
+                            let page_size = 20;
+                            let max_idx = state.history.len().saturating_sub(1);
+                            state.history_selected = (state.history_selected + page_size).min(max_idx);
+
+                            // Lazy load if near the end
+                            let load_threshold = state.history_loaded_count.saturating_sub(10);
+                            if state.history_selected >= load_threshold && state.history_loaded_count == state.history.len() {
+                                let current_count = state.history.len();
+                                let load_more = current_count.max(20);
+                                // Only the next page is read; the index knows the order
+                                if let Ok(new_items) = crate::storage::load_page(current_count, load_more) {
+                                    if !new_items.is_empty() {
+                                        state.history.extend(new_items);
+                                        state.history_loaded_count = state.history.len();
+                                        update_available_networks(&mut state);
                                     }
                                 }
                             }
                         }
-                        (_, KeyCode::Char('d')) => {
-                            if state.tab == 1 && !state.history.is_empty() {
-                                // history_selected directly maps to history index (newest first)
-                                if state.history_selected < state.history.len() {
-                                    let to_delete = state.history[state.history_selected].clone();
-                                    if let Err(e) = crate::storage::delete_run(&to_delete) {
-                                        state.info = format!("Delete failed: {e:#}");
-                                    } else {
-                                        state.history.remove(state.history_selected);
-                                        // Adjust scroll offset if needed
-                                        if state.history_scroll_offset >= state.history.len() && !state.history.is_empty() {
-                                            state.history_scroll_offset = state.history.len().saturating_sub(20).max(0);
-                                        }
-                                        // Adjust selection if needed
-                                        if state.history_selected >= state.history.len() && !state.history.is_empty() {
-                                            state.history_selected = state.history.len() - 1;
-                                        } else if state.history.is_empty() {
-                                            state.history_selected = 0;
-                                            state.history_scroll_offset = 0;
-                                        }
-                                        state.info = "Deleted".into();
-                                    }
+                        (_, KeyCode::Char('d'))
+                            if state.tab == 1
+                                && !state.history.is_empty()
+                                && state.history_selected < state.history.len() =>
+                        {
+                            // history_selected directly maps to history index (newest first)
+                            let to_delete = state.history[state.history_selected].clone();
+                            if let Err(e) = crate::storage::delete_run(&to_delete) {
+                                state.info = format!("Delete failed: {e:#}");
+                            } else {
+                                state.history.remove(state.history_selected);
+                                // Adjust scroll offset if needed
+                                if state.history_scroll_offset >= state.history.len() && !state.history.is_empty() {
+                                    state.history_scroll_offset = state.history.len().saturating_sub(20);
+                                }
+                                // Adjust selection if needed
+                                if state.history_selected >= state.history.len() && !state.history.is_empty() {
+                                    state.history_selected = state.history.len() - 1;
+                                } else if state.history.is_empty() {
+                                    state.history_selected = 0;
+                                    state.history_scroll_offset = 0;
                                 }
+                                state.info = "Deleted".into();
                             }
                         }
                         // Enter key to view JSON detail (only on History tab)
-                        (_, KeyCode::Enter) => {
-                            if state.tab == 1 && !state.history.is_empty() {
-                                state.history_detail_view = true;
-                                state.history_detail_scroll = 0;
-                            }
+                        (_, KeyCode::Enter) if state.tab == 1 && !state.history.is_empty() => {
+                            state.history_detail_view = true;
+                            state.history_detail_scroll = 0;
                         }
                         // Filter controls (only on History tab)
-                        (_, KeyCode::Char('/')) => {
-                            if state.tab == 1 {
-                                state.history_filter_editing = true;
-                            }
+                        (_, KeyCode::Char('/')) if state.tab == 1 => {
+                            state.history_filter_editing = true;
                         }
-                        (_, KeyCode::Esc) => {
-                            if state.tab == 1 && !state.history_filter.is_empty() {
-                                // Clear filter when Escape pressed and filter is active
-                                state.history_filter.clear();
-                                state.history_selected = 0;
-                                state.history_scroll_offset = 0;
-                            }
+                        (_, KeyCode::Esc) if state.tab == 1 && !state.history_filter.is_empty() => {
+                            // Clear filter when Escape pressed and filter is active
+                            state.history_filter.clear();
+                            state.history_selected = 0;
+                            state.history_scroll_offset = 0;
                         }
                         // Charts tab: cycle through networks with left/right or h/l
-                        (_, KeyCode::Left) | (_, KeyCode::Char('h')) => {
-                            if state.tab == 2 && !state.charts_available_networks.is_empty() {
-                                // Cycle backwards: All -> last network -> ... -> first network -> All
-                                match &state.charts_network_filter {
-                                    None => {
-                                        // Currently "All", go to last network
-                                        state.charts_network_filter = Some(
-                                            state.charts_available_networks.last().unwrap().clone(),
-                                        );
-                                    }
-                                    Some(current) => {
-                                        // Find current index and go to previous
-                                        if let Some(idx) = state
-                                            .charts_available_networks
-                                            .iter()
-                                            .position(|n| n == current)
-                                        {
-                                            if idx == 0 {
-                                                state.charts_network_filter = None; // Go to "All"
-                                            } else {
-                                                state.charts_network_filter = Some(
-                                                    state.charts_available_networks[idx - 1].clone(),
-                                                );
-                                            }
+                        (_, KeyCode::Left) | (_, KeyCode::Char('h'))
+                            if state.tab == 2
+                                && !state.charts_available_networks.is_empty() =>
+                        {
+                            // Cycle backwards: All -> last network -> ... -> first network -> All
+                            match &state.charts_network_filter {
+                                None => {
+                                    // Currently "All", go to last network
+                                    state.charts_network_filter = Some(
+                                        state.charts_available_networks.last().unwrap().clone(),
+                                    );
+                                }
+                                Some(current) => {
+                                    // Find current index and go to previous
+                                    if let Some(idx) = state
+                                        .charts_available_networks
+                                        .iter()
+                                        .position(|n| n == current)
+                                    {
+                                        if idx == 0 {
+                                            state.charts_network_filter = None; // Go to "All"
                                         } else {
-                                            state.charts_network_filter = None;
+                                            state.charts_network_filter = Some(
+                                                state.charts_available_networks[idx - 1].clone(),
+                                            );
                                         }
+                                    } else {
+                                        state.charts_network_filter = None;
                                     }
                                 }
                             }
                         }
-                        (_, KeyCode::Right) | (_, KeyCode::Char('l')) => {
-                            if state.tab == 2 && !state.charts_available_networks.is_empty() {
-                                // Cycle forwards: All -> first network -> ... -> last network -> All
-                                match &state.charts_network_filter {
-                                    None => {
-                                        // Currently "All", go to first network
-                                        state.charts_network_filter = Some(
-                                            state.charts_available_networks.first().unwrap().clone(),
-                                        );
-                                    }
-                                    Some(current) => {
-                                        // Find current index and go to next
-                                        if let Some(idx) = state
-                                            .charts_available_networks
-                                            .iter()
-                                            .position(|n| n == current)
-                                        {
-                                            if idx >= state.charts_available_networks.len() - 1 {
-                                                state.charts_network_filter = None; // Go to "All"
-                                            } else {
-                                                state.charts_network_filter = Some(
-                                                    state.charts_available_networks[idx + 1].clone(),
-                                                );
-                                            }
+                        (_, KeyCode::Right) | (_, KeyCode::Char('l'))
+                            if state.tab == 2
+                                && !state.charts_available_networks.is_empty() =>
+                        {
+                            // Cycle forwards: All -> first network -> ... -> last network -> All
+                            match &state.charts_network_filter {
+                                None => {
+                                    // Currently "All", go to first network
+                                    state.charts_network_filter = Some(
+                                        state.charts_available_networks.first().unwrap().clone(),
+                                    );
+                                }
+                                Some(current) => {
+                                    // Find current index and go to next
+                                    if let Some(idx) = state
+                                        .charts_available_networks
+                                        .iter()
+                                        .position(|n| n == current)
+                                    {
+                                        if idx >= state.charts_available_networks.len() - 1 {
+                                            state.charts_network_filter = None; // Go to "All"
                                         } else {
-                                            state.charts_network_filter = None;
+                                            state.charts_network_filter = Some(
+                                                state.charts_available_networks[idx + 1].clone(),
+                                            );
                                         }
+                                    } else {
+                                        state.charts_network_filter = None;
                                     }
                                 }
                             }
@@ -565,13 +646,13 @@ pub async fn run(args: Cli) -> Result<()> {
                 match maybe_engine_ev {
                     None => {
                         // engine finished; wait for result
+                        if traceroute_rx.is_none() {
+                            state.traceroute_running = false;
+                        }
                         if let Some(ctx) = &mut run_ctx {
                             if let Some(h) = ctx.handle.take() {
                             match h.await {
                                 Ok(Ok(r)) => {
-                                    if state.auto_save {
-                                        save_and_show_path(&r, &mut state);
-                                    }
                                     if let Some(meta) = r.meta.as_ref() {
                                         let extracted = crate::network::extract_metadata(meta);
                                         state.ip = extracted.ip;
@@ -587,34 +668,26 @@ pub async fn run(args: Cli) -> Result<()> {
                                     let enriched = enrich_result_with_network_info(&r, &state);
                                     state.last_result = Some(enriched.clone());
 
-                                    // Handle command-line export flags
-                                    let mut export_messages = Vec::new();
-                                    if let Some(export_path) = args.export_json.as_deref() {
-                                        match crate::storage::export_json(export_path, &enriched) {
-                                            Ok(_) => export_messages.push(format!("Exported JSON: {}", export_path.display())),
-                                            Err(e) => export_messages.push(format!("Export JSON failed: {e:#}")),
-                                        }
-                                    }
-                                    if let Some(export_path) = args.export_csv.as_deref() {
-                                        match crate::storage::export_csv(export_path, &enriched) {
-                                            Ok(_) => export_messages.push(format!("Exported CSV: {}", export_path.display())),
-                                            Err(e) => export_messages.push(format!("Export CSV failed: {e:#}")),
+                                    // Publish to the sinks the run started with. Webhooks retry
+                                    // for up to half a minute, so this runs in the background
+                                    let sinks = ctx.sinks.clone();
+                                    let published_tx = published_tx.clone();
+                                    let result = enriched.clone();
+                                    tokio::spawn(async move {
+                                        let mut messages = Vec::new();
+                                        for (_, sink) in sinks.iter() {
+                                            match sink.publish(&result).await {
+                                                Ok(published) => {
+                                                    messages.extend(published.message);
+                                                    messages.extend(published.warning);
+                                                }
+                                                Err(e) => messages.push(format!("{} failed: {e:#}", sink.name())),
+                                            }
                                         }
-                                    }
-                                    if !export_messages.is_empty() {
-                                        state.info = export_messages.join("; ");
-                                    }
-
-                                    // Reload history to include the new test
-                                    // Load at least one more than we had before to ensure the new test is included
-                                    let reload_size = (state.history_loaded_count + 1).max(state.initial_history_load_size);
-                                    state.history = crate::storage::load_recent(reload_size).unwrap_or_default();
-                                    state.history_loaded_count = state.history.len();
-                                    update_available_networks(&mut state);
-                                    // Reset selection to show the new test (most recent) if on history tab
-                                    if state.tab == 1 {
-                                        state.history_selected = 0;
-                                        state.history_scroll_offset = 0;
+                                        published_tx.send(messages).ok();
+                                    });
+                                    if state.notify {
+                                        crate::notify::finished(std::slice::from_ref(&enriched));
                                     }
                                 }
                                 Ok(Err(e)) => state.info = format!("Run failed: {e:#}"),
@@ -624,7 +697,93 @@ pub async fn run(args: Cli) -> Result<()> {
                             run_ctx = None;
                         }
                     }
+                    Some(TestEvent::PhaseResult { phase, snapshot }) => {
+                        match phase {
+                            Phase::IdleLatency => state.ttfb = snapshot.ttfb.clone(),
+                            Phase::Download => {
+                                state.loaded_dl_segments =
+                                    snapshot.loaded_latency_download_segments.clone()
+                            }
+                            Phase::Upload => {
+                                state.loaded_ul_segments =
+                                    snapshot.loaded_latency_upload_segments.clone()
+                            }
+                            Phase::ProtocolComparison => {
+                                state.protocol_results = snapshot.protocol_comparison.clone()
+                            }
+                            Phase::UdpThroughput => {
+                                state.udp_throughput = snapshot.udp_throughput.clone()
+                            }
+                            _ => {}
+                        }
+                        if let Some(ctx) = &run_ctx {
+                            ctx.phase_tx.send((phase, snapshot)).ok();
+                        }
+                    }
+                    Some(ev) => apply_event(&mut state, ev),
+                }
+            }
+            Some(failures) = phase_failures_rx.recv() => {
+                state.info = failures.join("; ");
+            }
+            Some(messages) = published_rx.recv() => {
+                if !messages.is_empty() {
+                    state.info = messages.join("; ");
+                }
+                // Reload history to include the new test
+                // Load at least one more than we had before to ensure the new test is included
+                let reload_size = (state.history_loaded_count + 1).max(state.initial_history_load_size);
+                state.history = crate::storage::load_recent(reload_size).unwrap_or_default();
+                state.history_loaded_count = state.history.len();
+                update_available_networks(&mut state);
+                // Reset selection to show the new test (most recent) if on history tab
+                if state.tab == 1 {
+                    state.history_selected = 0;
+                    state.history_scroll_offset = 0;
+                }
+            }
+            maybe_traceroute_ev = async {
+                if let Some(ref mut rx) = traceroute_rx {
+                    rx.recv().await
+                } else {
+                    future::pending().await
+                }
+            } => {
+                match maybe_traceroute_ev {
                     Some(ev) => apply_event(&mut state, ev),
+                    None => {
+                        traceroute_rx = None;
+                        state.traceroute_running = false;
+                    }
+                }
+            }
+            maybe_locations = async {
+                if let Some(ref mut rx) = locations_rx {
+                    rx.recv().await
+                } else {
+                    future::pending().await
+                }
+            } => {
+                locations_rx = None;
+                match maybe_locations {
+                    Some(Ok(locations)) => {
+                        // The picker may have been closed while they loaded
+                        if let Some(picker) = state.colo_picker.as_mut() {
+                            // Start on the colo the next run asks for
+                            picker.selected = state
+                                .requested_colo
+                                .as_ref()
+                                .and_then(|c| locations.iter().position(|l| &l.iata == c))
+                                .map_or(0, |i| i + 1);
+                            picker.locations = locations;
+                            picker.loading = false;
+                        }
+                    }
+                    Some(Err(e)) => {
+                        state.colo_picker = None;
+                        state.info = format!("Could not load the colos: {e:#}");
+                    }
+                    None => state.colo_picker = None,
                 }
             }
         }
@@ -634,6 +793,9 @@ pub async fn run(args: Cli) -> Result<()> {
     disable_raw_mode().ok();
     let mut stdout = io::stdout();
     execute!(stdout, LeaveAlternateScreen).ok();
+    for line in crate::logging::end_capture() {
+        eprintln!("{}", line);
+    }
     res
 }
 
@@ -641,21 +803,78 @@ struct RunCtx {
     ctrl_tx: mpsc::Sender<EngineControl>,
     event_rx: mpsc::Receiver<TestEvent>,
     handle: Option<tokio::task::JoinHandle<Result<RunResult>>>,
+    /// Where the run's result goes
+    sinks: Arc<crate::sinks::Sinks>,
+    /// Phase snapshots for the sinks, published by a task of the run's own
+    phase_tx: mpsc::UnboundedSender<(Phase, Box<RunResult>)>,
 }
 
-async fn start_run(args: &Cli) -> Result<RunCtx> {
+/// Start a run, saving it to the history when `auto_save` is on. Sink failures on phase
+/// snapshots are reported to `phase_failures`.
+async fn start_run(
+    args: &Cli,
+    auto_save: bool,
+    phase_failures: &mpsc::UnboundedSender<Vec<String>>,
+) -> Result<RunCtx> {
+    // stdout would corrupt the TUI
+    let mut sinks = crate::sinks::configured(args, false, auto_save);
+    sinks.retain(|spec| *spec != crate::sinks::SinkSpec::StdoutJson);
     let cfg = build_config(args);
     let (event_tx, event_rx) = mpsc::channel::<TestEvent>(4096);
     let (ctrl_tx, ctrl_rx) = mpsc::channel::<EngineControl>(32);
     let engine = TestEngine::new(cfg);
     let handle = tokio::spawn(async move { engine.run(event_tx, ctrl_rx).await });
+
+    // Webhooks retry for up to half a minute, so the snapshots are published off the
+    // event loop, one after the other to keep them in phase order
+    let sinks = Arc::new(sinks);
+    let (phase_tx, mut phase_rx) = mpsc::unbounded_channel::<(Phase, Box<RunResult>)>();
+    let phase_sinks = sinks.clone();
+    let phase_failures = phase_failures.clone();
+    tokio::spawn(async move {
+        while let Some((phase, snapshot)) = phase_rx.recv().await {
+            let failures = phase_sinks.publish_phase(phase, &snapshot).await;
+            if !failures.is_empty() {
+                phase_failures.send(failures).ok();
+            }
+        }
+    });
     Ok(RunCtx {
         ctrl_tx,
         event_rx,
         handle: Some(handle),
+        sinks,
+        phase_tx,
     })
 }
 
+/// Trace the path to the server on its own, outside a full run. The channel closes once
+/// the trace is done.
+fn start_traceroute(args: &Cli) -> mpsc::Receiver<TestEvent> {
+    let cfg = build_config(args);
+    let (event_tx, event_rx) = mpsc::channel::<TestEvent>(4096);
+    tokio::spawn(async move {
+        let Some(hostname) = crate::engine::dns::extract_hostname(&cfg.base_url) else {
+            let message = format!("Traceroute failed: no host in {}", cfg.base_url);
+            event_tx.send(TestEvent::Info { message }).await.ok();
+            return;
+        };
+        match crate::engine::traceroute::run_traceroute(&hostname, &cfg, &event_tx).await {
+            Ok(summary) => {
+                event_tx
+                    .send(TestEvent::TracerouteComplete { summary })
+                    .await
+                    .ok();
+            }
+            Err(e) => {
+                let message = format!("Traceroute failed: {e:#}");
+                event_tx.send(TestEvent::Info { message }).await.ok();
+            }
+        }
+    });
+    event_rx
+}
+
 fn apply_event(state: &mut UiState, ev: TestEvent) {
     match ev {
         TestEvent::PhaseStarted { phase } => {
@@ -833,6 +1052,22 @@ fn apply_event(state: &mut UiState, ev: TestEvent) {
                 sent, total, loss_pct
             );
         }
+        TestEvent::StreamTick {
+            phase,
+            worker_id,
+            bps,
+        } => {
+            let streams = match phase {
+                Phase::Download => &mut state.dl_stream_points,
+                Phase::Upload => &mut state.ul_stream_points,
+                _ => return,
+            };
+            if streams.len() <= worker_id {
+                streams.resize_with(worker_id + 1, Vec::new);
+            }
+            let t = state.run_start.elapsed().as_secs_f64();
+            UiState::push_point(&mut streams[worker_id], t, bps * 8.0 / 1_000_000.0);
+        }
         // Diagnostic events - store results and display summary in info bar
         TestEvent::DiagnosticDns { summary } => {
             state.info = format!(
@@ -851,6 +1086,10 @@ fn apply_event(state: &mut UiState, ev: TestEvent) {
             );
             state.tls_summary = Some(summary);
         }
+        TestEvent::DiagnosticTiming { timing } => {
+            state.info = format!("Timing ({:?}): {}", timing.phase, timing.breakdown());
+            state.request_timings.push(timing);
+        }
         TestEvent::DiagnosticIpComparison { comparison } => {
             let v4_info = comparison
                 .ipv4_result
@@ -875,10 +1114,65 @@ fn apply_event(state: &mut UiState, ev: TestEvent) {
                 })
                 .unwrap_or_else(|| "-".to_string());
             state.info = format!("IP Comparison: {} / {}", v4_info, v6_info);
-            state.ip_comparison = Some(comparison);
+            state.ip_comparison = Some(*comparison);
+        }
+        TestEvent::IpComparisonTick {
+            family,
+            phase,
+            bps_instant,
+        } => {
+            let mbps = (bps_instant * 8.0) / 1_000_000.0;
+            let rates = &mut state.ip_comparison_rates;
+            match rates
+                .iter_mut()
+                .find(|r| r.family == family && r.phase == phase)
+            {
+                Some(rate) => rate.mbps = mbps,
+                None => rates.push(IpComparisonRate {
+                    family,
+                    phase,
+                    mbps,
+                }),
+            }
+        }
+        TestEvent::TracerouteReply {
+            hop_number,
+            ip_address,
+            rtt_ms,
+        } => {
+            state.traceroute_running = true;
+            let hops = &mut state.traceroute_hops;
+            let index = match hops.binary_search_by_key(&hop_number, |hop| hop.hop_number) {
+                Ok(index) => index,
+                Err(index) => {
+                    hops.insert(
+                        index,
+                        TracerouteHop {
+                            hop_number,
+                            ip_address: Some(ip_address),
+                            hostname: None,
+                            rtt_ms: Vec::new(),
+                            timeout: false,
+                            asn: None,
+                            as_name: None,
+                            country: None,
+                        },
+                    );
+                    index
+                }
+            };
+            hops[index].rtt_ms.push(rtt_ms);
         }
         TestEvent::TracerouteHop { hop_number, hop } => {
-            let addr = hop.ip_address.as_deref().unwrap_or("*");
+            // The finished hop, named, replaces what its replies showed
+            match state
+                .traceroute_hops
+                .binary_search_by_key(&hop_number, |hop| hop.hop_number)
+            {
+                Ok(index) => state.traceroute_hops[index] = hop.clone(),
+                Err(index) => state.traceroute_hops.insert(index, hop.clone()),
+            }
+            let addr = hop.describe();
             let rtt = hop
                 .rtt_ms
                 .first()
@@ -886,18 +1180,33 @@ fn apply_event(state: &mut UiState, ev: TestEvent) {
                 .unwrap_or_else(|| "*".to_string());
             state.info = format!("Traceroute hop {}: {} {}", hop_number, addr, rtt);
         }
+        TestEvent::DiagnosticMtu { summary } => {
+            state.info = format!("Path MTU: {}", summary.describe());
+            state.mtu_summary = Some(summary);
+        }
         TestEvent::TracerouteComplete { summary } => {
             state.info = format!(
                 "Traceroute: {} hops to {}",
                 summary.hops.len(),
                 summary.destination
             );
+            state.traceroute_hops = summary.hops.clone();
+            state.traceroute_running = false;
             state.traceroute_summary = Some(summary);
         }
         TestEvent::ExternalIps { ipv4, ipv6 } => {
             state.external_ipv4 = ipv4;
             state.external_ipv6 = ipv6;
         }
+        TestEvent::Protocol { protocol } => state.protocols.push(protocol),
+        // Forwarded to the sinks by the event loop
+        TestEvent::PhaseResult { .. } => {}
+        TestEvent::Wireless { event } => {
+            state
+                .wireless_marks
+                .push(state.run_start.elapsed().as_secs_f64());
+            state.info = event.describe();
+        }
     }
 }
 
@@ -911,6 +1220,7 @@ fn draw(area: Rect, f: &mut ratatui::Frame, state: &mut UiState) {
         Line::from("Dashboard"),
         Line::from("History"),
         Line::from("Charts"),
+        Line::from("Traceroute"),
         Line::from("Help"),
     ])
     .select(state.tab)
@@ -919,11 +1229,23 @@ fn draw(area: Rect, f: &mut ratatui::Frame, state: &mut UiState) {
             .borders(Borders::ALL)
             .title(match &state.update_status {
                 Some(Some(v)) => Line::from(vec![
-                    Span::raw(format!("cloudflare-speed-cli v{} ", env!("CARGO_PKG_VERSION"))),
-                    Span::styled(format!("(v{} available)", v), Style::default().fg(Color::Cyan)),
+                    Span::raw(format!(
+                        "cloudflare-speed-cli v{} ",
+                        env!("CARGO_PKG_VERSION")
+                    )),
+                    Span::styled(
+                        format!("(v{} available)", v),
+                        Style::default().fg(Color::Cyan),
+                    ),
                 ]),
-                Some(None) => Line::from(format!("cloudflare-speed-cli v{} (latest)", env!("CARGO_PKG_VERSION"))),
-                None => Line::from(format!("cloudflare-speed-cli v{}", env!("CARGO_PKG_VERSION"))),
+                Some(None) => Line::from(format!(
+                    "cloudflare-speed-cli v{} (latest)",
+                    env!("CARGO_PKG_VERSION")
+                )),
+                None => Line::from(format!(
+                    "cloudflare-speed-cli v{}",
+                    env!("CARGO_PKG_VERSION")
+                )),
             }),
     )
     .highlight_style(Style::default().fg(Color::Yellow));
@@ -939,6 +1261,10 @@ fn draw(area: Rect, f: &mut ratatui::Frame, state: &mut UiState) {
             }
         }
         2 => draw_charts(chunks[1], f, state),
+        3 => draw_traceroute(chunks[1], f, state),
         _ => draw_help(chunks[1], f),
     }
+    if let Some(picker) = &state.colo_picker {
+        draw_colo_picker(chunks[1], f, picker, state.requested_colo.as_deref());
+    }
 }
@@ -1,4 +1,8 @@
-use crate::model::{DnsSummary, IpVersionComparison, Phase, RunResult, TlsSummary, TracerouteSummary};
+use crate::model::{
+    DnsSummary, IpVersionComparison, LatencySegments, LatencySummary, MtuSummary, Phase,
+    ProtocolResult, RequestTiming, RunResult, TlsSummary, TracerouteHop, TracerouteSummary,
+    UdpThroughputSummary,
+};
 use ratatui::{
     style::Color,
     style::Style,
@@ -6,12 +10,37 @@ use ratatui::{
 };
 use std::time::Instant;
 
+/// Latest rate of one family's download or upload in `--compare-ip-versions`
+#[derive(Debug, Clone)]
+pub struct IpComparisonRate {
+    /// "IPv4" or "IPv6"
+    pub family: String,
+    pub phase: Phase,
+    pub mbps: f64,
+}
+
+/// The colo picker opened with 'L'
+#[derive(Debug, Clone, Default)]
+pub struct ColoPicker {
+    /// Colos nearest first; empty while loading
+    pub locations: Vec<crate::locations::Location>,
+    /// Row under the cursor, 0 being automatic selection
+    pub selected: usize,
+    pub loading: bool,
+}
+
 pub struct UiState {
     pub tab: usize,
     pub paused: bool,
     pub phase: Phase,
     pub info: String,
     pub comments: Option<String>,
+    /// Comment being typed at the dashboard prompt (`Some` while editing)
+    pub comment_input: Option<String>,
+    /// Colo picker, while open
+    pub colo_picker: Option<ColoPicker>,
+    /// Colo the next runs ask for with `cf.colo`; None lets Cloudflare choose
+    pub requested_colo: Option<String>,
 
     pub dl_series: Vec<u64>,
     pub ul_series: Vec<u64>,
@@ -26,6 +55,15 @@ pub struct UiState {
     pub idle_lat_points: Vec<(f64, f64)>,
     pub loaded_dl_lat_points: Vec<(f64, f64)>,
     pub loaded_ul_lat_points: Vec<(f64, f64)>,
+    // Per-connection throughput, indexed by worker
+    pub dl_stream_points: Vec<Vec<(f64, f64)>>,
+    pub ul_stream_points: Vec<Vec<(f64, f64)>>,
+    // Throughput charts show one line per connection instead of the total
+    pub show_streams: bool,
+    // Wi-Fi roams/channel switches (seconds since run start), drawn as chart markers
+    pub wireless_marks: Vec<f64>,
+    // Negotiated HTTP/TLS details of the phases finished so far
+    pub protocols: Vec<crate::model::PhaseProtocol>,
 
     pub dl_mbps: f64,
     pub ul_mbps: f64,
@@ -64,7 +102,7 @@ pub struct UiState {
     pub charts_network_filter: Option<String>, // None = all networks, Some(name) = specific network
     pub charts_available_networks: Vec<String>, // List of unique network names from history
     // History detail view state
-    pub history_detail_view: bool,    // Whether showing JSON detail view
+    pub history_detail_view: bool, // Whether showing JSON detail view
     pub history_detail_scroll: usize, // Scroll position in detail view
     pub ip: Option<String>,
     pub colo: Option<String>,
@@ -72,12 +110,19 @@ pub struct UiState {
     pub asn: Option<String>,
     pub as_org: Option<String>,
     pub auto_save: bool,
+    /// Desktop notification when a run finishes (`--notify`)
+    pub notify: bool,
+    /// `--compress` for runs saved from the dashboard
+    pub compression: crate::storage::Compression,
     pub last_exported_path: Option<String>,
     // Network interface information
     pub interface_name: Option<String>,
     pub network_name: Option<String>,
     pub is_wireless: Option<bool>,
+    pub vpn_detected: bool,
+    pub wireless_link: Option<crate::model::WirelessLink>,
     pub interface_mac: Option<String>,
+    pub link_speed_mbps: Option<u64>,
     pub local_ipv4: Option<String>,
     pub local_ipv6: Option<String>,
     pub external_ipv4: Option<String>,
@@ -87,10 +132,30 @@ pub struct UiState {
     // Diagnostic results
     pub dns_summary: Option<DnsSummary>,
     pub tls_summary: Option<TlsSummary>,
+    /// Fresh-request timing per phase, in the order measured
+    pub request_timings: Vec<RequestTiming>,
     pub ip_comparison: Option<IpVersionComparison>,
+    /// Live `--compare-ip-versions` rates until `ip_comparison` arrives
+    pub ip_comparison_rates: Vec<IpComparisonRate>,
+    /// Time to first byte measured before the idle latency probes
+    pub ttfb: Option<LatencySummary>,
+    /// Early/mid/late loaded latency of the finished download and upload phases
+    pub loaded_dl_segments: Option<LatencySegments>,
+    pub loaded_ul_segments: Option<LatencySegments>,
+    /// `--compare-protocols` results, one per HTTP version
+    pub protocol_results: Vec<ProtocolResult>,
+    /// `--udp-throughput` stream through the TURN relay
+    pub udp_throughput: Option<UdpThroughputSummary>,
     pub traceroute_summary: Option<TracerouteSummary>,
+    /// Hops of the traceroute tab by hop number, filled in as replies arrive
+    pub traceroute_hops: Vec<TracerouteHop>,
+    /// A traceroute is probing and `traceroute_hops` is still growing
+    pub traceroute_running: bool,
+    pub mtu_summary: Option<MtuSummary>,
     /// None = check not completed, Some(None) = on latest, Some(Some(v)) = update available
     pub update_status: Option<Option<String>>,
+    /// Next `--schedule` slot, when running on a schedule
+    pub next_scheduled_run: Option<time::OffsetDateTime>,
 }
 
 impl Default for UiState {
@@ -101,6 +166,9 @@ impl Default for UiState {
             phase: Phase::IdleLatency,
             info: String::new(),
             comments: None,
+            comment_input: None,
+            colo_picker: None,
+            requested_colo: None,
             dl_series: Vec::new(),
             ul_series: Vec::new(),
             idle_lat_series: Vec::new(),
@@ -109,9 +177,14 @@ impl Default for UiState {
             run_start: Instant::now(),
             dl_points: Vec::new(),
             ul_points: Vec::new(),
+            dl_stream_points: Vec::new(),
+            ul_stream_points: Vec::new(),
+            show_streams: false,
             idle_lat_points: Vec::new(),
             loaded_dl_lat_points: Vec::new(),
             loaded_ul_lat_points: Vec::new(),
+            wireless_marks: Vec::new(),
+            protocols: Vec::new(),
             dl_mbps: 0.0,
             ul_mbps: 0.0,
             dl_avg_mbps: 0.0,
@@ -151,11 +224,16 @@ impl Default for UiState {
             asn: None,
             as_org: None,
             auto_save: true,
+            notify: false,
+            compression: crate::storage::Compression::None,
             last_exported_path: None,
             interface_name: None,
             network_name: None,
             is_wireless: None,
+            vpn_detected: false,
+            wireless_link: None,
             interface_mac: None,
+            link_speed_mbps: None,
             local_ipv4: None,
             local_ipv6: None,
             external_ipv4: None,
@@ -165,9 +243,20 @@ impl Default for UiState {
             // Diagnostic results
             dns_summary: None,
             tls_summary: None,
+            request_timings: Vec::new(),
             ip_comparison: None,
+            ip_comparison_rates: Vec::new(),
+            ttfb: None,
+            loaded_dl_segments: None,
+            loaded_ul_segments: None,
+            protocol_results: Vec::new(),
+            udp_throughput: None,
             traceroute_summary: None,
+            traceroute_hops: Vec::new(),
+            traceroute_running: false,
+            mtu_summary: None,
             update_status: None,
+            next_scheduled_run: None,
         }
     }
 }
@@ -238,6 +327,65 @@ pub fn push_wrapped_status_kv(
 }
 
 impl UiState {
+    /// Clear the live data of the previous run before starting a new one.
+    pub fn reset_for_run(&mut self) {
+        self.last_result = None;
+        self.run_start = Instant::now();
+        self.dl_series.clear();
+        self.ul_series.clear();
+        self.idle_lat_series.clear();
+        self.loaded_dl_lat_series.clear();
+        self.loaded_ul_lat_series.clear();
+        self.dl_points.clear();
+        self.ul_points.clear();
+        self.dl_stream_points.clear();
+        self.ul_stream_points.clear();
+        self.idle_lat_points.clear();
+        self.loaded_dl_lat_points.clear();
+        self.loaded_ul_lat_points.clear();
+        self.wireless_marks.clear();
+        self.protocols.clear();
+        self.dl_mbps = 0.0;
+        self.ul_mbps = 0.0;
+        self.dl_avg_mbps = 0.0;
+        self.ul_avg_mbps = 0.0;
+        self.dl_bytes_total = 0;
+        self.ul_bytes_total = 0;
+        self.dl_phase_start = None;
+        self.ul_phase_start = None;
+        self.idle_latency_samples.clear();
+        self.loaded_dl_latency_samples.clear();
+        self.loaded_ul_latency_samples.clear();
+        self.idle_latency_sent = 0;
+        self.idle_latency_received = 0;
+        self.loaded_dl_latency_sent = 0;
+        self.loaded_dl_latency_received = 0;
+        self.loaded_ul_latency_sent = 0;
+        self.loaded_ul_latency_received = 0;
+        self.phase = Phase::IdleLatency;
+        self.paused = false;
+        // Clear UDP loss counters
+        self.udp_loss_sent = 0;
+        self.udp_loss_received = 0;
+        self.udp_loss_total = 0;
+        self.udp_loss_latest_rtt_ms = None;
+        // Clear diagnostic results
+        self.dns_summary = None;
+        self.tls_summary = None;
+        self.request_timings.clear();
+        self.ip_comparison = None;
+        self.ip_comparison_rates.clear();
+        self.ttfb = None;
+        self.loaded_dl_segments = None;
+        self.loaded_ul_segments = None;
+        self.protocol_results.clear();
+        self.udp_throughput = None;
+        self.traceroute_summary = None;
+        self.traceroute_hops.clear();
+        self.traceroute_running = false;
+        self.mtu_summary = None;
+    }
+
     pub fn push_series(series: &mut Vec<u64>, v: u64) {
         const MAX: usize = 120;
         series.push(v);
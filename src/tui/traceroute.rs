@@ -0,0 +1,177 @@
+use ratatui::{
+    layout::{Constraint, Direction, Layout, Rect},
+    style::Color,
+    style::Style,
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph},
+    Frame,
+};
+
+use super::state::UiState;
+use crate::model::TracerouteHop;
+
+/// Width of the RTT bar drawn for each hop
+const BAR_WIDTH: usize = 24;
+
+/// Draw the traceroute tab: one row per hop as replies come in, each with its RTTs and a
+/// bar of the average scaled to the slowest hop, then where the trace ended.
+pub fn draw_traceroute(area: Rect, f: &mut Frame, state: &UiState) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Traceroute (r: rerun traceroute)");
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(0), Constraint::Length(3)].as_ref())
+        .split(inner);
+
+    let gray = Style::default().fg(Color::Gray);
+    let hops = &state.traceroute_hops;
+    if hops.is_empty() {
+        let message = if state.traceroute_running {
+            "Waiting for the first replies…"
+        } else {
+            "No traceroute yet. Press 'r' to trace the path to the server, or run with --traceroute."
+        };
+        f.render_widget(Paragraph::new(Line::styled(message, gray)), chunks[0]);
+        return;
+    }
+
+    let slowest = hops.iter().filter_map(average_rtt).fold(0.0, f64::max);
+    let last = hops.last().map_or(0, |hop| hop.hop_number);
+    let mut lines = Vec::new();
+    let mut next = hops.iter().peekable();
+    // Hops with no reply yet appear between those with one, so the path stays in order
+    for hop_number in 1..=last {
+        let hop = next.next_if(|hop| hop.hop_number == hop_number);
+        lines.push(hop_line(hop_number, hop, slowest, state.traceroute_running));
+    }
+    // Keep the newest hops in view on short terminals
+    let scroll = lines.len().saturating_sub(usize::from(chunks[0].height));
+    f.render_widget(Paragraph::new(lines).scroll((scroll as u16, 0)), chunks[0]);
+
+    f.render_widget(Paragraph::new(status_lines(state)), chunks[1]);
+}
+
+/// One hop: number, address, RTT bar and the RTT of each probe
+fn hop_line(
+    hop_number: u8,
+    hop: Option<&TracerouteHop>,
+    slowest: f64,
+    running: bool,
+) -> Line<'static> {
+    let gray = Style::default().fg(Color::Gray);
+    let number = Span::raw(format!("{:>3}  ", hop_number));
+    let Some(hop) = hop.filter(|hop| !hop.timeout) else {
+        let (text, style) = if running {
+            ("…", gray)
+        } else {
+            ("*  timeout", Style::default().fg(Color::DarkGray))
+        };
+        return Line::from(vec![number, Span::styled(text, style)]);
+    };
+
+    let mut address = hop.describe();
+    if address.chars().count() > 48 {
+        address = address.chars().take(47).collect::<String>() + "…";
+    }
+    let average = average_rtt(hop).unwrap_or_default();
+    let filled = if slowest > 0.0 {
+        ((average / slowest * BAR_WIDTH as f64).round() as usize).clamp(1, BAR_WIDTH)
+    } else {
+        1
+    };
+    let rtts: Vec<String> = hop.rtt_ms.iter().map(|r| format!("{:.1}ms", r)).collect();
+    Line::from(vec![
+        number,
+        Span::raw(format!("{:<48} ", address)),
+        Span::styled("█".repeat(filled), Style::default().fg(rtt_color(average))),
+        Span::raw(" ".repeat(BAR_WIDTH - filled + 1)),
+        Span::styled(rtts.join(" "), gray),
+    ])
+}
+
+/// Where the trace stands: running, or the destination reached or not, with the method,
+/// AS path and ECMP split when known
+fn status_lines(state: &UiState) -> Vec<Line<'static>> {
+    let gray = Style::default().fg(Color::Gray);
+    let Some(summary) = state
+        .traceroute_summary
+        .as_ref()
+        .filter(|_| !state.traceroute_running)
+    else {
+        let answered = state
+            .traceroute_hops
+            .iter()
+            .filter(|hop| !hop.timeout)
+            .count();
+        return vec![Line::from(vec![
+            Span::styled("Tracing… ", Style::default().fg(Color::Yellow)),
+            Span::styled(format!("{} hops answered", answered), gray),
+        ])];
+    };
+
+    let method = summary
+        .method
+        .as_deref()
+        .map(|m| format!(" via {}", m))
+        .unwrap_or_default();
+    let status = if summary.completed {
+        Span::styled(
+            format!(
+                "Reached {} in {} hops{}",
+                summary.destination,
+                summary.hops.len(),
+                method
+            ),
+            Style::default().fg(Color::Green),
+        )
+    } else {
+        Span::styled(
+            format!(
+                "{} not reached after {} hops{}",
+                summary.destination,
+                summary.hops.len(),
+                method
+            ),
+            Style::default().fg(Color::Red),
+        )
+    };
+    let mut lines = vec![Line::from(status)];
+    if let Some(path) = summary.as_path() {
+        lines.push(Line::from(vec![
+            Span::styled("AS path: ", gray),
+            Span::raw(path),
+        ]));
+    }
+    if !summary.paths.is_empty() {
+        let split = summary.ecmp_hops();
+        let split = if split.is_empty() {
+            ", all on one path".to_string()
+        } else {
+            let hops: Vec<String> = split.iter().map(u8::to_string).collect();
+            format!(", paths differ at hops {}", hops.join(", "))
+        };
+        lines.push(Line::from(vec![
+            Span::styled("ECMP: ", gray),
+            Span::raw(format!("{} flows{}", summary.paths.len() + 1, split)),
+        ]));
+    }
+    lines
+}
+
+fn average_rtt(hop: &TracerouteHop) -> Option<f64> {
+    (!hop.rtt_ms.is_empty()).then(|| hop.rtt_ms.iter().sum::<f64>() / hop.rtt_ms.len() as f64)
+}
+
+fn rtt_color(rtt_ms: f64) -> Color {
+    if rtt_ms < 30.0 {
+        Color::Green
+    } else if rtt_ms < 100.0 {
+        Color::Yellow
+    } else {
+        Color::Red
+    }
+}
@@ -0,0 +1,1969 @@
+//! Runs the full binary against the local mock server and checks the emitted result.
+
+mod mock;
+
+use mock::{MockDns, MockServer, MockSocks, Shaping};
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::time::Duration;
+
+/// Home directory `run_cli` uses for the given extra flags.
+fn home_for(extra: &[&str]) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "cloudflare-speed-cli-e2e-{}-{}",
+        std::process::id(),
+        extra.join("").replace('/', "_")
+    ))
+}
+
+/// Run the binary against `server` with short phases and the given extra flags.
+async fn run_cli(server: &MockServer, extra: &[&str]) -> Output {
+    let home = home_for(extra);
+    let mut args = vec![
+        "--base-url",
+        &server.base_url,
+        "--download-duration",
+        "3s",
+        "--upload-duration",
+        "3s",
+        "--idle-latency-duration",
+        "1s",
+        "--concurrency",
+        "4",
+        "--skip-diagnostics",
+        "--udp-packets",
+        "0",
+        "--auto-save",
+        "false",
+        "--no-proxy",
+    ];
+    args.extend(extra);
+    run_in_home(&home, &args).await
+}
+
+/// Run the binary with exactly `args`, keeping its state in `home`.
+async fn run_in_home(home: &Path, args: &[&str]) -> Output {
+    run_with_env(home, args, &[]).await
+}
+
+/// `run_in_home` with extra environment variables.
+async fn run_with_env(home: &Path, args: &[&str], vars: &[(&str, &str)]) -> Output {
+    tokio::process::Command::new(env!("CARGO_BIN_EXE_cloudflare-speed-cli"))
+        .args(args)
+        // Keep any stray state (history, config) out of the real home directory
+        .env("HOME", home)
+        .env("XDG_DATA_HOME", home)
+        .env("XDG_CONFIG_HOME", home)
+        .envs(vars.iter().copied())
+        .output()
+        .await
+        .expect("run cloudflare-speed-cli")
+}
+
+fn assert_success(out: &Output) {
+    assert!(
+        out.status.success(),
+        "exit status {}\nstderr:\n{}",
+        out.status,
+        String::from_utf8_lossy(&out.stderr)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn json_result_reflects_shaping() {
+    let server = MockServer::start(Shaping::mbps(20, 40, 20)).await;
+    let out = run_cli(&server, &["--json"]).await;
+    assert_success(&out);
+
+    let result: serde_json::Value =
+        serde_json::from_slice(&out.stdout).expect("stdout is a RunResult JSON document");
+    assert_eq!(result["base_url"], server.base_url.as_str());
+    assert_eq!(result["colo"], mock::COLO);
+    assert_eq!(result["partial"], false);
+
+    let dl = result["download"]["mbps"].as_f64().unwrap();
+    let ul = result["upload"]["mbps"].as_f64().unwrap();
+    assert!(
+        (20.0..=44.0).contains(&dl),
+        "download {dl} Mbps, shaped to 40"
+    );
+    // Loopback socket buffers absorb several MB before the mock's pacing pushes back,
+    // so the client-side upload figure is only bounded from below
+    assert!(ul >= 10.0, "upload {ul} Mbps, shaped to 20");
+    assert!(result["download"]["bytes"].as_u64().unwrap() > 0);
+    assert_eq!(result["download"]["steady_window"], "trimmed");
+
+    let idle = result["idle_latency"]["median_ms"].as_f64().unwrap();
+    assert!(
+        idle >= 20.0,
+        "idle latency {idle} ms below the mock's 20 ms"
+    );
+    let ttfb = result["ttfb"]["median_ms"].as_f64().unwrap();
+    assert!(ttfb >= 20.0, "TTFB {ttfb} ms below the mock's 20 ms");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn text_summary_is_printed() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(&server, &["--text"]).await;
+    assert_success(&out);
+
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    for needle in ["Download: avg", "Upload:", "Idle latency: avg", "TTFB: med"] {
+        assert!(stdout.contains(needle), "missing {needle:?} in:\n{stdout}");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn json_export_matches_stdout() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let path = std::env::temp_dir().join(format!(
+        "cloudflare-speed-cli-e2e-{}.json",
+        std::process::id()
+    ));
+    let out = run_cli(
+        &server,
+        &[
+            "--json",
+            "--export-json",
+            path.to_str().unwrap(),
+            "--comment",
+            "e2e run",
+        ],
+    )
+    .await;
+    assert_success(&out);
+
+    let exported: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    let printed: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    std::fs::remove_file(&path).ok();
+    assert_eq!(exported["meas_id"], printed["meas_id"]);
+    assert_eq!(exported["download"]["bytes"], printed["download"]["bytes"]);
+    assert_eq!(exported["comments"], "e2e run");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn repeated_runs_are_aggregated_and_saved() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let extra = ["--json", "--count", "2", "--sink", "history"];
+    let out = run_cli(&server, &extra).await;
+    assert_success(&out);
+
+    let doc: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let runs = doc["runs"].as_array().unwrap();
+    assert_eq!(runs.len(), 2);
+    assert_eq!(doc["summary"]["runs"], 2);
+    assert_eq!(doc["summary"]["failed"], 0);
+    assert!(doc["failures"].as_array().unwrap().is_empty());
+    let dl = &doc["summary"]["download_mbps"];
+    let (min, max) = (dl["min"].as_f64().unwrap(), dl["max"].as_f64().unwrap());
+    assert!(
+        min > 0.0 && min <= dl["median"].as_f64().unwrap() && dl["mean"].as_f64().unwrap() <= max
+    );
+
+    let home = home_for(&extra);
+    let saved = run_in_home(&home, &["history", "query", "--json"]).await;
+    assert_success(&saved);
+    let saved: Vec<serde_json::Value> = serde_json::from_slice(&saved.stdout).unwrap();
+    std::fs::remove_dir_all(&home).ok();
+    assert_eq!(saved.len(), 2);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn missed_threshold_exits_with_json_error() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(
+        &server,
+        &[
+            "--json",
+            "--fail-below-download",
+            "1000",
+            "--fail-above-latency",
+            "1000",
+        ],
+    )
+    .await;
+    assert_eq!(out.status.code(), Some(3));
+    // The result is still printed
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert!(result["download"]["mbps"].as_f64().unwrap() > 0.0);
+
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let error: serde_json::Value = serde_json::from_str(stderr.lines().last().unwrap()).unwrap();
+    assert_eq!(error["error"], "threshold_failed");
+    let failures = error["failures"].as_array().unwrap();
+    assert_eq!(failures.len(), 1, "{}", stderr);
+    assert_eq!(failures[0]["metric"], "download_mbps");
+    assert_eq!(failures[0]["threshold"], 1000.0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn events_are_streamed_as_ndjson() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(&server, &["--events-ndjson"]).await;
+    assert_success(&out);
+
+    let events: Vec<serde_json::Value> = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .map(|l| serde_json::from_str(l).expect("every line is a JSON object"))
+        .collect();
+    let count = |kind: &str| events.iter().filter(|e| e["type"] == kind).count();
+    assert!(count("phase_started") >= 3);
+    assert!(count("throughput_tick") > 0);
+    assert!(count("latency_sample") > 0);
+    assert!(events.iter().all(|e| e["t_ms"].is_u64()));
+    // One stream tick per connection (`--concurrency 4`) and throughput tick
+    assert_eq!(count("stream_tick"), 4 * count("throughput_tick"));
+
+    let last = events.last().unwrap();
+    assert_eq!(last["type"], "result");
+    assert_eq!(last["result"]["colo"], mock::COLO);
+    let streams = last["result"]["download"]["stream_bytes"]
+        .as_array()
+        .unwrap();
+    assert_eq!(streams.len(), 4);
+    assert!(streams.iter().all(|b| b.as_u64().unwrap() > 0));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn data_budget_stops_transfers_early() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(
+        &server,
+        &[
+            "--events-ndjson",
+            "--max-total-bytes",
+            "4MB",
+            "--download-bytes-per-req",
+            "1000000",
+            "--ramped",
+            "--compare-protocols",
+        ],
+    )
+    .await;
+    assert_success(&out);
+    // The latency probes' one-byte downloads are outside the budget
+    let served = server
+        .transferred
+        .load(std::sync::atomic::Ordering::Relaxed);
+    assert!(served <= 4_010_000, "{} bytes transferred", served);
+
+    let events: Vec<serde_json::Value> = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    let transferred = |phase: &str| {
+        events
+            .iter()
+            .filter(|e| e["type"] == "throughput_tick" && e["phase"] == phase)
+            .filter_map(|e| e["bytes_total"].as_u64())
+            .max()
+            .unwrap_or(0)
+    };
+    assert!(transferred("Download") > 0);
+    assert!(transferred("Download") + transferred("Upload") <= 4_000_000);
+    let budget_messages = events
+        .iter()
+        .filter(|e| {
+            e["type"] == "info"
+                && e["message"]
+                    .as_str()
+                    .is_some_and(|m| m.contains("data budget"))
+        })
+        .count();
+    // Download, upload, ramped and protocol comparison
+    assert_eq!(budget_messages, 4);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn adaptive_duration_ends_stable_phases_early() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    // Longer phases than `run_cli` uses, so ending early is visible
+    let args = [
+        "--base-url",
+        &server.base_url,
+        "--download-duration",
+        "15s",
+        "--upload-duration",
+        "15s",
+        "--idle-latency-duration",
+        "1s",
+        "--skip-diagnostics",
+        "--udp-packets",
+        "0",
+        "--auto-save",
+        "false",
+        "--no-proxy",
+        "--json",
+        "--adaptive",
+        // Loopback socket buffers take megabytes of upload at once
+        "--send-buffer",
+        "64KiB",
+        "--adaptive-cv",
+        "20",
+    ];
+    let out = run_in_home(&home_for(&["adaptive"]), &args).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    for phase in ["download", "upload"] {
+        assert_eq!(result[phase]["stabilized"], true, "{}", phase);
+        let elapsed = result[phase]["elapsed_ms"].as_u64().unwrap();
+        assert!((2_000..15_000).contains(&elapsed), "{}: {}", phase, elapsed);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrency_sweep_reports_each_level() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(
+        &server,
+        &["--json", "--no-upload", "--sweep-concurrency", "1,4"],
+    )
+    .await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let sweep = &result["concurrency_sweep"];
+    let levels = sweep["levels"].as_array().unwrap();
+    assert_eq!(levels.len(), 2);
+    assert_eq!(levels[0]["connections"], 1);
+    assert_eq!(levels[1]["connections"], 4);
+    assert!(levels.iter().all(|l| l["mbps"].as_f64().unwrap() > 0.0));
+    // The mock shapes the aggregate rate, so a single connection already fills it
+    assert_eq!(sweep["bottleneck"], "aggregate");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn protocol_comparison_measures_each_version() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(&server, &["--json", "--no-upload", "--compare-protocols"]).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let protocols = result["protocol_comparison"].as_array().unwrap();
+    for name in ["http1", "http2"] {
+        let p = protocols
+            .iter()
+            .find(|p| p["protocol"] == name)
+            .unwrap_or_else(|| panic!("no {name} entry"));
+        assert_eq!(p["available"], true, "{name}: {p}");
+        assert!(p["mbps"].as_f64().unwrap() > 0.0);
+        assert!(p["ttfb_ms"].is_f64());
+    }
+    let comparisons = result["comparisons"].as_array().unwrap();
+    assert!(comparisons.iter().any(|c| c["kind"] == "Protocol"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ip_comparison_streams_each_family() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(
+        &server,
+        &["--events-ndjson", "--no-upload", "--compare-ip-versions"],
+    )
+    .await;
+    assert_success(&out);
+
+    let events: Vec<serde_json::Value> = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    // The mock only listens on IPv4
+    assert!(events.iter().any(|e| e["type"] == "ip_comparison_tick"
+        && e["family"] == "IPv4"
+        && e["phase"] == "Download"
+        && e["bps_instant"].as_f64().unwrap() > 0.0));
+    assert!(events
+        .iter()
+        .any(|e| e["type"] == "phase_started" && e["phase"] == "IpComparison"));
+
+    let comparison = &events.last().unwrap()["result"]["ip_comparison"];
+    let v4 = &comparison["ipv4_result"];
+    assert_eq!(v4["available"], true, "{v4}");
+    assert_eq!(v4["ip_address"], "127.0.0.1");
+    assert!(v4["download_mbps"].as_f64().unwrap() > 0.0);
+    assert!(v4["latency_ms"].as_f64().unwrap() > 0.0);
+    assert!(v4["idle_latency"]["received"].as_u64().unwrap() > 0);
+    let loaded = &v4["loaded_latency_download"];
+    assert!(loaded["received"].as_u64().unwrap() > 0, "{v4}");
+    assert!(loaded["median_ms"].as_f64().unwrap() > 0.0);
+    assert_eq!(comparison["ipv6_result"]["available"], false);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test(flavor = "multi_thread")]
+async fn tcp_info_is_attached_to_throughput_phases() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(&server, &["--json"]).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    for phase in ["download", "upload"] {
+        let tcp = &result[phase]["tcp_info"];
+        let connections = tcp["connections"].as_array().unwrap();
+        assert!(!connections.is_empty(), "{phase}: {tcp}");
+        assert!(connections.iter().all(|c| c["cwnd"].as_u64().unwrap() > 0));
+        assert!(tcp["rtt_ms"].is_f64());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn timing_breakdown_covers_each_phase() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    // Diagnostics on, which `run_cli` turns off
+    let args = [
+        "--base-url",
+        &server.base_url,
+        "--download-duration",
+        "2s",
+        "--upload-duration",
+        "2s",
+        "--idle-latency-duration",
+        "1s",
+        "--udp-packets",
+        "0",
+        "--auto-save",
+        "false",
+        "--no-proxy",
+        "--json",
+    ];
+    let out = run_in_home(&home_for(&["timing"]), &args).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let requests = result["timing_breakdown"]["requests"].as_array().unwrap();
+    let phases: Vec<&str> = requests
+        .iter()
+        .map(|r| r["phase"].as_str().unwrap())
+        .collect();
+    assert_eq!(phases, ["IdleLatency", "Download", "Upload"]);
+    for r in requests {
+        // The mock is addressed by IP over plain HTTP
+        assert!(r["dns_ms"].is_null() && r["tls_ms"].is_null());
+        assert!(r["connect_ms"].is_f64());
+        assert!(r["ttfb_ms"].as_f64().unwrap() > 0.0);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn steady_window_selects_the_measured_part() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(
+        &server,
+        &[
+            "--json",
+            "--no-upload",
+            "--warmup",
+            "500ms",
+            "--steady-window",
+            "last-1s",
+        ],
+    )
+    .await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["download"]["steady_window"], "last-1s");
+    let duration_ms = result["download"]["duration_ms"].as_u64().unwrap();
+    assert!((600..=1100).contains(&duration_ms), "{duration_ms} ms");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn skipped_phase_is_null() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(&server, &["--json", "--no-upload"]).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert!(result["upload"].is_null());
+    assert!(result["loaded_latency_upload"].is_null());
+    assert!(result["download"]["mbps"].as_f64().unwrap() > 0.0);
+    assert!(result["idle_latency"]["median_ms"].is_f64());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn influx_export_appends_line_protocol() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let path = std::env::temp_dir().join(format!(
+        "cloudflare-speed-cli-e2e-{}.influx",
+        std::process::id()
+    ));
+    let out = run_cli(
+        &server,
+        &["--text", "--export-influx", path.to_str().unwrap()],
+    )
+    .await;
+    assert_success(&out);
+
+    let exported = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+    let line = exported.lines().next().unwrap();
+    assert!(line.starts_with("speedtest,"), "{}", line);
+    assert!(line.contains("colo=TST"), "{}", line);
+    assert!(line.contains(" partial=false,download_mbps="), "{}", line);
+    // Trailing nanosecond timestamp
+    let ts = line.rsplit(' ').next().unwrap();
+    assert!(
+        ts.len() >= 19 && ts.bytes().all(|b| b.is_ascii_digit()),
+        "{}",
+        line
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn har_export_records_measurement_requests() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let path = std::env::temp_dir().join(format!(
+        "cloudflare-speed-cli-e2e-{}.har",
+        std::process::id()
+    ));
+    let out = run_cli(&server, &["--json", "--export-har", path.to_str().unwrap()]).await;
+    assert_success(&out);
+
+    let har: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).ok();
+    assert_eq!(har["log"]["version"], "1.2");
+    let entries = har["log"]["entries"].as_array().unwrap();
+    let with_path = |p: &str| {
+        entries
+            .iter()
+            .filter(|e| e["request"]["url"].as_str().unwrap().contains(p))
+            .collect::<Vec<_>>()
+    };
+    let downloads = with_path("/__down");
+    assert!(!with_path("/__up").is_empty());
+    assert!(downloads
+        .iter()
+        .all(|e| e["response"]["status"] == 200 && e["timings"]["wait"].as_f64().unwrap() > 0.0));
+    // Throughput requests carry a body; their size is what was read of it
+    assert!(downloads
+        .iter()
+        .any(|e| e["response"]["bodySize"].as_i64().unwrap() > 100_000));
+}
+
+#[cfg(feature = "mqtt")]
+#[tokio::test(flavor = "multi_thread")]
+async fn mqtt_publishes_state_and_discovery() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let broker = mock::MockBroker::start().await;
+    let out = run_cli(
+        &server,
+        &[
+            "--text",
+            "--mqtt",
+            &broker.url,
+            "--mqtt-topic",
+            "home/speed",
+        ],
+    )
+    .await;
+    assert_success(&out);
+
+    let messages = broker.messages.lock().unwrap().clone();
+    let state = messages
+        .iter()
+        .find(|(topic, _)| topic == "home/speed/state")
+        .expect("state message");
+    let state: serde_json::Value = serde_json::from_slice(&state.1).unwrap();
+    assert!(state["download_mbps"].as_f64().unwrap() > 0.0);
+    assert_eq!(state["colo"], mock::COLO);
+
+    let download = messages
+        .iter()
+        .find(|(topic, _)| topic == "homeassistant/sensor/home_speed/download_mbps/config")
+        .expect("download discovery config");
+    let config: serde_json::Value = serde_json::from_slice(&download.1).unwrap();
+    assert_eq!(config["state_topic"], "home/speed/state");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn webhook_is_retried_until_delivered() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let url = format!("{}/__webhook", server.base_url);
+    let out = run_cli(&server, &["--json", "--webhook-url", &url]).await;
+    assert_success(&out);
+
+    // The mock fails the first delivery, so the retry carries the result
+    let received = server.webhooks.lock().unwrap().clone();
+    assert_eq!(received.len(), 2);
+    let delivered: serde_json::Value = serde_json::from_slice(&received[1]).unwrap();
+    let printed: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(delivered["meas_id"], printed["meas_id"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn config_file_provides_defaults() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let extra = ["--comments", "from-cli"];
+    let home = home_for(&extra);
+    let dir = home.join("cloudflare-speed-cli");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("config.toml"),
+        "json = true\ncomments = \"from-file\"\nanchors = [\"127.0.0.1:1\"]\n",
+    )
+    .unwrap();
+
+    let out = run_cli(&server, &extra).await;
+    assert_success(&out);
+    // JSON output comes from the file; the command line wins over the file's comment
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["comments"], "from-cli");
+    assert_eq!(result["anchors"][0]["target"], "127.0.0.1:1");
+
+    // The environment overrides the file
+    let shown = run_with_env(
+        &home,
+        &["config", "show"],
+        &[
+            ("CFSPEED_COMMENTS", "from-env"),
+            ("CFSPEED_INTERVAL", "900"),
+        ],
+    )
+    .await;
+    assert_success(&shown);
+    let shown = String::from_utf8_lossy(&shown.stdout);
+    assert!(shown.contains("json = true  # config file"), "{}", shown);
+    assert!(
+        shown.contains("comments = \"from-env\"  # environment"),
+        "{}",
+        shown
+    );
+    assert!(shown.contains("interval = 900  # environment"), "{}", shown);
+    std::fs::remove_dir_all(&home).ok();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sinks_come_from_the_config_file_and_environment() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let home = home_for(&["sink-settings"]);
+    std::fs::remove_dir_all(&home).ok();
+    let dir = home.join("cloudflare-speed-cli");
+    std::fs::create_dir_all(&dir).unwrap();
+    let from_file = home.join("from-file.json");
+    std::fs::write(
+        dir.join("config.toml"),
+        format!("sink = [\"file-json:{}\"]\n", from_file.display()),
+    )
+    .unwrap();
+    let args = [
+        "--base-url",
+        &server.base_url,
+        "--download-duration",
+        "1s",
+        "--upload-duration",
+        "1s",
+        "--idle-latency-duration",
+        "1s",
+        "--skip-diagnostics",
+        "--udp-packets",
+        "0",
+        "--auto-save",
+        "false",
+        "--no-proxy",
+        "--json",
+    ];
+
+    let out = run_in_home(&home, &args).await;
+    assert_success(&out);
+    assert!(from_file.exists());
+
+    // The environment replaces the file's sinks, several of them separated by commas
+    std::fs::remove_file(&from_file).unwrap();
+    let json = home.join("from-env.json");
+    let csv = home.join("from-env.csv");
+    let sinks = format!("file-json:{},csv-append:{}", json.display(), csv.display());
+    let out = run_with_env(&home, &args, &[("CFSPEED_SINKS", &sinks)]).await;
+    assert_success(&out);
+    assert!(json.exists() && csv.exists());
+    assert!(!from_file.exists());
+    std::fs::remove_dir_all(&home).ok();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn history_query_and_prune() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let extra = ["--json", "--sink", "history", "--tag", "site=lab"];
+    let home = home_for(&extra);
+    std::fs::remove_dir_all(&home).ok();
+    let out = run_cli(&server, &extra).await;
+    assert_success(&out);
+    let printed: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+
+    #[cfg(feature = "sqlite")]
+    {
+        let migrated = run_in_home(&home, &["history", "migrate-sqlite"]).await;
+        assert_success(&migrated);
+        assert!(String::from_utf8_lossy(&migrated.stdout).contains("Imported 1 run"));
+    }
+
+    let query = |colo: &'static str| {
+        let home = home.clone();
+        async move {
+            let out = run_in_home(&home, &["history", "query", "--json", "--colo", colo]).await;
+            assert_success(&out);
+            serde_json::from_slice::<Vec<serde_json::Value>>(&out.stdout).unwrap()
+        }
+    };
+    let matching = query(mock::COLO).await;
+    assert_eq!(matching.len(), 1);
+    assert_eq!(matching[0]["meas_id"], printed["meas_id"]);
+    assert_eq!(matching[0]["tags"]["site"], "lab");
+    assert!(query("NOPE").await.is_empty());
+    // Whether the run went over a VPN depends on the host's default interface
+    let vpn = printed["vpn_detected"].as_bool().unwrap();
+    for (flag, expected) in [(vpn, 1), (!vpn, 0)] {
+        let out = run_in_home(
+            &home,
+            &["history", "query", "--json", "--vpn", &flag.to_string()],
+        )
+        .await;
+        assert_success(&out);
+        let runs: Vec<serde_json::Value> = serde_json::from_slice(&out.stdout).unwrap();
+        assert_eq!(runs.len(), expected);
+    }
+    let table = run_in_home(&home, &["history", "query"]).await;
+    assert!(String::from_utf8_lossy(&table.stdout).contains("[site=lab]"));
+
+    // The JSON store lists history from an index built on first use
+    #[cfg(not(feature = "sqlite"))]
+    {
+        let index = home.join("cloudflare-speed-cli/runs/index.jsonl");
+        let rows = std::fs::read_to_string(&index).unwrap();
+        assert_eq!(rows.lines().count(), 1);
+        assert!(rows.contains(printed["meas_id"].as_str().unwrap()));
+
+        // Compressed runs are rewritten in place and still read back transparently
+        let compressed = run_in_home(&home, &["history", "compress", "--format", "gzip"]).await;
+        assert_success(&compressed);
+        assert!(String::from_utf8_lossy(&compressed.stdout).contains("Rewrote 1 run"));
+        let rows = std::fs::read_to_string(&index).unwrap();
+        assert!(rows.contains(".json.gz"));
+        assert_eq!(query(mock::COLO).await.len(), 1);
+    }
+
+    for (format, lines) in [("csv", 2), ("ndjson", 1)] {
+        let out_path = home.join(format!("export.{}", format));
+        let out_path = out_path.to_str().unwrap();
+        let exported = run_in_home(
+            &home,
+            &[
+                "history", "export", "--all", "--format", format, "--out", out_path,
+            ],
+        )
+        .await;
+        assert_success(&exported);
+        let data = std::fs::read_to_string(out_path).unwrap();
+        assert_eq!(data.lines().count(), lines);
+        assert!(data.contains(printed["meas_id"].as_str().unwrap()));
+    }
+
+    // Re-importing our own export changes nothing; a speed.cloudflare.com result adds a run
+    let website = home.join("website.json");
+    let record = serde_json::json!([{
+        "date": "2020-01-01T00:00:00Z",
+        "download": 50_000_000.0,
+        "upload": 10_000_000.0,
+        "latency": 15.0,
+        "colo": mock::COLO,
+    }]);
+    std::fs::write(&website, record.to_string()).unwrap();
+    for file in [home.join("export.ndjson"), website] {
+        let imported = run_in_home(&home, &["history", "import", file.to_str().unwrap()]).await;
+        assert_success(&imported);
+        assert!(String::from_utf8_lossy(&imported.stdout).contains("Imported 1 run"));
+    }
+    let runs = query(mock::COLO).await;
+    assert_eq!(runs.len(), 2);
+    assert_eq!(runs[1]["download"]["mbps"], 50.0);
+
+    // A run from just now survives a day-based limit but not a zero run count
+    let pruned = run_in_home(&home, &["history", "prune", "--keep-days", "1"]).await;
+    assert_success(&pruned);
+    assert_eq!(query(mock::COLO).await.len(), 1);
+    let pruned = run_in_home(&home, &["history", "prune", "--keep-runs", "0"]).await;
+    assert_success(&pruned);
+    assert!(String::from_utf8_lossy(&pruned.stdout).contains("Deleted 1 run"));
+    assert!(query(mock::COLO).await.is_empty());
+    std::fs::remove_dir_all(&home).ok();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ramped_measurements_cover_both_directions() {
+    let server = MockServer::start(Shaping::mbps(5, 400, 400)).await;
+    let out = run_cli(&server, &["--json", "--ramped"]).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let measurements = result["measurements"].as_array().unwrap();
+    for direction in ["Download", "Upload"] {
+        let sizes: Vec<u64> = measurements
+            .iter()
+            .filter(|m| m["direction"] == direction)
+            .map(|m| m["bytes"].as_u64().unwrap())
+            .collect();
+        assert!(
+            sizes.starts_with(&[100_000, 1_000_000]),
+            "{direction} sizes {sizes:?}"
+        );
+    }
+    assert!(measurements[0]["median_mbps"].as_f64().unwrap() > 0.0);
+    assert!(measurements[0]["median_latency_ms"].as_f64().unwrap() >= 5.0);
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn ctrl_c_returns_partial_result() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let home = home_for(&["ctrl-c"]);
+    let child = tokio::process::Command::new(env!("CARGO_BIN_EXE_cloudflare-speed-cli"))
+        .args([
+            "--base-url",
+            &server.base_url,
+            "--json",
+            "--download-duration",
+            "30s",
+            "--idle-latency-duration",
+            "1s",
+            "--skip-diagnostics",
+            "--udp-packets",
+            "0",
+            "--auto-save",
+            "false",
+            "--no-proxy",
+        ])
+        .env("HOME", &home)
+        .env("XDG_DATA_HOME", &home)
+        .env("XDG_CONFIG_HOME", &home)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("run cloudflare-speed-cli");
+
+    // Interrupt the download phase
+    tokio::time::sleep(Duration::from_secs(4)).await;
+    let pid = child.id().expect("child is running") as libc::pid_t;
+    unsafe { libc::kill(pid, libc::SIGINT) };
+    let out = tokio::time::timeout(Duration::from_secs(20), child.wait_with_output())
+        .await
+        .expect("run winds down after Ctrl-C")
+        .unwrap();
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["partial"], true);
+    assert!(result["idle_latency"]["median_ms"].as_f64().is_some());
+    assert!(result["download"]["duration_ms"].as_u64().unwrap() < 10_000);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn traffic_goes_through_the_proxy() {
+    // The mock also serves absolute-form requests, so it doubles as a forward proxy for a
+    // host that does not resolve
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let proxy = server.base_url.replace("http://", "http://user:secret@");
+    let base = [
+        "--base-url",
+        "http://speed.test.invalid",
+        "--download-duration",
+        "2s",
+        "--idle-latency-duration",
+        "1s",
+        "--skip-diagnostics",
+        "--udp-packets",
+        "0",
+        "--auto-save",
+        "false",
+        "--json",
+        "--no-upload",
+    ];
+    let redacted = format!("{}/", server.base_url);
+
+    let mut args = base.to_vec();
+    args.extend(["--proxy", &proxy]);
+    let out = run_in_home(&home_for(&["proxy"]), &args).await;
+    assert_success(&out);
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["proxy"], redacted.as_str());
+    assert!(result["download"]["bytes"].as_u64().unwrap() > 0);
+
+    // Picked up from the environment without --proxy
+    let out = run_with_env(
+        &home_for(&["proxy-env"]),
+        &base,
+        &[("HTTP_PROXY", proxy.as_str()), ("NO_PROXY", "")],
+    )
+    .await;
+    assert_success(&out);
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["proxy"], redacted.as_str());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Detected system proxy from HTTP_PROXY"));
+
+    // HTTP/3 would go around the system proxy, so it is refused like with --proxy
+    let mut args = base.to_vec();
+    args.extend(["--protocol", "h3"]);
+    let out = run_with_env(
+        &home_for(&["proxy-env", "h3"]),
+        &args,
+        &[("HTTP_PROXY", proxy.as_str()), ("NO_PROXY", "")],
+    )
+    .await;
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("HTTP/3 cannot be used through a proxy"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn socks_proxy_skips_the_udp_probe() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let socks = MockSocks::start(server.base_url.trim_start_matches("http://")).await;
+    // Packet loss stays selected (no --udp-packets 0): the SOCKS proxy turns it off
+    let args = [
+        "--base-url",
+        "http://speed.test.invalid",
+        "--proxy",
+        &socks.url,
+        "--download-duration",
+        "2s",
+        "--idle-latency-duration",
+        "1s",
+        "--skip-diagnostics",
+        "--no-captive-portal-check",
+        "--auto-save",
+        "false",
+        "--json",
+        "--no-upload",
+    ];
+    let out = run_in_home(&home_for(&["socks"]), &args).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["proxy"], socks.url.as_str());
+    assert!(result["download"]["bytes"].as_u64().unwrap() > 0);
+    assert!(result["experimental_udp"].is_null());
+    assert!(result["udp_skipped"].as_str().unwrap().contains("SOCKS"));
+    // socks5h: the proxy resolves the name
+    let targets = socks.targets.lock().unwrap();
+    assert!(!targets.is_empty());
+    assert!(targets.iter().all(|t| t == "speed.test.invalid:80"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn packet_loss_is_measured_through_the_turn_relay() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let args = [
+        "--base-url",
+        &server.base_url,
+        "--packet-loss-only",
+        "--udp-packets",
+        "5",
+        "--udp-packet-size",
+        "99",
+        "--udp-interval-ms",
+        "10",
+        "--udp-timeout-ms",
+        "300",
+        "--skip-diagnostics",
+        "--auto-save",
+        "false",
+        "--no-proxy",
+        "--json",
+    ];
+    let out = run_in_home(&home_for(&["turn"]), &args).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let udp = &result["experimental_udp"];
+    assert_eq!(udp["latency"]["received"], 5);
+    // Padded up to whole STUN attributes
+    assert_eq!(
+        udp["params"],
+        serde_json::json!({
+            "packets": 5,
+            "packet_size": 100,
+            "interval_ms": 10,
+            "timeout_ms": 300,
+        })
+    );
+    assert_eq!(
+        udp["mapped_address"].as_str().unwrap().split(':').next(),
+        Some("127.0.0.1")
+    );
+    let relay = &udp["relay"];
+    assert!(udp["relay_error"].is_null(), "{}", udp["relay_error"]);
+    assert_eq!(relay["latency"]["sent"], 5);
+    assert_eq!(relay["latency"]["received"], 5);
+    assert_eq!(relay["relayed_addresses"].as_array().unwrap().len(), 2);
+    assert_eq!(*server.turn.allocations.lock().unwrap(), 2);
+    // Credentials are not kept with the result
+    assert!(result["turn"]["username"].is_string());
+    assert!(result["turn"]["credential"].is_null());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn udp_throughput_streams_through_the_turn_relay() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let args = [
+        "--base-url",
+        &server.base_url,
+        "--packet-loss-only",
+        "--udp-packets",
+        "0",
+        "--udp-throughput",
+        "1s",
+        "--udp-throughput-rate",
+        "2",
+        "--skip-diagnostics",
+        "--auto-save",
+        "false",
+        "--no-proxy",
+        "--json",
+    ];
+    let out = run_in_home(&home_for(&["udp-throughput"]), &args).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let udp = &result["udp_throughput"];
+    assert!(udp["error"].is_null(), "{}", udp["error"]);
+    assert_eq!(udp["target_mbps"], 2.0);
+    assert_eq!(udp["packet_size"], 1200);
+    // 2 Mbit/s of 1200 byte datagrams is about 200 a second
+    let sent = udp["sent"].as_u64().unwrap();
+    assert!((150..=250).contains(&sent), "{sent} sent");
+    assert!(udp["received"].as_u64().unwrap() * 10 >= sent * 9);
+    let goodput = udp["goodput_mbps"].as_f64().unwrap();
+    assert!(goodput > 1.0 && goodput < 3.0, "{goodput} Mbps");
+    // No loss probe packets, so no relayed loss probe either
+    assert!(result["experimental_udp"]["relay"].is_null());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_certificate_is_presented_for_mtls() {
+    let server = MockServer::start_mtls(Shaping::mbps(5, 80, 40)).await;
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mtls");
+    let ca = format!("{fixtures}/ca.pem");
+    let cert = format!("{fixtures}/client.pem");
+    let key = format!("{fixtures}/client.key");
+    let base = [
+        "--base-url",
+        &server.base_url,
+        "--certificate",
+        &ca,
+        "--download-duration",
+        "2s",
+        "--idle-latency-duration",
+        "1s",
+        "--no-captive-portal-check",
+        "--udp-packets",
+        "0",
+        "--auto-save",
+        "false",
+        "--no-proxy",
+        "--json",
+        "--no-upload",
+    ];
+
+    // Diagnostics on, for the timing breakdown
+    let mut args = base.to_vec();
+    args.extend(["--client-cert", &cert, "--client-key", &key]);
+    let out = run_in_home(&home_for(&["mtls"]), &args).await;
+    assert_success(&out);
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert!(result["download"]["bytes"].as_u64().unwrap() > 0);
+    // The hand-made timing request trusts `--certificate` too
+    let timing = &result["timing_breakdown"]["requests"][0];
+    assert!(timing["tls_ms"].as_f64().is_some(), "{timing}");
+    // What the download connections negotiated
+    let download = result["protocols"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|p| p["phase"] == "Download")
+        .unwrap();
+    assert_eq!(download["tls_version"], "TLSv1_3", "{download}");
+    assert!(download["cipher_suite"].as_str().is_some(), "{download}");
+    assert_eq!(download["via_proxy"], false);
+
+    // Without the client certificate the server refuses every connection
+    let mut args = base.to_vec();
+    args.push("--skip-diagnostics");
+    let out = run_in_home(&home_for(&["mtls-none"]), &args).await;
+    assert_success(&out);
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["download"]["bytes"], 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn custom_headers_are_sent_with_every_request() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(
+        &server,
+        &[
+            "--json",
+            "--header",
+            "X-Auth: secret",
+            "--header",
+            "User-Agent: probe/1.0",
+        ],
+    )
+    .await;
+    assert_success(&out);
+
+    let headers = server.headers.lock().unwrap();
+    assert!(headers.contains("x-auth: secret"), "{:?}", headers);
+    let agents: Vec<_> = headers
+        .iter()
+        .filter(|h| h.starts_with("user-agent:"))
+        .collect();
+    assert_eq!(agents, ["user-agent: probe/1.0"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dns_server_resolves_the_endpoint() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let dns = MockDns::start().await;
+    // A name only the mock DNS server knows, on the mock's port
+    let port = server.base_url.rsplit(':').next().unwrap();
+    let base_url = format!("http://speed.test.invalid:{port}");
+    // Diagnostics on, which `run_cli` turns off
+    let args = [
+        "--base-url",
+        &base_url,
+        "--dns-server",
+        &dns.addr,
+        "--download-duration",
+        "2s",
+        "--idle-latency-duration",
+        "1s",
+        "--udp-packets",
+        "0",
+        "--auto-save",
+        "false",
+        "--no-proxy",
+        "--no-captive-portal-check",
+        "--json",
+        "--no-upload",
+    ];
+    let out = run_in_home(&home_for(&["dns-server"]), &args).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert!(result["download"]["bytes"].as_u64().unwrap() > 0);
+    assert_eq!(result["dns"]["resolver"], dns.addr.as_str());
+    assert_eq!(
+        result["dns"]["resolved_ips"],
+        serde_json::json!(["127.0.0.1"])
+    );
+    let timing = &result["timing_breakdown"]["requests"][0];
+    assert!(timing["dns_ms"].as_f64().is_some(), "{timing}");
+    assert!(dns
+        .queries
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|name| name == "speed.test.invalid"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dnssec_check_sees_a_validating_resolver() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let dns = MockDns::start().await;
+    // Diagnostics on, which `run_cli` turns off
+    let args = [
+        "--base-url",
+        &server.base_url,
+        "--dns-server",
+        &dns.addr,
+        "--dnssec",
+        "--download-duration",
+        "1s",
+        "--idle-latency-duration",
+        "1s",
+        "--udp-packets",
+        "0",
+        "--auto-save",
+        "false",
+        "--no-proxy",
+        "--no-captive-portal-check",
+        "--json",
+        "--no-upload",
+    ];
+    let out = run_in_home(&home_for(&["dnssec"]), &args).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["dns"]["dnssec_validating"], true);
+    let detail = result["dns"]["dnssec_detail"].as_str().unwrap();
+    assert!(detail.contains("authenticated"), "{detail}");
+    assert!(dns
+        .queries
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|name| name == mock::DNSSEC_BROKEN));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test(flavor = "multi_thread")]
+async fn traceroute_hops_are_named_by_reverse_dns() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let dns = MockDns::start().await;
+    let out = run_cli(
+        &server,
+        &[
+            "--traceroute",
+            "--traceroute-protocol",
+            "udp",
+            "--dns-server",
+            &dns.addr,
+            "--json",
+            "--no-upload",
+        ],
+    )
+    .await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let traceroute = &result["traceroute"];
+    assert_eq!(traceroute["method"], "udp");
+    assert_eq!(traceroute["completed"], true);
+    let hop = &traceroute["hops"][0];
+    assert_eq!(hop["ip_address"], "127.0.0.1");
+    assert_eq!(hop["hostname"], mock::PTR_NAME);
+    // Loopback has no origin AS, so it is not looked up at Team Cymru
+    assert_eq!(hop["asn"], serde_json::Value::Null);
+    let queries = dns.queries.lock().unwrap();
+    assert!(queries.iter().any(|name| name == "1.0.0.127.in-addr.arpa"));
+    assert!(!queries.iter().any(|name| name.contains("cymru")));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test(flavor = "multi_thread")]
+async fn paris_traceroute_traces_each_flow() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(
+        &server,
+        &[
+            "--traceroute",
+            "--traceroute-protocol",
+            "udp",
+            "--traceroute-paths",
+            "3",
+            "--json",
+            "--no-upload",
+        ],
+    )
+    .await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let traceroute = &result["traceroute"];
+    assert_eq!(traceroute["completed"], true);
+    let paths = traceroute["paths"].as_array().unwrap();
+    assert_eq!(paths.len(), 2);
+    // Every flow leaves from its own source port
+    let mut flows: Vec<u64> = paths.iter().map(|p| p["flow"].as_u64().unwrap()).collect();
+    flows.push(traceroute["flow"].as_u64().unwrap());
+    flows.sort_unstable();
+    flows.dedup();
+    assert_eq!(flows.len(), 3);
+    assert!(paths
+        .iter()
+        .all(|p| p["hops"][0]["ip_address"] == "127.0.0.1"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn encrypted_dns_is_timed_next_to_plain_dns() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let dot = MockDns::start_tls().await;
+    let doh = MockServer::start_tls(Shaping::mbps(5, 80, 40)).await;
+    // A name the system resolver knows too, for the plain lookup to compare with
+    let port = server.base_url.rsplit(':').next().unwrap();
+    let base_url = format!("http://localhost:{port}");
+    let doh_url = format!("{}/dns-query", doh.base_url);
+    let ca = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mtls/ca.pem");
+
+    for (flag, resolver, protocol) in [
+        ("--doh", doh_url.clone(), "DoH"),
+        ("--dot", dot.addr.clone(), "DoT"),
+    ] {
+        // Diagnostics on, which `run_cli` turns off
+        let args = [
+            "--base-url",
+            &base_url,
+            flag,
+            &resolver,
+            "--certificate",
+            ca,
+            "--download-duration",
+            "2s",
+            "--idle-latency-duration",
+            "1s",
+            "--udp-packets",
+            "0",
+            "--auto-save",
+            "false",
+            "--no-proxy",
+            "--no-captive-portal-check",
+            "--json",
+            "--no-upload",
+        ];
+        let out = run_in_home(&home_for(&["encrypted-dns"]), &args).await;
+        assert_success(&out);
+
+        let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+        assert!(result["download"]["bytes"].as_u64().unwrap() > 0);
+        let dns = &result["dns"];
+        assert!(dns["resolution_time_ms"].as_f64().is_some(), "{dns}");
+        assert_eq!(dns["resolver"], serde_json::Value::Null);
+        let encrypted = &dns["encrypted"];
+        assert_eq!(encrypted["protocol"], protocol);
+        assert!(encrypted["resolution_time_ms"].as_f64().unwrap() > 0.0);
+        assert_eq!(encrypted["resolved_ips"], serde_json::json!(["127.0.0.1"]));
+    }
+
+    assert!(doh
+        .headers
+        .lock()
+        .unwrap()
+        .contains("content-type: application/dns-message"));
+    assert_eq!(
+        dot.queries.lock().unwrap().first().map(String::as_str),
+        Some("localhost")
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resolve_pins_the_endpoint() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    // Only the pin makes this name reachable
+    let port = server.base_url.rsplit(':').next().unwrap();
+    let base_url = format!("http://speed.test.invalid:{port}");
+    let pin = format!("speed.test.invalid:{port}:127.0.0.1");
+    let args = [
+        "--base-url",
+        &base_url,
+        "--resolve",
+        "speed.test.invalid:1:192.0.2.1",
+        "--resolve",
+        &pin,
+        "--download-duration",
+        "2s",
+        "--idle-latency-duration",
+        "1s",
+        "--skip-diagnostics",
+        "--udp-packets",
+        "0",
+        "--auto-save",
+        "false",
+        "--no-proxy",
+        "--json",
+        "--no-upload",
+    ];
+    let out = run_in_home(&home_for(&["resolve"]), &args).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert!(result["download"]["bytes"].as_u64().unwrap() > 0);
+    assert_eq!(result["pinned_ip"], "127.0.0.1");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn address_family_flags_limit_the_run() {
+    // The mock only listens on 127.0.0.1, so only the IPv4 run reaches it
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let port = server.base_url.rsplit(':').next().unwrap();
+    let base_url = format!("http://localhost:{port}");
+
+    for (flag, reachable) in [("-4", true), ("-6", false)] {
+        let args = [
+            "--base-url",
+            &base_url,
+            flag,
+            "--download-duration",
+            "2s",
+            "--idle-latency-duration",
+            "1s",
+            "--skip-diagnostics",
+            "--udp-packets",
+            "0",
+            "--auto-save",
+            "false",
+            "--no-proxy",
+            "--json",
+            "--no-upload",
+        ];
+        let out = run_in_home(&home_for(&["family", flag]), &args).await;
+        assert_success(&out);
+        let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+        let bytes = result["download"]["bytes"].as_u64().unwrap();
+        assert_eq!(bytes > 0, reachable, "{flag}: {bytes} bytes");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dscp_marked_probes_still_measure_latency() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(&server, &["--dscp", "EF", "--json", "--no-upload"]).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["dscp"], "EF (46)");
+    // The marked probes go over their own connections, through the relay
+    assert!(result["idle_latency"]["median_ms"].as_f64().unwrap() >= 5.0);
+    assert!(
+        result["loaded_latency_download"]["received"]
+            .as_u64()
+            .unwrap()
+            > 0
+    );
+    assert!(result["download"]["bytes"].as_u64().unwrap() > 0);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test(flavor = "multi_thread")]
+async fn congestion_control_is_set_on_the_test_connections() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    // reno is built into every kernel and always allowed
+    let out = run_cli(&server, &["--congestion", "reno", "--json"]).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["download"]["congestion"], "reno");
+    assert_eq!(result["upload"]["congestion"], "reno");
+    assert!(result["download"]["bytes"].as_u64().unwrap() > 0);
+    assert!(result["upload"]["bytes"].as_u64().unwrap() > 0);
+
+    let out = run_cli(&server, &["--congestion", "no_such_cc", "--json"]).await;
+    assert!(!out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("no_such_cc"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn socket_buffers_are_applied_and_logged() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(
+        &server,
+        &[
+            "--send-buffer",
+            "1MiB",
+            "--recv-buffer",
+            "1MiB",
+            "--json",
+            "--no-upload",
+            "-v",
+        ],
+    )
+    .await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert!(result["download"]["bytes"].as_u64().unwrap() > 0);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("TCP connection socket buffers: send")
+            && stderr.contains("(requested 1048576)"),
+        "{}",
+        stderr
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test(flavor = "multi_thread")]
+async fn mtu_discovery_reports_the_loopback_path() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(&server, &["--mtu", "--json", "--no-upload"]).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let mtu = &result["mtu"];
+    assert!(mtu.is_object(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(mtu["target"], "127.0.0.1");
+    // Loopback carries far more than the search ceiling, so nothing gets fragmented
+    assert_eq!(mtu["path_mtu"], 9000);
+    assert_eq!(mtu["fragmentation_needed"], false);
+    assert_eq!(mtu["mss_clamped"], false);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn icmp_ping_runs_alongside_idle_latency() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(&server, &["--icmp-ping", "--json", "--no-upload"]).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    let icmp = &result["icmp_latency"];
+    assert_eq!(icmp["target"], "127.0.0.1");
+    if icmp["error"].is_null() {
+        assert!(icmp["latency"]["received"].as_u64().unwrap() > 0);
+        // The mock's latency is added to HTTP responses only, not to loopback pings
+        let icmp_ms = icmp["latency"]["median_ms"].as_f64().unwrap();
+        let http_ms = result["idle_latency"]["median_ms"].as_f64().unwrap();
+        assert!(icmp_ms < http_ms, "ICMP {icmp_ms} ms, HTTP {http_ms} ms");
+    } else {
+        // Neither a raw nor a ping socket is allowed here; the run still succeeds
+        assert!(icmp["socket"].is_null());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn captive_portal_stops_the_run() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let check = format!("{}/generate_204", server.base_url);
+    let out = run_cli(
+        &server,
+        &["--captive-portal-url", &check, "--json", "--no-upload"],
+    )
+    .await;
+    assert_success(&out);
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["captive_portal"], false);
+
+    let portal = format!("{}/portal", server.base_url);
+    let out = run_cli(
+        &server,
+        &["--captive-portal-url", &portal, "--json", "--no-upload"],
+    )
+    .await;
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("captive portal") && stderr.contains("http://login.portal.invalid/"),
+        "{stderr}"
+    );
+
+    let out = run_cli(
+        &server,
+        &[
+            "--captive-portal-url",
+            &portal,
+            "--allow-captive-portal",
+            "--json",
+            "--no-upload",
+        ],
+    )
+    .await;
+    assert_success(&out);
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["captive_portal"], true);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn colo_is_requested_and_checked() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(&server, &["--colo", "alt", "--json", "--no-upload"]).await;
+    assert_success(&out);
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["requested_colo"], mock::ALT_COLO);
+    assert_eq!(result["colo"], mock::ALT_COLO);
+
+    // The mock has no such colo and answers from its own
+    let out = run_cli(&server, &["--colo", "SIN", "--json", "--no-upload"]).await;
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("requested colo SIN but TST answered"),
+        "{stderr}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn locations_lists_nearest_colos_with_latency() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let out = run_cli(&server, &["locations", "--probe", "3", "--json"]).await;
+    assert_success(&out);
+
+    let colos: Vec<serde_json::Value> = serde_json::from_slice(&out.stdout).unwrap();
+    let codes: Vec<&str> = colos.iter().map(|c| c["iata"].as_str().unwrap()).collect();
+    assert_eq!(codes, [mock::COLO, mock::ALT_COLO, mock::FAR_COLO]);
+    assert!(colos[0]["distance_km"].as_f64().unwrap() < colos[1]["distance_km"].as_f64().unwrap());
+    assert_eq!(colos[0]["country"], "ZZ");
+    for colo in &colos {
+        assert!(colo["latency_ms"].as_f64().unwrap() >= 5.0, "{colo}");
+    }
+    assert!(colos[1]["served_by"].is_null());
+    // The mock does not serve FAR, so its own colo answered
+    assert_eq!(colos[2]["served_by"], mock::COLO);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unreachable_base_url_fails_over_to_the_next() {
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    // Nothing listens on the discard port
+    let dead = "http://127.0.0.1:9";
+    let args = [
+        "--base-url",
+        dead,
+        "--base-url",
+        &server.base_url,
+        "--download-duration",
+        "2s",
+        "--idle-latency-duration",
+        "1s",
+        "--skip-diagnostics",
+        "--udp-packets",
+        "0",
+        "--auto-save",
+        "false",
+        "--no-proxy",
+        "--no-captive-portal-check",
+        "--json",
+        "--no-upload",
+    ];
+    let out = run_in_home(&home_for(&["failover"]), &args).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["base_url"], server.base_url.as_str());
+    assert_eq!(result["unreachable_endpoints"], serde_json::json!([dead]));
+    assert_eq!(result["colo"], mock::COLO);
+    assert!(result["download"]["bytes"].as_u64().unwrap() > 0);
+
+    // With every base URL down the run fails, naming them
+    let args = [
+        "--base-url",
+        dead,
+        "--base-url",
+        "http://127.0.0.1:7",
+        "--no-proxy",
+        "--json",
+    ];
+    let out = run_in_home(&home_for(&["failover-none"]), &args).await;
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("none of the base URLs answered"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("127.0.0.1:7"), "{stderr}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn self_hosted_worker_with_token_and_paths() {
+    let server = MockServer::start_worker(Shaping::mbps(5, 80, 40), "s3cret").await;
+    let args = [
+        "--base-url",
+        &server.base_url,
+        "--endpoint-auth-token",
+        "s3cret",
+        "--down-path",
+        "/down/{bytes}",
+        "--up-path",
+        "/up",
+        "--download-duration",
+        "2s",
+        "--upload-duration",
+        "2s",
+        "--idle-latency-duration",
+        "1s",
+        "--skip-diagnostics",
+        "--udp-packets",
+        "0",
+        "--auto-save",
+        "false",
+        "--no-proxy",
+        "--no-captive-portal-check",
+        "--json",
+    ];
+    let out = run_in_home(&home_for(&["worker"]), &args).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert!(result["download"]["bytes"].as_u64().unwrap() > 0);
+    assert!(result["upload"]["bytes"].as_u64().unwrap() > 0);
+    assert!(server
+        .headers
+        .lock()
+        .unwrap()
+        .contains("authorization: Bearer s3cret"));
+
+    // A Worker without /locations says so instead of failing to parse
+    let args = [
+        "--base-url",
+        &server.base_url,
+        "--endpoint-auth-token",
+        "s3cret",
+        "--no-proxy",
+        "locations",
+    ];
+    let out = run_in_home(&home_for(&["worker-locations"]), &args).await;
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("no /locations list"), "{}", stderr);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn librespeed_backend_measures_and_saves() {
+    let server = MockServer::start_librespeed(Shaping::mbps(5, 80, 40)).await;
+    let base_url = format!("{}/backend", server.base_url);
+    let home = home_for(&["librespeed"]);
+    let args = [
+        "--backend",
+        "librespeed",
+        "--base-url",
+        &base_url,
+        "--download-duration",
+        "2s",
+        "--upload-duration",
+        "2s",
+        "--idle-latency-duration",
+        "1s",
+        "--skip-diagnostics",
+        "--udp-packets",
+        "0",
+        "--no-proxy",
+        "--no-captive-portal-check",
+        "--json",
+        "--sink",
+        "history",
+    ];
+    let out = run_in_home(&home, &args).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["backend"], "librespeed");
+    assert!(result["download"]["bytes"].as_u64().unwrap() > 0);
+    assert!(result["upload"]["bytes"].as_u64().unwrap() > 0);
+    assert!(result["idle_latency"]["median_ms"].as_f64().is_some());
+    assert_eq!(result["meta"]["clientIp"], "127.0.0.1");
+    assert_eq!(result["meta"]["asn"], 64512);
+
+    let saved = run_in_home(&home, &["history", "query", "--json"]).await;
+    assert_success(&saved);
+    let saved: Vec<serde_json::Value> = serde_json::from_slice(&saved.stdout).unwrap();
+    std::fs::remove_dir_all(&home).ok();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0]["backend"], "librespeed");
+
+    // LibreSpeed servers have no colos to pick
+    let out = run_in_home(
+        &home_for(&["librespeed-colo"]),
+        &[
+            "--backend",
+            "librespeed",
+            "--base-url",
+            &base_url,
+            "--colo",
+            "AMS",
+            "--no-proxy",
+            "--json",
+        ],
+    )
+    .await;
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains("--colo needs the cloudflare backend"),
+        "{}",
+        stderr
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lan_mode_between_two_instances() {
+    use tokio::io::AsyncBufReadExt;
+
+    let home = home_for(&["lan"]);
+    let mut server = tokio::process::Command::new(env!("CARGO_BIN_EXE_cloudflare-speed-cli"))
+        .args(["serve", "--lan", "127.0.0.1:0"])
+        .env("HOME", &home)
+        .stdout(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("run cloudflare-speed-cli serve");
+    // "Serving LAN tests on 127.0.0.1:PORT (TCP and UDP)"
+    let mut lines = tokio::io::BufReader::new(server.stdout.take().unwrap()).lines();
+    let first = lines.next_line().await.unwrap().unwrap();
+    let addr = first.split_whitespace().nth(4).unwrap().to_string();
+
+    let args = [
+        "--lan",
+        &addr,
+        "--download-duration",
+        "2s",
+        "--upload-duration",
+        "2s",
+        "--idle-latency-duration",
+        "1s",
+        "--udp-throughput",
+        "1s",
+        "--udp-throughput-rate",
+        "5",
+        "--json",
+        "--sink",
+        "history",
+    ];
+    let out = run_in_home(&home, &args).await;
+    assert_success(&out);
+
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["backend"], "lan");
+    assert_eq!(result["base_url"], format!("lan://{}", addr));
+    assert!(result["idle_latency"]["median_ms"].as_f64().is_some());
+    assert!(
+        result["loaded_latency_download"]["received"]
+            .as_u64()
+            .unwrap()
+            > 0
+    );
+    assert!(result["download"]["mbps"].as_f64().unwrap() > 0.0);
+    let upload = &result["upload"];
+    assert!(upload["bytes"].as_u64().unwrap() > 0);
+    assert_eq!(upload["bytes"], upload["acknowledged_bytes"]);
+    let udp = &result["udp_throughput"];
+    assert!(udp["sent"].as_u64().unwrap() > 0);
+    assert!(udp["received"].as_u64().unwrap() > 0, "{}", udp);
+
+    // Saved next to internet runs
+    let saved = run_in_home(&home, &["history", "query", "--json"]).await;
+    assert_success(&saved);
+    let saved: Vec<serde_json::Value> = serde_json::from_slice(&saved.stdout).unwrap();
+    std::fs::remove_dir_all(&home).ok();
+    assert_eq!(saved[0]["backend"], "lan");
+
+    // Nothing listening is reported before any phase runs
+    drop(server);
+    let out = run_in_home(&home_for(&["lan-down"]), &["--lan", &addr, "--json"]).await;
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("is `serve --lan` running?"), "{}", stderr);
+}
+
+#[tokio::test]
+async fn api_runs_tests_and_serves_results() {
+    use futures::StreamExt;
+    use tokio::io::AsyncBufReadExt;
+
+    let server = MockServer::start(Shaping::mbps(5, 80, 40)).await;
+    let home = home_for(&["api"]);
+    let mut api = tokio::process::Command::new(env!("CARGO_BIN_EXE_cloudflare-speed-cli"))
+        .args([
+            "--base-url",
+            &server.base_url,
+            "--download-duration",
+            "2s",
+            "--upload-duration",
+            "2s",
+            "--idle-latency-duration",
+            "1s",
+            "--skip-diagnostics",
+            "--udp-packets",
+            "0",
+            "--no-proxy",
+            "serve",
+            "--api",
+            "127.0.0.1:0",
+        ])
+        .env("HOME", &home)
+        .env("XDG_DATA_HOME", &home)
+        .env("XDG_CONFIG_HOME", &home)
+        .stdout(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("run cloudflare-speed-cli serve");
+    // "Serving the API on http://127.0.0.1:PORT/api"
+    let mut lines = tokio::io::BufReader::new(api.stdout.take().unwrap()).lines();
+    let first = lines.next_line().await.unwrap().unwrap();
+    let url = first.split_whitespace().nth(4).unwrap().to_string();
+    let client = reqwest::Client::builder().no_proxy().build().unwrap();
+
+    let latest = client
+        .get(format!("{url}/results/latest"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(latest.status(), 404);
+    assert!(!latest.headers().contains_key("access-control-allow-origin"));
+
+    let events = client.get(format!("{url}/events")).send().await.unwrap();
+    assert_eq!(events.headers()["content-type"], "text/event-stream");
+    let mut events = events.bytes_stream();
+
+    // What a cross-site form or script could send does not start a test
+    let form = client.post(format!("{url}/run")).send().await.unwrap();
+    assert_eq!(form.status(), 415);
+    let run = || {
+        client
+            .post(format!("{url}/run"))
+            .header("content-type", "application/json")
+    };
+    let foreign = run().header("origin", "https://example.com").send().await;
+    assert_eq!(foreign.unwrap().status(), 403);
+    // Nor can it read results
+    let foreign = client
+        .get(format!("{url}/results"))
+        .header("origin", "https://example.com")
+        .send()
+        .await;
+    assert_eq!(foreign.unwrap().status(), 403);
+
+    let started = run().send().await.unwrap();
+    assert_eq!(started.status(), 202);
+    let meas_id = started.json::<serde_json::Value>().await.unwrap()["meas_id"].clone();
+    let busy = run().send().await.unwrap();
+    assert_eq!(busy.status(), 409);
+    let status: serde_json::Value = client
+        .get(format!("{url}/status"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(status["running"], true);
+    assert_eq!(status["meas_id"], meas_id);
+
+    // Progress as messages, then the result as a `result` event
+    let mut stream = String::new();
+    let result = tokio::time::timeout(Duration::from_secs(60), async {
+        loop {
+            stream.push_str(&String::from_utf8_lossy(
+                &events.next().await.unwrap().unwrap(),
+            ));
+            let Some(at) = stream.find("event: result\ndata: ") else {
+                continue;
+            };
+            let frame = &stream[at + "event: result\ndata: ".len()..];
+            if let Some((data, _)) = frame.split_once("\n\n") {
+                return serde_json::from_str::<serde_json::Value>(data).unwrap();
+            }
+        }
+    })
+    .await
+    .expect("no result event");
+    assert!(stream.contains("event: started\n"), "{}", stream);
+    assert!(
+        stream.contains("data: {\"type\":\"phase_started\""),
+        "{}",
+        stream
+    );
+    assert_eq!(result["meas_id"], meas_id);
+    assert!(result["download"]["mbps"].as_f64().unwrap() > 0.0);
+
+    let latest: serde_json::Value = client
+        .get(format!("{url}/results/latest"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(latest["meas_id"], meas_id);
+    let saved: Vec<serde_json::Value> = client
+        .get(format!("{url}/results?limit=5&since=2000-01-01"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0]["meas_id"], meas_id);
+    let bad = client.get(format!("{url}/results?vpn=maybe")).send().await;
+    assert_eq!(bad.unwrap().status(), 400);
+    let missing = client.get(format!("{url}/nothing")).send().await;
+    assert_eq!(missing.unwrap().status(), 404);
+    std::fs::remove_dir_all(&home).ok();
+}
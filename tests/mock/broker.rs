@@ -0,0 +1,102 @@
+//! Minimal MQTT 3.1.1 broker for the `--mqtt` end-to-end test
+//!
+//! Accepts any CONNECT, acknowledges QoS 1 PUBLISHes and records every published
+//! message. There is no subscription support; tests inspect `messages` directly.
+
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// (topic, payload) of every message received so far
+pub type Messages = Arc<Mutex<Vec<(String, Vec<u8>)>>>;
+
+/// Running mock broker; stops when dropped.
+pub struct MockBroker {
+    pub url: String,
+    pub messages: Messages,
+    task: JoinHandle<()>,
+}
+
+impl MockBroker {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind broker");
+        let addr = listener.local_addr().unwrap();
+        let messages = Arc::new(Mutex::new(Vec::new()));
+
+        let received = messages.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    continue;
+                };
+                tokio::spawn(serve(stream, received.clone()));
+            }
+        });
+
+        Self {
+            url: format!("mqtt://{}", addr),
+            messages,
+            task,
+        }
+    }
+}
+
+impl Drop for MockBroker {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(mut stream: TcpStream, received: Messages) {
+    loop {
+        let Ok(header) = stream.read_u8().await else {
+            return;
+        };
+        // Remaining length is a base-128 varint
+        let mut len = 0usize;
+        for shift in 0..4 {
+            let Ok(b) = stream.read_u8().await else {
+                return;
+            };
+            len |= usize::from(b & 0x7f) << (7 * shift);
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        if stream.read_exact(&mut body).await.is_err() {
+            return;
+        }
+
+        let reply: &[u8] = match header >> 4 {
+            // CONNECT -> CONNACK (accepted)
+            1 => &[0x20, 0x02, 0x00, 0x00],
+            // PUBLISH -> record, PUBACK for QoS 1
+            3 => {
+                let qos = (header >> 1) & 0x03;
+                let topic_len = usize::from(u16::from_be_bytes([body[0], body[1]]));
+                let topic = String::from_utf8_lossy(&body[2..2 + topic_len]).into_owned();
+                let mut rest = &body[2 + topic_len..];
+                let mut ack = Vec::new();
+                if qos > 0 {
+                    ack = vec![0x40, 0x02, rest[0], rest[1]];
+                    rest = &rest[2..];
+                }
+                received.lock().unwrap().push((topic, rest.to_vec()));
+                if !ack.is_empty() && stream.write_all(&ack).await.is_err() {
+                    return;
+                }
+                continue;
+            }
+            // PINGREQ -> PINGRESP
+            12 => &[0xd0, 0x00],
+            // DISCONNECT
+            14 => return,
+            _ => continue,
+        };
+        if stream.write_all(reply).await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,146 @@
+//! Minimal DNS server for the `--dns-server` and `--dot` end-to-end tests
+//!
+//! Answers every A query with 127.0.0.1, every PTR query with `PTR_NAME` and every
+//! other query with no records, and records the names asked for. Like a validating
+//! resolver, it fails names under `DNSSEC_BROKEN` and marks the other answers
+//! authenticated when asked to. `start` serves plain DNS over UDP, `start_tls`
+//! DNS-over-TLS with the `localhost` certificate of `tests/fixtures/mtls`.
+
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+
+/// Name every address points back to
+pub const PTR_NAME: &str = "hop.mock.test";
+
+/// Zone answered with SERVFAIL, as for broken DNSSEC signatures
+pub const DNSSEC_BROKEN: &str = "dnssec-failed.org";
+
+/// Running mock DNS server; stops when dropped.
+pub struct MockDns {
+    /// "IP:PORT" to pass to `--dns-server`, "localhost:PORT" for `--dot`
+    pub addr: String,
+    /// Names of every query so far
+    pub queries: Arc<Mutex<Vec<String>>>,
+    task: JoinHandle<()>,
+}
+
+impl MockDns {
+    pub async fn start() -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.expect("bind dns");
+        let addr = socket.local_addr().unwrap().to_string();
+        let queries = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = queries.clone();
+        let task = tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            loop {
+                let Ok((n, peer)) = socket.recv_from(&mut buf).await else {
+                    continue;
+                };
+                if let Some((name, reply)) = answer(&buf[..n]) {
+                    recorded.lock().unwrap().push(name);
+                    socket.send_to(&reply, peer).await.ok();
+                }
+            }
+        });
+
+        Self {
+            addr,
+            queries,
+            task,
+        }
+    }
+
+    pub async fn start_tls() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind dot");
+        let addr = format!("localhost:{}", listener.local_addr().unwrap().port());
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let tls = TlsAcceptor::from(Arc::new(super::server_tls_config(false)));
+
+        let recorded = queries.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    continue;
+                };
+                let tls = tls.clone();
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = tls.accept(stream).await else {
+                        return;
+                    };
+                    // Length-prefixed queries until the client hangs up
+                    while let Ok(len) = stream.read_u16().await {
+                        let mut query = vec![0u8; len as usize];
+                        if stream.read_exact(&mut query).await.is_err() {
+                            return;
+                        }
+                        let Some((name, reply)) = answer(&query) else {
+                            return;
+                        };
+                        recorded.lock().unwrap().push(name);
+                        stream.write_u16(reply.len() as u16).await.ok();
+                        stream.write_all(&reply).await.ok();
+                    }
+                });
+            }
+        });
+
+        Self {
+            addr,
+            queries,
+            task,
+        }
+    }
+}
+
+impl Drop for MockDns {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// The queried name and the reply to a single-question query
+pub(super) fn answer(query: &[u8]) -> Option<(String, Vec<u8>)> {
+    let mut labels = Vec::new();
+    let mut at = 12;
+    loop {
+        let len = *query.get(at)? as usize;
+        at += 1;
+        if len == 0 {
+            break;
+        }
+        labels.push(String::from_utf8_lossy(query.get(at..at + len)?).into_owned());
+        at += len;
+    }
+    let qtype = u16::from_be_bytes([*query.get(at)?, *query.get(at + 1)?]);
+    let question_end = at + 4;
+
+    let mut reply = query.get(..question_end)?.to_vec();
+    // Response, recursion available, AD as the query asks; answer count below
+    let name = labels.join(".");
+    reply[2] = 0x81;
+    reply[3] = 0x80 | (query[3] & 0x20);
+    reply[6..12].fill(0);
+    if name == DNSSEC_BROKEN || name.ends_with(&format!(".{}", DNSSEC_BROKEN)) {
+        reply[3] = 0x82;
+    } else if qtype == 1 {
+        reply[7] = 1;
+        // Pointer to the question name, A, IN, TTL 60, 127.0.0.1
+        reply.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 127, 0, 0, 1]);
+    } else if qtype == 12 {
+        reply[7] = 1;
+        let mut name = Vec::new();
+        for label in PTR_NAME.split('.') {
+            name.push(label.len() as u8);
+            name.extend(label.as_bytes());
+        }
+        name.push(0);
+        reply.extend([0xc0, 12, 0, 12, 0, 1, 0, 0, 0, 60, 0, name.len() as u8]);
+        reply.extend(name);
+    }
+    Some((name, reply))
+}
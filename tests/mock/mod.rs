@@ -0,0 +1,519 @@
+//! Local mock of the speed.cloudflare.com endpoints used by the end-to-end tests
+//!
+//! Serves `/__down`, `/__up`, `/meta`, `/locations`, `/__turn` and `/cdn-cgi/trace`
+//! over plain HTTP/1.1 or HTTP/2 (prior knowledge) on an ephemeral port, plus
+//! `/__webhook`, which records request bodies and fails the first delivery with a 503 to
+//! exercise retries, and `/dns-query`, a DNS-over-HTTPS endpoint answering like
+//! `MockDns`. Every response is delayed by the configured latency, and transfer bodies
+//! are paced by a token bucket shared by all connections so the aggregate throughput
+//! matches the configured rate. `start_mtls` serves the same over TLS and requires a
+//! client certificate, `start_tls` serves it over TLS without one. `/__turn` points at a
+//! `MockTurn` started alongside.
+
+#[cfg(feature = "mqtt")]
+mod broker;
+#[cfg(feature = "mqtt")]
+pub use broker::MockBroker;
+mod dns;
+mod socks;
+mod turn;
+pub use dns::{MockDns, DNSSEC_BROKEN, PTR_NAME};
+pub use socks::MockSocks;
+pub use turn::MockTurn;
+
+use bytes::Bytes;
+use http_body_util::{combinators::BoxBody, BodyExt, Full, StreamBody};
+use hyper::body::{Frame, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_rustls::TlsAcceptor;
+
+/// Colo reported by the mock, so tests can tell its metadata from the real service's
+pub const COLO: &str = "TST";
+
+/// Second colo the mock serves when a request asks for it with `cf.colo`; any other
+/// requested colo is ignored and `COLO` answers
+pub const ALT_COLO: &str = "ALT";
+
+/// Colo listed by `/locations` that the mock never answers as
+pub const FAR_COLO: &str = "FAR";
+
+/// Size of the chunks download bodies are streamed in
+const CHUNK: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Shaping {
+    /// Delay before every response
+    pub latency: Duration,
+    /// Aggregate download rate in bytes per second (None = unshaped)
+    pub download_bps: Option<u64>,
+    /// Aggregate upload rate in bytes per second (None = unshaped)
+    pub upload_bps: Option<u64>,
+}
+
+impl Shaping {
+    pub fn mbps(latency_ms: u64, download_mbps: u64, upload_mbps: u64) -> Self {
+        Self {
+            latency: Duration::from_millis(latency_ms),
+            download_bps: Some(download_mbps * 1_000_000 / 8),
+            upload_bps: Some(upload_mbps * 1_000_000 / 8),
+        }
+    }
+}
+
+/// Token bucket shared by all transfers in one direction
+struct Pacer {
+    bytes_per_sec: Option<u64>,
+    next: Mutex<Option<Instant>>,
+}
+
+impl Pacer {
+    fn new(bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            bytes_per_sec,
+            next: Mutex::new(None),
+        }
+    }
+
+    /// Wait until `bytes` more may be transferred.
+    async fn pace(&self, bytes: usize) {
+        let Some(bps) = self.bytes_per_sec else {
+            return;
+        };
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let now = Instant::now();
+            let start = next.map_or(now, |n| n.max(now));
+            *next = Some(start + Duration::from_secs_f64(bytes as f64 / bps as f64));
+            start
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+/// Which server the mock imitates
+#[derive(Clone, Copy)]
+enum Flavor {
+    Cloudflare,
+    /// A self-hosted Worker with this bearer token, see `MockServer::start_worker`
+    Worker(&'static str),
+    /// A LibreSpeed server, see `MockServer::start_librespeed`
+    LibreSpeed,
+}
+
+struct State {
+    latency: Duration,
+    down: Pacer,
+    up: Pacer,
+    webhooks: Arc<Mutex<Vec<Vec<u8>>>>,
+    headers: Headers,
+    transferred: Arc<AtomicU64>,
+    turn: std::net::SocketAddr,
+    flavor: Flavor,
+}
+
+/// Distinct "name: value" request headers, names in lowercase
+pub type Headers = Arc<Mutex<BTreeSet<String>>>;
+
+/// Running mock server; stops when dropped.
+pub struct MockServer {
+    pub base_url: String,
+    /// Bodies POSTed to `/__webhook`, including failed deliveries
+    pub webhooks: Arc<Mutex<Vec<Vec<u8>>>>,
+    /// Headers of every request received so far
+    pub headers: Headers,
+    /// Download and upload body bytes sent and received so far
+    pub transferred: Arc<AtomicU64>,
+    /// STUN/TURN server advertised by `/__turn`
+    pub turn: MockTurn,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    pub async fn start(shaping: Shaping) -> Self {
+        Self::serve(shaping, None, Flavor::Cloudflare).await
+    }
+
+    /// Serve like a minimal self-hosted Worker: only `GET /down/{bytes}` and `POST /up`,
+    /// both requiring `Authorization: Bearer <token>`, and 404 for everything else.
+    pub async fn start_worker(shaping: Shaping, token: &'static str) -> Self {
+        Self::serve(shaping, None, Flavor::Worker(token)).await
+    }
+
+    /// Serve like a LibreSpeed server installed in `/backend/`: `garbage.php`, `empty.php`
+    /// and `getIP.php` there, and 404 for everything else.
+    pub async fn start_librespeed(shaping: Shaping) -> Self {
+        Self::serve(shaping, None, Flavor::LibreSpeed).await
+    }
+
+    /// Serve over TLS with the certificates in `tests/fixtures/mtls`, accepting only
+    /// clients that present a certificate signed by its `ca.pem`.
+    pub async fn start_mtls(shaping: Shaping) -> Self {
+        Self::serve(shaping, Some(tls_acceptor(true)), Flavor::Cloudflare).await
+    }
+
+    /// Serve over TLS with the `localhost` certificate in `tests/fixtures/mtls`, without
+    /// asking for a client certificate.
+    pub async fn start_tls(shaping: Shaping) -> Self {
+        Self::serve(shaping, Some(tls_acceptor(false)), Flavor::Cloudflare).await
+    }
+
+    async fn serve(shaping: Shaping, tls: Option<TlsAcceptor>, flavor: Flavor) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind mock");
+        let addr = listener.local_addr().unwrap();
+        let turn = MockTurn::start().await;
+        let state = Arc::new(State {
+            latency: shaping.latency,
+            down: Pacer::new(shaping.download_bps),
+            up: Pacer::new(shaping.upload_bps),
+            webhooks: Arc::new(Mutex::new(Vec::new())),
+            headers: Headers::default(),
+            transferred: Arc::default(),
+            turn: turn.addr,
+            flavor,
+        });
+        let webhooks = state.webhooks.clone();
+        let headers = state.headers.clone();
+        let transferred = state.transferred.clone();
+        let base_url = match tls {
+            Some(_) => format!("https://localhost:{}", addr.port()),
+            None => format!("http://{}", addr),
+        };
+
+        let task = tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    continue;
+                };
+                let state = state.clone();
+                let tls = tls.clone();
+                tokio::spawn(async move {
+                    let service = hyper::service::service_fn(move |req| {
+                        let state = state.clone();
+                        async move { Ok::<_, Infallible>(handle(req, state).await) }
+                    });
+                    let builder =
+                        hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
+                    match tls {
+                        Some(tls) => {
+                            // With `start_mtls`, handshakes without a valid client
+                            // certificate fail here
+                            let Ok(stream) = tls.accept(stream).await else {
+                                return;
+                            };
+                            builder
+                                .serve_connection(TokioIo::new(stream), service)
+                                .await
+                                .ok();
+                        }
+                        None => {
+                            builder
+                                .serve_connection(TokioIo::new(stream), service)
+                                .await
+                                .ok();
+                        }
+                    }
+                });
+            }
+        });
+
+        Self {
+            base_url,
+            webhooks,
+            headers,
+            transferred,
+            turn,
+            task,
+        }
+    }
+}
+
+fn tls_acceptor(client_auth: bool) -> TlsAcceptor {
+    let mut config = server_tls_config(client_auth);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    TlsAcceptor::from(Arc::new(config))
+}
+
+/// TLS with the `localhost` certificate in `tests/fixtures/mtls`, requiring a client
+/// certificate signed by its `ca.pem` when `client_auth` is set
+fn server_tls_config(client_auth: bool) -> rustls::ServerConfig {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mtls");
+    let certs = |name: &str| {
+        CertificateDer::pem_file_iter(format!("{dir}/{name}"))
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    };
+    let builder = rustls::ServerConfig::builder();
+    let builder = if client_auth {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add_parsable_certificates(certs("ca.pem"));
+        let verifier = rustls::server::WebPkiClientVerifier::builder(Arc::new(roots))
+            .build()
+            .unwrap();
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+    builder
+        .with_single_cert(
+            certs("server.pem"),
+            PrivateKeyDer::from_pem_file(format!("{dir}/server.key")).unwrap(),
+        )
+        .unwrap()
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+type Body = BoxBody<Bytes, Infallible>;
+
+fn full(body: impl Into<Bytes>) -> Body {
+    Full::new(body.into()).boxed()
+}
+
+fn json(value: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .header("content-type", "application/json")
+        .body(full(value.to_string()))
+        .unwrap()
+}
+
+async fn handle(req: Request<Incoming>, state: Arc<State>) -> Response<Body> {
+    tokio::time::sleep(state.latency).await;
+    state.headers.lock().unwrap().extend(
+        req.headers().iter().map(|(name, value)| {
+            format!("{}: {}", name, String::from_utf8_lossy(value.as_bytes()))
+        }),
+    );
+
+    match state.flavor {
+        Flavor::Cloudflare => {}
+        Flavor::Worker(token) => return worker(req, state, token).await,
+        Flavor::LibreSpeed => return librespeed(req, state).await,
+    }
+
+    let colo = colo(&req);
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/__down") => {
+            let bytes = query_param(&req, "bytes").and_then(|v| v.parse().ok());
+            download(state, bytes.unwrap_or(0), colo)
+        }
+        (&Method::POST, "/__up") => upload(req, state).await,
+        (&Method::POST, "/__webhook") => webhook(req, state).await,
+        (&Method::POST, "/dns-query") => dns_query(req).await,
+        (&Method::GET, "/meta") => json(serde_json::json!({
+            "clientIp": "127.0.0.1",
+            "colo": colo,
+            "asn": 64512,
+            "asOrganization": "Mock Networks",
+            "city": "Testville",
+            "country": "ZZ",
+            "latitude": "1.0",
+            "longitude": "1.0",
+        })),
+        // Nearest first from the client's position in /meta; FAR_COLO is one the mock
+        // does not serve
+        (&Method::GET, "/locations") => json(serde_json::json!([
+            { "iata": FAR_COLO, "city": "Farville", "cca2": "ZZ", "region": "Mock", "lat": 40.0, "lon": 40.0 },
+            { "iata": ALT_COLO, "city": "Altville", "cca2": "ZZ", "region": "Mock", "lat": 10.0, "lon": 10.0 },
+            { "iata": COLO, "city": "Testville", "cca2": "ZZ", "region": "Mock", "lat": 0.0, "lon": 0.0 },
+        ])),
+        (&Method::GET, "/__turn") => json(serde_json::json!({
+            "urls": [
+                format!("stun:{}", state.turn),
+                format!("turn:{}?transport=udp", state.turn),
+            ],
+            "username": turn::USERNAME,
+            "credential": turn::CREDENTIAL,
+        })),
+        (&Method::GET, "/generate_204") => Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(full(""))
+            .unwrap(),
+        // A captive portal sending every request to its login page
+        (&Method::GET, "/portal") => Response::builder()
+            .status(StatusCode::FOUND)
+            .header("location", "http://login.portal.invalid/")
+            .body(full(""))
+            .unwrap(),
+        (&Method::GET, "/cdn-cgi/trace") => Response::new(full(format!(
+            "ip=127.0.0.1\ncolo={}\nloc=ZZ\nhttp=http/1.1\n",
+            colo
+        ))),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(full(""))
+            .unwrap(),
+    }
+}
+
+/// A self-hosted Worker: the byte count in the download path, no `/meta`, `/__turn` or
+/// `/locations`, and 401 without the token.
+async fn worker(req: Request<Incoming>, state: Arc<State>, token: &str) -> Response<Body> {
+    let authorized = req
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        == Some(token);
+    if !authorized {
+        return Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .body(full(""))
+            .unwrap();
+    }
+    let path = req.uri().path().to_string();
+    match (req.method(), path.strip_prefix("/down/")) {
+        (&Method::GET, Some(bytes)) => match bytes.parse() {
+            Ok(bytes) => download(state, bytes, COLO),
+            Err(_) => Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(full(""))
+                .unwrap(),
+        },
+        (&Method::POST, None) if path == "/up" => upload(req, state).await,
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(full(""))
+            .unwrap(),
+    }
+}
+
+/// A LibreSpeed server: downloads in megabyte chunks, and the client's address from
+/// `getIP.php` instead of in headers.
+async fn librespeed(req: Request<Incoming>, state: Arc<State>) -> Response<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/backend/garbage.php") => {
+            let chunks: usize = query_param(&req, "ckSize")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(4);
+            let mut resp = download(state, chunks << 20, COLO);
+            // LibreSpeed sends no cf-meta-* headers
+            let headers = resp.headers_mut();
+            for name in ["ip", "colo", "asn", "city", "country"] {
+                headers.remove(format!("cf-meta-{}", name));
+            }
+            resp
+        }
+        (&Method::GET, "/backend/empty.php") => Response::new(full("")),
+        (&Method::POST, "/backend/empty.php") => upload(req, state).await,
+        (&Method::GET, "/backend/getIP.php") => json(serde_json::json!({
+            "processedString": "127.0.0.1 - Mock Networks, ZZ",
+            "rawIspInfo": {
+                "ip": "127.0.0.1",
+                "city": "Testville",
+                "country": "ZZ",
+                "org": "AS64512 Mock Networks",
+            },
+        })),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(full(""))
+            .unwrap(),
+    }
+}
+
+fn query_param<'a>(req: &'a Request<Incoming>, name: &str) -> Option<&'a str> {
+    req.uri()
+        .query()
+        .into_iter()
+        .flat_map(|q| q.split('&'))
+        .find_map(|kv| kv.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// The colo answering `req`: `ALT_COLO` when its `cf.colo` asks for it, else `COLO`
+fn colo(req: &Request<Incoming>) -> &'static str {
+    if query_param(req, "cf.colo") == Some(ALT_COLO) {
+        ALT_COLO
+    } else {
+        COLO
+    }
+}
+
+fn download(state: Arc<State>, bytes: usize, colo: &str) -> Response<Body> {
+    let chunks = futures::stream::unfold(bytes, move |left| {
+        let state = state.clone();
+        async move {
+            if left == 0 {
+                return None;
+            }
+            let n = left.min(CHUNK);
+            state.down.pace(n).await;
+            state.transferred.fetch_add(n as u64, Ordering::Relaxed);
+            Some((
+                Ok::<_, Infallible>(Frame::data(Bytes::from(vec![0u8; n]))),
+                left - n,
+            ))
+        }
+    });
+
+    Response::builder()
+        .header("content-length", bytes)
+        .header("cf-meta-ip", "127.0.0.1")
+        .header("cf-meta-colo", colo)
+        .header("cf-meta-asn", "64512")
+        .header("cf-meta-city", "Testville")
+        .header("cf-meta-country", "ZZ")
+        .body(StreamBody::new(chunks).boxed())
+        .unwrap()
+}
+
+async fn webhook(req: Request<Incoming>, state: Arc<State>) -> Response<Body> {
+    let body = req.into_body().collect().await.unwrap().to_bytes();
+    let mut received = state.webhooks.lock().unwrap();
+    received.push(body.to_vec());
+    let status = if received.len() == 1 {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    Response::builder().status(status).body(full("")).unwrap()
+}
+
+async fn dns_query(req: Request<Incoming>) -> Response<Body> {
+    let query = req.into_body().collect().await.unwrap().to_bytes();
+    match dns::answer(&query) {
+        Some((_, reply)) => Response::builder()
+            .header("content-type", "application/dns-message")
+            .body(full(reply))
+            .unwrap(),
+        None => Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(full(""))
+            .unwrap(),
+    }
+}
+
+async fn upload(req: Request<Incoming>, state: Arc<State>) -> Response<Body> {
+    let mut body = req.into_body();
+    while let Some(frame) = body.frame().await {
+        match frame {
+            Ok(frame) => {
+                if let Some(data) = frame.data_ref() {
+                    state.up.pace(data.len()).await;
+                    state
+                        .transferred
+                        .fetch_add(data.len() as u64, Ordering::Relaxed);
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    Response::new(full(""))
+}
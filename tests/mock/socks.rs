@@ -0,0 +1,93 @@
+//! Minimal SOCKS5 proxy for the `--proxy socks5h://` end-to-end test
+//!
+//! Accepts the "no authentication" method and CONNECT requests only, records the
+//! requested target and tunnels every connection to the mock server, whatever the target.
+
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// Running mock proxy; stops when dropped.
+pub struct MockSocks {
+    pub url: String,
+    /// "host:port" of every CONNECT request so far
+    pub targets: Arc<Mutex<Vec<String>>>,
+    task: JoinHandle<()>,
+}
+
+impl MockSocks {
+    /// Start a proxy that forwards to `upstream` ("host:port").
+    pub async fn start(upstream: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind socks");
+        let addr = listener.local_addr().unwrap();
+        let targets = Arc::new(Mutex::new(Vec::new()));
+
+        let upstream = upstream.to_string();
+        let recorded = targets.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    continue;
+                };
+                tokio::spawn(serve(stream, upstream.clone(), recorded.clone()));
+            }
+        });
+
+        Self {
+            url: format!("socks5h://{}", addr),
+            targets,
+            task,
+        }
+    }
+}
+
+impl Drop for MockSocks {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(
+    mut client: TcpStream,
+    upstream: String,
+    targets: Arc<Mutex<Vec<String>>>,
+) -> std::io::Result<()> {
+    // Greeting: version, method count, methods; choose "no authentication"
+    let mut head = [0u8; 2];
+    client.read_exact(&mut head).await?;
+    let mut methods = vec![0u8; head[1] as usize];
+    client.read_exact(&mut methods).await?;
+    client.write_all(&[5, 0]).await?;
+
+    // Request: version, command, reserved, address type, address, port
+    let mut req = [0u8; 4];
+    client.read_exact(&mut req).await?;
+    let host = match req[3] {
+        1 => {
+            let mut ip = [0u8; 4];
+            client.read_exact(&mut ip).await?;
+            std::net::Ipv4Addr::from(ip).to_string()
+        }
+        3 => {
+            let len = client.read_u8().await? as usize;
+            let mut name = vec![0u8; len];
+            client.read_exact(&mut name).await?;
+            String::from_utf8_lossy(&name).into_owned()
+        }
+        4 => {
+            let mut ip = [0u8; 16];
+            client.read_exact(&mut ip).await?;
+            format!("[{}]", std::net::Ipv6Addr::from(ip))
+        }
+        _ => return Ok(()),
+    };
+    let port = client.read_u16().await?;
+    targets.lock().unwrap().push(format!("{}:{}", host, port));
+
+    let mut server = TcpStream::connect(&upstream).await?;
+    // Succeeded, bound to 0.0.0.0:0
+    client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+    tokio::io::copy_bidirectional(&mut client, &mut server).await?;
+    Ok(())
+}
@@ -0,0 +1,190 @@
+//! Minimal STUN/TURN server for the packet loss end-to-end tests
+//!
+//! Answers binding requests, and allocates relayed addresses to clients that
+//! authenticate with `USERNAME`/`CREDENTIAL` under the long-term credential mechanism.
+//! Relayed addresses are not real sockets: a Send indication to one of them is handed
+//! to its client directly as a Data indication, which is all the client can observe.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+pub const USERNAME: &str = "1700000000:mock";
+pub const CREDENTIAL: &str = "mock-credential";
+const REALM: &str = "mock";
+const NONCE: &str = "mock-nonce";
+const COOKIE: [u8; 4] = [0x21, 0x12, 0xA4, 0x42];
+
+/// Running mock TURN server; stops when dropped.
+pub struct MockTurn {
+    pub addr: SocketAddr,
+    /// Allocations made so far
+    pub allocations: Arc<Mutex<usize>>,
+    task: JoinHandle<()>,
+}
+
+impl MockTurn {
+    pub async fn start() -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.expect("bind turn");
+        let addr = socket.local_addr().unwrap();
+        let allocations = Arc::new(Mutex::new(0));
+
+        let counter = allocations.clone();
+        let task = tokio::spawn(async move {
+            // Client address -> relayed address, and back
+            let mut relayed = HashMap::<SocketAddr, SocketAddr>::new();
+            let mut clients = HashMap::<SocketAddr, SocketAddr>::new();
+            let mut buf = [0u8; 1500];
+            loop {
+                let Ok((n, peer)) = socket.recv_from(&mut buf).await else {
+                    continue;
+                };
+                let Some((kind, txid, attrs)) = decode(&buf[..n]) else {
+                    continue;
+                };
+                let attr = |k: u16| attrs.iter().find(|(a, _)| *a == k).map(|(_, v)| v);
+                let reply = match kind {
+                    // Binding
+                    0x0001 => Some(encode(0x0101, txid, &[(0x0020, xor(peer))])),
+                    // Send indication: deliver as a Data indication from the sender's relay
+                    0x0016 => {
+                        let (Some(to), Some(data), Some(from)) = (
+                            attr(0x0012).and_then(|v| unxor(v)),
+                            attr(0x0013),
+                            relayed.get(&peer),
+                        ) else {
+                            continue;
+                        };
+                        if let Some(client) = clients.get(&to) {
+                            let txid = rand::random();
+                            let msg = encode(
+                                0x0017,
+                                txid,
+                                &[(0x0012, xor(*from)), (0x0013, data.clone())],
+                            );
+                            socket.send_to(&msg, client).await.ok();
+                        }
+                        None
+                    }
+                    // Allocate, Refresh, CreatePermission
+                    0x0003 | 0x0004 | 0x0008 => Some(if !authenticated(&buf[..n], &attrs) {
+                        encode(
+                            kind | 0x0110,
+                            txid,
+                            &[
+                                (0x0009, b"\0\0\x04\x01Unauthorized".to_vec()),
+                                (0x0014, REALM.as_bytes().to_vec()),
+                                (0x0015, NONCE.as_bytes().to_vec()),
+                            ],
+                        )
+                    } else if kind == 0x0003 {
+                        let port = 50000 + relayed.len() as u16;
+                        let relay = SocketAddr::from(([127, 0, 0, 1], port));
+                        relayed.insert(peer, relay);
+                        clients.insert(relay, peer);
+                        *counter.lock().unwrap() += 1;
+                        encode(0x0103, txid, &[(0x0016, xor(relay))])
+                    } else {
+                        encode(kind | 0x0100, txid, &[])
+                    }),
+                    _ => None,
+                };
+                if let Some(reply) = reply {
+                    socket.send_to(&reply, peer).await.ok();
+                }
+            }
+        });
+
+        Self {
+            addr,
+            allocations,
+            task,
+        }
+    }
+}
+
+impl Drop for MockTurn {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+type Attrs = Vec<(u16, Vec<u8>)>;
+
+fn decode(buf: &[u8]) -> Option<(u16, [u8; 12], Attrs)> {
+    if buf.get(4..8)? != COOKIE {
+        return None;
+    }
+    let len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+    let body = buf.get(20..20 + len)?;
+    let mut attrs = Vec::new();
+    let mut at = 0;
+    while at + 4 <= body.len() {
+        let kind = u16::from_be_bytes([body[at], body[at + 1]]);
+        let size = u16::from_be_bytes([body[at + 2], body[at + 3]]) as usize;
+        attrs.push((kind, body.get(at + 4..at + 4 + size)?.to_vec()));
+        at += 4 + size.next_multiple_of(4);
+    }
+    Some((
+        u16::from_be_bytes([buf[0], buf[1]]),
+        buf[8..20].try_into().ok()?,
+        attrs,
+    ))
+}
+
+fn encode(kind: u16, txid: [u8; 12], attrs: &[(u16, Vec<u8>)]) -> Vec<u8> {
+    let mut out = kind.to_be_bytes().to_vec();
+    out.extend_from_slice(&[0, 0]);
+    out.extend_from_slice(&COOKIE);
+    out.extend_from_slice(&txid);
+    for (kind, value) in attrs {
+        out.extend_from_slice(&kind.to_be_bytes());
+        out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        out.extend_from_slice(value);
+        out.resize(out.len().next_multiple_of(4), 0);
+    }
+    let len = (out.len() - 20) as u16;
+    out[2..4].copy_from_slice(&len.to_be_bytes());
+    out
+}
+
+/// Whether the request carries our credentials and a valid MESSAGE-INTEGRITY.
+fn authenticated(buf: &[u8], attrs: &Attrs) -> bool {
+    let attr = |k: u16| {
+        attrs
+            .iter()
+            .find(|(a, _)| *a == k)
+            .map(|(_, v)| v.as_slice())
+    };
+    if attr(0x0006) != Some(USERNAME.as_bytes()) || attr(0x0015) != Some(NONCE.as_bytes()) {
+        return false;
+    }
+    let Some(integrity) = attr(0x0008) else {
+        return false;
+    };
+    // Integrity is always the last attribute the client sends
+    let key = md5::compute(format!("{USERNAME}:{REALM}:{CREDENTIAL}"));
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &key.0);
+    let covered = &buf[..buf.len() - 24];
+    ring::hmac::verify(&key, covered, integrity).is_ok()
+}
+
+/// XOR-*-ADDRESS value; IPv4 only, which needs no transaction id
+fn xor(addr: SocketAddr) -> Vec<u8> {
+    let SocketAddr::V4(addr) = addr else {
+        unreachable!("the mock only listens on IPv4")
+    };
+    let mut value = vec![0, 1];
+    value.extend_from_slice(&(addr.port() ^ 0x2112).to_be_bytes());
+    value.extend(addr.ip().octets().iter().zip(COOKIE).map(|(b, k)| b ^ k));
+    value
+}
+
+fn unxor(value: &[u8]) -> Option<SocketAddr> {
+    let port = u16::from_be_bytes([*value.get(2)?, *value.get(3)?]) ^ 0x2112;
+    let ip: [u8; 4] = value.get(4..8)?.try_into().ok()?;
+    let ip = std::array::from_fn::<u8, 4, _>(|i| ip[i] ^ COOKIE[i]);
+    Some(SocketAddr::from((ip, port)))
+}